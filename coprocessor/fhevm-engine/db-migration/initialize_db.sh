@@ -24,8 +24,33 @@ echo "-------------- Start database initilaization --------------"
 echo "Creating database..."
 sqlx database create || { echo "Failed to create database."; exit 1; }
 
-echo "Running migrations..."
-sqlx migrate run --source $MIGRATION_DIR || { echo "Failed to run migrations."; exit 1; }
+# Appends the search_path of the schema to a database URL
+schema_url() {
+  local url="$1"
+  local schema="$2"
+  if [[ "$url" == *\?* ]]; then
+    echo "${url}&options=-csearch_path%3D${schema}"
+  else
+    echo "${url}?options=-csearch_path%3D${schema}"
+  fi
+}
+
+if [[ -z "$DATABASE_SCHEMAS" ]]; then
+  echo "Running migrations..."
+  sqlx migrate run --source $MIGRATION_DIR || { echo "Failed to run migrations."; exit 1; }
+else
+  # Schema-per-chain layout, each schema is migrated on its own
+  for SCHEMA in $DATABASE_SCHEMAS; do
+    echo "Running migrations in schema $SCHEMA..."
+    psql "$DATABASE_URL" -c "CREATE SCHEMA IF NOT EXISTS $SCHEMA;" || { echo "Failed to create schema $SCHEMA."; exit 1; }
+    sqlx migrate run --source $MIGRATION_DIR --database-url "$(schema_url "$DATABASE_URL" "$SCHEMA")" || {
+      echo "Failed to run migrations in schema $SCHEMA."; exit 1;
+    }
+  done
+  if [[ -n "$TENANT_SCHEMA" ]]; then
+    DATABASE_URL=$(schema_url "$DATABASE_URL" "$TENANT_SCHEMA")
+  fi
+fi
 
 echo "-------------- Start inserting keys for tenant: $TENANT_API_KEY --------------"
 
@@ -0,0 +1,15 @@
+-- Per-transaction fee accounting, populated by the transaction-sender from receipts.
+-- Costs are in wei and stored as NUMERIC as they can exceed BIGINT.
+CREATE TABLE IF NOT EXISTS gas_spend (
+    txn_hash BYTEA PRIMARY KEY,
+    operation TEXT NOT NULL,
+    chain_id BIGINT NOT NULL,
+    block_number BIGINT NULL,
+    gas_used BIGINT NOT NULL,
+    effective_gas_price NUMERIC NOT NULL,
+    total_cost NUMERIC NOT NULL,
+    success BOOLEAN NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_gas_spend_created_at ON gas_spend (created_at);
@@ -0,0 +1,38 @@
+-- Public and user decryption responses to be sent to the Gateway Decryption contract.
+CREATE TABLE IF NOT EXISTS decryption_responses (
+    -- uint256, big endian
+    decryption_id BYTEA NOT NULL,
+    response_type SMALLINT NOT NULL,
+    -- 0 - public decryption response
+    -- 1 - user decryption response
+    result BYTEA NOT NULL,
+    signature BYTEA NOT NULL,
+    extra_data BYTEA NOT NULL DEFAULT '\x',
+    txn_is_sent BOOLEAN NOT NULL DEFAULT FALSE,
+    txn_limited_retries_count INT NOT NULL DEFAULT 0,
+    txn_unlimited_retries_count INT NOT NULL DEFAULT 0,
+    txn_last_error TEXT DEFAULT NULL,
+    txn_last_error_at TIMESTAMP DEFAULT NULL,
+    txn_hash BYTEA DEFAULT NULL,
+    txn_block_number BIGINT DEFAULT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (decryption_id, response_type)
+);
+
+CREATE INDEX IF NOT EXISTS idx_decryption_responses_not_sent
+    ON decryption_responses (created_at)
+    WHERE txn_is_sent = FALSE;
+
+CREATE OR REPLACE FUNCTION notify_event_decryption_response()
+    RETURNS trigger AS $$
+BEGIN
+    NOTIFY event_decryption_response;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER on_insert_notify_event_decryption_response
+    AFTER INSERT
+    ON decryption_responses
+    FOR EACH STATEMENT
+    EXECUTE FUNCTION notify_event_decryption_response();
@@ -0,0 +1,17 @@
+-- Keys generated by the KMS, as announced by the Gateway KMSGeneration contract.
+CREATE TABLE IF NOT EXISTS kms_keys (
+    -- uint256, big endian
+    key_id BYTEA NOT NULL,
+    -- uint256, big endian, NULL when the KeygenRequest event has not been seen
+    prep_keygen_id BYTEA DEFAULT NULL,
+    -- 'requested' on KeygenRequest, 'activated' on ActivateKey
+    status TEXT NOT NULL,
+    requested_block_number BIGINT DEFAULT NULL,
+    requested_transaction_hash BYTEA DEFAULT NULL,
+    activated_block_number BIGINT DEFAULT NULL,
+    activated_transaction_hash BYTEA DEFAULT NULL,
+    activated_at TIMESTAMP DEFAULT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (key_id),
+    CHECK (status IN ('requested', 'activated'))
+);
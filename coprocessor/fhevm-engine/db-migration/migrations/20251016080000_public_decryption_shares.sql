@@ -0,0 +1,45 @@
+-- Public decryption responses of the individual KMS signers, aggregated into a single response
+-- once a quorum of signers agrees on the result.
+CREATE TABLE IF NOT EXISTS public_decryption_shares (
+    -- uint256, big endian
+    decryption_id BYTEA NOT NULL,
+    -- address of the KMS signer
+    signer BYTEA NOT NULL,
+    -- concatenation of the 32 bytes handles of the decrypted ciphertexts
+    ct_handles BYTEA NOT NULL,
+    result BYTEA NOT NULL,
+    -- EIP-712 signature of the result by the signer
+    signature BYTEA NOT NULL,
+    extra_data BYTEA NOT NULL DEFAULT '\x',
+    -- NULL until the signature is verified
+    is_valid BOOLEAN DEFAULT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (decryption_id, signer)
+);
+
+-- Outcome of the aggregation, a decryption is pending until it has a row here. Aggregated responses
+-- are inserted in decryption_responses with response_type 2, their signature being the ABI
+-- encoding of the bytes[] signatures of the quorum.
+CREATE TABLE IF NOT EXISTS public_decryption_aggregations (
+    decryption_id BYTEA PRIMARY KEY,
+    -- 0 - aggregated, the response is in decryption_responses
+    -- 1 - timed out before reaching the quorum
+    status SMALLINT NOT NULL,
+    signers_count INT NOT NULL,
+    error TEXT DEFAULT NULL,
+    completed_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE OR REPLACE FUNCTION notify_event_public_decryption_share()
+    RETURNS trigger AS $$
+BEGIN
+    NOTIFY event_public_decryption_share;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER on_insert_notify_event_public_decryption_share
+    AFTER INSERT
+    ON public_decryption_shares
+    FOR EACH STATEMENT
+    EXECUTE FUNCTION notify_event_public_decryption_share();
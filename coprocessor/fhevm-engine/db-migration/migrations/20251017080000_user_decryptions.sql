@@ -0,0 +1,58 @@
+-- Account delegations of the host chain ACL, checked when a user decrypts on behalf of another
+-- account. Only the latest event per delegation is kept, in delegation counter order.
+CREATE TABLE IF NOT EXISTS user_decryption_delegations (
+    tenant_id INT NOT NULL,
+    delegator TEXT NOT NULL,
+    delegate TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    delegation_counter BIGINT NOT NULL,
+    -- unix timestamp in seconds
+    expiry_date BIGINT NOT NULL,
+    block_number BIGINT DEFAULT NULL,
+    transaction_id BYTEA DEFAULT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (tenant_id, delegator, delegate, contract_address)
+);
+
+-- User decryption requests, re-encrypted under the user's public key by the KMS. The re-encrypted
+-- result is inserted in decryption_responses with response_type 1.
+CREATE TABLE IF NOT EXISTS user_decryption_requests (
+    -- uint256, big endian
+    decryption_id BYTEA PRIMARY KEY,
+    user_address TEXT NOT NULL,
+    -- set when the user decrypts the handles of another account, through a delegation
+    delegator_address TEXT DEFAULT NULL,
+    -- concatenation of the 32 bytes handles to decrypt
+    ct_handles BYTEA NOT NULL,
+    -- contract address of each handle, in the same order
+    contract_addresses TEXT[] NOT NULL,
+    public_key BYTEA NOT NULL,
+    extra_data BYTEA NOT NULL DEFAULT '\x',
+    -- 'queued' until the response is stored ('completed'), the requester is not allowed to
+    -- decrypt a handle ('rejected') or the retries are exhausted ('failed')
+    status TEXT NOT NULL DEFAULT 'queued',
+    retry_count INT NOT NULL DEFAULT 0,
+    last_error TEXT DEFAULT NULL,
+    last_error_at TIMESTAMP DEFAULT NULL,
+    completed_at TIMESTAMP DEFAULT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    CHECK (status IN ('queued', 'completed', 'rejected', 'failed'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_decryption_requests_queued
+    ON user_decryption_requests (created_at)
+    WHERE status = 'queued';
+
+CREATE OR REPLACE FUNCTION notify_event_user_decryption_request()
+    RETURNS trigger AS $$
+BEGIN
+    NOTIFY event_user_decryption_request;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER on_insert_notify_event_user_decryption_request
+    AFTER INSERT
+    ON user_decryption_requests
+    FOR EACH STATEMENT
+    EXECUTE FUNCTION notify_event_user_decryption_request();
@@ -0,0 +1,7 @@
+-- Key of the decrypted ciphertexts, as reported by the KMS signer. Thresholds may be overridden per
+-- key by the quorum policy of the transaction sender.
+ALTER TABLE public_decryption_shares ADD COLUMN IF NOT EXISTS key_id BYTEA DEFAULT NULL;
+
+-- Version of the signer set whose threshold was reached, NULL if timed out.
+ALTER TABLE public_decryption_aggregations
+    ADD COLUMN IF NOT EXISTS signer_set_version BIGINT DEFAULT NULL;
@@ -0,0 +1,13 @@
+-- User decryption requests given up by the sns-worker, their status being 'failed'. A request is
+-- requeued by deleting its row here and setting its status back to 'queued' with a zero
+-- retry_count.
+CREATE TABLE IF NOT EXISTS user_decryption_dead_letters (
+    decryption_id BYTEA PRIMARY KEY,
+    -- 'not_ready' - retries exhausted while the ciphertexts were not available
+    -- 'kms_unavailable' - retries exhausted while the KMS was unavailable
+    -- 'poison' - the request cannot be processed, e.g. invalid ciphertext format
+    kind TEXT NOT NULL,
+    error TEXT NOT NULL,
+    retry_count INT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,25 @@
+-- Every delegation event of the host chain ACL, with what became of it. Unlike
+-- user_decryption_delegations, which only keeps the latest state, the events are never updated
+-- away, so that support can tell whether a delegation was applied, replaced or ignored.
+CREATE TABLE IF NOT EXISTS user_decryption_delegation_history (
+    tenant_id INT NOT NULL,
+    delegator TEXT NOT NULL,
+    delegate TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    delegation_counter BIGINT NOT NULL,
+    -- unix timestamp in seconds
+    expiry_date BIGINT NOT NULL,
+    -- 'applied' - the event is the current state of the delegation
+    -- 'superseded' - a later event of the delegation was applied since
+    -- 'dismissed' - the event is older than the applied one, e.g. replayed after a reorg
+    status TEXT NOT NULL,
+    block_number BIGINT DEFAULT NULL,
+    transaction_id BYTEA DEFAULT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (tenant_id, delegator, delegate, contract_address, delegation_counter),
+    CHECK (status IN ('applied', 'superseded', 'dismissed'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_decryption_delegation_history_accounts
+    ON user_decryption_delegation_history (LOWER(delegator), LOWER(delegate));
@@ -0,0 +1,39 @@
+-- Delegation revocations of the host chain ACL, queued to be sent to the Gateway. A revocation
+-- also resets the expiry date of the delegation to 0 in user_decryption_delegations. The
+-- revocations and delegations of an account are ordered by their delegation counter, which must
+-- be followed when sending them.
+CREATE TABLE IF NOT EXISTS delegation_revocations (
+    tenant_id INT NOT NULL,
+    delegator TEXT NOT NULL,
+    delegate TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    delegation_counter BIGINT NOT NULL,
+    -- expiry date of the revoked delegation, unix timestamp in seconds
+    old_expiry_date BIGINT NOT NULL,
+    block_number BIGINT DEFAULT NULL,
+    transaction_id BYTEA DEFAULT NULL,
+    txn_is_sent BOOLEAN NOT NULL DEFAULT FALSE,
+    txn_retry_count INT NOT NULL DEFAULT 0,
+    txn_last_error TEXT DEFAULT NULL,
+    txn_last_error_at TIMESTAMP DEFAULT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (tenant_id, delegator, delegate, contract_address, delegation_counter)
+);
+
+CREATE INDEX IF NOT EXISTS idx_delegation_revocations_not_sent
+    ON delegation_revocations (delegation_counter)
+    WHERE txn_is_sent = FALSE;
+
+CREATE OR REPLACE FUNCTION notify_event_delegation_revoked()
+    RETURNS trigger AS $$
+BEGIN
+    NOTIFY event_delegation_revoked;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER on_insert_notify_event_delegation_revoked
+    AFTER INSERT
+    ON delegation_revocations
+    FOR EACH STATEMENT
+    EXECUTE FUNCTION notify_event_delegation_revoked();
@@ -0,0 +1,24 @@
+-- Receipts of the transactions sent by the transaction-sender, kept for audit and support. Fees
+-- are accounted in gas_spend.
+CREATE TABLE IF NOT EXISTS transaction_receipts (
+    txn_hash BYTEA PRIMARY KEY,
+    operation TEXT NOT NULL,
+    chain_id BIGINT NOT NULL,
+    -- keys of the queue rows the transaction was sent for, depending on the operation:
+    -- the zk_proof_id of verify_proofs, the hex handle of ciphertext_digest, the hex handle and
+    -- account address of allowed_handles ('<handle>:<account>') or the hex decryption_id of
+    -- decryption_responses
+    queue_keys TEXT[] NOT NULL,
+    block_number BIGINT DEFAULT NULL,
+    block_hash BYTEA DEFAULT NULL,
+    gas_used BIGINT NOT NULL,
+    success BOOLEAN NOT NULL,
+    -- decoded contract error of a reverted transaction, when it could be recovered
+    revert_reason TEXT DEFAULT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_transaction_receipts_queue_keys
+    ON transaction_receipts USING GIN (queue_keys);
+CREATE INDEX IF NOT EXISTS idx_transaction_receipts_created_at
+    ON transaction_receipts (created_at);
@@ -0,0 +1,19 @@
+-- Proof responses are no longer deleted once handled but move to a final status, and are only
+-- purged after a retention period. A proof request replayed by the gw-listener after a crash
+-- between the send and the cleanup is then ignored instead of being verified and sent again.
+ALTER TABLE verify_proofs
+    -- 'pending' - the response is still to be sent
+    -- 'sent' - the response is on the Gateway, sent by us or by a previous run
+    -- 'failed' - the response was given up, see last_error
+    ADD COLUMN IF NOT EXISTS txn_status TEXT NOT NULL DEFAULT 'pending',
+    ADD COLUMN IF NOT EXISTS txn_hash BYTEA DEFAULT NULL,
+    ADD COLUMN IF NOT EXISTS txn_status_updated_at TIMESTAMPTZ DEFAULT NULL,
+    ADD CONSTRAINT verify_proofs_txn_status_check CHECK (txn_status IN ('pending', 'sent', 'failed'));
+
+CREATE INDEX IF NOT EXISTS idx_verify_proofs_pending
+    ON verify_proofs (zk_proof_id)
+    WHERE txn_status = 'pending' AND verified IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_verify_proofs_handled_at
+    ON verify_proofs (txn_status_updated_at)
+    WHERE txn_status <> 'pending';
@@ -0,0 +1,6 @@
+-- The superseded and dismissed delegation events are now purged by the host-listener once they
+-- are older than a retention period, the applied ones are kept as the current state of the
+-- delegations. updated_at is the time the event was given its final status.
+CREATE INDEX IF NOT EXISTS idx_user_decryption_delegation_history_handled_at
+    ON user_decryption_delegation_history (tenant_id, updated_at)
+    WHERE status <> 'applied';
@@ -0,0 +1,10 @@
+-- Ciphertexts are tagged with the key they were computed or expanded under, i.e. the key_id of
+-- the tenant at the time. A ciphertext is never processed with another key, a mismatch marks
+-- the consuming computation as failed instead. NULL for the ciphertexts created before tagging,
+-- or for tenants without a key_id, which are processed under any key.
+ALTER TABLE ciphertexts ADD COLUMN IF NOT EXISTS key_id BYTEA DEFAULT NULL;
+
+-- SnS tasks rejected on a key mismatch, they are not picked up again
+ALTER TABLE pbs_computations
+    ADD COLUMN IF NOT EXISTS is_error BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN IF NOT EXISTS error_message TEXT DEFAULT NULL;
@@ -0,0 +1,8 @@
+-- Contract that requested the computation, the caller of the FHEVMExecutor. Workers can claim the
+-- computations in weighted fair order across callers, so that a contract with a large backlog does
+-- not delay the others. NULL for the computations inserted before, which share one fair share.
+ALTER TABLE computations ADD COLUMN IF NOT EXISTS caller BYTEA DEFAULT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_computations_caller_schedule_order
+    ON computations (caller, schedule_order)
+    WHERE is_completed = FALSE AND is_error = FALSE AND is_allowed = TRUE;
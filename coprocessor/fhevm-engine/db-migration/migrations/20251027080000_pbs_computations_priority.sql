@@ -0,0 +1,33 @@
+-- Priority of the SnS task, the sns-worker squashes the higher priorities first. Ciphertexts
+-- awaited by a decryption are latency critical:
+-- 0 - regular
+-- 1 - allowed for public decryption
+-- 2 - requested by a user decryption
+ALTER TABLE pbs_computations ADD COLUMN IF NOT EXISTS priority SMALLINT NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS idx_pbs_computations_pending_priority
+    ON pbs_computations (priority DESC, created_at)
+    WHERE is_completed = FALSE AND is_error = FALSE;
+
+-- The handles of a user decryption request are raised to the user decryption priority, as long as
+-- they are not squashed yet.
+CREATE OR REPLACE FUNCTION prioritize_user_decryption_handles()
+    RETURNS trigger AS $$
+BEGIN
+    UPDATE pbs_computations
+    SET priority = 2
+    WHERE handle IN (
+        SELECT substring(NEW.ct_handles FROM i FOR 32)
+        FROM generate_series(1, length(NEW.ct_handles), 32) AS i
+    )
+    AND is_completed = FALSE
+    AND priority < 2;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER on_insert_prioritize_user_decryption_handles
+    AFTER INSERT
+    ON user_decryption_requests
+    FOR EACH ROW
+    EXECUTE FUNCTION prioritize_user_decryption_handles();
@@ -0,0 +1,22 @@
+-- Logs of the watched host chain contracts stored as is by the host-listener with --raw-events, so
+-- that the events it can't decode yet, e.g. of a new event type, are not lost while it is being
+-- supported. They are decoded and ingested later with the decode_raw_events tool.
+CREATE TABLE IF NOT EXISTS raw_events (
+    tenant_id INT NOT NULL,
+    block_hash BYTEA NOT NULL,
+    log_index BIGINT NOT NULL,
+    block_number BIGINT NOT NULL,
+    transaction_hash BYTEA DEFAULT NULL,
+    contract_address TEXT NOT NULL,
+    topics BYTEA[] NOT NULL,
+    data BYTEA NOT NULL,
+    -- whether the event was decoded, by the host-listener or later by decode_raw_events
+    is_decoded BOOLEAN NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    decoded_at TIMESTAMP DEFAULT NULL,
+    PRIMARY KEY (tenant_id, block_hash, log_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_raw_events_undecoded
+    ON raw_events (tenant_id, block_number)
+    WHERE NOT is_decoded;
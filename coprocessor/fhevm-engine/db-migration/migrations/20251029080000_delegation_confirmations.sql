@@ -0,0 +1,11 @@
+-- Delegations are effective for the user decryptions once their block is confirmed, i.e. once the
+-- host-listener processed `block_delay` blocks after it, so that a reorg cannot let a user decrypt
+-- with a delegation the chain dropped. The delay is adapted by the host-listener to the reorgs it
+-- observes on the chain, between its --delegation-block-delay-min and --delegation-block-delay-max.
+CREATE TABLE IF NOT EXISTS host_chain_delegation_confirmations (
+    chain_id BIGINT PRIMARY KEY,
+    block_delay BIGINT NOT NULL,
+    -- the delegations of the blocks up to this one are effective
+    confirmed_block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,20 @@
+-- The host-listener prunes the rows of the tables given a retention, --retention <table>=<age> or
+-- <table>=<depth>blocks, once their work is done. Indexes of the prunable rows.
+CREATE INDEX IF NOT EXISTS idx_computations_completed_at
+    ON computations (tenant_id, completed_at)
+    WHERE is_completed = TRUE;
+
+CREATE INDEX IF NOT EXISTS idx_pbs_computations_completed_at
+    ON pbs_computations (tenant_id, completed_at)
+    WHERE is_completed = TRUE;
+
+CREATE INDEX IF NOT EXISTS idx_delegation_revocations_sent
+    ON delegation_revocations (tenant_id, block_number)
+    WHERE txn_is_sent = TRUE;
+
+CREATE INDEX IF NOT EXISTS idx_raw_events_decoded
+    ON raw_events (tenant_id, block_number)
+    WHERE is_decoded;
+
+CREATE INDEX IF NOT EXISTS idx_host_chain_blocks_valid_block_number
+    ON host_chain_blocks_valid (chain_id, block_number);
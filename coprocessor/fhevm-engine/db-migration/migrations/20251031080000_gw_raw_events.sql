@@ -0,0 +1,29 @@
+-- Logs of the Gateway events the gw-listener acted on, stored as is with --raw-events, to audit them
+-- and to act on them again with the replay_gw_events tool, e.g. after a decoding bug. Append-only.
+CREATE TABLE IF NOT EXISTS gw_raw_events (
+    block_hash BYTEA NOT NULL,
+    log_index BIGINT NOT NULL,
+    block_number BIGINT NOT NULL,
+    transaction_hash BYTEA DEFAULT NULL,
+    contract_address TEXT NOT NULL,
+    topics BYTEA[] NOT NULL,
+    data BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (block_hash, log_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_gw_raw_events_block_number
+    ON gw_raw_events (block_number);
+
+CREATE OR REPLACE FUNCTION reject_gw_raw_events_change()
+    RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'gw_raw_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER on_change_reject_gw_raw_events
+    BEFORE UPDATE OR DELETE
+    ON gw_raw_events
+    FOR EACH ROW
+    EXECUTE FUNCTION reject_gw_raw_events_change();
@@ -0,0 +1,40 @@
+-- With --lazy-sns, the host-listener only enqueues to the sns-worker the handles allowed for public
+-- decryption, as a prefetch. The handles of a user decryption request are then enqueued once
+-- requested, at the user decryption priority, unless already squashed. The ones already enqueued
+-- are raised, as before.
+CREATE OR REPLACE FUNCTION prioritize_user_decryption_handles()
+    RETURNS trigger AS $$
+BEGIN
+    UPDATE pbs_computations
+    SET priority = 2
+    WHERE handle IN (
+        SELECT substring(NEW.ct_handles FROM i FOR 32)
+        FROM generate_series(1, length(NEW.ct_handles), 32) AS i
+    )
+    AND is_completed = FALSE
+    AND priority < 2;
+
+    INSERT INTO pbs_computations (tenant_id, handle, priority)
+    SELECT DISTINCT a.tenant_id, a.handle, 2
+    FROM allowed_handles a
+    WHERE a.handle IN (
+        SELECT substring(NEW.ct_handles FROM i FOR 32)
+        FROM generate_series(1, length(NEW.ct_handles), 32) AS i
+    )
+    -- squashed, or uploaded and garbage collected
+    AND NOT EXISTS (
+        SELECT 1 FROM ciphertexts c
+        WHERE c.tenant_id = a.tenant_id
+        AND c.handle = a.handle
+        AND c.ciphertext128 IS NOT NULL
+    )
+    AND NOT EXISTS (
+        SELECT 1 FROM ciphertext_digest d
+        WHERE d.tenant_id = a.tenant_id
+        AND d.handle = a.handle
+        AND d.ciphertext128 IS NOT NULL
+    )
+    ON CONFLICT (tenant_id, handle) DO NOTHING;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,8 @@
+-- Parameter set the keys of the tenant were generated with, by name of a descriptor registered in
+-- the workers. NULL for the parameters the workers were built with.
+ALTER TABLE tenants ADD COLUMN IF NOT EXISTS param_set TEXT DEFAULT NULL;
+
+-- Ciphertexts are tagged with the parameter set they were computed or expanded under, next to
+-- their key_id. A ciphertext tagged with another set than the keys of the worker is rejected. NULL
+-- for the ciphertexts created before tagging, which are under the default parameters.
+ALTER TABLE ciphertexts ADD COLUMN IF NOT EXISTS param_set TEXT DEFAULT NULL;
@@ -0,0 +1,5 @@
+-- User decryption requests are claimed by a sns-worker for the time of their processing, instead
+-- of being locked by a transaction open across the S3 downloads and the KMS calls. The claim of a
+-- crashed worker is taken over once expired.
+ALTER TABLE user_decryption_requests
+    ADD COLUMN IF NOT EXISTS claimed_until TIMESTAMP DEFAULT NULL;
@@ -0,0 +1,17 @@
+-- Revocations are recorded in the delegation history, along with the expiry date of the
+-- delegation they revoked. Nothing sends them to the Gateway, which has no revocation endpoint,
+-- so their queue is dropped.
+ALTER TABLE user_decryption_delegation_history
+    ADD COLUMN IF NOT EXISTS old_expiry_date BIGINT DEFAULT NULL;
+
+UPDATE user_decryption_delegation_history h
+SET old_expiry_date = r.old_expiry_date
+FROM delegation_revocations r
+WHERE h.tenant_id = r.tenant_id
+AND h.delegator = r.delegator
+AND h.delegate = r.delegate
+AND h.contract_address = r.contract_address
+AND h.delegation_counter = r.delegation_counter;
+
+DROP TABLE IF EXISTS delegation_revocations;
+DROP FUNCTION IF EXISTS notify_event_delegation_revoked();
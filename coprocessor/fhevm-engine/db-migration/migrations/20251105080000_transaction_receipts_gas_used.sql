@@ -0,0 +1,2 @@
+-- The gas used by a transaction is accounted in gas_spend only.
+ALTER TABLE transaction_receipts DROP COLUMN IF EXISTS gas_used;
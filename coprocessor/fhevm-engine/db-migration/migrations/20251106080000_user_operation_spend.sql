@@ -0,0 +1,12 @@
+-- A bundle transaction can carry several user operations of the transaction-sender, each one is
+-- recorded with its own share of the gas, identified by its user operation hash. NULL for the
+-- transactions sent from the EOA of the signer.
+ALTER TABLE gas_spend ADD COLUMN IF NOT EXISTS user_op_hash BYTEA DEFAULT NULL;
+ALTER TABLE gas_spend DROP CONSTRAINT IF EXISTS gas_spend_pkey;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_gas_spend_txn_hash_user_op_hash
+    ON gas_spend (txn_hash, user_op_hash) NULLS NOT DISTINCT;
+
+ALTER TABLE transaction_receipts ADD COLUMN IF NOT EXISTS user_op_hash BYTEA DEFAULT NULL;
+ALTER TABLE transaction_receipts DROP CONSTRAINT IF EXISTS transaction_receipts_pkey;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_transaction_receipts_txn_hash_user_op_hash
+    ON transaction_receipts (txn_hash, user_op_hash) NULLS NOT DISTINCT;
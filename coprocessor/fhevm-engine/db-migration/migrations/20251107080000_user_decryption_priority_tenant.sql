@@ -0,0 +1,39 @@
+-- The handles of a user decryption request are only raised, or enqueued, in the tenants whose ACL
+-- allows them to the contract of the request, instead of in every tenant with the same handle.
+CREATE OR REPLACE FUNCTION prioritize_user_decryption_handles()
+    RETURNS trigger AS $$
+BEGIN
+    UPDATE pbs_computations p
+    SET priority = 2
+    FROM allowed_handles a
+    JOIN generate_series(1, length(NEW.ct_handles), 32) AS i
+        ON a.handle = substring(NEW.ct_handles FROM i FOR 32)
+        AND LOWER(a.account_address) = LOWER(NEW.contract_addresses[(i - 1) / 32 + 1])
+    WHERE p.tenant_id = a.tenant_id
+    AND p.handle = a.handle
+    AND p.is_completed = FALSE
+    AND p.priority < 2;
+
+    INSERT INTO pbs_computations (tenant_id, handle, priority)
+    SELECT DISTINCT a.tenant_id, a.handle, 2
+    FROM allowed_handles a
+    JOIN generate_series(1, length(NEW.ct_handles), 32) AS i
+        ON a.handle = substring(NEW.ct_handles FROM i FOR 32)
+        AND LOWER(a.account_address) = LOWER(NEW.contract_addresses[(i - 1) / 32 + 1])
+    -- squashed, or uploaded and garbage collected
+    WHERE NOT EXISTS (
+        SELECT 1 FROM ciphertexts c
+        WHERE c.tenant_id = a.tenant_id
+        AND c.handle = a.handle
+        AND c.ciphertext128 IS NOT NULL
+    )
+    AND NOT EXISTS (
+        SELECT 1 FROM ciphertext_digest d
+        WHERE d.tenant_id = a.tenant_id
+        AND d.handle = a.handle
+        AND d.ciphertext128 IS NOT NULL
+    )
+    ON CONFLICT (tenant_id, handle) DO NOTHING;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,141 @@
+//! Global allocator selection and allocator introspection.
+//!
+//! The allocator is chosen at build time with the `jemalloc` or `mimalloc` feature, the system
+//! allocator is used otherwise, jemalloc winning if both are enabled. Large ciphertext workloads
+//! allocate and free big buffers from many threads, which the glibc allocator tends to fragment;
+//! both alternatives keep RSS closer to the live heap.
+//!
+//! The global allocator is installed by the worker binaries, not by this library, so that tests and
+//! benchmarks remain free to install their own:
+//!
+//! ```ignore
+//! #[cfg(feature = "jemalloc")]
+//! #[global_allocator]
+//! static GLOBAL: allocator::Jemalloc = allocator::Jemalloc;
+//!
+//! #[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+//! #[global_allocator]
+//! static GLOBAL: allocator::MiMalloc = allocator::MiMalloc;
+//! ```
+//!
+//! With jemalloc, allocator statistics are exported as metrics and heap profiles can be dumped on
+//! demand. Profiling must be enabled at startup, e.g. `MALLOC_CONF=prof:true,lg_prof_sample:19`,
+//! and the dumps are analyzed with `jeprof`.
+
+use std::path::PathBuf;
+#[cfg(feature = "jemalloc")]
+use std::sync::LazyLock;
+
+#[cfg(feature = "jemalloc")]
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+
+#[cfg(feature = "mimalloc")]
+pub use mimalloc::MiMalloc;
+#[cfg(feature = "jemalloc")]
+pub use tikv_jemallocator::Jemalloc;
+
+/// Name of the global allocator, as selected by the features.
+pub const ALLOCATOR: &str = if cfg!(feature = "jemalloc") {
+    "jemalloc"
+} else if cfg!(feature = "mimalloc") {
+    "mimalloc"
+} else {
+    "system"
+};
+
+#[cfg(feature = "jemalloc")]
+static ALLOCATOR_BYTES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "coprocessor_allocator_bytes",
+        "Allocator statistics in bytes, see the jemalloc documentation of stats.<kind>",
+        &["allocator", "kind"]
+    )
+    .unwrap()
+});
+
+#[derive(thiserror::Error, Debug)]
+pub enum HeapProfileError {
+    #[error("Heap profiling is only supported with jemalloc, the allocator is {0}")]
+    Unsupported(&'static str),
+
+    #[error("Heap profiling is not enabled, start the process with MALLOC_CONF=prof:true")]
+    NotEnabled,
+
+    #[error("Heap profile dump failed: {0}")]
+    Dump(String),
+}
+
+/// Allocator statistics in bytes, by kind, only available with jemalloc.
+pub fn stats() -> Vec<(&'static str, u64)> {
+    #[cfg(feature = "jemalloc")]
+    {
+        use tikv_jemalloc_ctl::{epoch, stats};
+
+        // Statistics are cached by jemalloc until the epoch is advanced
+        if let Err(err) = epoch::advance() {
+            tracing::warn!(error = %err, "Failed to refresh jemalloc statistics");
+            return vec![];
+        }
+        let readings = [
+            ("allocated", stats::allocated::read()),
+            ("active", stats::active::read()),
+            ("resident", stats::resident::read()),
+            ("mapped", stats::mapped::read()),
+            ("retained", stats::retained::read()),
+            ("metadata", stats::metadata::read()),
+        ];
+        readings
+            .into_iter()
+            .filter_map(|(kind, value)| Some((kind, value.ok()? as u64)))
+            .collect()
+    }
+    #[cfg(not(feature = "jemalloc"))]
+    {
+        vec![]
+    }
+}
+
+/// Refreshes the allocator metrics, only available with jemalloc. Cheap enough to be called on
+/// every metrics scrape.
+pub fn update_metrics() {
+    #[cfg(feature = "jemalloc")]
+    for (kind, value) in stats() {
+        ALLOCATOR_BYTES
+            .with_label_values(&[ALLOCATOR, kind])
+            .set(value as i64);
+    }
+}
+
+/// Dumps a heap profile into the temporary directory and returns its path.
+pub fn dump_heap_profile() -> Result<PathBuf, HeapProfileError> {
+    #[cfg(feature = "jemalloc")]
+    {
+        use std::ffi::CString;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        // SAFETY: opt.prof is a read-only boolean option
+        let enabled = unsafe { tikv_jemalloc_ctl::raw::read::<bool>(b"opt.prof\0") }
+            .map_err(|err| HeapProfileError::Dump(err.to_string()))?;
+        if !enabled {
+            return Err(HeapProfileError::NotEnabled);
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path =
+            std::env::temp_dir().join(format!("heap-{}-{}.prof", std::process::id(), timestamp));
+        let c_path = CString::new(path.to_string_lossy().into_owned())
+            .map_err(|err| HeapProfileError::Dump(err.to_string()))?;
+        // SAFETY: prof.dump takes a NUL terminated file name, which outlives the call
+        unsafe { tikv_jemalloc_ctl::raw::write(b"prof.dump\0", c_path.as_ptr()) }
+            .map_err(|err| HeapProfileError::Dump(err.to_string()))?;
+        tracing::info!(path = %path.display(), "Heap profile dumped");
+        Ok(path)
+    }
+    #[cfg(not(feature = "jemalloc"))]
+    {
+        Err(HeapProfileError::Unsupported(ALLOCATOR))
+    }
+}
@@ -0,0 +1,295 @@
+//! Pool of byte buffers reused for ciphertext serialization and storage.
+//!
+//! Serialized ciphertexts are up to several megabytes and short lived: they are serialized,
+//! written to the database or S3 and dropped. Allocating them fresh every time puts a lot of
+//! pressure on the allocator, so released buffers are kept in power of two size classes and
+//! handed out again to the next serialization of a similar size.
+//!
+//! The pool is bounded by the total capacity of the buffers it retains, buffers released once the
+//! limit is reached are freed. The limit is set with [`set_max_retained_bytes`], the pool is
+//! disabled when it is 0.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+use bytes::Bytes;
+use prometheus::{register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
+use tracing::info;
+
+/// Smallest size class, smaller buffers are not worth pooling.
+pub const MIN_CLASS_SIZE: usize = 4 * 1024;
+/// Largest size class, larger buffers are not pooled.
+pub const MAX_CLASS_SIZE: usize = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_RETAINED_BYTES: usize = 256 * 1024 * 1024;
+
+const NUM_CLASSES: usize =
+    (MAX_CLASS_SIZE.trailing_zeros() - MIN_CLASS_SIZE.trailing_zeros() + 1) as usize;
+
+static MAX_RETAINED_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_RETAINED_BYTES);
+
+static POOL: LazyLock<BufferPool> = LazyLock::new(BufferPool::default);
+
+static BUFFER_POOL_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_buffer_pool_requests",
+        "Buffers requested from the pool, by result (hit or miss)",
+        &["result"]
+    )
+    .unwrap()
+});
+
+static BUFFER_POOL_RELEASES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_buffer_pool_releases",
+        "Buffers released to the pool, by result (retained or dropped)",
+        &["result"]
+    )
+    .unwrap()
+});
+
+static BUFFER_POOL_RETAINED_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "coprocessor_buffer_pool_retained_bytes",
+        "Total capacity of the buffers retained by the pool"
+    )
+    .unwrap()
+});
+
+/// Sets the maximum total capacity of the buffers retained by the pool.
+pub fn set_max_retained_bytes(max: usize) {
+    info!(max_retained_bytes = max, "Setting buffer pool size");
+    MAX_RETAINED_BYTES.store(max, Ordering::Relaxed);
+}
+
+/// Total capacity of the buffers retained by the pool.
+pub fn retained_bytes() -> usize {
+    POOL.retained_bytes.load(Ordering::Relaxed)
+}
+
+/// Returns an empty buffer able to hold at least `capacity` bytes, reused if possible.
+pub fn get(capacity: usize) -> Vec<u8> {
+    POOL.get(capacity)
+}
+
+/// Releases a buffer to the pool once its content is not needed anymore.
+pub fn put(buffer: Vec<u8>) {
+    POOL.put(buffer)
+}
+
+/// Same as [`put`] for a shared buffer, which is only retained if this is its last reference.
+pub fn put_bytes(bytes: Bytes) {
+    if let Ok(bytes) = bytes.try_into_mut() {
+        POOL.put(bytes.into())
+    }
+}
+
+#[derive(Default)]
+struct BufferPool {
+    classes: [Mutex<Vec<Vec<u8>>>; NUM_CLASSES],
+    retained_bytes: AtomicUsize,
+}
+
+impl BufferPool {
+    fn get(&self, capacity: usize) -> Vec<u8> {
+        let Some(class) = class_for_request(capacity) else {
+            BUFFER_POOL_REQUESTS.with_label_values(&["miss"]).inc();
+            return Vec::with_capacity(capacity);
+        };
+
+        let reused = self.classes[class].lock().expect("buffer pool lock").pop();
+        match reused {
+            Some(buffer) => {
+                self.retained_bytes
+                    .fetch_sub(buffer.capacity(), Ordering::Relaxed);
+                BUFFER_POOL_RETAINED_BYTES.sub(buffer.capacity() as i64);
+                BUFFER_POOL_REQUESTS.with_label_values(&["hit"]).inc();
+                buffer
+            }
+            None => {
+                BUFFER_POOL_REQUESTS.with_label_values(&["miss"]).inc();
+                // Round up so that the buffer can serve any request of its class once released
+                Vec::with_capacity(class_size(class))
+            }
+        }
+    }
+
+    fn put(&self, mut buffer: Vec<u8>) {
+        let capacity = buffer.capacity();
+        let Some(class) = class_for_release(capacity) else {
+            BUFFER_POOL_RELEASES.with_label_values(&["dropped"]).inc();
+            return;
+        };
+
+        let max = MAX_RETAINED_BYTES.load(Ordering::Relaxed);
+        let reserved =
+            self.retained_bytes
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |retained| {
+                    (retained + capacity <= max).then_some(retained + capacity)
+                });
+        if reserved.is_err() {
+            BUFFER_POOL_RELEASES.with_label_values(&["dropped"]).inc();
+            return;
+        }
+
+        buffer.clear();
+        self.classes[class]
+            .lock()
+            .expect("buffer pool lock")
+            .push(buffer);
+        BUFFER_POOL_RETAINED_BYTES.add(capacity as i64);
+        BUFFER_POOL_RELEASES.with_label_values(&["retained"]).inc();
+    }
+}
+
+fn class_size(class: usize) -> usize {
+    MIN_CLASS_SIZE << class
+}
+
+/// Smallest class whose buffers can hold `capacity` bytes.
+fn class_for_request(capacity: usize) -> Option<usize> {
+    if capacity > MAX_CLASS_SIZE {
+        return None;
+    }
+    let size = capacity.max(MIN_CLASS_SIZE).next_power_of_two();
+    Some((size.trailing_zeros() - MIN_CLASS_SIZE.trailing_zeros()) as usize)
+}
+
+/// Largest class whose requests a buffer of `capacity` bytes can serve.
+fn class_for_release(capacity: usize) -> Option<usize> {
+    if !(MIN_CLASS_SIZE..2 * MAX_CLASS_SIZE).contains(&capacity) {
+        return None;
+    }
+    let size = 1usize << (usize::BITS - 1 - capacity.leading_zeros());
+    Some((size.trailing_zeros() - MIN_CLASS_SIZE.trailing_zeros()) as usize)
+}
+
+/// Remembers the size of the last buffer produced per key, e.g. per ciphertext type, to request
+/// buffers of the right size before serializing.
+pub struct SizeHints<const N: usize>([AtomicUsize; N]);
+
+impl<const N: usize> SizeHints<N> {
+    pub const fn new() -> Self {
+        Self([const { AtomicUsize::new(0) }; N])
+    }
+
+    pub fn get(&self, key: usize) -> usize {
+        self.0
+            .get(key)
+            .map_or(0, |hint| hint.load(Ordering::Relaxed))
+    }
+
+    pub fn record(&self, key: usize, len: usize) {
+        if let Some(hint) = self.0.get(key) {
+            hint.store(len, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<const N: usize> Default for SizeHints<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_class_sizes() {
+        assert_eq!(NUM_CLASSES, 15);
+        assert_eq!(class_size(0), MIN_CLASS_SIZE);
+        assert_eq!(class_size(NUM_CLASSES - 1), MAX_CLASS_SIZE);
+    }
+
+    #[test]
+    fn test_class_for_request() {
+        // Small requests are served by the smallest class
+        assert_eq!(class_for_request(0), Some(0));
+        assert_eq!(class_for_request(1), Some(0));
+        assert_eq!(class_for_request(MIN_CLASS_SIZE), Some(0));
+        // Others by the class whose buffers can hold them
+        assert_eq!(class_for_request(MIN_CLASS_SIZE + 1), Some(1));
+        assert_eq!(class_for_request(2 * MIN_CLASS_SIZE), Some(1));
+        assert_eq!(class_for_request(1024 * 1024), Some(8));
+        assert_eq!(class_for_request(MAX_CLASS_SIZE), Some(NUM_CLASSES - 1));
+        assert_eq!(class_for_request(MAX_CLASS_SIZE + 1), None);
+        for capacity in [1, 5000, 100_000, 3 * 1024 * 1024, MAX_CLASS_SIZE] {
+            let class = class_for_request(capacity).unwrap();
+            assert!(class_size(class) >= capacity);
+        }
+    }
+
+    #[test]
+    fn test_class_for_release() {
+        assert_eq!(class_for_release(0), None);
+        assert_eq!(class_for_release(MIN_CLASS_SIZE - 1), None);
+        assert_eq!(class_for_release(MIN_CLASS_SIZE), Some(0));
+        // A buffer serves the requests of the largest class it can hold
+        assert_eq!(class_for_release(2 * MIN_CLASS_SIZE - 1), Some(0));
+        assert_eq!(class_for_release(2 * MIN_CLASS_SIZE), Some(1));
+        assert_eq!(class_for_release(MAX_CLASS_SIZE), Some(NUM_CLASSES - 1));
+        assert_eq!(
+            class_for_release(2 * MAX_CLASS_SIZE - 1),
+            Some(NUM_CLASSES - 1)
+        );
+        assert_eq!(class_for_release(2 * MAX_CLASS_SIZE), None);
+        for capacity in [MIN_CLASS_SIZE, 5000, 100_000, 3 * 1024 * 1024] {
+            let class = class_for_release(capacity).unwrap();
+            assert!(class_size(class) <= capacity);
+            assert!(capacity < 2 * class_size(class));
+        }
+    }
+
+    #[test]
+    fn test_released_buffer_is_reused() {
+        let pool = BufferPool::default();
+        let mut buffer = pool.get(5000);
+        assert!(buffer.capacity() >= class_size(1));
+        buffer.extend_from_slice(&[1; 5000]);
+        let ptr = buffer.as_ptr();
+        pool.put(buffer);
+        assert_eq!(pool.retained_bytes.load(Ordering::Relaxed), class_size(1));
+
+        // Cleared and handed out again for a request of the same class
+        let reused = pool.get(6000);
+        assert_eq!(reused.as_ptr(), ptr);
+        assert!(reused.is_empty());
+        assert_eq!(pool.retained_bytes.load(Ordering::Relaxed), 0);
+        // Not for a larger class
+        pool.put(reused);
+        assert_ne!(pool.get(3 * class_size(1)).as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_pool_is_bounded() {
+        let pool = BufferPool::default();
+        let max = MAX_RETAINED_BYTES.load(Ordering::Relaxed);
+        let count = max / MAX_CLASS_SIZE;
+        for _ in 0..=count {
+            pool.put(Vec::with_capacity(MAX_CLASS_SIZE));
+        }
+        assert_eq!(
+            pool.retained_bytes.load(Ordering::Relaxed),
+            count * MAX_CLASS_SIZE
+        );
+        // Too small or too large buffers are not retained
+        pool.put(Vec::with_capacity(MIN_CLASS_SIZE - 1));
+        pool.put(Vec::with_capacity(2 * MAX_CLASS_SIZE));
+        assert_eq!(
+            pool.retained_bytes.load(Ordering::Relaxed),
+            count * MAX_CLASS_SIZE
+        );
+    }
+
+    #[test]
+    fn test_size_hints() {
+        let hints = SizeHints::<4>::new();
+        assert_eq!(hints.get(1), 0);
+        hints.record(1, 1234);
+        assert_eq!(hints.get(1), 1234);
+        // Out of range keys are ignored
+        hints.record(4, 1);
+        assert_eq!(hints.get(4), 0);
+    }
+}
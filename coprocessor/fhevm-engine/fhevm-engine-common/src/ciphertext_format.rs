@@ -0,0 +1,299 @@
+//! Storage envelope of compressed ciphertexts (the `ciphertexts.ciphertext` column).
+//!
+//! An enveloped ciphertext is `ENVELOPE_MAGIC || format version || payload`. Rows written before
+//! the envelope was introduced hold the bare payload and are read as [`CiphertextFormat::Legacy`].
+//! A bare payload is a tfhe-rs safe serialization, which starts with the little-endian `u64`
+//! length of a short version string, so its second byte is always 0 and it cannot be mistaken for
+//! the magic.
+//!
+//! Readers accept every known format. Writers use the process-wide format set with
+//! [`set_write_format`] (legacy by default), so that readers can be rolled out before writers are
+//! switched.
+//!
+//! The envelope never leaves the coprocessor: the payload is what gets uploaded to S3, digested
+//! and served to clients.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use bytes::Bytes;
+use sqlx::{PgPool, Row};
+use tracing::info;
+
+use crate::types::FhevmError;
+
+pub const ENVELOPE_MAGIC: [u8; 4] = *b"FHCT";
+const ENVELOPE_HEADER_LEN: usize = ENVELOPE_MAGIC.len() + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum CiphertextFormat {
+    /// Bare tfhe-rs safe serialization, no envelope.
+    #[default]
+    Legacy = 0,
+    /// Envelope around a tfhe-rs safe serialization.
+    V1 = 1,
+}
+
+static WRITE_FORMAT: AtomicU8 = AtomicU8::new(CiphertextFormat::Legacy as u8);
+
+impl CiphertextFormat {
+    pub fn from_u8(version: u8) -> Option<Self> {
+        match version {
+            0 => Some(Self::Legacy),
+            1 => Some(Self::V1),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CiphertextFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CiphertextFormat::Legacy => write!(f, "legacy"),
+            CiphertextFormat::V1 => write!(f, "v1"),
+        }
+    }
+}
+
+impl FromStr for CiphertextFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "legacy" => Ok(Self::Legacy),
+            "v1" => Ok(Self::V1),
+            _ => Err(format!("unknown ciphertext format: {s}")),
+        }
+    }
+}
+
+/// Sets the format used by [`seal`] for all newly written ciphertexts.
+pub fn set_write_format(format: CiphertextFormat) {
+    info!(format = %format, "Setting ciphertext write format");
+    WRITE_FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+pub fn write_format() -> CiphertextFormat {
+    CiphertextFormat::from_u8(WRITE_FORMAT.load(Ordering::Relaxed)).unwrap_or_default()
+}
+
+/// Wraps a serialized ciphertext list into the current write format.
+pub fn seal(payload: Vec<u8>) -> Vec<u8> {
+    seal_as(payload, write_format())
+}
+
+/// Writes the envelope header of the current write format, to be followed by the serialized
+/// ciphertext list. Same as [`seal`] without copying the payload.
+pub fn write_header(out: &mut Vec<u8>) {
+    let format = write_format();
+    if format != CiphertextFormat::Legacy {
+        out.extend_from_slice(&ENVELOPE_MAGIC);
+        out.push(format as u8);
+    }
+}
+
+pub fn seal_as(payload: Vec<u8>, format: CiphertextFormat) -> Vec<u8> {
+    match format {
+        CiphertextFormat::Legacy => payload,
+        CiphertextFormat::V1 => {
+            let mut out = Vec::with_capacity(ENVELOPE_HEADER_LEN + payload.len());
+            out.extend_from_slice(&ENVELOPE_MAGIC);
+            out.push(format as u8);
+            out.extend_from_slice(&payload);
+            out
+        }
+    }
+}
+
+/// Returns the format of a stored ciphertext and its payload.
+pub fn open(blob: &[u8]) -> Result<(CiphertextFormat, &[u8]), FhevmError> {
+    if !blob.starts_with(&ENVELOPE_MAGIC) {
+        return Ok((CiphertextFormat::Legacy, blob));
+    }
+    let version = *blob
+        .get(ENVELOPE_MAGIC.len())
+        .ok_or(FhevmError::UnknownCiphertextFormat(None))?;
+    match CiphertextFormat::from_u8(version) {
+        Some(CiphertextFormat::Legacy) | None => {
+            Err(FhevmError::UnknownCiphertextFormat(Some(version)))
+        }
+        Some(format) => Ok((format, &blob[ENVELOPE_HEADER_LEN..])),
+    }
+}
+
+/// Same as [`open`], without copying legacy ciphertexts.
+pub fn into_payload(blob: Vec<u8>) -> Result<Vec<u8>, FhevmError> {
+    match open(&blob)? {
+        (CiphertextFormat::Legacy, _) => Ok(blob),
+        (_, payload) => Ok(payload.to_vec()),
+    }
+}
+
+/// Same as [`open`] on a shared buffer: the payload is a view into `blob`, no bytes are copied
+/// whatever the format.
+///
+/// The payload is only ever read as a byte stream by the tfhe-rs deserializer, hence the view
+/// does not need any particular alignment.
+pub fn open_bytes(blob: Bytes) -> Result<(CiphertextFormat, Bytes), FhevmError> {
+    let (format, payload) = open(&blob)?;
+    let offset = blob.len() - payload.len();
+    Ok((format, blob.slice(offset..)))
+}
+
+/// Re-encodes a stored ciphertext into the given format. Returns `None` if it already is.
+pub fn reencode(blob: &[u8], target: CiphertextFormat) -> Result<Option<Vec<u8>>, FhevmError> {
+    let (format, payload) = open(blob)?;
+    if format == target {
+        return Ok(None);
+    }
+    Ok(Some(seal_as(payload.to_vec(), target)))
+}
+
+/// Rewrites up to `limit` rows of the `ciphertexts` table that are not stored in the target
+/// format. Returns the number of migrated rows, 0 meaning the migration is complete.
+pub async fn migrate_ciphertexts(
+    pool: &PgPool,
+    target: CiphertextFormat,
+    limit: i64,
+) -> anyhow::Result<u64> {
+    let mut prefix = ENVELOPE_MAGIC.to_vec();
+    let query = match target {
+        CiphertextFormat::Legacy => {
+            "SELECT tenant_id, handle, ciphertext_version, ciphertext
+            FROM ciphertexts
+            WHERE substring(ciphertext FROM 1 FOR 4) = $1
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED"
+        }
+        _ => {
+            prefix.push(target as u8);
+            "SELECT tenant_id, handle, ciphertext_version, ciphertext
+            FROM ciphertexts
+            WHERE substring(ciphertext FROM 1 FOR 5) <> $1
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED"
+        }
+    };
+
+    let mut trx = pool.begin().await?;
+    let rows = sqlx::query(query)
+        .bind(prefix)
+        .bind(limit)
+        .fetch_all(trx.as_mut())
+        .await?;
+
+    let mut migrated = 0;
+    for row in rows {
+        let tenant_id: i32 = row.try_get("tenant_id")?;
+        let handle: Vec<u8> = row.try_get("handle")?;
+        let ciphertext_version: i16 = row.try_get("ciphertext_version")?;
+        let ciphertext: Vec<u8> = row.try_get("ciphertext")?;
+        let Some(ciphertext) = reencode(&ciphertext, target)? else {
+            continue;
+        };
+        sqlx::query(
+            "UPDATE ciphertexts
+            SET ciphertext = $1
+            WHERE tenant_id = $2 AND handle = $3 AND ciphertext_version = $4",
+        )
+        .bind(ciphertext)
+        .bind(tenant_id)
+        .bind(handle)
+        .bind(ciphertext_version)
+        .execute(trx.as_mut())
+        .await?;
+        migrated += 1;
+    }
+    trx.commit().await?;
+
+    info!(format = %target, migrated, "Migrated ciphertexts format");
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Starts like a tfhe-rs safe serialization: the little-endian length of the version string.
+    const PAYLOAD: &[u8] = &[5, 0, 0, 0, 0, 0, 0, 0, b'0', b'.', b'1', b'.', b'0', 42, 43];
+
+    #[test]
+    fn sealed_payloads_are_opened() {
+        for format in [CiphertextFormat::Legacy, CiphertextFormat::V1] {
+            let blob = seal_as(PAYLOAD.to_vec(), format);
+            assert_eq!(open(&blob).unwrap(), (format, PAYLOAD));
+            assert_eq!(into_payload(blob).unwrap(), PAYLOAD);
+        }
+
+        let blob = seal_as(PAYLOAD.to_vec(), CiphertextFormat::V1);
+        assert_eq!(blob.len(), ENVELOPE_HEADER_LEN + PAYLOAD.len());
+        assert!(blob.starts_with(&ENVELOPE_MAGIC));
+        assert_eq!(blob[ENVELOPE_MAGIC.len()], CiphertextFormat::V1 as u8);
+    }
+
+    #[test]
+    fn legacy_payloads_are_left_untouched() {
+        assert_eq!(seal_as(PAYLOAD.to_vec(), CiphertextFormat::Legacy), PAYLOAD);
+        assert_eq!(open(PAYLOAD).unwrap(), (CiphertextFormat::Legacy, PAYLOAD));
+        assert_eq!(open(&[]).unwrap(), (CiphertextFormat::Legacy, &[][..]));
+    }
+
+    #[test]
+    fn unknown_envelopes_are_rejected() {
+        let truncated = ENVELOPE_MAGIC.to_vec();
+        assert!(matches!(
+            open(&truncated),
+            Err(FhevmError::UnknownCiphertextFormat(None))
+        ));
+
+        for version in [CiphertextFormat::Legacy as u8, 2, u8::MAX] {
+            let mut blob = ENVELOPE_MAGIC.to_vec();
+            blob.push(version);
+            blob.extend_from_slice(PAYLOAD);
+            assert!(matches!(
+                open(&blob),
+                Err(FhevmError::UnknownCiphertextFormat(Some(v))) if v == version
+            ));
+            assert!(into_payload(blob).is_err());
+        }
+    }
+
+    #[test]
+    fn open_bytes_does_not_copy() {
+        let blob = Bytes::from(seal_as(PAYLOAD.to_vec(), CiphertextFormat::V1));
+        let (format, payload) = open_bytes(blob.clone()).unwrap();
+        assert_eq!(format, CiphertextFormat::V1);
+        assert_eq!(payload, PAYLOAD);
+        assert_eq!(payload.as_ptr(), blob[ENVELOPE_HEADER_LEN..].as_ptr());
+
+        let blob = Bytes::from_static(PAYLOAD);
+        let (format, payload) = open_bytes(blob.clone()).unwrap();
+        assert_eq!(format, CiphertextFormat::Legacy);
+        assert_eq!(payload.as_ptr(), blob.as_ptr());
+    }
+
+    #[test]
+    fn reencode_between_formats() {
+        let legacy = PAYLOAD.to_vec();
+        let v1 = reencode(&legacy, CiphertextFormat::V1).unwrap().unwrap();
+        assert_eq!(v1, seal_as(PAYLOAD.to_vec(), CiphertextFormat::V1));
+        assert_eq!(reencode(&v1, CiphertextFormat::V1).unwrap(), None);
+        assert_eq!(
+            reencode(&v1, CiphertextFormat::Legacy).unwrap(),
+            Some(legacy.clone())
+        );
+        assert_eq!(reencode(&legacy, CiphertextFormat::Legacy).unwrap(), None);
+    }
+
+    #[test]
+    fn format_names() {
+        for format in [CiphertextFormat::Legacy, CiphertextFormat::V1] {
+            assert_eq!(format.to_string().parse::<CiphertextFormat>(), Ok(format));
+            assert_eq!(CiphertextFormat::from_u8(format as u8), Some(format));
+        }
+        assert!("v2".parse::<CiphertextFormat>().is_err());
+        assert_eq!(CiphertextFormat::from_u8(2), None);
+    }
+}
@@ -0,0 +1,412 @@
+//! Envelope encryption of the sensitive columns of the queues, on top of the disk encryption of
+//! the database.
+//!
+//! Every value is encrypted with AES-256-GCM under a random data key, stored next to it wrapped
+//! by the column key. The column key comes from the secrets provider, mounted as a file or
+//! injected in an environment variable, see [`ColumnEncryption::from_secret`]. The key id is
+//! stored with every value, so that a value is never decrypted with the wrong key.
+//!
+//! Columns compared in queries, e.g. the delegator and delegate of a delegation, cannot take a
+//! random data key. They are encrypted deterministically instead, with a key and a nonce derived
+//! from the column key and the lowercase value, so that equal addresses give equal ciphertexts.
+//!
+//! Values written before the encryption was enabled are read as is, a value is only decrypted when
+//! it carries the encrypted value header. Lookups only match the rows written with the same
+//! setting, the delegation tables must be re-populated when the encryption is enabled.
+//!
+//! Encrypted columns:
+//! - `user_decryption_delegations` and `user_decryption_delegation_history`: `delegator` and
+//!   `delegate`, deterministic
+//! - `user_decryption_requests`: `user_address`, `delegator_address` and `public_key`
+//! - `decryption_responses`: `result`
+
+use std::fmt;
+use std::sync::Arc;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use sha3::{Digest, Sha3_256};
+use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Header of the encrypted binary values, version included.
+const BYTES_HEADER: [u8; 4] = [0xfe, b'c', b'e', 1];
+/// Prefixes of the encrypted text values, hex encoded after it.
+const TEXT_PREFIX: &str = "enc1:";
+const LOOKUP_PREFIX: &str = "encd1:";
+
+const KEY_ID_LEN: usize = 4;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const WRAPPED_KEY_LEN: usize = 32 + TAG_LEN;
+
+#[derive(Error, Debug)]
+pub enum ColumnEncryptionError {
+    #[error("Invalid column encryption secret: {0}")]
+    Secret(String),
+
+    #[error("Encrypted column value but no column encryption key is configured")]
+    NotConfigured,
+
+    #[error("Column value encrypted with key {found}, the configured key is {expected}")]
+    KeyMismatch { expected: String, found: String },
+
+    #[error("Malformed encrypted column value")]
+    Malformed,
+
+    #[error("Encrypted column value cannot be decrypted, altered or wrong key")]
+    Decryption,
+}
+
+/// The ciphers zeroize their round keys on drop.
+struct ColumnKey {
+    id: [u8; KEY_ID_LEN],
+    /// Wraps the data keys
+    cipher: Aes256Gcm,
+    /// Deterministic encryption of the lookup columns
+    lookup_cipher: Aes256Gcm,
+    lookup_nonce_key: [u8; 32],
+}
+
+impl Drop for ColumnKey {
+    fn drop(&mut self) {
+        self.lookup_nonce_key.zeroize();
+    }
+}
+
+/// Encryption of the designated columns, disabled by default, in which case values are written
+/// and read as is.
+#[derive(Clone, Default)]
+pub struct ColumnEncryption {
+    key: Option<Arc<ColumnKey>>,
+}
+
+impl fmt::Debug for ColumnEncryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ColumnEncryption")
+            .field("key_id", &self.key_id())
+            .finish()
+    }
+}
+
+fn derive(key: &[u8], label: &[u8]) -> [u8; 32] {
+    Sha3_256::new()
+        .chain_update(key)
+        .chain_update(label)
+        .finalize()
+        .into()
+}
+
+impl ColumnEncryption {
+    /// Loads the hex-encoded 32 bytes column key from the secrets provider, either
+    /// `file:<path>` for a mounted secret or `env:<variable>`.
+    pub fn from_secret(reference: &str) -> Result<Self, ColumnEncryptionError> {
+        let secret = Zeroizing::new(if let Some(path) = reference.strip_prefix("file:") {
+            std::fs::read_to_string(path)
+                .map_err(|err| ColumnEncryptionError::Secret(format!("{path}: {err}")))?
+        } else if let Some(variable) = reference.strip_prefix("env:") {
+            std::env::var(variable)
+                .map_err(|err| ColumnEncryptionError::Secret(format!("{variable}: {err}")))?
+        } else {
+            return Err(ColumnEncryptionError::Secret(format!(
+                "{reference}: expected file:<path> or env:<variable>"
+            )));
+        });
+        let key = Zeroizing::new(
+            hex::decode(secret.trim().trim_start_matches("0x"))
+                .map_err(|err| ColumnEncryptionError::Secret(err.to_string()))?,
+        );
+        Self::from_key(&key)
+    }
+
+    pub fn from_key(key: &[u8]) -> Result<Self, ColumnEncryptionError> {
+        if key.len() != 32 {
+            return Err(ColumnEncryptionError::Secret(format!(
+                "expected a 32 bytes key, got {} bytes",
+                key.len()
+            )));
+        }
+        let mut id = [0; KEY_ID_LEN];
+        id.copy_from_slice(&derive(key, b"key-id")[..KEY_ID_LEN]);
+        let lookup_key = Zeroizing::new(derive(key, b"lookup-key"));
+        Ok(Self {
+            key: Some(Arc::new(ColumnKey {
+                id,
+                cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+                lookup_cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(lookup_key.as_slice())),
+                lookup_nonce_key: derive(key, b"lookup-nonce"),
+            })),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Hex id of the column key, stored with the values it encrypts.
+    pub fn key_id(&self) -> Option<String> {
+        self.key.as_ref().map(|key| hex::encode(key.id))
+    }
+
+    /// Encrypts a binary value under a fresh data key.
+    pub fn encrypt(&self, value: &[u8]) -> Vec<u8> {
+        let Some(key) = &self.key else {
+            return value.to_vec();
+        };
+        let mut data_key = Aes256Gcm::generate_key(OsRng);
+        let wrap_nonce = Aes256Gcm::generate_nonce(OsRng);
+        let wrapped_key = key
+            .cipher
+            .encrypt(&wrap_nonce, data_key.as_slice())
+            .expect("data key wrapping cannot fail");
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+        let ciphertext = Aes256Gcm::new(&data_key)
+            .encrypt(&nonce, value)
+            .expect("column value too large to be encrypted");
+        data_key.as_mut_slice().zeroize();
+
+        let mut encrypted = Vec::with_capacity(
+            BYTES_HEADER.len() + KEY_ID_LEN + 2 * NONCE_LEN + WRAPPED_KEY_LEN + ciphertext.len(),
+        );
+        encrypted.extend_from_slice(&BYTES_HEADER);
+        encrypted.extend_from_slice(&key.id);
+        encrypted.extend_from_slice(&wrap_nonce);
+        encrypted.extend_from_slice(&wrapped_key);
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        encrypted
+    }
+
+    /// Decrypts a value written by [`Self::encrypt`], a value without the header is returned as is.
+    pub fn decrypt(&self, value: Vec<u8>) -> Result<Vec<u8>, ColumnEncryptionError> {
+        let Some(encrypted) = value.strip_prefix(&BYTES_HEADER) else {
+            return Ok(value);
+        };
+        let (key, encrypted) = self.key_for(encrypted)?;
+        if encrypted.len() < 2 * NONCE_LEN + WRAPPED_KEY_LEN + TAG_LEN {
+            return Err(ColumnEncryptionError::Malformed);
+        }
+        let (wrap_nonce, encrypted) = encrypted.split_at(NONCE_LEN);
+        let (wrapped_key, encrypted) = encrypted.split_at(WRAPPED_KEY_LEN);
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+        let data_key = Zeroizing::new(
+            key.cipher
+                .decrypt(Nonce::from_slice(wrap_nonce), wrapped_key)
+                .map_err(|_| ColumnEncryptionError::Decryption)?,
+        );
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key))
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| ColumnEncryptionError::Decryption)
+    }
+
+    /// Encrypts a text value under a fresh data key, hex encoded.
+    pub fn encrypt_text(&self, value: &str) -> String {
+        if !self.is_enabled() {
+            return value.to_owned();
+        }
+        let encrypted = self.encrypt(value.as_bytes());
+        format!(
+            "{TEXT_PREFIX}{}",
+            hex::encode(&encrypted[BYTES_HEADER.len()..])
+        )
+    }
+
+    /// Encrypts an address compared in queries, the same address always giving the same value.
+    /// The address is lowercased first, like the comparisons do.
+    pub fn encrypt_lookup(&self, value: &str) -> String {
+        let Some(key) = &self.key else {
+            return value.to_owned();
+        };
+        let value = value.to_lowercase();
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&derive(&key.lookup_nonce_key, value.as_bytes())[..NONCE_LEN]);
+        let ciphertext = key
+            .lookup_cipher
+            .encrypt(Nonce::from_slice(&nonce), value.as_bytes())
+            .expect("column value too large to be encrypted");
+        format!(
+            "{LOOKUP_PREFIX}{}{}{}",
+            hex::encode(key.id),
+            hex::encode(nonce),
+            hex::encode(ciphertext)
+        )
+    }
+
+    /// Decrypts a value written by [`Self::encrypt_text`] or [`Self::encrypt_lookup`], a value
+    /// without either prefix is returned as is.
+    pub fn decrypt_text(&self, value: String) -> Result<String, ColumnEncryptionError> {
+        let decrypted = if let Some(encrypted) = value.strip_prefix(TEXT_PREFIX) {
+            let encrypted = hex::decode(encrypted).map_err(|_| ColumnEncryptionError::Malformed)?;
+            self.decrypt([&BYTES_HEADER[..], &encrypted].concat())?
+        } else if let Some(encrypted) = value.strip_prefix(LOOKUP_PREFIX) {
+            let encrypted = hex::decode(encrypted).map_err(|_| ColumnEncryptionError::Malformed)?;
+            let (key, encrypted) = self.key_for(&encrypted)?;
+            if encrypted.len() < NONCE_LEN + TAG_LEN {
+                return Err(ColumnEncryptionError::Malformed);
+            }
+            let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+            key.lookup_cipher
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| ColumnEncryptionError::Decryption)?
+        } else {
+            return Ok(value);
+        };
+        String::from_utf8(decrypted).map_err(|_| ColumnEncryptionError::Malformed)
+    }
+
+    /// Checks the key id in front of an encrypted value, returns the key and the rest of the value.
+    fn key_for<'a>(
+        &self,
+        encrypted: &'a [u8],
+    ) -> Result<(&ColumnKey, &'a [u8]), ColumnEncryptionError> {
+        let key = self
+            .key
+            .as_deref()
+            .ok_or(ColumnEncryptionError::NotConfigured)?;
+        let (id, encrypted) = encrypted
+            .split_at_checked(KEY_ID_LEN)
+            .ok_or(ColumnEncryptionError::Malformed)?;
+        if id != key.id {
+            return Err(ColumnEncryptionError::KeyMismatch {
+                expected: hex::encode(key.id),
+                found: hex::encode(id),
+            });
+        }
+        Ok((key, encrypted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn enabled() -> ColumnEncryption {
+        ColumnEncryption::from_key(&KEY).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let enc = enabled();
+        let encrypted = enc.encrypt(b"decryption result");
+        assert!(encrypted.starts_with(&BYTES_HEADER));
+        assert_ne!(&encrypted[BYTES_HEADER.len()..], b"decryption result");
+        // Fresh data key and nonces for every value
+        assert_ne!(encrypted, enc.encrypt(b"decryption result"));
+        assert_eq!(enc.decrypt(encrypted).unwrap(), b"decryption result");
+
+        let encrypted = enc.encrypt_text("0xabcdef");
+        assert!(encrypted.starts_with(TEXT_PREFIX));
+        assert_eq!(enc.decrypt_text(encrypted).unwrap(), "0xabcdef");
+    }
+
+    #[test]
+    fn key_mismatch() {
+        let other = ColumnEncryption::from_key(&[8; 32]).unwrap();
+        let encrypted = enabled().encrypt(b"value");
+        assert!(matches!(
+            other.decrypt(encrypted),
+            Err(ColumnEncryptionError::KeyMismatch { expected, found })
+                if Some(expected.as_str()) == other.key_id().as_deref()
+                    && Some(found.as_str()) == enabled().key_id().as_deref()
+        ));
+        let encrypted = enabled().encrypt_lookup("0xabcdef");
+        assert!(matches!(
+            other.decrypt_text(encrypted),
+            Err(ColumnEncryptionError::KeyMismatch { .. })
+        ));
+        assert!(matches!(
+            ColumnEncryption::default().decrypt(enabled().encrypt(b"value")),
+            Err(ColumnEncryptionError::NotConfigured)
+        ));
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let enc = enabled();
+        let encrypted = enc.encrypt(b"value");
+        // Wrapped data key, then value ciphertext
+        for idx in [
+            BYTES_HEADER.len() + KEY_ID_LEN + NONCE_LEN,
+            encrypted.len() - 1,
+        ] {
+            let mut tampered = encrypted.clone();
+            tampered[idx] ^= 1;
+            assert!(matches!(
+                enc.decrypt(tampered),
+                Err(ColumnEncryptionError::Decryption)
+            ));
+        }
+        assert!(matches!(
+            enc.decrypt(encrypted[..encrypted.len() - 20].to_vec()),
+            Err(ColumnEncryptionError::Malformed)
+        ));
+
+        let mut tampered = enc.encrypt_lookup("0xabcdef");
+        let last = if tampered.ends_with('0') { "1" } else { "0" };
+        tampered.replace_range(tampered.len() - 1.., last);
+        assert!(matches!(
+            enc.decrypt_text(tampered),
+            Err(ColumnEncryptionError::Decryption)
+        ));
+    }
+
+    #[test]
+    fn legacy_plaintext_passthrough() {
+        let enc = enabled();
+        assert_eq!(enc.decrypt(b"plain".to_vec()).unwrap(), b"plain");
+        assert_eq!(enc.decrypt_text("0xabcdef".to_owned()).unwrap(), "0xabcdef");
+
+        let disabled = ColumnEncryption::default();
+        assert!(!disabled.is_enabled());
+        assert_eq!(disabled.key_id(), None);
+        assert_eq!(disabled.encrypt(b"plain"), b"plain");
+        assert_eq!(disabled.encrypt_text("0xabcdef"), "0xabcdef");
+        assert_eq!(disabled.encrypt_lookup("0xABCDEF"), "0xABCDEF");
+        assert_eq!(disabled.decrypt(b"plain".to_vec()).unwrap(), b"plain");
+    }
+
+    #[test]
+    fn lookup_is_deterministic_and_case_normalised() {
+        let enc = enabled();
+        let lookup = enc.encrypt_lookup("0xAbCdEf");
+        assert!(lookup.starts_with(LOOKUP_PREFIX));
+        assert_eq!(lookup, enc.encrypt_lookup("0xabcdef"));
+        assert_eq!(lookup, enc.encrypt_lookup("0xABCDEF"));
+        assert_ne!(lookup, enc.encrypt_lookup("0xabcdee"));
+        assert_eq!(enc.decrypt_text(lookup).unwrap(), "0xabcdef");
+    }
+
+    #[test]
+    fn from_secret_parsing() {
+        let hex_key = hex::encode(KEY);
+        let path =
+            std::env::temp_dir().join(format!("column_encryption_test_{}.key", std::process::id()));
+        std::fs::write(&path, format!("0x{hex_key}\n")).unwrap();
+        let from_file = ColumnEncryption::from_secret(&format!("file:{}", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(from_file.unwrap().key_id(), enabled().key_id());
+
+        let variable = "COLUMN_ENCRYPTION_TEST_KEY";
+        std::env::set_var(variable, &hex_key);
+        let from_env = ColumnEncryption::from_secret(&format!("env:{variable}")).unwrap();
+        assert_eq!(from_env.key_id(), enabled().key_id());
+        std::env::set_var(variable, "zz");
+        assert!(ColumnEncryption::from_secret(&format!("env:{variable}")).is_err());
+        std::env::set_var(variable, hex::encode([7; 16]));
+        assert!(ColumnEncryption::from_secret(&format!("env:{variable}")).is_err());
+        std::env::remove_var(variable);
+
+        for reference in [
+            "env:COLUMN_ENCRYPTION_TEST_UNSET",
+            "file:/nonexistent/column.key",
+            hex_key.as_str(),
+        ] {
+            assert!(matches!(
+                ColumnEncryption::from_secret(reference),
+                Err(ColumnEncryptionError::Secret(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Timeout, retry and slow-query logging of individual database queries.
+//!
+//! A query stuck on a lock or a lost connection used to hold a whole worker or transaction-sender
+//! cycle until the connection was dropped by the OS. [`run_query`] bounds each attempt of a query
+//! with a timeout and retries the errors that are expected to go away and that guarantee the query
+//! had no effect: serialization failures, deadlocks and pool timeouts. A connection lost while
+//! running the query is not retried, as the query may have been committed before the
+//! acknowledgement was lost and many of the queries are not idempotent, e.g. incrementing a retry
+//! counter. A timed out query is not retried either, as it would most likely time out again while
+//! adding load to the database. [`timed_query`] only applies the timeout and
+//! the logging, for the queries run inside a transaction that cannot be retried on their own, an
+//! error aborting the transaction.
+
+use std::future::Future;
+use std::io;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use tracing::{debug, warn};
+
+const CODE_SERIALIZATION_FAILURE: &str = "40001";
+const CODE_DEADLOCK_DETECTED: &str = "40P01";
+
+static QUERY_RETRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_db_query_retry_counter",
+        "Number of retries of a database query after a transient error",
+        &["query"]
+    )
+    .unwrap()
+});
+
+static QUERY_TIMEOUTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_db_query_timeout_counter",
+        "Number of database queries that timed out",
+        &["query"]
+    )
+    .unwrap()
+});
+
+static SLOW_QUERIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_db_slow_query_counter",
+        "Number of database queries slower than the slow query threshold",
+        &["query"]
+    )
+    .unwrap()
+});
+
+#[derive(Clone, Copy, Debug)]
+pub struct QueryPolicy {
+    /// Timeout of each attempt of a query.
+    pub timeout: Duration,
+    /// Attempts of a query failing with a transient error, at least 1.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each next one.
+    pub retry_backoff: Duration,
+    /// Queries taking longer are logged.
+    pub slow_query_threshold: Duration,
+}
+
+impl Default for QueryPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_attempts: 3,
+            retry_backoff: Duration::from_millis(100),
+            slow_query_threshold: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Whether the error is expected to go away when running the query again, the failed attempt
+/// having been rolled back or never sent.
+pub fn is_retryable(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(db_err) => db_err.code().is_some_and(|code| {
+            code == CODE_SERIALIZATION_FAILURE || code == CODE_DEADLOCK_DETECTED
+        }),
+        _ => false,
+    }
+}
+
+/// Runs the query built by `query`, retrying it on transient errors, see the module
+/// documentation. `name` identifies the query in the logs and metrics.
+///
+/// Only for queries that can be run again on their own, i.e. not within a transaction.
+pub async fn run_query<T, F, Fut>(
+    name: &'static str,
+    policy: &QueryPolicy,
+    mut query: F,
+) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut backoff = policy.retry_backoff;
+    let mut attempt = 1;
+    loop {
+        match timed_query(name, policy, query()).await {
+            Ok(res) => return Ok(res),
+            Err(err) if attempt < policy.max_attempts && is_retryable(&err) => {
+                warn!(query = name, error = %err, attempt, "Transient query error, retrying");
+                QUERY_RETRIES.with_label_values(&[name]).inc();
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Runs the query with the timeout of the policy and logs it if slow, without any retry.
+pub async fn timed_query<T>(
+    name: &'static str,
+    policy: &QueryPolicy,
+    query: impl Future<Output = Result<T, sqlx::Error>>,
+) -> Result<T, sqlx::Error> {
+    let started_at = Instant::now();
+    let res = match tokio::time::timeout(policy.timeout, query).await {
+        Ok(res) => res,
+        Err(_) => {
+            warn!(query = name, timeout = ?policy.timeout, "Query timed out");
+            QUERY_TIMEOUTS.with_label_values(&[name]).inc();
+            return Err(sqlx::Error::Io(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("query {name} timed out after {:?}", policy.timeout),
+            )));
+        }
+    };
+    let elapsed = started_at.elapsed();
+    if elapsed >= policy.slow_query_threshold {
+        warn!(query = name, elapsed = ?elapsed, "Slow query");
+        SLOW_QUERIES.with_label_values(&[name]).inc();
+    } else {
+        debug!(query = name, elapsed = ?elapsed, "Query done");
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::error::Error;
+    use std::fmt;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use sqlx::error::{DatabaseError, ErrorKind};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct TestDatabaseError(&'static str);
+
+    impl fmt::Display for TestDatabaseError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "database error {}", self.0)
+        }
+    }
+
+    impl Error for TestDatabaseError {}
+
+    impl DatabaseError for TestDatabaseError {
+        fn message(&self) -> &str {
+            self.0
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.0))
+        }
+
+        fn as_error(&self) -> &(dyn Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    fn database_error(code: &'static str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(TestDatabaseError(code)))
+    }
+
+    fn policy() -> QueryPolicy {
+        QueryPolicy {
+            retry_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_the_rolled_back_errors_are_retryable() {
+        assert!(is_retryable(&database_error(CODE_SERIALIZATION_FAILURE)));
+        assert!(is_retryable(&database_error(CODE_DEADLOCK_DETECTED)));
+        assert!(is_retryable(&sqlx::Error::PoolTimedOut));
+
+        // The query may have been committed before the connection was lost
+        assert!(!is_retryable(&database_error("57P01")));
+        assert!(!is_retryable(&database_error("08006")));
+        assert!(!is_retryable(&sqlx::Error::Io(io::Error::from(
+            io::ErrorKind::ConnectionReset
+        ))));
+        assert!(!is_retryable(&sqlx::Error::Protocol(
+            "unexpected message".into()
+        )));
+        assert!(!is_retryable(&sqlx::Error::Io(io::Error::from(
+            io::ErrorKind::TimedOut
+        ))));
+        assert!(!is_retryable(&database_error("23505")));
+        assert!(!is_retryable(&sqlx::Error::RowNotFound));
+    }
+
+    #[tokio::test]
+    async fn run_query_retries_the_retryable_errors() {
+        let attempts = &AtomicU32::new(0);
+        let res = run_query("test.retryable", &policy(), || async move {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(database_error(CODE_DEADLOCK_DETECTED)),
+                1 => Err(database_error(CODE_SERIALIZATION_FAILURE)),
+                attempt => Ok(attempt),
+            }
+        })
+        .await;
+        assert_eq!(res.unwrap(), 2);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn run_query_stops_after_the_max_attempts() {
+        let attempts = &AtomicU32::new(0);
+        let res: Result<(), _> = run_query("test.max_attempts", &policy(), || async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(database_error(CODE_DEADLOCK_DETECTED))
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), policy().max_attempts);
+    }
+
+    #[tokio::test]
+    async fn run_query_does_not_retry_a_lost_connection() {
+        let attempts = &AtomicU32::new(0);
+        let res: Result<(), _> = run_query("test.lost_connection", &policy(), || async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(sqlx::Error::Io(io::Error::from(
+                io::ErrorKind::ConnectionReset,
+            )))
+        })
+        .await;
+        assert!(matches!(res, Err(sqlx::Error::Io(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn run_query_does_not_retry_a_timeout() {
+        let policy = QueryPolicy {
+            timeout: Duration::from_millis(10),
+            ..policy()
+        };
+        let attempts = &AtomicU32::new(0);
+        let res: Result<(), _> = run_query("test.timeout", &policy, || async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(())
+        })
+        .await;
+        let Err(sqlx::Error::Io(err)) = res else {
+            panic!("the query did not time out: {res:?}");
+        };
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,355 @@
+//! Diagnostics snapshot dumped when a worker panics or exits on a fatal error.
+//!
+//! The workers describe what they are doing as they go: the current batch, the rows they claimed
+//! with [`set`], the operations in flight, e.g. S3 uploads, with [`track`]. Once [`install`]ed, a
+//! panic hook dumps that state with the memory statistics of the process, before the default
+//! backtrace, and [`dump`] does the same before exiting on a fatal error. The snapshot is logged
+//! and, if a directory is configured, written to `<dir>/<service>-<pid>-<timestamp>-<index>.json`.
+//!
+//! Snapshots are bounded: every section keeps its first [`MAX_ITEMS`] items, and its total count,
+//! and at most [`MAX_DUMPS`] snapshots are dumped by a process, the panics of the tokio tasks not
+//! stopping it.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::{allocator, buffer_pool};
+
+/// Items kept per section.
+pub const MAX_ITEMS: usize = 100;
+/// Snapshots dumped per process.
+pub const MAX_DUMPS: usize = 10;
+/// Characters kept per item.
+const MAX_ITEM_LEN: usize = 256;
+
+#[derive(Clone, Debug, Default)]
+pub struct DiagnosticsConfig {
+    pub service: String,
+    /// Directory the snapshots are written to, only logged if unset
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+struct Section {
+    total: usize,
+    items: Vec<String>,
+}
+
+struct InFlight {
+    description: String,
+    started_at: Instant,
+}
+
+static CONFIG: OnceLock<DiagnosticsConfig> = OnceLock::new();
+static SECTIONS: LazyLock<Mutex<BTreeMap<&'static str, Section>>> = LazyLock::new(Default::default);
+static IN_FLIGHT: LazyLock<Mutex<BTreeMap<&'static str, BTreeMap<u64, InFlight>>>> =
+    LazyLock::new(Default::default);
+static NEXT_IN_FLIGHT_ID: AtomicU64 = AtomicU64::new(0);
+static DUMPS: AtomicUsize = AtomicUsize::new(0);
+
+fn bounded(item: impl Display) -> String {
+    let mut item = item.to_string();
+    if item.len() > MAX_ITEM_LEN {
+        let mut end = MAX_ITEM_LEN;
+        while !item.is_char_boundary(end) {
+            end -= 1;
+        }
+        item.truncate(end);
+        item.push_str("..");
+    }
+    item
+}
+
+/// Installs the panic hook dumping the snapshot, once per process.
+pub fn install(conf: DiagnosticsConfig) {
+    if CONFIG.set(conf).is_err() {
+        return;
+    }
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        dump(&format!("panic: {panic_info}"));
+        previous(panic_info);
+    }));
+    info!("Diagnostics snapshot installed");
+}
+
+/// Replaces the items of the section, e.g. the ids of the current batch.
+pub fn set<T: Display>(name: &'static str, items: impl IntoIterator<Item = T>) {
+    let mut section = Section::default();
+    for item in items {
+        if section.total < MAX_ITEMS {
+            section.items.push(bounded(item));
+        }
+        section.total += 1;
+    }
+    if let Ok(mut sections) = SECTIONS.lock() {
+        sections.insert(name, section);
+    }
+}
+
+/// Operation reported in flight in its section until dropped.
+pub struct InFlightGuard {
+    section: &'static str,
+    id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = IN_FLIGHT.lock() {
+            if let Some(operations) = in_flight.get_mut(self.section) {
+                operations.remove(&self.id);
+            }
+        }
+    }
+}
+
+/// Reports the operation in flight until the guard is dropped, e.g. an S3 upload.
+pub fn track(section: &'static str, description: impl Display) -> InFlightGuard {
+    let id = NEXT_IN_FLIGHT_ID.fetch_add(1, Ordering::Relaxed);
+    if let Ok(mut in_flight) = IN_FLIGHT.lock() {
+        in_flight.entry(section).or_default().insert(
+            id,
+            InFlight {
+                description: bounded(description),
+                started_at: Instant::now(),
+            },
+        );
+    }
+    InFlightGuard { section, id }
+}
+
+#[derive(Serialize, Default)]
+struct MemoryStats {
+    /// From /proc/self/status, in bytes
+    rss_bytes: Option<u64>,
+    peak_rss_bytes: Option<u64>,
+    virtual_bytes: Option<u64>,
+    buffer_pool_retained_bytes: usize,
+    allocator: &'static str,
+    allocator_bytes: BTreeMap<&'static str, u64>,
+}
+
+fn memory_stats() -> MemoryStats {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+    let kilobytes = |field: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(field))
+            .and_then(|value| {
+                value
+                    .trim()
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+            .map(|value| value * 1024)
+    };
+    MemoryStats {
+        rss_bytes: kilobytes("VmRSS:"),
+        peak_rss_bytes: kilobytes("VmHWM:"),
+        virtual_bytes: kilobytes("VmSize:"),
+        buffer_pool_retained_bytes: buffer_pool::retained_bytes(),
+        allocator: allocator::ALLOCATOR,
+        allocator_bytes: allocator::stats().into_iter().collect(),
+    }
+}
+
+#[derive(Serialize)]
+struct Snapshot {
+    service: String,
+    pid: u32,
+    timestamp: u64,
+    reason: String,
+    thread: Option<String>,
+    memory: MemoryStats,
+    sections: BTreeMap<&'static str, Section>,
+    in_flight: BTreeMap<&'static str, Section>,
+}
+
+fn snapshot(reason: &str) -> Snapshot {
+    // try_lock, the dump may run while a lock is held by the panicking thread
+    let sections = SECTIONS
+        .try_lock()
+        .map(|sections| sections.clone())
+        .unwrap_or_default();
+    let in_flight = IN_FLIGHT
+        .try_lock()
+        .map(|in_flight| {
+            in_flight
+                .iter()
+                .filter(|(_, operations)| !operations.is_empty())
+                .map(|(section, operations)| {
+                    let items = operations
+                        .values()
+                        .take(MAX_ITEMS)
+                        .map(|operation| {
+                            format!(
+                                "{} for {:.1}s",
+                                operation.description,
+                                operation.started_at.elapsed().as_secs_f64()
+                            )
+                        })
+                        .collect();
+                    let section_snapshot = Section {
+                        total: operations.len(),
+                        items,
+                    };
+                    (*section, section_snapshot)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Snapshot {
+        service: CONFIG
+            .get()
+            .map(|conf| conf.service.clone())
+            .unwrap_or_default(),
+        pid: std::process::id(),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        reason: bounded(reason),
+        thread: std::thread::current().name().map(str::to_owned),
+        memory: memory_stats(),
+        sections,
+        in_flight,
+    }
+}
+
+/// Logs the snapshot and writes it to the configured directory, returns its path if written.
+pub fn dump(reason: &str) -> Option<PathBuf> {
+    let index = DUMPS.fetch_add(1, Ordering::Relaxed);
+    if index >= MAX_DUMPS {
+        return None;
+    }
+    let snapshot = snapshot(reason);
+    let json = match serde_json::to_string(&snapshot) {
+        Ok(json) => json,
+        Err(err) => {
+            error!(error = %err, "Failed to serialize the diagnostics snapshot");
+            return None;
+        }
+    };
+    error!(snapshot = json, "Diagnostics snapshot");
+
+    let dir = CONFIG.get().and_then(|conf| conf.dir.as_ref())?;
+    let path = dir.join(format!(
+        "{}-{}-{}-{index}.json",
+        snapshot.service, snapshot.pid, snapshot.timestamp
+    ));
+    match std::fs::create_dir_all(dir).and_then(|()| std::fs::write(&path, &json)) {
+        Ok(()) => {
+            error!(path = %path.display(), "Diagnostics snapshot written");
+            Some(path)
+        }
+        Err(err) => {
+            error!(path = %path.display(), error = %err, "Failed to write the diagnostics snapshot");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The state is global and the snapshot does not wait for its locks, the tests using it run
+    /// one at a time, each with its own sections
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    fn serial() -> std::sync::MutexGuard<'static, ()> {
+        SERIAL.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn section(name: &str) -> Option<Section> {
+        snapshot("test").sections.get(name).cloned()
+    }
+
+    fn in_flight(name: &str) -> Option<Section> {
+        snapshot("test").in_flight.get(name).cloned()
+    }
+
+    #[test]
+    fn items_are_bounded() {
+        assert_eq!(bounded("handle"), "handle");
+        let item = bounded("a".repeat(MAX_ITEM_LEN + 1));
+        assert_eq!(item, format!("{}..", "a".repeat(MAX_ITEM_LEN)));
+        // Truncated on a character boundary
+        let item = bounded(format!("a{}", "é".repeat(MAX_ITEM_LEN)));
+        assert_eq!(item.len(), MAX_ITEM_LEN - 1 + 2);
+        assert!(item.ends_with("é.."));
+    }
+
+    #[test]
+    fn sections_keep_their_first_items_and_count() {
+        let _serial = serial();
+        set("test_batch", 0..MAX_ITEMS + 5);
+        let batch = section("test_batch").unwrap();
+        assert_eq!(batch.total, MAX_ITEMS + 5);
+        assert_eq!(batch.items.len(), MAX_ITEMS);
+        assert_eq!(batch.items[0], "0");
+
+        // Replaced by the next batch
+        set("test_batch", ["tx1", "tx2"]);
+        let batch = section("test_batch").unwrap();
+        assert_eq!(batch.total, 2);
+        assert_eq!(batch.items, vec!["tx1", "tx2"]);
+    }
+
+    #[test]
+    fn operations_are_in_flight_until_dropped() {
+        let _serial = serial();
+        let upload = track("test_uploads", "upload 0x01");
+        let other = track("test_uploads", "upload 0x02");
+        let uploads = in_flight("test_uploads").unwrap();
+        assert_eq!(uploads.total, 2);
+        assert!(uploads.items[0].starts_with("upload 0x01 for "));
+        assert!(uploads.items[1].starts_with("upload 0x02 for "));
+
+        drop(upload);
+        let uploads = in_flight("test_uploads").unwrap();
+        assert_eq!(uploads.total, 1);
+        assert!(uploads.items[0].starts_with("upload 0x02 for "));
+
+        // Sections without operations are left out
+        drop(other);
+        assert!(in_flight("test_uploads").is_none());
+    }
+
+    #[test]
+    fn dump_writes_the_snapshot() {
+        let _serial = serial();
+        let dir = std::env::temp_dir().join(format!("diagnostics-{}", std::process::id()));
+        CONFIG
+            .set(DiagnosticsConfig {
+                service: "test-service".to_owned(),
+                dir: Some(dir.clone()),
+            })
+            .unwrap();
+        set("test_dump", ["tx1"]);
+        let _upload = track("test_dump", "upload 0x01");
+
+        let path = dump("fatal error").unwrap();
+        assert!(path.starts_with(&dir));
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        assert!(file_name.starts_with(&format!("test-service-{}-", std::process::id())));
+
+        let snapshot: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(snapshot["service"], "test-service");
+        assert_eq!(snapshot["reason"], "fatal error");
+        assert_eq!(snapshot["sections"]["test_dump"]["items"][0], "tx1");
+        assert_eq!(snapshot["in_flight"]["test_dump"]["total"], 1);
+        assert!(snapshot["memory"]["rss_bytes"].as_u64().unwrap() > 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
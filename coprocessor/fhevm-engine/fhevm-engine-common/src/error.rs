@@ -0,0 +1,129 @@
+use alloy::primitives::Bytes;
+use alloy::providers::PendingTransactionError;
+use alloy::sol_types::SolInterface;
+use alloy::transports::{RpcError, TransportError, TransportErrorKind};
+use thiserror::Error;
+
+use crate::types::FhevmError;
+use crate::write_batcher::WriteBatchError;
+
+/// Structured error shared by the engine crates.
+///
+/// The variant tells the caller how to handle the error (retry, drop, alert) without matching on
+/// error strings. `anyhow` is only meant to be used at binary boundaries.
+#[derive(Error, Debug)]
+pub enum FhevmEngineError {
+    /// The RPC node could not be reached or did not answer properly.
+    #[error("Transport error: {0}")]
+    Transport(#[source] TransportError),
+
+    /// A contract call or transaction reverted. `reason` is the decoded contract error when the
+    /// contract interface is known.
+    #[error("Contract reverted: {reason}")]
+    ContractRevert { reason: String, data: Option<Bytes> },
+
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    /// A write queued to a `WriteBatcher` failed with its batch, or the batcher stopped.
+    #[error(transparent)]
+    WriteBatch(#[from] WriteBatchError),
+
+    /// S3 or any other blob storage.
+    #[error("Storage error: {0}")]
+    Storage(String),
+
+    /// Invalid input data, retrying will not help.
+    #[error("Validation error: {0}")]
+    Validation(String),
+
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+impl FhevmEngineError {
+    /// Converts a transport error, decoding revert data with the given contract errors interface.
+    pub fn from_rpc<E: SolInterface + std::fmt::Debug>(err: TransportError) -> Self {
+        let decoded = err
+            .as_error_resp()
+            .and_then(|payload| payload.as_decoded_interface_error::<E>());
+        match decoded {
+            Some(contract_error) => Self::ContractRevert {
+                reason: format!("{contract_error:?}"),
+                data: err.as_error_resp().and_then(|p| p.as_revert_data()),
+            },
+            None => err.into(),
+        }
+    }
+
+    /// The connection to the node is lost for good, i.e. the provider exhausted its own retries.
+    pub fn is_backend_gone(&self) -> bool {
+        matches!(
+            self,
+            Self::Transport(RpcError::Transport(TransportErrorKind::BackendGone))
+        )
+    }
+
+    /// Transient errors that are expected to go away by themselves and can be retried without
+    /// limit. Local usage errors are included as they might be transient due to external AWS KMS
+    /// signers.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Transport(RpcError::Transport(kind)) => {
+                kind.is_retry_err() || matches!(kind, TransportErrorKind::BackendGone)
+            }
+            Self::Transport(RpcError::LocalUsageError(_)) => true,
+            Self::Database(e) => is_transient_database_error(e),
+            Self::WriteBatch(WriteBatchError::Database(e)) => is_transient_database_error(e),
+            Self::Storage(_) => true,
+            _ => false,
+        }
+    }
+}
+
+fn is_transient_database_error(err: &sqlx::Error) -> bool {
+    matches!(
+        err,
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed
+    )
+}
+
+impl From<TransportError> for FhevmEngineError {
+    fn from(err: TransportError) -> Self {
+        match err.as_error_resp().and_then(|p| p.as_revert_data()) {
+            Some(data) => Self::ContractRevert {
+                reason: err.to_string(),
+                data: Some(data),
+            },
+            None => Self::Transport(err),
+        }
+    }
+}
+
+impl From<PendingTransactionError> for FhevmEngineError {
+    fn from(err: PendingTransactionError) -> Self {
+        match err {
+            PendingTransactionError::TransportError(e) => e.into(),
+            // Receipt watching failures, e.g. timeouts, are a transport issue as well.
+            other => Self::Transport(TransportErrorKind::custom(other)),
+        }
+    }
+}
+
+impl From<FhevmError> for FhevmEngineError {
+    fn from(err: FhevmError) -> Self {
+        Self::Validation(err.to_string())
+    }
+}
+
+impl From<alloy::signers::Error> for FhevmEngineError {
+    fn from(err: alloy::signers::Error) -> Self {
+        Self::Internal(format!("signer: {err}"))
+    }
+}
+
+impl From<tokio::task::JoinError> for FhevmEngineError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Self::Internal(err.to_string())
+    }
+}
@@ -0,0 +1,271 @@
+//! Typed ciphertext handle.
+//!
+//! A handle is 32 bytes laid out as follows:
+//!
+//! | bytes    | content                                                    |
+//! |----------|------------------------------------------------------------|
+//! | `0..21`  | truncated keccak256 hash                                   |
+//! | `21`     | index of the ciphertext in its input list, 255 if computed |
+//! | `22..30` | chain id, big endian                                       |
+//! | `30`     | FHE type                                                   |
+//! | `31`     | ciphertext version                                         |
+//!
+//! Conversions from bytes only check the length, the metadata bytes are not
+//! validated. The handles of the legacy gRPC input API do not follow this
+//! layout: they keep the index at byte 29 and carry no chain id.
+
+use std::fmt;
+use std::str::FromStr;
+
+use alloy::primitives::FixedBytes;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef};
+use sqlx::{Decode, Encode, Postgres, Type};
+
+use crate::types::{FhevmError, HANDLE_LEN};
+
+const INDEX_BYTE: usize = 21;
+const CHAIN_ID_BYTES: std::ops::Range<usize> = 22..30;
+const FHE_TYPE_BYTE: usize = 30;
+const VERSION_BYTE: usize = 31;
+
+/// Index byte of handles produced by a computation rather than by an input proof.
+pub const COMPUTED_HANDLE_INDEX: u8 = u8::MAX;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TypedHandle([u8; HANDLE_LEN]);
+
+impl TypedHandle {
+    /// Builds a handle from a 32-byte hash, overwriting its trailing bytes with the metadata.
+    pub fn from_hash(
+        hash: [u8; HANDLE_LEN],
+        index: u8,
+        chain_id: u64,
+        fhe_type: i16,
+        version: u8,
+    ) -> Self {
+        let mut bytes = hash;
+        bytes[INDEX_BYTE] = index;
+        bytes[CHAIN_ID_BYTES].copy_from_slice(&chain_id.to_be_bytes());
+        bytes[FHE_TYPE_BYTE] = fhe_type as u8;
+        bytes[VERSION_BYTE] = version;
+        Self(bytes)
+    }
+
+    pub fn index(&self) -> u8 {
+        self.0[INDEX_BYTE]
+    }
+
+    pub fn is_computed(&self) -> bool {
+        self.index() == COMPUTED_HANDLE_INDEX
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        let mut chain_id = [0u8; 8];
+        chain_id.copy_from_slice(&self.0[CHAIN_ID_BYTES]);
+        u64::from_be_bytes(chain_id)
+    }
+
+    /// FHE type, as stored in the `ciphertexts.ciphertext_type` column.
+    pub fn fhe_type(&self) -> i16 {
+        self.0[FHE_TYPE_BYTE] as i16
+    }
+
+    pub fn version(&self) -> u8 {
+        self.0[VERSION_BYTE]
+    }
+
+    pub fn as_bytes(&self) -> &[u8; HANDLE_LEN] {
+        &self.0
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+impl AsRef<[u8]> for TypedHandle {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<[u8; HANDLE_LEN]> for TypedHandle {
+    fn from(bytes: [u8; HANDLE_LEN]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl TryFrom<&[u8]> for TypedHandle {
+    type Error = FhevmError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        bytes
+            .try_into()
+            .map(Self)
+            .map_err(|_| FhevmError::InvalidHandle)
+    }
+}
+
+impl TryFrom<Vec<u8>> for TypedHandle {
+    type Error = FhevmError;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::try_from(bytes.as_slice())
+    }
+}
+
+impl From<TypedHandle> for Vec<u8> {
+    fn from(handle: TypedHandle) -> Self {
+        handle.0.to_vec()
+    }
+}
+
+impl From<FixedBytes<HANDLE_LEN>> for TypedHandle {
+    fn from(bytes: FixedBytes<HANDLE_LEN>) -> Self {
+        Self(bytes.0)
+    }
+}
+
+impl From<TypedHandle> for FixedBytes<HANDLE_LEN> {
+    fn from(handle: TypedHandle) -> Self {
+        FixedBytes(handle.0)
+    }
+}
+
+/// Parses a hex handle, with or without the `0x` prefix.
+impl FromStr for TypedHandle {
+    type Err = FhevmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        let bytes = hex::decode(s).map_err(|_| FhevmError::InvalidHandle)?;
+        Self::try_from(bytes.as_slice())
+    }
+}
+
+impl fmt::Display for TypedHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for TypedHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TypedHandle({self})")
+    }
+}
+
+impl Serialize for TypedHandle {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TypedHandle {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+// Handles are stored as BYTEA.
+impl Type<Postgres> for TypedHandle {
+    fn type_info() -> PgTypeInfo {
+        <Vec<u8> as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <Vec<u8> as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl PgHasArrayType for TypedHandle {
+    fn array_type_info() -> PgTypeInfo {
+        <Vec<u8> as PgHasArrayType>::array_type_info()
+    }
+}
+
+impl Encode<'_, Postgres> for TypedHandle {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        <&[u8] as Encode<Postgres>>::encode(self.0.as_slice(), buf)
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for TypedHandle {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let bytes = <&[u8] as Decode<Postgres>>::decode(value)?;
+        Ok(Self::try_from(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> TypedHandle {
+        TypedHandle::from_hash([0xab; HANDLE_LEN], 3, 12345, 5, 1)
+    }
+
+    #[test]
+    fn from_hash_sets_the_metadata() {
+        let handle = sample();
+        assert_eq!(handle.index(), 3);
+        assert!(!handle.is_computed());
+        assert_eq!(handle.chain_id(), 12345);
+        assert_eq!(handle.fhe_type(), 5);
+        assert_eq!(handle.version(), 1);
+        // The hash is truncated to the bytes before the metadata
+        assert_eq!(handle.as_bytes()[..INDEX_BYTE], [0xab; INDEX_BYTE]);
+        assert_eq!(handle.as_bytes()[22..30], 12345u64.to_be_bytes());
+
+        let computed = TypedHandle::from_hash([0; HANDLE_LEN], COMPUTED_HANDLE_INDEX, 1, 0, 0);
+        assert!(computed.is_computed());
+    }
+
+    #[test]
+    fn bytes_conversions() {
+        let handle = sample();
+        let bytes: Vec<u8> = handle.into();
+        assert_eq!(bytes, handle.to_vec());
+        assert_eq!(TypedHandle::try_from(bytes.clone()).unwrap(), handle);
+        assert_eq!(TypedHandle::try_from(bytes.as_slice()).unwrap(), handle);
+
+        let fixed: FixedBytes<HANDLE_LEN> = handle.into();
+        assert_eq!(TypedHandle::from(fixed), handle);
+
+        assert!(matches!(
+            TypedHandle::try_from(&bytes[..31]),
+            Err(FhevmError::InvalidHandle)
+        ));
+        assert!(matches!(
+            TypedHandle::try_from([0u8; 33].to_vec()),
+            Err(FhevmError::InvalidHandle)
+        ));
+    }
+
+    #[test]
+    fn hex_parsing() {
+        let handle = sample();
+        let hex = handle.to_string();
+        assert!(hex.starts_with("0x"));
+        assert_eq!(hex.parse::<TypedHandle>().unwrap(), handle);
+        assert_eq!(hex[2..].parse::<TypedHandle>().unwrap(), handle);
+
+        assert!("0x1234".parse::<TypedHandle>().is_err());
+        assert!(format!("0x{}", "zz".repeat(HANDLE_LEN))
+            .parse::<TypedHandle>()
+            .is_err());
+    }
+
+    #[test]
+    fn serde_as_hex_string() {
+        let handle = sample();
+        let json = serde_json::to_string(&handle).unwrap();
+        assert_eq!(json, format!("\"{handle}\""));
+        assert_eq!(serde_json::from_str::<TypedHandle>(&json).unwrap(), handle);
+        assert!(serde_json::from_str::<TypedHandle>("\"0x00\"").is_err());
+    }
+}
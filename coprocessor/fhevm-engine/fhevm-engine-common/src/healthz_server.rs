@@ -1,9 +1,9 @@
 use alloy_provider::Provider;
 use axum::{
     extract::State,
-    http::StatusCode,
-    response::{IntoResponse, Json},
-    routing::{get, Router},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post, Router},
 };
 use serde::Serialize;
 use sqlx::PgPool;
@@ -12,6 +12,8 @@ use tokio::{net::TcpListener, time::timeout};
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info};
 
+use crate::allocator::{self, HeapProfileError};
+use crate::secret::SecretString;
 use crate::types::BlockchainProvider;
 
 #[derive(Serialize)]
@@ -72,6 +74,12 @@ pub trait HealthCheckService: Send + Sync {
     fn health_check(&self) -> impl std::future::Future<Output = HealthStatus> + Send;
     fn is_alive(&self) -> impl std::future::Future<Output = bool> + Send;
     fn get_version(&self) -> Version;
+
+    /// Whether the service is done warming up, e.g. loading its keys. A service reports ready on
+    /// `/readyz` once warmed up and healthy.
+    fn is_ready(&self) -> impl std::future::Future<Output = bool> + Send {
+        async { true }
+    }
 }
 
 /// Default implementation for the version information.
@@ -88,6 +96,7 @@ pub struct HttpServer<S: HealthCheckService + Send + Sync + 'static> {
     service: Arc<S>,
     port: u16,
     cancel_token: CancellationToken,
+    admin_token: Option<Arc<SecretString>>,
 }
 
 impl<S: HealthCheckService + Send + Sync + 'static> HttpServer<S> {
@@ -96,15 +105,31 @@ impl<S: HealthCheckService + Send + Sync + 'static> HttpServer<S> {
             service,
             port,
             cancel_token,
+            admin_token: None,
         }
     }
+
+    /// Serves the admin endpoints, e.g. `/admin/heap_profile`, to the requests carrying
+    /// `Authorization: Bearer <admin_token>`. They are not served without a token.
+    pub fn with_admin_token(mut self, admin_token: Option<SecretString>) -> Self {
+        self.admin_token = admin_token.map(Arc::new);
+        self
+    }
+
     pub async fn start(&self) -> anyhow::Result<()> {
-        let app = Router::new()
+        let mut app = Router::new()
             .route("/healthz", get(Self::health_handler))
             .route("/liveness", get(Self::liveness_handler))
+            .route("/readyz", get(Self::readiness_handler))
             .route("/version", get(Self::version_handler))
-            .route("/metrics", get(Self::metrics_handler))
-            .with_state(self.service.clone());
+            .route("/metrics", get(Self::metrics_handler));
+        if let Some(admin_token) = self.admin_token.clone() {
+            app = app.route(
+                "/admin/heap_profile",
+                post(move |headers: HeaderMap| Self::heap_profile_handler(admin_token, headers)),
+            );
+        }
+        let app = app.with_state(self.service.clone());
 
         let addr = SocketAddr::from(([0, 0, 0, 0], self.port));
         info!("Starting HTTP server on {}", addr);
@@ -139,6 +164,33 @@ impl<S: HealthCheckService + Send + Sync + 'static> HttpServer<S> {
         (http_status, Json(HealthResponse::from(status)))
     }
 
+    async fn readiness_handler(State(service): State<Arc<S>>) -> impl IntoResponse {
+        if !service.is_ready().await {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({
+                    "status_code": "503",
+                    "status": "warming_up"
+                })),
+            );
+        }
+        let status = service.health_check().await;
+        if status.is_healthy() {
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "status_code": "200",
+                    "status": "ready"
+                })),
+            )
+        } else {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!(HealthResponse::from(status))),
+            )
+        }
+    }
+
     async fn liveness_handler(State(service): State<Arc<S>>) -> impl IntoResponse {
         if service.is_alive().await {
             (
@@ -165,6 +217,7 @@ impl<S: HealthCheckService + Send + Sync + 'static> HttpServer<S> {
     }
 
     async fn metrics_handler() -> impl IntoResponse {
+        allocator::update_metrics();
         let encoder = prometheus::TextEncoder::new();
         let metric_families = prometheus::gather();
 
@@ -175,6 +228,44 @@ impl<S: HealthCheckService + Send + Sync + 'static> HttpServer<S> {
             Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
         }
     }
+
+    /// Dumps a heap profile on the local disk, to be collected from the container.
+    async fn heap_profile_handler(admin_token: Arc<SecretString>, headers: HeaderMap) -> Response {
+        if !is_admin(&headers, &admin_token) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({ "error": "missing or invalid admin token" })),
+            )
+                .into_response();
+        }
+        match allocator::dump_heap_profile() {
+            Ok(path) => (
+                StatusCode::OK,
+                Json(serde_json::json!({ "path": path.display().to_string() })),
+            )
+                .into_response(),
+            Err(e) => {
+                let status_code = match e {
+                    HeapProfileError::Dump(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                    _ => StatusCode::NOT_IMPLEMENTED,
+                };
+                (
+                    status_code,
+                    Json(serde_json::json!({ "error": e.to_string() })),
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// Whether the request carries the admin token.
+fn is_admin(headers: &HeaderMap, admin_token: &SecretString) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|provided| admin_token.matches(provided))
 }
 
 #[derive(Clone, Default)]
@@ -254,3 +345,26 @@ impl HealthStatus {
             .join("; ")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, authorization.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_is_admin() {
+        let token = SecretString::from("s3cret".to_owned());
+        assert!(is_admin(&headers("Bearer s3cret"), &token));
+        assert!(!is_admin(&headers("Bearer s3cre"), &token));
+        assert!(!is_admin(&headers("Bearer s3cret!"), &token));
+        assert!(!is_admin(&headers("Bearer other"), &token));
+        assert!(!is_admin(&headers("s3cret"), &token));
+        assert!(!is_admin(&headers("Basic s3cret"), &token));
+        assert!(!is_admin(&HeaderMap::new(), &token));
+    }
+}
@@ -0,0 +1,186 @@
+//! Memory-mapped loading of the large keys, with load time and resident size metrics.
+//!
+//! The SnS server key is over a gigabyte once serialized. Reading its large object into a buffer
+//! before deserializing it keeps both the buffer and the key resident at the peak, i.e. about twice
+//! the size of the key. The large object can instead be spilled once to a local key file, then
+//! memory-mapped and deserialized from the mapping: the serialized pages are read on demand from
+//! the file, can be reclaimed by the kernel at any time, and are unmapped as soon as the key is
+//! built. Key files are named after the large object oid, so that the next starts reuse them until
+//! the key is replaced, once checked to be of the size of the large object.
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use memmap2::{Advice, Mmap};
+use prometheus::{register_histogram_vec, register_int_gauge_vec, HistogramVec, IntGaugeVec};
+use sqlx::postgres::types::Oid;
+use sqlx::PgPool;
+use tracing::{info, warn};
+
+use crate::tenant_keys::{query_large_object_size, read_large_object_to_file, CHUNK_SIZE};
+
+static KEY_LOAD_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "coprocessor_key_load_duration_seconds",
+        "Time to fetch and deserialize a key",
+        &["key"],
+        vec![0.1, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+    )
+    .unwrap()
+});
+
+static KEY_SERIALIZED_BYTES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "coprocessor_key_serialized_bytes",
+        "Serialized size of the last loaded key",
+        &["key"]
+    )
+    .unwrap()
+});
+
+static KEY_LOAD_RSS_BYTES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "coprocessor_key_load_rss_bytes",
+        "Resident set size of the process after loading a key",
+        &["key"]
+    )
+    .unwrap()
+});
+
+/// Read-only memory mapping of a whole file.
+pub struct MappedFile {
+    /// None for an empty file, which cannot be mapped.
+    mmap: Option<Mmap>,
+}
+
+impl MappedFile {
+    /// Maps the file, which must be `expected_len` bytes long: a file of another size was not
+    /// written completely and must not be deserialized.
+    pub fn open(path: &Path, expected_len: u64) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if len != expected_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{path:?} is {len} bytes long, expected {expected_len}"),
+            ));
+        }
+        if len == 0 {
+            return Ok(Self { mmap: None });
+        }
+        // SAFETY: key files are written once under a temporary name and never modified in place,
+        // so the mapping is not changed under the slices borrowed from it.
+        let mmap = unsafe { Mmap::map(&file)? };
+        // Only a hint, deserialization reads the key from start to end
+        let _ = mmap.advise(Advice::Sequential);
+        Ok(Self { mmap: Some(mmap) })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.mmap.as_deref().unwrap_or_default()
+    }
+}
+
+/// Maps the key file of the large object in `dir`, reading the large object into it first unless
+/// a complete key file is already present.
+pub async fn map_large_object(
+    pool: &PgPool,
+    dir: &Path,
+    key: &str,
+    oid: Oid,
+) -> anyhow::Result<MappedFile> {
+    let path = key_file_path(dir, key, oid);
+    let len = query_large_object_size(pool, oid).await?;
+    match open_blocking(path.clone(), len).await? {
+        Ok(mapped) => {
+            info!(path = ?path, "Reusing {key} key file");
+            return Ok(mapped);
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => warn!(path = ?path, error = %err, "Invalid {key} key file, reading it again"),
+    }
+    read_large_object_to_file(pool, oid, CHUNK_SIZE, &path).await?;
+    Ok(open_blocking(path, len).await??)
+}
+
+/// Maps the file without blocking the runtime threads.
+async fn open_blocking(
+    path: PathBuf,
+    expected_len: u64,
+) -> Result<io::Result<MappedFile>, tokio::task::JoinError> {
+    tokio::task::spawn_blocking(move || MappedFile::open(&path, expected_len)).await
+}
+
+/// Path of the key file of the large object in `dir`.
+pub fn key_file_path(dir: &Path, key: &str, oid: Oid) -> PathBuf {
+    dir.join(format!("{key}-{}.bin", oid.0))
+}
+
+/// Resident set size of the process, only available on Linux.
+pub fn resident_set_size() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let resident_pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    (page_size > 0).then(|| resident_pages * page_size as u64)
+}
+
+/// Records the metrics of a key loaded since `started_at`.
+pub fn observe_key_load(key: &str, started_at: Instant, serialized_bytes: usize) {
+    let elapsed = started_at.elapsed();
+    KEY_LOAD_DURATION
+        .with_label_values(&[key])
+        .observe(elapsed.as_secs_f64());
+    KEY_SERIALIZED_BYTES
+        .with_label_values(&[key])
+        .set(serialized_bytes as i64);
+    let rss = resident_set_size();
+    if let Some(rss) = rss {
+        KEY_LOAD_RSS_BYTES.with_label_values(&[key]).set(rss as i64);
+    }
+    info!(key, ?elapsed, serialized_bytes, rss, "Key loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_key_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}.bin", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn maps_a_complete_key_file() {
+        let path = write_key_file("complete", &[1, 2, 3]);
+        let mapped = MappedFile::open(&path, 3).unwrap();
+        assert_eq!(mapped.as_slice(), &[1, 2, 3]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn maps_an_empty_key_file() {
+        let path = write_key_file("empty", &[]);
+        let mapped = MappedFile::open(&path, 0).unwrap();
+        assert!(mapped.as_slice().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_truncated_key_file() {
+        let path = write_key_file("truncated", &[1, 2]);
+        let err = MappedFile::open(&path, 3).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reports_a_missing_key_file() {
+        let path = std::env::temp_dir().join("missing-key-file.bin");
+        let err = MappedFile::open(&path, 3).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,49 @@
+//! Key version tagging of the ciphertexts.
+//!
+//! A ciphertext computed under a key can only be processed with that same key: after a key
+//! rotation, the ciphertexts of the previous key must not be fed to the new one. Every ciphertext
+//! row is therefore tagged with the `key_id` of its tenant when it is written, by the zkproof-worker
+//! for the inputs and by the tfhe-worker for the results.
+//!
+//! Before processing, workers negotiate their key with the database: cached keys whose `key_id`
+//! no longer matches the tenant are evicted and loaded again, so that work is always done with the
+//! current key. Ciphertexts tagged with another key are then rejected with a key mismatch status.
+//! Untagged ciphertexts and keys, i.e. created before tagging or for tenants without a `key_id`,
+//! are compatible with any key.
+
+use std::sync::LazyLock;
+
+use prometheus::{register_int_counter_vec, IntCounterVec};
+
+static KEY_VERSION_MISMATCH_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_key_version_mismatches",
+        "Items rejected because their ciphertext is under another key than the worker's",
+        &["service"]
+    )
+    .unwrap()
+});
+
+/// Whether a ciphertext tagged with `ct_key_id` can be processed with the key `key_id`.
+pub fn is_compatible(ct_key_id: Option<&[u8]>, key_id: Option<&[u8]>) -> bool {
+    match (ct_key_id, key_id) {
+        (Some(ct_key_id), Some(key_id)) => ct_key_id == key_id,
+        _ => true,
+    }
+}
+
+/// Error message stored with the rejected items, and counts the rejection.
+pub fn mismatch_message(service: &str, ct_key_id: Option<&[u8]>, key_id: Option<&[u8]>) -> String {
+    KEY_VERSION_MISMATCH_COUNTER
+        .with_label_values(&[service])
+        .inc();
+    format!(
+        "key version mismatch: ciphertext under key {}, current key {}",
+        format_key_id(ct_key_id),
+        format_key_id(key_id)
+    )
+}
+
+fn format_key_id(key_id: Option<&[u8]>) -> String {
+    key_id.map_or_else(|| "none".to_owned(), |id| format!("0x{}", hex::encode(id)))
+}
@@ -13,9 +13,11 @@ use tfhe::{
         CompressionParameters, MetaNoiseSquashingParameters, ShortintKeySwitchingParameters,
     },
     zk::CompactPkeCrs,
-    ClientKey, CompactPublicKey, CompressedServerKey, Config, ConfigBuilder, ServerKey,
+    ClientKey, CompactPublicKey, CompressedServerKey, Config, ServerKey,
 };
 
+use crate::param_set::DEFAULT_PARAM_SET;
+use crate::secret::SecretBytes;
 use crate::utils::{safe_deserialize_key, safe_serialize_key};
 
 #[cfg(not(feature = "gpu"))]
@@ -63,7 +65,8 @@ pub struct SerializedFhevmKeys {
     pub server_key: Vec<u8>,
     #[cfg(not(feature = "gpu"))]
     pub server_key_without_ns: Vec<u8>,
-    pub client_key: Option<Vec<u8>>,
+    /// Serialized client key, zeroed once dropped
+    pub client_key: Option<SecretBytes>,
     pub compact_public_key: Vec<u8>,
     pub public_params: Vec<u8>,
     #[cfg(feature = "gpu")]
@@ -129,20 +132,7 @@ impl FhevmKeys {
     }
 
     pub fn new_config() -> Config {
-        ConfigBuilder::with_custom_parameters(TFHE_PARAMS)
-            .enable_noise_squashing(TFHE_NOISE_SQUASHING_PARAMS.parameters)
-            .enable_noise_squashing_compression(
-                TFHE_NOISE_SQUASHING_PARAMS
-                    .compression_parameters
-                    .expect("Missing noise squahing compression parameters."),
-            )
-            .enable_compression(TFHE_COMPRESSION_PARAMS)
-            .use_dedicated_compact_public_key_parameters((
-                TFHE_COMPACT_PK_PARAMS.pke_params,
-                TFHE_COMPACT_PK_PARAMS.ksk_params,
-            ))
-            .enable_ciphertext_re_randomization(TFHE_PKS_RERANDOMIZATION_PARAMS)
-            .build()
+        DEFAULT_PARAM_SET.config()
     }
 
     pub fn set_server_key_for_current_thread(&self) {
@@ -190,9 +180,9 @@ impl SerializedFhevmKeys {
             println!("Creating file {}", Self::FULL_SKS);
             std::fs::write(Self::FULL_SKS, self.server_key).expect("write sns_pk");
 
-            if self.client_key.is_some() {
+            if let Some(client_key) = &self.client_key {
                 println!("Creating file {}", Self::CKS);
-                std::fs::write(Self::CKS, self.client_key.unwrap()).expect("write cks");
+                std::fs::write(Self::CKS, client_key.expose()).expect("write cks");
             }
 
             println!("Creating file {}", Self::PKS);
@@ -206,9 +196,9 @@ impl SerializedFhevmKeys {
             println!("Creating file {}", Self::GPU_CSKS);
             std::fs::write(Self::GPU_CSKS, self.compressed_server_key).expect("write gpu csks");
 
-            if self.client_key.is_some() {
+            if let Some(client_key) = &self.client_key {
                 println!("Creating file {}", Self::GPU_CKS);
-                std::fs::write(Self::GPU_CKS, self.client_key.unwrap()).expect("write gpu cks");
+                std::fs::write(Self::GPU_CKS, client_key.expose()).expect("write gpu cks");
             }
 
             println!("Creating file {}", Self::GPU_PKS);
@@ -230,7 +220,7 @@ impl SerializedFhevmKeys {
         let server_key = read(keys_dir.join(sns_pk)).expect("read full server key (sns_pk)");
         #[cfg(not(feature = "gpu"))]
         let server_key_without_ns = read(keys_dir.join(sks)).expect("read server key");
-        let client_key = read(keys_dir.join(cks)).ok();
+        let client_key = read(keys_dir.join(cks)).ok().map(SecretBytes::from);
         let compact_public_key = read(keys_dir.join(pks)).expect("read compact public key");
         let public_params = read(keys_dir.join(pp)).expect("read public params");
         SerializedFhevmKeys {
@@ -250,7 +240,7 @@ impl SerializedFhevmKeys {
 impl From<FhevmKeys> for SerializedFhevmKeys {
     fn from(f: FhevmKeys) -> Self {
         SerializedFhevmKeys {
-            client_key: f.client_key.map(|c| safe_serialize_key(&c)),
+            client_key: f.client_key.map(|c| safe_serialize_key(&c).into()),
             compact_public_key: safe_serialize_key(&f.compact_public_key),
             public_params: safe_serialize_key(f.public_params.as_ref()),
             #[cfg(not(feature = "gpu"))]
@@ -267,7 +257,7 @@ impl From<SerializedFhevmKeys> for FhevmKeys {
     fn from(f: SerializedFhevmKeys) -> Self {
         let client_key = f
             .client_key
-            .map(|c| safe_deserialize_key(&c).expect("deserialize client key"));
+            .map(|c| safe_deserialize_key(c.expose()).expect("deserialize client key"));
         #[cfg(feature = "gpu")]
         let compressed_server_key: CompressedServerKey =
             safe_deserialize_key(&f.compressed_server_key)
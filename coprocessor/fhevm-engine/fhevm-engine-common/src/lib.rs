@@ -1,13 +1,37 @@
+pub mod allocator;
+pub mod buffer_pool;
+pub mod ciphertext_format;
+pub mod column_encryption;
+pub mod db_query;
+pub mod db_schema;
+pub mod diagnostics;
+pub mod error;
 #[cfg(feature = "gpu")]
 pub mod gpu_memory;
+pub mod handle;
 pub mod healthz_server;
+pub mod key_file;
+pub mod key_version;
 pub mod keys;
+pub mod metrics_push;
+pub mod notification_bus;
+pub mod numa;
+pub mod param_set;
+pub mod pg_listener;
 pub mod pg_pool;
+pub mod raw_events;
+pub mod secret;
+pub mod status_api;
+pub mod status_push;
 pub mod telemetry;
 pub mod tenant_keys;
 pub mod tfhe_ops;
+pub mod tls;
 pub mod types;
 pub mod utils;
+pub mod warmup;
+pub mod webhook;
+pub mod write_batcher;
 
 pub mod common {
     tonic::include_proto!("fhevm.common");
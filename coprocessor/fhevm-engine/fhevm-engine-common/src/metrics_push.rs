@@ -0,0 +1,71 @@
+//! Push of the metrics to a Prometheus Pushgateway, for the runs that end before being scraped.
+//!
+//! Services normally expose their metrics on the `/metrics` route of the health check server. A
+//! one-shot run, e.g. a catchup of the host-listener bounded by `--end-at-block`, can instead push
+//! them periodically and once more when done, so that the metrics of its last moments are kept.
+//! Each push replaces the metrics previously pushed under the same job and instance.
+
+use std::time::Duration;
+
+use prometheus::{Encoder, TextEncoder};
+use reqwest::{Client, Url};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Timeout of a single push, a push failure never stops the run.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+pub struct MetricsPusher {
+    client: Client,
+    url: Url,
+}
+
+impl MetricsPusher {
+    /// The metrics are grouped under `job` and `instance` on the Pushgateway at `gateway_url`.
+    pub fn new(gateway_url: &str, job: &str, instance: &str) -> anyhow::Result<Self> {
+        let mut url: Url = gateway_url.parse()?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("invalid Pushgateway URL {gateway_url}"))?
+            .pop_if_empty()
+            .extend(["metrics", "job", job, "instance", instance]);
+        let client = Client::builder().timeout(PUSH_TIMEOUT).build()?;
+        Ok(Self { client, url })
+    }
+
+    /// Pushes all the metrics of the default registry.
+    pub async fn push(&self) -> anyhow::Result<()> {
+        let mut body = vec![];
+        let encoder = TextEncoder::new();
+        encoder.encode(&prometheus::gather(), &mut body)?;
+        self.client
+            .put(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, encoder.format_type())
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        debug!(url = %self.url, "Metrics pushed");
+        Ok(())
+    }
+
+    /// Pushes the metrics every `interval` until cancelled, then a last time.
+    pub fn spawn(self, interval: Duration, cancel_token: CancellationToken) -> JoinHandle<()> {
+        info!(url = %self.url, ?interval, "Pushing metrics to Pushgateway");
+        tokio::spawn(async move {
+            loop {
+                let cancelled = tokio::select! {
+                    _ = cancel_token.cancelled() => true,
+                    _ = tokio::time::sleep(interval) => false,
+                };
+                if let Err(err) = self.push().await {
+                    warn!(url = %self.url, error = %err, "Failed to push metrics");
+                }
+                if cancelled {
+                    break;
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,439 @@
+//! Work-dispatch notifications, on Postgres LISTEN/NOTIFY or on a message bus.
+//!
+//! The queues stay in the database, a notification only wakes a service up to query them again.
+//! Subscribers are told when they may have missed notifications, so that they catch up, and keep
+//! polling as a fallback, whatever the bus.
+//!
+//! With Postgres, notifications are sent by the triggers and the statements of the writers and
+//! delivered on commit, see [`crate::pg_listener`]. With NATS, the listeners publish on the subject
+//! `<prefix>.<channel>` once their writes are committed, fanned out to every subscriber. The
+//! subjects can be captured by a JetStream stream to be replayed. The writers that only notify
+//! Postgres are caught up with by polling.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::stream::{SelectAll, StreamExt};
+use sqlx::{Pool, Postgres};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use crate::pg_listener::{Coalesced, ListenerEvent, SupervisedListener};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BusKind {
+    #[default]
+    Postgres,
+    Nats,
+}
+
+impl FromStr for BusKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "postgres" => Ok(Self::Postgres),
+            "nats" => Ok(Self::Nats),
+            _ => Err(format!(
+                "invalid message bus {s:?}, expected postgres or nats"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for BusKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Postgres => "postgres",
+            Self::Nats => "nats",
+        })
+    }
+}
+
+pub const DEFAULT_SUBJECT_PREFIX: &str = "fhevm";
+
+#[derive(Clone, Debug)]
+pub struct BusConfig {
+    pub kind: BusKind,
+    /// Server of the NATS bus, e.g. `nats://localhost:4222`
+    pub nats_url: Option<String>,
+    /// Prefix of the NATS subjects, followed by the channel
+    pub subject_prefix: String,
+}
+
+impl Default for BusConfig {
+    fn default() -> Self {
+        Self {
+            kind: BusKind::Postgres,
+            nats_url: None,
+            subject_prefix: DEFAULT_SUBJECT_PREFIX.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BusEvent {
+    Notification {
+        channel: String,
+        payload: String,
+    },
+    /// The connection was lost and is up again, notifications may have been missed.
+    Reconnected,
+}
+
+/// Wake-up for one or more notifications, like [`Coalesced`].
+#[derive(Debug)]
+pub struct Wakeup {
+    /// `Reconnected` if the connection was re-established meanwhile, else the last notification
+    pub event: BusEvent,
+    /// Number of notifications folded into this wake-up
+    pub count: usize,
+    /// Highest block number among the payloads that are a block number
+    pub latest_block: Option<u64>,
+}
+
+impl Wakeup {
+    fn new(event: BusEvent) -> Self {
+        let (count, latest_block) = match &event {
+            BusEvent::Notification { payload, .. } => (1, payload.parse().ok()),
+            BusEvent::Reconnected => (0, None),
+        };
+        Self {
+            event,
+            count,
+            latest_block,
+        }
+    }
+
+    fn add(&mut self, notification: BusEvent) {
+        if let BusEvent::Notification { payload, .. } = &notification {
+            self.count += 1;
+            self.latest_block = self.latest_block.max(payload.parse().ok());
+        }
+        if let BusEvent::Notification { .. } = self.event {
+            self.event = notification;
+        }
+    }
+}
+
+impl From<Coalesced> for Wakeup {
+    fn from(coalesced: Coalesced) -> Self {
+        Self {
+            event: match coalesced.event {
+                ListenerEvent::Notification(notification) => BusEvent::Notification {
+                    channel: notification.channel().to_owned(),
+                    payload: notification.payload().to_owned(),
+                },
+                ListenerEvent::Reconnected => BusEvent::Reconnected,
+            },
+            count: coalesced.count,
+            latest_block: coalesced.latest_block,
+        }
+    }
+}
+
+#[async_trait]
+pub trait Subscription: Send {
+    /// Waits for the next notification, then folds into the same wake-up the notifications
+    /// already received and the ones arriving within `debounce` of the first one.
+    async fn recv_coalesced(&mut self, debounce: Duration) -> Wakeup;
+}
+
+#[async_trait]
+pub trait NotificationBus: Send + Sync {
+    fn kind(&self) -> BusKind;
+
+    /// Subscribes to the channels. The first connection is not retried, so that a
+    /// misconfiguration is reported right away.
+    async fn subscribe(&self, channels: &[String]) -> anyhow::Result<Box<dyn Subscription>>;
+
+    /// Notifies the subscribers of the channel, right away.
+    async fn publish(&self, channel: &str, payload: &str) -> anyhow::Result<()>;
+}
+
+/// Connects to the configured bus, the Postgres one using the pool.
+pub async fn connect(
+    conf: &BusConfig,
+    pool: &Pool<Postgres>,
+) -> anyhow::Result<Arc<dyn NotificationBus>> {
+    Ok(match connect_external(conf).await? {
+        Some(bus) => bus,
+        None => Arc::new(PostgresBus::new(pool.clone())),
+    })
+}
+
+/// Connects to the configured bus unless it is Postgres, for the publishers whose writes are
+/// already notified on Postgres by the triggers or their statements.
+pub async fn connect_external(
+    conf: &BusConfig,
+) -> anyhow::Result<Option<Arc<dyn NotificationBus>>> {
+    match conf.kind {
+        BusKind::Postgres => Ok(None),
+        BusKind::Nats => {
+            let Some(url) = &conf.nats_url else {
+                anyhow::bail!("The NATS message bus requires a NATS URL");
+            };
+            Ok(Some(Arc::new(
+                NatsBus::connect(url, &conf.subject_prefix).await?,
+            )))
+        }
+    }
+}
+
+pub struct PostgresBus {
+    pool: Pool<Postgres>,
+}
+
+impl PostgresBus {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl Subscription for SupervisedListener {
+    async fn recv_coalesced(&mut self, debounce: Duration) -> Wakeup {
+        SupervisedListener::recv_coalesced(self, debounce)
+            .await
+            .into()
+    }
+}
+
+#[async_trait]
+impl NotificationBus for PostgresBus {
+    fn kind(&self) -> BusKind {
+        BusKind::Postgres
+    }
+
+    async fn subscribe(&self, channels: &[String]) -> anyhow::Result<Box<dyn Subscription>> {
+        Ok(Box::new(
+            SupervisedListener::connect(&self.pool, channels.iter().cloned()).await?,
+        ))
+    }
+
+    async fn publish(&self, channel: &str, payload: &str) -> anyhow::Result<()> {
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(channel)
+            .bind(payload)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// NATS core bus, reconnected by the client in the background.
+pub struct NatsBus {
+    client: async_nats::Client,
+    subject_prefix: String,
+    /// Bumped on every (re)connection
+    connections: watch::Receiver<u64>,
+}
+
+impl NatsBus {
+    pub async fn connect(url: &str, subject_prefix: &str) -> anyhow::Result<Self> {
+        let (connected, connections) = watch::channel(0);
+        let connected = Arc::new(connected);
+        let client = async_nats::ConnectOptions::new()
+            .event_callback(move |event| {
+                let connected = connected.clone();
+                async move {
+                    match event {
+                        async_nats::Event::Connected => {
+                            info!("NATS connection established");
+                            connected.send_modify(|connections| *connections += 1);
+                        }
+                        async_nats::Event::Disconnected => {
+                            warn!("NATS connection lost, reconnecting")
+                        }
+                        event => info!(%event, "NATS event"),
+                    }
+                }
+            })
+            .connect(url)
+            .await?;
+        info!(url, subject_prefix, "Connected to the NATS message bus");
+        Ok(Self {
+            client,
+            subject_prefix: subject_prefix.to_owned(),
+            connections,
+        })
+    }
+
+    fn subject(&self, channel: &str) -> String {
+        format!("{}.{channel}", self.subject_prefix)
+    }
+}
+
+#[async_trait]
+impl NotificationBus for NatsBus {
+    fn kind(&self) -> BusKind {
+        BusKind::Nats
+    }
+
+    async fn subscribe(&self, channels: &[String]) -> anyhow::Result<Box<dyn Subscription>> {
+        let mut subscribers = SelectAll::new();
+        for channel in channels {
+            subscribers.push(self.client.subscribe(self.subject(channel)).await?);
+        }
+        let mut connections = self.connections.clone();
+        connections.borrow_and_update();
+        Ok(Box::new(NatsSubscription {
+            subject_prefix: format!("{}.", self.subject_prefix),
+            subscribers,
+            connections,
+        }))
+    }
+
+    async fn publish(&self, channel: &str, payload: &str) -> anyhow::Result<()> {
+        self.client
+            .publish(self.subject(channel), payload.to_owned().into())
+            .await?;
+        self.client.flush().await?;
+        Ok(())
+    }
+}
+
+struct NatsSubscription {
+    subject_prefix: String,
+    subscribers: SelectAll<async_nats::Subscriber>,
+    connections: watch::Receiver<u64>,
+}
+
+impl NatsSubscription {
+    fn notification(&self, message: async_nats::Message) -> BusEvent {
+        let subject: &str = &message.subject;
+        BusEvent::Notification {
+            channel: subject
+                .strip_prefix(&self.subject_prefix)
+                .unwrap_or(subject)
+                .to_owned(),
+            payload: String::from_utf8_lossy(&message.payload).into_owned(),
+        }
+    }
+
+    async fn recv(&mut self) -> BusEvent {
+        tokio::select! {
+            Ok(()) = self.connections.changed() => BusEvent::Reconnected,
+            Some(message) = self.subscribers.next() => self.notification(message),
+            else => {
+                // Only polling is left
+                error!("NATS client closed, no more notifications");
+                std::future::pending().await
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Subscription for NatsSubscription {
+    async fn recv_coalesced(&mut self, debounce: Duration) -> Wakeup {
+        let mut wakeup = Wakeup::new(self.recv().await);
+        let deadline = Instant::now() + debounce;
+        while let Ok(Some(message)) =
+            tokio::time::timeout_at(deadline, self.subscribers.next()).await
+        {
+            let notification = self.notification(message);
+            wakeup.add(notification);
+        }
+        wakeup
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(channel: &str, payload: &str) -> BusEvent {
+        BusEvent::Notification {
+            channel: channel.to_owned(),
+            payload: payload.to_owned(),
+        }
+    }
+
+    fn payload(wakeup: &Wakeup) -> &str {
+        match &wakeup.event {
+            BusEvent::Notification { payload, .. } => payload,
+            BusEvent::Reconnected => panic!("unexpected reconnection"),
+        }
+    }
+
+    #[test]
+    fn parses_and_displays_bus_kinds() {
+        for kind in [BusKind::Postgres, BusKind::Nats] {
+            assert_eq!(kind.to_string().parse(), Ok(kind));
+        }
+        assert!("kafka".parse::<BusKind>().is_err());
+    }
+
+    #[test]
+    fn wakeup_folds_the_notifications() {
+        let mut wakeup = Wakeup::new(notification("work", "5"));
+        assert_eq!((wakeup.count, wakeup.latest_block), (1, Some(5)));
+
+        wakeup.add(notification("work", "9"));
+        wakeup.add(notification("work", "not a block"));
+        wakeup.add(notification("work", "7"));
+        assert_eq!((wakeup.count, wakeup.latest_block), (4, Some(9)));
+        assert_eq!(payload(&wakeup), "7");
+    }
+
+    #[test]
+    fn wakeup_keeps_reporting_a_reconnection() {
+        let mut wakeup = Wakeup::new(BusEvent::Reconnected);
+        assert_eq!((wakeup.count, wakeup.latest_block), (0, None));
+
+        wakeup.add(notification("work", "3"));
+        assert!(matches!(wakeup.event, BusEvent::Reconnected));
+        assert_eq!((wakeup.count, wakeup.latest_block), (1, Some(3)));
+    }
+
+    #[tokio::test]
+    async fn only_nats_is_an_external_bus() {
+        let conf = BusConfig::default();
+        assert_eq!(conf.kind, BusKind::Postgres);
+        assert!(connect_external(&conf).await.unwrap().is_none());
+
+        let conf = BusConfig {
+            kind: BusKind::Nats,
+            ..Default::default()
+        };
+        assert!(connect_external(&conf).await.is_err());
+    }
+
+    #[test]
+    fn nats_subjects_are_mapped_to_channels() {
+        let subscription = NatsSubscription {
+            subject_prefix: format!("{DEFAULT_SUBJECT_PREFIX}."),
+            subscribers: SelectAll::new(),
+            connections: watch::channel(0).1,
+        };
+        let message = |subject: &str| async_nats::Message {
+            subject: subject.into(),
+            reply: None,
+            payload: "12".into(),
+            headers: None,
+            status: None,
+            description: None,
+            length: 0,
+        };
+
+        match subscription.notification(message("fhevm.work_available")) {
+            BusEvent::Notification { channel, payload } => {
+                assert_eq!(channel, "work_available");
+                assert_eq!(payload, "12");
+            }
+            BusEvent::Reconnected => panic!("unexpected reconnection"),
+        }
+        // Subjects outside of the prefix are kept whole
+        match subscription.notification(message("other.work_available")) {
+            BusEvent::Notification { channel, .. } => {
+                assert_eq!(channel, "other.work_available")
+            }
+            BusEvent::Reconnected => panic!("unexpected reconnection"),
+        }
+    }
+}
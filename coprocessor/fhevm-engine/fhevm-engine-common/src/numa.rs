@@ -0,0 +1,97 @@
+//! NUMA topology discovery and thread pinning, only available on Linux.
+//!
+//! On multi-socket machines memory is attached to a NUMA node and accessing the memory of another
+//! node is notably slower. Pages are allocated on the node of the thread first touching them, so a
+//! thread pinned to the CPUs of a node keeps the data it allocates, e.g. decompressed ciphertexts,
+//! local to the node.
+
+use std::io;
+
+const NODES_PATH: &str = "/sys/devices/system/node";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NumaNode {
+    pub id: usize,
+    pub cpus: Vec<usize>,
+}
+
+/// Returns the NUMA nodes with at least one online CPU, ordered by id.
+pub fn nodes() -> io::Result<Vec<NumaNode>> {
+    let mut nodes = vec![];
+    for entry in std::fs::read_dir(NODES_PATH)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(id) = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|id| id.parse().ok())
+        else {
+            continue;
+        };
+        let cpus = parse_cpu_list(&std::fs::read_to_string(entry.path().join("cpulist"))?)?;
+        if !cpus.is_empty() {
+            nodes.push(NumaNode { id, cpus });
+        }
+    }
+    nodes.sort_by_key(|node| node.id);
+    Ok(nodes)
+}
+
+/// Parses a kernel CPU list, e.g. `0-3,8-11`.
+pub fn parse_cpu_list(list: &str) -> io::Result<Vec<usize>> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid CPU list {list}"),
+        )
+    };
+    let mut cpus = vec![];
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                let first: usize = first.parse().map_err(|_| invalid())?;
+                let last: usize = last.parse().map_err(|_| invalid())?;
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(range.parse().map_err(|_| invalid())?),
+        }
+    }
+    Ok(cpus)
+}
+
+/// Restricts the calling thread to `cpus`.
+pub fn pin_current_thread(cpus: &[usize]) -> io::Result<()> {
+    // SAFETY: the set is zero initialized and only filled with CPUs below CPU_SETSIZE
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus.iter().filter(|&&cpu| cpu < libc::CPU_SETSIZE as usize) {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cpu_lists() {
+        assert_eq!(parse_cpu_list("0").unwrap(), vec![0]);
+        assert_eq!(parse_cpu_list("0-3\n").unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(parse_cpu_list("0-1,4,8-9").unwrap(), vec![0, 1, 4, 8, 9]);
+        // A node without CPUs has an empty list
+        assert!(parse_cpu_list("\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_cpu_lists() {
+        for list in ["a", "0-", "-3", "0-3-5", "1,x"] {
+            let err = parse_cpu_list(list).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{list}");
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Parameter sets of the keysets.
+//!
+//! Keys are generated for a set of TFHE parameters, and a ciphertext can only be processed with
+//! keys of the same parameters. Rather than assuming the parameters built into the workers, each
+//! keyset is attached to a [`ParamSet`] descriptor, named by the `param_set` of its tenant, so
+//! that tenants with different parameters can be served side by side:
+//! - the public key of the tenant is checked against its descriptor when its keys are loaded
+//! - input lists are deserialized with the conformance parameters of the tenant's descriptor
+//! - ciphertext rows are tagged with the `param_set` they were written under, next to their
+//!   `key_id` (see [`crate::key_version`]), and ciphertexts tagged with another set are rejected
+//!   with a parameter set mismatch status
+//!
+//! Tenants and ciphertexts without a `param_set`, i.e. created before the descriptors, use
+//! [`DEFAULT_PARAM_SET`], the set the workers were built with.
+
+use std::sync::LazyLock;
+
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use tfhe::{
+    shortint::{
+        parameters::{
+            meta::DedicatedCompactPublicKeyParameters, CompactPublicKeyEncryptionParameters,
+            CompressionParameters, MetaNoiseSquashingParameters, ShortintKeySwitchingParameters,
+        },
+        AtomicPatternParameters,
+    },
+    CompactPublicKey, Config, ConfigBuilder,
+};
+
+use crate::keys::{
+    TFHE_COMPACT_PK_PARAMS, TFHE_COMPRESSION_PARAMS, TFHE_NOISE_SQUASHING_PARAMS, TFHE_PARAMS,
+    TFHE_PKS_RERANDOMIZATION_PARAMS,
+};
+use crate::types::FhevmError;
+
+static PARAM_SET_MISMATCH_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_param_set_mismatches",
+        "Items rejected because their ciphertext is under another parameter set than the worker's keys",
+        &["service"]
+    )
+    .unwrap()
+});
+
+/// TFHE parameters a keyset was generated with.
+#[derive(Debug)]
+pub struct ParamSet {
+    /// Name stored in the `param_set` columns
+    pub name: &'static str,
+    pub compute: AtomicPatternParameters,
+    pub compression: CompressionParameters,
+    pub compact_pk: DedicatedCompactPublicKeyParameters,
+    pub noise_squashing: MetaNoiseSquashingParameters,
+    pub rerandomization: ShortintKeySwitchingParameters,
+}
+
+/// The parameter set the workers were built with, used by tenants without a `param_set`.
+pub const DEFAULT_PARAM_SET: ParamSet = ParamSet {
+    #[cfg(not(feature = "gpu"))]
+    name: "cpu_2_2_ks_pbs_pke_to_small_zkv2_tuniform_2m128",
+    #[cfg(feature = "gpu")]
+    name: "gpu_2_2_multi_bit_group_4_ks_pbs_tuniform_2m128",
+    compute: TFHE_PARAMS,
+    compression: TFHE_COMPRESSION_PARAMS,
+    compact_pk: TFHE_COMPACT_PK_PARAMS,
+    noise_squashing: TFHE_NOISE_SQUASHING_PARAMS,
+    rerandomization: TFHE_PKS_RERANDOMIZATION_PARAMS,
+};
+
+/// Parameter sets the workers can serve, new sets are registered here.
+pub static PARAM_SETS: &[&ParamSet] = &[&DEFAULT_PARAM_SET];
+
+impl ParamSet {
+    /// Resolves the `param_set` of a tenant, the default set when NULL.
+    pub fn resolve(name: Option<&str>) -> Result<&'static ParamSet, FhevmError> {
+        let Some(name) = name else {
+            return Ok(&DEFAULT_PARAM_SET);
+        };
+        PARAM_SETS
+            .iter()
+            .find(|param_set| param_set.name == name)
+            .copied()
+            .ok_or_else(|| FhevmError::UnknownParamSet(name.to_owned()))
+    }
+
+    pub fn config(&self) -> Config {
+        ConfigBuilder::with_custom_parameters(self.compute)
+            .enable_noise_squashing(self.noise_squashing.parameters)
+            .enable_noise_squashing_compression(
+                self.noise_squashing
+                    .compression_parameters
+                    .expect("Missing noise squahing compression parameters."),
+            )
+            .enable_compression(self.compression)
+            .use_dedicated_compact_public_key_parameters((
+                self.compact_pk.pke_params,
+                self.compact_pk.ksk_params,
+            ))
+            .enable_ciphertext_re_randomization(self.rerandomization)
+            .build()
+    }
+
+    /// Parameters the input lists are encrypted with.
+    pub fn public_key_encryption_parameters(&self) -> CompactPublicKeyEncryptionParameters {
+        self.compact_pk.pke_params
+    }
+
+    /// Checks that a public key was generated with this parameter set.
+    pub fn check_public_key(&self, pks: &CompactPublicKey) -> Result<(), FhevmError> {
+        if pks.parameters() != self.compact_pk.pke_params {
+            return Err(FhevmError::ParamSetMismatch(self.name));
+        }
+        Ok(())
+    }
+
+    /// Whether a ciphertext tagged with `ct_param_set` can be processed with keys of this set.
+    pub fn is_compatible(&self, ct_param_set: Option<&str>) -> bool {
+        ct_param_set.unwrap_or(DEFAULT_PARAM_SET.name) == self.name
+    }
+
+    /// Error message stored with the rejected items, and counts the rejection.
+    pub fn mismatch_message(&self, service: &str, ct_param_set: Option<&str>) -> String {
+        PARAM_SET_MISMATCH_COUNTER
+            .with_label_values(&[service])
+            .inc();
+        format!(
+            "param set mismatch: ciphertext under parameter set {}, current parameter set {}",
+            ct_param_set.unwrap_or(DEFAULT_PARAM_SET.name),
+            self.name
+        )
+    }
+}
@@ -0,0 +1,223 @@
+//! Long-lived LISTEN connection surviving database connection losses.
+//!
+//! A `PgListener` reports a lost connection as an empty notification or an error, which the
+//! workers used to take as a hint to poll the database, leaving a listener that cannot reconnect
+//! unnoticed behind the polling. [`SupervisedListener`] instead reconnects and subscribes again to
+//! its channels, with backoff, until it succeeds. Notifications sent while disconnected are lost,
+//! so the reconnection is reported to the caller, which must catch up by querying for the work
+//! that may have been notified meanwhile.
+//!
+//! A burst of writes, e.g. the events of many blocks, sends a burst of notifications, each waking
+//! the caller for the same query. [`SupervisedListener::recv_coalesced`] folds the notifications
+//! already received, and optionally the ones following within a debounce interval, into a single
+//! wake-up.
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use prometheus::{register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec};
+use sqlx::postgres::{PgListener, PgNotification};
+use sqlx::{Pool, Postgres};
+use tracing::{error, info, warn};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+static LISTENER_CONNECTED: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "coprocessor_pg_listener_connected",
+        "Whether the LISTEN connection of a channel is up (1) or reconnecting (0)",
+        &["channel"]
+    )
+    .unwrap()
+});
+
+static LISTENER_RECONNECTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_pg_listener_reconnect_counter",
+        "Number of reconnections of the LISTEN connection of a channel",
+        &["channel"]
+    )
+    .unwrap()
+});
+
+static LISTENER_COALESCED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_pg_listener_coalesced_counter",
+        "Number of notifications of a channel folded into an earlier wake-up",
+        &["channel"]
+    )
+    .unwrap()
+});
+
+#[derive(Debug)]
+pub enum ListenerEvent {
+    Notification(PgNotification),
+    /// The connection was lost and is up again, notifications may have been missed.
+    Reconnected,
+}
+
+/// Wake-up for one or more notifications, see [`SupervisedListener::recv_coalesced`].
+#[derive(Debug)]
+pub struct Coalesced {
+    /// `Reconnected` if the connection was re-established meanwhile, else the last notification
+    pub event: ListenerEvent,
+    /// Number of notifications folded into this wake-up
+    pub count: usize,
+    /// Highest block number among the payloads that are a block number
+    pub latest_block: Option<u64>,
+}
+
+impl Coalesced {
+    fn new(event: ListenerEvent) -> Self {
+        let (count, latest_block) = match &event {
+            ListenerEvent::Notification(notification) => (1, block_number(notification)),
+            ListenerEvent::Reconnected => (0, None),
+        };
+        Self {
+            event,
+            count,
+            latest_block,
+        }
+    }
+
+    fn add(&mut self, notification: PgNotification) {
+        LISTENER_COALESCED
+            .with_label_values(&[notification.channel()])
+            .inc();
+        self.count += 1;
+        self.latest_block = self.latest_block.max(block_number(&notification));
+        if let ListenerEvent::Notification(_) = self.event {
+            self.event = ListenerEvent::Notification(notification);
+        }
+    }
+}
+
+fn block_number(notification: &PgNotification) -> Option<u64> {
+    notification.payload().parse().ok()
+}
+
+pub struct SupervisedListener {
+    pool: Pool<Postgres>,
+    channels: Vec<String>,
+    /// None while reconnecting
+    listener: Option<PgListener>,
+    backoff: Duration,
+}
+
+impl SupervisedListener {
+    /// Listens to the channels. The first connection is not retried, so that a misconfiguration
+    /// is reported right away.
+    pub async fn connect(
+        pool: &Pool<Postgres>,
+        channels: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Self, sqlx::Error> {
+        let channels: Vec<String> = channels.into_iter().map(Into::into).collect();
+        let listener = Self::listen(pool, &channels).await?;
+        set_connected(&channels, true);
+        Ok(Self {
+            pool: pool.clone(),
+            channels,
+            listener: Some(listener),
+            backoff: INITIAL_BACKOFF,
+        })
+    }
+
+    /// Waits for the next notification, reconnecting as long as needed.
+    ///
+    /// Cancel safe, a reconnection interrupted by the caller is resumed on the next call.
+    pub async fn recv(&mut self) -> ListenerEvent {
+        loop {
+            let Some(listener) = self.listener.as_mut() else {
+                self.reconnect().await;
+                return ListenerEvent::Reconnected;
+            };
+            match listener.try_recv().await {
+                Ok(Some(notification)) => return ListenerEvent::Notification(notification),
+                res => self.disconnected(res.err()),
+            }
+        }
+    }
+
+    /// Waits for the next notification like [`Self::recv`], then folds into the same wake-up the
+    /// notifications already received and the ones arriving within `debounce` of the first one.
+    /// With a zero `debounce`, a burst costs one wake-up without delaying the caller.
+    ///
+    /// Cancelling during the debounce drops the notifications folded so far, which only matters
+    /// to callers that do not recheck for work on the other branches of their `select!`.
+    pub async fn recv_coalesced(&mut self, debounce: Duration) -> Coalesced {
+        let mut coalesced = Coalesced::new(self.recv().await);
+        let deadline = Instant::now() + debounce;
+        while let Some(listener) = self.listener.as_mut() {
+            // The pending notifications are taken even past the deadline, a timeout polls the
+            // future first
+            match tokio::time::timeout_at(deadline, listener.try_recv()).await {
+                Err(_) => break,
+                Ok(Ok(Some(notification))) => coalesced.add(notification),
+                // Reported as a reconnection by the next call
+                Ok(res) => self.disconnected(res.err()),
+            }
+        }
+        coalesced
+    }
+
+    fn disconnected(&mut self, err: Option<sqlx::Error>) {
+        match err {
+            None => warn!(channels = ?self.channels, "LISTEN connection lost, reconnecting"),
+            Some(err) => error!(
+                channels = ?self.channels,
+                error = %err,
+                "LISTEN connection failed, reconnecting"
+            ),
+        }
+        self.listener = None;
+        set_connected(&self.channels, false);
+    }
+
+    async fn reconnect(&mut self) {
+        loop {
+            match Self::listen(&self.pool, &self.channels).await {
+                Ok(listener) => {
+                    info!(channels = ?self.channels, "LISTEN connection re-established");
+                    self.listener = Some(listener);
+                    self.backoff = INITIAL_BACKOFF;
+                    set_connected(&self.channels, true);
+                    for channel in &self.channels {
+                        LISTENER_RECONNECTS
+                            .with_label_values(&[channel.as_str()])
+                            .inc();
+                    }
+                    return;
+                }
+                Err(err) => {
+                    warn!(
+                        channels = ?self.channels,
+                        error = %err,
+                        backoff = ?self.backoff,
+                        "Failed to reconnect LISTEN connection"
+                    );
+                    tokio::time::sleep(self.backoff).await;
+                    self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    async fn listen(pool: &Pool<Postgres>, channels: &[String]) -> Result<PgListener, sqlx::Error> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener
+            .listen_all(channels.iter().map(String::as_str))
+            .await?;
+        Ok(listener)
+    }
+}
+
+fn set_connected(channels: &[String], connected: bool) {
+    for channel in channels {
+        LISTENER_CONNECTED
+            .with_label_values(&[channel.as_str()])
+            .set(connected.into());
+    }
+}
@@ -0,0 +1,105 @@
+//! Logs stored as is by the listeners, in `raw_events` by the host-listener and in
+//! `gw_raw_events` by the gw-listener, to be decoded or acted on again later.
+
+use std::str::FromStr;
+
+use alloy::primitives::{Address, Bytes, LogData, B256};
+use alloy::rpc::types::Log;
+
+/// A stored log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawEvent {
+    pub block_hash: Vec<u8>,
+    pub block_number: i64,
+    pub log_index: i64,
+    pub transaction_hash: Option<Vec<u8>>,
+    pub contract_address: String,
+    pub topics: Vec<Vec<u8>>,
+    pub data: Vec<u8>,
+}
+
+impl RawEvent {
+    /// None if the log is not mined yet, i.e. has no block or index.
+    pub fn from_log(log: &Log) -> Option<Self> {
+        Some(RawEvent {
+            block_hash: log.block_hash?.to_vec(),
+            block_number: log.block_number? as i64,
+            log_index: log.log_index? as i64,
+            transaction_hash: log.transaction_hash.map(|h| h.to_vec()),
+            contract_address: log.address().to_string(),
+            topics: log.topics().iter().map(|topic| topic.to_vec()).collect(),
+            data: log.data().data.to_vec(),
+        })
+    }
+
+    /// Rebuilds the log as received from the node.
+    pub fn to_log(&self) -> anyhow::Result<Log> {
+        let topics = self
+            .topics
+            .iter()
+            .map(|topic| B256::try_from(topic.as_slice()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Log {
+            inner: alloy::primitives::Log {
+                address: Address::from_str(&self.contract_address)?,
+                data: LogData::new_unchecked(topics, Bytes::from(self.data.clone())),
+            },
+            block_hash: Some(B256::try_from(self.block_hash.as_slice())?),
+            block_number: Some(self.block_number as u64),
+            transaction_hash: self
+                .transaction_hash
+                .as_deref()
+                .map(B256::try_from)
+                .transpose()?,
+            log_index: Some(self.log_index as u64),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log() -> Log {
+        Log {
+            inner: alloy::primitives::Log {
+                address: Address::repeat_byte(0x11),
+                data: LogData::new_unchecked(
+                    vec![B256::repeat_byte(0x22), B256::repeat_byte(0x33)],
+                    Bytes::from_static(b"data"),
+                ),
+            },
+            block_hash: Some(B256::repeat_byte(0x44)),
+            block_number: Some(42),
+            transaction_hash: Some(B256::repeat_byte(0x55)),
+            log_index: Some(3),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let event = RawEvent::from_log(&log()).unwrap();
+        assert_eq!(event.block_number, 42);
+        assert_eq!(event.log_index, 3);
+        assert_eq!(event.topics.len(), 2);
+        assert_eq!(event.to_log().unwrap(), log());
+    }
+
+    #[test]
+    fn pending_log_is_not_stored() {
+        let pending = Log {
+            block_hash: None,
+            ..log()
+        };
+        assert!(RawEvent::from_log(&pending).is_none());
+    }
+
+    #[test]
+    fn malformed_topic_is_rejected() {
+        let mut event = RawEvent::from_log(&log()).unwrap();
+        event.topics[0].pop();
+        assert!(event.to_log().is_err());
+    }
+}
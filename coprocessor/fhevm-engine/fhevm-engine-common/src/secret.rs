@@ -0,0 +1,139 @@
+//! Secrets held in memory, e.g. signer keys and the webhook HMAC key.
+//!
+//! A [`Secret`] is zeroed when dropped and redacted when formatted, so that it neither lingers in
+//! freed memory nor ends up in the logs with the configuration holding it. Reading it takes an
+//! explicit [`Secret::expose`], which keeps its uses easy to audit. Tokens and API keys are
+//! checked with [`SecretString::matches`].
+
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T: Zeroize>(T);
+
+pub type SecretString = Secret<String>;
+pub type SecretBytes = Secret<Vec<u8>>;
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl SecretString {
+    /// Compares in constant time, not to leak the secret through timing, only its length.
+    pub fn matches(&self, provided: &str) -> bool {
+        self.0.len() == provided.len()
+            && self
+                .0
+                .bytes()
+                .zip(provided.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize> ZeroizeOnDrop for Secret<T> {}
+
+impl<T: Zeroize> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+/// Parses command line arguments and environment variables.
+impl FromStr for SecretString {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct Config {
+        url: String,
+        key: SecretString,
+    }
+
+    #[test]
+    fn secrets_are_redacted_when_formatted() {
+        let key: SecretString = "hunter2".parse().unwrap();
+        assert_eq!(format!("{key:?}"), "Secret([REDACTED])");
+
+        let config = Config {
+            url: "http://localhost".to_owned(),
+            key,
+        };
+        let formatted = format!("{config:?} {config:#?}");
+        assert!(formatted.contains("http://localhost"));
+        assert!(!formatted.contains("hunter2"));
+
+        let bytes = SecretBytes::new(vec![0xde, 0xad]);
+        assert_eq!(format!("{bytes:?}"), "Secret([REDACTED])");
+    }
+
+    #[test]
+    fn secrets_are_exposed_explicitly() {
+        let key = SecretString::from("hunter2".to_owned());
+        assert_eq!(key.expose(), "hunter2");
+        assert_eq!(key.clone(), key);
+        assert!(SecretString::default().expose().is_empty());
+    }
+
+    #[test]
+    fn secrets_are_matched_exactly() {
+        let token = SecretString::from("s3cret".to_owned());
+        assert!(token.matches("s3cret"));
+        assert!(!token.matches("s3cre"));
+        assert!(!token.matches("s3cret!"));
+        assert!(!token.matches("S3cret"));
+        assert!(!token.matches(""));
+        assert!(SecretString::default().matches(""));
+    }
+
+    /// Records whether it was zeroized.
+    struct Tracked(Arc<AtomicBool>);
+
+    impl Zeroize for Tracked {
+        fn zeroize(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn secrets_are_zeroized_when_dropped() {
+        let zeroized = Arc::new(AtomicBool::new(false));
+        let secret = Secret::new(Tracked(zeroized.clone()));
+        assert!(!zeroized.load(Ordering::SeqCst));
+        drop(secret);
+        assert!(zeroized.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,764 @@
+//! Read-only HTTP API reporting where a handle, a transaction or a request stands in the
+//! coprocessor pipeline.
+//!
+//! The status is derived from the engine tables, nothing is stored for the API itself:
+//! - `queued`: the computation is waiting for its output to be allowed
+//! - `computing`: the computation or the input verification is scheduled
+//! - `squashing`: the ciphertext is computed, switch-and-squash and upload are pending
+//! - `awaiting_receipt`: the result is ready, the gateway transaction is not confirmed yet
+//! - `done`: the gateway transaction is confirmed
+//! - `failed`: the computation or the verification failed, with the reason
+//!
+//! Input verifications are deleted once their response is sent, an unknown request is reported
+//! as not found.
+//!
+//! Delegations are reported from their event history instead, every event being `applied`,
+//! `superseded` by a later one or `dismissed` because older than the applied one.
+//!
+//! Status transitions can also be pushed over a WebSocket, see [`crate::status_push`].
+//!
+//! Requests authenticate as `Authorization: Bearer <key>` with the API key of a tenant, and only
+//! see the handles, transactions, inputs and delegations of that tenant. The auth token, when
+//! configured, is the operator key seeing all the tenants. Decryption responses are not bound to
+//! a tenant, they are reported to any authenticated request.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Extension, Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use sqlx::{Pool, Postgres};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::column_encryption::ColumnEncryption;
+use crate::secret::SecretString;
+use crate::status_push;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 100;
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(Clone, Debug)]
+pub struct StatusApiConfig {
+    pub port: u16,
+    /// Bearer token of the operator, seeing all the tenants. The tenants authenticate with their
+    /// API key
+    pub auth_token: Option<SecretString>,
+    /// Channels triggering a refresh of the pushed statuses
+    pub notify_channels: Vec<String>,
+    /// Refresh period of the pushed statuses, for the transitions that are not notified
+    pub push_poll_interval: Duration,
+    /// Encryption of the delegators and delegates looked up
+    pub column_encryption: ColumnEncryption,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Queued,
+    Computing,
+    Squashing,
+    AwaitingReceipt,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HandleStatus {
+    pub handle: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TransactionStatus {
+    pub transaction_id: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_number: Option<i64>,
+    pub handles: Vec<HandleStatus>,
+    /// Cursor of the next page of handles, absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InputStatus {
+    pub zk_proof_id: i64,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub handles: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DecryptionStatus {
+    pub decryption_id: String,
+    pub response_type: &'static str,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txn_hash: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DelegationStatus {
+    pub contract_address: String,
+    pub delegation_counter: i64,
+    pub expiry_date: i64,
+    pub status: String,
+    /// The expiry date of an applied delegation is past
+    pub expired: bool,
+    /// Expiry date of the delegation revoked by the event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_expiry_date: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_number: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct DelegationFilter {
+    pub contract_address: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Page {
+    pub limit: Option<i64>,
+    /// Hex encoded handle after which the page starts
+    pub after: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("not found")]
+    NotFound,
+
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status_code, message) = match self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::NotFound => (StatusCode::NOT_FOUND, "not found".to_string()),
+            ApiError::Database(err) => {
+                error!(error = %err, "Status query failed");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "database error".to_string(),
+                )
+            }
+        };
+        (status_code, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+/// Progress of a handle, as read from the computation, squash and digest tables.
+#[derive(Default, Debug)]
+struct HandleProgress {
+    computation: Option<ComputationProgress>,
+    pbs_completed: Option<bool>,
+    digest: Option<DigestProgress>,
+}
+
+#[derive(Debug)]
+struct ComputationProgress {
+    is_completed: bool,
+    is_error: bool,
+    is_allowed: bool,
+    error_message: Option<String>,
+}
+
+#[derive(Debug)]
+struct DigestProgress {
+    ciphertext128_ready: bool,
+    txn_is_sent: bool,
+    txn_last_error: Option<String>,
+}
+
+impl HandleProgress {
+    fn is_empty(&self) -> bool {
+        self.computation.is_none() && self.pbs_completed.is_none() && self.digest.is_none()
+    }
+
+    fn status(&self) -> (Status, Option<String>) {
+        if let Some(computation) = &self.computation {
+            if computation.is_error {
+                return (Status::Failed, computation.error_message.clone());
+            }
+            if !computation.is_completed {
+                let status = if computation.is_allowed {
+                    Status::Computing
+                } else {
+                    Status::Queued
+                };
+                return (status, None);
+            }
+        }
+        match (&self.digest, self.pbs_completed) {
+            (Some(digest), _) if digest.txn_is_sent => (Status::Done, None),
+            (Some(digest), _) if digest.ciphertext128_ready => {
+                (Status::AwaitingReceipt, digest.txn_last_error.clone())
+            }
+            (Some(_), _) | (None, Some(_)) => (Status::Squashing, None),
+            // Computed but not requested for decryption, nothing more to do
+            (None, None) => (Status::Done, None),
+        }
+    }
+}
+
+/// Status of a group of steps, the least advanced one unless any failed.
+fn aggregate(statuses: impl IntoIterator<Item = Status>) -> Option<Status> {
+    let rank = |status: &Status| match status {
+        Status::Queued => 0,
+        Status::Computing => 1,
+        Status::Squashing => 2,
+        Status::AwaitingReceipt => 3,
+        Status::Done => 4,
+        Status::Failed => 5,
+    };
+    let statuses: Vec<_> = statuses.into_iter().collect();
+    if statuses.contains(&Status::Failed) {
+        return Some(Status::Failed);
+    }
+    statuses.into_iter().min_by_key(rank)
+}
+
+pub(crate) fn decode_hex(value: &str, what: &str) -> Result<Vec<u8>, ApiError> {
+    hex::decode(value.strip_prefix("0x").unwrap_or(value))
+        .map_err(|err| ApiError::BadRequest(format!("invalid {what}: {err}")))
+}
+
+fn encode_hex(value: &[u8]) -> String {
+    format!("0x{}", hex::encode(value))
+}
+
+/// Tenants whose statuses a request reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Scope {
+    /// Authenticated with the operator token
+    All,
+    /// Authenticated with the API key of the tenant
+    Tenant { tenant_id: i32, chain_id: i64 },
+}
+
+impl Scope {
+    fn tenant_id(self) -> Option<i32> {
+        match self {
+            Scope::All => None,
+            Scope::Tenant { tenant_id, .. } => Some(tenant_id),
+        }
+    }
+
+    fn chain_id(self) -> Option<i64> {
+        match self {
+            Scope::All => None,
+            Scope::Tenant { chain_id, .. } => Some(chain_id),
+        }
+    }
+}
+
+pub(crate) struct ApiState {
+    pub(crate) pool: Pool<Postgres>,
+    auth_token: Option<SecretString>,
+    /// Bumped on every progress notification
+    pub(crate) updates: watch::Receiver<u64>,
+    pub(crate) push_poll_interval: Duration,
+    /// Statuses pushed to the WebSocket subscribers
+    pub(crate) hub: status_push::StatusHub,
+    column_encryption: ColumnEncryption,
+}
+
+pub struct StatusApiServer {
+    state: Arc<ApiState>,
+    port: u16,
+    notify_channels: Vec<String>,
+    updates: Arc<watch::Sender<u64>>,
+    cancel_token: CancellationToken,
+}
+
+impl StatusApiServer {
+    pub fn new(
+        pool: Pool<Postgres>,
+        conf: StatusApiConfig,
+        cancel_token: CancellationToken,
+    ) -> Self {
+        let (updates, receiver) = watch::channel(0);
+        Self {
+            state: Arc::new(ApiState {
+                pool,
+                auth_token: conf.auth_token,
+                updates: receiver,
+                push_poll_interval: conf.push_poll_interval,
+                hub: status_push::StatusHub::new(),
+                column_encryption: conf.column_encryption,
+            }),
+            port: conf.port,
+            notify_channels: conf.notify_channels,
+            updates: Arc::new(updates),
+            cancel_token,
+        }
+    }
+
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/v1/status/handles/:handle", get(handle_status))
+            .route("/v1/status/transactions/:id", get(transaction_status))
+            .route("/v1/status/inputs/:zk_proof_id", get(input_status))
+            .route("/v1/status/decryptions/:id", get(decryption_status))
+            .route(
+                "/v1/status/delegations/:delegator/:delegate",
+                get(delegation_status),
+            )
+            .route("/v1/subscribe", get(status_push::subscribe_handler))
+            .route_layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                authorize,
+            ))
+            .with_state(self.state.clone())
+    }
+
+    pub async fn start(&self) -> anyhow::Result<()> {
+        let addr = SocketAddr::from(([0, 0, 0, 0], self.port));
+        info!(
+            address = %addr,
+            auth = self.state.auth_token.is_some(),
+            "Starting status API server"
+        );
+
+        let shutdown = {
+            let cancel_token = self.cancel_token.clone();
+            async move {
+                cancel_token.cancelled().await;
+            }
+        };
+
+        tokio::spawn(status_push::listen_notifications(
+            self.state.pool.clone(),
+            self.notify_channels.clone(),
+            self.updates.clone(),
+            self.cancel_token.clone(),
+        ));
+        tokio::spawn(status_push::refresh_statuses(
+            self.state.clone(),
+            self.cancel_token.clone(),
+        ));
+
+        let listener = TcpListener::bind(addr).await?;
+        let server = axum::serve(listener, self.router().into_make_service())
+            .with_graceful_shutdown(shutdown);
+
+        if let Err(err) = server.await {
+            error!("Status API server error: {}", err);
+            return Err(anyhow::anyhow!("Status API server error: {}", err));
+        }
+
+        Ok(())
+    }
+}
+
+async fn authorize(
+    State(state): State<Arc<ApiState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match resolve_scope(&state, provided).await {
+        Ok(Some(scope)) => {
+            request.extensions_mut().insert(scope);
+            next.run(request).await
+        }
+        Ok(None) => (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({ "error": "unauthorized" })),
+        )
+            .into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// Scope of the operator token or of the tenant API key, `None` if neither.
+async fn resolve_scope(
+    state: &ApiState,
+    provided: Option<&str>,
+) -> Result<Option<Scope>, ApiError> {
+    let Some(provided) = provided else {
+        return Ok(None);
+    };
+    if let Some(expected) = &state.auth_token {
+        if expected.matches(provided) {
+            return Ok(Some(Scope::All));
+        }
+    }
+    let Ok(api_key) = Uuid::parse_str(provided) else {
+        return Ok(None);
+    };
+    let tenant = sqlx::query!(
+        "SELECT tenant_id, chain_id FROM tenants WHERE tenant_api_key = $1",
+        api_key,
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+    Ok(tenant.map(|tenant| Scope::Tenant {
+        tenant_id: tenant.tenant_id,
+        chain_id: tenant.chain_id,
+    }))
+}
+
+async fn handle_status(
+    State(state): State<Arc<ApiState>>,
+    Extension(scope): Extension<Scope>,
+    Path(handle): Path<String>,
+) -> Result<Json<HandleStatus>, ApiError> {
+    let handle = decode_hex(&handle, "handle")?;
+    fetch_handle_status(&state.pool, scope, &handle)
+        .await
+        .map(Json)
+}
+
+pub(crate) async fn fetch_handle_status(
+    pool: &Pool<Postgres>,
+    scope: Scope,
+    handle: &[u8],
+) -> Result<HandleStatus, ApiError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            c.is_completed AS "is_completed?",
+            c.is_error AS "is_error?",
+            c.is_allowed AS "is_allowed?",
+            c.error_message,
+            p.is_completed AS "pbs_completed?",
+            d.ciphertext128_ready AS "ciphertext128_ready?",
+            d.txn_is_sent AS "txn_is_sent?",
+            d.txn_last_error
+        FROM (SELECT $1::BYTEA AS handle) h
+        LEFT JOIN LATERAL (
+            SELECT is_completed, is_error, is_allowed, error_message
+            FROM computations
+            WHERE output_handle = h.handle AND ($2::INT IS NULL OR tenant_id = $2)
+            ORDER BY created_at DESC
+            LIMIT 1
+        ) c ON TRUE
+        LEFT JOIN LATERAL (
+            SELECT is_completed
+            FROM pbs_computations
+            WHERE handle = h.handle AND ($2::INT IS NULL OR tenant_id = $2)
+            LIMIT 1
+        ) p ON TRUE
+        LEFT JOIN LATERAL (
+            SELECT ciphertext128 IS NOT NULL AS ciphertext128_ready, txn_is_sent, txn_last_error
+            FROM ciphertext_digest
+            WHERE handle = h.handle AND ($2::INT IS NULL OR tenant_id = $2)
+            LIMIT 1
+        ) d ON TRUE
+        "#,
+        handle,
+        scope.tenant_id(),
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let progress = HandleProgress {
+        computation: row.is_completed.map(|is_completed| ComputationProgress {
+            is_completed,
+            is_error: row.is_error.unwrap_or(false),
+            is_allowed: row.is_allowed.unwrap_or(false),
+            error_message: row.error_message,
+        }),
+        pbs_completed: row.pbs_completed,
+        digest: row.txn_is_sent.map(|txn_is_sent| DigestProgress {
+            ciphertext128_ready: row.ciphertext128_ready.unwrap_or(false),
+            txn_is_sent,
+            txn_last_error: row.txn_last_error,
+        }),
+    };
+    if progress.is_empty() {
+        return Err(ApiError::NotFound);
+    }
+
+    let (status, reason) = progress.status();
+    Ok(HandleStatus {
+        handle: encode_hex(handle),
+        status,
+        reason,
+    })
+}
+
+async fn transaction_status(
+    State(state): State<Arc<ApiState>>,
+    Extension(scope): Extension<Scope>,
+    Path(id): Path<String>,
+    Query(page): Query<Page>,
+) -> Result<Json<TransactionStatus>, ApiError> {
+    let transaction_id = decode_hex(&id, "transaction id")?;
+    let limit = page
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let after = match &page.after {
+        Some(after) => decode_hex(after, "cursor")?,
+        None => vec![],
+    };
+
+    let transaction = sqlx::query!(
+        "SELECT block_number, completed_at IS NOT NULL AS \"is_completed!\"
+         FROM transactions
+         WHERE id = $1 AND ($2::BIGINT IS NULL OR chain_id = $2)",
+        transaction_id,
+        scope.chain_id(),
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+
+    // One more row than the page to know if there is a next one
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            c.output_handle,
+            c.is_completed,
+            c.is_error,
+            c.is_allowed,
+            c.error_message,
+            p.is_completed AS "pbs_completed?",
+            d.ciphertext128 IS NOT NULL AS "ciphertext128_ready?",
+            d.txn_is_sent AS "txn_is_sent?",
+            d.txn_last_error
+        FROM computations c
+        LEFT JOIN pbs_computations p
+            ON p.tenant_id = c.tenant_id AND p.handle = c.output_handle
+        LEFT JOIN ciphertext_digest d
+            ON d.tenant_id = c.tenant_id AND d.handle = c.output_handle
+        WHERE c.transaction_id = $1 AND c.output_handle > $2
+        AND ($4::INT IS NULL OR c.tenant_id = $4)
+        ORDER BY c.output_handle
+        LIMIT $3
+        "#,
+        transaction_id,
+        after,
+        limit + 1,
+        scope.tenant_id(),
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    if transaction.is_none() && rows.is_empty() && page.after.is_none() {
+        return Err(ApiError::NotFound);
+    }
+
+    let has_next = rows.len() as i64 > limit;
+    let handles: Vec<HandleStatus> = rows
+        .into_iter()
+        .take(limit as usize)
+        .map(|row| {
+            let progress = HandleProgress {
+                computation: Some(ComputationProgress {
+                    is_completed: row.is_completed,
+                    is_error: row.is_error,
+                    is_allowed: row.is_allowed,
+                    error_message: row.error_message,
+                }),
+                pbs_completed: row.pbs_completed,
+                digest: row.txn_is_sent.map(|txn_is_sent| DigestProgress {
+                    ciphertext128_ready: row.ciphertext128_ready.unwrap_or(false),
+                    txn_is_sent,
+                    txn_last_error: row.txn_last_error,
+                }),
+            };
+            let (status, reason) = progress.status();
+            HandleStatus {
+                handle: encode_hex(&row.output_handle),
+                status,
+                reason,
+            }
+        })
+        .collect();
+
+    // The status of the transaction only covers the handles of the page, unless it is completed
+    let status = match &transaction {
+        Some(transaction) if transaction.is_completed => Status::Done,
+        _ => aggregate(handles.iter().map(|h| h.status)).unwrap_or(Status::Queued),
+    };
+    let next = if has_next {
+        handles.last().map(|h| h.handle.clone())
+    } else {
+        None
+    };
+
+    Ok(Json(TransactionStatus {
+        transaction_id: encode_hex(&transaction_id),
+        status,
+        block_number: transaction.map(|t| t.block_number),
+        handles,
+        next,
+    }))
+}
+
+async fn input_status(
+    State(state): State<Arc<ApiState>>,
+    Extension(scope): Extension<Scope>,
+    Path(zk_proof_id): Path<i64>,
+) -> Result<Json<InputStatus>, ApiError> {
+    fetch_input_status(&state.pool, scope, zk_proof_id)
+        .await
+        .map(Json)
+}
+
+pub(crate) async fn fetch_input_status(
+    pool: &Pool<Postgres>,
+    scope: Scope,
+    zk_proof_id: i64,
+) -> Result<InputStatus, ApiError> {
+    let row = sqlx::query!(
+        "SELECT verified, last_error, handles, txn_status
+         FROM verify_proofs
+         WHERE zk_proof_id = $1 AND ($2::BIGINT IS NULL OR chain_id = $2)",
+        zk_proof_id,
+        scope.chain_id(),
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(ApiError::NotFound)?;
+
+    let (status, reason) = match (row.verified, row.txn_status.as_str()) {
+        (None, _) => (Status::Computing, row.last_error),
+        (Some(true), "sent") => (Status::Done, None),
+        (Some(true), "failed") => (Status::Failed, row.last_error),
+        (Some(true), _) => (Status::AwaitingReceipt, None),
+        (Some(false), _) => (
+            Status::Failed,
+            Some(row.last_error.unwrap_or("proof rejected".to_string())),
+        ),
+    };
+    let handles = row
+        .handles
+        .unwrap_or_default()
+        .chunks(32)
+        .map(encode_hex)
+        .collect();
+
+    Ok(InputStatus {
+        zk_proof_id,
+        status,
+        reason,
+        handles,
+    })
+}
+
+async fn decryption_status(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<DecryptionStatus>>, ApiError> {
+    let decryption_id = decode_hex(&id, "decryption id")?;
+    fetch_decryption_status(&state.pool, &decryption_id)
+        .await
+        .map(Json)
+}
+
+pub(crate) async fn fetch_decryption_status(
+    pool: &Pool<Postgres>,
+    decryption_id: &[u8],
+) -> Result<Vec<DecryptionStatus>, ApiError> {
+    let rows = sqlx::query!(
+        "SELECT response_type, txn_is_sent, txn_last_error, txn_hash
+         FROM decryption_responses
+         WHERE decryption_id = $1
+         ORDER BY response_type",
+        decryption_id,
+    )
+    .fetch_all(pool)
+    .await?;
+    if rows.is_empty() {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok(rows
+        .into_iter()
+        .map(|row| DecryptionStatus {
+            decryption_id: encode_hex(decryption_id),
+            response_type: match row.response_type {
+                0 => "public",
+                1 => "user",
+                2 => "public_aggregated",
+                _ => "unknown",
+            },
+            status: if row.txn_is_sent {
+                Status::Done
+            } else {
+                Status::AwaitingReceipt
+            },
+            reason: row.txn_last_error,
+            txn_hash: row.txn_hash.as_deref().map(encode_hex),
+        })
+        .collect())
+}
+
+async fn delegation_status(
+    State(state): State<Arc<ApiState>>,
+    Extension(scope): Extension<Scope>,
+    Path((delegator, delegate)): Path<(String, String)>,
+    Query(filter): Query<DelegationFilter>,
+) -> Result<Json<Vec<DelegationStatus>>, ApiError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT contract_address, delegation_counter, expiry_date, old_expiry_date, status,
+            block_number, transaction_id,
+            status = 'applied' AND expiry_date <= EXTRACT(EPOCH FROM NOW())::BIGINT AS "expired!"
+        FROM user_decryption_delegation_history
+        WHERE LOWER(delegator) = LOWER($1)
+        AND LOWER(delegate) = LOWER($2)
+        AND ($3::TEXT IS NULL OR LOWER(contract_address) = LOWER($3))
+        AND ($5::INT IS NULL OR tenant_id = $5)
+        ORDER BY contract_address, delegation_counter DESC
+        LIMIT $4
+        "#,
+        state.column_encryption.encrypt_lookup(&delegator),
+        state.column_encryption.encrypt_lookup(&delegate),
+        filter.contract_address,
+        MAX_PAGE_SIZE,
+        scope.tenant_id(),
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    if rows.is_empty() {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok(Json(
+        rows.into_iter()
+            .map(|row| DelegationStatus {
+                contract_address: row.contract_address,
+                delegation_counter: row.delegation_counter,
+                expiry_date: row.expiry_date,
+                status: row.status,
+                expired: row.expired,
+                old_expiry_date: row.old_expiry_date,
+                block_number: row.block_number,
+                transaction_id: row.transaction_id.as_deref().map(encode_hex),
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,555 @@
+//! WebSocket push of the status transitions of subscribed handles and requests.
+//!
+//! Clients subscribe with text messages such as `{"action": "subscribe", "handle": "0x.."}`,
+//! `{"action": "subscribe", "input": 12}` or `{"action": "subscribe", "decryption": "0x.."}`,
+//! and unsubscribe with `"action": "unsubscribe"`. The server pushes the current status on
+//! subscription, then every time it changes, as `{"type": "handle", "data": {..}}` with the same
+//! payload as the status API. Subscriptions end by themselves once `done` or `failed` is pushed.
+//!
+//! Statuses are refreshed when the workers notify progress on the Postgres channels, and
+//! periodically for the transitions that are not notified, e.g. gateway receipts. A single task
+//! refreshes every subscription once for all the connections, which only diff the shared result.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Extension, State,
+    },
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use tokio::sync::watch;
+use tokio::time::{interval, sleep, sleep_until, Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+
+use crate::pg_listener::{ListenerEvent, SupervisedListener};
+use crate::status_api::{
+    decode_hex, fetch_decryption_status, fetch_handle_status, fetch_input_status, ApiError,
+    ApiState, DecryptionStatus, HandleStatus, InputStatus, Scope, Status,
+};
+
+/// Channels notified by the workers and the listeners when they make progress.
+pub const DEFAULT_NOTIFY_CHANNELS: &[&str] = &[
+    "work_available",
+    "event_allowed_handle",
+    "event_ciphertext_computed",
+    "event_pbs_computations",
+    "event_ciphertexts_uploaded",
+    "event_zkpok_new_work",
+    "event_zkpok_computed",
+    "event_decryption_response",
+];
+
+/// Maximum number of subscriptions of a single connection.
+pub const MAX_SUBSCRIPTIONS: usize = 256;
+
+/// Notifications come in bursts, statuses are refreshed once per burst.
+const REFRESH_DEBOUNCE: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Subscription {
+    Handle(Vec<u8>),
+    Input(i64),
+    Decryption(Vec<u8>),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    Subscribe,
+    Unsubscribe,
+}
+
+#[derive(Deserialize, Debug)]
+struct ClientMessage {
+    action: Action,
+    handle: Option<String>,
+    input: Option<i64>,
+    decryption: Option<String>,
+}
+
+impl ClientMessage {
+    fn subscription(&self) -> Result<Subscription, String> {
+        match (&self.handle, self.input, &self.decryption) {
+            (Some(handle), None, None) => decode_hex(handle, "handle").map(Subscription::Handle),
+            (None, Some(input), None) => Ok(Subscription::Input(input)),
+            (None, None, Some(decryption)) => {
+                decode_hex(decryption, "decryption id").map(Subscription::Decryption)
+            }
+            _ => Err(ApiError::BadRequest(
+                "exactly one of handle, input or decryption is expected".to_string(),
+            )),
+        }
+        .map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum Update {
+    Handle(HandleStatus),
+    Input(InputStatus),
+    Decryption(Vec<DecryptionStatus>),
+    Error(String),
+}
+
+impl Update {
+    fn is_final(&self) -> bool {
+        let is_final = |status: Status| matches!(status, Status::Done | Status::Failed);
+        match self {
+            Update::Handle(handle) => is_final(handle.status),
+            Update::Input(input) => is_final(input.status),
+            Update::Decryption(responses) => responses.iter().all(|r| is_final(r.status)),
+            Update::Error(_) => false,
+        }
+    }
+}
+
+/// Bumps the counter watched by the connections on every notification, until cancelled.
+pub(crate) async fn listen_notifications(
+    pool: Pool<Postgres>,
+    channels: Vec<String>,
+    sender: Arc<watch::Sender<u64>>,
+    cancel_token: CancellationToken,
+) {
+    loop {
+        let result = tokio::select! {
+            result = forward_notifications(&pool, &channels, &sender) => result,
+            _ = cancel_token.cancelled() => return,
+        };
+        if let Err(err) = result {
+            error!(error = %err, "Status notifications listener failed, restarting");
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+}
+
+async fn forward_notifications(
+    pool: &Pool<Postgres>,
+    channels: &[String],
+    sender: &watch::Sender<u64>,
+) -> Result<(), sqlx::Error> {
+    let mut listener = SupervisedListener::connect(pool, channels.iter().cloned()).await?;
+    info!(?channels, "Listening to status notifications");
+    loop {
+        match listener.recv().await {
+            ListenerEvent::Notification(notification) => {
+                debug!(channel = notification.channel(), "Status notification");
+            }
+            // Transitions may have been missed, the subscriptions are refreshed
+            ListenerEvent::Reconnected => {}
+        }
+        sender.send_modify(|count| *count = count.wrapping_add(1));
+    }
+}
+
+/// A subscription and the tenants it is read for.
+type Key = (Scope, Subscription);
+
+/// Status of a subscription as last fetched, shared by all the connections.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Fetched {
+    NotFound,
+    Found { text: String, is_final: bool },
+}
+
+impl From<Update> for Fetched {
+    fn from(update: Update) -> Self {
+        Fetched::Found {
+            text: serde_json::to_string(&update).expect("status serialization"),
+            is_final: update.is_final(),
+        }
+    }
+}
+
+/// Subscriptions of all the connections and their last fetched statuses.
+pub(crate) struct StatusHub {
+    /// Number of connections per subscription
+    subscribers: Mutex<HashMap<Key, usize>>,
+    statuses: watch::Sender<Arc<HashMap<Key, Fetched>>>,
+}
+
+impl StatusHub {
+    pub(crate) fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(HashMap::new()),
+            statuses: watch::channel(Arc::default()).0,
+        }
+    }
+
+    fn subscribe(&self, key: Key) {
+        *self.subscribers.lock().unwrap().entry(key).or_default() += 1;
+    }
+
+    fn unsubscribe(&self, key: &Key) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if let Some(count) = subscribers.get_mut(key) {
+            *count -= 1;
+            if *count == 0 {
+                subscribers.remove(key);
+            }
+        }
+    }
+
+    fn keys(&self) -> Vec<Key> {
+        self.subscribers.lock().unwrap().keys().cloned().collect()
+    }
+
+    fn statuses(&self) -> watch::Receiver<Arc<HashMap<Key, Fetched>>> {
+        self.statuses.subscribe()
+    }
+
+    fn publish(&self, statuses: HashMap<Key, Fetched>) {
+        self.statuses.send_replace(Arc::new(statuses));
+    }
+}
+
+/// Refreshes the subscribed statuses once per notification burst and per poll, until cancelled.
+pub(crate) async fn refresh_statuses(state: Arc<ApiState>, cancel_token: CancellationToken) {
+    let mut updates = state.updates.clone();
+    let mut poll = interval(state.push_poll_interval);
+    poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // Set on the first notification of a burst
+    let mut refresh_at: Option<Instant> = None;
+
+    loop {
+        tokio::select! {
+            _ = cancel_token.cancelled() => return,
+            changed = updates.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                refresh_at.get_or_insert_with(|| Instant::now() + REFRESH_DEBOUNCE);
+                continue;
+            }
+            _ = sleep_until(refresh_at.unwrap_or_else(Instant::now)), if refresh_at.is_some() => {
+                refresh_at = None;
+            }
+            _ = poll.tick() => {}
+        }
+        let statuses = fetch_all(state.hub.keys(), |key| fetch_status(&state.pool, key)).await;
+        state.hub.publish(statuses);
+    }
+}
+
+/// Fetches every subscription once, leaving out the ones that failed.
+async fn fetch_all<F, Fut>(keys: Vec<Key>, fetch: F) -> HashMap<Key, Fetched>
+where
+    F: Fn(Key) -> Fut,
+    Fut: Future<Output = Result<Fetched, ApiError>>,
+{
+    let mut statuses = HashMap::with_capacity(keys.len());
+    for key in keys {
+        match fetch(key.clone()).await {
+            Ok(fetched) => {
+                statuses.insert(key, fetched);
+            }
+            Err(err) => {
+                error!(error = %err, subscription = ?key.1, "Failed to refresh status subscription");
+            }
+        }
+    }
+    statuses
+}
+
+async fn fetch_status(
+    pool: &Pool<Postgres>,
+    (scope, subscription): Key,
+) -> Result<Fetched, ApiError> {
+    let result = match &subscription {
+        Subscription::Handle(handle) => fetch_handle_status(pool, scope, handle)
+            .await
+            .map(Update::Handle),
+        Subscription::Input(zk_proof_id) => fetch_input_status(pool, scope, *zk_proof_id)
+            .await
+            .map(Update::Input),
+        Subscription::Decryption(decryption_id) => fetch_decryption_status(pool, decryption_id)
+            .await
+            .map(Update::Decryption),
+    };
+    match result {
+        Ok(update) => Ok(update.into()),
+        Err(ApiError::NotFound) => Ok(Fetched::NotFound),
+        Err(err) => Err(err),
+    }
+}
+
+pub(crate) async fn subscribe_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<ApiState>>,
+    Extension(scope): Extension<Scope>,
+) -> Response {
+    ws.on_upgrade(move |socket| run_connection(socket, state, scope))
+}
+
+struct Connection {
+    socket: WebSocket,
+    state: Arc<ApiState>,
+    /// Tenants of the authenticated client
+    scope: Scope,
+    /// Last update pushed per subscription, to only push changes
+    subscriptions: HashMap<Subscription, Option<String>>,
+}
+
+async fn run_connection(socket: WebSocket, state: Arc<ApiState>, scope: Scope) {
+    let mut statuses = state.hub.statuses();
+    let mut connection = Connection {
+        socket,
+        state,
+        scope,
+        subscriptions: HashMap::new(),
+    };
+
+    loop {
+        let result = tokio::select! {
+            message = connection.socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => connection.on_message(&text).await,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => Ok(()),
+            },
+            changed = statuses.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                let statuses = statuses.borrow_and_update().clone();
+                connection.push_all(&statuses).await
+            }
+        };
+        if let Err(err) = result {
+            debug!(error = %err, "Status subscription closed");
+            return;
+        }
+    }
+}
+
+impl Connection {
+    async fn on_message(&mut self, text: &str) -> Result<(), axum::Error> {
+        let message = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(err) => return self.send(&Update::Error(err.to_string())).await,
+        };
+        let subscription = match message.subscription() {
+            Ok(subscription) => subscription,
+            Err(err) => return self.send(&Update::Error(err)).await,
+        };
+
+        match message.action {
+            Action::Unsubscribe => {
+                self.remove(&subscription);
+                Ok(())
+            }
+            Action::Subscribe if self.subscriptions.contains_key(&subscription) => Ok(()),
+            Action::Subscribe if self.subscriptions.len() >= MAX_SUBSCRIPTIONS => {
+                let error = format!("at most {MAX_SUBSCRIPTIONS} subscriptions per connection");
+                self.send(&Update::Error(error)).await
+            }
+            Action::Subscribe => {
+                self.subscriptions.insert(subscription.clone(), None);
+                self.state.hub.subscribe((self.scope, subscription.clone()));
+                // The current status is pushed right away, without waiting for the next refresh
+                match fetch_status(&self.state.pool, (self.scope, subscription.clone())).await {
+                    Ok(fetched) => self.push(subscription, fetched).await,
+                    Err(err) => {
+                        error!(error = %err, ?subscription, "Failed to fetch status subscription");
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    async fn push_all(&mut self, statuses: &HashMap<Key, Fetched>) -> Result<(), axum::Error> {
+        let subscriptions: Vec<_> = self.subscriptions.keys().cloned().collect();
+        for subscription in subscriptions {
+            if let Some(fetched) = statuses.get(&(self.scope, subscription.clone())) {
+                self.push(subscription, fetched.clone()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pushes the status of the subscription if it changed since the last push.
+    async fn push(
+        &mut self,
+        subscription: Subscription,
+        fetched: Fetched,
+    ) -> Result<(), axum::Error> {
+        let Some(last) = self.subscriptions.get(&subscription) else {
+            return Ok(());
+        };
+        let (text, is_final) = match (fetched, &subscription) {
+            (Fetched::Found { text, is_final }, _) => (text, is_final),
+            // Verifications are deleted once their response is sent
+            (Fetched::NotFound, Subscription::Input(zk_proof_id)) if last.is_some() => {
+                let update = Update::Input(InputStatus {
+                    zk_proof_id: *zk_proof_id,
+                    status: Status::Done,
+                    reason: None,
+                    handles: vec![],
+                });
+                (
+                    serde_json::to_string(&update).expect("status serialization"),
+                    true,
+                )
+            }
+            // Not known yet, e.g. a handle subscribed before its computation is inserted
+            (Fetched::NotFound, _) => return Ok(()),
+        };
+
+        if last.as_ref() == Some(&text) {
+            return Ok(());
+        }
+        self.socket.send(Message::Text(text.clone())).await?;
+        if is_final {
+            self.remove(&subscription);
+        } else {
+            self.subscriptions.insert(subscription, Some(text));
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, subscription: &Subscription) {
+        if self.subscriptions.remove(subscription).is_some() {
+            self.state
+                .hub
+                .unsubscribe(&(self.scope, subscription.clone()));
+        }
+    }
+
+    async fn send(&mut self, update: &Update) -> Result<(), axum::Error> {
+        let text = serde_json::to_string(update).expect("status serialization");
+        self.socket.send(Message::Text(text)).await
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for subscription in self.subscriptions.keys() {
+            self.state
+                .hub
+                .unsubscribe(&(self.scope, subscription.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    const TENANT: Scope = Scope::Tenant {
+        tenant_id: 1,
+        chain_id: 12345,
+    };
+
+    fn handle_key(scope: Scope, byte: u8) -> Key {
+        (scope, Subscription::Handle(vec![byte; 32]))
+    }
+
+    fn handle_update(status: Status) -> Update {
+        Update::Handle(HandleStatus {
+            handle: "0x01".to_string(),
+            status,
+            reason: None,
+        })
+    }
+
+    #[test]
+    fn test_client_message_subscription() {
+        let parse = |text: &str| {
+            serde_json::from_str::<ClientMessage>(text)
+                .unwrap()
+                .subscription()
+        };
+        assert_eq!(
+            parse(r#"{"action": "subscribe", "handle": "0x0102"}"#),
+            Ok(Subscription::Handle(vec![1, 2]))
+        );
+        assert_eq!(
+            parse(r#"{"action": "unsubscribe", "input": 12}"#),
+            Ok(Subscription::Input(12))
+        );
+        assert_eq!(
+            parse(r#"{"action": "subscribe", "decryption": "0a"}"#),
+            Ok(Subscription::Decryption(vec![10]))
+        );
+        assert!(parse(r#"{"action": "subscribe"}"#).is_err());
+        assert!(parse(r#"{"action": "subscribe", "handle": "0x01", "input": 1}"#).is_err());
+        assert!(parse(r#"{"action": "subscribe", "handle": "0xzz"}"#).is_err());
+    }
+
+    #[test]
+    fn test_update_is_final() {
+        assert!(!handle_update(Status::Computing).is_final());
+        assert!(handle_update(Status::Done).is_final());
+        assert!(handle_update(Status::Failed).is_final());
+        assert!(!Update::Error("error".to_string()).is_final());
+        assert!(Update::Decryption(vec![]).is_final());
+    }
+
+    #[test]
+    fn test_hub_counts_subscribers() {
+        let hub = StatusHub::new();
+        hub.subscribe(handle_key(TENANT, 1));
+        hub.subscribe(handle_key(TENANT, 1));
+        hub.subscribe(handle_key(Scope::All, 1));
+        assert_eq!(hub.keys().len(), 2);
+
+        hub.unsubscribe(&handle_key(TENANT, 1));
+        assert_eq!(hub.keys().len(), 2);
+        hub.unsubscribe(&handle_key(TENANT, 1));
+        assert_eq!(hub.keys(), vec![handle_key(Scope::All, 1)]);
+        // Unknown subscriptions are ignored
+        hub.unsubscribe(&handle_key(TENANT, 2));
+        assert_eq!(hub.keys().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_statuses_are_fetched_once_for_all_connections() {
+        let hub = StatusHub::new();
+        let mut statuses = hub.statuses();
+        // Two connections on the same handle, one on another
+        hub.subscribe(handle_key(TENANT, 1));
+        hub.subscribe(handle_key(TENANT, 1));
+        hub.subscribe(handle_key(TENANT, 2));
+        hub.subscribe(handle_key(TENANT, 3));
+
+        let fetches = AtomicUsize::new(0);
+        let fetched = fetch_all(hub.keys(), |key| {
+            fetches.fetch_add(1, Ordering::Relaxed);
+            async move {
+                match key.1 {
+                    Subscription::Handle(handle) if handle[0] == 1 => {
+                        Ok(handle_update(Status::Computing).into())
+                    }
+                    Subscription::Handle(handle) if handle[0] == 2 => Ok(Fetched::NotFound),
+                    _ => Err(ApiError::NotFound),
+                }
+            }
+        })
+        .await;
+        assert_eq!(fetches.load(Ordering::Relaxed), 3);
+        hub.publish(fetched);
+
+        assert!(statuses.has_changed().unwrap());
+        let statuses = statuses.borrow_and_update().clone();
+        assert_eq!(
+            statuses.get(&handle_key(TENANT, 1)),
+            Some(&handle_update(Status::Computing).into())
+        );
+        assert_eq!(
+            statuses.get(&handle_key(TENANT, 2)),
+            Some(&Fetched::NotFound)
+        );
+        // Failed fetches keep the last pushed status
+        assert_eq!(statuses.get(&handle_key(TENANT, 3)), None);
+    }
+}
@@ -3,7 +3,7 @@ use bigdecimal::num_traits::ToPrimitive;
 use opentelemetry::{
     global::{BoxedSpan, BoxedTracer, ObjectSafeSpan},
     trace::{SpanBuilder, Status, TraceContextExt, Tracer},
-    Context, KeyValue,
+    Context,
 };
 use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
 use prometheus::{register_histogram, Histogram};
@@ -16,6 +16,8 @@ use std::{
 use tokio::sync::RwLock;
 use tracing::{debug, info, warn};
 
+pub use opentelemetry::{KeyValue, Value};
+
 pub const GLOBAL_LATENCY_METRIC_NAME_L1: &str = "coprocessor_l1_txn_latency_seconds";
 pub const GLOBAL_LATENCY_METRIC_NAME_ZKPROOF: &str = "coprocessor_zkproof_txn_latency_seconds";
 
@@ -56,6 +58,18 @@ impl OtelTracer {
         self.tracer.start_with_context(name, &self.ctx)
     }
 
+    /// Starts a child span with the given attributes
+    pub fn child_span_with_attributes(
+        &self,
+        name: &'static str,
+        attributes: Vec<KeyValue>,
+    ) -> BoxedSpan {
+        self.tracer.build_with_context(
+            SpanBuilder::from_name(name).with_attributes(attributes),
+            &self.ctx,
+        )
+    }
+
     /// Sets attribute to the root span
     pub fn set_attribute(&self, key: &str, value: String) {
         self.ctx
@@ -63,11 +77,29 @@ impl OtelTracer {
             .set_attribute(KeyValue::new(key.to_owned(), value));
     }
 
+    /// Sets a typed attribute, e.g. a number or a boolean, to the root span
+    pub fn set_value(&self, key: &'static str, value: impl Into<Value>) {
+        self.ctx.span().set_attribute(KeyValue::new(key, value));
+    }
+
+    /// Records an event with attributes on the root span
+    pub fn add_event(&self, name: &'static str, attributes: Vec<KeyValue>) {
+        self.ctx.span().add_event(name, attributes);
+    }
+
     /// Consumes and ends the tracer with status Ok
     pub fn end(self) {
         self.ctx.span().set_status(Status::Ok);
         self.ctx.span().end();
     }
+
+    /// Consumes and ends the tracer with status Error with description
+    pub fn end_with_err(self, desc: String) {
+        self.ctx.span().set_status(Status::Error {
+            description: desc.into(),
+        });
+        self.ctx.span().end();
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -138,6 +170,16 @@ pub fn attribute(span: &mut BoxedSpan, key: &str, value: String) {
     span.set_attribute(KeyValue::new(key.to_owned(), value));
 }
 
+/// Sets a typed attribute, e.g. a number or a boolean, to the span
+pub fn set_value(span: &mut BoxedSpan, key: &'static str, value: impl Into<Value>) {
+    span.set_attribute(KeyValue::new(key, value));
+}
+
+/// Records an event with attributes on the span
+pub fn add_event(span: &mut BoxedSpan, name: &'static str, attributes: Vec<KeyValue>) {
+    span.add_event_with_timestamp(name.into(), SystemTime::now(), attributes);
+}
+
 /// Ends span with status Ok
 pub fn end_span(mut span: BoxedSpan) {
     span.set_status(Status::Ok);
@@ -461,3 +503,62 @@ pub async fn try_end_zkproof_transaction(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SpanData};
+
+    fn test_tracer(exporter: &InMemorySpanExporter) -> (SdkTracerProvider, OtelTracer) {
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let tracer = BoxedTracer::new(Box::new(provider.tracer("test")));
+        let ctx = Context::default().with_span(tracer.start("root"));
+        let tracer = OtelTracer {
+            ctx,
+            tracer: Arc::new(tracer),
+        };
+        (provider, tracer)
+    }
+
+    fn finished_span(exporter: &InMemorySpanExporter, name: &str) -> SpanData {
+        exporter
+            .get_finished_spans()
+            .unwrap()
+            .into_iter()
+            .find(|span| span.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn child_span_with_attributes() {
+        let exporter = InMemorySpanExporter::default();
+        let (_provider, t) = test_tracer(&exporter);
+
+        let s = t.child_span_with_attributes("child", vec![KeyValue::new("gas_limit", 21000)]);
+        end_span(s);
+        t.end();
+
+        let root = finished_span(&exporter, "root");
+        let child = finished_span(&exporter, "child");
+        assert_eq!(child.parent_span_id, root.span_context.span_id());
+        assert_eq!(child.span_context.trace_id(), root.span_context.trace_id());
+        assert!(child
+            .attributes
+            .contains(&KeyValue::new("gas_limit", 21000)));
+        assert_eq!(root.status, Status::Ok);
+    }
+
+    #[test]
+    fn end_with_err() {
+        let exporter = InMemorySpanExporter::default();
+        let (_provider, t) = test_tracer(&exporter);
+
+        t.end_with_err("receipt status = false".to_owned());
+
+        let root = finished_span(&exporter, "root");
+        assert_eq!(root.status, Status::error("receipt status = false"));
+    }
+}
@@ -1,10 +1,12 @@
+use crate::param_set::ParamSet;
 use crate::utils::safe_deserialize_key;
 use bytesize::ByteSize;
 use sqlx::{
     postgres::{types::Oid, PgRow},
     PgPool, Row,
 };
-use std::{ops::DerefMut, sync::Arc};
+use std::{ops::DerefMut, path::Path, sync::Arc};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 use tracing::info;
 
 pub struct TfheTenantKeys {
@@ -12,6 +14,10 @@ pub struct TfheTenantKeys {
     pub chain_id: i64,
     pub verifying_contract_address: String,
     pub acl_contract_address: String,
+    /// KMS key id of the tenant, see [`crate::key_version`]
+    pub key_id: Option<Vec<u8>>,
+    /// Parameters of the keys, see [`crate::param_set`]
+    pub param_set: &'static ParamSet,
     pub sks: tfhe::ServerKey,
 
     pub pks: tfhe::CompactPublicKey,
@@ -23,6 +29,8 @@ pub struct FetchTenantKeyResult {
     pub chain_id: i64,
     pub verifying_contract_address: String,
     pub acl_contract_address: String,
+    pub key_id: Option<Vec<u8>>,
+    pub param_set: &'static ParamSet,
     pub server_key: tfhe::ServerKey,
     pub public_params: Arc<tfhe::zk::CompactPkeCrs>,
     pub pks: tfhe::CompactPublicKey,
@@ -48,6 +56,8 @@ where
                     chain_id: key.chain_id,
                     verifying_contract_address: key.verifying_contract_address.clone(),
                     acl_contract_address: key.acl_contract_address.clone(),
+                    key_id: key.key_id.clone(),
+                    param_set: key.param_set,
                     server_key: key.sks.clone(),
                     public_params: key.public_params.clone(),
                     pks: key.pks.clone(),
@@ -74,7 +84,7 @@ where
 
     let query_str = format!(
         "
-            SELECT tenant_id, chain_id, acl_contract_address, verifying_contract_address, pks_key, sks_key, public_params
+            SELECT tenant_id, chain_id, acl_contract_address, verifying_contract_address, key_id, param_set, pks_key, sks_key, public_params
             FROM tenants
             WHERE {} = ANY($1::INT[])
         ",
@@ -93,6 +103,9 @@ where
         let chain_id: i64 = row.try_get("chain_id")?;
         let acl_contract_address: String = row.try_get("acl_contract_address")?;
         let verifying_contract_address: String = row.try_get("verifying_contract_address")?;
+        let key_id: Option<Vec<u8>> = row.try_get("key_id")?;
+        let param_set: Option<String> = row.try_get("param_set")?;
+        let param_set = ParamSet::resolve(param_set.as_deref())?;
         let pks_key: Vec<u8> = row.try_get("pks_key")?;
         let sks_key: Vec<u8> = row.try_get("sks_key")?;
         let public_params_key: Vec<u8> = row.try_get("public_params")?;
@@ -106,6 +119,7 @@ where
             csks.decompress()
         };
         let pks: tfhe::CompactPublicKey = safe_deserialize_key(&pks_key)?;
+        param_set.check_public_key(&pks)?;
         let public_params: tfhe::zk::CompactPkeCrs = safe_deserialize_key(&public_params_key)?;
 
         res.push(TfheTenantKeys {
@@ -113,6 +127,8 @@ where
             chain_id,
             acl_contract_address,
             verifying_contract_address,
+            key_id,
+            param_set,
             sks,
             pks,
             public_params: Arc::new(public_params),
@@ -168,6 +184,44 @@ where
     Ok(())
 }
 
+/// Evicts the cached keys whose key_id no longer matches the tenant, so that they are loaded
+/// again with the current key, see [`crate::key_version`].
+pub async fn evict_stale_tenant_keys<'a, T>(
+    ids: &[i64],
+    conn: T,
+    tenant_key_cache: &std::sync::Arc<tokio::sync::RwLock<lru::LruCache<i64, TfheTenantKeys>>>,
+    is_tenant_id: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    T: sqlx::PgExecutor<'a>,
+{
+    let column = if is_tenant_id {
+        "tenant_id::BIGINT"
+    } else {
+        "chain_id"
+    };
+    let rows = sqlx::query(&format!(
+        "SELECT {column} AS id, key_id FROM tenants WHERE {column} = ANY($1::BIGINT[])"
+    ))
+    .bind(ids)
+    .fetch_all(conn)
+    .await?;
+
+    let mut key_cache = tenant_key_cache.write().await;
+    for row in rows {
+        let id: i64 = row.try_get("id")?;
+        let key_id: Option<Vec<u8>> = row.try_get("key_id")?;
+        if key_cache
+            .peek(&id)
+            .is_some_and(|keys| keys.key_id != key_id)
+        {
+            info!(id, is_tenant_id, "Tenant key changed, evicting cached keys");
+            key_cache.pop(&id);
+        }
+    }
+    Ok(())
+}
+
 pub struct TenantInfo {
     /// The key_id of the tenant
     pub key_id: [u8; 32],
@@ -205,19 +259,32 @@ where
     Ok(res)
 }
 
-const CHUNK_SIZE: i32 = 64 * 1024; // 64KiB
+pub const CHUNK_SIZE: i32 = 64 * 1024; // 64KiB
+
+const INV_READ: i32 = 262144;
+/// `whence` of `lo_lseek64` seeking from the end of the large object.
+const SEEK_END: i32 = 2;
 pub async fn read_keys_from_large_object(
     pool: &PgPool,
     tenant_api_key: &String,
     keys_column_name: &str,
     capacity: usize,
 ) -> anyhow::Result<Vec<u8>> {
+    let oid = query_large_object_oid(pool, tenant_api_key, keys_column_name).await?;
+    read_large_object_in_chunks(pool, oid, CHUNK_SIZE, capacity).await
+}
+
+/// Returns the Oid of the large object referenced by the column of the tenant
+pub async fn query_large_object_oid(
+    pool: &PgPool,
+    tenant_api_key: &String,
+    keys_column_name: &str,
+) -> anyhow::Result<Oid> {
     let query = format!(
         "SELECT {} FROM tenants WHERE tenant_api_key = $1::uuid",
         keys_column_name
     );
 
-    // Read the Oid of the large object
     let row: PgRow = sqlx::query(&query)
         .bind(tenant_api_key)
         .fetch_one(pool)
@@ -225,8 +292,7 @@ pub async fn read_keys_from_large_object(
 
     let oid: Oid = row.try_get(0)?;
     info!("Retrieved oid: {:?}, column: {}", oid, keys_column_name);
-
-    read_large_object_in_chunks(pool, oid, CHUNK_SIZE, capacity).await
+    Ok(oid)
 }
 
 /// Read a large object by Oid from the database in chunks
@@ -236,7 +302,78 @@ pub async fn read_large_object_in_chunks(
     chunk_size: i32,
     capacity: usize,
 ) -> anyhow::Result<Vec<u8>> {
-    const INV_READ: i32 = 262144;
+    let mut bytes = Vec::with_capacity(capacity);
+    read_large_object_with(pool, large_object_oid, chunk_size, &mut bytes).await?;
+    Ok(bytes)
+}
+
+/// Returns the size of a large object, without reading it.
+pub async fn query_large_object_size(pool: &PgPool, large_object_oid: Oid) -> anyhow::Result<u64> {
+    // The large object descriptor is closed with the transaction
+    let mut tx = pool.begin().await?;
+    let fd: i32 = sqlx::query_scalar("SELECT lo_open($1, $2)")
+        .bind(large_object_oid)
+        .bind(INV_READ)
+        .fetch_one(&mut *tx)
+        .await?;
+    let size: i64 = sqlx::query_scalar("SELECT lo_lseek64($1, 0, $2)")
+        .bind(fd)
+        .bind(SEEK_END)
+        .fetch_one(&mut *tx)
+        .await?;
+    tx.rollback().await?;
+    Ok(size.try_into()?)
+}
+
+/// Read a large object by Oid from the database into a file, without keeping it in memory.
+///
+/// The file is written under a temporary name and only renamed to `path` once complete, so that
+/// an interrupted read never leaves a truncated file behind.
+pub async fn read_large_object_to_file(
+    pool: &PgPool,
+    large_object_oid: Oid,
+    chunk_size: i32,
+    path: &Path,
+) -> anyhow::Result<u64> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = BufWriter::new(tokio::fs::File::create(&tmp_path).await?);
+    let len = read_large_object_with(pool, large_object_oid, chunk_size, &mut file).await?;
+    file.flush().await?;
+    file.into_inner().sync_all().await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    info!(
+        "Large object ({:?}) written to {:?}",
+        large_object_oid, path
+    );
+    Ok(len)
+}
+
+/// Destination of the chunks of a large object.
+trait LargeObjectSink {
+    async fn write_chunk(&mut self, data: &[u8]) -> std::io::Result<()>;
+}
+
+impl LargeObjectSink for Vec<u8> {
+    async fn write_chunk(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> LargeObjectSink for BufWriter<W> {
+    async fn write_chunk(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.write_all(data).await
+    }
+}
+
+/// Read a large object by Oid from the database in chunks, written to `sink` in order. Returns the
+/// size of the large object.
+async fn read_large_object_with(
+    pool: &PgPool,
+    large_object_oid: Oid,
+    chunk_size: i32,
+    sink: &mut impl LargeObjectSink,
+) -> anyhow::Result<u64> {
     // DB transaction must be kept open until the large object is being read
     let mut tx: sqlx::Transaction<'_, sqlx::Postgres> = pool.begin().await?;
 
@@ -252,7 +389,7 @@ pub async fn read_large_object_in_chunks(
         large_object_oid, fd, chunk_size
     );
 
-    let mut bytes = Vec::with_capacity(capacity);
+    let mut len = 0u64;
 
     let mut timestamp = std::time::Instant::now();
     let started_at = std::time::Instant::now();
@@ -271,7 +408,8 @@ pub async fn read_large_object_in_chunks(
                     // No more data to read
                     break;
                 }
-                bytes.extend_from_slice(&data);
+                sink.write_chunk(&data).await?;
+                len += data.len() as u64;
             }
             _ => {
                 break;
@@ -282,15 +420,11 @@ pub async fn read_large_object_in_chunks(
         if timestamp.elapsed().as_secs() > 10 {
             // calculate the bandwidth of the read operation
             let elapsed = started_at.elapsed().as_secs();
-            let bandwidth = if elapsed > 0 {
-                bytes.len() as u64 / elapsed
-            } else {
-                bytes.len() as u64
-            };
+            let bandwidth = if elapsed > 0 { len / elapsed } else { len };
 
             info!(
                 "Read {} bytes so far from large object (Oid: {:?}), bandwidth: {}/s",
-                ByteSize::b(bytes.len() as u64),
+                ByteSize::b(len),
                 large_object_oid,
                 ByteSize::b(bandwidth)
             );
@@ -302,7 +436,7 @@ pub async fn read_large_object_in_chunks(
     info!(
         "End of large object ({:?}) reached, result length: {}, elapsed: {}",
         large_object_oid,
-        ByteSize::b(bytes.len() as u64),
+        ByteSize::b(len),
         started_at.elapsed().as_secs()
     );
 
@@ -311,7 +445,7 @@ pub async fn read_large_object_in_chunks(
         .fetch_one(&mut *tx)
         .await?;
 
-    Ok(bytes)
+    Ok(len)
 }
 
 /// Write a large object to the database in chunks
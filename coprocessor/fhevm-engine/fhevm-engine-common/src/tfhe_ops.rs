@@ -1,5 +1,5 @@
 use crate::{
-    keys::FhevmKeys,
+    param_set::ParamSet,
     types::{FheOperationType, FhevmError, SupportedFheCiphertexts, SupportedFheOperations},
     utils::{safe_deserialize, safe_deserialize_conformant},
 };
@@ -267,10 +267,9 @@ pub fn current_ciphertext_version() -> i16 {
 pub fn try_expand_ciphertext_list(
     input_ciphertext: &[u8],
     public_params: &CompactPkeCrs,
+    param_set: &ParamSet,
 ) -> Result<Vec<SupportedFheCiphertexts>, FhevmError> {
-    let pk_params = FhevmKeys::new_config()
-        .public_key_encryption_parameters()
-        .map_err(|_| FhevmError::MissingTfheRsData)?;
+    let pk_params = param_set.public_key_encryption_parameters();
 
     let the_list: tfhe::ProvenCompactCiphertextList = safe_deserialize_conformant(
         input_ciphertext,
@@ -0,0 +1,638 @@
+//! TLS of the connections to the database and to the RPC nodes, e.g. mutual TLS to internal
+//! endpoints.
+//!
+//! The database connections take the usual libpq settings, either in the URL (`sslmode`,
+//! `sslrootcert`, `sslcert` and `sslkey` query parameters) or in the `PGSSLMODE`, `PGSSLROOTCERT`,
+//! `PGSSLCERT` and `PGSSLKEY` environment variables. `sslmode=verify-ca` checks the certificate
+//! chain only and `sslmode=verify-full` the host name too, which is also sent as SNI.
+//!
+//! alloy's `WsConnect` uses the default TLS settings of the platform. [`RpcConnect`] is used in its
+//! place: without TLS options it defers to `WsConnect`, else it sets up the TLS session itself,
+//! with the CA bundle, client certificate, server name and verification mode of [`RpcTlsConfig`],
+//! before the WebSocket handshake.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use alloy::providers::WsConnect;
+use alloy::pubsub::{ConnectionHandle, ConnectionInterface, PubSubConnect};
+use alloy::rpc::json_rpc::PubSubItem;
+use alloy::transports::{TransportErrorKind, TransportResult};
+use anyhow::{bail, Context};
+use futures::{SinkExt, StreamExt};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::{debug, warn};
+
+/// Interval of the pings keeping an idle WebSocket connection alive, as alloy's.
+const KEEPALIVE: Duration = Duration::from_secs(10);
+
+/// How the certificate of the RPC node is verified.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TlsVerify {
+    /// Certificate chain and server name
+    #[default]
+    Full,
+    /// Certificate chain only, e.g. for nodes reached by IP address
+    CaOnly,
+    /// Nothing, for tests only
+    None,
+}
+
+impl FromStr for TlsVerify {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "full" => Ok(TlsVerify::Full),
+            "ca-only" => Ok(TlsVerify::CaOnly),
+            "none" => Ok(TlsVerify::None),
+            _ => Err(format!(
+                "invalid TLS verification {s:?}, expected full, ca-only or none"
+            )),
+        }
+    }
+}
+
+/// TLS options of the RPC connections. The default keeps the platform settings.
+#[derive(Clone, Debug, Default)]
+pub struct RpcTlsConfig {
+    /// PEM bundle of the CAs trusted instead of the platform ones
+    pub ca_cert: Option<PathBuf>,
+    /// PEM certificate chain presented to the node, with `client_key`
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key of `client_cert`
+    pub client_key: Option<PathBuf>,
+    /// Name sent as SNI and verified instead of the host of the URL
+    pub server_name: Option<String>,
+    pub verify: TlsVerify,
+}
+
+impl RpcTlsConfig {
+    pub fn is_default(&self) -> bool {
+        self.ca_cert.is_none()
+            && self.client_cert.is_none()
+            && self.client_key.is_none()
+            && self.server_name.is_none()
+            && self.verify == TlsVerify::Full
+    }
+
+    /// Loads the certificates and keys, so that a misconfiguration is reported on startup.
+    pub fn build(&self) -> anyhow::Result<RpcTls> {
+        if self.is_default() {
+            return Ok(RpcTls::default());
+        }
+        let server_name = self
+            .server_name
+            .as_deref()
+            .map(|name| ServerName::try_from(name.to_owned()))
+            .transpose()
+            .context("Invalid TLS server name")?;
+        Ok(RpcTls {
+            client_config: Some(Arc::new(self.client_config()?)),
+            server_name,
+        })
+    }
+
+    fn client_config(&self) -> anyhow::Result<ClientConfig> {
+        let provider = Arc::new(crypto::aws_lc_rs::default_provider());
+        let mut roots = RootCertStore::empty();
+        match &self.ca_cert {
+            Some(path) => {
+                for cert in load_certs(path)? {
+                    roots.add(cert)?;
+                }
+            }
+            None => {
+                let native = rustls_native_certs::load_native_certs();
+                for err in native.errors {
+                    warn!(error = %err, "Cannot load a platform CA certificate");
+                }
+                roots.add_parsable_certificates(native.certs);
+            }
+        }
+
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match self.verify {
+            TlsVerify::Full => builder.with_root_certificates(roots),
+            TlsVerify::CaOnly => {
+                let verifier =
+                    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()?;
+                builder
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(CaOnlyVerifier(verifier)))
+            }
+            TlsVerify::None => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoVerifier(provider))),
+        };
+        Ok(match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => bail!("The TLS client certificate and key must be given together"),
+        })
+    }
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid PEM certificates {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("No certificate in {}", path.display());
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Invalid PEM private key {}", path.display()))?
+        .with_context(|| format!("No private key in {}", path.display()))
+}
+
+/// Verifies the certificate chain but not the server name.
+#[derive(Debug)]
+struct CaOnlyVerifier(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for CaOnlyVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            res => res,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+/// Accepts any certificate, the handshake signatures are still checked.
+#[derive(Debug)]
+struct NoVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// TLS settings of the RPC connections, built by [`RpcTlsConfig::build`]. The default keeps the
+/// platform settings.
+#[derive(Clone, Debug, Default)]
+pub struct RpcTls {
+    client_config: Option<Arc<ClientConfig>>,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl RpcTls {
+    /// Connection to a WebSocket RPC endpoint, given to `ProviderBuilder::connect_pubsub_with`.
+    pub fn connect(&self, url: impl Into<String>) -> RpcConnect {
+        RpcConnect {
+            url: url.into(),
+            tls: self.clone(),
+            max_retries: None,
+            retry_interval: None,
+        }
+    }
+}
+
+/// Replacement of `WsConnect` applying the [`RpcTls`] settings.
+#[derive(Clone, Debug)]
+pub struct RpcConnect {
+    url: String,
+    tls: RpcTls,
+    max_retries: Option<u32>,
+    retry_interval: Option<Duration>,
+}
+
+impl RpcConnect {
+    /// See `WsConnect::with_max_retries`.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// See `WsConnect::with_retry_interval`.
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = Some(retry_interval);
+        self
+    }
+
+    fn ws_connect(&self) -> WsConnect {
+        let mut ws = WsConnect::new(self.url.clone());
+        if let Some(max_retries) = self.max_retries {
+            ws = ws.with_max_retries(max_retries);
+        }
+        if let Some(retry_interval) = self.retry_interval {
+            ws = ws.with_retry_interval(retry_interval);
+        }
+        ws
+    }
+}
+
+impl PubSubConnect for RpcConnect {
+    fn is_local(&self) -> bool {
+        self.ws_connect().is_local()
+    }
+
+    async fn connect(&self) -> TransportResult<ConnectionHandle> {
+        let Some(client_config) = self.tls.client_config.clone() else {
+            return self.ws_connect().connect().await;
+        };
+        let request = self
+            .url
+            .as_str()
+            .into_client_request()
+            .map_err(TransportErrorKind::custom)?;
+        if request.uri().scheme_str() != Some("wss") {
+            return Err(TransportErrorKind::custom_str(
+                "the RPC TLS options require a wss:// URL",
+            ));
+        }
+        let host = request
+            .uri()
+            .host()
+            .ok_or_else(|| TransportErrorKind::custom_str("no host in the RPC URL"))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned();
+        let port = request.uri().port_u16().unwrap_or(443);
+        let server_name = match &self.tls.server_name {
+            Some(server_name) => server_name.clone(),
+            None => ServerName::try_from(host.clone()).map_err(TransportErrorKind::custom)?,
+        };
+
+        let tcp = TcpStream::connect((host.as_str(), port))
+            .await
+            .map_err(TransportErrorKind::custom)?;
+        let tls = TlsConnector::from(client_config)
+            .connect(server_name, tcp)
+            .await
+            .map_err(TransportErrorKind::custom)?;
+        let (socket, _) = tokio_tungstenite::client_async(request, tls)
+            .await
+            .map_err(TransportErrorKind::custom)?;
+
+        let (mut handle, interface) = ConnectionHandle::new();
+        if let Some(max_retries) = self.max_retries {
+            handle = handle.with_max_retries(max_retries);
+        }
+        if let Some(retry_interval) = self.retry_interval {
+            handle = handle.with_retry_interval(retry_interval);
+        }
+        tokio::spawn(run_backend(socket, interface));
+        Ok(handle)
+    }
+}
+
+/// Relays the requests of the provider to the socket and the responses and subscription items
+/// back, like alloy's WebSocket backend. The provider reconnects when the connection is closed
+/// with an error.
+async fn run_backend<S>(mut socket: WebSocketStream<S>, mut interface: ConnectionInterface)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut keepalive = tokio::time::interval(KEEPALIVE);
+    keepalive.reset();
+    let errored = loop {
+        tokio::select! {
+            biased;
+            request = interface.recv_from_frontend() => {
+                let Some(request) = request else {
+                    debug!("RPC frontend dropped, closing the connection");
+                    break false;
+                };
+                keepalive.reset();
+                let message = Message::Text(request.get().to_owned().into());
+                if let Err(err) = socket.send(message).await {
+                    warn!(error = %err, "Cannot send to the RPC node");
+                    break true;
+                }
+            }
+            _ = keepalive.tick() => {
+                if let Err(err) = socket.send(Message::Ping(Default::default())).await {
+                    warn!(error = %err, "Cannot ping the RPC node");
+                    break true;
+                }
+            }
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<PubSubItem>(&text) {
+                    Ok(item) => {
+                        if interface.send_to_frontend(item).is_err() {
+                            break false;
+                        }
+                    }
+                    Err(err) => {
+                        warn!(error = %err, "Invalid message from the RPC node");
+                        break true;
+                    }
+                },
+                Some(Ok(Message::Close(_))) | None => {
+                    warn!("RPC node closed the connection");
+                    break true;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => {
+                    warn!(error = %err, "RPC connection failed");
+                    break true;
+                }
+            }
+        }
+    };
+    if errored {
+        interface.close_with_error();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::ServerConfig;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/tls")
+            .join(name)
+    }
+
+    fn ca_config(verify: TlsVerify, ca_cert: &str) -> RpcTlsConfig {
+        RpcTlsConfig {
+            ca_cert: Some(fixture(ca_cert)),
+            verify,
+            ..Default::default()
+        }
+    }
+
+    /// Verifies the server certificate, issued for `node.internal`, as `server_name`.
+    fn verify_server_cert(
+        verifier: &dyn ServerCertVerifier,
+        server_name: &str,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let chain = load_certs(&fixture("server.pem")).unwrap();
+        verifier.verify_server_cert(
+            &chain[0],
+            &chain[1..],
+            &ServerName::try_from(server_name.to_owned()).unwrap(),
+            &[],
+            UnixTime::now(),
+        )
+    }
+
+    fn web_pki_verifier(ca_cert: &str) -> Arc<WebPkiServerVerifier> {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(&fixture(ca_cert)).unwrap() {
+            roots.add(cert).unwrap();
+        }
+        WebPkiServerVerifier::builder_with_provider(
+            Arc::new(roots),
+            Arc::new(crypto::aws_lc_rs::default_provider()),
+        )
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_verification_modes() {
+        assert_eq!("full".parse(), Ok(TlsVerify::Full));
+        assert_eq!("ca-only".parse(), Ok(TlsVerify::CaOnly));
+        assert_eq!("none".parse(), Ok(TlsVerify::None));
+        assert!("Full".parse::<TlsVerify>().is_err());
+    }
+
+    #[test]
+    fn default_config_keeps_the_platform_settings() {
+        let config = RpcTlsConfig::default();
+        assert!(config.is_default());
+        let tls = config.build().unwrap();
+        assert!(tls.client_config.is_none());
+        assert!(tls.server_name.is_none());
+
+        let config = RpcTlsConfig {
+            verify: TlsVerify::CaOnly,
+            ..Default::default()
+        };
+        assert!(!config.is_default());
+        assert!(config.build().unwrap().client_config.is_some());
+    }
+
+    #[test]
+    fn loads_pem_certificates_and_keys() {
+        assert_eq!(load_certs(&fixture("ca.pem")).unwrap().len(), 1);
+        assert!(load_key(&fixture("client.key")).is_ok());
+
+        let err = load_certs(&fixture("client.key")).unwrap_err();
+        assert!(err.to_string().starts_with("No certificate"), "{err}");
+        let err = load_key(&fixture("client.pem")).unwrap_err();
+        assert!(err.to_string().starts_with("No private key"), "{err}");
+        let err = load_certs(&fixture("missing.pem")).unwrap_err();
+        assert!(err.to_string().starts_with("Cannot read"), "{err}");
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let config = RpcTlsConfig {
+            client_cert: Some(fixture("client.pem")),
+            ..ca_config(TlsVerify::Full, "ca.pem")
+        };
+        assert!(config.build().is_err());
+
+        let config = RpcTlsConfig {
+            server_name: Some("not a name".to_owned()),
+            ..ca_config(TlsVerify::Full, "ca.pem")
+        };
+        assert!(config.build().is_err());
+
+        let config = RpcTlsConfig {
+            client_cert: Some(fixture("client.pem")),
+            client_key: Some(fixture("client.key")),
+            ..ca_config(TlsVerify::Full, "ca.pem")
+        };
+        assert!(config.build().is_ok());
+    }
+
+    #[test]
+    fn ca_only_verifier_ignores_the_server_name() {
+        let full = web_pki_verifier("ca.pem");
+        let ca_only = CaOnlyVerifier(full.clone());
+
+        assert!(verify_server_cert(full.as_ref(), "node.internal").is_ok());
+        assert!(matches!(
+            verify_server_cert(full.as_ref(), "other.internal"),
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. }
+            ))
+        ));
+        assert!(verify_server_cert(&ca_only, "other.internal").is_ok());
+        assert!(verify_server_cert(&ca_only, "127.0.0.1").is_ok());
+
+        // The certificate chain is still verified
+        let ca_only = CaOnlyVerifier(web_pki_verifier("other-ca.pem"));
+        assert!(matches!(
+            verify_server_cert(&ca_only, "node.internal"),
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::UnknownIssuer
+            ))
+        ));
+    }
+
+    /// Accepts one TLS connection with the server certificate and returns its address.
+    async fn serve_tls() -> std::net::SocketAddr {
+        let provider = Arc::new(crypto::aws_lc_rs::default_provider());
+        let server_config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                load_certs(&fixture("server.pem")).unwrap(),
+                load_key(&fixture("server.key")).unwrap(),
+            )
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let acceptor = TlsAcceptor::from(Arc::new(server_config));
+            while let Ok((tcp, _)) = listener.accept().await {
+                let _ = acceptor.accept(tcp).await;
+            }
+        });
+        addr
+    }
+
+    async fn handshake(config: &RpcTlsConfig, server_name: &str) -> std::io::Result<()> {
+        let addr = serve_tls().await;
+        let tls = config.build().unwrap();
+        let server_name = tls
+            .server_name
+            .clone()
+            .unwrap_or_else(|| ServerName::try_from(server_name.to_owned()).unwrap());
+        let tcp = TcpStream::connect(addr).await?;
+        TlsConnector::from(tls.client_config.unwrap())
+            .connect(server_name, tcp)
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn handshakes_with_the_configured_verification() {
+        assert!(
+            handshake(&ca_config(TlsVerify::Full, "ca.pem"), "127.0.0.1")
+                .await
+                .is_err()
+        );
+        assert!(
+            handshake(&ca_config(TlsVerify::CaOnly, "ca.pem"), "127.0.0.1")
+                .await
+                .is_ok()
+        );
+        assert!(
+            handshake(&ca_config(TlsVerify::CaOnly, "other-ca.pem"), "127.0.0.1")
+                .await
+                .is_err()
+        );
+        assert!(
+            handshake(&ca_config(TlsVerify::None, "other-ca.pem"), "127.0.0.1")
+                .await
+                .is_ok()
+        );
+
+        // The configured server name is verified in place of the host
+        let config = RpcTlsConfig {
+            server_name: Some("node.internal".to_owned()),
+            ..ca_config(TlsVerify::Full, "ca.pem")
+        };
+        assert!(handshake(&config, "127.0.0.1").await.is_ok());
+    }
+}
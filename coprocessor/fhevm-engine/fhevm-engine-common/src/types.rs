@@ -14,7 +14,12 @@ use tfhe::{
     ReRandomizationContext,
 };
 
-use crate::utils::{safe_deserialize, safe_serialize};
+use crate::buffer_pool::{self, SizeHints};
+use crate::ciphertext_format;
+use crate::utils::{safe_deserialize, safe_serialize, safe_serialize_into};
+
+/// Size of the last compressed ciphertext of each type, to reuse pooled buffers of the right size.
+static COMPRESSED_SIZE_HINTS: SizeHints<32> = SizeHints::new();
 
 #[derive(Debug)]
 pub enum FhevmError {
@@ -131,6 +136,9 @@ pub enum FhevmError {
         fhe_operation: String,
         type_to_cast_to: i16,
     },
+    UnknownCiphertextFormat(Option<u8>),
+    UnknownParamSet(String),
+    ParamSetMismatch(&'static str),
 }
 
 impl std::error::Error for FhevmError {}
@@ -298,6 +306,18 @@ impl std::fmt::Display for FhevmError {
             Self::InvalidHandle => {
                 write!(f, "Invalid ciphertext handle")
             }
+            Self::UnknownCiphertextFormat(Some(version)) => {
+                write!(f, "Unknown ciphertext format version: {version}")
+            }
+            Self::UnknownCiphertextFormat(None) => {
+                write!(f, "Truncated ciphertext envelope")
+            }
+            Self::UnknownParamSet(name) => {
+                write!(f, "Unknown parameter set: {name}")
+            }
+            Self::ParamSetMismatch(name) => {
+                write!(f, "Keys were not generated with parameter set {name}")
+            }
             Self::UnsupportedFheTypes {
                 fhe_operation,
                 input_types,
@@ -340,6 +360,30 @@ pub enum SupportedFheCiphertexts {
     Scalar(Vec<u8>),
 }
 
+/// Names of the ciphertext types, indexed by their type number.
+const FHE_TYPE_NAMES: [&str; 12] = [
+    "FheBool",
+    "FheUint4",
+    "FheUint8",
+    "FheUint16",
+    "FheUint32",
+    "FheUint64",
+    "FheUint128",
+    "FheUint160",
+    "FheUint256",
+    "FheBytes64",
+    "FheBytes128",
+    "FheBytes256",
+];
+
+/// Name of a ciphertext type number, as stored in `ciphertexts` and handles.
+pub fn fhe_type_name(fhe_type: i16) -> Option<&'static str> {
+    usize::try_from(fhe_type)
+        .ok()
+        .and_then(|index| FHE_TYPE_NAMES.get(index))
+        .copied()
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, strum::EnumIter)]
 #[repr(i8)]
 pub enum SupportedFheOperations {
@@ -448,19 +492,8 @@ impl SupportedFheCiphertexts {
 
     pub fn type_name(&self) -> &'static str {
         match self {
-            SupportedFheCiphertexts::FheBool(..) => "FheBool",
-            SupportedFheCiphertexts::FheUint4(..) => "FheUint4",
-            SupportedFheCiphertexts::FheUint8(..) => "FheUint8",
-            SupportedFheCiphertexts::FheUint16(..) => "FheUint16",
-            SupportedFheCiphertexts::FheUint32(..) => "FheUint32",
-            SupportedFheCiphertexts::FheUint64(..) => "FheUint64",
-            SupportedFheCiphertexts::FheUint128(..) => "FheUint128",
-            SupportedFheCiphertexts::FheUint160(..) => "FheUint160",
-            SupportedFheCiphertexts::FheUint256(..) => "FheUint256",
-            SupportedFheCiphertexts::FheBytes64(..) => "FheBytes64",
-            SupportedFheCiphertexts::FheBytes128(..) => "FheBytes128",
-            SupportedFheCiphertexts::FheBytes256(..) => "FheBytes256",
             SupportedFheCiphertexts::Scalar(..) => "Scalar",
+            _ => FHE_TYPE_NAMES[self.type_num() as usize],
         }
     }
 
@@ -544,13 +577,17 @@ impl SupportedFheCiphertexts {
             }
         };
         let list = builder.build().expect("ciphertext compression");
-        (type_num, safe_serialize(&list))
+        let mut out = buffer_pool::get(COMPRESSED_SIZE_HINTS.get(type_num as usize));
+        ciphertext_format::write_header(&mut out);
+        safe_serialize_into(&list, &mut out);
+        COMPRESSED_SIZE_HINTS.record(type_num as usize, out.len());
+        (type_num, out)
     }
 
     #[cfg(feature = "gpu")]
     pub fn decompress(ct_type: i16, list: &[u8], gpu_idx: usize) -> Result<Self> {
         use crate::gpu_memory::{release_memory_on_gpu, reserve_memory_on_gpu};
-        let ctlist: CompressedCiphertextList = safe_deserialize(list)?;
+        let ctlist = Self::parse_compressed_list(list)?;
         let mut reserved_mem = 0;
         if let Ok(Some(decomp_size)) = ctlist.get_decompression_size_on_gpu(gpu_idx) {
             reserved_mem = decomp_size;
@@ -563,17 +600,24 @@ impl SupportedFheCiphertexts {
 
     #[cfg(not(feature = "gpu"))]
     pub fn decompress(ct_type: i16, list: &[u8], _: usize) -> Result<Self> {
-        let ctlist: CompressedCiphertextList = safe_deserialize(list)?;
+        let ctlist = Self::parse_compressed_list(list)?;
         Self::decompress_impl(ct_type, &ctlist)
     }
 
     // Decompress without checking if enough GPU memory is available -
     // used when GPU featre is active, but decompressing on CPU
     pub fn decompress_no_memcheck(ct_type: i16, list: &[u8]) -> Result<Self> {
-        let ctlist: CompressedCiphertextList = safe_deserialize(list)?;
+        let ctlist = Self::parse_compressed_list(list)?;
         Self::decompress_impl(ct_type, &ctlist)
     }
 
+    /// Parses a stored ciphertext (see [`ciphertext_format`]) into a compressed list, without
+    /// decompressing it. Does not need the server key.
+    pub fn parse_compressed_list(list: &[u8]) -> Result<CompressedCiphertextList> {
+        let (_, list) = ciphertext_format::open(list)?;
+        Ok(safe_deserialize(list)?)
+    }
+
     pub fn decompress_impl(ct_type: i16, list: &CompressedCiphertextList) -> Result<Self> {
         match ct_type {
             0 => Ok(SupportedFheCiphertexts::FheBool(
@@ -967,14 +1011,12 @@ impl From<SupportedFheOperations> for i16 {
     }
 }
 
+/// Raw handle bytes. See [`crate::handle::TypedHandle`] for the typed handle.
 pub type Handle = Vec<u8>;
 pub const HANDLE_LEN: usize = 32;
 
 pub fn get_ct_type(handle: &[u8]) -> Result<i16, FhevmError> {
-    match handle.len() {
-        HANDLE_LEN => Ok(handle[30] as i16),
-        _ => Err(FhevmError::InvalidHandle),
-    }
+    Ok(crate::handle::TypedHandle::try_from(handle)?.fhe_type())
 }
 
 pub fn is_ebytes_type(inp: i16) -> bool {
@@ -1002,6 +1044,38 @@ impl TryFrom<i16> for AllowEvents {
     }
 }
 
+/// Kind of a row in the `decryption_responses` table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecryptionResponseType {
+    Public = 0,
+    User = 1,
+    /// Public decryption response agreed on by a quorum of KMS signers, the signature column
+    /// holds the ABI encoding of their signatures as `bytes[]`, sent one per response
+    PublicAggregated = 2,
+}
+
+impl DecryptionResponseType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DecryptionResponseType::Public => "public",
+            DecryptionResponseType::User => "user",
+            DecryptionResponseType::PublicAggregated => "public_aggregated",
+        }
+    }
+}
+
+impl TryFrom<i16> for DecryptionResponseType {
+    type Error = FhevmError;
+    fn try_from(value: i16) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(DecryptionResponseType::Public),
+            1 => Ok(DecryptionResponseType::User),
+            2 => Ok(DecryptionResponseType::PublicAggregated),
+            _ => Err(FhevmError::BadInputs),
+        }
+    }
+}
+
 pub type BlockchainProvider = FillProvider<
     JoinFill<
         alloy::providers::Identity,
@@ -13,11 +13,17 @@ pub const SAFE_SER_DESER_SNS_KEY_LIMIT: u64 = 1024 * 1024 * 1024 * 2;
 
 pub fn safe_serialize<T: Serialize + Named + Versionize>(object: &T) -> Vec<u8> {
     let mut out = vec![];
-    tfhe::safe_serialization::safe_serialize(object, &mut out, SAFE_SER_DESER_LIMIT)
-        .expect("safe serialize succeeds");
+    safe_serialize_into(object, &mut out);
     out
 }
 
+/// Same as [`safe_serialize`], appending to `out`, e.g. a buffer from the
+/// [`buffer_pool`](crate::buffer_pool).
+pub fn safe_serialize_into<T: Serialize + Named + Versionize>(object: &T, out: &mut Vec<u8>) {
+    tfhe::safe_serialization::safe_serialize(object, out, SAFE_SER_DESER_LIMIT)
+        .expect("safe serialize succeeds");
+}
+
 pub fn safe_deserialize<T: DeserializeOwned + Named + Unversionize>(
     input: &[u8],
 ) -> Result<T, FhevmError> {
@@ -0,0 +1,86 @@
+//! Warm-up of the keys before a worker reports ready.
+//!
+//! The first operations run with a freshly loaded key are much slower than the next ones, while
+//! its pages are faulted in and the FFT plans are built. Workers therefore warm their keys up
+//! before processing any work: they load each key and run a canary operation with it, and only
+//! then report ready on `/readyz`, so that no work is routed to a cold worker. The operands of the
+//! canary are encrypted with the public key of the tenant: the operations on trivial ciphertexts
+//! are evaluated in the clear, without any PBS, and would leave the key cold. The warm-up duration
+//! of each key, load included, is exported as a metric.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
+
+use prometheus::{register_histogram_vec, HistogramVec};
+use tracing::info;
+
+static KEY_WARMUP_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "coprocessor_key_warmup_duration_seconds",
+        "Time to load a key and run a canary operation with it",
+        &["key"],
+        vec![0.1, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+    )
+    .unwrap()
+});
+
+/// Readiness of a worker, shared between the worker and its health check.
+#[derive(Clone, Debug, Default)]
+pub struct Readiness(Arc<AtomicBool>);
+
+impl Readiness {
+    pub fn set_ready(&self, ready: bool) {
+        self.0.store(ready, Ordering::SeqCst);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Runs a canary operation with the server key, its operands encrypted with the public key, on
+/// the current thread. The server key of the thread is unset afterwards.
+pub fn run_canary(
+    server_key: &tfhe::ServerKey,
+    public_key: &tfhe::CompactPublicKey,
+) -> Result<(), tfhe::Error> {
+    tfhe::set_server_key(server_key.clone());
+    let res = canary(public_key);
+    tfhe::unset_server_key();
+    res
+}
+
+/// Same as [`run_canary`], on the GPU of the server key.
+#[cfg(feature = "gpu")]
+pub fn run_gpu_canary(
+    server_key: &tfhe::CudaServerKey,
+    public_key: &tfhe::CompactPublicKey,
+) -> Result<(), tfhe::Error> {
+    tfhe::set_server_key(server_key.clone());
+    let res = canary(public_key);
+    tfhe::unset_server_key();
+    res
+}
+
+fn canary(public_key: &tfhe::CompactPublicKey) -> Result<(), tfhe::Error> {
+    let list = tfhe::CompactCiphertextList::builder(public_key)
+        .push(1u8)
+        .push(2u8)
+        .build();
+    let operands = list.expand()?;
+    let missing = || tfhe::Error::new("missing canary operand".to_string());
+    let a: tfhe::FheUint8 = operands.get(0)?.ok_or_else(missing)?;
+    let b: tfhe::FheUint8 = operands.get(1)?.ok_or_else(missing)?;
+    std::hint::black_box(&a * &b);
+    Ok(())
+}
+
+/// Records the warm-up of a key started at `started_at`.
+pub fn observe_warm_up(key: &str, started_at: Instant) {
+    let elapsed = started_at.elapsed();
+    KEY_WARMUP_DURATION
+        .with_label_values(&[key])
+        .observe(elapsed.as_secs_f64());
+    info!(key, ?elapsed, "Key warmed up");
+}
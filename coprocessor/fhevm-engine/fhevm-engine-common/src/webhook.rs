@@ -0,0 +1,336 @@
+//! Export of the pipeline results to an external HTTP endpoint, e.g. an indexer.
+//!
+//! The services hand their state transitions to a [`WebhookExporter`] once they are committed:
+//! computations completed or failed by the tfhe-worker, decryption responses confirmed on the
+//! Gateway by the transaction-sender. Events are queued in memory and POSTed in batches, as a
+//! JSON array of events tagged with their type, e.g.
+//! `[{"type": "computation_completed", "tenant_id": 1, "handle": "0x..", ..}]`.
+//!
+//! When a secret is configured, every request carries `X-Webhook-Timestamp`, in seconds since
+//! the epoch, and `X-Webhook-Signature: sha256=<hex>`, the HMAC-SHA256 of `<timestamp>.<body>`
+//! under the secret, so that the receiver can authenticate the batch and reject replays.
+//!
+//! Delivery is best effort and never slows the pipeline down: a batch is retried with an
+//! exponential backoff on network errors, 5xx, 408 and 429 responses, up to the configured
+//! attempts, and events are dropped when the queue is full. Drops are counted in
+//! `coprocessor_webhook_events_dropped_counter`. Receivers must tolerate duplicates, a batch may
+//! be delivered again if its response is lost.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::secret::SecretString;
+
+pub use reqwest::Url;
+
+/// Events waiting to be sent, beyond which new ones are dropped.
+const QUEUE_CAPACITY: usize = 10_000;
+/// Timeout of a single POST.
+const POST_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_BACKOFF_INITIAL: Duration = Duration::from_millis(500);
+const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+static EVENTS_EXPORTED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_webhook_events_exported_counter",
+        "Number of events delivered to the webhook",
+        &["type"]
+    )
+    .unwrap()
+});
+
+static EVENTS_DROPPED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_webhook_events_dropped_counter",
+        "Number of events not delivered to the webhook, queue full or attempts exhausted",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+static POST_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_webhook_post_failure_counter",
+        "Number of failed POSTs to the webhook, retried or not",
+        &["status"]
+    )
+    .unwrap()
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WebhookEventType {
+    ComputationCompleted,
+    ComputationFailed,
+    DecryptionCompleted,
+}
+
+impl WebhookEventType {
+    pub const ALL: [Self; 3] = [
+        Self::ComputationCompleted,
+        Self::ComputationFailed,
+        Self::DecryptionCompleted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ComputationCompleted => "computation_completed",
+            Self::ComputationFailed => "computation_failed",
+            Self::DecryptionCompleted => "decryption_completed",
+        }
+    }
+}
+
+impl FromStr for WebhookEventType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|event_type| event_type.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "invalid webhook event type {s:?}, expected one of {}",
+                    Self::ALL.map(|event_type| event_type.as_str()).join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for WebhookEventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// State transition exported, handles and ids are `0x` prefixed hex.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    ComputationCompleted {
+        tenant_id: i32,
+        handle: String,
+        transaction_id: String,
+    },
+    ComputationFailed {
+        tenant_id: i32,
+        handle: String,
+        transaction_id: String,
+        error: String,
+    },
+    DecryptionCompleted {
+        decryption_id: String,
+        response_type: String,
+        transaction_hash: String,
+    },
+}
+
+impl WebhookEvent {
+    pub fn event_type(&self) -> WebhookEventType {
+        match self {
+            Self::ComputationCompleted { .. } => WebhookEventType::ComputationCompleted,
+            Self::ComputationFailed { .. } => WebhookEventType::ComputationFailed,
+            Self::DecryptionCompleted { .. } => WebhookEventType::DecryptionCompleted,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    pub url: Url,
+    /// HMAC key signing the batches, unsigned when unset
+    pub secret: Option<SecretString>,
+    /// Exported event types, all when empty
+    pub event_types: Vec<WebhookEventType>,
+    /// Maximum number of events of a POST
+    pub max_batch_size: usize,
+    /// Maximum time an event waits for others before being sent
+    pub max_latency: Duration,
+    /// Attempts of a POST before its events are dropped
+    pub max_attempts: u32,
+}
+
+impl WebhookConfig {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            secret: None,
+            event_types: vec![],
+            max_batch_size: 100,
+            max_latency: Duration::from_secs(1),
+            max_attempts: 5,
+        }
+    }
+}
+
+/// Handle queuing the events of the service, cheap to clone. The events are sent by a
+/// background task, which stops once every handle is dropped and the queue is drained.
+#[derive(Clone)]
+pub struct WebhookExporter {
+    sender: mpsc::Sender<WebhookEvent>,
+    event_types: Arc<HashSet<WebhookEventType>>,
+}
+
+impl fmt::Debug for WebhookExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebhookExporter")
+            .field("event_types", &self.event_types)
+            .finish()
+    }
+}
+
+impl WebhookExporter {
+    pub fn spawn(conf: WebhookConfig) -> anyhow::Result<Self> {
+        let client = Client::builder().timeout(POST_TIMEOUT).build()?;
+        let event_types = if conf.event_types.is_empty() {
+            WebhookEventType::ALL.into_iter().collect()
+        } else {
+            conf.event_types.iter().copied().collect()
+        };
+        info!(url = %conf.url, ?event_types, "Exporting events to webhook");
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(run(client, conf, receiver));
+        Ok(Self {
+            sender,
+            event_types: Arc::new(event_types),
+        })
+    }
+
+    /// Whether events of this type are exported, to skip building the others.
+    pub fn wants(&self, event_type: WebhookEventType) -> bool {
+        self.event_types.contains(&event_type)
+    }
+
+    /// Queues the event if its type is exported, never waits.
+    pub fn export(&self, event: WebhookEvent) {
+        if !self.wants(event.event_type()) {
+            return;
+        }
+        if let Err(err) = self.sender.try_send(event) {
+            EVENTS_DROPPED.with_label_values(&["queue_full"]).inc();
+            debug!(error = %err, "Webhook queue full, event dropped");
+        }
+    }
+
+    pub fn export_all(&self, events: impl IntoIterator<Item = WebhookEvent>) {
+        events.into_iter().for_each(|event| self.export(event));
+    }
+}
+
+async fn run(client: Client, conf: WebhookConfig, mut receiver: mpsc::Receiver<WebhookEvent>) {
+    let mut batch = Vec::with_capacity(conf.max_batch_size);
+    while let Some(event) = receiver.recv().await {
+        batch.push(event);
+        let deadline = Instant::now() + conf.max_latency;
+        while batch.len() < conf.max_batch_size {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(event)) => batch.push(event),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        deliver(&client, &conf, &batch).await;
+        batch.clear();
+    }
+    info!(url = %conf.url, "Webhook exporter stopped");
+}
+
+/// Sends the batch, retrying the transient failures.
+async fn deliver(client: &Client, conf: &WebhookConfig, batch: &[WebhookEvent]) {
+    let body = match serde_json::to_vec(batch) {
+        Ok(body) => body,
+        Err(err) => {
+            warn!(error = %err, "Cannot serialize webhook events");
+            EVENTS_DROPPED
+                .with_label_values(&["serialization"])
+                .inc_by(batch.len() as u64);
+            return;
+        }
+    };
+    let mut backoff = RETRY_BACKOFF_INITIAL;
+    for attempt in 1..=conf.max_attempts.max(1) {
+        let (status, retryable) = match post(client, conf, &body).await {
+            Ok(()) => {
+                for event in batch {
+                    EVENTS_EXPORTED
+                        .with_label_values(&[event.event_type().as_str()])
+                        .inc();
+                }
+                debug!(count = batch.len(), "Webhook events delivered");
+                return;
+            }
+            Err(PostError::Status(status)) => (
+                status.as_str().to_owned(),
+                status.is_server_error()
+                    || status == StatusCode::REQUEST_TIMEOUT
+                    || status == StatusCode::TOO_MANY_REQUESTS,
+            ),
+            Err(PostError::Request(err)) => {
+                warn!(url = %conf.url, attempt, error = %err, "Webhook POST failed");
+                ("error".to_owned(), true)
+            }
+        };
+        POST_FAILURES.with_label_values(&[&status]).inc();
+        warn!(url = %conf.url, attempt, status, retryable, "Webhook POST rejected");
+        if !retryable || attempt == conf.max_attempts {
+            break;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(RETRY_BACKOFF_MAX);
+    }
+    EVENTS_DROPPED
+        .with_label_values(&["undelivered"])
+        .inc_by(batch.len() as u64);
+    warn!(url = %conf.url, count = batch.len(), "Webhook events dropped");
+}
+
+enum PostError {
+    Status(StatusCode),
+    Request(reqwest::Error),
+}
+
+async fn post(client: &Client, conf: &WebhookConfig, body: &[u8]) -> Result<(), PostError> {
+    let mut request = client
+        .post(conf.url.clone())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_vec());
+    if let Some(secret) = &conf.secret {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string();
+        request = request
+            .header(TIMESTAMP_HEADER, &timestamp)
+            .header(SIGNATURE_HEADER, sign(secret.expose(), &timestamp, body));
+    }
+    let response = request.send().await.map_err(PostError::Request)?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(PostError::Status(response.status()))
+    }
+}
+
+/// `sha256=<hex>` signature of the body sent at `timestamp`.
+pub fn sign(secret: &str, timestamp: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
@@ -0,0 +1,252 @@
+//! Batches the small writes issued per event or per item into a single transaction.
+//!
+//! Services push writes to a [`WriteBatcher`] from as many tasks as they like. A background task
+//! accumulates them and flushes them together, within one transaction, as soon as either
+//! `max_batch_size` writes are pending or the oldest pending write has waited for `max_latency`.
+//! Statements issued with `sqlx::query!` are prepared once per connection and cached, so the
+//! writes of a flush only pay for the round trips, and a single commit.
+//!
+//! At most [`QUEUED_BATCHES`] batches of writes are queued, beyond that [`WriteBatcher::write`]
+//! waits for room so that producers slow down to the pace of the database.
+
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use prometheus::{register_histogram, Histogram};
+use sqlx::{PgConnection, Pool, Postgres};
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, error};
+
+static WRITE_BATCH_SIZE_HISTOGRAM: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "coprocessor_write_batch_size",
+        "Number of writes flushed in a single transaction",
+        vec![1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0]
+    )
+    .unwrap()
+});
+
+static WRITE_BATCH_FLUSH_DURATION_HISTOGRAM: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "coprocessor_write_batch_flush_duration_seconds",
+        "Time to write and commit a batch of writes",
+        vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]
+    )
+    .unwrap()
+});
+
+/// Number of full batches that can be queued before the producers wait.
+pub const QUEUED_BATCHES: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct WriteBatcherConfig {
+    /// Number of pending writes that triggers a flush. A value of 0 or 1 flushes every write on
+    /// its own.
+    pub max_batch_size: usize,
+    /// Maximum time a write waits for others before being flushed.
+    pub max_latency: Duration,
+}
+
+impl Default for WriteBatcherConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 100,
+            max_latency: Duration::from_millis(10),
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum WriteBatchError {
+    /// The transaction of the batch failed, none of its writes is committed.
+    #[error("Batch write failed: {0}")]
+    Database(Arc<sqlx::Error>),
+
+    #[error("Write batcher stopped")]
+    Stopped,
+
+    #[error("Write batcher queue is full")]
+    Full,
+}
+
+/// Writes a batch of items with the given connection.
+///
+/// The connection is within the transaction of the batch, the implementation must not commit.
+#[async_trait]
+pub trait BatchWriter: Send + Sync + 'static {
+    type Item: Send + Sync + 'static;
+
+    async fn write(&self, conn: &mut PgConnection, items: &[Self::Item])
+        -> Result<(), sqlx::Error>;
+}
+
+type Responder = oneshot::Sender<Result<(), WriteBatchError>>;
+
+enum Command<T> {
+    Write(T, Responder),
+    Flush(Responder),
+}
+
+/// Resolves once the write is committed or its batch failed.
+///
+/// Dropping the handle does not cancel the write, failures are logged by the batcher anyway.
+pub struct WriteHandle(oneshot::Receiver<Result<(), WriteBatchError>>);
+
+impl WriteHandle {
+    pub async fn committed(self) -> Result<(), WriteBatchError> {
+        self.0.await.unwrap_or(Err(WriteBatchError::Stopped))
+    }
+}
+
+/// Handle to a background task flushing the pushed writes in batches.
+///
+/// Cheap to clone. The task flushes the pending writes and stops once all the handles are
+/// dropped.
+pub struct WriteBatcher<T> {
+    sender: mpsc::Sender<Command<T>>,
+}
+
+impl<T> Clone for WriteBatcher<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> WriteBatcher<T> {
+    /// Spawns the flushing task on the current runtime.
+    pub fn spawn<W>(pool: Pool<Postgres>, writer: W, conf: WriteBatcherConfig) -> Self
+    where
+        W: BatchWriter<Item = T>,
+    {
+        let (sender, receiver) = mpsc::channel(conf.max_batch_size.max(1) * QUEUED_BATCHES);
+        tokio::spawn(run(pool, writer, conf, receiver));
+        Self { sender }
+    }
+
+    /// Queues a write, to be flushed with the next batch. Waits for room if the queue is full.
+    pub async fn write(&self, item: T) -> WriteHandle {
+        let (responder, receiver) = oneshot::channel();
+        if let Err(mpsc::error::SendError(Command::Write(_, responder))) =
+            self.sender.send(Command::Write(item, responder)).await
+        {
+            let _ = responder.send(Err(WriteBatchError::Stopped));
+        }
+        WriteHandle(receiver)
+    }
+
+    /// Queues a write like [`WriteBatcher::write`], but fails with [`WriteBatchError::Full`]
+    /// instead of waiting if the queue is full.
+    pub fn try_write(&self, item: T) -> WriteHandle {
+        let (responder, receiver) = oneshot::channel();
+        if let Err(err) = self.sender.try_send(Command::Write(item, responder)) {
+            let (error, command) = match err {
+                mpsc::error::TrySendError::Full(command) => (WriteBatchError::Full, command),
+                mpsc::error::TrySendError::Closed(command) => (WriteBatchError::Stopped, command),
+            };
+            if let Command::Write(_, responder) = command {
+                let _ = responder.send(Err(error));
+            }
+        }
+        WriteHandle(receiver)
+    }
+
+    /// Flushes the pending writes without waiting for the batch to fill up, and waits until they
+    /// are committed.
+    pub async fn flush(&self) -> Result<(), WriteBatchError> {
+        let (responder, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::Flush(responder))
+            .await
+            .map_err(|_| WriteBatchError::Stopped)?;
+        WriteHandle(receiver).committed().await
+    }
+}
+
+async fn run<W: BatchWriter>(
+    pool: Pool<Postgres>,
+    writer: W,
+    conf: WriteBatcherConfig,
+    mut receiver: mpsc::Receiver<Command<W::Item>>,
+) {
+    let max_batch_size = conf.max_batch_size.max(1);
+    let mut items = Vec::with_capacity(max_batch_size);
+    let mut responders = Vec::with_capacity(max_batch_size);
+    // Set when the first write of a batch is pushed
+    let mut deadline: Option<Instant> = None;
+
+    loop {
+        let command = tokio::select! {
+            command = receiver.recv() => command,
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                flush(&pool, &writer, &mut items, &mut responders).await;
+                deadline = None;
+                continue;
+            }
+        };
+
+        match command {
+            Some(Command::Write(item, responder)) => {
+                items.push(item);
+                responders.push(responder);
+                if items.len() >= max_batch_size {
+                    flush(&pool, &writer, &mut items, &mut responders).await;
+                    deadline = None;
+                } else if deadline.is_none() {
+                    deadline = Some(Instant::now() + conf.max_latency);
+                }
+            }
+            Some(Command::Flush(responder)) => {
+                responders.push(responder);
+                flush(&pool, &writer, &mut items, &mut responders).await;
+                deadline = None;
+            }
+            None => {
+                flush(&pool, &writer, &mut items, &mut responders).await;
+                debug!("Write batcher stopped");
+                return;
+            }
+        }
+    }
+}
+
+/// Writes the pending items in one transaction and notifies all the responders of the outcome.
+async fn flush<W: BatchWriter>(
+    pool: &Pool<Postgres>,
+    writer: &W,
+    items: &mut Vec<W::Item>,
+    responders: &mut Vec<Responder>,
+) {
+    let result = if items.is_empty() {
+        Ok(())
+    } else {
+        let count = items.len();
+        let started_at = std::time::Instant::now();
+        let result = write_batch(pool, writer, items).await;
+        WRITE_BATCH_SIZE_HISTOGRAM.observe(count as f64);
+        WRITE_BATCH_FLUSH_DURATION_HISTOGRAM.observe(started_at.elapsed().as_secs_f64());
+        match &result {
+            Ok(_) => debug!(count, "Flushed batch of writes"),
+            Err(err) => error!(error = %err, count, "Failed to flush batch of writes"),
+        }
+        result.map_err(|err| WriteBatchError::Database(Arc::new(err)))
+    };
+    items.clear();
+    for responder in responders.drain(..) {
+        let _ = responder.send(result.clone());
+    }
+}
+
+async fn write_batch<W: BatchWriter>(
+    pool: &Pool<Postgres>,
+    writer: &W,
+    items: &[W::Item],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    writer.write(&mut tx, items).await?;
+    tx.commit().await
+}
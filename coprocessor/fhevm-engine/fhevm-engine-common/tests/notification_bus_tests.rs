@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use fhevm_engine_common::notification_bus::{self, BusConfig, BusEvent, BusKind};
+use sqlx::PgPool;
+use test_harness::instance::{setup_test_db, ImportMode};
+
+#[tokio::test]
+async fn postgres_bus_delivers_the_published_notifications() -> anyhow::Result<()> {
+    let db_instance = setup_test_db(ImportMode::None)
+        .await
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+    let pool = PgPool::connect(db_instance.db_url()).await?;
+    let bus = notification_bus::connect(&BusConfig::default(), &pool).await?;
+    assert_eq!(bus.kind(), BusKind::Postgres);
+
+    let mut subscription = bus.subscribe(&["bus_work".to_owned()]).await?;
+    bus.publish("bus_work", "41").await?;
+    bus.publish("bus_work", "42").await?;
+    bus.publish("bus_other", "43").await?;
+
+    let wakeup = subscription
+        .recv_coalesced(Duration::from_millis(500))
+        .await;
+    assert_eq!(wakeup.count, 2);
+    assert_eq!(wakeup.latest_block, Some(42));
+    match wakeup.event {
+        BusEvent::Notification { channel, payload } => {
+            assert_eq!(channel, "bus_work");
+            assert_eq!(payload, "42");
+        }
+        BusEvent::Reconnected => panic!("unexpected reconnection"),
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use fhevm_engine_common::pg_listener::{Coalesced, ListenerEvent, SupervisedListener};
+use sqlx::PgPool;
+use test_harness::instance::{setup_test_db, DBInstance, ImportMode};
+
+struct Setup {
+    pool: PgPool,
+    listener: SupervisedListener,
+    _db_instance: DBInstance,
+}
+
+impl Setup {
+    async fn new(channel: &str) -> anyhow::Result<Self> {
+        let db_instance = setup_test_db(ImportMode::None)
+            .await
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        let pool = PgPool::connect(db_instance.db_url()).await?;
+        let listener = SupervisedListener::connect(&pool, [channel]).await?;
+        Ok(Self {
+            pool,
+            listener,
+            _db_instance: db_instance,
+        })
+    }
+}
+
+async fn notify(pool: &PgPool, channel: &str, payload: &str) {
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(channel)
+        .bind(payload)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+fn payload(coalesced: &Coalesced) -> &str {
+    match &coalesced.event {
+        ListenerEvent::Notification(notification) => notification.payload(),
+        ListenerEvent::Reconnected => panic!("unexpected reconnection"),
+    }
+}
+
+#[tokio::test]
+async fn pending_notifications_are_folded_without_debounce() -> anyhow::Result<()> {
+    let channel = "coalesce_pending";
+    let mut setup = Setup::new(channel).await?;
+    for payload in ["5", "9", "7"] {
+        notify(&setup.pool, channel, payload).await;
+    }
+    // Let the notifications reach the listener connection
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let coalesced = setup.listener.recv_coalesced(Duration::ZERO).await;
+    assert_eq!(coalesced.count, 3);
+    assert_eq!(coalesced.latest_block, Some(9));
+    assert_eq!(payload(&coalesced), "7");
+    Ok(())
+}
+
+#[tokio::test]
+async fn notifications_within_the_debounce_are_folded() -> anyhow::Result<()> {
+    let channel = "coalesce_debounce";
+    let mut setup = Setup::new(channel).await?;
+    notify(&setup.pool, channel, "1").await;
+    let pool = setup.pool.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        notify(&pool, channel, "not a block").await;
+        notify(&pool, channel, "2").await;
+        // Past the debounce, woken separately
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        notify(&pool, channel, "3").await;
+    });
+
+    let coalesced = setup
+        .listener
+        .recv_coalesced(Duration::from_millis(1000))
+        .await;
+    assert_eq!(coalesced.count, 3);
+    assert_eq!(coalesced.latest_block, Some(2));
+    assert_eq!(payload(&coalesced), "2");
+
+    let coalesced = setup.listener.recv_coalesced(Duration::ZERO).await;
+    assert_eq!(coalesced.count, 1);
+    assert_eq!(payload(&coalesced), "3");
+    Ok(())
+}
+
+#[tokio::test]
+async fn reconnection_is_reported_and_folds_the_following_notifications() -> anyhow::Result<()> {
+    let channel = "coalesce_reconnect";
+    let mut setup = Setup::new(channel).await?;
+    notify(&setup.pool, channel, "1").await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    sqlx::query(
+        "SELECT pg_terminate_backend(pid) FROM pg_stat_activity
+         WHERE query LIKE 'LISTEN%' || $1 || '%' AND pid <> pg_backend_pid()",
+    )
+    .bind(channel)
+    .execute(&setup.pool)
+    .await?;
+
+    // The notification received before the connection loss is still delivered
+    let coalesced = setup
+        .listener
+        .recv_coalesced(Duration::from_millis(500))
+        .await;
+    assert_eq!(coalesced.count, 1);
+    assert_eq!(payload(&coalesced), "1");
+
+    let pool = setup.pool.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        notify(&pool, channel, "4").await;
+    });
+    let coalesced = setup
+        .listener
+        .recv_coalesced(Duration::from_millis(1000))
+        .await;
+    assert!(matches!(coalesced.event, ListenerEvent::Reconnected));
+    assert_eq!(coalesced.count, 1);
+    assert_eq!(coalesced.latest_block, Some(4));
+    Ok(())
+}
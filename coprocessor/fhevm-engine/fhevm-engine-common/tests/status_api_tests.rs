@@ -0,0 +1,320 @@
+use std::time::Duration;
+
+use fhevm_engine_common::column_encryption::ColumnEncryption;
+use fhevm_engine_common::secret::SecretString;
+use fhevm_engine_common::status_api::{StatusApiConfig, StatusApiServer};
+use reqwest::StatusCode;
+use serde_json::Value;
+use sqlx::types::Uuid;
+use sqlx::PgPool;
+use test_harness::db_utils::insert_random_tenant;
+use test_harness::instance::{setup_test_db, DBInstance, ImportMode};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+const OPERATOR_TOKEN: &str = "operator-token";
+
+struct Tenant {
+    tenant_id: i32,
+    chain_id: i64,
+    api_key: Uuid,
+}
+
+struct Setup {
+    pool: PgPool,
+    base_url: String,
+    client: reqwest::Client,
+    cancel_token: CancellationToken,
+    _db_instance: DBInstance,
+}
+
+impl Setup {
+    async fn new() -> anyhow::Result<Self> {
+        let db_instance = setup_test_db(ImportMode::None)
+            .await
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        let pool = PgPool::connect(db_instance.db_url()).await?;
+        let cancel_token = CancellationToken::new();
+        let router = StatusApiServer::new(
+            pool.clone(),
+            StatusApiConfig {
+                port: 0,
+                auth_token: Some(SecretString::from(OPERATOR_TOKEN.to_string())),
+                notify_channels: vec![],
+                push_poll_interval: Duration::from_secs(1),
+                column_encryption: ColumnEncryption::default(),
+            },
+            cancel_token.clone(),
+        )
+        .router();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let base_url = format!("http://{}", listener.local_addr()?);
+        let shutdown = cancel_token.clone();
+        tokio::spawn(async move {
+            axum::serve(listener, router)
+                .with_graceful_shutdown(async move { shutdown.cancelled().await })
+                .await
+        });
+        Ok(Self {
+            pool,
+            base_url,
+            client: reqwest::Client::new(),
+            cancel_token,
+            _db_instance: db_instance,
+        })
+    }
+
+    async fn tenant(&self) -> anyhow::Result<Tenant> {
+        let tenant_id = insert_random_tenant(&self.pool).await?;
+        let (chain_id, api_key): (i64, Uuid) =
+            sqlx::query_as("SELECT chain_id, tenant_api_key FROM tenants WHERE tenant_id = $1")
+                .bind(tenant_id)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(Tenant {
+            tenant_id,
+            chain_id,
+            api_key,
+        })
+    }
+
+    async fn insert_computation(
+        &self,
+        tenant: &Tenant,
+        handle: &[u8],
+        transaction_id: &[u8],
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO computations
+                (tenant_id, output_handle, output_type, dependencies, fhe_operation, is_scalar,
+                 transaction_id)
+             VALUES ($1, $2, 4, '{}', 24, FALSE, $3)",
+        )
+        .bind(tenant.tenant_id)
+        .bind(handle)
+        .bind(transaction_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_proof(&self, tenant: &Tenant, zk_proof_id: i64) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO verify_proofs (zk_proof_id, chain_id, contract_address, user_address)
+             VALUES ($1, $2, '0x00', '0x00')",
+        )
+        .bind(zk_proof_id)
+        .bind(tenant.chain_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_delegation(
+        &self,
+        tenant: &Tenant,
+        contract_address: &str,
+        delegation_counter: i64,
+        expiry_date: i64,
+        status: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO user_decryption_delegation_history
+                (tenant_id, delegator, delegate, contract_address, delegation_counter,
+                 expiry_date, status)
+             VALUES ($1, '0xDelegator', '0xDelegate', $2, $3, $4, $5)",
+        )
+        .bind(tenant.tenant_id)
+        .bind(contract_address)
+        .bind(delegation_counter)
+        .bind(expiry_date)
+        .bind(status)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get(&self, path: &str, token: Option<&str>) -> anyhow::Result<(StatusCode, Value)> {
+        let mut request = self.client.get(format!("{}{path}", self.base_url));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.json().await.unwrap_or(Value::Null);
+        Ok((status, body))
+    }
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        self.cancel_token.cancel();
+    }
+}
+
+fn encode_hex(value: &[u8]) -> String {
+    format!("0x{}", hex::encode(value))
+}
+
+#[tokio::test]
+async fn requests_without_a_known_token_are_rejected() -> anyhow::Result<()> {
+    let setup = Setup::new().await?;
+    let path = format!("/v1/status/handles/{}", encode_hex(&[1; 32]));
+
+    let (status, _) = setup.get(&path, None).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = setup.get(&path, Some("not-a-key")).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let unknown_key = Uuid::new_v4().to_string();
+    let (status, _) = setup.get(&path, Some(&unknown_key)).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    Ok(())
+}
+
+#[tokio::test]
+async fn tenant_only_sees_its_own_handles() -> anyhow::Result<()> {
+    let setup = Setup::new().await?;
+    let alice = setup.tenant().await?;
+    let bob = setup.tenant().await?;
+    let alice_handle = [1u8; 32];
+    let bob_handle = [2u8; 32];
+    setup
+        .insert_computation(&alice, &alice_handle, &[10; 32])
+        .await?;
+    setup
+        .insert_computation(&bob, &bob_handle, &[11; 32])
+        .await?;
+    let alice_key = alice.api_key.to_string();
+
+    let path = format!("/v1/status/handles/{}", encode_hex(&alice_handle));
+    let (status, body) = setup.get(&path, Some(&alice_key)).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["handle"], encode_hex(&alice_handle));
+    assert_eq!(body["status"], "queued");
+
+    let path = format!("/v1/status/handles/{}", encode_hex(&bob_handle));
+    let (status, _) = setup.get(&path, Some(&alice_key)).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // The operator sees all the tenants
+    let (status, body) = setup.get(&path, Some(OPERATOR_TOKEN)).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["handle"], encode_hex(&bob_handle));
+    Ok(())
+}
+
+#[tokio::test]
+async fn transaction_handles_are_paginated_and_scoped() -> anyhow::Result<()> {
+    let setup = Setup::new().await?;
+    let alice = setup.tenant().await?;
+    let bob = setup.tenant().await?;
+    let transaction_id = [10u8; 32];
+    for i in 1..=3 {
+        setup
+            .insert_computation(&alice, &[i; 32], &transaction_id)
+            .await?;
+    }
+    let alice_key = alice.api_key.to_string();
+
+    let path = format!(
+        "/v1/status/transactions/{}?limit=2",
+        encode_hex(&transaction_id)
+    );
+    let (status, body) = setup.get(&path, Some(&alice_key)).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["handles"].as_array().unwrap().len(), 2);
+    assert_eq!(body["next"], encode_hex(&[2; 32]));
+
+    let path = format!(
+        "/v1/status/transactions/{}?limit=2&after={}",
+        encode_hex(&transaction_id),
+        encode_hex(&[2; 32])
+    );
+    let (status, body) = setup.get(&path, Some(&alice_key)).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["handles"][0]["handle"], encode_hex(&[3; 32]));
+    assert!(body["next"].is_null());
+
+    let path = format!("/v1/status/transactions/{}", encode_hex(&transaction_id));
+    let (status, _) = setup.get(&path, Some(&bob.api_key.to_string())).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+#[tokio::test]
+async fn tenant_only_sees_the_inputs_of_its_chain() -> anyhow::Result<()> {
+    let setup = Setup::new().await?;
+    let alice = setup.tenant().await?;
+    let bob = setup.tenant().await?;
+    setup.insert_proof(&alice, 1).await?;
+
+    let (status, body) = setup
+        .get("/v1/status/inputs/1", Some(&alice.api_key.to_string()))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["zk_proof_id"], 1);
+    assert_eq!(body["status"], "computing");
+
+    if bob.chain_id != alice.chain_id {
+        let (status, _) = setup
+            .get("/v1/status/inputs/1", Some(&bob.api_key.to_string()))
+            .await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    let (status, _) = setup
+        .get("/v1/status/inputs/1", Some(OPERATOR_TOKEN))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_handles_are_rejected() -> anyhow::Result<()> {
+    let setup = Setup::new().await?;
+    let (status, _) = setup
+        .get("/v1/status/handles/0xnothex", Some(OPERATOR_TOKEN))
+        .await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    Ok(())
+}
+
+#[tokio::test]
+async fn delegation_history_is_reported() -> anyhow::Result<()> {
+    let setup = Setup::new().await?;
+    let alice = setup.tenant().await?;
+    let bob = setup.tenant().await?;
+    setup
+        .insert_delegation(&alice, "0xContractA", 1, i64::MAX, "superseded")
+        .await?;
+    setup
+        .insert_delegation(&alice, "0xContractA", 2, 1, "applied")
+        .await?;
+    setup
+        .insert_delegation(&alice, "0xContractB", 1, i64::MAX, "applied")
+        .await?;
+    let alice_key = alice.api_key.to_string();
+
+    // The accounts are matched case insensitively
+    let path = "/v1/status/delegations/0xdelegator/0xDELEGATE";
+    let (status, body) = setup.get(path, Some(&alice_key)).await?;
+    assert_eq!(status, StatusCode::OK);
+    let events = body.as_array().unwrap();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0]["contract_address"], "0xContractA");
+    assert_eq!(events[0]["delegation_counter"], 2);
+    assert_eq!(events[0]["status"], "applied");
+    assert_eq!(events[0]["expired"], true);
+    assert_eq!(events[1]["status"], "superseded");
+    assert_eq!(events[1]["expired"], false);
+    assert_eq!(events[2]["expired"], false);
+
+    let path = format!("{path}?contract_address=0xcontractb");
+    let (status, body) = setup.get(&path, Some(&alice_key)).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+
+    let (status, _) = setup.get(&path, Some(&bob.api_key.to_string())).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    Ok(())
+}
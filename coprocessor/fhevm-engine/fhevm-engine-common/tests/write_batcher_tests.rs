@@ -0,0 +1,206 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use fhevm_engine_common::write_batcher::{
+    BatchWriter, WriteBatchError, WriteBatcher, WriteBatcherConfig, QUEUED_BATCHES,
+};
+use sqlx::{PgConnection, PgPool};
+use test_harness::instance::{setup_test_db, DBInstance, ImportMode};
+use tokio::time::timeout;
+
+/// Inserts the values into its own table, recording the size of each batch.
+#[derive(Clone)]
+struct TestWriter {
+    table: &'static str,
+    batches: Arc<Mutex<Vec<usize>>>,
+}
+
+#[async_trait]
+impl BatchWriter for TestWriter {
+    type Item = i32;
+
+    async fn write(&self, conn: &mut PgConnection, items: &[i32]) -> Result<(), sqlx::Error> {
+        self.batches.lock().unwrap().push(items.len());
+        for value in items {
+            sqlx::query(&format!("INSERT INTO {} (value) VALUES ($1)", self.table))
+                .bind(value)
+                .execute(&mut *conn)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+struct Setup {
+    pool: PgPool,
+    writer: TestWriter,
+    _db_instance: DBInstance,
+}
+
+impl Setup {
+    async fn new(table: &'static str) -> anyhow::Result<Self> {
+        let db_instance = setup_test_db(ImportMode::None)
+            .await
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        let pool = PgPool::connect(db_instance.db_url()).await?;
+        sqlx::query(&format!("DROP TABLE IF EXISTS {table}"))
+            .execute(&pool)
+            .await?;
+        sqlx::query(&format!("CREATE TABLE {table} (value INT PRIMARY KEY)"))
+            .execute(&pool)
+            .await?;
+        Ok(Self {
+            pool,
+            writer: TestWriter {
+                table,
+                batches: Arc::default(),
+            },
+            _db_instance: db_instance,
+        })
+    }
+
+    fn spawn(&self, max_batch_size: usize, max_latency: Duration) -> WriteBatcher<i32> {
+        WriteBatcher::spawn(
+            self.pool.clone(),
+            self.writer.clone(),
+            WriteBatcherConfig {
+                max_batch_size,
+                max_latency,
+            },
+        )
+    }
+
+    fn batches(&self) -> Vec<usize> {
+        self.writer.batches.lock().unwrap().clone()
+    }
+
+    async fn values(&self) -> anyhow::Result<Vec<i32>> {
+        let values = sqlx::query_scalar(&format!(
+            "SELECT value FROM {} ORDER BY value",
+            self.writer.table
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(values)
+    }
+}
+
+const NEVER: Duration = Duration::from_secs(3600);
+const COMMIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test]
+async fn writes_are_flushed_together_after_max_latency() -> anyhow::Result<()> {
+    let setup = Setup::new("write_batcher_latency").await?;
+    let batcher = setup.spawn(10, Duration::from_millis(50));
+
+    let mut written = vec![];
+    for value in 1..=3 {
+        written.push(batcher.write(value).await);
+    }
+    for handle in written {
+        timeout(COMMIT_TIMEOUT, handle.committed()).await??;
+    }
+    assert_eq!(setup.batches(), vec![3]);
+    assert_eq!(setup.values().await?, vec![1, 2, 3]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn full_batch_is_flushed_without_waiting() -> anyhow::Result<()> {
+    let setup = Setup::new("write_batcher_full").await?;
+    let batcher = setup.spawn(2, NEVER);
+
+    let mut written = vec![];
+    for value in 1..=5 {
+        written.push(batcher.write(value).await);
+    }
+    let mut written = written.into_iter();
+    for handle in written.by_ref().take(4) {
+        timeout(COMMIT_TIMEOUT, handle.committed()).await??;
+    }
+    assert_eq!(setup.batches(), vec![2, 2]);
+    // The last write waits for another one, or for a flush
+    let last = written.next().unwrap();
+    assert!(timeout(Duration::from_millis(200), last.committed())
+        .await
+        .is_err());
+    assert_eq!(setup.values().await?, vec![1, 2, 3, 4]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn flush_commits_the_pending_writes() -> anyhow::Result<()> {
+    let setup = Setup::new("write_batcher_flush").await?;
+    let batcher = setup.spawn(10, NEVER);
+
+    // Nothing pending, nothing written
+    timeout(COMMIT_TIMEOUT, batcher.flush()).await??;
+    assert!(setup.batches().is_empty());
+
+    let written = batcher.write(1).await;
+    timeout(COMMIT_TIMEOUT, batcher.flush()).await??;
+    timeout(COMMIT_TIMEOUT, written.committed()).await??;
+    assert_eq!(setup.batches(), vec![1]);
+    assert_eq!(setup.values().await?, vec![1]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_batch_fails_all_its_writes() -> anyhow::Result<()> {
+    let setup = Setup::new("write_batcher_failure").await?;
+    let batcher = setup.spawn(3, NEVER);
+
+    // The duplicate value fails the whole batch
+    let mut written = vec![];
+    for value in [1, 2, 1] {
+        written.push(batcher.write(value).await);
+    }
+    for handle in written {
+        let result = timeout(COMMIT_TIMEOUT, handle.committed()).await?;
+        assert!(matches!(result, Err(WriteBatchError::Database(_))));
+    }
+    assert!(setup.values().await?.is_empty());
+
+    // The next batch is not affected
+    let written = batcher.write(1).await;
+    timeout(COMMIT_TIMEOUT, batcher.flush()).await??;
+    timeout(COMMIT_TIMEOUT, written.committed()).await??;
+    assert_eq!(setup.values().await?, vec![1]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn pending_writes_are_flushed_when_the_batcher_is_dropped() -> anyhow::Result<()> {
+    let setup = Setup::new("write_batcher_drop").await?;
+    let batcher = setup.spawn(10, NEVER);
+
+    let written = batcher.write(1).await;
+    drop(batcher);
+    timeout(COMMIT_TIMEOUT, written.committed()).await??;
+    assert_eq!(setup.values().await?, vec![1]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn full_queue_rejects_try_write() -> anyhow::Result<()> {
+    let setup = Setup::new("write_batcher_queue").await?;
+    let batcher = setup.spawn(1, NEVER);
+
+    // The flushing task does not run before this test yields, so nothing is dequeued
+    let queued = QUEUED_BATCHES as i32;
+    let written: Vec<_> = (1..=queued + 1)
+        .map(|value| batcher.try_write(value))
+        .collect();
+    let mut written = written.into_iter();
+    let rejected = written.next_back().unwrap();
+    assert!(matches!(
+        rejected.committed().await,
+        Err(WriteBatchError::Full)
+    ));
+    for handle in written {
+        timeout(COMMIT_TIMEOUT, handle.committed()).await??;
+    }
+    assert_eq!(setup.values().await?, (1..=queued).collect::<Vec<_>>());
+    Ok(())
+}
@@ -0,0 +1,25 @@
+#![no_main]
+
+use fhevm_engine_common::ciphertext_format::{self, CiphertextFormat};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|blob: &[u8]| {
+    let Ok((format, payload)) = ciphertext_format::open(blob) else {
+        return;
+    };
+    assert_eq!(ciphertext_format::seal_as(payload.to_vec(), format), blob);
+    assert_eq!(
+        ciphertext_format::into_payload(blob.to_vec()).unwrap(),
+        payload
+    );
+
+    // Only the V1 round trip is checked: an arbitrary legacy payload may look like an envelope,
+    // which a tfhe-rs serialization never does.
+    let reencoded = ciphertext_format::reencode(blob, CiphertextFormat::V1).unwrap();
+    let reencoded = reencoded.as_deref().unwrap_or(blob);
+    assert_eq!(
+        ciphertext_format::open(reencoded).unwrap(),
+        (CiphertextFormat::V1, payload)
+    );
+    ciphertext_format::reencode(blob, CiphertextFormat::Legacy).unwrap();
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use fhevm_engine_common::types::SupportedFheCiphertexts;
+use libfuzzer_sys::fuzz_target;
+
+// Ciphertexts are read back from the database and from other coprocessors' uploads
+fuzz_target!(|blob: &[u8]| {
+    let _ = SupportedFheCiphertexts::parse_compressed_list(blob);
+});
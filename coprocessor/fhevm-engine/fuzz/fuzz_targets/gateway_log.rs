@@ -0,0 +1,60 @@
+#![no_main]
+
+use alloy::primitives::{address, Address};
+use fhevm_engine_fuzz::event_log;
+use fhevm_gateway_bindings::ciphertext_commits::CiphertextCommits::CiphertextCommitsEvents;
+use fhevm_gateway_bindings::decryption::Decryption::DecryptionEvents;
+use fhevm_gateway_bindings::events::{decode_any_event, GatewayAddresses};
+use fhevm_gateway_bindings::gateway_config::GatewayConfig::GatewayConfigEvents;
+use fhevm_gateway_bindings::input_verification::InputVerification::InputVerificationEvents;
+use fhevm_gateway_bindings::kms_generation::KMSGeneration::KMSGenerationEvents;
+use fhevm_gateway_bindings::multichain_acl::MultichainACL::MultichainACLEvents;
+use libfuzzer_sys::fuzz_target;
+
+const CONTRACTS: [(Address, &[[u8; 32]]); 6] = [
+    (
+        address!("0x0000000000000000000000000000000000000001"),
+        CiphertextCommitsEvents::SELECTORS,
+    ),
+    (
+        address!("0x0000000000000000000000000000000000000002"),
+        DecryptionEvents::SELECTORS,
+    ),
+    (
+        address!("0x0000000000000000000000000000000000000003"),
+        GatewayConfigEvents::SELECTORS,
+    ),
+    (
+        address!("0x0000000000000000000000000000000000000004"),
+        InputVerificationEvents::SELECTORS,
+    ),
+    (
+        address!("0x0000000000000000000000000000000000000005"),
+        KMSGenerationEvents::SELECTORS,
+    ),
+    (
+        address!("0x0000000000000000000000000000000000000006"),
+        MultichainACLEvents::SELECTORS,
+    ),
+];
+
+const ADDRESSES: GatewayAddresses = GatewayAddresses {
+    ciphertext_commits: Some(CONTRACTS[0].0),
+    decryption: Some(CONTRACTS[1].0),
+    gateway_config: Some(CONTRACTS[2].0),
+    input_verification: Some(CONTRACTS[3].0),
+    kms_generation: Some(CONTRACTS[4].0),
+    multichain_acl: Some(CONTRACTS[5].0),
+};
+
+// Logs as received from the Gateway chain node
+fuzz_target!(|input: &[u8]| {
+    let Some((&contract, input)) = input.split_first() else {
+        return;
+    };
+    let (address, selectors) = CONTRACTS[contract as usize % CONTRACTS.len()];
+    let Some(log) = event_log(address, selectors, input) else {
+        return;
+    };
+    let _ = decode_any_event(&log, &ADDRESSES);
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use fhevm_engine_common::handle::TypedHandle;
+use fhevm_engine_common::types::get_ct_type;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|bytes: &[u8]| {
+    let _ = get_ct_type(bytes);
+    let Ok(handle) = TypedHandle::try_from(bytes) else {
+        return;
+    };
+    assert_eq!(handle.as_bytes().as_slice(), bytes);
+    let _ = (
+        handle.index(),
+        handle.is_computed(),
+        handle.chain_id(),
+        handle.fhe_type(),
+        handle.version(),
+    );
+});
@@ -0,0 +1,35 @@
+#![no_main]
+
+use alloy::primitives::Address;
+use fhevm_engine_fuzz::event_log;
+use fhevm_gateway_bindings::events::decode_event;
+use host_listener::contracts::AclContract::AclContractEvents;
+use host_listener::contracts::TfheContract::TfheContractEvents;
+use host_listener::database::tfhe_event_propagate::{
+    acl_result_handles, event_name, event_to_op_int, tfhe_result_handle,
+};
+use libfuzzer_sys::fuzz_target;
+
+// Logs as received from the host chain node
+fuzz_target!(|input: &[u8]| {
+    let Some((&is_acl, input)) = input.split_first() else {
+        return;
+    };
+    if is_acl % 2 == 0 {
+        let Some(log) = event_log(Address::ZERO, TfheContractEvents::SELECTORS, input) else {
+            return;
+        };
+        if let Ok(decoded) = decode_event::<TfheContractEvents>(&log) {
+            let _ = event_name(&decoded.log.data);
+            let _ = event_to_op_int(&decoded.log.data);
+            let _ = tfhe_result_handle(&decoded.log.data);
+        }
+    } else {
+        let Some(log) = event_log(Address::ZERO, AclContractEvents::SELECTORS, input) else {
+            return;
+        };
+        if let Ok(decoded) = decode_event::<AclContractEvents>(&log) {
+            let _ = acl_result_handles(&decoded.log);
+        }
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use gw_listener::aws_s3::split_url;
+use libfuzzer_sys::fuzz_target;
+
+// Bucket URLs are announced on the Gateway
+fuzz_target!(|s3_bucket_url: String| {
+    let _ = split_url(&s3_bucket_url);
+});
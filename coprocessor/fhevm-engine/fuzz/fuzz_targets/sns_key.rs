@@ -0,0 +1,9 @@
+#![no_main]
+
+use gw_listener::sks_key::extract_server_key_without_ns;
+use libfuzzer_sys::fuzz_target;
+
+// Keys are downloaded from the URLs announced on the Gateway
+fuzz_target!(|sns_key: &[u8]| {
+    let _ = extract_server_key_without_ns(sns_key);
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zkproof_worker::auxiliary::ZkData;
+
+// Addresses are given by clients along with their input lists
+fuzz_target!(|input: (String, String, String, i64)| {
+    let (contract_address, user_address, acl_contract_address, chain_id) = input;
+    let aux_data = ZkData {
+        contract_address,
+        user_address,
+        acl_contract_address,
+        chain_id,
+    };
+    let _ = aux_data.assemble();
+});
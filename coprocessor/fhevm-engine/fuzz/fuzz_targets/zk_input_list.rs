@@ -0,0 +1,27 @@
+#![no_main]
+
+use std::sync::LazyLock;
+
+use fhevm_engine_common::utils::safe_deserialize_key;
+use libfuzzer_sys::fuzz_target;
+use zkproof_worker::verifier::parse_input_list;
+
+static KEYS: LazyLock<(tfhe::CompactPublicKey, tfhe::zk::CompactPkeCrs)> = LazyLock::new(|| {
+    let read = |name: &str| {
+        std::fs::read(format!(
+            "{}/../fhevm-keys/{name}",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .expect("can't read test keys")
+    };
+    (
+        safe_deserialize_key(&read("pks")).expect("valid pks"),
+        safe_deserialize_key(&read("pp")).expect("valid public params"),
+    )
+});
+
+// Input lists are uploaded by clients as is
+fuzz_target!(|raw_ct: &[u8]| {
+    let (pks, public_params) = &*KEYS;
+    let _ = parse_input_list(raw_ct, pks, public_params);
+});
@@ -0,0 +1,26 @@
+//! Helpers shared by the fuzz targets.
+
+use alloy::primitives::{Address, Bytes, Log as PrimitiveLog, B256};
+use alloy::rpc::types::Log;
+
+/// Builds a log from fuzzer input: `event index || extra topic count || topics || data`.
+///
+/// The first topic is always one of the given event selectors (`SELECTORS` of a `sol!` events
+/// enum), so that the fuzzer reaches the decoding of the event fields instead of failing on the
+/// selector.
+pub fn event_log(address: Address, selectors: &[[u8; 32]], input: &[u8]) -> Option<Log> {
+    let (&[event, topic_count], rest) = input.split_first_chunk::<2>()?;
+    let topic_count = (topic_count % 4) as usize;
+    if rest.len() < topic_count * 32 {
+        return None;
+    }
+    let (topics, data) = rest.split_at(topic_count * 32);
+
+    let mut all_topics = vec![B256::from(selectors[event as usize % selectors.len()])];
+    all_topics.extend(topics.chunks_exact(32).map(B256::from_slice));
+
+    Some(Log {
+        inner: PrimitiveLog::new_unchecked(address, all_topics, Bytes::copy_from_slice(data)),
+        ..Default::default()
+    })
+}
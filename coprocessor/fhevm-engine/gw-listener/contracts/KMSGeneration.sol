@@ -31,6 +31,10 @@ contract KMSGeneration is IKMSGeneration {
         emit ActivateKey(keyId, urls, digests);
     }
 
+    function keygen_request(uint256 prepKeygenId, uint256 keyId) external {
+        emit KeygenRequest(prepKeygenId, keyId);
+    }
+
     function keygen(ParamsType paramsType) external {
         uint256 keyId = 16;
         string[] memory urls = new string[](4);
@@ -98,7 +98,7 @@ pub trait AwsS3Interface: Send + Sync {
     ) -> anyhow::Result<bytes::Bytes>;
 }
 
-fn split_url(s3_bucket_url: &String) -> anyhow::Result<(String, String)> {
+pub fn split_url(s3_bucket_url: &String) -> anyhow::Result<(String, String)> {
     let parsed_url_and_bucket = url::Url::parse(s3_bucket_url)?;
     let bucket = parsed_url_and_bucket.path();
     let host = s3_bucket_url
@@ -1,9 +1,14 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
-use alloy::providers::{ProviderBuilder, WsConnect};
+use alloy::providers::ProviderBuilder;
 use alloy::{primitives::Address, transports::http::reqwest::Url};
 use clap::Parser;
+use fhevm_engine_common::db_schema;
+use fhevm_engine_common::notification_bus::{BusConfig, BusKind, DEFAULT_SUBJECT_PREFIX};
 use fhevm_engine_common::telemetry;
+use fhevm_engine_common::tls::{RpcTlsConfig, TlsVerify};
+use fhevm_engine_common::write_batcher::WriteBatcherConfig;
 use gw_listener::aws_s3::AwsS3Client;
 use gw_listener::chain_id_from_env;
 use gw_listener::gw_listener::GatewayListener;
@@ -23,12 +28,39 @@ struct Conf {
     #[arg(long, default_value = "16")]
     database_pool_size: u32,
 
+    /// Database schema of the host chain, in the schema-per-chain layout
+    #[arg(long, value_parser = db_schema::parse_schema_name)]
+    database_schema: Option<String>,
+
     #[arg(long, default_value = "verify_proof_requests")]
     verify_proof_req_database_channel: String,
 
+    #[arg(long, default_value = "event_key_activated")]
+    key_activated_database_channel: String,
+
     #[arg(long)]
     gw_url: Url,
 
+    /// PEM bundle of the CAs trusted for the Gateway node instead of the platform ones
+    #[arg(long)]
+    rpc_tls_ca_cert: Option<PathBuf>,
+
+    /// PEM certificate chain presented to the Gateway node, for mutual TLS
+    #[arg(long)]
+    rpc_tls_client_cert: Option<PathBuf>,
+
+    /// PEM private key of the client certificate
+    #[arg(long)]
+    rpc_tls_client_key: Option<PathBuf>,
+
+    /// Name sent as SNI and verified instead of the host of the Gateway URL
+    #[arg(long)]
+    rpc_tls_server_name: Option<String>,
+
+    /// Verification of the certificate of the Gateway node: full, ca-only (no name check) or none
+    #[arg(long, default_value = "full")]
+    rpc_tls_verify: TlsVerify,
+
     #[arg(short, long)]
     input_verification_address: Address,
 
@@ -69,6 +101,61 @@ struct Conf {
     #[arg(long, default_value_t = 100)]
     get_logs_block_batch_size: u64,
 
+    /// Maximum number of proof requests inserted in a single transaction
+    #[arg(long, default_value_t = 100)]
+    verify_proof_req_write_batch_size: usize,
+
+    /// Maximum time a proof request waits for others before being inserted
+    #[arg(long, default_value = "10ms", value_parser = parse_duration)]
+    verify_proof_req_write_max_latency: Duration,
+
+    /// Do not check at startup that the deployed contracts match the bindings
+    #[arg(long, default_value_t = false)]
+    skip_bindings_check: bool,
+
+    /// Endpoint the activated keys are downloaded from, instead of the KMS storages announced in
+    /// the events
+    #[arg(long)]
+    key_endpoint_url: Option<Url>,
+
+    /// Directory where the downloaded keys are cached
+    #[arg(long)]
+    key_cache_dir: Option<PathBuf>,
+
+    /// Bus the proof requests and key activations are also published on: postgres (LISTEN/NOTIFY
+    /// only) or nats
+    #[arg(long, default_value = "postgres")]
+    message_bus: BusKind,
+
+    /// Server of the NATS message bus
+    #[arg(long)]
+    nats_url: Option<String>,
+
+    /// Prefix of the NATS subjects, followed by the database channel
+    #[arg(long, default_value = DEFAULT_SUBJECT_PREFIX)]
+    nats_subject_prefix: String,
+
+    /// Store the logs of the events acted on in gw_raw_events, to audit and replay them
+    #[arg(long, default_value_t = false)]
+    raw_events: bool,
+
+    /// Database the events are also written to, to validate a migration before switching the
+    /// readers to it
+    #[arg(long)]
+    shadow_database_url: Option<String>,
+
+    /// Database schema of the host chain in the shadow database
+    #[arg(long, value_parser = db_schema::parse_schema_name)]
+    shadow_database_schema: Option<String>,
+
+    /// Interval between two comparisons of the database and the shadow database
+    #[arg(long, default_value = "5m", value_parser = parse_duration)]
+    shadow_report_interval: Duration,
+
+    /// Maximum time of a write to the shadow database, the write being counted as failed past it
+    #[arg(long, default_value = "5s", value_parser = parse_duration)]
+    shadow_write_timeout: Duration,
+
     /// gw-listener service name in OTLP traces
     #[arg(long, default_value = "gw-listener")]
     pub service_name: String,
@@ -112,10 +199,19 @@ async fn main() -> anyhow::Result<()> {
         .clone()
         .unwrap_or_else(|| std::env::var("DATABASE_URL").expect("DATABASE_URL is undefined"));
 
+    let rpc_tls = RpcTlsConfig {
+        ca_cert: conf.rpc_tls_ca_cert.clone(),
+        client_cert: conf.rpc_tls_client_cert.clone(),
+        client_key: conf.rpc_tls_client_key.clone(),
+        server_name: conf.rpc_tls_server_name.clone(),
+        verify: conf.rpc_tls_verify,
+    }
+    .build()?;
     let provider = loop {
         match ProviderBuilder::new()
-            .connect_ws(
-                WsConnect::new(conf.gw_url.clone())
+            .connect_pubsub_with(
+                rpc_tls
+                    .connect(conf.gw_url.clone())
                     .with_max_retries(conf.provider_max_retries)
                     .with_retry_interval(conf.provider_retry_interval),
             )
@@ -148,7 +244,9 @@ async fn main() -> anyhow::Result<()> {
         host_chain_id,
         database_url,
         database_pool_size: conf.database_pool_size,
+        database_schema: conf.database_schema,
         verify_proof_req_db_channel: conf.verify_proof_req_database_channel,
+        key_activated_db_channel: conf.key_activated_database_channel,
         gw_url: conf.gw_url,
         error_sleep_initial_secs: conf.error_sleep_initial_secs,
         error_sleep_max_secs: conf.error_sleep_max_secs,
@@ -156,6 +254,22 @@ async fn main() -> anyhow::Result<()> {
         health_check_timeout: conf.health_check_timeout,
         get_logs_poll_interval: conf.get_logs_poll_interval,
         get_logs_block_batch_size: conf.get_logs_block_batch_size,
+        verify_proof_req_write_batch: WriteBatcherConfig {
+            max_batch_size: conf.verify_proof_req_write_batch_size,
+            max_latency: conf.verify_proof_req_write_max_latency,
+        },
+        key_endpoint_url: conf.key_endpoint_url,
+        key_cache_dir: conf.key_cache_dir,
+        message_bus: BusConfig {
+            kind: conf.message_bus,
+            nats_url: conf.nats_url,
+            subject_prefix: conf.nats_subject_prefix,
+        },
+        store_raw_events: conf.raw_events,
+        shadow_database_url: conf.shadow_database_url,
+        shadow_database_schema: conf.shadow_database_schema,
+        shadow_report_interval: conf.shadow_report_interval,
+        shadow_write_timeout: conf.shadow_write_timeout,
     };
 
     let gw_listener = GatewayListener::new(
@@ -167,6 +281,10 @@ async fn main() -> anyhow::Result<()> {
         aws_s3_client.clone(),
     );
 
+    if !conf.skip_bindings_check {
+        gw_listener.check_bindings().await?;
+    }
+
     // Wrap the GatewayListener in an Arc
     let gw_listener = std::sync::Arc::new(gw_listener);
 
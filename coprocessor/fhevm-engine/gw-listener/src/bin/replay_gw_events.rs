@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use alloy::primitives::Address;
+use alloy::providers::ProviderBuilder;
+use alloy::transports::http::reqwest::Url;
+use clap::Parser;
+use fhevm_engine_common::db_schema;
+use gw_listener::aws_s3::AwsS3Client;
+use gw_listener::chain_id_from_env;
+use gw_listener::gw_listener::GatewayListener;
+use gw_listener::raw_events::{event_name, read_raw_events};
+use gw_listener::ConfigSettings;
+use sqlx::postgres::PgPoolOptions;
+use tokio_util::sync::CancellationToken;
+use tracing::Level;
+
+/// Acts again on the Gateway events stored in gw_raw_events by the gw-listener with --raw-events,
+/// e.g. after a decoding bug. Only lists the events unless --apply is given.
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
+struct Conf {
+    #[arg(long)]
+    database_url: Option<String>,
+
+    /// Database schema of the host chain, in the schema-per-chain layout
+    #[arg(long, value_parser = db_schema::parse_schema_name)]
+    database_schema: Option<String>,
+
+    #[arg(long, default_value = "verify_proof_requests")]
+    verify_proof_req_database_channel: String,
+
+    #[arg(long, default_value = "event_key_activated")]
+    key_activated_database_channel: String,
+
+    #[arg(short, long)]
+    input_verification_address: Address,
+
+    #[arg(long)]
+    kms_generation_address: Address,
+
+    #[arg(long)]
+    host_chain_id: Option<u64>,
+
+    #[arg(long, default_value_t = 0)]
+    from_block: i64,
+
+    #[arg(long, default_value_t = i64::MAX)]
+    to_block: i64,
+
+    /// Endpoint the activated keys are downloaded from, instead of the KMS storages announced in
+    /// the events
+    #[arg(long)]
+    key_endpoint_url: Option<Url>,
+
+    /// Directory where the downloaded keys are cached
+    #[arg(long)]
+    key_cache_dir: Option<PathBuf>,
+
+    /// Act on the events like the listener: insert the proof requests, download and activate the
+    /// keys and CRS
+    #[arg(long)]
+    apply: bool,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(Level),
+        default_value_t = Level::INFO)]
+    log_level: Level,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+    let conf = Conf::parse();
+
+    tracing_subscriber::fmt()
+        .json()
+        .with_level(true)
+        .with_max_level(conf.log_level)
+        .init();
+
+    let database_url = conf
+        .database_url
+        .clone()
+        .unwrap_or_else(|| std::env::var("DATABASE_URL").expect("DATABASE_URL is undefined"));
+    let Some(host_chain_id) = conf.host_chain_id.or_else(chain_id_from_env) else {
+        anyhow::bail!("--host-chain-id or CHAIN_ID env var is missing.")
+    };
+    let db_pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(db_schema::connect_options(
+            &database_url,
+            conf.database_schema.as_deref(),
+        )?)
+        .await?;
+
+    let events = read_raw_events(&db_pool, conf.from_block, conf.to_block).await?;
+    for event in &events {
+        let log = event.to_log()?;
+        println!(
+            "block {} log {} contract {} event {}",
+            event.block_number,
+            event.log_index,
+            event.contract_address,
+            event_name(&log).unwrap_or("unknown"),
+        );
+    }
+    if !conf.apply {
+        println!("{} raw events", events.len());
+        return Ok(());
+    }
+
+    let config = ConfigSettings {
+        host_chain_id,
+        database_url,
+        database_pool_size: 1,
+        database_schema: conf.database_schema,
+        verify_proof_req_db_channel: conf.verify_proof_req_database_channel,
+        key_activated_db_channel: conf.key_activated_database_channel,
+        key_endpoint_url: conf.key_endpoint_url,
+        key_cache_dir: conf.key_cache_dir,
+        ..Default::default()
+    };
+    // The Gateway is not queried, the events are read from the database
+    let provider = ProviderBuilder::new().connect_http(config.gw_url.clone());
+    let gw_listener = GatewayListener::new(
+        conf.input_verification_address,
+        conf.kms_generation_address,
+        config,
+        CancellationToken::new(),
+        provider,
+        AwsS3Client {},
+    );
+    let replayed = gw_listener.replay(&db_pool, &events).await?;
+    println!("{} raw events, {replayed} replayed", events.len());
+    Ok(())
+}
@@ -112,3 +112,108 @@ pub async fn update_tenant_crs(
     }
     Ok(())
 }
+
+/// Records a KeygenRequest. Does nothing if the key is already known, e.g. already activated.
+pub async fn insert_key_request(
+    db_pool: &Pool<Postgres>,
+    key_id: &[u8],
+    prep_keygen_id: &[u8],
+    block_number: Option<u64>,
+    transaction_hash: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    let block_number = block_number.map(i64::try_from).transpose()?;
+    sqlx::query!(
+        "INSERT INTO kms_keys (key_id, prep_keygen_id, status, requested_block_number, requested_transaction_hash)
+        VALUES ($1, $2, 'requested', $3, $4)
+        ON CONFLICT (key_id) DO NOTHING",
+        key_id,
+        prep_keygen_id,
+        block_number,
+        transaction_hash,
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(())
+}
+
+/// Marks a key as activated and notifies `channel`. The notification is only delivered once the
+/// transaction is committed, i.e. after the tenant keys have been updated.
+pub async fn activate_key(
+    tx: &mut Transaction<'_, Postgres>,
+    key_id: &[u8],
+    block_number: Option<u64>,
+    transaction_hash: Option<&[u8]>,
+    channel: &str,
+) -> anyhow::Result<()> {
+    let block_number = block_number.map(i64::try_from).transpose()?;
+    info!(key_id, channel, "Activating key");
+    sqlx::query!(
+        "WITH upsert AS (
+            INSERT INTO kms_keys (key_id, status, activated_block_number, activated_transaction_hash, activated_at)
+            VALUES ($1, 'activated', $2, $3, NOW())
+            ON CONFLICT (key_id) DO UPDATE SET
+                status = 'activated',
+                activated_block_number = EXCLUDED.activated_block_number,
+                activated_transaction_hash = EXCLUDED.activated_transaction_hash,
+                activated_at = EXCLUDED.activated_at
+        )
+        SELECT pg_notify($4, encode($1, 'hex'))",
+        key_id,
+        block_number,
+        transaction_hash,
+        channel,
+    )
+    .execute(tx.deref_mut())
+    .await?;
+    Ok(())
+}
+
+/// State of the tenant of a chain compared between databases, see `shadow`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TenantState {
+    pub key_id: Option<Vec<u8>>,
+    /// MD5 of the CRS, to compare it without reading it
+    pub crs_md5: Option<String>,
+}
+
+pub async fn tenant_state(
+    db_pool: &Pool<Postgres>,
+    chain_id: ChainId,
+) -> anyhow::Result<Option<TenantState>> {
+    let row = sqlx::query!(
+        "SELECT key_id, md5(public_params) AS crs_md5 FROM tenants WHERE chain_id = $1",
+        chain_id as i64
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    Ok(row.map(|row| TenantState {
+        key_id: row.key_id,
+        crs_md5: row.crs_md5,
+    }))
+}
+
+/// Range of the ZK proof IDs in `verify_proofs`.
+pub async fn proof_request_ids(db_pool: &Pool<Postgres>) -> anyhow::Result<Option<(i64, i64)>> {
+    let row = sqlx::query!(
+        "SELECT MIN(zk_proof_id) AS min_id, MAX(zk_proof_id) AS max_id FROM verify_proofs"
+    )
+    .fetch_one(db_pool)
+    .await?;
+    Ok(row.min_id.zip(row.max_id))
+}
+
+/// Number of proof requests in `verify_proofs` with an ID in the given inclusive range.
+pub async fn count_proof_requests(
+    db_pool: &Pool<Postgres>,
+    from_id: i64,
+    to_id: i64,
+) -> anyhow::Result<i64> {
+    let count = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM verify_proofs WHERE zk_proof_id BETWEEN $1 AND $2",
+        from_id,
+        to_id,
+    )
+    .fetch_one(db_pool)
+    .await?;
+    Ok(count.unwrap_or_default())
+}
@@ -1,18 +1,33 @@
+use std::sync::Arc;
 use std::time::Duration;
 
+use alloy::primitives::B256;
 use alloy::rpc::types::Filter;
-use alloy::sol_types::SolEventInterface;
 use alloy::{network::Ethereum, primitives::Address, providers::Provider, rpc::types::Log, sol};
+use async_trait::async_trait;
+use fhevm_engine_common::db_schema;
+use fhevm_engine_common::notification_bus::{self, NotificationBus};
+use fhevm_engine_common::raw_events::RawEvent;
 use fhevm_engine_common::telemetry;
 use fhevm_engine_common::utils::compact_hex;
+use fhevm_engine_common::write_batcher::{BatchWriter, WriteBatcher};
+use fhevm_gateway_bindings::drift::{check_selectors, ExpectedSelector};
+use fhevm_gateway_bindings::events::decode_event;
 use futures_util::{future::join_all, StreamExt};
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use sqlx::{postgres::PgPoolOptions, PgConnection, Pool, Postgres};
+use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-use crate::aws_s3::{download_key_from_s3, AwsS3Interface};
-use crate::database::{tenant_id, update_tenant_crs, update_tenant_key};
+use crate::aws_s3::AwsS3Interface;
+use crate::database::{
+    activate_key, count_proof_requests, insert_key_request, proof_request_ids, tenant_id,
+    tenant_state, update_tenant_crs, update_tenant_key,
+};
 use crate::digest::{digest_crs, digest_key};
+use crate::key_provider::{DigestMismatchError, KeyProvider};
+use crate::raw_events::{event_name, insert_raw_event};
+use crate::shadow::{DatabaseState, ShadowDatabase};
 use crate::sks_key::extract_server_key_without_ns;
 use crate::{ChainId, ConfigSettings, HealthStatus, KeyId, KeyType};
 
@@ -28,18 +43,105 @@ sol!(
     "./../../../gateway-contracts/artifacts/contracts/KMSGeneration.sol/KMSGeneration.json"
 );
 
-#[derive(Debug)]
-struct DigestMismatchError {
-    id: String,
+/// A `VerifyProofRequest` event to insert into `verify_proofs`.
+#[derive(Clone)]
+pub(crate) struct ProofRequest {
+    pub zk_proof_id: i64,
+    chain_id: i64,
+    contract_address: String,
+    user_address: String,
+    input: Vec<u8>,
+    extra_data: Vec<u8>,
+    transaction_id: Vec<u8>,
+    /// Log of the request, to store in `gw_raw_events`
+    raw_event: Option<RawEvent>,
+}
+
+// TODO: check if we can avoid the cast from u256 to i64
+fn proof_request(
+    request: InputVerification::VerifyProofRequest,
+    log: &Log,
+    raw_event: Option<RawEvent>,
+) -> ProofRequest {
+    ProofRequest {
+        zk_proof_id: request.zkProofId.to::<i64>(),
+        chain_id: request.contractChainId.to::<i64>(),
+        contract_address: request.contractAddress.to_string(),
+        user_address: request.userAddress.to_string(),
+        input: request.ciphertextWithZKProof.to_vec(),
+        extra_data: request.extraData.to_vec(),
+        transaction_id: log.transaction_hash.map(|h| h.to_vec()).unwrap_or_default(),
+        raw_event,
+    }
 }
 
-impl std::fmt::Display for DigestMismatchError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Invalid Key digest for key ID {}", self.id)
+/// Blocks of the proof requests whose write failed, whose logs are fetched again once subscribed
+/// anew.
+struct FailedProofRequests {
+    sender: mpsc::UnboundedSender<u64>,
+    receiver: mpsc::UnboundedReceiver<u64>,
+    /// First block to fetch the proof requests of again
+    resume_from_block: Option<u64>,
+}
+
+impl FailedProofRequests {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self {
+            sender,
+            receiver,
+            resume_from_block: None,
+        }
+    }
+
+    fn resume_from(&mut self, block_number: u64) {
+        self.resume_from_block = Some(
+            self.resume_from_block
+                .map_or(block_number, |resume| resume.min(block_number)),
+        );
     }
 }
 
-impl std::error::Error for DigestMismatchError {}
+pub(crate) struct ProofRequestWriter {
+    pub notify_channel: String,
+}
+
+#[async_trait]
+impl BatchWriter for ProofRequestWriter {
+    type Item = ProofRequest;
+
+    async fn write(
+        &self,
+        conn: &mut PgConnection,
+        items: &[ProofRequest],
+    ) -> Result<(), sqlx::Error> {
+        for request in items {
+            // Notifications are delivered on commit, identical ones of a batch only once
+            sqlx::query!(
+                "WITH ins AS (
+                    INSERT INTO verify_proofs (zk_proof_id, chain_id, contract_address, user_address, input, extra_data, transaction_id)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)
+                    ON CONFLICT(zk_proof_id) DO NOTHING
+                )
+                SELECT pg_notify($8, '')",
+                request.zk_proof_id,
+                request.chain_id,
+                request.contract_address,
+                request.user_address,
+                Some(request.input.as_slice()),
+                request.extra_data.as_slice(),
+                request.transaction_id,
+                self.notify_channel
+            )
+            .execute(&mut *conn)
+            .await?;
+            if let Some(raw_event) = &request.raw_event {
+                insert_raw_event(&mut *conn, raw_event).await?;
+            }
+        }
+        Ok(())
+    }
+}
 
 #[derive(Clone)]
 pub struct GatewayListener<
@@ -51,7 +153,11 @@ pub struct GatewayListener<
     conf: ConfigSettings,
     cancel_token: CancellationToken,
     provider: P,
-    aws_s3_client: A,
+    key_provider: KeyProvider<A>,
+    /// External bus the writes are also published on, see `notification_bus`
+    message_bus: Option<Arc<dyn NotificationBus>>,
+    /// Database the writes are also made to, see `shadow`
+    shadow: Option<ShadowDatabase>,
 }
 
 impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'static>
@@ -65,13 +171,20 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
         provider: P,
         aws_client: A,
     ) -> Self {
+        let key_provider = KeyProvider::new(
+            aws_client,
+            conf.key_endpoint_url.clone(),
+            conf.key_cache_dir.clone(),
+        );
         GatewayListener {
             input_verification_address,
             kms_generation_address,
             conf,
             cancel_token,
             provider,
-            aws_s3_client: aws_client,
+            key_provider,
+            message_bus: None,
+            shadow: None,
         }
     }
 
@@ -84,16 +197,52 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
         );
         let db_pool = PgPoolOptions::new()
             .max_connections(self.conf.database_pool_size)
-            .connect(&self.conf.database_url)
+            .connect_with(db_schema::connect_options(
+                &self.conf.database_url,
+                self.conf.database_schema.as_deref(),
+            )?)
             .await?;
+        let shadow = match &self.conf.shadow_database_url {
+            Some(url) => {
+                let shadow = ShadowDatabase::connect(&self.conf, url).await?;
+                info!(
+                    shadow_database_schema = ?self.conf.shadow_database_schema,
+                    "Connected to the shadow database"
+                );
+                Some(shadow)
+            }
+            None => None,
+        };
+        let this = Self {
+            message_bus: notification_bus::connect_external(&self.conf.message_bus).await?,
+            shadow,
+            ..self.clone()
+        };
+
+        let proof_requests = WriteBatcher::spawn(
+            db_pool.clone(),
+            ProofRequestWriter {
+                notify_channel: self.conf.verify_proof_req_db_channel.clone(),
+            },
+            self.conf.verify_proof_req_write_batch,
+        );
 
         let input_verification_handle = {
-            let s = self.clone();
+            let s = this.clone();
             let d = db_pool.clone();
             tokio::spawn(async move {
                 let mut sleep_duration = s.conf.error_sleep_initial_secs as u64;
+                let mut failed = FailedProofRequests::new();
                 loop {
-                    match s.run_input_verification(&d, &mut sleep_duration).await {
+                    match s
+                        .run_input_verification(
+                            &d,
+                            &proof_requests,
+                            &mut failed,
+                            &mut sleep_duration,
+                        )
+                        .await
+                    {
                         Ok(_) => {
                             info!("run_input_verification() stopped");
                             break;
@@ -108,7 +257,7 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
         };
 
         let get_logs_handle = {
-            let s = self.clone();
+            let s = this.clone();
             let d = db_pool.clone();
             tokio::spawn(async move {
                 let mut sleep_duration = s.conf.error_sleep_initial_secs as u64;
@@ -133,9 +282,39 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
         Ok(())
     }
 
+    /// Fails if the deployed contracts do not emit the events the listener relies on, i.e. the
+    /// bindings and the deployed contracts have diverged.
+    pub async fn check_bindings(&self) -> anyhow::Result<()> {
+        let contracts = [
+            (
+                "InputVerification",
+                self.input_verification_address,
+                vec![ExpectedSelector::event::<
+                    InputVerification::VerifyProofRequest,
+                >()],
+            ),
+            (
+                "KMSGeneration",
+                self.kms_generation_address,
+                vec![
+                    ExpectedSelector::event::<KMSGeneration::ActivateKey>(),
+                    ExpectedSelector::event::<KMSGeneration::ActivateCrs>(),
+                    ExpectedSelector::event::<KMSGeneration::KeygenRequest>(),
+                ],
+            ),
+        ];
+        for (contract, address, expected) in contracts {
+            let code_hash = check_selectors(&self.provider, address, &expected).await?;
+            info!(contract, %address, %code_hash, "Bindings match the deployed contract");
+        }
+        Ok(())
+    }
+
     async fn run_input_verification(
         &self,
         db_pool: &Pool<Postgres>,
+        proof_requests: &WriteBatcher<ProofRequest>,
+        failed: &mut FailedProofRequests,
         sleep_duration: &mut u64,
     ) -> anyhow::Result<()> {
         let input_verification =
@@ -147,6 +326,9 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
         // That might lead to skipped events, but that is acceptable for input verification requests as the client will eventually retry.
         // Furthermore, replaying old input verification requests is unnecessary as input verification is a synchronous request/response interaction on the client side.
         // Finally, no data on the GW will be left in an inconsistent state.
+        //
+        // The requests whose write failed are the exception: they are fetched again from their
+        // block once subscribed anew, as the client is not told about the failure.
         let mut verify_proof_request = input_verification
             .VerifyProofRequest_filter()
             .subscribe()
@@ -155,6 +337,38 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
             .fuse();
         info!("Subscribed to InputVerification.VerifyProofRequest events");
 
+        if let Some(from_block) = failed.resume_from_block {
+            let to_block = self.provider.get_block_number().await?;
+            let filter = Filter::new()
+                .address(self.input_verification_address)
+                .from_block(from_block)
+                .to_block(to_block);
+            let logs = self.provider.get_logs(&filter).await?;
+            info!(
+                from_block,
+                to_block, "Processing again the proof requests whose write failed"
+            );
+            for log in logs {
+                let Ok(event) = decode_event::<InputVerification::InputVerificationEvents>(&log)
+                else {
+                    continue;
+                };
+                if let InputVerification::InputVerificationEvents::VerifyProofRequest(request) =
+                    event.log.data
+                {
+                    self.verify_proof_request(
+                        db_pool,
+                        proof_requests,
+                        &failed.sender,
+                        request,
+                        log,
+                    )
+                    .await;
+                }
+            }
+            failed.resume_from_block = None;
+        }
+
         loop {
             tokio::select! {
                 biased;
@@ -169,7 +383,17 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
                         return Err(anyhow::anyhow!("Block stream closed"));
                     };
                     let (request, log) = item?;
-                    self.verify_proof_request(db_pool, request, log).await?;
+                    self.verify_proof_request(db_pool, proof_requests, &failed.sender, request, log).await;
+                }
+
+                Some(block_number) = failed.receiver.recv() => {
+                    failed.resume_from(block_number);
+                    while let Ok(block_number) = failed.receiver.try_recv() {
+                        failed.resume_from(block_number);
+                    }
+                    return Err(anyhow::anyhow!(
+                        "Failed to write the proof requests of block {block_number}"
+                    ));
                 }
             }
             // Reset sleep duration on successful iteration.
@@ -185,6 +409,16 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
     ) -> anyhow::Result<()> {
         let mut ticker = tokio::time::interval(self.conf.get_logs_poll_interval);
         let mut last_processed_block_num = self.get_last_processed_block_num(db_pool).await?;
+        // Acts on the events in the shadow database without publishing them again
+        let shadow = self.shadow.as_ref().map(|shadow| {
+            let listener = Self {
+                message_bus: None,
+                shadow: None,
+                ..self.clone()
+            };
+            (shadow, listener)
+        });
+        let mut last_shadow_report = tokio::time::Instant::now();
 
         loop {
             tokio::select! {
@@ -218,35 +452,22 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
 
                     let logs = self.provider.get_logs(&filter).await?;
                     for log in logs {
-                        if let Ok(event) = KMSGeneration::KMSGenerationEvents::decode_log(&log.inner) {
-                            match event.data {
-                                KMSGeneration::KMSGenerationEvents::ActivateCrs(a) => {
-                                    // IMPORTANT: If we ignore the event due to digest mismatch, this might lead to inconsistency between coprocessors.
-                                    // We choose to ignore the event and then manually fix if it happens.
-                                    match self.activate_crs(db_pool, a, &self.aws_s3_client, self.conf.host_chain_id).await {
-                                        Ok(_) => info!("ActivateCrs event successful"),
-                                        Err(e) if e.is::<DigestMismatchError>() => {
-                                            error!(error = %e, "CRS digest mismatch, ignoring event");
-                                        }
-                                        Err(e) => return Err(e),
-                                    }
-                                },
-                                // IMPORTANT: See comment above.
-                                KMSGeneration::KMSGenerationEvents::ActivateKey(a) => {
-                                    match self.activate_key(db_pool, a, &self.aws_s3_client, self.conf.host_chain_id).await {
-                                        Ok(_) => info!("ActivateKey event successful"),
-                                        Err(e) if e.is::<DigestMismatchError>() => {
-                                            error!(error = %e, "Key digest mismatch, ignoring event");
-                                        }
-                                        Err(e) => return Err(e),
-                                    };
-                                },
-                                _ => {}
-                            }
+                        self.kms_generation_log(db_pool, &log, self.conf.store_raw_events).await?;
+                        if let Some((shadow, listener)) = &shadow {
+                            let write = listener.kms_generation_log(&shadow.pool, &log, self.conf.store_raw_events);
+                            shadow.write("KMSGeneration event", log.block_number, write).await;
                         }
                     }
                     last_processed_block_num = Some(to_block);
                     self.update_last_block_num(db_pool, last_processed_block_num).await?;
+                    if let Some((shadow, _)) = &shadow {
+                        let write = self.update_last_block_num(&shadow.pool, last_processed_block_num);
+                        shadow.write("last block", Some(to_block), write).await;
+                        if last_shadow_report.elapsed() >= self.conf.shadow_report_interval {
+                            last_shadow_report = tokio::time::Instant::now();
+                            self.shadow_report(db_pool, shadow).await;
+                        }
+                    }
                     if to_block < current_block {
                         debug!(to_block = to_block,
                             current_block = current_block,
@@ -262,53 +483,192 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
         Ok(())
     }
 
+    /// Acts on a KMSGeneration log, the other events being ignored. The log is first stored in
+    /// `gw_raw_events` if `store_raw_event` is set.
+    async fn kms_generation_log(
+        &self,
+        db_pool: &Pool<Postgres>,
+        log: &Log,
+        store_raw_event: bool,
+    ) -> anyhow::Result<()> {
+        let Ok(event) = decode_event::<KMSGeneration::KMSGenerationEvents>(log) else {
+            return Ok(());
+        };
+        if store_raw_event && event_name(log).is_some() {
+            if let Some(raw_event) = RawEvent::from_log(log) {
+                insert_raw_event(&mut *db_pool.acquire().await?, &raw_event).await?;
+            }
+        }
+        let block_number = event.block_number;
+        let transaction_hash = event.transaction_hash;
+        match event.log.data {
+            KMSGeneration::KMSGenerationEvents::ActivateCrs(a) => {
+                // IMPORTANT: If we ignore the event due to digest mismatch, this might lead to inconsistency between coprocessors.
+                // We choose to ignore the event and then manually fix if it happens.
+                match self.activate_crs(db_pool, a, self.conf.host_chain_id).await {
+                    Ok(_) => info!(
+                        block_number,
+                        ?transaction_hash,
+                        "ActivateCrs event successful"
+                    ),
+                    Err(e) if e.is::<DigestMismatchError>() => {
+                        error!(error = %e, "CRS digest mismatch, ignoring event");
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            // IMPORTANT: See comment above.
+            KMSGeneration::KMSGenerationEvents::ActivateKey(a) => {
+                match self
+                    .activate_key(
+                        db_pool,
+                        a,
+                        self.conf.host_chain_id,
+                        block_number,
+                        transaction_hash,
+                    )
+                    .await
+                {
+                    Ok(_) => info!(
+                        block_number,
+                        ?transaction_hash,
+                        "ActivateKey event successful"
+                    ),
+                    Err(e) if e.is::<DigestMismatchError>() => {
+                        error!(error = %e, "Key digest mismatch, ignoring event");
+                    }
+                    Err(e) => return Err(e),
+                };
+            }
+            KMSGeneration::KMSGenerationEvents::KeygenRequest(r) => {
+                self.keygen_request(db_pool, r, block_number, transaction_hash)
+                    .await?;
+                info!(
+                    block_number,
+                    ?transaction_hash,
+                    "KeygenRequest event successful"
+                );
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Acts again on the stored events, in the given order, as if they had just been received:
+    /// the proof requests not in `verify_proofs` are inserted, the keys and CRS downloaded and
+    /// activated again. Events of other contracts than the listened ones are skipped. Returns the
+    /// number of events replayed.
+    pub async fn replay(
+        &self,
+        db_pool: &Pool<Postgres>,
+        events: &[RawEvent],
+    ) -> anyhow::Result<usize> {
+        let proof_requests = ProofRequestWriter {
+            notify_channel: self.conf.verify_proof_req_db_channel.clone(),
+        };
+        let mut replayed = 0;
+        for raw_event in events {
+            let log = raw_event.to_log()?;
+            let address = log.address();
+            if address == self.input_verification_address {
+                let Ok(event) = decode_event::<InputVerification::InputVerificationEvents>(&log)
+                else {
+                    continue;
+                };
+                let InputVerification::InputVerificationEvents::VerifyProofRequest(request) =
+                    event.log.data
+                else {
+                    continue;
+                };
+                info!(zk_proof_id = %request.zkProofId, block_number = raw_event.block_number, "Replaying ZK proof request event");
+                let mut tx = db_pool.begin().await?;
+                proof_requests
+                    .write(&mut tx, &[proof_request(request, &log, None)])
+                    .await?;
+                tx.commit().await?;
+            } else if address == self.kms_generation_address {
+                if event_name(&log).is_none() {
+                    continue;
+                }
+                info!(
+                    block_number = raw_event.block_number,
+                    log_index = raw_event.log_index,
+                    "Replaying KMSGeneration event"
+                );
+                self.kms_generation_log(db_pool, &log, false).await?;
+            } else {
+                continue;
+            }
+            replayed += 1;
+        }
+        Ok(replayed)
+    }
+
+    /// Queues the insertion of the request. The block of the request is sent to `failed_writes`
+    /// if its write fails, to be processed again (see `run_input_verification()`).
     async fn verify_proof_request(
         &self,
         db_pool: &Pool<Postgres>,
+        proof_requests: &WriteBatcher<ProofRequest>,
+        failed_writes: &mpsc::UnboundedSender<u64>,
         request: InputVerification::VerifyProofRequest,
         log: Log,
-    ) -> anyhow::Result<()> {
+    ) {
         let transaction_id = log.transaction_hash.map(|h| h.to_vec()).unwrap_or_default();
         info!(zk_proof_id = %request.zkProofId, tid = %compact_hex(&transaction_id), "Received ZK proof request event");
 
-        let chain_id = request.contractChainId.to::<i64>();
-
         let _ = telemetry::try_begin_transaction(
             db_pool,
-            chain_id,
+            request.contractChainId.to::<i64>(),
             &transaction_id,
             log.block_number.unwrap_or_default(),
         )
         .await;
 
-        // TODO: check if we can avoid the cast from u256 to i64
-        sqlx::query!(
-            "WITH ins AS (
-                INSERT INTO verify_proofs (zk_proof_id, chain_id, contract_address, user_address, input, extra_data, transaction_id)
-                VALUES ($1, $2, $3, $4, $5, $6, $7)
-                ON CONFLICT(zk_proof_id) DO NOTHING
-            )
-            SELECT pg_notify($8, '')",
-            request.zkProofId.to::<i64>(),
-            request.contractChainId.to::<i64>(),
-            request.contractAddress.to_string(),
-            request.userAddress.to_string(),
-            Some(request.ciphertextWithZKProof.as_ref()),
-            request.extraData.as_ref(),
-            transaction_id,
-            self.conf.verify_proof_req_db_channel
-        )
-        .execute(db_pool)
-        .await?;
-        Ok(())
+        let raw_event = if self.conf.store_raw_events {
+            RawEvent::from_log(&log)
+        } else {
+            None
+        };
+        let request = proof_request(request, &log, raw_event);
+        if let Some(shadow) = &self.shadow {
+            shadow.write_proof_request(request.clone());
+        }
+        let zk_proof_id = request.zk_proof_id;
+        let written = proof_requests.write(request).await;
+        let bus = self.message_bus.clone();
+        let channel = self.conf.verify_proof_req_db_channel.clone();
+        let failed_writes = failed_writes.clone();
+        let block_number = log.block_number;
+        tokio::spawn(async move {
+            match written.committed().await {
+                Ok(()) => {
+                    if let Some(bus) = bus {
+                        if let Err(err) = bus.publish(&channel, "").await {
+                            warn!(channel, error = %err, "Failed to publish proof request");
+                        }
+                    }
+                }
+                Err(err) => match block_number {
+                    Some(block_number) => {
+                        warn!(zk_proof_id, block_number, error = %err, "Failed to write proof request, processing its block again");
+                        let _ = failed_writes.send(block_number);
+                    }
+                    None => {
+                        error!(zk_proof_id, error = %err, "Failed to write proof request of an unknown block");
+                    }
+                },
+            }
+        });
     }
 
     async fn activate_key(
         &self,
         db_pool: &Pool<Postgres>,
         request: KMSGeneration::ActivateKey,
-        s3_client: &A,
         host_chain_id: ChainId,
+        block_number: Option<u64>,
+        transaction_hash: Option<B256>,
     ) -> anyhow::Result<()> {
         let key_id: KeyId = request.keyId;
         let s3_bucket_urls = request.kmsNodeStorageUrls;
@@ -318,7 +678,8 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
             bucket_urls = ?s3_bucket_urls,
             "Received ActivateKey event"
         );
-        // Download keys from S3
+        // Download keys
+        let key_id_str = key_id.to_string();
         let mut downloads = vec![];
         let mut key_types = vec![];
         for (i_key, key_digest) in digests.iter().enumerate() {
@@ -327,25 +688,30 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
             key_types.push(key_type);
             let key_id_no_0x = key_id_to_key_bucket(key_id);
             let key_path = format!("{key_type_path}/{key_id_no_0x}");
-            let download = download_key_from_s3(s3_client, &s3_bucket_urls, key_path, i_key);
+            let download = self.key_provider.fetch(
+                &key_id_str,
+                &s3_bucket_urls,
+                key_path,
+                i_key,
+                digest_key,
+                key_digest.digest.0.as_ref(),
+            );
             downloads.push(download);
         }
         let mut downloads = join_all(downloads).await;
         let mut keys_bytes = vec![];
         for (i_key, bytes) in downloads.drain(..).enumerate() {
-            let Ok(bytes) = bytes else {
-                error!(key_id = ?key_id, key = i_key, "Failed to download key, stopping");
-                anyhow::bail!("Failed to download key id:{key_id}, key {}", i_key + 1);
-            };
-            let download_digest = digest_key(&bytes);
-            let expected_digest = digests[i_key].digest.0.as_ref();
-            if download_digest != expected_digest {
-                error!(key = i_key, download_digest = ?download_digest, expected_digest = ?expected_digest, "Key digest mismatch, stopping");
-                return Err(DigestMismatchError {
-                    id: key_id.to_string(),
+            let bytes = match bytes {
+                Ok(bytes) => bytes,
+                Err(e) if e.is::<DigestMismatchError>() => {
+                    error!(key_id = ?key_id, key = i_key, "Key digest mismatch, stopping");
+                    return Err(e);
                 }
-                .into());
-            }
+                Err(e) => {
+                    error!(key_id = ?key_id, key = i_key, error = %e, "Failed to download key, stopping");
+                    anyhow::bail!("Failed to download key id:{key_id}, key {}", i_key + 1);
+                }
+            };
             keys_bytes.push(bytes);
         }
         let Some(tenant_id) = tenant_id(db_pool, host_chain_id).await? else {
@@ -370,15 +736,50 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
             )
             .await?;
         }
+        activate_key(
+            &mut tx,
+            &key_id,
+            block_number,
+            transaction_hash.as_ref().map(|h| h.as_slice()),
+            &self.conf.key_activated_db_channel,
+        )
+        .await?;
         tx.commit().await?;
+        if let Some(bus) = &self.message_bus {
+            let channel = &self.conf.key_activated_db_channel;
+            if let Err(err) = bus.publish(channel, &alloy::hex::encode(key_id)).await {
+                warn!(channel, error = %err, "Failed to publish key activation");
+            }
+        }
         Ok(())
     }
 
+    async fn keygen_request(
+        &self,
+        db_pool: &Pool<Postgres>,
+        request: KMSGeneration::KeygenRequest,
+        block_number: Option<u64>,
+        transaction_hash: Option<B256>,
+    ) -> anyhow::Result<()> {
+        info!(
+            key_id = request.keyId.to_string(),
+            prep_keygen_id = request.prepKeygenId.to_string(),
+            "Received KeygenRequest event"
+        );
+        insert_key_request(
+            db_pool,
+            &key_id_to_database_bytes(request.keyId),
+            &key_id_to_database_bytes(request.prepKeygenId),
+            block_number,
+            transaction_hash.as_ref().map(|h| h.as_slice()),
+        )
+        .await
+    }
+
     async fn activate_crs(
         &self,
         db_pool: &Pool<Postgres>,
         request: KMSGeneration::ActivateCrs,
-        s3_client: &A,
         host_chain_id: ChainId,
     ) -> anyhow::Result<()> {
         let crs_id: KeyId = request.crsId;
@@ -389,22 +790,31 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
             bucket_urls = ?s3_bucket_urls,
             "Received ActivateCrs event"
         );
-        // Download keys from S3
+        // Download keys
         let crs_id_no_0x = key_id_to_key_bucket(crs_id);
         let key_path = format!("PUB/CRS/{crs_id_no_0x}");
-        let Ok(bytes) = download_key_from_s3(s3_client, &s3_bucket_urls, key_path, 0).await else {
-            error!(key_id = ?crs_id, "Failed to download crs, stopping");
-            anyhow::bail!("Failed to download crs key id:{crs_id}");
-        };
-        let download_digest = digest_crs(&bytes);
-        let expected_digest = digest.0.as_ref();
-        if download_digest != expected_digest {
-            error!(download_digest = ?download_digest, expected_digest = ?expected_digest, "Key digest mismatch, stopping");
-            return Err(DigestMismatchError {
-                id: crs_id.to_string(),
+        let bytes = match self
+            .key_provider
+            .fetch(
+                &crs_id.to_string(),
+                &s3_bucket_urls,
+                key_path,
+                0,
+                digest_crs,
+                digest.0.as_ref(),
+            )
+            .await
+        {
+            Ok(bytes) => bytes,
+            Err(e) if e.is::<DigestMismatchError>() => {
+                error!(key_id = ?crs_id, "Crs digest mismatch, stopping");
+                return Err(e);
             }
-            .into());
-        }
+            Err(e) => {
+                error!(key_id = ?crs_id, error = %e, "Failed to download crs, stopping");
+                anyhow::bail!("Failed to download crs key id:{crs_id}");
+            }
+        };
         let Some(tenant_id) = tenant_id(db_pool, host_chain_id).await? else {
             error!(host_chain_id, "No tenant found for chain id, stopping");
             anyhow::bail!("No tenant found for chain id {}", host_chain_id);
@@ -468,6 +878,46 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
         Ok(())
     }
 
+    /// Compares the database and the shadow database, failures being only logged as the next
+    /// report compares them again.
+    async fn shadow_report(&self, db_pool: &Pool<Postgres>, shadow: &ShadowDatabase) {
+        let states = async {
+            // Only the proof requests received since both databases are written are compared
+            let ids = proof_request_ids(db_pool).await?;
+            let shadow_ids = proof_request_ids(&shadow.pool).await?;
+            let range = shadow_ids
+                .zip(ids)
+                .map(|((from_id, shadow_to_id), (_, to_id))| {
+                    (from_id, std::cmp::min(to_id, shadow_to_id))
+                });
+            anyhow::Ok((
+                self.database_state(db_pool, range).await?,
+                self.database_state(&shadow.pool, range).await?,
+            ))
+        };
+        match states.await {
+            Ok((state, shadow_state)) => shadow.report(&state, &shadow_state),
+            Err(err) => warn!(error = %err, "Failed to compare the shadow database"),
+        }
+    }
+
+    /// State of a database, with the number of proof requests in the `proof_ids` range.
+    async fn database_state(
+        &self,
+        db_pool: &Pool<Postgres>,
+        proof_ids: Option<(i64, i64)>,
+    ) -> anyhow::Result<DatabaseState> {
+        let proof_requests = match proof_ids {
+            Some((from_id, to_id)) => Some(count_proof_requests(db_pool, from_id, to_id).await?),
+            None => None,
+        };
+        Ok(DatabaseState {
+            last_block_num: self.get_last_processed_block_num(db_pool).await?,
+            tenant: tenant_state(db_pool, self.conf.host_chain_id).await?,
+            proof_requests,
+        })
+    }
+
     /// Checks the health of the gateway listener's connections
     pub async fn health_check(&self) -> HealthStatus {
         let mut database_connected = false;
@@ -475,10 +925,18 @@ impl<P: Provider<Ethereum> + Clone + 'static, A: AwsS3Interface + Clone + 'stati
         let mut error_details = Vec::new();
 
         // Check database connection
-        let db_pool_result = PgPoolOptions::new()
-            .max_connections(self.conf.database_pool_size)
-            .connect(&self.conf.database_url)
-            .await;
+        let db_pool_result = match db_schema::connect_options(
+            &self.conf.database_url,
+            self.conf.database_schema.as_deref(),
+        ) {
+            Ok(options) => {
+                PgPoolOptions::new()
+                    .max_connections(self.conf.database_pool_size)
+                    .connect_with(options)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
 
         match db_pool_result {
             Ok(pool) => {
@@ -0,0 +1,168 @@
+//! Retrieval of the keys and CRS activated on the Gateway.
+//!
+//! By default the key material is downloaded from the KMS node storages announced in the
+//! activation events, usually S3 buckets. A key endpoint, e.g. a KMS or Gateway HTTP service, can
+//! be configured instead, the key paths being resolved against it, the storages of the event being
+//! used as a fallback if the endpoint fails. Every download is verified against the digest of the
+//! event and can be cached in a local directory, so that an activation replayed after a restart
+//! does not download the keys again.
+
+use std::path::{Path, PathBuf};
+
+use alloy::transports::http::reqwest::{self, Url};
+use tokio_util::bytes::Bytes;
+use tracing::{info, warn};
+
+use crate::aws_s3::{download_key_from_s3, AwsS3Interface};
+
+#[derive(Debug)]
+pub struct DigestMismatchError {
+    pub id: String,
+}
+
+impl std::fmt::Display for DigestMismatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid Key digest for key ID {}", self.id)
+    }
+}
+
+impl std::error::Error for DigestMismatchError {}
+
+#[derive(Clone)]
+pub struct KeyProvider<A: AwsS3Interface + Clone> {
+    s3_client: A,
+    endpoint: Option<Url>,
+    http_client: reqwest::Client,
+    cache_dir: Option<PathBuf>,
+}
+
+impl<A: AwsS3Interface + Clone> KeyProvider<A> {
+    pub fn new(s3_client: A, mut endpoint: Option<Url>, cache_dir: Option<PathBuf>) -> Self {
+        if let Some(endpoint) = &mut endpoint {
+            // Key paths are resolved under the endpoint path
+            if !endpoint.path().ends_with('/') {
+                endpoint.set_path(&format!("{}/", endpoint.path()));
+            }
+        }
+        Self {
+            s3_client,
+            endpoint,
+            http_client: reqwest::Client::new(),
+            cache_dir,
+        }
+    }
+
+    /// Returns the key material at `key_path` with the expected digest, from the cache if
+    /// possible. `storage_urls` are the storages announced in the event, tried from
+    /// `offset_bucket` if no key endpoint is configured or if it fails.
+    pub async fn fetch(
+        &self,
+        id: &str,
+        storage_urls: &[String],
+        key_path: String,
+        offset_bucket: usize,
+        digest: fn(&[u8]) -> [u8; 32],
+        expected_digest: &[u8],
+    ) -> anyhow::Result<Bytes> {
+        let cache_path = self.cache_path(&key_path);
+        if let Some(cache_path) = &cache_path {
+            if let Ok(bytes) = tokio::fs::read(cache_path).await {
+                if digest(&bytes) == expected_digest {
+                    info!(key_path, ?cache_path, "Key found in cache");
+                    return Ok(bytes.into());
+                }
+                warn!(
+                    key_path,
+                    ?cache_path,
+                    "Cached key digest mismatch, downloading again"
+                );
+            }
+        }
+
+        let check_digest = |bytes: Bytes| {
+            let download_digest = digest(&bytes);
+            if download_digest != expected_digest {
+                warn!(
+                    key_path,
+                    ?download_digest,
+                    ?expected_digest,
+                    "Key digest mismatch"
+                );
+                return Err(anyhow::Error::from(DigestMismatchError {
+                    id: id.to_owned(),
+                }));
+            }
+            Ok(bytes)
+        };
+        let from_endpoint = match &self.endpoint {
+            Some(endpoint) => match self.download_from_endpoint(endpoint, &key_path).await {
+                Ok(bytes) => match check_digest(bytes) {
+                    Ok(bytes) => Some(bytes),
+                    Err(err) if storage_urls.is_empty() => return Err(err),
+                    Err(_) => None,
+                },
+                Err(err) if storage_urls.is_empty() => return Err(err),
+                Err(err) => {
+                    warn!(key_path, error = %err, "Failed to download key from endpoint");
+                    None
+                }
+            },
+            None => None,
+        };
+        let bytes = match from_endpoint {
+            Some(bytes) => bytes,
+            None => {
+                if self.endpoint.is_some() {
+                    info!(key_path, "Falling back on the storages of the event");
+                }
+                let bytes = download_key_from_s3(
+                    &self.s3_client,
+                    storage_urls,
+                    key_path.clone(),
+                    offset_bucket,
+                )
+                .await?;
+                check_digest(bytes)?
+            }
+        };
+
+        if let Some(cache_path) = &cache_path {
+            // The cache is best effort, the key is downloaded again if missing
+            if let Err(err) = write_cache(cache_path, &bytes).await {
+                warn!(key_path, ?cache_path, error = %err, "Failed to cache key");
+            }
+        }
+        Ok(bytes)
+    }
+
+    async fn download_from_endpoint(
+        &self,
+        endpoint: &Url,
+        key_path: &str,
+    ) -> anyhow::Result<Bytes> {
+        let url = endpoint.join(key_path)?;
+        info!(%url, "Downloading key from endpoint");
+        let bytes = self
+            .http_client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(bytes)
+    }
+
+    fn cache_path(&self, key_path: &str) -> Option<PathBuf> {
+        // Key paths are like PUB/ServerKey/<key id>
+        let file_name = key_path.replace('/', "_");
+        self.cache_dir.as_ref().map(|dir| dir.join(file_name))
+    }
+}
+
+/// Writes under a temporary name first, so that a partial file is never read as a key.
+async fn write_cache(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, bytes).await?;
+    tokio::fs::rename(tmp_path, path).await
+}
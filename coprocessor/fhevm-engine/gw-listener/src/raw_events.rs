@@ -0,0 +1,73 @@
+//! Logs of the Gateway events the gw-listener acted on, stored as is in the append-only
+//! `gw_raw_events` with `--raw-events`, to audit them and to act on them again, e.g. after a
+//! decoding bug, with the replay_gw_events tool.
+
+use alloy::rpc::types::Log;
+use fhevm_engine_common::raw_events::RawEvent;
+use fhevm_gateway_bindings::events::decode_event;
+use sqlx::{PgConnection, PgPool};
+
+use crate::gw_listener::{InputVerification, KMSGeneration};
+
+/// Name of the event if the gw-listener acts on it.
+pub fn event_name(log: &Log) -> Option<&'static str> {
+    if let Ok(event) = decode_event::<InputVerification::InputVerificationEvents>(log) {
+        return match event.log.data {
+            InputVerification::InputVerificationEvents::VerifyProofRequest(_) => {
+                Some("VerifyProofRequest")
+            }
+            _ => None,
+        };
+    }
+    match decode_event::<KMSGeneration::KMSGenerationEvents>(log)
+        .ok()?
+        .log
+        .data
+    {
+        KMSGeneration::KMSGenerationEvents::ActivateCrs(_) => Some("ActivateCrs"),
+        KMSGeneration::KMSGenerationEvents::ActivateKey(_) => Some("ActivateKey"),
+        KMSGeneration::KMSGenerationEvents::KeygenRequest(_) => Some("KeygenRequest"),
+        _ => None,
+    }
+}
+
+/// Does nothing if the log is already stored, e.g. its block is processed again after a restart.
+pub async fn insert_raw_event(
+    conn: &mut PgConnection,
+    event: &RawEvent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO gw_raw_events (block_hash, log_index, block_number, transaction_hash, contract_address, topics, data)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (block_hash, log_index) DO NOTHING",
+        event.block_hash,
+        event.log_index,
+        event.block_number,
+        event.transaction_hash,
+        event.contract_address,
+        &event.topics,
+        event.data,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Reads the stored events in the given block range, in chain order.
+pub async fn read_raw_events(
+    pool: &PgPool,
+    from_block: i64,
+    to_block: i64,
+) -> Result<Vec<RawEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        RawEvent,
+        "SELECT block_hash, block_number, log_index, transaction_hash, contract_address, topics, data
+        FROM gw_raw_events
+        WHERE block_number BETWEEN $1 AND $2
+        ORDER BY block_number, log_index",
+        from_block,
+        to_block,
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -5,7 +5,6 @@ use std::{
 
 use alloy::{
     network::EthereumWallet,
-    node_bindings::{Anvil, AnvilInstance},
     primitives::U256,
     providers::{Provider, ProviderBuilder, WsConnect},
     signers::local::PrivateKeySigner,
@@ -17,10 +16,15 @@ use aws_sdk_s3::{operation::get_object::GetObjectError, Client};
 use gw_listener::{
     aws_s3::{AwsS3Client, AwsS3Interface},
     gw_listener::{key_id_to_key_bucket, to_bucket_key_prefix, GatewayListener},
+    raw_events::{event_name, read_raw_events},
     ConfigSettings,
 };
 use serial_test::serial;
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use sqlx::{
+    postgres::{PgListener, PgPoolOptions},
+    Pool, Postgres,
+};
+use test_harness::anvil::{expect_event, AnvilFixture};
 use test_harness::instance::ImportMode;
 use tokio::time::sleep;
 use tokio_util::bytes;
@@ -102,7 +106,7 @@ struct TestEnvironment {
     cancel_token: CancellationToken,
     _test_instance: Option<test_harness::instance::DBInstance>, // maintain db alive
     db_pool: Pool<Postgres>,
-    anvil: AnvilInstance,
+    anvil: AnvilFixture,
     test_logs: TestLogs,
 }
 
@@ -147,9 +151,14 @@ impl TestEnvironment {
             .execute(&db_pool)
             .await?;
 
-        let anvil = Anvil::new().block_time(1).chain_id(12345).try_spawn()?;
-        let signer: PrivateKeySigner = anvil.keys()[0].clone().into();
-        let wallet = signer.clone().into();
+        sqlx::query!("TRUNCATE kms_keys",).execute(&db_pool).await?;
+
+        sqlx::query!("TRUNCATE gw_raw_events",)
+            .execute(&db_pool)
+            .await?;
+
+        let anvil = AnvilFixture::start_with(12345, Some(1))?;
+        let wallet = anvil.wallet(0);
         Ok(Self {
             wallet,
             conf,
@@ -241,6 +250,173 @@ async fn verify_proof_request_inserted_into_db() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+#[serial(db)]
+async fn bindings_check_detects_drift() -> anyhow::Result<()> {
+    let env = TestEnvironment::new().await?;
+    let provider = ProviderBuilder::new()
+        .wallet(env.wallet)
+        .connect_ws(WsConnect::new(env.anvil.ws_endpoint_url()))
+        .await?;
+    let input_verification = InputVerification::deploy(&provider).await?;
+    let kms_generation = KMSGeneration::deploy(&provider).await?;
+
+    let gw_listener = GatewayListener::new(
+        *input_verification.address(),
+        *kms_generation.address(),
+        env.conf.clone(),
+        env.cancel_token.clone(),
+        provider.clone(),
+        AwsS3Client {},
+    );
+    gw_listener.check_bindings().await?;
+
+    // KMSGeneration events are not emitted by the InputVerification contract.
+    let gw_listener = GatewayListener::new(
+        *input_verification.address(),
+        *input_verification.address(),
+        env.conf.clone(),
+        env.cancel_token.clone(),
+        provider.clone(),
+        AwsS3Client {},
+    );
+    let err = gw_listener.check_bindings().await.unwrap_err();
+    assert!(err.to_string().contains("ActivateKey"));
+    Ok(())
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn keygen_request_inserted_into_db() -> anyhow::Result<()> {
+    let env = TestEnvironment::new().await?;
+    let provider = ProviderBuilder::new()
+        .wallet(env.wallet)
+        .connect_ws(WsConnect::new(env.anvil.ws_endpoint_url()))
+        .await?;
+    let aws_s3_client = AwsS3Client {};
+    let input_verification = InputVerification::deploy(&provider).await?;
+    let kms_generation = KMSGeneration::deploy(&provider).await?;
+    let gw_listener = GatewayListener::new(
+        *input_verification.address(),
+        *kms_generation.address(),
+        env.conf.clone(),
+        env.cancel_token.clone(),
+        provider.clone(),
+        aws_s3_client.clone(),
+    );
+
+    let run_handle = tokio::spawn(async move { gw_listener.run().await });
+
+    let key_id = U256::from(42);
+    let txn_req = kms_generation
+        .keygen_request(U256::from(7), key_id)
+        .into_transaction_request();
+    let pending_txn = provider.send_transaction(txn_req).await?;
+    let receipt = pending_txn.get_receipt().await?;
+    assert!(receipt.status());
+    let event = expect_event::<KMSGeneration::KeygenRequest>(&receipt)?;
+    assert_eq!(event.keyId, key_id);
+
+    for retry in 0..=RETRY_EVENT_TO_DB {
+        sleep(RETRY_DELAY).await;
+        if let Some(status) = kms_key_status(&env.db_pool, key_id).await? {
+            assert_eq!(status, "requested");
+            break;
+        }
+        assert!(
+            retry < RETRY_EVENT_TO_DB,
+            "Timed out waiting for event to be processed"
+        );
+    }
+
+    env.cancel_token.cancel();
+    run_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn keygen_request_stored_and_replayed() -> anyhow::Result<()> {
+    let mut env = TestEnvironment::new().await?;
+    env.conf.store_raw_events = true;
+    let provider = ProviderBuilder::new()
+        .wallet(env.wallet)
+        .connect_ws(WsConnect::new(env.anvil.ws_endpoint_url()))
+        .await?;
+    let input_verification = InputVerification::deploy(&provider).await?;
+    let kms_generation = KMSGeneration::deploy(&provider).await?;
+    let gw_listener = GatewayListener::new(
+        *input_verification.address(),
+        *kms_generation.address(),
+        env.conf.clone(),
+        env.cancel_token.clone(),
+        provider.clone(),
+        AwsS3Client {},
+    );
+
+    let run_handle = {
+        let gw_listener = gw_listener.clone();
+        tokio::spawn(async move { gw_listener.run().await })
+    };
+
+    let key_id = U256::from(43);
+    let txn_req = kms_generation
+        .keygen_request(U256::from(7), key_id)
+        .into_transaction_request();
+    let pending_txn = provider.send_transaction(txn_req).await?;
+    let receipt = pending_txn.get_receipt().await?;
+    assert!(receipt.status());
+
+    for retry in 0..=RETRY_EVENT_TO_DB {
+        sleep(RETRY_DELAY).await;
+        if kms_key_status(&env.db_pool, key_id).await?.is_some() {
+            break;
+        }
+        assert!(
+            retry < RETRY_EVENT_TO_DB,
+            "Timed out waiting for event to be processed"
+        );
+    }
+    env.cancel_token.cancel();
+    run_handle.await??;
+
+    let events = read_raw_events(&env.db_pool, 0, i64::MAX).await?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].block_number, receipt.block_number.unwrap() as i64);
+    assert_eq!(
+        events[0].transaction_hash,
+        Some(receipt.transaction_hash.to_vec())
+    );
+    assert_eq!(event_name(&events[0].to_log()?), Some("KeygenRequest"));
+
+    // The stored events are append-only
+    assert!(sqlx::query!("DELETE FROM gw_raw_events")
+        .execute(&env.db_pool)
+        .await
+        .is_err());
+
+    // Lost key request, e.g. after a decoding bug
+    sqlx::query!("TRUNCATE kms_keys")
+        .execute(&env.db_pool)
+        .await?;
+    assert_eq!(gw_listener.replay(&env.db_pool, &events).await?, 1);
+    assert_eq!(
+        kms_key_status(&env.db_pool, key_id).await?.as_deref(),
+        Some("requested")
+    );
+    Ok(())
+}
+
+async fn kms_key_status(db_pool: &Pool<Postgres>, key_id: U256) -> anyhow::Result<Option<String>> {
+    let row = sqlx::query!(
+        "SELECT status FROM kms_keys WHERE key_id = $1",
+        &key_id.to_be_bytes::<32>(),
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    Ok(row.map(|r| r.status))
+}
+
 async fn has_not_public_key(db_pool: &Pool<Postgres>) -> anyhow::Result<bool> {
     has_public_key_gen(db_pool, false).await.map(|b| !b)
 }
@@ -426,6 +602,11 @@ async fn keygen_ok() -> anyhow::Result<()> {
         aws_s3_client.clone(),
     );
 
+    let mut key_activated = PgListener::connect_with(&env.db_pool).await?;
+    key_activated
+        .listen(&env.conf.key_activated_db_channel)
+        .await?;
+
     let listener = tokio::spawn(async move { gw_listener.run().await });
 
     assert!(has_not_public_key(&env.db_pool.clone()).await?);
@@ -439,6 +620,12 @@ async fn keygen_ok() -> anyhow::Result<()> {
     let receipt = pending_txn.get_receipt().await?;
     assert!(receipt.status());
     assert!(has_public_key(&env.db_pool.clone()).await?);
+    assert_eq!(
+        kms_key_status(&env.db_pool, key_id).await?.as_deref(),
+        Some("activated")
+    );
+    let notification = tokio::time::timeout(RETRY_DELAY * 4, key_activated.recv()).await??;
+    assert_eq!(notification.payload(), key_id_to_key_bucket(key_id));
 
     let txn_req = kms_generation
         .keygen_server_key()
@@ -0,0 +1,187 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use alloy::transports::http::reqwest::Url;
+use async_trait::async_trait;
+use axum::{extract, http::StatusCode, routing::get, Router};
+use gw_listener::{
+    aws_s3::AwsS3Interface,
+    key_provider::{DigestMismatchError, KeyProvider},
+};
+use sha3::{Digest, Keccak256};
+use tokio::net::TcpListener;
+use tokio_util::bytes::Bytes;
+
+const KEY_PATH: &str = "PUB/ServerKey/0a";
+const KEY: &[u8] = b"server key";
+const STORAGE_URL: &str = "http://storage.local/bucket";
+
+fn digest(bytes: &[u8]) -> [u8; 32] {
+    Keccak256::digest(bytes).into()
+}
+
+/// Storage of the keys announced in the events, recording the requested keys.
+#[derive(Clone, Default)]
+struct MockStorage {
+    objects: HashMap<String, Bytes>,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockStorage {
+    fn with_key(bytes: &'static [u8]) -> Self {
+        Self {
+            objects: HashMap::from([(KEY_PATH.to_owned(), Bytes::from_static(bytes))]),
+            ..Default::default()
+        }
+    }
+
+    fn requests(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+#[async_trait]
+impl AwsS3Interface for MockStorage {
+    async fn get_bucket_key(&self, _url: &str, bucket: &str, key: &str) -> anyhow::Result<Bytes> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push(format!("{bucket}/{key}"));
+        self.objects
+            .get(key)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("no key {key} in bucket {bucket}"))
+    }
+}
+
+/// Serves the key endpoint, with `bytes` at the key path if any.
+async fn serve_endpoint(bytes: Option<&'static [u8]>) -> Url {
+    let router = Router::new().route(
+        "/keys/*path",
+        get(
+            move |extract::Path(path): extract::Path<String>| async move {
+                match bytes {
+                    Some(bytes) if path == KEY_PATH => Ok(bytes),
+                    _ => Err(StatusCode::NOT_FOUND),
+                }
+            },
+        ),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/keys", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    url.parse().unwrap()
+}
+
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("key-provider-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn fetch(
+    provider: &KeyProvider<MockStorage>,
+    storage_urls: &[String],
+    expected_digest: [u8; 32],
+) -> anyhow::Result<Bytes> {
+    provider
+        .fetch(
+            "0a",
+            storage_urls,
+            KEY_PATH.to_owned(),
+            0,
+            digest,
+            &expected_digest,
+        )
+        .await
+}
+
+#[tokio::test]
+async fn downloads_from_the_storages_and_checks_the_digest() -> anyhow::Result<()> {
+    let storage = MockStorage::with_key(KEY);
+    let provider = KeyProvider::new(storage.clone(), None, None);
+    let storage_urls = [STORAGE_URL.to_owned()];
+
+    assert_eq!(fetch(&provider, &storage_urls, digest(KEY)).await?, KEY);
+    assert_eq!(
+        storage.requests.lock().unwrap()[0],
+        format!("bucket/{KEY_PATH}")
+    );
+
+    let err = fetch(&provider, &storage_urls, digest(b"other key"))
+        .await
+        .unwrap_err();
+    assert!(err.is::<DigestMismatchError>());
+    Ok(())
+}
+
+#[tokio::test]
+async fn cached_key_is_reused_while_its_digest_matches() -> anyhow::Result<()> {
+    let storage = MockStorage::with_key(KEY);
+    let dir = cache_dir("reused");
+    let provider = KeyProvider::new(storage.clone(), None, Some(dir.clone()));
+    let storage_urls = [STORAGE_URL.to_owned()];
+
+    assert_eq!(fetch(&provider, &storage_urls, digest(KEY)).await?, KEY);
+    assert_eq!(storage.requests(), 1);
+    let cache_path = dir.join("PUB_ServerKey_0a");
+    assert_eq!(std::fs::read(&cache_path)?, KEY);
+
+    assert_eq!(fetch(&provider, &storage_urls, digest(KEY)).await?, KEY);
+    assert_eq!(storage.requests(), 1);
+
+    // A corrupted cached key is downloaded again
+    std::fs::write(&cache_path, b"corrupted")?;
+    assert_eq!(fetch(&provider, &storage_urls, digest(KEY)).await?, KEY);
+    assert_eq!(storage.requests(), 2);
+    assert_eq!(std::fs::read(&cache_path)?, KEY);
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn downloads_from_the_endpoint() -> anyhow::Result<()> {
+    let storage = MockStorage::default();
+    let endpoint = serve_endpoint(Some(KEY)).await;
+    let provider = KeyProvider::new(storage.clone(), Some(endpoint), None);
+
+    let storage_urls = [STORAGE_URL.to_owned()];
+    assert_eq!(fetch(&provider, &storage_urls, digest(KEY)).await?, KEY);
+    assert_eq!(storage.requests(), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn falls_back_on_the_storages_when_the_endpoint_fails() -> anyhow::Result<()> {
+    let storage = MockStorage::with_key(KEY);
+    let endpoint = serve_endpoint(None).await;
+    let provider = KeyProvider::new(storage.clone(), Some(endpoint), None);
+
+    let storage_urls = [STORAGE_URL.to_owned()];
+    assert_eq!(fetch(&provider, &storage_urls, digest(KEY)).await?, KEY);
+    assert_eq!(storage.requests(), 1);
+
+    // Without any storage the error of the endpoint is returned
+    assert!(fetch(&provider, &[], digest(KEY)).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn falls_back_on_the_storages_when_the_endpoint_digest_mismatches() -> anyhow::Result<()> {
+    let storage = MockStorage::with_key(KEY);
+    let endpoint = serve_endpoint(Some(b"other key")).await;
+    let provider = KeyProvider::new(storage.clone(), Some(endpoint), None);
+
+    let storage_urls = [STORAGE_URL.to_owned()];
+    assert_eq!(fetch(&provider, &storage_urls, digest(KEY)).await?, KEY);
+    assert_eq!(storage.requests(), 1);
+
+    let err = fetch(&provider, &[], digest(KEY)).await.unwrap_err();
+    assert!(err.is::<DigestMismatchError>());
+    Ok(())
+}
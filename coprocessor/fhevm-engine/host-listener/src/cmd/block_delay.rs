@@ -0,0 +1,119 @@
+/// Number of blocks before the delegations of a block are effective for the
+/// user decryptions, adapted to the reorgs observed on the chain.
+///
+/// The delay starts at the minimum. A reorg widens it to its depth plus one
+/// block, and a delegation event dismissed out of a catch-up, i.e. arriving
+/// live after a newer one, widens it by one block. Once `window` blocks passed
+/// without widening, it narrows by one block per `window` blocks, down to the
+/// minimum.
+pub struct AdaptiveBlockDelay {
+    min: u64,
+    max: u64,
+    window: u64,
+    current: u64,
+    /// Block of the last widening or narrowing
+    last_change: u64,
+}
+
+impl AdaptiveBlockDelay {
+    pub fn new(min: u64, max: u64, window: u64) -> Self {
+        Self {
+            min,
+            max: max.max(min),
+            window: window.max(1),
+            current: min,
+            last_change: 0,
+        }
+    }
+
+    pub fn current(&self) -> u64 {
+        self.current
+    }
+
+    /// Last block whose delegations are effective, at the given block.
+    pub fn confirmed_block(&self, block_number: u64) -> u64 {
+        block_number.saturating_sub(self.current)
+    }
+
+    pub fn observe_reorg(&mut self, block_number: u64, depth: u64) {
+        self.widen_to(block_number, depth + 1);
+    }
+
+    pub fn observe_dismissals(&mut self, block_number: u64, dismissed: usize) {
+        if dismissed > 0 {
+            self.widen_to(block_number, self.current + 1);
+        }
+    }
+
+    /// Narrows the delay if no reorg nor dismissal was observed recently.
+    pub fn observe_block(&mut self, block_number: u64) {
+        if self.current > self.min
+            && block_number >= self.last_change + self.window
+        {
+            self.current -= 1;
+            self.last_change = block_number;
+        }
+    }
+
+    fn widen_to(&mut self, block_number: u64, delay: u64) {
+        self.current = self.current.max(delay.clamp(self.min, self.max));
+        self.last_change = block_number;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_widen_on_reorg() {
+        let mut delay = AdaptiveBlockDelay::new(1, 10, 100);
+        assert_eq!(delay.current(), 1);
+        assert_eq!(delay.confirmed_block(1000), 999);
+        delay.observe_reorg(1000, 3);
+        assert_eq!(delay.current(), 4);
+        assert_eq!(delay.confirmed_block(1000), 996);
+        // a shallower reorg does not narrow it
+        delay.observe_reorg(1001, 1);
+        assert_eq!(delay.current(), 4);
+        // bounded
+        delay.observe_reorg(1002, 50);
+        assert_eq!(delay.current(), 10);
+    }
+
+    #[test]
+    fn test_widen_on_dismissals() {
+        let mut delay = AdaptiveBlockDelay::new(0, 2, 100);
+        delay.observe_dismissals(10, 0);
+        assert_eq!(delay.current(), 0);
+        delay.observe_dismissals(10, 3);
+        assert_eq!(delay.current(), 1);
+        delay.observe_dismissals(11, 1);
+        delay.observe_dismissals(12, 1);
+        assert_eq!(delay.current(), 2);
+    }
+
+    #[test]
+    fn test_narrow_without_reorg() {
+        let mut delay = AdaptiveBlockDelay::new(1, 10, 100);
+        delay.observe_reorg(1000, 4);
+        assert_eq!(delay.current(), 5);
+        delay.observe_block(1050);
+        assert_eq!(delay.current(), 5);
+        delay.observe_block(1099);
+        assert_eq!(delay.current(), 5);
+        delay.observe_block(1100);
+        assert_eq!(delay.current(), 4);
+        delay.observe_block(1150);
+        assert_eq!(delay.current(), 4);
+        // a dismissal restarts the window
+        delay.observe_dismissals(1160, 1);
+        assert_eq!(delay.current(), 5);
+        delay.observe_block(1200);
+        assert_eq!(delay.current(), 5);
+        for block_number in (1260..=2000).step_by(100) {
+            delay.observe_block(block_number);
+        }
+        assert_eq!(delay.current(), 1);
+    }
+}
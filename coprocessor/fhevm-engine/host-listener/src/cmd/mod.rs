@@ -1,22 +1,30 @@
 use alloy::eips::BlockId;
 use alloy::primitives::Address;
-use alloy::providers::{Provider, ProviderBuilder, WsConnect};
+use alloy::providers::{Provider, ProviderBuilder};
 use alloy::pubsub::SubscriptionStream;
 use alloy::rpc::types::{Block, BlockNumberOrTag, Filter, Header, Log};
-use alloy::sol_types::SolEventInterface;
 use anyhow::{anyhow, Result};
+use fhevm_engine_common::column_encryption::ColumnEncryption;
+use fhevm_engine_common::db_schema;
+use fhevm_engine_common::metrics_push::MetricsPusher;
+use fhevm_engine_common::notification_bus::{
+    self, BusConfig, BusKind, DEFAULT_SUBJECT_PREFIX,
+};
 use fhevm_engine_common::telemetry;
+use fhevm_engine_common::tls::{RpcTls, RpcTlsConfig, TlsVerify};
 use futures_util::stream::StreamExt;
+use humantime::parse_duration;
 use sqlx::types::Uuid;
 
 use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tracing::{error, info, warn, Level};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 use rustls;
 
@@ -25,15 +33,26 @@ use tokio_util::sync::CancellationToken;
 use fhevm_engine_common::healthz_server::HttpServer as HealthHttpServer;
 use fhevm_engine_common::types::{BlockchainProvider, Handle};
 use fhevm_engine_common::utils::HeartBeat;
+use fhevm_gateway_bindings::events::decode_event;
 
 use crate::contracts::{AclContract, TfheContract};
+use crate::database::retention::{
+    self, parse_retention_policy, RetentionPolicy,
+};
 use crate::database::tfhe_event_propagate::{
-    acl_result_handles, tfhe_result_handle, ChainId, Database, LogTfhe,
+    acl_event_name, acl_result_handles, event_name, tfhe_result_handle,
+    ChainId, Database, InsertBatch, LogTfhe, TenantId,
+    DEFAULT_INSERT_BATCH_SIZE,
 };
 use crate::health_check::HealthCheck;
+use crate::metrics;
 
+pub mod block_delay;
 pub mod block_history;
+pub mod shadow;
+use block_delay::AdaptiveBlockDelay;
 use block_history::{BlockHash, BlockHistory, BlockSummary};
+use shadow::ShadowDatabase;
 
 const REORG_RETRY_GET_LOGS: u64 = 10; // retry 10 times to get logs for a block
 const RETRY_GET_LOGS_DELAY_IN_MS: u64 = 100;
@@ -60,6 +79,13 @@ pub struct Args {
     )]
     pub database_url: String,
 
+    #[arg(
+        long,
+        value_parser = db_schema::parse_schema_name,
+        help = "Database schema of the host chain, in the schema-per-chain layout"
+    )]
+    pub database_schema: Option<String>,
+
     #[arg(long, default_value = None, help = "Can be negative from last block", allow_hyphen_values = true)]
     pub start_at_block: Option<i64>,
 
@@ -116,6 +142,191 @@ pub struct Args {
     /// service name in OTLP traces
     #[arg(long, default_value = "host-listener")]
     pub service_name: String,
+
+    #[arg(
+        long,
+        default_value_t = DEFAULT_INSERT_BATCH_SIZE,
+        help = "Maximum number of rows written per insert statement"
+    )]
+    pub insert_batch_size: usize,
+
+    #[arg(
+        long,
+        help = "Squash on demand: only the handles allowed for public decryption are enqueued to the sns-worker when allowed, the others once a user decryption requests them"
+    )]
+    pub lazy_sns: bool,
+
+    #[arg(
+        long,
+        default_value = "7d",
+        value_parser = parse_duration,
+        help = "How long superseded and dismissed delegation events are kept"
+    )]
+    pub delegation_history_retention: Duration,
+
+    #[arg(
+        long,
+        default_value = "1h",
+        value_parser = parse_duration,
+        help = "Interval between two purges of the delegation history and the tables with a retention"
+    )]
+    pub purge_interval: Duration,
+
+    #[arg(
+        long,
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Maximum number of rows deleted per purge statement"
+    )]
+    pub purge_batch_size: u32,
+
+    #[arg(
+        long = "retention",
+        value_parser = parse_retention_policy,
+        help = "Retention of a table, <table>=<age> or <table>=<depth>blocks, repeated per table among computations, pbs_computations, raw_events and host_chain_blocks_valid. Kept forever if unspecified"
+    )]
+    pub retention_policies: Vec<RetentionPolicy>,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Minimum number of blocks before a delegation is effective for the user decryptions"
+    )]
+    pub delegation_block_delay_min: u64,
+
+    #[arg(
+        long,
+        default_value_t = 20,
+        help = "Maximum number of blocks before a delegation is effective, the delay widening up to it with the observed reorgs"
+    )]
+    pub delegation_block_delay_max: u64,
+
+    #[arg(
+        long,
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Blocks without reorg nor dismissed delegation before the delegation block delay narrows by one block"
+    )]
+    pub delegation_block_delay_window: u64,
+
+    #[arg(
+        long,
+        help = "Pushgateway receiving the metrics of a run with --end-at-block"
+    )]
+    pub metrics_push_gateway_url: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "15s",
+        value_parser = parse_duration,
+        help = "Interval between two pushes of the metrics"
+    )]
+    pub metrics_push_interval: Duration,
+
+    #[arg(
+        long,
+        default_value = "5m",
+        value_parser = parse_duration,
+        help = "Interval between two checks that the node still reports the chain ID of the database"
+    )]
+    pub chain_id_check_interval: Duration,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "none",
+        help = "Logs of the watched contracts also stored as is in raw_events, to be decoded later with decode_raw_events"
+    )]
+    pub raw_events: RawEvents,
+
+    #[arg(
+        long,
+        help = "PEM bundle of the CAs trusted for the node instead of the platform ones"
+    )]
+    pub rpc_tls_ca_cert: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "PEM certificate chain presented to the node, for mutual TLS"
+    )]
+    pub rpc_tls_client_cert: Option<PathBuf>,
+
+    #[arg(long, help = "PEM private key of the client certificate")]
+    pub rpc_tls_client_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Name sent as SNI and verified instead of the host of the URL"
+    )]
+    pub rpc_tls_server_name: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "full",
+        help = "Verification of the certificate of the node: full, ca-only (no name check) or none"
+    )]
+    pub rpc_tls_verify: TlsVerify,
+
+    #[arg(
+        long,
+        help = "Column key encrypting the delegators and delegates, file:<path> or env:<variable> holding 32 hex-encoded bytes"
+    )]
+    pub column_encryption_key: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "postgres",
+        help = "Bus the workers are notified on: postgres (LISTEN/NOTIFY by the triggers) or nats"
+    )]
+    pub message_bus: BusKind,
+
+    #[arg(long, help = "Server of the NATS message bus")]
+    pub nats_url: Option<String>,
+
+    #[arg(
+        long,
+        default_value = DEFAULT_SUBJECT_PREFIX,
+        help = "Prefix of the NATS subjects, followed by the database channel"
+    )]
+    pub nats_subject_prefix: String,
+
+    #[arg(
+        long,
+        help = "Database being migrated to, also written with each block and checked against the database"
+    )]
+    pub shadow_database_url: Option<String>,
+
+    #[arg(
+        long,
+        value_parser = db_schema::parse_schema_name,
+        help = "Database schema of the host chain in the shadow database"
+    )]
+    pub shadow_database_schema: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Blocks between two logged reports of the divergences of the shadow database"
+    )]
+    pub shadow_report_interval: u64,
+
+    #[arg(
+        long,
+        default_value = "5s",
+        value_parser = parse_duration,
+        help = "Maximum time writing a block to the shadow database, the block being reported as failed past it"
+    )]
+    pub shadow_write_timeout: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum RawEvents {
+    #[default]
+    None,
+    /// The logs that can't be decoded, e.g. a new event type
+    Undecoded,
+    All,
 }
 
 // TODO: to merge with Levent works
@@ -138,6 +349,12 @@ struct InfiniteLogIter {
     pub tick_block: HeartBeat,
     reorg_maximum_duration_in_blocks: u64, // in blocks
     block_history: BlockHistory,           // to detect reorgs
+    pub delegation_block_delay: AdaptiveBlockDelay,
+    // providers reporting another chain id are refused, once known
+    chain_id: Option<ChainId>,
+    chain_id_check_interval: Duration,
+    last_chain_id_check: Instant,
+    rpc_tls: RpcTls,
 }
 
 struct BlockLogs<T> {
@@ -153,7 +370,7 @@ enum BlockOrTimeoutOrNone {
 }
 
 impl InfiniteLogIter {
-    fn new(args: &Args) -> Self {
+    fn new(args: &Args, rpc_tls: RpcTls) -> Self {
         let mut contract_addresses = vec![];
         if !args.acl_contract_address.is_empty() {
             contract_addresses
@@ -183,12 +400,50 @@ impl InfiniteLogIter {
             block_history: BlockHistory::new(
                 args.reorg_maximum_duration_in_blocks as usize,
             ),
+            delegation_block_delay: AdaptiveBlockDelay::new(
+                args.delegation_block_delay_min,
+                args.delegation_block_delay_max,
+                args.delegation_block_delay_window,
+            ),
+            chain_id: None,
+            chain_id_check_interval: args.chain_id_check_interval,
+            last_chain_id_check: Instant::now(),
+            rpc_tls,
         }
     }
 
+    /// Fails if the provider reports another chain id than the expected one
+    async fn check_chain_id(
+        &self,
+        provider: &BlockchainProvider,
+    ) -> anyhow::Result<()> {
+        let Some(expected) = self.chain_id else {
+            return Ok(());
+        };
+        let chain_id = provider.get_chain_id().await?;
+        if chain_id != expected {
+            anyhow::bail!(
+                "Wrong chain, node reports chain ID {chain_id}, expected {expected}"
+            );
+        }
+        Ok(())
+    }
+
+    async fn revalidate_chain_id(&mut self) -> anyhow::Result<()> {
+        if self.last_chain_id_check.elapsed() < self.chain_id_check_interval {
+            return Ok(());
+        }
+        let Some(provider) = self.provider.read().await.clone() else {
+            return Ok(());
+        };
+        self.check_chain_id(&provider).await?;
+        self.last_chain_id_check = Instant::now();
+        Ok(())
+    }
+
     async fn get_chain_id(&self) -> anyhow::Result<ChainId> {
-        let ws = WsConnect::new(&self.url);
-        let provider = ProviderBuilder::new().connect_ws(ws).await?;
+        let ws = self.rpc_tls.connect(&self.url);
+        let provider = ProviderBuilder::new().connect_pubsub_with(ws).await?;
         Ok(provider.get_chain_id().await?)
     }
 
@@ -579,6 +834,7 @@ impl InfiniteLogIter {
 
         let missing_blocks =
             self.get_missings_ancestors(current_block_summary).await;
+        self.observe_reorg(&current_block_summary, &missing_blocks);
         if missing_blocks.is_empty() {
             // we don't add to history from which we have no event
             // e.g. at timeout, because empty blocks are not get_logs
@@ -597,13 +853,53 @@ impl InfiniteLogIter {
         warn!("Missing ancestors catchup done.");
     }
 
+    /// The new blocks replace the known blocks from the first one, if not
+    /// after the tip, which is a reorg of that depth.
+    fn observe_reorg(
+        &mut self,
+        current_block_summary: &BlockSummary,
+        missing_blocks: &[BlockSummary],
+    ) {
+        if self.block_history.is_known(&current_block_summary.hash) {
+            // e.g. the last block again at timeout
+            return;
+        }
+        let Some(tip) = self.block_history.tip() else {
+            return;
+        };
+        let first = missing_blocks.first().unwrap_or(current_block_summary);
+        if first.number > tip.number {
+            return;
+        }
+        let depth = tip.number - first.number + 1;
+        warn!(
+            depth,
+            tip = ?tip,
+            new_block = ?current_block_summary,
+            "Reorg detected"
+        );
+        self.delegation_block_delay
+            .observe_reorg(current_block_summary.number, depth);
+    }
+
     async fn new_log_stream(&mut self, not_initialized: bool) {
         let mut retry = 20;
         loop {
-            let ws = WsConnect::new(&self.url).with_max_retries(0); // disabled, alloy skips events
+            // disabled, alloy skips events
+            let ws = self.rpc_tls.connect(&self.url).with_max_retries(0);
 
-            match ProviderBuilder::new().connect_ws(ws).await {
+            match ProviderBuilder::new().connect_pubsub_with(ws).await {
                 Ok(provider) => {
+                    if let Err(err) = self.check_chain_id(&provider).await {
+                        error!(
+                            error = %err,
+                            url = %self.url,
+                            "Refusing provider, will retry infinitely"
+                        );
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        continue;
+                    }
+                    self.last_chain_id_check = Instant::now();
                     let catch_up_from =
                         self.catchup_block_from(&provider).await;
                     self.catchup_blocks = Some((
@@ -723,6 +1019,12 @@ impl InfiniteLogIter {
                 not_initialized = false;
                 continue;
             };
+            if let Err(err) = self.revalidate_chain_id().await {
+                error!(error = %err, "Chain ID check failed, reconnecting");
+                self.stream = None; // to restart
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
             if self.next_blocklogs.is_empty() {
                 self.consume_catchup_blocks().await;
             };
@@ -779,12 +1081,117 @@ impl InfiniteLogIter {
     }
 }
 
+/// Periodically purges the delegation events given a final status longer than
+/// the retention ago. They are kept meanwhile, so that a reorg can still be
+/// investigated from what was superseded or dismissed. The tables with a
+/// retention policy are pruned on the same schedule.
+async fn purge_history(
+    pool: Arc<RwLock<sqlx::Pool<sqlx::Postgres>>>,
+    tenant_id: TenantId,
+    chain_id: ChainId,
+    args: Args,
+    cancel_token: CancellationToken,
+) {
+    info!(
+        retention = ?args.delegation_history_retention,
+        policies = ?args.retention_policies,
+        purge_interval = ?args.purge_interval,
+        "Starting delegation history purge"
+    );
+    loop {
+        tokio::select! {
+            _ = cancel_token.cancelled() => return,
+            _ = tokio::time::sleep(args.purge_interval) => {}
+        }
+        // the pool is replaced on reconnection
+        let current_pool = pool.read().await.clone();
+        let mut purged = 0;
+        loop {
+            // best effort, the events are purged on the next run on error
+            match Database::purge_delegation_history(
+                &current_pool,
+                tenant_id,
+                args.delegation_history_retention,
+                args.purge_batch_size,
+            )
+            .await
+            {
+                Ok(count) => {
+                    purged += count;
+                    if count < args.purge_batch_size as u64
+                        || cancel_token.is_cancelled()
+                    {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    warn!(error = %err, "Failed to purge delegation history");
+                    break;
+                }
+            }
+        }
+        if purged > 0 {
+            metrics::observe_pruned(
+                "user_decryption_delegation_history",
+                purged,
+            );
+            info!(purged, "Purged delegation history");
+        }
+
+        if args.retention_policies.is_empty() {
+            continue;
+        }
+        let last_valid_block =
+            match retention::last_valid_block(&current_pool, chain_id).await {
+                Ok(last_valid_block) => last_valid_block,
+                Err(err) => {
+                    warn!(error = %err, "Failed to read last valid block");
+                    continue;
+                }
+            };
+        for policy in &args.retention_policies {
+            let mut pruned = 0;
+            loop {
+                match retention::prune_batch(
+                    &current_pool,
+                    tenant_id,
+                    chain_id,
+                    policy,
+                    last_valid_block,
+                    args.purge_batch_size,
+                )
+                .await
+                {
+                    Ok(count) => {
+                        pruned += count;
+                        metrics::observe_pruned(policy.table.as_str(), count);
+                        if count < args.purge_batch_size as u64
+                            || cancel_token.is_cancelled()
+                        {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        warn!(table = %policy.table, error = %err, "Failed to prune table");
+                        break;
+                    }
+                }
+            }
+            if pruned > 0 {
+                info!(table = %policy.table, pruned, "Pruned table");
+            }
+        }
+    }
+}
+
 async fn db_insert_block(
     db: &mut Database,
     block_logs: &BlockLogs<Log>,
     acl_contract_address: &Option<Address>,
     tfhe_contract_address: &Option<Address>,
-) -> anyhow::Result<()> {
+    raw_events: RawEvents,
+    delegation_block_delay: u64,
+) -> anyhow::Result<InsertBatch> {
     info!(
         block = ?block_logs.summary,
         nb_events = block_logs.logs.len(),
@@ -798,10 +1205,13 @@ async fn db_insert_block(
             block_logs,
             acl_contract_address,
             tfhe_contract_address,
+            raw_events,
+            delegation_block_delay,
         )
         .await;
-        let Err(err) = res else {
-            return Ok(());
+        let err = match res {
+            Ok(batch) => return Ok(batch),
+            Err(err) => err,
         };
         if retries == 0 {
             error!(error = %err, block = ?block_logs.summary, "Error inserting block");
@@ -819,53 +1229,72 @@ async fn db_insert_block(
     }
 }
 
-async fn db_insert_block_no_retry(
-    db: &mut Database,
-    block_logs: &BlockLogs<Log>,
+/// Events of a block decoded and pushed in an insert batch, not yet flushed.
+pub struct DecodedLogs<'a> {
+    pub batch: InsertBatch,
+    /// Contract and event type of the decoded events
+    pub decoded_events: Vec<(Address, &'static str)>,
+    /// Logs that can't be decoded, failed if emitted by a watched contract
+    pub undecoded_events: Vec<(&'a Log, bool)>,
+}
+
+/// Decodes the logs of a block and pushes their events in a new insert batch.
+pub async fn decode_logs<'a>(
+    db: &Database,
+    logs: &'a [Log],
     acl_contract_address: &Option<Address>,
     tfhe_contract_address: &Option<Address>,
-) -> std::result::Result<(), sqlx::Error> {
-    let mut tx = db.new_transaction().await?;
+) -> DecodedLogs<'a> {
+    let mut batch = InsertBatch::default();
     let mut is_allowed = HashSet::<Handle>::new();
     let mut tfhe_event_log = vec![];
-    for log in &block_logs.logs {
+    let mut decoded_events = vec![];
+    let mut undecoded_events = vec![];
+    for log in logs {
         let current_address = Some(log.inner.address);
         let is_acl_address = &current_address == acl_contract_address;
         if acl_contract_address.is_none() || is_acl_address {
-            if let Ok(event) =
-                AclContract::AclContractEvents::decode_log(&log.inner)
+            if let Ok(decoded) =
+                decode_event::<AclContract::AclContractEvents>(log)
             {
+                let event = decoded.log;
                 info!(acl_event = ?event, "ACL event");
+                decoded_events
+                    .push((log.inner.address, acl_event_name(&event)));
                 let handles = acl_result_handles(&event);
                 for handle in handles {
                     is_allowed.insert(handle.to_vec());
                 }
-                db.handle_acl_event(
-                    &mut tx,
+                db.push_acl_event(
+                    &mut batch,
                     &event,
-                    &log.transaction_hash,
-                    &log.block_number,
+                    &decoded.transaction_hash,
+                    &decoded.block_number,
                 )
-                .await?;
+                .await;
                 continue;
             }
         }
         let is_tfhe_address = &current_address == tfhe_contract_address;
         if tfhe_contract_address.is_none() || is_tfhe_address {
-            if let Ok(event) =
-                TfheContract::TfheContractEvents::decode_log(&log.inner)
+            if let Ok(decoded) =
+                decode_event::<TfheContract::TfheContractEvents>(log)
             {
+                decoded_events
+                    .push((log.inner.address, event_name(&decoded.log)));
                 let log = LogTfhe {
-                    event,
-                    transaction_hash: log.transaction_hash,
+                    event: decoded.log,
+                    transaction_hash: decoded.transaction_hash,
                     is_allowed: false, // updated in the next loop
-                    block_number: log.block_number,
+                    block_number: decoded.block_number,
                 };
                 tfhe_event_log.push(log);
                 continue;
             }
         }
-        if is_acl_address || is_tfhe_address {
+        let is_failed = is_acl_address || is_tfhe_address;
+        undecoded_events.push((log, is_failed));
+        if is_failed {
             error!(
                 event_address = ?log.inner.address,
                 acl_contract_address = ?acl_contract_address,
@@ -887,10 +1316,99 @@ async fn db_insert_block_no_retry(
             is_allowed,
             ..tfhe_log
         };
-        db.insert_tfhe_event(&mut tx, &tfhe_log).await?;
+        db.push_tfhe_event(&mut batch, &tfhe_log).await;
+    }
+    DecodedLogs {
+        batch,
+        decoded_events,
+        undecoded_events,
+    }
+}
+
+/// Returns the flushed batch, with the number of dismissed delegation events
+/// and the rows written.
+async fn db_insert_block_no_retry(
+    db: &mut Database,
+    block_logs: &BlockLogs<Log>,
+    acl_contract_address: &Option<Address>,
+    tfhe_contract_address: &Option<Address>,
+    raw_events: RawEvents,
+    delegation_block_delay: u64,
+) -> std::result::Result<InsertBatch, sqlx::Error> {
+    let mut tx = db.new_transaction().await?;
+    // Observed once the block is committed, a retried block is counted once
+    let DecodedLogs {
+        mut batch,
+        decoded_events,
+        undecoded_events,
+    } = decode_logs(
+        db,
+        &block_logs.logs,
+        acl_contract_address,
+        tfhe_contract_address,
+    )
+    .await;
+    if batch.coalesced() > 0 {
+        info!(
+            block = block_logs.summary.number,
+            coalesced = batch.coalesced(),
+            "Coalesced duplicate allow events"
+        );
     }
+    let captured: Vec<(&Log, bool)> = match raw_events {
+        RawEvents::None => vec![],
+        RawEvents::Undecoded => undecoded_events
+            .iter()
+            .map(|(log, _)| (*log, false))
+            .collect(),
+        RawEvents::All => block_logs
+            .logs
+            .iter()
+            .map(|log| {
+                let is_decoded = !undecoded_events
+                    .iter()
+                    .any(|(undecoded, _)| std::ptr::eq(*undecoded, log));
+                (log, is_decoded)
+            })
+            .collect(),
+    };
+    db.insert_raw_events(&mut tx, &block_logs.summary, &captured)
+        .await?;
+    let channels = batch.notified_channels();
+    db.flush_batch(&mut tx, &mut batch).await?;
+    db.confirm_delegations(
+        &mut tx,
+        &block_logs.summary,
+        delegation_block_delay,
+    )
+    .await?;
     db.mark_block_as_valid(&mut tx, &block_logs.summary).await?;
-    tx.commit().await
+    tx.commit().await?;
+
+    if let Some(bus) = &db.message_bus {
+        let block_number = block_logs.summary.number.to_string();
+        for channel in channels {
+            // Subscribers catch up by polling
+            if let Err(err) = bus.publish(channel, &block_number).await {
+                warn!(channel, error = %err, "Failed to publish block");
+            }
+        }
+    }
+
+    if db.is_shadow {
+        return Ok(batch);
+    }
+    for (contract, event_type) in decoded_events {
+        metrics::observe_decoded(db.chain_id, &contract, event_type);
+    }
+    for (log, is_failed) in undecoded_events {
+        if is_failed {
+            metrics::observe_failed(db.chain_id, log);
+        } else {
+            metrics::observe_skipped(db.chain_id, log);
+        }
+    }
+    Ok(batch)
 }
 
 pub async fn main(args: Args) -> anyhow::Result<()> {
@@ -932,7 +1450,15 @@ pub async fn main(args: Args) -> anyhow::Result<()> {
         }
     }
 
-    let mut log_iter = InfiniteLogIter::new(&args);
+    let rpc_tls = RpcTlsConfig {
+        ca_cert: args.rpc_tls_ca_cert.clone(),
+        client_cert: args.rpc_tls_client_cert.clone(),
+        client_key: args.rpc_tls_client_key.clone(),
+        server_name: args.rpc_tls_server_name.clone(),
+        verify: args.rpc_tls_verify,
+    }
+    .build()?;
+    let mut log_iter = InfiniteLogIter::new(&args, rpc_tls);
     let chain_id = log_iter.get_chain_id().await?;
     info!(chain_id = chain_id, "Chain ID");
     if args.database_url.is_empty() {
@@ -943,12 +1469,24 @@ pub async fn main(args: Args) -> anyhow::Result<()> {
         error!("A Coprocessor API key is required to access the database");
         panic!("A Coprocessor API key is required to access the database");
     };
-    let mut db = Database::new(
+    let mut db = Database::new_in_schema(
         &args.database_url,
+        args.database_schema.as_deref(),
         &coprocessor_api_key,
         args.dependence_cache_size,
     )
     .await?;
+    db.insert_batch_size = args.insert_batch_size;
+    db.lazy_sns = args.lazy_sns;
+    if let Some(secret) = &args.column_encryption_key {
+        db.column_encryption = ColumnEncryption::from_secret(secret)?;
+    }
+    db.message_bus = notification_bus::connect_external(&BusConfig {
+        kind: args.message_bus,
+        nats_url: args.nats_url.clone(),
+        subject_prefix: args.nats_subject_prefix.clone(),
+    })
+    .await?;
 
     if chain_id != db.chain_id {
         error!(
@@ -966,6 +1504,44 @@ pub async fn main(args: Args) -> anyhow::Result<()> {
             coprocessor_api_key
         ));
     }
+    log_iter.chain_id = Some(chain_id);
+
+    let mut shadow = match &args.shadow_database_url {
+        Some(shadow_database_url) => {
+            let mut shadow_db = Database::new_in_schema(
+                shadow_database_url,
+                args.shadow_database_schema.as_deref(),
+                &coprocessor_api_key,
+                args.dependence_cache_size,
+            )
+            .await?;
+            if shadow_db.chain_id != chain_id {
+                error!(
+                    chain_id_blockchain = ?chain_id,
+                    chain_id_shadow_db = ?shadow_db.chain_id,
+                    "Chain ID mismatch with shadow database",
+                );
+                return Err(anyhow!(
+                    "Chain ID mismatch with shadow database, blockchain: {} vs shadow db: {}",
+                    chain_id,
+                    shadow_db.chain_id,
+                ));
+            }
+            shadow_db.insert_batch_size = args.insert_batch_size;
+            shadow_db.lazy_sns = args.lazy_sns;
+            shadow_db.column_encryption = db.column_encryption.clone();
+            info!(
+                tenant_id = shadow_db.tenant_id,
+                "Writing the blocks to the shadow database"
+            );
+            Some(ShadowDatabase::new(
+                shadow_db,
+                args.shadow_report_interval,
+                args.shadow_write_timeout,
+            ))
+        }
+        None => None,
+    };
 
     let health_check = HealthCheck {
         blockchain_timeout_tick: log_iter.tick_timeout.clone(),
@@ -981,6 +1557,30 @@ pub async fn main(args: Args) -> anyhow::Result<()> {
         cancel_token.clone(),
     );
     tokio::spawn(async move { health_check_server.start().await });
+    // a bounded run may end before being scraped
+    let metrics_pusher =
+        match (&args.metrics_push_gateway_url, args.end_at_block) {
+            (Some(url), Some(_)) => Some(
+                MetricsPusher::new(
+                    url,
+                    &args.service_name,
+                    &chain_id.to_string(),
+                )?
+                .spawn(args.metrics_push_interval, cancel_token.clone()),
+            ),
+            (Some(_), None) => {
+                warn!("Metrics are only pushed with --end-at-block");
+                None
+            }
+            _ => None,
+        };
+    tokio::spawn(purge_history(
+        db.pool.clone(),
+        db.tenant_id,
+        db.chain_id,
+        args.clone(),
+        cancel_token.clone(),
+    ));
 
     if log_iter.start_at_block.is_none() {
         log_iter.start_at_block = db
@@ -992,15 +1592,46 @@ pub async fn main(args: Args) -> anyhow::Result<()> {
     log_iter.new_log_stream(true).await;
 
     while let Some(block_logs) = log_iter.next().await {
-        let _ = db_insert_block(
+        let block_delay = &mut log_iter.delegation_block_delay;
+        let block_number = block_logs.summary.number;
+        let inserted = db_insert_block(
             &mut db,
             &block_logs,
             &acl_contract_address,
             &tfhe_contract_address,
+            args.raw_events,
+            block_delay.current(),
         )
         .await;
         // logging & retry on error is already done in db_insert_block
+        if let Ok(batch) = inserted {
+            // the catch-up replays events already applied
+            if !block_logs.catchup {
+                block_delay.observe_dismissals(block_number, batch.dismissed());
+            }
+            if let Some(shadow) = &mut shadow {
+                shadow
+                    .write_block(
+                        &block_logs,
+                        &batch,
+                        &acl_contract_address,
+                        &tfhe_contract_address,
+                        args.raw_events,
+                        block_delay.current(),
+                    )
+                    .await;
+            }
+        }
+        block_delay.observe_block(block_number);
+        metrics::set_delegation_block_delay(chain_id, block_delay.current());
     }
     cancel_token.cancel();
+    if let Some(shadow) = &shadow {
+        shadow.report().log();
+    }
+    if let Some(metrics_pusher) = metrics_pusher {
+        // last push, after the cancellation
+        let _ = metrics_pusher.await;
+    }
     anyhow::Result::Ok(())
 }
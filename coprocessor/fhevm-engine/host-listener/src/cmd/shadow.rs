@@ -0,0 +1,356 @@
+//! Shadow writes, to migrate to a new database without stopping ingestion.
+//!
+//! With `--shadow-database-url`, each block committed to the database is then
+//! written to the shadow database, in its own transaction, with the tenant and
+//! dependence chains of the shadow database. The shadow database is never read
+//! from and its failures do not stop the ingestion: a block that can't be
+//! written is reported, to be replayed in the shadow database, e.g. with
+//! `decode_raw_events --ingest`. Writing a block is bounded by
+//! `--shadow-write-timeout`, a slow shadow database only delaying the
+//! ingestion by as much.
+//!
+//! Each block is checked by comparing the rows written per table to both
+//! databases, the divergences are counted and logged in a report every
+//! `--shadow-report-interval` blocks. The readers can be switched to the
+//! shadow database once it has been consistent for long enough.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use alloy::primitives::Address;
+use alloy::rpc::types::Log;
+use tracing::{error, info, warn};
+
+use super::{db_insert_block_no_retry, BlockLogs, RawEvents};
+use crate::database::tfhe_event_propagate::{Database, InsertBatch};
+use crate::metrics;
+
+/// Attempts at writing a block to the shadow database before reporting it.
+const SHADOW_WRITE_ATTEMPTS: usize = 3;
+
+/// Blocks listed in the report, the others are only counted.
+const MAX_REPORTED_BLOCKS: usize = 100;
+
+/// Rows written to a table for a block, by table.
+pub type WrittenRows = BTreeMap<&'static str, u64>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowOutcome {
+    Consistent,
+    Diverging,
+    Failed,
+}
+
+impl ShadowOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShadowOutcome::Consistent => "consistent",
+            ShadowOutcome::Diverging => "diverging",
+            ShadowOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// A table whose rows written for a block differ between the databases.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableDivergence {
+    pub table: &'static str,
+    pub rows: u64,
+    pub shadow_rows: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockDivergence {
+    pub block_number: u64,
+    pub tables: Vec<TableDivergence>,
+}
+
+/// Blocks written to the shadow database since the listener started.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DivergenceReport {
+    pub blocks: u64,
+    pub failed: u64,
+    pub diverging: u64,
+    /// First failed blocks, at most `MAX_REPORTED_BLOCKS`
+    pub failed_blocks: Vec<u64>,
+    /// First diverging blocks, at most `MAX_REPORTED_BLOCKS`
+    pub diverging_blocks: Vec<BlockDivergence>,
+    /// First block since which all the blocks are consistent
+    pub consistent_since: Option<u64>,
+}
+
+/// Tables with a different number of rows written, a table missing on one
+/// side having no row written.
+pub fn diverging_tables(
+    rows: &WrittenRows,
+    shadow_rows: &WrittenRows,
+) -> Vec<TableDivergence> {
+    let mut tables: Vec<&'static str> =
+        rows.keys().chain(shadow_rows.keys()).copied().collect();
+    tables.sort_unstable();
+    tables.dedup();
+    tables
+        .into_iter()
+        .filter_map(|table| {
+            let rows = rows.get(table).copied().unwrap_or_default();
+            let shadow_rows =
+                shadow_rows.get(table).copied().unwrap_or_default();
+            (rows != shadow_rows).then_some(TableDivergence {
+                table,
+                rows,
+                shadow_rows,
+            })
+        })
+        .collect()
+}
+
+impl DivergenceReport {
+    /// Compares the rows written for a block, `shadow_rows` being None if the
+    /// block could not be written to the shadow database.
+    pub fn observe(
+        &mut self,
+        block_number: u64,
+        rows: &WrittenRows,
+        shadow_rows: Option<&WrittenRows>,
+    ) -> ShadowOutcome {
+        self.blocks += 1;
+        let outcome = match shadow_rows {
+            None => {
+                self.failed += 1;
+                if self.failed_blocks.len() < MAX_REPORTED_BLOCKS {
+                    self.failed_blocks.push(block_number);
+                }
+                ShadowOutcome::Failed
+            }
+            Some(shadow_rows) => {
+                let tables = diverging_tables(rows, shadow_rows);
+                if tables.is_empty() {
+                    ShadowOutcome::Consistent
+                } else {
+                    self.diverging += 1;
+                    if self.diverging_blocks.len() < MAX_REPORTED_BLOCKS {
+                        self.diverging_blocks.push(BlockDivergence {
+                            block_number,
+                            tables,
+                        });
+                    }
+                    ShadowOutcome::Diverging
+                }
+            }
+        };
+        if outcome == ShadowOutcome::Consistent {
+            self.consistent_since.get_or_insert(block_number);
+        } else {
+            self.consistent_since = None;
+        }
+        outcome
+    }
+
+    pub fn log(&self) {
+        if self.failed == 0 && self.diverging == 0 {
+            info!(
+                blocks = self.blocks,
+                consistent_since = ?self.consistent_since,
+                "Shadow database consistent"
+            );
+        } else {
+            warn!(
+                blocks = self.blocks,
+                failed = self.failed,
+                diverging = self.diverging,
+                failed_blocks = ?self.failed_blocks,
+                diverging_blocks = ?self.diverging_blocks,
+                consistent_since = ?self.consistent_since,
+                "Shadow database diverging"
+            );
+        }
+    }
+}
+
+pub struct ShadowDatabase {
+    pub db: Database,
+    report: DivergenceReport,
+    /// Blocks between two logged reports
+    report_interval: u64,
+    /// Maximum time writing a block, attempts included
+    write_timeout: Duration,
+}
+
+impl ShadowDatabase {
+    pub fn new(
+        mut db: Database,
+        report_interval: u64,
+        write_timeout: Duration,
+    ) -> Self {
+        db.is_shadow = true;
+        Self {
+            db,
+            report: DivergenceReport::default(),
+            report_interval: report_interval.max(1),
+            write_timeout,
+        }
+    }
+
+    pub fn report(&self) -> &DivergenceReport {
+        &self.report
+    }
+
+    /// Writes a block already committed to the database, `batch` being the
+    /// batch it was committed with.
+    pub(super) async fn write_block(
+        &mut self,
+        block_logs: &BlockLogs<Log>,
+        batch: &InsertBatch,
+        acl_contract_address: &Option<Address>,
+        tfhe_contract_address: &Option<Address>,
+        raw_events: RawEvents,
+        delegation_block_delay: u64,
+    ) {
+        let block_number = block_logs.summary.number;
+        let db = &mut self.db;
+        let write = async {
+            let mut attempts = SHADOW_WRITE_ATTEMPTS;
+            loop {
+                let res = db_insert_block_no_retry(
+                    db,
+                    block_logs,
+                    acl_contract_address,
+                    tfhe_contract_address,
+                    raw_events,
+                    delegation_block_delay,
+                )
+                .await;
+                match res {
+                    Ok(shadow_batch) => break Some(shadow_batch),
+                    Err(err) if attempts > 1 => {
+                        warn!(error = %err, block = block_number, "Retry writing block to the shadow database");
+                        attempts -= 1;
+                        db.reconnect().await;
+                    }
+                    Err(err) => {
+                        error!(error = %err, block = block_number, "Failed to write block to the shadow database");
+                        break None;
+                    }
+                }
+            }
+        };
+        // the transaction of an interrupted write is rolled back when dropped
+        let shadow_batch =
+            match tokio::time::timeout(self.write_timeout, write).await {
+                Ok(shadow_batch) => shadow_batch,
+                Err(_) => {
+                    error!(
+                        block = block_number,
+                        timeout = ?self.write_timeout,
+                        "Timeout writing block to the shadow database"
+                    );
+                    None
+                }
+            };
+
+        let rows = batch.written_rows();
+        let shadow_rows = shadow_batch.as_ref().map(InsertBatch::written_rows);
+        let chain_id = self.db.chain_id;
+        let outcome = self.report.observe(block_number, rows, shadow_rows);
+        metrics::observe_shadow_block(chain_id, outcome.as_str());
+        if let Some(shadow_rows) = shadow_rows {
+            for divergence in diverging_tables(rows, shadow_rows) {
+                warn!(
+                    block = block_number,
+                    table = divergence.table,
+                    rows = divergence.rows,
+                    shadow_rows = divergence.shadow_rows,
+                    "Rows written to the shadow database diverge"
+                );
+                metrics::observe_shadow_divergence(
+                    chain_id,
+                    divergence.table,
+                    divergence.rows.abs_diff(divergence.shadow_rows),
+                );
+            }
+        }
+        if self.report.blocks % self.report_interval == 0 {
+            self.report.log();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(tables: &[(&'static str, u64)]) -> WrittenRows {
+        tables.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_diverging_tables() {
+        let written = rows(&[("computations", 3), ("allowed_handles", 2)]);
+        assert!(diverging_tables(&written, &written).is_empty());
+        // a table without row written may be missing
+        let shadow = rows(&[
+            ("computations", 3),
+            ("allowed_handles", 2),
+            ("pbs_computations", 0),
+        ]);
+        assert!(diverging_tables(&written, &shadow).is_empty());
+        let shadow = rows(&[("computations", 1), ("pbs_computations", 1)]);
+        assert_eq!(
+            diverging_tables(&written, &shadow),
+            vec![
+                TableDivergence {
+                    table: "allowed_handles",
+                    rows: 2,
+                    shadow_rows: 0,
+                },
+                TableDivergence {
+                    table: "computations",
+                    rows: 3,
+                    shadow_rows: 1,
+                },
+                TableDivergence {
+                    table: "pbs_computations",
+                    rows: 0,
+                    shadow_rows: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let mut report = DivergenceReport::default();
+        let written = rows(&[("computations", 3)]);
+        assert_eq!(
+            report.observe(10, &written, Some(&written)),
+            ShadowOutcome::Consistent
+        );
+        assert_eq!(report.consistent_since, Some(10));
+        assert_eq!(report.observe(11, &written, None), ShadowOutcome::Failed);
+        assert_eq!(report.consistent_since, None);
+        assert_eq!(
+            report.observe(12, &written, Some(&rows(&[]))),
+            ShadowOutcome::Diverging
+        );
+        for block_number in 13..16 {
+            report.observe(block_number, &written, Some(&written));
+        }
+        assert_eq!(report.blocks, 6);
+        assert_eq!(report.failed_blocks, vec![11]);
+        assert_eq!(report.diverging, 1);
+        assert_eq!(report.diverging_blocks[0].block_number, 12);
+        assert_eq!(report.consistent_since, Some(13));
+    }
+
+    #[test]
+    fn test_report_is_bounded() {
+        let mut report = DivergenceReport::default();
+        let written = rows(&[("computations", 1)]);
+        for block_number in 0..2 * MAX_REPORTED_BLOCKS as u64 {
+            report.observe(block_number, &written, None);
+        }
+        assert_eq!(report.failed, 2 * MAX_REPORTED_BLOCKS as u64);
+        assert_eq!(report.failed_blocks.len(), MAX_REPORTED_BLOCKS);
+        assert_eq!(report.failed_blocks[0], 0);
+    }
+}
@@ -0,0 +1,261 @@
+//! Retention of the historical rows written by the host-listener.
+//!
+//! A table is pruned of the rows older than its retention, by age or by depth
+//! in blocks behind the last valid block, in batches. Rows still needed by
+//! pending work are kept whatever their age: the computations of a
+//! transaction with computations to do, the pending SnS computations and the
+//! raw events not decoded yet.
+//!
+//! The retention must be longer than the catch-up margin, the events of a
+//! replayed block being inserted again, and their work done again, once
+//! pruned.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use sqlx::Error as SqlxError;
+use sqlx::PgPool;
+
+use crate::database::tfhe_event_propagate::{ChainId, TenantId};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetainedTable {
+    Computations,
+    PbsComputations,
+    RawEvents,
+    BlocksValid,
+}
+
+impl RetainedTable {
+    pub const ALL: [RetainedTable; 4] = [
+        Self::Computations,
+        Self::PbsComputations,
+        Self::RawEvents,
+        Self::BlocksValid,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Computations => "computations",
+            Self::PbsComputations => "pbs_computations",
+            Self::RawEvents => "raw_events",
+            Self::BlocksValid => "host_chain_blocks_valid",
+        }
+    }
+
+    fn has_age(&self) -> bool {
+        !matches!(self, Self::BlocksValid)
+    }
+
+    fn has_block_number(&self) -> bool {
+        !matches!(self, Self::Computations | Self::PbsComputations)
+    }
+}
+
+impl fmt::Display for RetainedTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RetainedTable {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|table| table.as_str() == value)
+            .ok_or_else(|| {
+                let tables: Vec<&str> =
+                    Self::ALL.iter().map(RetainedTable::as_str).collect();
+                format!(
+                    "unknown table {value}, expected one of {}",
+                    tables.join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Retention {
+    Age(Duration),
+    /// Depth behind the last valid block
+    Blocks(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub table: RetainedTable,
+    pub retention: Retention,
+}
+
+/// Parses `<table>=<age>`, e.g. `computations=30d`, or
+/// `<table>=<depth>blocks`, e.g. `raw_events=100000blocks`.
+pub fn parse_retention_policy(value: &str) -> Result<RetentionPolicy, String> {
+    let (table, retention) = value.split_once('=').ok_or_else(|| {
+        format!("expected <table>=<age> or <table>=<depth>blocks, got {value}")
+    })?;
+    let table = RetainedTable::from_str(table.trim())?;
+    let retention = retention.trim();
+    let retention = match retention.strip_suffix("blocks") {
+        Some(depth) => Retention::Blocks(
+            depth
+                .trim()
+                .parse()
+                .map_err(|err| format!("invalid depth {depth}: {err}"))?,
+        ),
+        None => Retention::Age(
+            humantime::parse_duration(retention)
+                .map_err(|err| format!("invalid age {retention}: {err}"))?,
+        ),
+    };
+    match retention {
+        Retention::Age(_) if !table.has_age() => {
+            Err(format!("{table} can only be retained by depth in blocks"))
+        }
+        Retention::Blocks(_) if !table.has_block_number() => {
+            Err(format!("{table} can only be retained by age"))
+        }
+        _ => Ok(RetentionPolicy { table, retention }),
+    }
+}
+
+/// Deletes up to `batch_size` rows of the table older than the retention,
+/// and returns how many were deleted. `last_valid_block` is required for a
+/// retention by depth, nothing being deleted without it.
+pub async fn prune_batch(
+    pool: &PgPool,
+    tenant_id: TenantId,
+    chain_id: ChainId,
+    policy: &RetentionPolicy,
+    last_valid_block: Option<i64>,
+    batch_size: u32,
+) -> Result<u64, SqlxError> {
+    let (age, before_block) = match policy.retention {
+        Retention::Age(age) => (Some(age.as_secs_f64()), None),
+        Retention::Blocks(depth) => match last_valid_block {
+            Some(last_valid_block) => {
+                (None, Some(last_valid_block.saturating_sub(depth as i64)))
+            }
+            None => return Ok(0),
+        },
+    };
+    let batch_size = batch_size as i64;
+    let query = match policy.table {
+        RetainedTable::Computations => sqlx::query!(
+            r#"
+            DELETE FROM computations
+            WHERE ctid IN (
+                SELECT c.ctid FROM computations c
+                WHERE c.tenant_id = $1
+                AND c.is_completed = TRUE
+                AND c.completed_at < NOW() - make_interval(secs => $2)
+                AND NOT EXISTS (
+                    SELECT 1 FROM computations p
+                    WHERE p.tenant_id = c.tenant_id
+                    AND p.transaction_id = c.transaction_id
+                    AND p.is_completed = FALSE
+                )
+                LIMIT $3
+            )
+            "#,
+            tenant_id,
+            age.unwrap_or_default(),
+            batch_size,
+        ),
+        RetainedTable::PbsComputations => sqlx::query!(
+            r#"
+            DELETE FROM pbs_computations
+            WHERE ctid IN (
+                SELECT ctid FROM pbs_computations
+                WHERE tenant_id = $1
+                AND is_completed = TRUE
+                AND completed_at < NOW() - make_interval(secs => $2)
+                LIMIT $3
+            )
+            "#,
+            tenant_id,
+            age.unwrap_or_default(),
+            batch_size,
+        ),
+        RetainedTable::RawEvents => sqlx::query!(
+            r#"
+            DELETE FROM raw_events
+            WHERE ctid IN (
+                SELECT ctid FROM raw_events
+                WHERE tenant_id = $1
+                AND is_decoded = TRUE
+                AND ($2::FLOAT8 IS NULL OR created_at < NOW() - make_interval(secs => $2))
+                AND ($3::BIGINT IS NULL OR block_number < $3)
+                LIMIT $4
+            )
+            "#,
+            tenant_id,
+            age,
+            before_block,
+            batch_size,
+        ),
+        RetainedTable::BlocksValid => sqlx::query!(
+            r#"
+            DELETE FROM host_chain_blocks_valid
+            WHERE ctid IN (
+                SELECT ctid FROM host_chain_blocks_valid
+                WHERE chain_id = $1
+                AND block_number < $2
+                LIMIT $3
+            )
+            "#,
+            chain_id as i64,
+            before_block.unwrap_or_default(),
+            batch_size,
+        ),
+    };
+    let res = query.execute(pool).await?;
+    Ok(res.rows_affected())
+}
+
+/// Last block marked valid for the chain, the reference of the retentions by
+/// depth.
+pub async fn last_valid_block(
+    pool: &PgPool,
+    chain_id: ChainId,
+) -> Result<Option<i64>, SqlxError> {
+    let record = sqlx::query!(
+        r#"
+            SELECT MAX(block_number) FROM host_chain_blocks_valid WHERE chain_id = $1;
+            "#,
+        chain_id as i64,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(record.max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retention_policy() {
+        assert_eq!(
+            parse_retention_policy("computations=30d"),
+            Ok(RetentionPolicy {
+                table: RetainedTable::Computations,
+                retention: Retention::Age(Duration::from_secs(30 * 24 * 3600)),
+            })
+        );
+        assert_eq!(
+            parse_retention_policy("host_chain_blocks_valid=100000blocks"),
+            Ok(RetentionPolicy {
+                table: RetainedTable::BlocksValid,
+                retention: Retention::Blocks(100000),
+            })
+        );
+        assert!(parse_retention_policy("computations").is_err());
+        assert!(parse_retention_policy("allowed_handles=30d").is_err());
+        assert!(parse_retention_policy("raw_events=soon").is_err());
+        assert!(parse_retention_policy("computations=10blocks").is_err());
+        assert!(parse_retention_policy("host_chain_blocks_valid=1h").is_err());
+    }
+}
@@ -1,7 +1,12 @@
+use alloy::rpc::types::Log as RpcLog;
+use alloy_primitives::Address;
 use alloy_primitives::FixedBytes;
 use alloy_primitives::Log;
 use alloy_primitives::Uint;
 use anyhow::Result;
+use fhevm_engine_common::column_encryption::ColumnEncryption;
+use fhevm_engine_common::db_schema;
+use fhevm_engine_common::notification_bus::NotificationBus;
 use fhevm_engine_common::telemetry;
 use fhevm_engine_common::types::AllowEvents;
 use fhevm_engine_common::types::SupportedFheOperations;
@@ -10,7 +15,8 @@ use sqlx::postgres::PgConnectOptions;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::types::Uuid;
 use sqlx::Error as SqlxError;
-use sqlx::{PgPool, Postgres};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ops::DerefMut;
 use std::sync::Arc;
 use std::time::Duration;
@@ -20,12 +26,13 @@ use tracing::info;
 use tracing::warn;
 
 use crate::cmd::block_history::BlockSummary;
+use crate::contracts::AclContract;
 use crate::contracts::AclContract::AclContractEvents;
 use crate::contracts::TfheContract;
 use crate::contracts::TfheContract::TfheContractEvents;
 
 type CoprocessorApiKey = Uuid;
-type FheOperation = i32;
+pub type FheOperation = i32;
 pub type Handle = FixedBytes<32>;
 pub type TransactionHash = FixedBytes<32>;
 pub type TenantId = i32;
@@ -36,6 +43,11 @@ pub type ClearConst = Uint<256, 4>;
 
 const MINIMUM_BUCKET_CACHE_SIZE: u16 = 16;
 
+/// Rows written by a single multi-row insert, unless limited by the number of
+/// bind parameters.
+pub const DEFAULT_INSERT_BATCH_SIZE: usize = 1000;
+const MAX_BIND_PARAMETERS: usize = u16::MAX as usize;
+
 const MAX_RETRY_FOR_TRANSIENT_ERROR: usize = 20;
 const MAX_RETRY_ON_UNKNOWN_ERROR: usize = 5;
 
@@ -71,11 +83,24 @@ pub fn retry_on_sqlx_error(err: &SqlxError, retry_count: &mut usize) -> bool {
 // A pool of connection with some cached information and automatic reconnection
 pub struct Database {
     url: String,
+    schema: Option<String>,
     pub pool: Arc<RwLock<sqlx::Pool<Postgres>>>,
     pub tenant_id: TenantId,
     pub chain_id: ChainId,
     bucket_cache: tokio::sync::RwLock<lru::LruCache<Handle, Handle>>,
     pub tick: HeartBeat,
+    pub insert_batch_size: usize,
+    /// Encrypts the delegators and delegates before they are written
+    pub column_encryption: ColumnEncryption,
+    /// Message bus the committed blocks are published on, when not Postgres
+    /// whose triggers already notify
+    pub message_bus: Option<Arc<dyn NotificationBus>>,
+    /// The handles allowed to an account are not enqueued to the sns-worker,
+    /// the user decryptions enqueue theirs, see `--lazy-sns`
+    pub lazy_sns: bool,
+    /// Shadow database receiving a copy of the writes, whose events are not
+    /// counted again in the metrics, see `cmd::shadow`
+    pub is_shadow: bool,
 }
 
 #[derive(Debug)]
@@ -88,13 +113,211 @@ pub struct LogTfhe {
 
 pub type Transaction<'l> = sqlx::Transaction<'l, Postgres>;
 
+struct ComputationRow {
+    output_handle: Vec<u8>,
+    dependencies: Vec<Vec<u8>>,
+    fhe_operation: i16,
+    is_scalar: bool,
+    dependence_chain_id: Vec<u8>,
+    transaction_id: Option<Vec<u8>>,
+    is_allowed: bool,
+    caller: Option<Vec<u8>>,
+}
+
+struct AllowedHandleRow {
+    handle: Vec<u8>,
+    account_address: String,
+    event_type: i16,
+    transaction_id: Option<Vec<u8>>,
+}
+
+struct PbsComputationRow {
+    handle: Vec<u8>,
+    transaction_id: Option<Vec<u8>>,
+    priority: i16,
+}
+
+/// `pbs_computations.priority` of the handles allowed for public decryption,
+/// squashed ahead of the others.
+const PUBLIC_DECRYPTION_PRIORITY: i16 = 1;
+
+struct DelegationRow {
+    delegator: String,
+    delegate: String,
+    contract_address: String,
+    delegation_counter: i64,
+    expiry_date: i64,
+    /// Expiry date of the revoked delegation, for a revocation
+    old_expiry_date: Option<i64>,
+    block_number: Option<i64>,
+    transaction_id: Option<Vec<u8>>,
+}
+
+impl DelegationRow {
+    /// Row of a delegation event, `None` if its counter does not fit in the
+    /// database. Expiry dates beyond it are clamped, being in a far future.
+    fn delegated(
+        event: &AclContract::DelegatedAccount,
+        block_number: &Option<u64>,
+        transaction_id: Option<Vec<u8>>,
+    ) -> Option<Self> {
+        Some(Self {
+            delegator: event.delegator.to_string(),
+            delegate: event.delegate.to_string(),
+            contract_address: event.contractAddress.to_string(),
+            delegation_counter: event.delegationCounter.try_into().ok()?,
+            expiry_date: clamp_timestamp(event.newExpiryDate),
+            old_expiry_date: None,
+            block_number: block_number.and_then(|n| n.try_into().ok()),
+            transaction_id,
+        })
+    }
+
+    /// Row of a revocation event, applied as a delegation expiring at 0,
+    /// ordered with the other events of the delegation by its counter.
+    fn revoked(
+        event: &AclContract::RevokedDelegation,
+        block_number: &Option<u64>,
+        transaction_id: Option<Vec<u8>>,
+    ) -> Option<Self> {
+        Some(Self {
+            delegator: event.delegator.to_string(),
+            delegate: event.delegate.to_string(),
+            contract_address: event.contractAddress.to_string(),
+            delegation_counter: event.delegationCounter.try_into().ok()?,
+            expiry_date: 0,
+            old_expiry_date: Some(clamp_timestamp(event.oldExpiryDate)),
+            block_number: block_number.and_then(|n| n.try_into().ok()),
+            transaction_id,
+        })
+    }
+}
+
+fn clamp_timestamp(timestamp: u64) -> i64 {
+    timestamp.try_into().unwrap_or(i64::MAX)
+}
+
+/// Rows of `columns` bind parameters each written by a single statement, at
+/// least one and within the bind parameters limit of Postgres.
+fn rows_per_insert(insert_batch_size: usize, columns: usize) -> usize {
+    insert_batch_size.clamp(1, MAX_BIND_PARAMETERS / columns)
+}
+
+/// Channels notified by the triggers of the tables the listener writes to.
+const WORK_AVAILABLE_CHANNEL: &str = "work_available";
+const ALLOWED_HANDLE_CHANNEL: &str = "event_allowed_handle";
+const PBS_COMPUTATIONS_CHANNEL: &str = "event_pbs_computations";
+
+/// Rows produced by the events of a block, written with multi-row inserts by
+/// `Database::flush_batch` instead of one statement per row.
+///
+/// Allow events repeated in a block for the same handle and account are
+/// coalesced into their first occurrence, which is the one the database keeps
+/// anyway.
+#[derive(Default)]
+pub struct InsertBatch {
+    computations: Vec<ComputationRow>,
+    allowed_handles: Vec<AllowedHandleRow>,
+    pbs_computations: Vec<PbsComputationRow>,
+    delegations: Vec<DelegationRow>,
+    allowed_keys: HashSet<(Vec<u8>, String)>,
+    pbs_keys: HashMap<Vec<u8>, usize>,
+    coalesced: usize,
+    dismissed: usize,
+    written: BTreeMap<&'static str, u64>,
+}
+
+impl InsertBatch {
+    pub fn len(&self) -> usize {
+        self.computations.len()
+            + self.allowed_handles.len()
+            + self.pbs_computations.len()
+            + self.delegations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Channels notified once the batch is flushed and committed, to be
+    /// published on a message bus other than Postgres.
+    pub fn notified_channels(&self) -> Vec<&'static str> {
+        [
+            (!self.computations.is_empty(), WORK_AVAILABLE_CHANNEL),
+            (!self.allowed_handles.is_empty(), ALLOWED_HANDLE_CHANNEL),
+            (!self.pbs_computations.is_empty(), PBS_COMPUTATIONS_CHANNEL),
+        ]
+        .into_iter()
+        .filter_map(|(notified, channel)| notified.then_some(channel))
+        .collect()
+    }
+
+    /// Number of rows dropped as duplicates since the batch was created.
+    pub fn coalesced(&self) -> usize {
+        self.coalesced
+    }
+
+    /// Number of delegation events dismissed when the batch was flushed, as
+    /// older than the applied ones.
+    pub fn dismissed(&self) -> usize {
+        self.dismissed
+    }
+
+    /// Rows inserted or updated per table when the batch was flushed, the
+    /// conflicting rows left as is excluded.
+    pub fn written_rows(&self) -> &BTreeMap<&'static str, u64> {
+        &self.written
+    }
+
+    fn count_written(&mut self, table: &'static str, rows: u64) {
+        *self.written.entry(table).or_default() += rows;
+    }
+
+    fn push_allowed_handle(&mut self, row: AllowedHandleRow) {
+        let key = (row.handle.clone(), row.account_address.clone());
+        if self.allowed_keys.insert(key) {
+            self.allowed_handles.push(row);
+        } else {
+            self.coalesced += 1;
+        }
+    }
+
+    /// A handle repeated in the block keeps its highest priority.
+    fn push_pbs_computation(&mut self, row: PbsComputationRow) {
+        match self.pbs_keys.get(&row.handle) {
+            Some(&index) => {
+                let first = &mut self.pbs_computations[index];
+                first.priority = first.priority.max(row.priority);
+                self.coalesced += 1;
+            }
+            None => {
+                self.pbs_keys
+                    .insert(row.handle.clone(), self.pbs_computations.len());
+                self.pbs_computations.push(row);
+            }
+        }
+    }
+}
+
 impl Database {
     pub async fn new(
         url: &str,
         coprocessor_api_key: &CoprocessorApiKey,
         bucket_cache_size: u16,
     ) -> Result<Self> {
-        let pool = Self::new_pool(url).await;
+        Self::new_in_schema(url, None, coprocessor_api_key, bucket_cache_size)
+            .await
+    }
+
+    /// Like [`Database::new`], with all the connections restricted to the
+    /// schema of the chain in the schema-per-chain layout.
+    pub async fn new_in_schema(
+        url: &str,
+        schema: Option<&str>,
+        coprocessor_api_key: &CoprocessorApiKey,
+        bucket_cache_size: u16,
+    ) -> Result<Self> {
+        let pool = Self::new_pool(url, schema).await;
         let (tenant_id, chain_id) =
             Self::find_tenant_id(&pool, coprocessor_api_key).await?;
         let bucket_cache = tokio::sync::RwLock::new(lru::LruCache::new(
@@ -106,17 +329,23 @@ impl Database {
         ));
         Ok(Database {
             url: url.into(),
+            schema: schema.map(Into::into),
             tenant_id,
             chain_id,
             pool: Arc::new(RwLock::new(pool)),
             bucket_cache,
             tick: HeartBeat::default(),
+            insert_batch_size: DEFAULT_INSERT_BATCH_SIZE,
+            column_encryption: ColumnEncryption::default(),
+            message_bus: None,
+            lazy_sns: false,
+            is_shadow: false,
         })
     }
 
-    async fn new_pool(url: &str) -> PgPool {
+    async fn new_pool(url: &str, schema: Option<&str>) -> PgPool {
         let options: PgConnectOptions = url.parse().expect("bad url");
-        let options = options.options([
+        let options = db_schema::with_schema(options, schema).options([
             ("statement_timeout", "10000"), // 5 seconds
         ]);
         let connect = || {
@@ -146,7 +375,8 @@ impl Database {
     pub async fn reconnect(&mut self) {
         tokio::time::sleep(RECONNECTION_DELAY).await;
         let old_pool = {
-            let new_pool = Self::new_pool(&self.url).await;
+            let new_pool =
+                Self::new_pool(&self.url, self.schema.as_deref()).await;
             let mut pool = self.pool.write().await;
             std::mem::replace(&mut *pool, new_pool)
         };
@@ -190,8 +420,7 @@ impl Database {
     #[allow(clippy::too_many_arguments)]
     async fn insert_computation_bytes(
         &self,
-        tx: &mut Transaction<'_>,
-        tenant_id: TenantId,
+        batch: &mut InsertBatch,
         result: &Handle,
         dependencies_handles: &[&Handle],
         dependencies_bytes: &[Vec<u8>], /* always added after
@@ -199,7 +428,7 @@ impl Database {
         fhe_operation: FheOperation,
         scalar_byte: &FixedBytes<1>,
         log: &LogTfhe,
-    ) -> Result<(), SqlxError> {
+    ) {
         let bucket = self
             .sort_computation_into_bucket(
                 result,
@@ -212,30 +441,26 @@ impl Database {
             .map(|d| d.to_vec())
             .collect::<Vec<_>>();
         let dependencies = [&dependencies_handles, dependencies_bytes].concat();
-        self.insert_computation_inner(
-            tx,
-            tenant_id,
+        Self::insert_computation_inner(
+            batch,
             result,
             dependencies,
             fhe_operation,
             scalar_byte,
             log,
             &bucket,
-        )
-        .await
+        );
     }
 
-    #[allow(clippy::too_many_arguments)]
     async fn insert_computation(
         &self,
-        tx: &mut Transaction<'_>,
-        tenant_id: TenantId,
+        batch: &mut InsertBatch,
         result: &Handle,
         dependencies: &[&Handle],
         fhe_operation: FheOperation,
         scalar_byte: &FixedBytes<1>,
         log: &LogTfhe,
-    ) -> Result<(), SqlxError> {
+    ) {
         let bucket = self
             .sort_computation_into_bucket(
                 result,
@@ -245,58 +470,264 @@ impl Database {
             .await;
         let dependencies =
             dependencies.iter().map(|d| d.to_vec()).collect::<Vec<_>>();
-        self.insert_computation_inner(
-            tx,
-            tenant_id,
+        Self::insert_computation_inner(
+            batch,
             result,
             dependencies,
             fhe_operation,
             scalar_byte,
             log,
             &bucket,
-        )
-        .await
+        );
     }
 
-    #[allow(clippy::too_many_arguments)]
-    async fn insert_computation_inner(
-        &self,
-        tx: &mut Transaction<'_>,
-        tenant_id: TenantId,
+    fn insert_computation_inner(
+        batch: &mut InsertBatch,
         result: &Handle,
         dependencies: Vec<Vec<u8>>,
         fhe_operation: FheOperation,
         scalar_byte: &FixedBytes<1>,
         log: &LogTfhe,
         bucket: &Handle,
+    ) {
+        batch.computations.push(ComputationRow {
+            output_handle: result.to_vec(),
+            dependencies,
+            fhe_operation: fhe_operation as i16,
+            is_scalar: !scalar_byte.is_zero(),
+            dependence_chain_id: bucket.to_vec(),
+            transaction_id: log.transaction_hash.map(|txh| txh.to_vec()),
+            is_allowed: log.is_allowed,
+            caller: tfhe_caller(&log.event.data).map(|caller| caller.to_vec()),
+        });
+    }
+
+    /// Writes the rows of the batch with multi-row inserts, at most
+    /// `insert_batch_size` rows per statement.
+    pub async fn flush_batch(
+        &self,
+        tx: &mut Transaction<'_>,
+        batch: &mut InsertBatch,
     ) -> Result<(), SqlxError> {
-        let is_scalar = !scalar_byte.is_zero();
-        let output_handle = result.to_vec();
-        let query = sqlx::query!(
-            r#"
-            INSERT INTO computations (
+        let tenant_id = self.tenant_id;
+        batch.allowed_keys.clear();
+        batch.pbs_keys.clear();
+
+        let computations = std::mem::take(&mut batch.computations);
+        for rows in computations.chunks(self.rows_per_insert(9)) {
+            let mut query = QueryBuilder::<Postgres>::new(
+                "INSERT INTO computations (tenant_id, output_handle, dependencies, fhe_operation, \
+                 is_scalar, dependence_chain_id, transaction_id, is_allowed, caller) ",
+            );
+            query.push_values(rows, |mut values, row| {
+                values
+                    .push_bind(tenant_id)
+                    .push_bind(&row.output_handle)
+                    .push_bind(&row.dependencies)
+                    .push_bind(row.fhe_operation)
+                    .push_bind(row.is_scalar)
+                    .push_bind(&row.dependence_chain_id)
+                    .push_bind(&row.transaction_id)
+                    .push_bind(row.is_allowed)
+                    .push_bind(&row.caller);
+            });
+            query.push(
+                " ON CONFLICT (tenant_id, output_handle, transaction_id) DO NOTHING",
+            );
+            let written = query.build().execute(tx.deref_mut()).await?;
+            batch.count_written("computations", written.rows_affected());
+        }
+
+        let allowed_handles = std::mem::take(&mut batch.allowed_handles);
+        for rows in allowed_handles.chunks(self.rows_per_insert(5)) {
+            let mut query = QueryBuilder::<Postgres>::new(
+                "INSERT INTO allowed_handles(tenant_id, handle, account_address, event_type, transaction_id) ",
+            );
+            query.push_values(rows, |mut values, row| {
+                values
+                    .push_bind(tenant_id)
+                    .push_bind(&row.handle)
+                    .push_bind(&row.account_address)
+                    .push_bind(row.event_type)
+                    .push_bind(&row.transaction_id);
+            });
+            query.push(" ON CONFLICT DO NOTHING");
+            let written = query.build().execute(tx.deref_mut()).await?;
+            batch.count_written("allowed_handles", written.rows_affected());
+        }
+
+        let pbs_computations = std::mem::take(&mut batch.pbs_computations);
+        for rows in pbs_computations.chunks(self.rows_per_insert(4)) {
+            let mut query = QueryBuilder::<Postgres>::new(
+                "INSERT INTO pbs_computations(tenant_id, handle, transaction_id, priority) ",
+            );
+            query.push_values(rows, |mut values, row| {
+                values
+                    .push_bind(tenant_id)
+                    .push_bind(&row.handle)
+                    .push_bind(&row.transaction_id)
+                    .push_bind(row.priority);
+            });
+            // A handle allowed for decryption after being allowed to an
+            // account is raised if still pending
+            query.push(
+                " ON CONFLICT (tenant_id, handle) DO UPDATE \
+                 SET priority = EXCLUDED.priority \
+                 WHERE pbs_computations.priority < EXCLUDED.priority \
+                 AND pbs_computations.is_completed = FALSE",
+            );
+            let written = query.build().execute(tx.deref_mut()).await?;
+            batch.count_written("pbs_computations", written.rows_affected());
+        }
+
+        let mut delegations = std::mem::take(&mut batch.delegations);
+        for row in &mut delegations {
+            row.delegator =
+                self.column_encryption.encrypt_lookup(&row.delegator);
+            row.delegate = self.column_encryption.encrypt_lookup(&row.delegate);
+        }
+        if !delegations.is_empty() {
+            batch.dismissed +=
+                self.record_delegation_history(tx, &delegations).await?;
+        }
+
+        // A statement cannot update the same row twice, only the latest event
+        // of each delegation is kept
+        delegations.sort_by(|a, b| {
+            (&a.delegator, &a.delegate, &a.contract_address)
+                .cmp(&(&b.delegator, &b.delegate, &b.contract_address))
+                .then(b.delegation_counter.cmp(&a.delegation_counter))
+        });
+        delegations.dedup_by(|a, b| {
+            (&a.delegator, &a.delegate, &a.contract_address)
+                == (&b.delegator, &b.delegate, &b.contract_address)
+        });
+        for rows in delegations.chunks(self.rows_per_insert(8)) {
+            let mut query = QueryBuilder::<Postgres>::new(
+                "INSERT INTO user_decryption_delegations(tenant_id, delegator, delegate, contract_address, \
+                 delegation_counter, expiry_date, block_number, transaction_id) ",
+            );
+            query.push_values(rows, |mut values, row| {
+                values
+                    .push_bind(tenant_id)
+                    .push_bind(&row.delegator)
+                    .push_bind(&row.delegate)
+                    .push_bind(&row.contract_address)
+                    .push_bind(row.delegation_counter)
+                    .push_bind(row.expiry_date)
+                    .push_bind(row.block_number)
+                    .push_bind(&row.transaction_id);
+            });
+            // Events of a reorged or replayed block must not roll back a
+            // newer delegation
+            query.push(
+                " ON CONFLICT (tenant_id, delegator, delegate, contract_address) DO UPDATE SET \
+                 delegation_counter = EXCLUDED.delegation_counter, \
+                 expiry_date = EXCLUDED.expiry_date, \
+                 block_number = EXCLUDED.block_number, \
+                 transaction_id = EXCLUDED.transaction_id, \
+                 updated_at = NOW() \
+                 WHERE user_decryption_delegations.delegation_counter < EXCLUDED.delegation_counter",
+            );
+            let written = query.build().execute(tx.deref_mut()).await?;
+            batch.count_written(
+                "user_decryption_delegations",
+                written.rows_affected(),
+            );
+        }
+
+        if !delegations.is_empty() {
+            // The events replaced by the delegations just applied
+            let mut delegators = Vec::with_capacity(delegations.len());
+            let mut delegates = Vec::with_capacity(delegations.len());
+            let mut contract_addresses = Vec::with_capacity(delegations.len());
+            for row in &delegations {
+                delegators.push(row.delegator.clone());
+                delegates.push(row.delegate.clone());
+                contract_addresses.push(row.contract_address.clone());
+            }
+            sqlx::query!(
+                r#"
+                UPDATE user_decryption_delegation_history h
+                SET status = 'superseded', updated_at = NOW()
+                FROM user_decryption_delegations d,
+                    UNNEST($2::TEXT[], $3::TEXT[], $4::TEXT[])
+                        AS k(delegator, delegate, contract_address)
+                WHERE h.tenant_id = $1
+                AND h.delegator = k.delegator
+                AND h.delegate = k.delegate
+                AND h.contract_address = k.contract_address
+                AND h.status = 'applied'
+                AND d.tenant_id = h.tenant_id
+                AND d.delegator = h.delegator
+                AND d.delegate = h.delegate
+                AND d.contract_address = h.contract_address
+                AND h.delegation_counter < d.delegation_counter
+                "#,
                 tenant_id,
-                output_handle,
-                dependencies,
-                fhe_operation,
-                is_scalar,
-                dependence_chain_id,
-                transaction_id,
-                is_allowed
+                &delegators,
+                &delegates,
+                &contract_addresses,
             )
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-            ON CONFLICT (tenant_id, output_handle, transaction_id) DO NOTHING
-            "#,
-            tenant_id as i32,
-            output_handle,
-            &dependencies,
-            fhe_operation as i16,
-            is_scalar,
-            bucket.to_vec(),
-            log.transaction_hash.map(|txh| txh.to_vec()),
-            log.is_allowed,
-        );
-        query.execute(tx.deref_mut()).await.map(|_| ())
+            .execute(tx.deref_mut())
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Records every delegation event in the history, dismissed if the
+    /// delegation already has a newer state, before it is applied. Returns the
+    /// number of dismissed events.
+    async fn record_delegation_history(
+        &self,
+        tx: &mut Transaction<'_>,
+        delegations: &[DelegationRow],
+    ) -> Result<usize, SqlxError> {
+        let mut dismissed = 0;
+        for rows in delegations.chunks(self.rows_per_insert(9)) {
+            let mut query = QueryBuilder::<Postgres>::new(
+                "INSERT INTO user_decryption_delegation_history(tenant_id, delegator, delegate, \
+                 contract_address, delegation_counter, expiry_date, old_expiry_date, block_number, \
+                 transaction_id, status) \
+                 SELECT e.*, CASE WHEN d.delegation_counter > e.delegation_counter \
+                 THEN 'dismissed' ELSE 'applied' END \
+                 FROM (",
+            );
+            query.push_values(rows, |mut values, row| {
+                values
+                    .push_bind(self.tenant_id)
+                    .push_bind(&row.delegator)
+                    .push_bind(&row.delegate)
+                    .push_bind(&row.contract_address)
+                    .push_bind(row.delegation_counter)
+                    .push_bind(row.expiry_date)
+                    .push_bind(row.old_expiry_date)
+                    .push_bind(row.block_number)
+                    .push_bind(&row.transaction_id);
+            });
+            query.push(
+                ") AS e(tenant_id, delegator, delegate, contract_address, \
+                 delegation_counter, expiry_date, old_expiry_date, block_number, \
+                 transaction_id) \
+                 LEFT JOIN user_decryption_delegations d \
+                 ON d.tenant_id = e.tenant_id AND d.delegator = e.delegator \
+                 AND d.delegate = e.delegate \
+                 AND d.contract_address = e.contract_address \
+                 ON CONFLICT DO NOTHING \
+                 RETURNING status",
+            );
+            let statuses: Vec<String> =
+                query.build_query_scalar().fetch_all(tx.deref_mut()).await?;
+            dismissed += statuses
+                .iter()
+                .filter(|status| status.as_str() == "dismissed")
+                .count();
+        }
+        Ok(dismissed)
+    }
+
+    fn rows_per_insert(&self, columns: usize) -> usize {
+        rows_per_insert(self.insert_batch_size, columns)
     }
 
     async fn sort_computation_into_bucket(
@@ -347,12 +778,23 @@ impl Database {
         *output
     }
 
-    #[rustfmt::skip]
     pub async fn insert_tfhe_event(
         &self,
         tx: &mut Transaction<'_>,
         log: &LogTfhe,
     ) -> Result<(), SqlxError> {
+        let mut batch = InsertBatch::default();
+        self.push_tfhe_event(&mut batch, log).await;
+        self.flush_batch(tx, &mut batch).await
+    }
+
+    /// Adds the rows of a TFHE event to the batch, see `flush_batch`.
+    #[rustfmt::skip]
+    pub async fn push_tfhe_event(
+        &self,
+        batch: &mut InsertBatch,
+        log: &LogTfhe,
+    ) {
         use TfheContract as C;
         use TfheContractEvents as E;
         const HAS_SCALAR : FixedBytes::<1> = FixedBytes([1]); // if any dependency is a scalar.
@@ -361,13 +803,12 @@ impl Database {
         let event = &log.event;
         let ty = |to_type: &ToType| vec![*to_type];
         let as_bytes = |x: &ClearConst| x.to_be_bytes_vec();
-        let tenant_id = self.tenant_id;
         let fhe_operation = event_to_op_int(event);
-        let insert_computation = |tx, result, dependencies, scalar_byte| {
-            self.insert_computation(tx, tenant_id, result, dependencies, fhe_operation, scalar_byte, log)
+        let insert_computation = |batch, result, dependencies, scalar_byte| {
+            self.insert_computation(batch, result, dependencies, fhe_operation, scalar_byte, log)
         };
-        let insert_computation_bytes = |tx, result, dependencies_handles, dependencies_bytes, scalar_byte| {
-            self.insert_computation_bytes(tx, tenant_id, result, dependencies_handles, dependencies_bytes, fhe_operation, scalar_byte, log)
+        let insert_computation_bytes = |batch, result, dependencies_handles, dependencies_bytes, scalar_byte| {
+            self.insert_computation_bytes(batch, result, dependencies_handles, dependencies_bytes, fhe_operation, scalar_byte, log)
         };
 
         let _t = telemetry::tracer(
@@ -390,7 +831,7 @@ impl Database {
 
         match &event.data {
             E::Cast(C::Cast {ct, toType, result, ..})
-            => insert_computation_bytes(tx, result, &[ct], &[ty(toType)], &HAS_SCALAR).await,
+            => insert_computation_bytes(batch, result, &[ct], &[ty(toType)], &HAS_SCALAR).await,
 
             E::FheAdd(C::FheAdd {lhs, rhs, scalarByte, result, ..})
             | E::FheBitAnd(C::FheBitAnd {lhs, rhs, scalarByte, result, ..})
@@ -406,10 +847,10 @@ impl Database {
             | E::FheShl(C::FheShl {lhs, rhs, scalarByte, result, ..})
             | E::FheShr(C::FheShr {lhs, rhs, scalarByte, result, ..})
             | E::FheSub(C::FheSub {lhs, rhs, scalarByte, result, ..})
-            => insert_computation(tx, result, &[lhs, rhs], scalarByte).await,
+            => insert_computation(batch, result, &[lhs, rhs], scalarByte).await,
 
             E::FheIfThenElse(C::FheIfThenElse {control, ifTrue, ifFalse, result, ..})
-            => insert_computation(tx, result, &[control, ifTrue, ifFalse], &NO_SCALAR).await,
+            => insert_computation(batch, result, &[control, ifTrue, ifFalse], &NO_SCALAR).await,
 
             | E::FheEq(C::FheEq {lhs, rhs, scalarByte, result, ..})
             | E::FheGe(C::FheGe {lhs, rhs, scalarByte, result, ..})
@@ -417,26 +858,26 @@ impl Database {
             | E::FheLe(C::FheLe {lhs, rhs, scalarByte, result, ..})
             | E::FheLt(C::FheLt {lhs, rhs, scalarByte, result, ..})
             | E::FheNe(C::FheNe {lhs, rhs, scalarByte, result, ..})
-            => insert_computation(tx, result, &[lhs, rhs], scalarByte).await,
+            => insert_computation(batch, result, &[lhs, rhs], scalarByte).await,
 
 
             E::FheNeg(C::FheNeg {ct, result, ..})
             | E::FheNot(C::FheNot {ct, result, ..})
-            => insert_computation(tx, result, &[ct], &NO_SCALAR).await,
+            => insert_computation(batch, result, &[ct], &NO_SCALAR).await,
 
             | E::FheRand(C::FheRand {randType, seed, result, ..})
-            => insert_computation_bytes(tx, result, &[], &[seed.to_vec(), ty(randType)], &HAS_SCALAR).await,
+            => insert_computation_bytes(batch, result, &[], &[seed.to_vec(), ty(randType)], &HAS_SCALAR).await,
 
             | E::FheRandBounded(C::FheRandBounded {upperBound, randType, seed, result, ..})
-            => insert_computation_bytes(tx, result, &[], &[seed.to_vec(), as_bytes(upperBound), ty(randType)], &HAS_SCALAR).await,
+            => insert_computation_bytes(batch, result, &[], &[seed.to_vec(), as_bytes(upperBound), ty(randType)], &HAS_SCALAR).await,
 
             | E::TrivialEncrypt(C::TrivialEncrypt {pt, toType, result, ..})
-            => insert_computation_bytes(tx, result, &[], &[as_bytes(pt), ty(toType)], &HAS_SCALAR).await,
+            => insert_computation_bytes(batch, result, &[], &[as_bytes(pt), ty(toType)], &HAS_SCALAR).await,
 
             | E::Initialized(_)
             | E::Upgraded(_)
             | E::VerifyInput(_)
-            => Ok(()),
+            => (),
         }
     }
 
@@ -460,6 +901,72 @@ impl Database {
         Ok(())
     }
 
+    /// Makes the delegations of the blocks up to `block_delay` blocks before
+    /// this one effective for the user decryptions.
+    pub async fn confirm_delegations(
+        &self,
+        tx: &mut Transaction<'_>,
+        block_summary: &BlockSummary,
+        block_delay: u64,
+    ) -> Result<(), SqlxError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO host_chain_delegation_confirmations
+                (chain_id, block_delay, confirmed_block_number)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (chain_id) DO UPDATE SET
+                block_delay = EXCLUDED.block_delay,
+                confirmed_block_number = EXCLUDED.confirmed_block_number,
+                updated_at = NOW();
+            "#,
+            self.chain_id as i64,
+            block_delay as i64,
+            block_summary.number.saturating_sub(block_delay) as i64,
+        )
+        .execute(tx.deref_mut())
+        .await?;
+        Ok(())
+    }
+
+    /// Stores logs as is, to be decoded later, with whether they were
+    /// decoded when captured.
+    pub async fn insert_raw_events(
+        &self,
+        tx: &mut Transaction<'_>,
+        block_summary: &BlockSummary,
+        logs: &[(&RpcLog, bool)],
+    ) -> Result<(), SqlxError> {
+        for rows in logs.chunks(self.rows_per_insert(9)) {
+            let mut query = QueryBuilder::<Postgres>::new(
+                "INSERT INTO raw_events(tenant_id, block_hash, log_index, block_number, \
+                 transaction_hash, contract_address, topics, data, is_decoded) ",
+            );
+            query.push_values(rows, |mut values, (log, is_decoded)| {
+                values
+                    .push_bind(self.tenant_id)
+                    .push_bind(block_summary.hash.to_vec())
+                    .push_bind(log.log_index.unwrap_or_default() as i64)
+                    .push_bind(block_summary.number as i64)
+                    .push_bind(log.transaction_hash.map(|h| h.to_vec()))
+                    .push_bind(log.inner.address.to_string())
+                    .push_bind(
+                        log.inner
+                            .topics()
+                            .iter()
+                            .map(|topic| topic.to_vec())
+                            .collect::<Vec<_>>(),
+                    )
+                    .push_bind(log.inner.data.data.to_vec())
+                    .push_bind(*is_decoded);
+            });
+            query.push(
+                " ON CONFLICT (tenant_id, block_hash, log_index) DO NOTHING",
+            );
+            query.build().execute(tx.deref_mut()).await?;
+        }
+        Ok(())
+    }
+
     pub async fn read_last_valid_block(&mut self) -> Option<i64> {
         let query = sqlx::query!(
             r#"
@@ -474,6 +981,36 @@ impl Database {
         }
     }
 
+    /// Deletes up to `batch_size` delegation events that were superseded or
+    /// dismissed more than `retention` ago, and returns how many were deleted.
+    /// The applied events, the current state of the delegations, are kept.
+    pub async fn purge_delegation_history(
+        pool: &PgPool,
+        tenant_id: TenantId,
+        retention: Duration,
+        batch_size: u32,
+    ) -> Result<u64, SqlxError> {
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM user_decryption_delegation_history
+            WHERE (tenant_id, delegator, delegate, contract_address, delegation_counter) IN (
+                SELECT tenant_id, delegator, delegate, contract_address, delegation_counter
+                FROM user_decryption_delegation_history
+                WHERE tenant_id = $1
+                AND status <> 'applied'
+                AND updated_at < NOW() - make_interval(secs => $2)
+                LIMIT $3
+            )
+            "#,
+            tenant_id,
+            retention.as_secs_f64(),
+            batch_size as i64,
+        )
+        .execute(pool)
+        .await?;
+        Ok(res.rows_affected())
+    }
+
     /// Handles all types of ACL events
     pub async fn handle_acl_event(
         &self,
@@ -482,6 +1019,20 @@ impl Database {
         transaction_hash: &Option<Handle>,
         block_number: &Option<u64>,
     ) -> Result<(), SqlxError> {
+        let mut batch = InsertBatch::default();
+        self.push_acl_event(&mut batch, event, transaction_hash, block_number)
+            .await;
+        self.flush_batch(tx, &mut batch).await
+    }
+
+    /// Adds the rows of an ACL event to the batch, see `flush_batch`.
+    pub async fn push_acl_event(
+        &self,
+        batch: &mut InsertBatch,
+        event: &Log<AclContractEvents>,
+        transaction_hash: &Option<Handle>,
+        block_number: &Option<u64>,
+    ) {
         let data = &event.data;
 
         let transaction_hash = transaction_hash.map(|h| h.to_vec());
@@ -502,21 +1053,19 @@ impl Database {
             AclContractEvents::Allowed(allowed) => {
                 let handle = allowed.handle.to_vec();
 
-                self.insert_allowed_handle(
-                    tx,
-                    handle.clone(),
-                    allowed.account.to_string(),
-                    AllowEvents::AllowedAccount,
-                    transaction_hash.clone(),
-                )
-                .await?;
-
-                self.insert_pbs_computations(
-                    tx,
-                    &vec![handle],
-                    transaction_hash,
-                )
-                .await?;
+                batch.push_allowed_handle(AllowedHandleRow {
+                    handle: handle.clone(),
+                    account_address: allowed.account.to_string(),
+                    event_type: AllowEvents::AllowedAccount as i16,
+                    transaction_id: transaction_hash.clone(),
+                });
+                if !self.lazy_sns {
+                    batch.push_pbs_computation(PbsComputationRow {
+                        handle,
+                        transaction_id: transaction_hash,
+                        priority: 0,
+                    });
+                }
             }
             AclContractEvents::AllowedForDecryption(allowed_for_decryption) => {
                 let handles = allowed_for_decryption
@@ -525,37 +1074,49 @@ impl Database {
                     .map(|h| h.to_vec())
                     .collect::<Vec<_>>();
 
-                for handle in handles.clone() {
+                for handle in handles {
                     info!(
                         handle = compact_hex(&handle),
                         "Allowed for public decryption"
                     );
 
-                    self.insert_allowed_handle(
-                        tx,
+                    batch.push_allowed_handle(AllowedHandleRow {
+                        handle: handle.clone(),
+                        account_address: "".to_string(),
+                        event_type: AllowEvents::AllowedForDecryption as i16,
+                        transaction_id: transaction_hash.clone(),
+                    });
+                    // Prefetched in lazy mode, the public decryptions not
+                    // being known to the coprocessor
+                    batch.push_pbs_computation(PbsComputationRow {
                         handle,
-                        "".to_string(),
-                        AllowEvents::AllowedForDecryption,
-                        transaction_hash.clone(),
-                    )
-                    .await?;
+                        transaction_id: transaction_hash.clone(),
+                        priority: PUBLIC_DECRYPTION_PRIORITY,
+                    });
                 }
-
-                self.insert_pbs_computations(
-                    tx,
-                    &handles,
-                    transaction_hash.clone(),
-                )
-                .await?;
             }
             AclContractEvents::Initialized(initialized) => {
                 warn!(event = ?initialized, "unhandled Acl::Initialized event");
             }
-            AclContractEvents::DelegatedAccount(delegate_account) => {
-                warn!(
-                    event = ?delegate_account,
-                    "unhandled Acl::DelegatedAccount event"
+            AclContractEvents::DelegatedAccount(delegated_account) => {
+                info!(
+                    delegator = %delegated_account.delegator,
+                    delegate = %delegated_account.delegate,
+                    contract_address = %delegated_account.contractAddress,
+                    expiry_date = delegated_account.newExpiryDate,
+                    "Delegated account"
                 );
+                match DelegationRow::delegated(
+                    delegated_account,
+                    block_number,
+                    transaction_hash,
+                ) {
+                    Some(row) => batch.delegations.push(row),
+                    None => error!(
+                        event = ?delegated_account,
+                        "Delegation counter out of range, event ignored"
+                    ),
+                }
             }
             AclContractEvents::OwnershipTransferStarted(
                 ownership_transfer_started,
@@ -566,10 +1127,23 @@ impl Database {
                 );
             }
             AclContractEvents::RevokedDelegation(revoked_delegation) => {
-                warn!(
-                    event = ?revoked_delegation,
-                    "unhandled Acl::RevokedDelegation event"
+                info!(
+                    delegator = %revoked_delegation.delegator,
+                    delegate = %revoked_delegation.delegate,
+                    contract_address = %revoked_delegation.contractAddress,
+                    "Revoked delegation"
                 );
+                match DelegationRow::revoked(
+                    revoked_delegation,
+                    block_number,
+                    transaction_hash,
+                ) {
+                    Some(row) => batch.delegations.push(row),
+                    None => error!(
+                        event = ?revoked_delegation,
+                        "Delegation counter out of range, event ignored"
+                    ),
+                }
             }
             AclContractEvents::OwnershipTransferred(ownership_transferred) => {
                 warn!(
@@ -597,52 +1171,6 @@ impl Database {
             }
         }
         self.tick.update();
-        Ok(())
-    }
-
-    /// Adds handles to the pbs_computations table and alerts the SnS worker
-    /// about new of PBS work.
-    pub async fn insert_pbs_computations(
-        &self,
-        tx: &mut Transaction<'_>,
-        handles: &Vec<Vec<u8>>,
-        transaction_id: Option<Vec<u8>>,
-    ) -> Result<(), SqlxError> {
-        let tenant_id = self.tenant_id;
-        for handle in handles {
-            let query = sqlx::query!(
-                "INSERT INTO pbs_computations(tenant_id, handle, transaction_id) VALUES($1, $2, $3)
-                 ON CONFLICT DO NOTHING;",
-                tenant_id,
-                handle,
-                transaction_id
-            );
-            query.execute(tx.deref_mut()).await?;
-        }
-        Ok(())
-    }
-
-    /// Add the handle to the allowed_handles table
-    pub async fn insert_allowed_handle(
-        &self,
-        tx: &mut Transaction<'_>,
-        handle: Vec<u8>,
-        account_address: String,
-        event_type: AllowEvents,
-        transaction_id: Option<Vec<u8>>,
-    ) -> Result<(), SqlxError> {
-        let tenant_id = self.tenant_id;
-        let query = sqlx::query!(
-            "INSERT INTO allowed_handles(tenant_id, handle, account_address, event_type, transaction_id) VALUES($1, $2, $3, $4, $5)
-                    ON CONFLICT DO NOTHING;",
-            tenant_id,
-            handle,
-            account_address,
-            event_type as i16,
-            transaction_id
-        );
-        query.execute(tx.deref_mut()).await?;
-        Ok(())
     }
 
     async fn record_transaction_begin(
@@ -663,7 +1191,7 @@ impl Database {
     }
 }
 
-fn event_to_op_int(op: &TfheContractEvents) -> FheOperation {
+pub fn event_to_op_int(op: &TfheContractEvents) -> FheOperation {
     use SupportedFheOperations as O;
     use TfheContractEvents as E;
     match op {
@@ -735,6 +1263,22 @@ pub fn event_name(op: &TfheContractEvents) -> &'static str {
     }
 }
 
+pub fn acl_event_name(event: &AclContractEvents) -> &'static str {
+    use AclContractEvents as E;
+    match event {
+        E::Allowed(_) => "Allowed",
+        E::AllowedForDecryption(_) => "AllowedForDecryption",
+        E::DelegatedAccount(_) => "DelegatedAccount",
+        E::RevokedDelegation(_) => "RevokedDelegation",
+        E::Initialized(_) => "Initialized",
+        E::OwnershipTransferStarted(_) => "OwnershipTransferStarted",
+        E::OwnershipTransferred(_) => "OwnershipTransferred",
+        E::Upgraded(_) => "Upgraded",
+        E::Paused(_) => "Paused",
+        E::Unpaused(_) => "Unpaused",
+    }
+}
+
 pub fn tfhe_result_handle(op: &TfheContractEvents) -> Option<Handle> {
     use TfheContract as C;
     use TfheContractEvents as E;
@@ -771,6 +1315,43 @@ pub fn tfhe_result_handle(op: &TfheContractEvents) -> Option<Handle> {
     }
 }
 
+/// Contract that requested the operation
+pub fn tfhe_caller(op: &TfheContractEvents) -> Option<Address> {
+    use TfheContract as C;
+    use TfheContractEvents as E;
+    match op {
+        E::Cast(C::Cast { caller, .. })
+        | E::FheAdd(C::FheAdd { caller, .. })
+        | E::FheBitAnd(C::FheBitAnd { caller, .. })
+        | E::FheBitOr(C::FheBitOr { caller, .. })
+        | E::FheBitXor(C::FheBitXor { caller, .. })
+        | E::FheDiv(C::FheDiv { caller, .. })
+        | E::FheMax(C::FheMax { caller, .. })
+        | E::FheMin(C::FheMin { caller, .. })
+        | E::FheMul(C::FheMul { caller, .. })
+        | E::FheRem(C::FheRem { caller, .. })
+        | E::FheRotl(C::FheRotl { caller, .. })
+        | E::FheRotr(C::FheRotr { caller, .. })
+        | E::FheShl(C::FheShl { caller, .. })
+        | E::FheShr(C::FheShr { caller, .. })
+        | E::FheSub(C::FheSub { caller, .. })
+        | E::FheIfThenElse(C::FheIfThenElse { caller, .. })
+        | E::FheEq(C::FheEq { caller, .. })
+        | E::FheGe(C::FheGe { caller, .. })
+        | E::FheGt(C::FheGt { caller, .. })
+        | E::FheLe(C::FheLe { caller, .. })
+        | E::FheLt(C::FheLt { caller, .. })
+        | E::FheNe(C::FheNe { caller, .. })
+        | E::FheNeg(C::FheNeg { caller, .. })
+        | E::FheNot(C::FheNot { caller, .. })
+        | E::FheRand(C::FheRand { caller, .. })
+        | E::FheRandBounded(C::FheRandBounded { caller, .. })
+        | E::TrivialEncrypt(C::TrivialEncrypt { caller, .. }) => Some(*caller),
+
+        E::Initialized(_) | E::Upgraded(_) | E::VerifyInput(_) => None,
+    }
+}
+
 pub fn acl_result_handles(event: &Log<AclContractEvents>) -> Vec<Handle> {
     let data = &event.data;
     match data {
@@ -788,3 +1369,137 @@ pub fn acl_result_handles(event: &Log<AclContractEvents>) -> Vec<Handle> {
         | AclContractEvents::Unpaused(_) => vec![],
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed_handle(handle: u8, account: &str) -> AllowedHandleRow {
+        AllowedHandleRow {
+            handle: vec![handle; 32],
+            account_address: account.to_string(),
+            event_type: 0,
+            transaction_id: None,
+        }
+    }
+
+    fn pbs_computation(handle: u8, priority: i16) -> PbsComputationRow {
+        PbsComputationRow {
+            handle: vec![handle; 32],
+            transaction_id: None,
+            priority,
+        }
+    }
+
+    #[test]
+    fn test_rows_per_insert() {
+        assert_eq!(rows_per_insert(DEFAULT_INSERT_BATCH_SIZE, 9), 1000);
+        assert_eq!(rows_per_insert(3, 9), 3);
+        // At least one row per statement
+        assert_eq!(rows_per_insert(0, 9), 1);
+        // Within the bind parameters limit
+        assert_eq!(rows_per_insert(100_000, 9), MAX_BIND_PARAMETERS / 9);
+        assert_eq!(rows_per_insert(100_000, 4), MAX_BIND_PARAMETERS / 4);
+        assert!(rows_per_insert(usize::MAX, 9) * 9 <= MAX_BIND_PARAMETERS);
+    }
+
+    #[test]
+    fn test_chunks_cover_all_rows() {
+        for (rows, batch_size) in [(0, 3), (2, 3), (3, 3), (10, 3), (7, 0)] {
+            let rows: Vec<usize> = (0..rows).collect();
+            let chunks: Vec<&[usize]> =
+                rows.chunks(rows_per_insert(batch_size, 5)).collect();
+            assert!(chunks
+                .iter()
+                .all(|chunk| chunk.len() <= batch_size.max(1)));
+            assert_eq!(chunks.concat(), rows);
+        }
+    }
+
+    #[test]
+    fn test_repeated_allowed_handles_are_coalesced() {
+        let mut batch = InsertBatch::default();
+        assert!(batch.is_empty());
+        batch.push_allowed_handle(allowed_handle(1, "0xa"));
+        batch.push_allowed_handle(allowed_handle(1, "0xb"));
+        batch.push_allowed_handle(allowed_handle(1, "0xa"));
+        batch.push_allowed_handle(allowed_handle(2, "0xa"));
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch.coalesced(), 1);
+        assert_eq!(batch.notified_channels(), vec![ALLOWED_HANDLE_CHANNEL]);
+    }
+
+    #[test]
+    fn test_repeated_pbs_computations_keep_highest_priority() {
+        let mut batch = InsertBatch::default();
+        batch.push_pbs_computation(pbs_computation(1, 0));
+        batch.push_pbs_computation(pbs_computation(2, 0));
+        batch.push_pbs_computation(pbs_computation(
+            1,
+            PUBLIC_DECRYPTION_PRIORITY,
+        ));
+        batch.push_pbs_computation(pbs_computation(1, 0));
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.coalesced(), 2);
+        let priorities: Vec<i16> = batch
+            .pbs_computations
+            .iter()
+            .map(|row| row.priority)
+            .collect();
+        assert_eq!(priorities, vec![PUBLIC_DECRYPTION_PRIORITY, 0]);
+        assert_eq!(batch.notified_channels(), vec![PBS_COMPUTATIONS_CHANNEL]);
+    }
+
+    #[test]
+    fn test_delegation_rows() {
+        let delegated = AclContract::DelegatedAccount {
+            delegator: Address::with_last_byte(1),
+            delegate: Address::with_last_byte(2),
+            contractAddress: Address::with_last_byte(3),
+            delegationCounter: 7,
+            oldExpiryDate: 0,
+            newExpiryDate: u64::MAX,
+        };
+        let row =
+            DelegationRow::delegated(&delegated, &Some(10), None).unwrap();
+        assert_eq!(row.delegation_counter, 7);
+        // Never expiring
+        assert_eq!(row.expiry_date, i64::MAX);
+        assert_eq!(row.old_expiry_date, None);
+        assert_eq!(row.block_number, Some(10));
+
+        let revoked = AclContract::RevokedDelegation {
+            delegator: Address::with_last_byte(1),
+            delegate: Address::with_last_byte(2),
+            contractAddress: Address::with_last_byte(3),
+            delegationCounter: 8,
+            oldExpiryDate: 1000,
+        };
+        let row = DelegationRow::revoked(&revoked, &None, None).unwrap();
+        assert_eq!(row.delegation_counter, 8);
+        assert_eq!(row.expiry_date, 0);
+        assert_eq!(row.old_expiry_date, Some(1000));
+        assert_eq!(row.block_number, None);
+    }
+
+    #[test]
+    fn test_delegation_counter_out_of_range_is_ignored() {
+        let delegated = AclContract::DelegatedAccount {
+            delegator: Address::with_last_byte(1),
+            delegate: Address::with_last_byte(2),
+            contractAddress: Address::with_last_byte(3),
+            delegationCounter: u64::MAX,
+            oldExpiryDate: 0,
+            newExpiryDate: 1000,
+        };
+        assert!(DelegationRow::delegated(&delegated, &None, None).is_none());
+        let revoked = AclContract::RevokedDelegation {
+            delegator: Address::with_last_byte(1),
+            delegate: Address::with_last_byte(2),
+            contractAddress: Address::with_last_byte(3),
+            delegationCounter: i64::MAX as u64 + 1,
+            oldExpiryDate: 1000,
+        };
+        assert!(DelegationRow::revoked(&revoked, &None, None).is_none());
+    }
+}
@@ -2,3 +2,5 @@ pub mod cmd;
 pub mod contracts;
 pub mod database;
 pub mod health_check;
+pub mod metrics;
+pub mod raw_events;
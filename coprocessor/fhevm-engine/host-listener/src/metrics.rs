@@ -0,0 +1,137 @@
+//! Event ingestion metrics, per chain, contract and event type.
+//!
+//! A contract whose events stop arriving shows as a flat decoded counter, one
+//! whose events change shape as a growing failed counter.
+
+use std::sync::LazyLock;
+
+use alloy::primitives::Address;
+use alloy::rpc::types::Log;
+use prometheus::{
+    register_int_counter_vec, register_int_gauge_vec, IntCounterVec,
+    IntGaugeVec,
+};
+
+const LABELS: &[&str] = &["chain_id", "contract", "event_type"];
+
+static DECODED_EVENTS_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_host_listener_decoded_events",
+        "Number of events decoded from the ACL and TFHE contracts",
+        LABELS
+    )
+    .unwrap()
+});
+
+static SKIPPED_EVENTS_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_host_listener_skipped_events",
+        "Number of events of other contracts, not decoded",
+        LABELS
+    )
+    .unwrap()
+});
+
+static FAILED_EVENTS_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_host_listener_failed_events",
+        "Number of events of the ACL and TFHE contracts that cannot be decoded",
+        LABELS
+    )
+    .unwrap()
+});
+
+static PRUNED_ROWS_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_host_listener_pruned_rows",
+        "Number of rows deleted once older than the retention of their table",
+        &["table"]
+    )
+    .unwrap()
+});
+
+static SHADOW_BLOCKS_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_host_listener_shadow_blocks",
+        "Number of blocks written to the shadow database, by outcome: consistent, diverging or failed",
+        &["chain_id", "outcome"]
+    )
+    .unwrap()
+});
+
+static SHADOW_DIVERGING_ROWS_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(
+    || {
+        register_int_counter_vec!(
+        "coprocessor_host_listener_shadow_diverging_rows",
+        "Difference between the rows written to the database and to the shadow database, per table",
+        &["chain_id", "table"]
+    )
+    .unwrap()
+    },
+);
+
+static DELEGATION_BLOCK_DELAY_GAUGE: LazyLock<IntGaugeVec> = LazyLock::new(
+    || {
+        register_int_gauge_vec!(
+        "coprocessor_host_listener_delegation_block_delay",
+        "Blocks before a delegation is effective, adapted to the observed reorgs",
+        &["chain_id"]
+    )
+    .unwrap()
+    },
+);
+
+pub fn observe_decoded(chain_id: u64, contract: &Address, event_type: &str) {
+    DECODED_EVENTS_COUNTER
+        .with_label_values(&[
+            &chain_id.to_string(),
+            &contract.to_string(),
+            event_type,
+        ])
+        .inc();
+}
+
+/// Events that are not decoded are labeled with their signature hash.
+pub fn observe_skipped(chain_id: u64, log: &Log) {
+    observe_undecoded(&SKIPPED_EVENTS_COUNTER, chain_id, log);
+}
+
+pub fn observe_failed(chain_id: u64, log: &Log) {
+    observe_undecoded(&FAILED_EVENTS_COUNTER, chain_id, log);
+}
+
+fn observe_undecoded(counter: &IntCounterVec, chain_id: u64, log: &Log) {
+    let event_type = log
+        .topic0()
+        .map(|topic| topic.to_string())
+        .unwrap_or_else(|| "anonymous".to_string());
+    counter
+        .with_label_values(&[
+            &chain_id.to_string(),
+            &log.inner.address.to_string(),
+            &event_type,
+        ])
+        .inc();
+}
+
+pub fn set_delegation_block_delay(chain_id: u64, delay: u64) {
+    DELEGATION_BLOCK_DELAY_GAUGE
+        .with_label_values(&[&chain_id.to_string()])
+        .set(delay as i64);
+}
+
+pub fn observe_pruned(table: &str, rows: u64) {
+    PRUNED_ROWS_COUNTER.with_label_values(&[table]).inc_by(rows);
+}
+
+pub fn observe_shadow_block(chain_id: u64, outcome: &str) {
+    SHADOW_BLOCKS_COUNTER
+        .with_label_values(&[&chain_id.to_string(), outcome])
+        .inc();
+}
+
+pub fn observe_shadow_divergence(chain_id: u64, table: &str, rows: u64) {
+    SHADOW_DIVERGING_ROWS_COUNTER
+        .with_label_values(&[&chain_id.to_string(), table])
+        .inc_by(rows);
+}
@@ -0,0 +1,110 @@
+//! Later decoding of the logs stored as is in `raw_events`, see
+//! `--raw-events`.
+
+use std::ops::DerefMut;
+
+use alloy::primitives::Address;
+use fhevm_engine_common::raw_events::RawEvent;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::cmd::{decode_logs, DecodedLogs};
+use crate::database::tfhe_event_propagate::Database;
+
+/// Reads the undecoded events of the tenant in the given block range, in
+/// chain order.
+pub async fn read_undecoded(
+    pool: &PgPool,
+    tenant_id: i32,
+    from_block: i64,
+    to_block: i64,
+) -> Result<Vec<RawEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        RawEvent,
+        r#"
+        SELECT block_hash, block_number, log_index, transaction_hash,
+               contract_address, topics, data
+        FROM raw_events
+        WHERE tenant_id = $1
+        AND NOT is_decoded
+        AND block_number BETWEEN $2 AND $3
+        ORDER BY block_number, block_hash, log_index
+        "#,
+        tenant_id,
+        from_block,
+        to_block,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Outcome of the decoding of the raw events of a block.
+#[derive(Debug, Default)]
+pub struct DecodeReport {
+    pub decoded: Vec<(Address, &'static str)>,
+    pub undecoded: usize,
+}
+
+/// Decodes the raw events of a block with the events known to this version
+/// and, when `ingest` is set, stores them like the listener would have done
+/// and marks them as decoded. Events that are still unknown are left as is.
+pub async fn decode_block(
+    db: &Database,
+    events: &[RawEvent],
+    acl_contract_address: &Option<Address>,
+    tfhe_contract_address: &Option<Address>,
+    ingest: bool,
+) -> anyhow::Result<DecodeReport> {
+    let logs = events
+        .iter()
+        .map(RawEvent::to_log)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let DecodedLogs {
+        mut batch,
+        decoded_events,
+        undecoded_events,
+    } = decode_logs(db, &logs, acl_contract_address, tfhe_contract_address)
+        .await;
+    let report = DecodeReport {
+        decoded: decoded_events,
+        undecoded: undecoded_events.len(),
+    };
+    if !ingest || report.decoded.is_empty() {
+        return Ok(report);
+    }
+
+    let decoded_log_indexes: Vec<i64> = events
+        .iter()
+        .zip(&logs)
+        .filter(|(_, log)| {
+            !undecoded_events
+                .iter()
+                .any(|(undecoded, _)| std::ptr::eq(*undecoded, *log))
+        })
+        .map(|(event, _)| event.log_index)
+        .collect();
+    let mut tx = db.new_transaction().await?;
+    db.flush_batch(&mut tx, &mut batch).await?;
+    sqlx::query!(
+        r#"
+        UPDATE raw_events
+        SET is_decoded = TRUE, decoded_at = NOW()
+        WHERE tenant_id = $1
+        AND block_hash = $2
+        AND log_index = ANY($3::BIGINT[])
+        "#,
+        db.tenant_id,
+        &events[0].block_hash,
+        &decoded_log_indexes,
+    )
+    .execute(tx.deref_mut())
+    .await?;
+    tx.commit().await?;
+    info!(
+        block_number = events[0].block_number,
+        decoded = decoded_log_indexes.len(),
+        undecoded = report.undecoded,
+        "Ingested raw events"
+    );
+    Ok(report)
+}
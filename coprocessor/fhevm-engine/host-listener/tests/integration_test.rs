@@ -1,8 +1,7 @@
 use alloy::network::EthereumWallet;
 use alloy::node_bindings::Anvil;
 use alloy::node_bindings::AnvilInstance;
-use alloy::primitives::U256;
-use alloy::providers::ext::AnvilApi;
+use alloy::primitives::{Address, Log, U256};
 use alloy::providers::fillers::{
     BlobGasFiller, ChainIdFiller, FillProvider, GasFiller, JoinFill,
     NonceFiller, WalletFiller,
@@ -10,7 +9,6 @@ use alloy::providers::fillers::{
 use alloy::providers::{
     Provider, ProviderBuilder, RootProvider, WalletProvider, WsConnect,
 };
-use alloy::rpc::types::anvil::{ReorgOptions, TransactionData};
 use alloy::rpc::types::TransactionRequest;
 use alloy::signers::local::PrivateKeySigner;
 use alloy::sol;
@@ -20,13 +18,20 @@ use sqlx::postgres::PgPoolOptions;
 use std::process::Command;
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
+use test_harness::anvil::{self, HostTestContracts};
 use test_harness::health_check;
 use test_harness::instance::ImportMode;
 use tracing::{warn, Level};
 
+use fhevm_engine_common::notification_bus::{BusKind, DEFAULT_SUBJECT_PREFIX};
+use fhevm_engine_common::tls::TlsVerify;
 use host_listener::cmd::main;
-use host_listener::cmd::Args;
-use host_listener::database::tfhe_event_propagate::{Database, ToType};
+use host_listener::cmd::{Args, RawEvents};
+use host_listener::contracts::AclContract::{self, AclContractEvents};
+use host_listener::database::tfhe_event_propagate::{
+    Database, InsertBatch, ToType,
+};
+use host_listener::raw_events::{decode_block, read_undecoded};
 
 // contracts are compiled in build.rs/build_contract() using solc
 // json are generated in build.rs/build_contract() using solc
@@ -99,19 +104,19 @@ async fn emit_events<P, N>(
                     // ACL event is only in the past of winning chain in reorg
                     let cur_block = receipt.block_number.unwrap();
                     warn!("Start reorg");
-                    provider
-                        .anvil_reorg(ReorgOptions {
-                            // Use a large reorg depth (25) to ensure Anvil triggers subscription events correctly;
-                            // smaller depths may not reliably cause event notifications.
-                            depth: 25,
-                            tx_block_pairs: vec![
-                                (TransactionData::JSON(tfhe_txn_req), 24),
-                                // this event is only on winning chain
-                                (TransactionData::JSON(acl_txn_req), 0),
-                            ],
-                        })
-                        .await
-                        .unwrap();
+                    // Use a large reorg depth (25) to ensure Anvil triggers subscription events correctly;
+                    // smaller depths may not reliably cause event notifications.
+                    anvil::reorg(
+                        &provider,
+                        25,
+                        vec![
+                            (tfhe_txn_req, 24),
+                            // this event is only on winning chain
+                            (acl_txn_req, 0),
+                        ],
+                    )
+                    .await
+                    .unwrap();
                     warn!("Reorg happened at block {cur_block}");
                 } else {
                     let pending_txn = provider
@@ -208,14 +213,17 @@ async fn setup(node_chain_id: Option<u64>) -> Result<Setup, anyhow::Error> {
         .connect_ws(WsConnect::new(url.clone()))
         .await?;
 
-    let tfhe_contract = FHEVMExecutorTest::deploy(provider.clone()).await?;
-    let acl_contract = ACLTest::deploy(provider.clone()).await?;
+    let contracts = HostTestContracts::deploy(&provider).await?;
+    let tfhe_contract =
+        FHEVMExecutorTest::new(contracts.executor, provider.clone());
+    let acl_contract = ACLTest::new(contracts.acl, provider.clone());
     let args = Args {
         url,
         initial_block_time: 1,
         acl_contract_address: acl_contract.address().to_string(),
         tfhe_contract_address: tfhe_contract.address().to_string(),
         database_url: test_instance.db_url().to_string(),
+        database_schema: None,
         coprocessor_api_key: Some(coprocessor_api_key),
         start_at_block: None,
         end_at_block: None,
@@ -226,6 +234,34 @@ async fn setup(node_chain_id: Option<u64>) -> Result<Setup, anyhow::Error> {
         dependence_cache_size: 128,
         reorg_maximum_duration_in_blocks: 100, // to go beyond chain start
         service_name: "host-listener-test".to_string(),
+        insert_batch_size: 3, // several statements per block
+        lazy_sns: false,
+        delegation_history_retention: tokio::time::Duration::from_secs(
+            7 * 24 * 3600,
+        ),
+        purge_interval: tokio::time::Duration::from_secs(3600),
+        purge_batch_size: 1000,
+        retention_policies: vec![],
+        delegation_block_delay_min: 0,
+        delegation_block_delay_max: 20,
+        delegation_block_delay_window: 1000,
+        metrics_push_gateway_url: None,
+        metrics_push_interval: tokio::time::Duration::from_secs(15),
+        chain_id_check_interval: tokio::time::Duration::from_secs(300),
+        raw_events: RawEvents::None,
+        rpc_tls_ca_cert: None,
+        rpc_tls_client_cert: None,
+        rpc_tls_client_key: None,
+        rpc_tls_server_name: None,
+        rpc_tls_verify: TlsVerify::Full,
+        column_encryption_key: None,
+        message_bus: BusKind::Postgres,
+        nats_url: None,
+        nats_subject_prefix: DEFAULT_SUBJECT_PREFIX.to_owned(),
+        shadow_database_url: None,
+        shadow_database_schema: None,
+        shadow_report_interval: 1000,
+        shadow_write_timeout: tokio::time::Duration::from_secs(5),
     };
     let health_check_url = format!("http://127.0.0.1:{}", args.health_port);
 
@@ -401,3 +437,294 @@ async fn test_health() -> Result<(), anyhow::Error> {
     listener_handle.abort();
     Ok(())
 }
+
+#[tokio::test]
+#[serial(db)]
+async fn test_purge_delegation_history() -> Result<(), anyhow::Error> {
+    let setup = setup(None).await.expect("setup failed");
+    let tenant_id = sqlx::query!("SELECT tenant_id FROM tenants LIMIT 1")
+        .fetch_one(&setup.db_pool)
+        .await?
+        .tenant_id;
+
+    // counter: (status, age of the final status in hours)
+    let events = [
+        (1, "superseded", 48),
+        (2, "dismissed", 48),
+        (3, "superseded", 0),
+        (4, "applied", 48),
+    ];
+    for (counter, status, age) in events {
+        sqlx::query!(
+            "INSERT INTO user_decryption_delegation_history(tenant_id, \
+             delegator, delegate, contract_address, delegation_counter, \
+             expiry_date, status, updated_at) \
+             VALUES ($1, 'delegator', 'delegate', 'contract', $2, 0, $3, \
+             NOW() - make_interval(hours => $4))",
+            tenant_id,
+            counter as i64,
+            status,
+            age as i32,
+        )
+        .execute(&setup.db_pool)
+        .await?;
+    }
+
+    let retention = tokio::time::Duration::from_secs(24 * 3600);
+    // batches of 1 event
+    for expected in [1, 1, 0] {
+        let purged = Database::purge_delegation_history(
+            &setup.db_pool,
+            tenant_id,
+            retention,
+            1,
+        )
+        .await?;
+        assert_eq!(purged, expected);
+    }
+
+    let remaining = sqlx::query!(
+        "SELECT delegation_counter FROM user_decryption_delegation_history \
+         WHERE tenant_id = $1 ORDER BY delegation_counter",
+        tenant_id,
+    )
+    .fetch_all(&setup.db_pool)
+    .await?
+    .into_iter()
+    .map(|row| row.delegation_counter)
+    .collect::<Vec<_>>();
+    assert_eq!(remaining, vec![3, 4]);
+    Ok(())
+}
+
+fn delegated_account(
+    delegator: Address,
+    delegate: Address,
+    contract_address: Address,
+    counter: u64,
+) -> Log<AclContractEvents> {
+    Log {
+        address: Address::ZERO,
+        data: AclContractEvents::DelegatedAccount(
+            AclContract::DelegatedAccount {
+                delegator,
+                delegate,
+                contractAddress: contract_address,
+                delegationCounter: counter,
+                oldExpiryDate: 0,
+                newExpiryDate: u64::MAX / 2,
+            },
+        ),
+    }
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn test_delegation_history() -> Result<(), anyhow::Error> {
+    let setup = setup(None).await.expect("setup failed");
+    let database = Database::new(
+        &setup.args.database_url,
+        &setup.args.coprocessor_api_key.unwrap(),
+        setup.args.dependence_cache_size,
+    )
+    .await?;
+    let delegator = Address::with_last_byte(1);
+    let delegate = Address::with_last_byte(2);
+    let contract_address = Address::with_last_byte(3);
+    let event = |counter| {
+        delegated_account(delegator, delegate, contract_address, counter)
+    };
+
+    // One event per batch, the 2nd being replayed after the 3rd
+    for counter in [1, 3, 2] {
+        let mut tx = database.new_transaction().await?;
+        database
+            .handle_acl_event(&mut tx, &event(counter), &None, &Some(counter))
+            .await?;
+        tx.commit().await?;
+    }
+    // Several events of the delegation in the same batch
+    let mut batch = InsertBatch::default();
+    for counter in [4, 5] {
+        database
+            .push_acl_event(&mut batch, &event(counter), &None, &Some(counter))
+            .await;
+    }
+    let mut tx = database.new_transaction().await?;
+    database.flush_batch(&mut tx, &mut batch).await?;
+    tx.commit().await?;
+    // Revoked
+    let revoked = Log {
+        address: Address::ZERO,
+        data: AclContractEvents::RevokedDelegation(
+            AclContract::RevokedDelegation {
+                delegator,
+                delegate,
+                contractAddress: contract_address,
+                delegationCounter: 6,
+                oldExpiryDate: u64::MAX / 2,
+            },
+        ),
+    };
+    let mut tx = database.new_transaction().await?;
+    database
+        .handle_acl_event(&mut tx, &revoked, &None, &Some(6))
+        .await?;
+    tx.commit().await?;
+
+    let history = sqlx::query!(
+        "SELECT delegation_counter, status, block_number, old_expiry_date \
+         FROM user_decryption_delegation_history \
+         WHERE tenant_id = $1 ORDER BY delegation_counter",
+        database.tenant_id,
+    )
+    .fetch_all(&setup.db_pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            row.delegation_counter,
+            row.status,
+            row.block_number,
+            row.old_expiry_date,
+        )
+    })
+    .collect::<Vec<_>>();
+    let mut expected = [
+        (1, "superseded"),
+        (2, "dismissed"),
+        (3, "superseded"),
+        (4, "superseded"),
+        (5, "superseded"),
+        (6, "applied"),
+    ]
+    .map(|(counter, status)| (counter, status.to_owned(), Some(counter), None));
+    expected[5].3 = Some(i64::MAX);
+    assert_eq!(history, expected);
+
+    let applied = sqlx::query!(
+        "SELECT delegation_counter, expiry_date FROM user_decryption_delegations \
+         WHERE tenant_id = $1",
+        database.tenant_id,
+    )
+    .fetch_one(&setup.db_pool)
+    .await?;
+    assert_eq!(applied.delegation_counter, 6);
+    assert_eq!(applied.expiry_date, 0);
+    Ok(())
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn test_raw_events() -> Result<(), anyhow::Error> {
+    let setup = setup(None).await?;
+    let mut args = setup.args.clone();
+    args.raw_events = RawEvents::All;
+
+    let listener_handle = tokio::spawn(main(args.clone()));
+    assert!(health_check::wait_healthy(&setup.health_check_url, 60, 1).await);
+    emit_events(
+        &setup.wallets,
+        &args.url,
+        setup.tfhe_contract.clone(),
+        setup.acl_contract.clone(),
+        false,
+    )
+    .await;
+    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+    listener_handle.abort();
+
+    let counts = sqlx::query!(
+        "SELECT COUNT(*) AS total, COUNT(*) FILTER (WHERE NOT is_decoded) \
+         AS undecoded FROM raw_events"
+    )
+    .fetch_one(&setup.db_pool)
+    .await?;
+    let total = counts.total.unwrap_or(0);
+    assert!(total > 0);
+    assert_eq!(counts.undecoded, Some(0));
+
+    // As if captured by a version not knowing these events
+    sqlx::query!("UPDATE raw_events SET is_decoded = FALSE")
+        .execute(&setup.db_pool)
+        .await?;
+    let database = Database::new(
+        &args.database_url,
+        &args.coprocessor_api_key.unwrap(),
+        args.dependence_cache_size,
+    )
+    .await?;
+    let events =
+        read_undecoded(&setup.db_pool, database.tenant_id, 0, i64::MAX).await?;
+    assert_eq!(events.len() as i64, total);
+    let acl_contract_address = Some(*setup.acl_contract.address());
+    let tfhe_contract_address = Some(*setup.tfhe_contract.address());
+    let mut decoded = 0;
+    for block in events.chunk_by(|a, b| a.block_hash == b.block_hash) {
+        let report = decode_block(
+            &database,
+            block,
+            &acl_contract_address,
+            &tfhe_contract_address,
+            false,
+        )
+        .await?;
+        assert_eq!(report.undecoded, 0);
+        decoded += report.decoded.len();
+    }
+    assert_eq!(decoded as i64, total);
+    // Dry run
+    let events =
+        read_undecoded(&setup.db_pool, database.tenant_id, 0, i64::MAX).await?;
+    assert_eq!(events.len() as i64, total);
+
+    // Ingest, as if the events had been dropped when captured
+    let count_computations = || async {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM computations")
+            .fetch_one(&setup.db_pool)
+            .await
+    };
+    let computations = count_computations().await?;
+    assert!(computations > 0);
+    sqlx::query("DELETE FROM computations")
+        .execute(&setup.db_pool)
+        .await?;
+    for block in events.chunk_by(|a, b| a.block_hash == b.block_hash) {
+        let report = decode_block(
+            &database,
+            block,
+            &acl_contract_address,
+            &tfhe_contract_address,
+            true,
+        )
+        .await?;
+        assert_eq!(report.decoded.len(), block.len());
+    }
+    assert_eq!(count_computations().await?, computations);
+    assert!(
+        read_undecoded(&setup.db_pool, database.tenant_id, 0, i64::MAX)
+            .await?
+            .is_empty()
+    );
+    let decoded: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM raw_events \
+         WHERE is_decoded AND decoded_at IS NOT NULL",
+    )
+    .fetch_one(&setup.db_pool)
+    .await?;
+    assert_eq!(decoded, total);
+
+    // Replaying ingested events inserts nothing more
+    for block in events.chunk_by(|a, b| a.block_hash == b.block_hash) {
+        decode_block(
+            &database,
+            block,
+            &acl_contract_address,
+            &tfhe_contract_address,
+            true,
+        )
+        .await?;
+    }
+    assert_eq!(count_computations().await?, computations);
+    Ok(())
+}
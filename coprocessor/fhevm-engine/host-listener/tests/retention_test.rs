@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use serial_test::serial;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use test_harness::instance::ImportMode;
+
+use host_listener::database::retention::{
+    prune_batch, RetainedTable, Retention, RetentionPolicy,
+};
+
+const TENANT_ID: i32 = 1;
+const CHAIN_ID: u64 = 12345;
+const DAY: Duration = Duration::from_secs(24 * 3600);
+
+async fn insert_computation(
+    pool: &PgPool,
+    output_handle: u8,
+    transaction_id: u8,
+    completed_days_ago: Option<i32>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO computations (tenant_id, output_handle, dependencies, fhe_operation, is_scalar, transaction_id, is_completed, completed_at)
+        VALUES ($1, $2, '{}', 0, FALSE, $3, $4, NOW() - make_interval(days => $5))",
+    )
+    .bind(TENANT_ID)
+    .bind(vec![output_handle])
+    .bind(vec![transaction_id])
+    .bind(completed_days_ago.is_some())
+    .bind(completed_days_ago)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn insert_pbs_computation(
+    pool: &PgPool,
+    handle: u8,
+    is_completed: bool,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO pbs_computations (tenant_id, handle, is_completed, completed_at)
+        VALUES ($1, $2, $3, NOW() - INTERVAL '2 days')",
+    )
+    .bind(TENANT_ID)
+    .bind(vec![handle])
+    .bind(is_completed)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn insert_raw_event(
+    pool: &PgPool,
+    block_number: i64,
+    is_decoded: bool,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO raw_events (tenant_id, block_hash, log_index, block_number, contract_address, topics, data, is_decoded)
+        VALUES ($1, $2, 0, $3, '0x00', '{}', '', $4)",
+    )
+    .bind(TENANT_ID)
+    .bind(block_number.to_be_bytes().to_vec())
+    .bind(block_number)
+    .bind(is_decoded)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn remaining(pool: &PgPool, query: &str) -> anyhow::Result<Vec<Vec<u8>>> {
+    Ok(sqlx::query_scalar(query).fetch_all(pool).await?)
+}
+
+async fn prune(
+    pool: &PgPool,
+    table: RetainedTable,
+    retention: Retention,
+    last_valid_block: Option<i64>,
+) -> anyhow::Result<u64> {
+    let policy = RetentionPolicy { table, retention };
+    Ok(
+        prune_batch(pool, TENANT_ID, CHAIN_ID, &policy, last_valid_block, 100)
+            .await?,
+    )
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn test_prune_keeps_pending_work() -> anyhow::Result<()> {
+    let test_instance = test_harness::instance::setup_test_db(ImportMode::None)
+        .await
+        .expect("valid db instance");
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(test_instance.db_url())
+        .await?;
+
+    // Transaction 1 is done, transaction 2 still has a computation to do
+    insert_computation(&pool, 1, 1, Some(2)).await?;
+    insert_computation(&pool, 2, 2, Some(2)).await?;
+    insert_computation(&pool, 3, 2, None).await?;
+    insert_computation(&pool, 4, 3, Some(0)).await?;
+    let deleted = prune(
+        &pool,
+        RetainedTable::Computations,
+        Retention::Age(DAY),
+        None,
+    )
+    .await?;
+    assert_eq!(deleted, 1);
+    let rows = remaining(
+        &pool,
+        "SELECT output_handle FROM computations ORDER BY output_handle",
+    )
+    .await?;
+    assert_eq!(rows, vec![vec![2], vec![3], vec![4]]);
+
+    insert_pbs_computation(&pool, 1, true).await?;
+    insert_pbs_computation(&pool, 2, false).await?;
+    let deleted = prune(
+        &pool,
+        RetainedTable::PbsComputations,
+        Retention::Age(DAY),
+        None,
+    )
+    .await?;
+    assert_eq!(deleted, 1);
+    let rows = remaining(&pool, "SELECT handle FROM pbs_computations").await?;
+    assert_eq!(rows, vec![vec![2]]);
+
+    insert_raw_event(&pool, 10, true).await?;
+    insert_raw_event(&pool, 11, false).await?;
+    insert_raw_event(&pool, 95, true).await?;
+    // No retention by depth without a valid block
+    let deleted =
+        prune(&pool, RetainedTable::RawEvents, Retention::Blocks(10), None)
+            .await?;
+    assert_eq!(deleted, 0);
+    let deleted = prune(
+        &pool,
+        RetainedTable::RawEvents,
+        Retention::Blocks(10),
+        Some(100),
+    )
+    .await?;
+    assert_eq!(deleted, 1);
+    let rows = remaining(
+        &pool,
+        "SELECT block_hash FROM raw_events ORDER BY block_number",
+    )
+    .await?;
+    assert_eq!(
+        rows,
+        vec![11_i64.to_be_bytes().to_vec(), 95_i64.to_be_bytes().to_vec()]
+    );
+    Ok(())
+}
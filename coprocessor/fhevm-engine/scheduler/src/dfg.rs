@@ -1,3 +1,4 @@
+pub mod export;
 pub mod scheduler;
 pub mod types;
 
@@ -0,0 +1,426 @@
+//! Export of a transaction's dependence graph, to debug its results.
+//!
+//! A [`GraphExport`] is built from a [`TxNode`] before execution, every node
+//! pending, and updated by the scheduler as the operations complete, see
+//! [`crate::dfg::scheduler::Scheduler::with_graph_export`]. It renders as
+//! JSON or as a Graphviz DOT digraph, the nodes coloured by status.
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use daggy::petgraph::visit::{EdgeRef, IntoEdgeReferences};
+use fhevm_engine_common::common::FheOperation;
+use fhevm_engine_common::types::SupportedFheCiphertexts;
+use serde::Serialize;
+
+use crate::dfg::{types::DFGTaskInput, TxNode};
+
+/// Index of the FHE type in a handle
+const HANDLE_TYPE_BYTE: usize = 30;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeStatus {
+    Pending,
+    Completed,
+    Failed,
+    /// Not executed, an input of the operation or of the transaction is missing
+    Skipped,
+}
+
+impl NodeStatus {
+    fn color(&self) -> &'static str {
+        match self {
+            Self::Pending => "lightgrey",
+            Self::Completed => "palegreen",
+            Self::Failed => "salmon",
+            Self::Skipped => "khaki",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct NodeExport {
+    pub index: usize,
+    pub handle: String,
+    pub operation: String,
+    /// FHE type of the result, from its handle
+    pub fhe_type: Option<i16>,
+    /// Type of the computed ciphertext
+    pub output_type: Option<String>,
+    pub is_allowed: bool,
+    /// Handles of the dependences, `scalar` or `ciphertext` for the values
+    pub inputs: Vec<String>,
+    pub status: NodeStatus,
+    pub error: Option<String>,
+    /// Start of the operation, since the start of the transaction
+    pub started_at_ms: Option<f64>,
+    pub duration_ms: Option<f64>,
+    /// Completion time, when exported from the database
+    pub completed_at: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct EdgeExport {
+    pub source: usize,
+    pub target: usize,
+    /// Position of the source result among the target inputs
+    pub input: u8,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct GraphExport {
+    pub transaction_id: String,
+    /// Handles required from outside the transaction
+    pub inputs: Vec<String>,
+    pub nodes: Vec<NodeExport>,
+    pub edges: Vec<EdgeExport>,
+    /// Error of the transaction as a whole, e.g. its inputs could not be re-randomised
+    pub error: Option<String>,
+    pub duration_ms: Option<f64>,
+}
+
+fn to_hex(handle: &[u8]) -> String {
+    format!("0x{}", hex::encode(handle))
+}
+
+fn operation_name(opcode: i32) -> String {
+    FheOperation::try_from(opcode)
+        .map(|op| op.as_str_name().to_owned())
+        .unwrap_or_else(|_| format!("unknown({opcode})"))
+}
+
+pub type DFGExportSender = tokio::sync::mpsc::UnboundedSender<GraphExport>;
+
+impl TxNode {
+    /// Snapshot of the graph, every node pending.
+    pub fn export(&self) -> GraphExport {
+        let graph = &self.graph.graph;
+        let nodes = graph
+            .raw_nodes()
+            .iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let node = &node.weight;
+                NodeExport {
+                    index,
+                    handle: to_hex(&node.result_handle),
+                    operation: operation_name(node.opcode),
+                    fhe_type: node
+                        .result_handle
+                        .get(HANDLE_TYPE_BYTE)
+                        .map(|fhe_type| *fhe_type as i16),
+                    output_type: None,
+                    is_allowed: node.is_allowed,
+                    inputs: node
+                        .inputs
+                        .iter()
+                        .map(|input| match input {
+                            DFGTaskInput::Dependence(handle) => to_hex(handle),
+                            DFGTaskInput::Value(SupportedFheCiphertexts::Scalar(_)) => {
+                                "scalar".to_owned()
+                            }
+                            DFGTaskInput::Value(_) | DFGTaskInput::Compressed(_) => {
+                                "ciphertext".to_owned()
+                            }
+                        })
+                        .collect(),
+                    status: NodeStatus::Pending,
+                    error: None,
+                    started_at_ms: None,
+                    duration_ms: None,
+                    completed_at: None,
+                }
+            })
+            .collect();
+        let edges = graph
+            .edge_references()
+            .map(|edge| EdgeExport {
+                source: edge.source().index(),
+                target: edge.target().index(),
+                input: *edge.weight(),
+            })
+            .collect();
+        let mut inputs: Vec<String> = self.inputs.keys().map(|handle| to_hex(handle)).collect();
+        inputs.sort();
+        GraphExport {
+            transaction_id: to_hex(&self.transaction_id),
+            inputs,
+            nodes,
+            edges,
+            error: None,
+            duration_ms: None,
+        }
+    }
+}
+
+impl GraphExport {
+    pub fn node_mut(&mut self, handle: &[u8]) -> Option<&mut NodeExport> {
+        let handle = to_hex(handle);
+        self.nodes.iter_mut().find(|node| node.handle == handle)
+    }
+
+    pub(crate) fn complete(
+        &mut self,
+        index: usize,
+        output_type: &str,
+        started_at: Duration,
+        duration: Duration,
+    ) {
+        if let Some(node) = self.nodes.get_mut(index) {
+            node.status = NodeStatus::Completed;
+            node.output_type = Some(output_type.to_owned());
+            node.started_at_ms = Some(started_at.as_secs_f64() * 1000.0);
+            node.duration_ms = Some(duration.as_secs_f64() * 1000.0);
+        }
+    }
+
+    pub(crate) fn fail(
+        &mut self,
+        index: usize,
+        error: String,
+        started_at: Duration,
+        duration: Duration,
+    ) {
+        if let Some(node) = self.nodes.get_mut(index) {
+            node.status = NodeStatus::Failed;
+            node.error = Some(error);
+            node.started_at_ms = Some(started_at.as_secs_f64() * 1000.0);
+            node.duration_ms = Some(duration.as_secs_f64() * 1000.0);
+        }
+    }
+
+    /// Marks the nodes not executed as skipped, with the error of the transaction if any.
+    pub fn skip_pending(&mut self, error: Option<String>) {
+        for node in self.nodes.iter_mut() {
+            if node.status == NodeStatus::Pending {
+                node.status = NodeStatus::Skipped;
+            }
+        }
+        if error.is_some() {
+            self.error = error;
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Graphviz digraph, the edges labelled with the input position.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        let _ = writeln!(dot, "digraph \"{}\" {{", self.transaction_id);
+        let _ = writeln!(dot, "  node [shape=box, style=filled];");
+        let _ = writeln!(dot, "  label=\"transaction {}\";", self.transaction_id);
+        for (index, handle) in self.inputs.iter().enumerate() {
+            let _ = writeln!(
+                dot,
+                "  in{index} [label=\"input\\n{}\", shape=ellipse, fillcolor=white];",
+                short_handle(handle)
+            );
+        }
+        for node in self.nodes.iter() {
+            let mut label = format!(
+                "#{} {}\\n{}",
+                node.index,
+                node.operation,
+                short_handle(&node.handle)
+            );
+            if let Some(output_type) = &node.output_type {
+                let _ = write!(label, "\\n{output_type}");
+            } else if let Some(fhe_type) = node.fhe_type {
+                let _ = write!(label, "\\ntype {fhe_type}");
+            }
+            let _ = write!(label, "\\n{:?}", node.status);
+            if let Some(duration_ms) = node.duration_ms {
+                let _ = write!(label, " {duration_ms:.1}ms");
+            }
+            if let Some(error) = &node.error {
+                let _ = write!(label, "\\n{}", escape(error));
+            }
+            let peripheries = if node.is_allowed { 2 } else { 1 };
+            let _ = writeln!(
+                dot,
+                "  n{} [label=\"{label}\", fillcolor={}, peripheries={peripheries}];",
+                node.index,
+                node.status.color()
+            );
+            for (position, input) in node.inputs.iter().enumerate() {
+                if let Some(source) = self.inputs.iter().position(|handle| handle == input) {
+                    let _ = writeln!(
+                        dot,
+                        "  in{source} -> n{} [label=\"{position}\"];",
+                        node.index
+                    );
+                }
+            }
+        }
+        for edge in self.edges.iter() {
+            let _ = writeln!(
+                dot,
+                "  n{} -> n{} [label=\"{}\"];",
+                edge.source, edge.target, edge.input
+            );
+        }
+        let _ = writeln!(dot, "}}");
+        dot
+    }
+}
+
+fn short_handle(handle: &str) -> String {
+    if handle.len() > 14 {
+        format!("{}..{}", &handle[..8], &handle[handle.len() - 4..])
+    } else {
+        handle.to_owned()
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dfg::DFGOp;
+    use fhevm_engine_common::types::{Handle, SupportedFheOperations};
+
+    fn handle(byte: u8) -> Handle {
+        let mut handle = vec![byte; 32];
+        handle[HANDLE_TYPE_BYTE] = 4;
+        handle
+    }
+
+    /// `(input * scalar)` added to the input, only the sum being allowed
+    fn export() -> GraphExport {
+        let operations = vec![
+            DFGOp {
+                output_handle: handle(2),
+                fhe_op: SupportedFheOperations::FheMul,
+                inputs: vec![
+                    DFGTaskInput::Dependence(handle(1)),
+                    DFGTaskInput::Value(SupportedFheCiphertexts::Scalar(vec![3])),
+                ],
+                is_allowed: false,
+            },
+            DFGOp {
+                output_handle: handle(3),
+                fhe_op: SupportedFheOperations::FheAdd,
+                inputs: vec![
+                    DFGTaskInput::Dependence(handle(1)),
+                    DFGTaskInput::Dependence(handle(2)),
+                ],
+                is_allowed: true,
+            },
+        ];
+        let mut tx = TxNode::default();
+        tx.build(operations, &vec![9; 32]).unwrap();
+        tx.export()
+    }
+
+    #[test]
+    fn exports_the_transaction_graph() {
+        let export = export();
+        assert_eq!(export.transaction_id, to_hex(&[9; 32]));
+        assert_eq!(export.inputs, vec![to_hex(&handle(1))]);
+        assert_eq!(export.nodes.len(), 2);
+
+        let mul = &export.nodes[0];
+        assert_eq!(mul.operation, "FHE_MUL");
+        assert_eq!(mul.fhe_type, Some(4));
+        assert_eq!(mul.inputs, vec![to_hex(&handle(1)), "scalar".to_owned()]);
+        assert!(!mul.is_allowed);
+        assert!(export
+            .nodes
+            .iter()
+            .all(|node| node.status == NodeStatus::Pending));
+
+        assert_eq!(export.edges.len(), 1);
+        let edge = &export.edges[0];
+        assert_eq!((edge.source, edge.target, edge.input), (0, 1, 1));
+    }
+
+    #[test]
+    fn records_the_node_outcomes() {
+        let mut export = export();
+        export.complete(
+            0,
+            "FheUint32",
+            Duration::from_millis(1),
+            Duration::from_millis(2),
+        );
+        export.skip_pending(Some("missing input".to_owned()));
+        assert_eq!(export.nodes[0].status, NodeStatus::Completed);
+        assert_eq!(export.nodes[0].output_type.as_deref(), Some("FheUint32"));
+        assert_eq!(export.nodes[0].started_at_ms, Some(1.0));
+        assert_eq!(export.nodes[0].duration_ms, Some(2.0));
+        assert_eq!(export.nodes[1].status, NodeStatus::Skipped);
+        assert_eq!(export.error.as_deref(), Some("missing input"));
+
+        // A later skip without error keeps the error of the transaction
+        export.skip_pending(None);
+        assert_eq!(export.error.as_deref(), Some("missing input"));
+
+        let node = export.node_mut(&handle(3)).unwrap();
+        assert_eq!(node.index, 1);
+        assert!(export.node_mut(&handle(4)).is_none());
+    }
+
+    #[test]
+    fn renders_as_json() {
+        let mut export = export();
+        export.fail(
+            1,
+            "overflow".to_owned(),
+            Duration::from_millis(1),
+            Duration::from_millis(1),
+        );
+        let json: serde_json::Value = serde_json::from_str(&export.to_json().unwrap()).unwrap();
+        assert_eq!(json["transaction_id"], to_hex(&[9; 32]));
+        assert_eq!(json["nodes"][0]["status"], "pending");
+        assert_eq!(json["nodes"][1]["status"], "failed");
+        assert_eq!(json["nodes"][1]["error"], "overflow");
+        assert_eq!(json["nodes"][1]["inputs"][1], to_hex(&handle(2)));
+        assert_eq!(json["edges"][0]["input"], 1);
+    }
+
+    #[test]
+    fn renders_as_dot() {
+        let mut export = export();
+        export.complete(
+            0,
+            "FheUint32",
+            Duration::from_millis(1),
+            Duration::from_millis(2),
+        );
+        export.fail(
+            1,
+            "invalid \"operand\"".to_owned(),
+            Duration::from_millis(3),
+            Duration::from_millis(1),
+        );
+        let dot = export.to_dot();
+        assert!(dot.starts_with(&format!("digraph \"{}\" {{\n", to_hex(&[9; 32]))));
+        assert!(dot.ends_with("}\n"));
+
+        let input = short_handle(&to_hex(&handle(1)));
+        assert!(dot.contains(&format!("  in0 [label=\"input\\n{input}\"")));
+        assert!(dot.contains("  in0 -> n0 [label=\"0\"];\n"));
+        assert!(dot.contains("  in0 -> n1 [label=\"0\"];\n"));
+        assert!(dot.contains("  n0 -> n1 [label=\"1\"];\n"));
+
+        let mul = dot.lines().find(|line| line.starts_with("  n0 [")).unwrap();
+        assert!(mul.contains("#0 FHE_MUL"));
+        assert!(mul.contains("\\nFheUint32\\nCompleted 2.0ms"));
+        assert!(mul.contains("fillcolor=palegreen, peripheries=1"));
+        let add = dot.lines().find(|line| line.starts_with("  n1 [")).unwrap();
+        assert!(add.contains("\\ninvalid \\\"operand\\\""));
+        assert!(add.contains("fillcolor=salmon, peripheries=2"));
+    }
+
+    #[test]
+    fn shortens_the_handles() {
+        assert_eq!(short_handle(&to_hex(&[0xab; 32])), "0xababab..abab");
+        assert_eq!(short_handle("0x01"), "0x01");
+    }
+}
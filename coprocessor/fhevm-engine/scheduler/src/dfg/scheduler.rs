@@ -1,4 +1,6 @@
+use crate::dfg::export::{DFGExportSender, GraphExport};
 use crate::dfg::{types::*, TxEdge};
+use crate::pools::{self, Pool};
 use anyhow::Result;
 use daggy::{
     petgraph::{
@@ -17,7 +19,10 @@ use fhevm_engine_common::utils::HeartBeat;
 use fhevm_engine_common::{common::FheOperation, telemetry};
 use fhevm_engine_common::{telemetry::gen_buckets, tfhe_ops::perform_fhe_operation};
 use opentelemetry::trace::{Span, Tracer};
-use prometheus::{register_histogram, Histogram};
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter_vec, Histogram, HistogramVec,
+    IntCounterVec,
+};
 use std::{
     collections::HashMap,
     sync::{atomic::AtomicUsize, LazyLock},
@@ -53,6 +58,25 @@ pub(crate) static FHE_LATENCY_HISTOGRAM: LazyLock<Histogram> = LazyLock::new(||
     .unwrap()
 });
 
+pub(crate) static FHE_OP_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_fhe_op_count",
+        "FHE operations executed, by operation, operand type and result",
+        &["op", "operand_type", "result"]
+    )
+    .unwrap()
+});
+
+pub(crate) static FHE_OP_DURATION_HISTOGRAM: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "coprocessor_fhe_op_duration_seconds",
+        "Execution time of FHE operations, by operation and operand type, in seconds",
+        &["op", "operand_type"],
+        vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]
+    )
+    .unwrap()
+});
+
 struct ExecNode {
     df_nodes: Vec<NodeIndex>,
     dependence_counter: AtomicUsize,
@@ -95,6 +119,8 @@ pub struct Scheduler<'a> {
     #[cfg(feature = "gpu")]
     csks: Vec<tfhe::CudaServerKey>,
     activity_heartbeat: HeartBeat,
+    result_sender: Option<DFGResultSender>,
+    graph_export: Option<DFGExportSender>,
 }
 
 impl<'a> Scheduler<'a> {
@@ -119,9 +145,25 @@ impl<'a> Scheduler<'a> {
             #[cfg(feature = "gpu")]
             csks: csks.clone(),
             activity_heartbeat,
+            result_sender: None,
+            graph_export: None,
         }
     }
 
+    /// Sends the allowed handles to `sender` as soon as they are computed,
+    /// before the whole graph is scheduled.
+    pub fn with_result_sender(mut self, sender: DFGResultSender) -> Self {
+        self.result_sender = Some(sender);
+        self
+    }
+
+    /// Sends the dependence graph of every transaction to `sender` once it is
+    /// executed or skipped, with the status and timings of its operations.
+    pub fn with_graph_export(mut self, sender: DFGExportSender) -> Self {
+        self.graph_export = Some(sender);
+        self
+    }
+
     pub async fn schedule(&mut self, loop_ctx: &'a opentelemetry::Context) -> Result<()> {
         let schedule_type = std::env::var("FHEVM_DF_SCHEDULE");
         match schedule_type {
@@ -226,17 +268,33 @@ impl<'a> Scheduler<'a> {
                         .graph
                         .node_weight_mut(*nidx)
                         .ok_or(SchedulerError::DataflowGraphError)?;
+                    let export = self.graph_export.as_ref().map(|_| tx.export());
                     args.push((
                         std::mem::take(&mut tx.graph),
                         std::mem::take(&mut tx.inputs),
                         tx.transaction_id.clone(),
+                        export,
                     ));
                 }
                 let (sks, cpk) = self.get_keys(DeviceSelection::RoundRobin)?;
+                let numa_node = pools::next_node();
+                let result_sender = self.result_sender.clone();
+                let graph_export = self.graph_export.clone();
                 let loop_ctx = loop_ctx.clone();
-                set.spawn(
-                    async move { execute_partition(args, index, 0, sks, cpk, &loop_ctx).await },
-                );
+                set.spawn(async move {
+                    execute_partition(
+                        args,
+                        index,
+                        0,
+                        numa_node,
+                        sks,
+                        cpk,
+                        result_sender,
+                        graph_export,
+                        &loop_ctx,
+                    )
+                    .await
+                });
             }
         }
         while let Some(result) = set.join_next().await {
@@ -272,18 +330,39 @@ impl<'a> Scheduler<'a> {
                         // Skip transactions that cannot complete
                         // because of missing dependences.
                         if tx.is_uncomputable {
+                            if let Some(sender) = &self.graph_export {
+                                let mut export = tx.export();
+                                export.skip_pending(Some("an input transaction failed".to_owned()));
+                                let _ = sender.send(export);
+                            }
                             continue;
                         }
+                        let export = self.graph_export.as_ref().map(|_| tx.export());
                         args.push((
                             std::mem::take(&mut tx.graph),
                             std::mem::take(&mut tx.inputs),
                             tx.transaction_id.clone(),
+                            export,
                         ));
                     }
                     let (sks, cpk) = self.get_keys(DeviceSelection::RoundRobin)?;
+                    let numa_node = pools::next_node();
+                    let result_sender = self.result_sender.clone();
+                    let graph_export = self.graph_export.clone();
                     let loop_ctx = loop_ctx.clone();
                     set.spawn(async move {
-                        execute_partition(args, dependent_task_index, 0, sks, cpk, &loop_ctx).await
+                        execute_partition(
+                            args,
+                            dependent_task_index,
+                            0,
+                            numa_node,
+                            sks,
+                            cpk,
+                            result_sender,
+                            graph_export,
+                            &loop_ctx,
+                        )
+                        .await
                     });
                 }
             }
@@ -484,20 +563,48 @@ fn decompress_transaction_inputs(
 }
 
 type TaskResult = Result<(SupportedFheCiphertexts, i16, Vec<u8>)>;
+type PartitionTransaction = (
+    DFGraph,
+    HashMap<Handle, Option<DFGTxInput>>,
+    Handle,
+    Option<GraphExport>,
+);
+
+/// Sends the graph of a transaction that is done with, its remaining
+/// operations skipped.
+fn send_export(
+    sender: &Option<DFGExportSender>,
+    export: Option<GraphExport>,
+    error: Option<String>,
+    started_at: std::time::Instant,
+) {
+    if let (Some(sender), Some(mut export)) = (sender, export) {
+        export.skip_pending(error);
+        export.duration_ms = Some(started_at.elapsed().as_secs_f64() * 1000.0);
+        // The receiver may be gone, the export is only for debugging
+        let _ = sender.send(export);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn execute_partition(
-    transactions: Vec<(DFGraph, HashMap<Handle, Option<DFGTxInput>>, Handle)>,
+    transactions: Vec<PartitionTransaction>,
     task_id: NodeIndex,
     gpu_idx: usize,
+    numa_node: usize,
     #[cfg(not(feature = "gpu"))] sks: tfhe::ServerKey,
     #[cfg(feature = "gpu")] sks: tfhe::CudaServerKey,
     cpk: tfhe::CompactPublicKey,
+    result_sender: Option<DFGResultSender>,
+    graph_export: Option<DFGExportSender>,
     loop_ctx: &opentelemetry::Context,
 ) -> (HashMap<Handle, TaskResult>, NodeIndex) {
     let mut res: HashMap<Handle, TaskResult> = HashMap::with_capacity(transactions.len());
     let tracer = opentelemetry::global::tracer("tfhe_worker");
     // Traverse transactions within the partition. The transactions
     // are topologically sorted so the order is executable
-    'tx: for (ref mut dfg, ref mut tx_inputs, tid) in transactions {
+    'tx: for (ref mut dfg, ref mut tx_inputs, tid, mut export) in transactions {
+        let tx_started_at = std::time::Instant::now();
         tfhe::set_server_key(sks.clone());
         // Update the transaction inputs based on allowed handles so
         // far. If any input is still missing, and we cannot fill it
@@ -520,6 +627,12 @@ async fn execute_partition(
                             );
                         }
                     }
+                    send_export(
+                        &graph_export,
+                        export,
+                        Some(SchedulerError::MissingInputs.to_string()),
+                        tx_started_at,
+                    );
                     continue 'tx;
                 };
                 *i = Some(DFGTxInput::Value(ct.0.clone()));
@@ -532,7 +645,10 @@ async fn execute_partition(
             let started_at = std::time::Instant::now();
             // Re-randomise inputs of the transaction - this also
             // decompresses ciphertexts
-            if let Err(e) = re_randomise_transaction_inputs(tx_inputs, &tid, gpu_idx, cpk.clone()) {
+            if let Err(e) = pools::install(numa_node, Pool::Compression, || {
+                tfhe::set_server_key(sks.clone());
+                re_randomise_transaction_inputs(tx_inputs, &tid, gpu_idx, cpk.clone())
+            }) {
                 error!(target: "scheduler", {transaction_id = ?tid, error = ?e },
 		       "Error while re-randomising inputs");
                 for nidx in dfg.graph.node_identifiers() {
@@ -547,6 +663,7 @@ async fn execute_partition(
                         );
                     }
                 }
+                send_export(&graph_export, export, Some(e.to_string()), tx_started_at);
                 continue 'tx;
             }
 
@@ -572,6 +689,7 @@ async fn execute_partition(
                         );
                     }
                 }
+                send_export(&graph_export, export, Some(e.to_string()), tx_started_at);
                 continue 'tx;
             }
         }
@@ -581,7 +699,7 @@ async fn execute_partition(
         telemetry::set_txn_id(&mut s, &tid);
         let started_at = std::time::Instant::now();
 
-        let mut set: JoinSet<(usize, OpResult)> = JoinSet::new();
+        let mut set: JoinSet<(usize, OpResult, OpTiming)> = JoinSet::new();
         for nidx in dfg.graph.node_identifiers() {
             let Some(node) = dfg.graph.node_weight_mut(nidx) else {
                 error!(target: "scheduler", {index = ?nidx.index() }, "Wrong dataflow graph index");
@@ -593,6 +711,7 @@ async fn execute_partition(
                 &mut set,
                 tx_inputs,
                 gpu_idx,
+                numa_node,
                 sks.clone(),
             );
         }
@@ -601,6 +720,16 @@ async fn execute_partition(
             tfhe::set_server_key(sks.clone());
             if let Ok(result) = result {
                 let nidx = NodeIndex::new(result.0);
+                if let Some(export) = &mut export {
+                    let timing = &result.2;
+                    let started_at = timing.started_at.saturating_duration_since(tx_started_at);
+                    match &result.1 {
+                        Ok((ct, _)) => {
+                            export.complete(result.0, ct.type_name(), started_at, timing.duration)
+                        }
+                        Err(e) => export.fail(result.0, e.to_string(), started_at, timing.duration),
+                    }
+                }
                 if result.1.is_ok() {
                     for edge in edges.edges_directed(nidx, Direction::Outgoing) {
                         let child_index = edge.target();
@@ -619,6 +748,7 @@ async fn execute_partition(
                             &mut set,
                             tx_inputs,
                             gpu_idx,
+                            numa_node,
                             sks.clone(),
                         );
                     }
@@ -626,6 +756,18 @@ async fn execute_partition(
                 // Update partition's outputs (allowed handles only)
                 let node = dfg.graph.node_weight_mut(nidx).unwrap();
                 if node.is_allowed {
+                    if let (Some(sender), Ok((_, Some((ct_type, ct_bytes))))) =
+                        (&result_sender, &result.1)
+                    {
+                        // The receiver may be gone, results are returned
+                        // with the partition anyway
+                        let _ = sender.send(DFGComputedResult {
+                            handle: node.result_handle.clone(),
+                            transaction_id: tid.clone(),
+                            ct_type: *ct_type,
+                            ct_bytes: ct_bytes.clone(),
+                        });
+                    }
                     res.insert(
                         node.result_handle.clone(),
                         result
@@ -638,6 +780,7 @@ async fn execute_partition(
         s.end();
         let elapsed = started_at.elapsed();
         FHE_LATENCY_HISTOGRAM.observe(elapsed.as_secs_f64());
+        send_export(&graph_export, export, None, tx_started_at);
     }
     (res, task_id)
 }
@@ -645,9 +788,10 @@ async fn execute_partition(
 fn try_schedule_node(
     node: &mut OpNode,
     node_index: usize,
-    set: &mut JoinSet<(usize, OpResult)>,
+    set: &mut JoinSet<(usize, OpResult, OpTiming)>,
     tx_inputs: &mut HashMap<Handle, Option<DFGTxInput>>,
     gpu_idx: usize,
+    numa_node: usize,
     #[cfg(not(feature = "gpu"))] sks: tfhe::ServerKey,
     #[cfg(feature = "gpu")] sks: tfhe::CudaServerKey,
 ) {
@@ -669,32 +813,87 @@ fn try_schedule_node(
     let is_allowed = node.is_allowed;
     let sks = sks.clone();
     set.spawn_blocking(move || {
-        tfhe::set_server_key(sks.clone());
-        run_computation(opcode, cts, node_index, is_allowed, gpu_idx)
+        let started_at = std::time::Instant::now();
+        let (index, result) =
+            run_computation(opcode, cts, node_index, is_allowed, gpu_idx, numa_node, sks);
+        let timing = OpTiming {
+            started_at,
+            duration: started_at.elapsed(),
+        };
+        (index, result, timing)
     });
 }
 
+/// Records the execution of an operation. The operand type is the type of the
+/// first operand, or of the second one for a select whose first operand is the
+/// condition.
+fn observe_fhe_op(
+    op: FheOperation,
+    inputs: &[SupportedFheCiphertexts],
+    started_at: std::time::Instant,
+    is_ok: bool,
+) {
+    let operand = match op {
+        FheOperation::FheIfThenElse => inputs.get(1),
+        _ => inputs.first(),
+    };
+    let op_name = op.as_str_name();
+    let operand_type = operand.map_or("none", |ct| ct.type_name());
+    let result = if is_ok { "ok" } else { "error" };
+    FHE_OP_COUNTER
+        .with_label_values(&[op_name, operand_type, result])
+        .inc();
+    if is_ok {
+        FHE_OP_DURATION_HISTOGRAM
+            .with_label_values(&[op_name, operand_type])
+            .observe(started_at.elapsed().as_secs_f64());
+    }
+}
+
 type OpResult = Result<(SupportedFheCiphertexts, Option<(i16, Vec<u8>)>)>;
+
+/// Execution of an operation, reported in the graph exports
+struct OpTiming {
+    started_at: std::time::Instant,
+    duration: std::time::Duration,
+}
+
 fn run_computation(
     operation: i32,
     inputs: Vec<SupportedFheCiphertexts>,
     graph_node_index: usize,
     is_allowed: bool,
     gpu_idx: usize,
+    numa_node: usize,
+    #[cfg(not(feature = "gpu"))] sks: tfhe::ServerKey,
+    #[cfg(feature = "gpu")] sks: tfhe::CudaServerKey,
 ) -> (usize, OpResult) {
+    // The server key is thread local, set it on the pool threads
+    let compress = |ct: &SupportedFheCiphertexts| {
+        pools::install(numa_node, Pool::Compression, || {
+            tfhe::set_server_key(sks.clone());
+            ct.compress()
+        })
+    };
     let op = FheOperation::try_from(operation);
     match op {
         Ok(FheOperation::FheGetCiphertext) => {
-            let (ct_type, ct_bytes) = inputs[0].compress();
+            let (ct_type, ct_bytes) = compress(&inputs[0]);
             (
                 graph_node_index,
                 Ok((inputs[0].clone(), Some((ct_type, ct_bytes)))),
             )
         }
-        Ok(_) => match perform_fhe_operation(operation as i16, &inputs, gpu_idx) {
+        Ok(op) => match pools::install(numa_node, Pool::Pbs, || {
+            tfhe::set_server_key(sks.clone());
+            let started_at = std::time::Instant::now();
+            let result = perform_fhe_operation(operation as i16, &inputs, gpu_idx);
+            observe_fhe_op(op, &inputs, started_at, result.is_ok());
+            result
+        }) {
             Ok(result) => {
                 if is_allowed {
-                    let (ct_type, ct_bytes) = result.compress();
+                    let (ct_type, ct_bytes) = compress(&result);
                     (graph_node_index, Ok((result, Some((ct_type, ct_bytes)))))
                 } else {
                     (graph_node_index, Ok((result, None)))
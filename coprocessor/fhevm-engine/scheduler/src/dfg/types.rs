@@ -1,4 +1,5 @@
 use anyhow::Result;
+use bytes::Bytes;
 use fhevm_engine_common::types::{Handle, SupportedFheCiphertexts};
 
 pub struct DFGTxResult {
@@ -21,10 +22,20 @@ impl std::fmt::Debug for DFGTxResult {
         writeln!(f)
     }
 }
+/// Allowed handle computed by the scheduler, sent as soon as its operation
+/// completes, see [`crate::dfg::scheduler::Scheduler::with_result_sender`].
+pub struct DFGComputedResult {
+    pub handle: Handle,
+    pub transaction_id: Handle,
+    pub ct_type: i16,
+    pub ct_bytes: Vec<u8>,
+}
+pub type DFGResultSender = tokio::sync::mpsc::UnboundedSender<DFGComputedResult>;
+
 #[derive(Clone)]
 pub enum DFGTxInput {
     Value(SupportedFheCiphertexts),
-    Compressed((i16, Vec<u8>)),
+    Compressed((i16, Bytes)),
 }
 impl std::fmt::Debug for DFGTxInput {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -38,7 +49,7 @@ impl std::fmt::Debug for DFGTxInput {
 #[derive(Clone)]
 pub enum DFGTaskInput {
     Value(SupportedFheCiphertexts),
-    Compressed((i16, Vec<u8>)),
+    Compressed((i16, Bytes)),
     Dependence(Handle),
 }
 impl std::fmt::Debug for DFGTaskInput {
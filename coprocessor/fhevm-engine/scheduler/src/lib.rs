@@ -1 +1,2 @@
 pub mod dfg;
+pub mod pools;
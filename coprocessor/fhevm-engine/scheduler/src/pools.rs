@@ -0,0 +1,195 @@
+//! Thread pools of the FHE computations.
+//!
+//! Ciphertext decompression, re-randomisation and compression run in a compression pool, separate
+//! from the PBS pool running the operations, so that a burst of (de)compression cannot delay the
+//! operations and the other way around. Both pools are sized independently, the PBS pool defaults
+//! to the global rayon pool. Parallel iterators of tfhe run in the pool of their caller.
+//!
+//! With NUMA pinning, both pools are built per NUMA node with their threads pinned to the CPUs of
+//! the node. The scheduler assigns each partition to a node, round robin, so the ciphertexts of a
+//! partition are allocated and used on a single node.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, OnceLock};
+use std::time::Instant;
+
+use anyhow::Result;
+use fhevm_engine_common::numa;
+use prometheus::{register_histogram_vec, register_int_gauge_vec, HistogramVec, IntGaugeVec};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use tracing::{info, warn};
+
+static POOL_QUEUE_DEPTH: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "coprocessor_pool_queue_depth",
+        "Jobs waiting for a thread of the pool",
+        &["pool"]
+    )
+    .unwrap()
+});
+
+static POOL_ACTIVE_JOBS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "coprocessor_pool_active_jobs",
+        "Jobs running in the pool",
+        &["pool"]
+    )
+    .unwrap()
+});
+
+static POOL_QUEUE_WAIT: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "coprocessor_pool_queue_wait_seconds",
+        "Time jobs wait for a thread of the pool, in seconds",
+        &["pool"],
+        vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]
+    )
+    .unwrap()
+});
+
+#[derive(Clone, Copy, Debug)]
+pub enum Pool {
+    /// Decompression, re-randomisation and compression of ciphertexts
+    Compression,
+    /// FHE operations
+    Pbs,
+}
+
+impl Pool {
+    fn as_str(self) -> &'static str {
+        match self {
+            Pool::Compression => "compression",
+            Pool::Pbs => "pbs",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PoolsConfig {
+    /// Threads of the compression pool, 0 runs (de)compression on the calling thread
+    pub compression_threads: usize,
+    /// Threads of the PBS pool, 0 uses the global rayon pool, or all the CPUs of a node
+    pub pbs_threads: usize,
+    /// Build the pools per NUMA node. Ignored on single node machines
+    pub numa_pinning: bool,
+}
+
+#[derive(Default)]
+struct NodePools {
+    compression: Option<ThreadPool>,
+    pbs: Option<ThreadPool>,
+}
+
+impl NodePools {
+    fn get(&self, pool: Pool) -> Option<&ThreadPool> {
+        match pool {
+            Pool::Compression => self.compression.as_ref(),
+            Pool::Pbs => self.pbs.as_ref(),
+        }
+    }
+}
+
+static POOLS: OnceLock<Vec<NodePools>> = OnceLock::new();
+
+/// Builds the pools, returns the number of NUMA nodes they are built for, 0 without pinning.
+pub fn init(conf: &PoolsConfig) -> Result<usize> {
+    let mut nodes = vec![];
+    if conf.numa_pinning {
+        nodes = numa::nodes()?;
+        if nodes.len() < 2 {
+            info!(target: "scheduler", nodes = nodes.len(), "Single NUMA node, pinning disabled");
+            nodes.clear();
+        }
+    }
+
+    let pools = if nodes.is_empty() {
+        vec![NodePools {
+            compression: build_pool(Pool::Compression, conf.compression_threads, None)?,
+            pbs: build_pool(Pool::Pbs, conf.pbs_threads, None)?,
+        }]
+    } else {
+        let mut pools = Vec::with_capacity(nodes.len());
+        for node in nodes.iter() {
+            let pbs_threads = match conf.pbs_threads {
+                0 => node.cpus.len(),
+                threads => threads.div_ceil(nodes.len()),
+            };
+            pools.push(NodePools {
+                compression: build_pool(
+                    Pool::Compression,
+                    conf.compression_threads.div_ceil(nodes.len()),
+                    Some(node),
+                )?,
+                pbs: build_pool(Pool::Pbs, pbs_threads, Some(node))?,
+            });
+        }
+        pools
+    };
+    if POOLS.set(pools).is_err() {
+        warn!(target: "scheduler", "Thread pools already initialized");
+    }
+    Ok(nodes.len())
+}
+
+fn build_pool(
+    pool: Pool,
+    threads: usize,
+    node: Option<&numa::NumaNode>,
+) -> Result<Option<ThreadPool>> {
+    if threads == 0 {
+        return Ok(None);
+    }
+    let node_id = node.map(|node| node.id);
+    let cpus = node.map(|node| node.cpus.clone());
+    let thread_pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(move |i| match node_id {
+            Some(node_id) => format!("{}-node{node_id}-{i}", pool.as_str()),
+            None => format!("{}-{i}", pool.as_str()),
+        })
+        .start_handler(move |_| {
+            let Some(cpus) = &cpus else {
+                return;
+            };
+            if let Err(e) = numa::pin_current_thread(cpus) {
+                warn!(target: "scheduler", node = node_id, error = %e, "Failed to pin thread");
+            }
+        })
+        .build()?;
+    info!(target: "scheduler", pool = pool.as_str(), threads, node = node_id, "Thread pool");
+    Ok(Some(thread_pool))
+}
+
+/// Node of the next partition, always 0 without NUMA pinning.
+pub(crate) fn next_node() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    match POOLS.get() {
+        Some(pools) if pools.len() > 1 => NEXT.fetch_add(1, Ordering::Relaxed) % pools.len(),
+        _ => 0,
+    }
+}
+
+/// Runs `f` in `pool` of `node`, or on the calling thread if the pool is not built.
+pub(crate) fn install<R: Send>(node: usize, pool: Pool, f: impl FnOnce() -> R + Send) -> R {
+    let Some(thread_pool) = POOLS
+        .get()
+        .and_then(|pools| pools.get(node))
+        .and_then(|pools| pools.get(pool))
+    else {
+        return f();
+    };
+    let label = pool.as_str();
+    let queued_at = Instant::now();
+    POOL_QUEUE_DEPTH.with_label_values(&[label]).inc();
+    thread_pool.install(|| {
+        POOL_QUEUE_DEPTH.with_label_values(&[label]).dec();
+        POOL_QUEUE_WAIT
+            .with_label_values(&[label])
+            .observe(queued_at.elapsed().as_secs_f64());
+        let active = POOL_ACTIVE_JOBS.with_label_values(&[label]);
+        active.inc();
+        let res = f();
+        active.dec();
+        res
+    })
+}
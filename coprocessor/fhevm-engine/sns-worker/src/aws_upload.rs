@@ -6,7 +6,11 @@ use aws_sdk_s3::operation::head_bucket::HeadBucketError;
 use aws_sdk_s3::operation::head_object::HeadObjectError;
 use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::Client;
+use bytes::Bytes;
 use bytesize::ByteSize;
+use fhevm_engine_common::ciphertext_format;
+use fhevm_engine_common::db_query::{run_query, QueryPolicy};
+use fhevm_engine_common::diagnostics;
 use fhevm_engine_common::pg_pool::{PostgresPoolManager, ServiceError};
 use fhevm_engine_common::telemetry::{self};
 use fhevm_engine_common::utils::compact_hex;
@@ -260,7 +264,7 @@ async fn upload_ciphertexts(
                     .bucket(conf.bucket_ct128.clone())
                     .metadata("Ct-Format", format_as_str)
                     .key(key)
-                    .body(ByteStream::from(ct128_bytes.to_vec()))
+                    .body(ByteStream::from(task.ct128.to_bytes()))
                     .send(),
                 UploadResult::CtType128((ct128_digest.clone(), span)),
             ));
@@ -278,7 +282,7 @@ async fn upload_ciphertexts(
     }
 
     if !task.ct64_compressed.is_empty() {
-        let ct64_compressed = task.ct64_compressed.as_ref();
+        let ct64_compressed = &task.ct64_compressed;
         info!(
             handle = handle_as_hex,
             len = ?ByteSize::b(ct64_compressed.len() as u64),
@@ -328,11 +332,23 @@ async fn upload_ciphertexts(
     }
 
     // Execute all uploads and collect results with their IDs
-    let results: Vec<(Result<_, _>, UploadResult, SystemTime)> = join_all(
-        jobs.into_iter()
-            .map(|(fut, upload)| async move { (fut.await, upload, SystemTime::now()) }),
-    )
-    .await;
+    let results: Vec<(Result<_, _>, UploadResult, SystemTime)> =
+        join_all(jobs.into_iter().map(|(fut, upload)| {
+            let (variant, bucket) = match upload {
+                UploadResult::CtType128(_) => ("ct128", &conf.bucket_ct128),
+                UploadResult::CtType64(_) => ("ct64", &conf.bucket_ct64),
+            };
+            let in_flight = diagnostics::track(
+                "s3_uploads",
+                format!("{variant} {handle_as_hex} to {bucket}"),
+            );
+            async move {
+                let result = fut.await;
+                drop(in_flight);
+                (result, upload, SystemTime::now())
+            }
+        }))
+        .await;
 
     let mut transient_error: Option<ExecutionError> = None;
 
@@ -395,7 +411,11 @@ async fn fetch_pending_uploads(
     db_pool: &Pool<Postgres>,
     limit: i64,
 ) -> Result<Vec<UploadJob>, ExecutionError> {
-    let rows = sqlx::query!(
+    let rows = run_query(
+        "sns_worker.fetch_pending_uploads",
+        &QueryPolicy::default(),
+        || {
+            sqlx::query!(
         "SELECT tenant_id, handle, ciphertext, ciphertext128, ciphertext128_format, transaction_id 
         FROM ciphertext_digest 
         WHERE ciphertext IS NULL OR ciphertext128 IS NULL
@@ -403,13 +423,15 @@ async fn fetch_pending_uploads(
         LIMIT $1;",
         limit
     )
-    .fetch_all(db_pool)
+            .fetch_all(db_pool)
+        },
+    )
     .await?;
 
     let mut jobs = Vec::new();
 
     for row in rows {
-        let mut ct64_compressed = Arc::new(Vec::new());
+        let mut ct64_compressed = Bytes::new();
         let mut ct128 = Vec::new();
         let ciphertext_digest = row.ciphertext;
         let ciphertext128_digest = row.ciphertext128;
@@ -427,7 +449,12 @@ async fn fetch_pending_uploads(
             .await
             {
                 if let Some(record) = row {
-                    ct64_compressed = Arc::new(record.ciphertext);
+                    match ciphertext_format::open_bytes(record.ciphertext.into()) {
+                        Ok((_, ct64)) => ct64_compressed = ct64,
+                        Err(err) => {
+                            error!(handle = hex::encode(&handle), error = %err, "Invalid ciphertext");
+                        }
+                    }
                 } else {
                     error!(handle = hex::encode(&handle), "Missing ciphertext");
                 }
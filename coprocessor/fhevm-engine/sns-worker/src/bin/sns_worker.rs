@@ -1,10 +1,28 @@
-use sns_worker::{Config, DBConfig, HealthCheckConfig, S3Config, S3RetryPolicy};
+use fhevm_engine_common::column_encryption::ColumnEncryption;
+use fhevm_engine_common::diagnostics;
+use fhevm_engine_common::secret::SecretString;
+use sns_worker::{
+    parse_api_keys, CiphertextApiConfig, Config, DBConfig, HealthCheckConfig, S3Config,
+    S3RetryPolicy, UserDecryptConfig,
+};
 
 use tokio::signal::unix;
 use tokio_util::sync::CancellationToken;
 use tracing::error;
+use zeroize::Zeroizing;
 mod utils;
 
+#[cfg(any(feature = "jemalloc", feature = "mimalloc"))]
+use fhevm_engine_common::allocator;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: allocator::Jemalloc = allocator::Jemalloc;
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+#[global_allocator]
+static GLOBAL: allocator::MiMalloc = allocator::MiMalloc;
+
 fn handle_sigint(token: CancellationToken) {
     tokio::spawn(async move {
         let mut signal = unix::signal(unix::SignalKind::interrupt()).unwrap();
@@ -21,13 +39,38 @@ fn construct_config() -> Config {
         .clone()
         .unwrap_or_else(|| std::env::var("DATABASE_URL").expect("DATABASE_URL is undefined"));
 
+    let column_encryption = args
+        .column_encryption_key
+        .as_deref()
+        .map(|secret| ColumnEncryption::from_secret(secret).expect("Invalid column encryption key"))
+        .unwrap_or_default();
+
+    let ciphertext_api = args.ciphertext_api_port.map(|port| CiphertextApiConfig {
+        port,
+        api_keys: args
+            .ciphertext_api_keys_file
+            .as_ref()
+            .map(|path| {
+                let content = Zeroizing::new(
+                    std::fs::read_to_string(path)
+                        .expect("Failed to read the ciphertext API keys file"),
+                );
+                parse_api_keys(&content).expect("Invalid ciphertext API keys file")
+            })
+            .unwrap_or_default(),
+        signature_max_age: args.ciphertext_api_signature_max_age,
+        column_encryption: column_encryption.clone(),
+    });
+
     Config {
         tenant_api_key: args.tenant_api_key,
         service_name: args.service_name,
         db: DBConfig {
             url: db_url,
+            schema: args.database_schema,
             listen_channels: args.pg_listen_channels,
             notify_channel: args.pg_notify_channel,
+            key_activated_channel: args.key_activated_listen_channel,
             batch_limit: args.work_items_batch_size,
             gc_batch_limit: args.gc_batch_size,
             polling_interval: args.pg_polling_interval,
@@ -52,10 +95,28 @@ fn construct_config() -> Config {
         health_checks: HealthCheckConfig {
             liveness_threshold: args.liveness_threshold,
             port: args.health_check_port,
+            admin_token: args
+                .admin_token
+                .or_else(|| std::env::var("ADMIN_TOKEN").ok().map(SecretString::from)),
         },
         enable_compression: args.enable_compression,
         schedule_policy: args.schedule_policy,
         pg_auto_explain_with_min_duration: args.pg_auto_explain_with_min_duration,
+        buffer_pool_max_bytes: args.buffer_pool_max_bytes,
+        keys_cache_dir: args.keys_cache_dir,
+        staging_dir: args.staging_dir,
+        diagnostics_dir: args.diagnostics_dir,
+        user_decrypt: args.kms_user_decrypt_url.map(|kms_url| UserDecryptConfig {
+            kms_url,
+            kms_request_timeout: args.kms_request_timeout,
+            kms_max_attempts: args.kms_max_attempts,
+            kms_retry_backoff: args.kms_retry_backoff,
+            listen_channel: args.user_decrypt_listen_channel,
+            batch_limit: args.user_decrypt_batch_size,
+            max_retries: args.user_decrypt_max_retries,
+            column_encryption,
+        }),
+        ciphertext_api,
     }
 }
 
@@ -80,6 +141,7 @@ async fn main() {
         .await
         .unwrap_or_else(|err| {
             error!(error = %err, "Error running SNS worker");
+            diagnostics::dump(&format!("fatal error: {err}"));
             std::process::exit(1);
         });
 }
@@ -1,6 +1,10 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
 use clap::{command, Parser};
+use fhevm_engine_common::buffer_pool;
+use fhevm_engine_common::db_schema;
+use fhevm_engine_common::secret::SecretString;
 use humantime::parse_duration;
 use sns_worker::SchedulePolicy;
 use tracing::Level;
@@ -28,6 +32,11 @@ pub struct Args {
     #[arg(long)]
     pub pg_notify_channel: String,
 
+    /// NOTIFY/LISTEN channel of the key activations, on which the keyset of
+    /// the tenant is reloaded if its key changed
+    #[arg(long, default_value = sns_worker::EVENT_KEY_ACTIVATED)]
+    pub key_activated_listen_channel: String,
+
     /// Polling interval in seconds
     #[arg(long, default_value_t = 60)]
     pub pg_polling_interval: u32,
@@ -49,6 +58,10 @@ pub struct Args {
     #[arg(long)]
     pub database_url: Option<String>,
 
+    /// Database schema of the host chain, in the schema-per-chain layout
+    #[arg(long, value_parser = db_schema::parse_schema_name)]
+    pub database_schema: Option<String>,
+
     /// KeySet file. If unspecified the the keys are read from the database
     #[arg(long)]
     pub keys_file_path: Option<String>,
@@ -118,6 +131,84 @@ pub struct Args {
     /// Schedule policy for processing tasks
     #[arg(long, default_value = "rayon_parallel", value_parser = clap::value_parser!(SchedulePolicy))]
     pub schedule_policy: SchedulePolicy,
+
+    /// Maximum total size of the serialization buffers kept for reuse, 0 disables the pool
+    #[arg(long, default_value_t = buffer_pool::DEFAULT_MAX_RETAINED_BYTES)]
+    pub buffer_pool_max_bytes: usize,
+
+    /// Directory where the server key is spilled and memory-mapped from,
+    /// which lowers the peak memory at key loading. The key is read in memory
+    /// if unspecified
+    #[arg(long)]
+    pub keys_cache_dir: Option<PathBuf>,
+
+    /// Directory where the squashed ciphertexts are staged until they are
+    /// stored, so that a restart resumes their uploads without squashing them
+    /// again. Not staged if unspecified
+    #[arg(long)]
+    pub staging_dir: Option<PathBuf>,
+
+    /// KMS endpoint re-encrypting user decryptions. User decryptions are not
+    /// processed if unspecified
+    #[arg(long)]
+    pub kms_user_decrypt_url: Option<String>,
+
+    /// Timeout of a re-encryption request to the KMS
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    pub kms_request_timeout: Duration,
+
+    /// Calls to the KMS per processing of a user decryption while it is
+    /// unavailable
+    #[arg(long, default_value_t = 3)]
+    pub kms_max_attempts: u32,
+
+    /// Delay before calling an unavailable KMS again, doubled on every attempt
+    #[arg(long, default_value = "500ms", value_parser = parse_duration)]
+    pub kms_retry_backoff: Duration,
+
+    /// NOTIFY/LISTEN channel of the new user decryption requests
+    #[arg(long, default_value = sns_worker::EVENT_USER_DECRYPTION_REQUEST)]
+    pub user_decrypt_listen_channel: String,
+
+    /// User decryption requests processed per batch
+    #[arg(long, default_value_t = 10)]
+    pub user_decrypt_batch_size: u32,
+
+    /// Attempts before a user decryption is marked as failed, e.g. while its
+    /// ciphertexts are not squashed or the KMS is unavailable
+    #[arg(long, default_value_t = 30)]
+    pub user_decrypt_max_retries: i32,
+
+    /// Column key of the user decryption requests and responses, file:<path>
+    /// or env:<variable> holding 32 hex-encoded bytes
+    #[arg(long)]
+    pub column_encryption_key: Option<String>,
+
+    /// Port of the HTTP API serving the ciphertexts to the relayers. Not
+    /// served if unspecified
+    #[arg(long)]
+    pub ciphertext_api_port: Option<u16>,
+
+    /// API keys of the ciphertext API, one <address>=<key> per line, the key
+    /// authenticating the account
+    #[arg(long)]
+    pub ciphertext_api_keys_file: Option<PathBuf>,
+
+    /// Validity of a request signed by an account, around its timestamp
+    #[arg(long, default_value = "5m", value_parser = parse_duration)]
+    pub ciphertext_api_signature_max_age: Duration,
+
+    /// Directory the diagnostics snapshot is written to on panic or fatal
+    /// error, only logged if unspecified
+    #[arg(long)]
+    pub diagnostics_dir: Option<PathBuf>,
+
+    /// Bearer token required by the admin endpoints of the health check
+    /// server, e.g. /admin/heap_profile. If unspecified ADMIN_TOKEN
+    /// environment variable is used, the admin endpoints are disabled if both
+    /// are unset
+    #[arg(long)]
+    pub admin_token: Option<SecretString>,
 }
 
 pub fn parse_args() -> Args {
@@ -0,0 +1,476 @@
+//! Authenticated HTTP API serving the squashed ciphertexts and the re-encrypted user decryption
+//! results, so that relayers do not need access to the buckets.
+//!
+//! Callers authenticate as an account, either:
+//! - with an API key, `Authorization: Bearer <key>`, bound to an account in the configuration
+//! - or by signing the request with the account: `X-Fhevm-Address`, `X-Fhevm-Timestamp` in
+//!   seconds since the epoch and `X-Fhevm-Signature`, the EIP-191 signature of
+//!   `fhevm-ciphertext-api:GET <path and query>:<timestamp>`, accepted for
+//!   `signature_max_age` around the timestamp
+//!
+//! Routes:
+//! - `GET /v1/ciphertexts/:handle`: the squashed ciphertext of the handle, from the database or
+//!   from the ct128 bucket once garbage collected. The account must be allowed on the handle, or
+//!   act for an allowed delegator with `?delegator=<address>&contract=<address>`, the contract
+//!   being allowed too and the delegation not expired, like for a user decryption. The digest and
+//!   the format are returned in `X-Ciphertext-Digest` and `X-Ciphertext-Format`, the client checks
+//!   the digest as the body is streamed.
+//! - `GET /v1/user-decryptions/:decryption_id`: the re-encrypted result and the KMS signature of
+//!   a user decryption requested by the account.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use alloy_primitives::{Address, Signature};
+use aws_sdk_s3::Client;
+use axum::{
+    body::Body,
+    extract::{OriginalUri, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use fhevm_engine_common::column_encryption::ColumnEncryption;
+use fhevm_engine_common::secret::SecretString;
+use fhevm_engine_common::types::DecryptionResponseType;
+use fhevm_engine_common::utils::compact_hex;
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::user_decrypt::active_delegation;
+use crate::{Ciphertext128Format, S3Config};
+
+pub const ADDRESS_HEADER: &str = "X-Fhevm-Address";
+pub const TIMESTAMP_HEADER: &str = "X-Fhevm-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Fhevm-Signature";
+pub const DIGEST_HEADER: &str = "X-Ciphertext-Digest";
+pub const FORMAT_HEADER: &str = "X-Ciphertext-Format";
+
+#[derive(Clone)]
+pub struct CiphertextApiConfig {
+    pub port: u16,
+    /// API keys and the account each one authenticates
+    pub api_keys: Vec<(SecretString, String)>,
+    /// Validity of a signed request around its timestamp
+    pub signature_max_age: Duration,
+    /// Encrypted columns of the user decryption requests and responses
+    pub column_encryption: ColumnEncryption,
+}
+
+impl fmt::Debug for CiphertextApiConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The API keys are secrets
+        f.debug_struct("CiphertextApiConfig")
+            .field("port", &self.port)
+            .field("api_keys", &self.api_keys.len())
+            .field("signature_max_age", &self.signature_max_age)
+            .field("column_encryption", &self.column_encryption)
+            .finish()
+    }
+}
+
+/// Parses the API keys file, one `<address>=<key>` per line, `#` starting a comment.
+pub fn parse_api_keys(content: &str) -> anyhow::Result<Vec<(SecretString, String)>> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (address, key) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("invalid API key line, expected <address>=<key>"))?;
+            let address: Address = address.trim().parse()?;
+            Ok((
+                key.trim().to_owned().into(),
+                address.to_string().to_lowercase(),
+            ))
+        })
+        .collect()
+}
+
+#[derive(thiserror::Error, Debug)]
+enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("{0}")]
+    Unauthorized(String),
+
+    #[error("{0}")]
+    Forbidden(String),
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("storage error: {0}")]
+    Storage(String),
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status_code = match &self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Database(err) => {
+                error!(error = %err, "Ciphertext API query failed");
+                return internal_error("database error");
+            }
+            ApiError::Storage(err) => {
+                error!(error = %err, "Ciphertext API download failed");
+                return internal_error("storage error");
+            }
+        };
+        (
+            status_code,
+            Json(serde_json::json!({ "error": self.to_string() })),
+        )
+            .into_response()
+    }
+}
+
+fn internal_error(message: &str) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({ "error": message })),
+    )
+        .into_response()
+}
+
+struct ApiState {
+    pool: Pool<Postgres>,
+    conf: CiphertextApiConfig,
+    s3_conf: S3Config,
+    client: Arc<Client>,
+}
+
+pub struct CiphertextApiServer {
+    state: Arc<ApiState>,
+    cancel_token: CancellationToken,
+}
+
+impl CiphertextApiServer {
+    pub fn new(
+        pool: Pool<Postgres>,
+        conf: CiphertextApiConfig,
+        s3_conf: S3Config,
+        client: Arc<Client>,
+        cancel_token: CancellationToken,
+    ) -> Self {
+        Self {
+            state: Arc::new(ApiState {
+                pool,
+                conf,
+                s3_conf,
+                client,
+            }),
+            cancel_token,
+        }
+    }
+
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/v1/ciphertexts/:handle", get(get_ciphertext))
+            .route(
+                "/v1/user-decryptions/:decryption_id",
+                get(get_user_decryption),
+            )
+            .with_state(self.state.clone())
+    }
+
+    pub async fn start(&self) -> anyhow::Result<()> {
+        let addr = SocketAddr::from(([0, 0, 0, 0], self.state.conf.port));
+        info!(
+            address = %addr,
+            api_keys = self.state.conf.api_keys.len(),
+            "Starting ciphertext API server"
+        );
+        let cancel_token = self.cancel_token.clone();
+        let listener = TcpListener::bind(addr).await?;
+        axum::serve(listener, self.router().into_make_service())
+            .with_graceful_shutdown(async move { cancel_token.cancelled().await })
+            .await?;
+        Ok(())
+    }
+}
+
+fn decode_hex(value: &str, what: &str) -> Result<Vec<u8>, ApiError> {
+    hex::decode(value.strip_prefix("0x").unwrap_or(value))
+        .map_err(|err| ApiError::BadRequest(format!("invalid {what}: {err}")))
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Returns the lowercase address of the authenticated account.
+fn authenticate(
+    conf: &CiphertextApiConfig,
+    headers: &HeaderMap,
+    uri: &OriginalUri,
+) -> Result<String, ApiError> {
+    if let Some(provided) = header_value(headers, header::AUTHORIZATION.as_str())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        return conf
+            .api_keys
+            .iter()
+            .find(|(key, _)| key.matches(provided))
+            .map(|(_, account)| account.clone())
+            .ok_or_else(|| ApiError::Unauthorized("unknown API key".to_owned()));
+    }
+
+    let (Some(address), Some(timestamp), Some(signature)) = (
+        header_value(headers, ADDRESS_HEADER),
+        header_value(headers, TIMESTAMP_HEADER),
+        header_value(headers, SIGNATURE_HEADER),
+    ) else {
+        return Err(ApiError::Unauthorized(
+            "missing API key or request signature".to_owned(),
+        ));
+    };
+    let address: Address = address
+        .parse()
+        .map_err(|_| ApiError::Unauthorized("invalid address".to_owned()))?;
+    let signed_at: u64 = timestamp
+        .parse()
+        .map_err(|_| ApiError::Unauthorized("invalid timestamp".to_owned()))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if now.abs_diff(signed_at) > conf.signature_max_age.as_secs() {
+        return Err(ApiError::Unauthorized("expired signature".to_owned()));
+    }
+    let signature = decode_hex(signature, "signature")
+        .ok()
+        .and_then(|signature| Signature::from_raw(&signature).ok())
+        .ok_or_else(|| ApiError::Unauthorized("invalid signature".to_owned()))?;
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or_else(|| uri.path());
+    let message = signed_message(path, timestamp);
+    match signature.recover_address_from_msg(message.as_bytes()) {
+        Ok(signer) if signer == address => Ok(address.to_string().to_lowercase()),
+        _ => Err(ApiError::Unauthorized(
+            "signature does not match the address".to_owned(),
+        )),
+    }
+}
+
+/// Message signed by the account for a request to `path`, query included.
+pub fn signed_message(path: &str, timestamp: &str) -> String {
+    format!("fhevm-ciphertext-api:GET {path}:{timestamp}")
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct OnBehalf {
+    delegator: Option<String>,
+    contract: Option<String>,
+}
+
+async fn get_ciphertext(
+    State(state): State<Arc<ApiState>>,
+    Path(handle): Path<String>,
+    Query(on_behalf): Query<OnBehalf>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let account = authenticate(&state.conf, &headers, &uri)?;
+    let handle = decode_hex(&handle, "handle")?;
+
+    let row = sqlx::query!(
+        "
+        SELECT d.tenant_id, d.ciphertext128 AS digest, d.ciphertext128_format, c.ciphertext128
+        FROM ciphertext_digest d
+        LEFT JOIN ciphertexts c ON c.tenant_id = d.tenant_id AND c.handle = d.handle
+        WHERE d.handle = $1
+        ORDER BY c.ciphertext_version DESC NULLS LAST
+        LIMIT 1
+        ",
+        &handle,
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+    let Some(row) = row else {
+        return Err(ApiError::NotFound(format!(
+            "handle {} is not computed yet",
+            compact_hex(&handle)
+        )));
+    };
+
+    check_access(&state, &handle, row.tenant_id, &account, &on_behalf).await?;
+
+    let Some(digest) = row.digest else {
+        return Err(ApiError::NotFound(format!(
+            "squashed ciphertext of handle {} is not uploaded yet",
+            compact_hex(&handle)
+        )));
+    };
+    let format = Ciphertext128Format::from_i16(row.ciphertext128_format).unwrap_or_default();
+    let headers = [
+        (
+            header::CONTENT_TYPE.as_str(),
+            "application/octet-stream".to_owned(),
+        ),
+        (DIGEST_HEADER, hex::encode(&digest)),
+        (FORMAT_HEADER, format.to_string()),
+    ];
+    info!(
+        handle = compact_hex(&handle),
+        account, "Serving squashed ciphertext"
+    );
+
+    if let Some(ciphertext) = row.ciphertext128 {
+        return Ok((headers, ciphertext).into_response());
+    }
+
+    // Garbage collected from the database once uploaded
+    let key = if cfg!(feature = "test_s3_use_handle_as_key") {
+        hex::encode(&handle)
+    } else {
+        hex::encode(&digest)
+    };
+    let object = state
+        .client
+        .get_object()
+        .bucket(&state.s3_conf.bucket_ct128)
+        .key(&key)
+        .send()
+        .await
+        .map_err(|err| ApiError::Storage(err.to_string()))?;
+    let stream = futures::stream::unfold(object.body, |mut body| async move {
+        body.next().await.map(|chunk| (chunk, body))
+    });
+    Ok((headers, Body::from_stream(stream)).into_response())
+}
+
+/// Checks the ACL of the handle for the account, or for the delegator it acts for.
+async fn check_access(
+    state: &ApiState,
+    handle: &[u8],
+    tenant_id: i32,
+    account: &str,
+    on_behalf: &OnBehalf,
+) -> Result<(), ApiError> {
+    let forbidden = |reason: String| {
+        warn!(
+            handle = compact_hex(handle),
+            account, reason, "Ciphertext access denied"
+        );
+        Err(ApiError::Forbidden(reason))
+    };
+    let delegation = match (&on_behalf.delegator, &on_behalf.contract) {
+        (None, None) => None,
+        (Some(delegator), Some(contract)) => {
+            Some((delegator.to_lowercase(), contract.to_lowercase()))
+        }
+        _ => {
+            return Err(ApiError::BadRequest(
+                "delegator and contract go together".to_owned(),
+            ))
+        }
+    };
+    let mut accounts = vec![account.to_owned()];
+    if let Some((delegator, contract)) = &delegation {
+        accounts.extend([delegator.clone(), contract.clone()]);
+    }
+
+    let allowed: Vec<String> = sqlx::query_scalar!(
+        "SELECT LOWER(account_address) AS \"account_address!\"
+             FROM allowed_handles
+             WHERE handle = $1 AND LOWER(account_address) = ANY($2)",
+        handle,
+        &accounts,
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    let Some((delegator, contract)) = delegation else {
+        if allowed.iter().any(|allowed| allowed == account) {
+            return Ok(());
+        }
+        return forbidden(format!("account {account} is not allowed on the handle"));
+    };
+    for allowed_account in [&delegator, &contract] {
+        if !allowed.contains(allowed_account) {
+            return forbidden(format!("{allowed_account} is not allowed on the handle"));
+        }
+    }
+    let delegation = active_delegation(
+        &state.pool,
+        &state.conf.column_encryption,
+        tenant_id,
+        &delegator,
+        account,
+        &contract,
+    )
+    .await?;
+    let Some(delegation) = delegation else {
+        return forbidden(format!(
+            "account {account} has no delegation from {delegator} for contract {contract}"
+        ));
+    };
+    if !delegation.is_confirmed {
+        return forbidden(format!(
+            "delegation from {delegator} for contract {contract} is not confirmed yet"
+        ));
+    }
+    Ok(())
+}
+
+async fn get_user_decryption(
+    State(state): State<Arc<ApiState>>,
+    Path(decryption_id): Path<String>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let account = authenticate(&state.conf, &headers, &uri)?;
+    let decryption_id = decode_hex(&decryption_id, "decryption id")?;
+
+    let row = sqlx::query!(
+        "SELECT r.user_address, d.result, d.signature
+         FROM user_decryption_requests r
+         LEFT JOIN decryption_responses d
+           ON d.decryption_id = r.decryption_id AND d.response_type = $2
+         WHERE r.decryption_id = $1",
+        &decryption_id,
+        DecryptionResponseType::User as i16,
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+    let not_found = || ApiError::NotFound("unknown user decryption".to_owned());
+    let row = row.ok_or_else(not_found)?;
+    let column_encryption = &state.conf.column_encryption;
+    let user_address = column_encryption
+        .decrypt_text(row.user_address)
+        .map_err(|err| ApiError::Storage(err.to_string()))?;
+    if user_address.to_lowercase() != account {
+        // Not telling apart the decryptions of other users from unknown ones
+        return Err(not_found());
+    }
+    let (Some(result), Some(signature)) = (row.result, row.signature) else {
+        return Err(ApiError::NotFound(
+            "user decryption is not completed yet".to_owned(),
+        ));
+    };
+    let result = column_encryption
+        .decrypt(result)
+        .map_err(|err| ApiError::Storage(err.to_string()))?;
+    Ok(Json(serde_json::json!({
+        "decryption_id": format!("0x{}", hex::encode(&decryption_id)),
+        "result": format!("0x{}", hex::encode(result)),
+        "signature": format!("0x{}", hex::encode(signature)),
+    })))
+}
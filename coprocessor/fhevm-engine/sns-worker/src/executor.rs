@@ -1,6 +1,7 @@
 use crate::aws_upload::check_is_ready;
-use crate::keyset::fetch_keyset;
+use crate::keyset::{fetch_key_id, fetch_keyset, fetch_public_key};
 use crate::squash_noise::SquashNoiseCiphertext;
+use crate::staging::{Staging, MAX_STAGING_AGE};
 use crate::BigCiphertext;
 use crate::Ciphertext128Format;
 use crate::HandleItem;
@@ -10,24 +11,33 @@ use crate::SchedulePolicy;
 use crate::UploadJob;
 use crate::{Config, ExecutionError};
 use aws_sdk_s3::Client;
+use fhevm_engine_common::ciphertext_format;
+use fhevm_engine_common::db_query::{timed_query, QueryPolicy};
+use fhevm_engine_common::diagnostics;
 use fhevm_engine_common::healthz_server::{HealthCheckService, HealthStatus, Version};
+use fhevm_engine_common::key_version;
+use fhevm_engine_common::param_set::{ParamSet, DEFAULT_PARAM_SET};
+use fhevm_engine_common::pg_listener::{ListenerEvent, SupervisedListener};
 use fhevm_engine_common::pg_pool::PostgresPoolManager;
 use fhevm_engine_common::pg_pool::ServiceError;
 use fhevm_engine_common::telemetry;
 use fhevm_engine_common::telemetry::gen_buckets;
 use fhevm_engine_common::types::{get_ct_type, SupportedFheCiphertexts};
 use fhevm_engine_common::utils::compact_hex;
-use prometheus::register_histogram;
-use prometheus::Histogram;
+use fhevm_engine_common::warmup::{self, Readiness};
+use prometheus::{register_histogram, register_int_counter};
+use prometheus::{Histogram, IntCounter};
 use rayon::prelude::*;
-use sqlx::postgres::PgListener;
 use sqlx::Pool;
 use sqlx::{PgPool, Postgres, Row, Transaction};
+use std::collections::HashSet;
 use std::fmt;
 use std::num::NonZeroUsize;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::LazyLock;
 use std::time::Duration;
+use std::time::Instant;
 use std::time::SystemTime;
 use tfhe::set_server_key;
 use tfhe::ClientKey;
@@ -53,6 +63,14 @@ pub(crate) static SNS_LATENCY_HISTOGRAM: LazyLock<Histogram> = LazyLock::new(||
     .unwrap()
 });
 
+static SNS_PRIORITY_TASKS_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_sns_priority_tasks_counter",
+        "Number of SnS tasks fetched ahead of the others as awaited by a decryption"
+    )
+    .unwrap()
+});
+
 #[derive(Debug, Clone, Copy)]
 pub enum Order {
     Asc,
@@ -79,6 +97,9 @@ pub struct SwitchNSquashService {
 
     /// Channel to emit internal events, e.g. keys-loaded event
     events_tx: InternalEvents,
+
+    /// Set once the keys are warmed up
+    readiness: Readiness,
 }
 impl HealthCheckService for SwitchNSquashService {
     async fn health_check(&self) -> HealthStatus {
@@ -123,6 +144,10 @@ impl HealthCheckService for SwitchNSquashService {
             < threshold.as_secs() as u32
     }
 
+    async fn is_ready(&self) -> bool {
+        self.readiness.is_ready()
+    }
+
     fn get_version(&self) -> Version {
         // Later, the unknowns will be initialized from build.rs
         Version {
@@ -150,6 +175,7 @@ impl SwitchNSquashService {
             s3_client,
             tx,
             events_tx,
+            readiness: Readiness::default(),
         })
     }
 
@@ -164,6 +190,7 @@ impl SwitchNSquashService {
             let last_active_at = self.last_active_at.clone();
             let keys_cache = keys_cache.clone();
             let events_tx = self.events_tx.clone();
+            let readiness = self.readiness.clone();
 
             async move {
                 run_loop(
@@ -174,6 +201,7 @@ impl SwitchNSquashService {
                     last_active_at.clone(),
                     keys_cache,
                     events_tx,
+                    readiness,
                 )
                 .await
                 .map_err(ServiceError::from)
@@ -188,6 +216,7 @@ async fn get_keyset(
     pool: PgPool,
     keys_cache: Arc<RwLock<lru::LruCache<String, KeySet>>>,
     tenant_api_key: &String,
+    keys_cache_dir: Option<&Path>,
 ) -> Result<Option<KeySet>, ExecutionError> {
     let _t = telemetry::tracer("fetch_keyset", &None);
     {
@@ -197,11 +226,13 @@ async fn get_keyset(
             return Ok(Some(keys.clone()));
         }
     }
-    let keys: Option<KeySet> = fetch_keyset(&keys_cache, &pool, tenant_api_key).await?;
+    let keys: Option<KeySet> =
+        fetch_keyset(&keys_cache, &pool, tenant_api_key, keys_cache_dir).await?;
     Ok(keys)
 }
 
 /// Executes the worker logic for the SnS task.
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn run_loop(
     conf: Config,
     tx: Sender<UploadJob>,
@@ -210,30 +241,76 @@ pub(crate) async fn run_loop(
     last_active_at: Arc<RwLock<SystemTime>>,
     keys_cache: Arc<RwLock<lru::LruCache<String, KeySet>>>,
     events_tx: InternalEvents,
+    readiness: Readiness,
 ) -> Result<(), ExecutionError> {
     update_last_active(last_active_at.clone()).await;
 
     let tenant_api_key = &conf.tenant_api_key;
-    let mut listener = PgListener::connect_with(&pool).await?;
+    let channels = conf
+        .db
+        .listen_channels
+        .iter()
+        .chain([&conf.db.key_activated_channel])
+        .cloned();
+    let mut listener = SupervisedListener::connect(&pool, channels).await?;
     info!("Connected to PostgresDB");
 
-    listener
-        .listen_all(conf.db.listen_channels.iter().map(|v| v.as_str()))
-        .await?;
-
     let mut keys = None;
+    // The key id of the tenant is checked again on key activation, after a reconnection and once
+    // per polling interval
+    let mut key_checked_at: Option<Instant> = None;
+    let key_check_interval = Duration::from_secs(conf.db.polling_interval.into());
     let mut gc_ticker = interval(conf.db.cleanup_interval);
     let mut gc_timestamp = SystemTime::now();
     let mut polling_ticker = interval(Duration::from_secs(conf.db.polling_interval.into()));
 
+    let staging = conf.staging_dir.as_deref().and_then(|dir| {
+        let staging =
+            Staging::new(dir).and_then(|staging| staging.purge(MAX_STAGING_AGE).map(|_| staging));
+        if let Err(err) = &staging {
+            error!(error = %err, dir = ?dir, "Failed to open the staging directory, disabled");
+        }
+        staging.ok()
+    });
+
     loop {
         // Continue looping until the service is cancelled or a critical error occurs
         update_last_active(last_active_at.clone()).await;
 
+        // Squash with the current key of the tenant, a stale keyset is loaded again
+        let key_check_due = key_checked_at.is_none_or(|at| at.elapsed() >= key_check_interval);
+        if let (Some(current), true) = (&keys, key_check_due) {
+            let key_id = fetch_key_id(&pool, tenant_api_key).await?;
+            key_checked_at = Some(Instant::now());
+            if key_id != current.key_id {
+                info!(
+                    tenant_api_key = tenant_api_key,
+                    "Tenant key changed, reloading keyset"
+                );
+                keys_cache.write().await.pop(tenant_api_key);
+                keys = None;
+            }
+        }
+
         let Some(keys) = keys.as_ref() else {
-            keys = get_keyset(pool.clone(), keys_cache.clone(), tenant_api_key).await?;
-            if keys.is_some() {
+            let started_at = Instant::now();
+            keys = get_keyset(
+                pool.clone(),
+                keys_cache.clone(),
+                tenant_api_key,
+                conf.keys_cache_dir.as_deref(),
+            )
+            .await?;
+            if let Some(keys) = &keys {
+                key_checked_at = Some(Instant::now());
                 info!(tenant_api_key = tenant_api_key, "Fetched keyset");
+                let server_key = keys.server_key.clone();
+                let public_key = fetch_public_key(&pool, tenant_api_key).await?;
+                tokio::task::spawn_blocking(move || warmup::run_canary(&server_key, &public_key))
+                    .await
+                    .map_err(anyhow::Error::from)??;
+                warmup::observe_warm_up("sns_pk", started_at);
+                readiness.set_ready(true);
                 // Notify that the keys are loaded
                 if let Some(events_tx) = &events_tx {
                     let _ = events_tx.try_send("event_keys_loaded");
@@ -252,7 +329,8 @@ pub(crate) async fn run_loop(
             continue;
         };
 
-        let maybe_remaining = fetch_and_execute_sns_tasks(&pool, &tx, keys, &conf, &token).await?;
+        let maybe_remaining =
+            fetch_and_execute_sns_tasks(&pool, &tx, keys, &conf, staging.as_ref(), &token).await?;
         if maybe_remaining {
             if token.is_cancelled() {
                 return Ok(());
@@ -273,8 +351,19 @@ pub(crate) async fn run_loop(
 
         select! {
             _ = token.cancelled() => return Ok(()),
-            n = listener.try_recv() => {
-                info!( notification = ?n, "Received notification");
+            event = listener.recv() => {
+                match event {
+                    ListenerEvent::Notification(n) if n.channel() == conf.db.key_activated_channel => {
+                        // The key of the tenant is compared at the start of the next iteration
+                        info!(key_id = n.payload(), "Key activated, checking the keyset");
+                        key_checked_at = None;
+                    }
+                    ListenerEvent::Reconnected => {
+                        info!("Listener reconnected, checking the keyset");
+                        key_checked_at = None;
+                    }
+                    event => info!(event = ?event, "Received notification"),
+                }
             },
             _ = polling_ticker.tick() => {
                 debug!( "Polling timeout, rechecking for tasks");
@@ -336,6 +425,7 @@ async fn fetch_and_execute_sns_tasks(
     tx: &Sender<UploadJob>,
     keys: &KeySet,
     conf: &Config,
+    staging: Option<&Staging>,
     token: &CancellationToken,
 ) -> Result<bool, ExecutionError> {
     let mut db_txn = match pool.begin().await {
@@ -358,6 +448,25 @@ async fn fetch_and_execute_sns_tasks(
     if let Some(mut tasks) = query_sns_tasks(trx, conf.db.batch_limit, order).await? {
         maybe_remaining = conf.db.batch_limit as usize == tasks.len();
 
+        let rejected =
+            reject_key_mismatches(trx, &tasks, keys.key_id.as_deref(), keys.param_set).await?;
+        tasks.retain(|task| !rejected.contains(&task.handle));
+        if tasks.is_empty() {
+            db_txn.commit().await?;
+            return Ok(maybe_remaining);
+        }
+
+        diagnostics::set(
+            "claimed_sns_tasks",
+            tasks.iter().map(|task| {
+                format!(
+                    "tenant {} handle {}",
+                    task.tenant_id,
+                    compact_hex(&task.handle)
+                )
+            }),
+        );
+
         let t = telemetry::tracer("batch_execution", &None);
         t.set_attribute("count", tasks.len().to_string());
 
@@ -367,6 +476,7 @@ async fn fetch_and_execute_sns_tasks(
             tx,
             conf.enable_compression,
             conf.schedule_policy,
+            staging,
             token.clone(),
         )?;
 
@@ -383,6 +493,13 @@ async fn fetch_and_execute_sns_tasks(
 
         db_txn.commit().await?;
 
+        // The ct128 are in the database from now on
+        if let Some(staging) = staging {
+            for task in tasks.iter() {
+                staging.remove(task.tenant_id, &task.handle);
+            }
+        }
+
         for task in tasks.iter() {
             if let Some(transaction_id) = &task.transaction_id {
                 telemetry::try_end_l1_transaction(pool, transaction_id).await?;
@@ -396,6 +513,9 @@ async fn fetch_and_execute_sns_tasks(
 }
 
 /// Queries the database for a fixed number of tasks.
+///
+/// Tasks awaited by a decryption, i.e. with a higher priority, are fetched first, `order` applies
+/// within a priority.
 pub async fn query_sns_tasks(
     db_txn: &mut Transaction<'_, Postgres>,
     limit: u32,
@@ -411,17 +531,22 @@ pub async fn query_sns_tasks(
         ON a.handle = c.handle
         WHERE c.ciphertext IS NOT NULL
         AND a.is_completed = FALSE
-        ORDER BY a.created_at {}
+        AND a.is_error = FALSE
+        ORDER BY a.priority DESC, a.created_at {}
         FOR UPDATE SKIP LOCKED
         LIMIT $1;
         ",
         order
     );
 
-    let records = sqlx::query(&query)
-        .bind(limit as i64)
-        .fetch_all(db_txn.as_mut())
-        .await?;
+    let records = timed_query(
+        "sns_worker.query_sns_tasks",
+        &QueryPolicy::default(),
+        sqlx::query(&query)
+            .bind(limit as i64)
+            .fetch_all(db_txn.as_mut()),
+    )
+    .await?;
 
     info!(target: "worker", { count = records.len(), order = order.to_string() }, "Fetched SnS tasks");
 
@@ -433,6 +558,12 @@ pub async fn query_sns_tasks(
     t.set_attribute("count", records.len().to_string());
     t.end();
 
+    let prioritized = records
+        .iter()
+        .filter(|record| record.try_get::<i16, _>("priority").is_ok_and(|p| p > 0))
+        .count();
+    SNS_PRIORITY_TASKS_COUNTER.inc_by(prioritized as u64);
+
     // Convert the records into HandleItem structs
     let tasks = records
         .into_iter()
@@ -445,7 +576,7 @@ pub async fn query_sns_tasks(
             Ok(HandleItem {
                 tenant_id,
                 handle: handle.clone(),
-                ct64_compressed: Arc::new(ciphertext),
+                ct64_compressed: ciphertext_format::open_bytes(ciphertext.into())?.1,
                 ct128: Arc::new(BigCiphertext::default()), // to be computed
                 otel: telemetry::tracer_with_handle("task", handle, &transaction_id),
                 transaction_id,
@@ -456,6 +587,59 @@ pub async fn query_sns_tasks(
     Ok(Some(tasks))
 }
 
+/// Rejects the claimed tasks whose ciphertext is under another key or parameter set than the
+/// keyset, see [`fhevm_engine_common::key_version`] and [`fhevm_engine_common::param_set`].
+/// Rejected tasks are not picked up again.
+///
+/// Returns the handles of the rejected tasks.
+async fn reject_key_mismatches(
+    db_txn: &mut Transaction<'_, Postgres>,
+    tasks: &[HandleItem],
+    key_id: Option<&[u8]>,
+    param_set: &ParamSet,
+) -> Result<HashSet<Vec<u8>>, ExecutionError> {
+    let handles: Vec<Vec<u8>> = tasks.iter().map(|task| task.handle.clone()).collect();
+    let records = sqlx::query!(
+        "
+        SELECT a.tenant_id, a.handle, c.key_id, c.param_set
+        FROM pbs_computations a
+        JOIN ciphertexts c
+        ON a.handle = c.handle
+        WHERE a.handle = ANY($1::BYTEA[])
+        AND (c.key_id <> $2 OR COALESCE(c.param_set, $3) <> $4);
+        ",
+        &handles,
+        key_id,
+        DEFAULT_PARAM_SET.name,
+        param_set.name
+    )
+    .fetch_all(db_txn.as_mut())
+    .await?;
+
+    let mut rejected = HashSet::new();
+    for record in records {
+        let message = if key_version::is_compatible(record.key_id.as_deref(), key_id) {
+            param_set.mismatch_message("sns_worker", record.param_set.as_deref())
+        } else {
+            key_version::mismatch_message("sns_worker", record.key_id.as_deref(), key_id)
+        };
+        error!(handle = compact_hex(&record.handle), error = %message, "Rejecting SnS task");
+        sqlx::query!(
+            "
+            UPDATE pbs_computations
+            SET is_error = TRUE, error_message = $1
+            WHERE tenant_id = $2 AND handle = $3;",
+            message,
+            record.tenant_id,
+            record.handle
+        )
+        .execute(db_txn.as_mut())
+        .await?;
+        rejected.insert(record.handle);
+    }
+    Ok(rejected)
+}
+
 async fn enqueue_upload_tasks(
     db_txn: &mut Transaction<'_, Postgres>,
     tasks: &[HandleItem],
@@ -471,12 +655,15 @@ async fn enqueue_upload_tasks(
 /// This uses the `rayon` to parallelize the squash_noise_and_serialize.
 ///
 /// The computed ciphertexts are sent to the upload worker via the provided channel.
+/// With a staging directory, they are staged as well and the ciphertexts already staged
+/// are not computed again.
 fn process_tasks(
     batch: &mut [HandleItem],
     keys: &KeySet,
     tx: &Sender<UploadJob>,
     enable_compression: bool,
     policy: SchedulePolicy,
+    staging: Option<&Staging>,
     token: CancellationToken,
 ) -> Result<(), ExecutionError> {
     set_server_key(keys.server_key.clone());
@@ -488,6 +675,8 @@ fn process_tasks(
                     task,
                     tx,
                     enable_compression,
+                    staging,
+                    keys.key_id.as_deref(),
                     token.clone(),
                     &keys.client_key,
                 );
@@ -503,6 +692,8 @@ fn process_tasks(
                     task,
                     tx,
                     enable_compression,
+                    staging,
+                    keys.key_id.as_deref(),
                     token.clone(),
                     &keys.client_key,
                 );
@@ -513,10 +704,13 @@ fn process_tasks(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn compute_task(
     task: &mut HandleItem,
     tx: &Sender<UploadJob>,
     enable_compression: bool,
+    staging: Option<&Staging>,
+    key_id: Option<&[u8]>,
     token: CancellationToken,
     _client_key: &Option<ClientKey>,
 ) {
@@ -539,6 +733,15 @@ fn compute_task(
         return; // Skip empty ciphertexts
     }
 
+    if let Some(ct128) =
+        staging.and_then(|staging| staging.load(task.tenant_id, &task.handle, key_id))
+    {
+        info!({ handle }, "Resuming staged ciphertext");
+        task.ct128 = Arc::new(ct128);
+        send_upload_job(task, tx);
+        return;
+    }
+
     let s = task.otel.child_span("decompress_ct64");
 
     let ct = decompress_ct(&task.handle, ct64_compressed).unwrap(); // TODO handle error properly
@@ -571,28 +774,14 @@ fn compute_task(
 
             task.ct128 = Arc::new(BigCiphertext::new(bytes, format));
 
-            // Start uploading the ciphertexts as soon as the ct128 is computed
-            //
-            // The service must continue running the squashed noise algorithm,
-            // regardless of the availability of the upload worker.
-            if let Err(err) = tx
-                .try_send(UploadJob::Normal(task.clone()))
-                .map_err(|err| ExecutionError::InternalSendError(err.to_string()))
-            {
-                // This could happen if either we are experiencing a burst of tasks
-                // or the upload worker cannot recover the connection to AWS S3
-                //
-                // In this case, we should log the error and rely on the retry mechanism.
-                //
-                // There are three levels of task buffering:
-                // 1. The spawned uploading tasks (size: conf.max_concurrent_uploads)
-                // 2. The input channel of the upload worker (size: conf.max_concurrent_uploads * 10)
-                // 3. The PostgresDB (size: unlimited)
-
-                error!({ action = "review", error = %err }, "Failed to send task to upload worker");
-                telemetry::end_span_with_err(task.otel.child_span("send_task"), err.to_string());
+            if let Some(staging) = staging {
+                if let Err(err) = staging.stage(task.tenant_id, &task.handle, key_id, &task.ct128) {
+                    warn!({ handle = handle, error = %err }, "Failed to stage ct128");
+                }
             }
 
+            send_upload_job(task, tx);
+
             let elapsed = started_at.elapsed().map(|d| d.as_secs_f64()).unwrap_or(0.0);
             if elapsed > 0.0 {
                 SNS_LATENCY_HISTOGRAM.observe(elapsed);
@@ -605,6 +794,30 @@ fn compute_task(
     };
 }
 
+/// Starts uploading the ciphertexts as soon as the ct128 is computed
+///
+/// The service must continue running the squashed noise algorithm,
+/// regardless of the availability of the upload worker.
+fn send_upload_job(task: &HandleItem, tx: &Sender<UploadJob>) {
+    if let Err(err) = tx
+        .try_send(UploadJob::Normal(task.clone()))
+        .map_err(|err| ExecutionError::InternalSendError(err.to_string()))
+    {
+        // This could happen if either we are experiencing a burst of tasks
+        // or the upload worker cannot recover the connection to AWS S3
+        //
+        // In this case, we should log the error and rely on the retry mechanism.
+        //
+        // There are three levels of task buffering:
+        // 1. The spawned uploading tasks (size: conf.max_concurrent_uploads)
+        // 2. The input channel of the upload worker (size: conf.max_concurrent_uploads * 10)
+        // 3. The PostgresDB (size: unlimited)
+
+        error!({ action = "review", error = %err }, "Failed to send task to upload worker");
+        telemetry::end_span_with_err(task.otel.child_span("send_task"), err.to_string());
+    }
+}
+
 /// Updates the database with the computed large ciphertexts.
 ///
 /// The ct128 is temporarily stored in PostgresDB to ensure reliability.
@@ -1,10 +1,14 @@
 use fhevm_engine_common::{
-    tenant_keys::read_keys_from_large_object, utils::safe_deserialize_sns_key,
+    key_file::{map_large_object, observe_key_load},
+    param_set::ParamSet,
+    tenant_keys::{query_large_object_oid, read_keys_from_large_object},
+    utils::{safe_deserialize_key, safe_deserialize_sns_key},
 };
 use sqlx::{PgPool, Row};
-use std::sync::Arc;
+use std::{path::Path, sync::Arc, time::Instant};
 use tokio::sync::RwLock;
 use tracing::info;
+use zeroize::Zeroizing;
 
 use crate::{ExecutionError, KeySet};
 
@@ -15,6 +19,7 @@ pub(crate) async fn fetch_keyset(
     cache: &Arc<RwLock<lru::LruCache<String, KeySet>>>,
     pool: &PgPool,
     tenant_api_key: &String,
+    keys_cache_dir: Option<&Path>,
 ) -> Result<Option<KeySet>, ExecutionError> {
     let mut cache = cache.write().await;
     if let Some(keys) = cache.get(tenant_api_key) {
@@ -23,12 +28,17 @@ pub(crate) async fn fetch_keyset(
     }
 
     info!(tenant_api_key, "Cache miss");
-    let Some((client_key, server_key)) = fetch_keys(pool, tenant_api_key).await? else {
+    let Some((client_key, server_key)) = fetch_keys(pool, tenant_api_key, keys_cache_dir).await?
+    else {
         return Ok(None);
     };
+    let key_id = fetch_key_id(pool, tenant_api_key).await?;
+    let param_set = fetch_param_set(pool, tenant_api_key).await?;
     let key_set: KeySet = KeySet {
         client_key,
         server_key,
+        key_id,
+        param_set,
     };
 
     cache.push(tenant_api_key.clone(), key_set.clone());
@@ -39,32 +49,105 @@ pub(crate) async fn fetch_keyset(
 ///
 /// The ServerKey is stored in a large object (LOB) in the database.
 /// ServerKey must be generated with enable_noise_squashing option.
+/// With a keys cache directory, the LOB is spilled to a key file and the
+/// ServerKey is deserialized from its memory mapping, see
+/// [`fhevm_engine_common::key_file`].
 ///
 /// The ClientKey is stored in a bytea column and is optional. It's used only
 /// for decrypting on testing.
 pub async fn fetch_keys(
     pool: &PgPool,
     tenant_api_key: &String,
+    keys_cache_dir: Option<&Path>,
 ) -> anyhow::Result<Option<(Option<tfhe::ClientKey>, tfhe::ServerKey)>> {
-    let blob = read_keys_from_large_object(
-        pool,
-        tenant_api_key,
-        "sns_pk",
-        SKS_KEY_WITH_NOISE_SQUASHING_SIZE,
-    )
-    .await?;
-    info!(bytes_len = blob.len(), "Retrieved sns_pk");
-    if blob.is_empty() {
-        return Ok(None);
-    }
-
-    let server_key: tfhe::ServerKey = safe_deserialize_sns_key(&blob)?;
+    let started_at = Instant::now();
+    let (server_key, serialized_len) = match keys_cache_dir {
+        Some(dir) => {
+            let oid = query_large_object_oid(pool, tenant_api_key, "sns_pk").await?;
+            let mapped = map_large_object(pool, dir, "sns_pk", oid).await?;
+            info!(bytes_len = mapped.as_slice().len(), "Mapped sns_pk");
+            if mapped.as_slice().is_empty() {
+                return Ok(None);
+            }
+            let server_key: tfhe::ServerKey = safe_deserialize_sns_key(mapped.as_slice())?;
+            (server_key, mapped.as_slice().len())
+        }
+        None => {
+            let blob = read_keys_from_large_object(
+                pool,
+                tenant_api_key,
+                "sns_pk",
+                SKS_KEY_WITH_NOISE_SQUASHING_SIZE,
+            )
+            .await?;
+            info!(bytes_len = blob.len(), "Retrieved sns_pk");
+            if blob.is_empty() {
+                return Ok(None);
+            }
+            (safe_deserialize_sns_key(&blob)?, blob.len())
+        }
+    };
+    observe_key_load("sns_pk", started_at, serialized_len);
 
     // Optionally retrieve the ClientKey for testing purposes
     let client_key = fetch_client_key(pool, tenant_api_key).await?;
     Ok(Some((client_key, server_key)))
 }
 
+/// Retrieve the current key id of the tenant, NULL for untagged keys
+pub async fn fetch_key_id(
+    pool: &PgPool,
+    tenant_api_key: &String,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let row = sqlx::query(
+        "
+                SELECT key_id FROM tenants
+                WHERE tenant_api_key = $1::uuid
+            ",
+    )
+    .bind(tenant_api_key)
+    .fetch_one(pool)
+    .await?;
+    Ok(row.try_get("key_id")?)
+}
+
+/// Retrieve the public key of the tenant, used to encrypt the operands of the
+/// warm-up canary
+pub async fn fetch_public_key(
+    pool: &PgPool,
+    tenant_api_key: &String,
+) -> anyhow::Result<tfhe::CompactPublicKey> {
+    let row = sqlx::query(
+        "
+                SELECT pks_key FROM tenants
+                WHERE tenant_api_key = $1::uuid
+            ",
+    )
+    .bind(tenant_api_key)
+    .fetch_one(pool)
+    .await?;
+    let pks: Vec<u8> = row.try_get("pks_key")?;
+    Ok(safe_deserialize_key(&pks)?)
+}
+
+/// Retrieve the parameter set of the tenant keys, the default set for NULL
+pub async fn fetch_param_set(
+    pool: &PgPool,
+    tenant_api_key: &String,
+) -> anyhow::Result<&'static ParamSet> {
+    let row = sqlx::query(
+        "
+                SELECT param_set FROM tenants
+                WHERE tenant_api_key = $1::uuid
+            ",
+    )
+    .bind(tenant_api_key)
+    .fetch_one(pool)
+    .await?;
+    let param_set: Option<String> = row.try_get("param_set")?;
+    Ok(ParamSet::resolve(param_set.as_deref())?)
+}
+
 pub async fn fetch_client_key(
     pool: &PgPool,
     tenant_api_key: &String,
@@ -80,6 +163,7 @@ pub async fn fetch_client_key(
     .await
     {
         if let Ok(cks) = keys.try_get::<Vec<u8>, _>(0) {
+            let cks = Zeroizing::new(cks);
             if !cks.is_empty() {
                 info!(bytes_len = cks.len(), "Retrieved cks");
                 let client_key: tfhe::ClientKey = safe_deserialize_sns_key(&cks)?;
@@ -1,12 +1,16 @@
 mod aws_upload;
+mod ciphertext_api;
 mod executor;
 mod keyset;
 mod squash_noise;
+pub mod staging;
+mod user_decrypt;
 
 #[cfg(test)]
 mod tests;
 
 use std::{
+    path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -16,9 +20,15 @@ use std::{
 
 use aws_config::{retry::RetryConfig, timeout::TimeoutConfig, BehaviorVersion};
 use aws_sdk_s3::{config::Builder, Client};
+use bytes::Bytes;
 use fhevm_engine_common::{
+    buffer_pool,
+    column_encryption::ColumnEncryptionError,
+    diagnostics::{self, DiagnosticsConfig},
     healthz_server::HttpServer,
+    param_set::{ParamSet, DEFAULT_PARAM_SET},
     pg_pool::{PostgresPoolManager, ServiceError},
+    secret::SecretString,
     telemetry::{self, OtelTracer},
     types::FhevmError,
     utils::compact_hex,
@@ -40,10 +50,17 @@ use tracing::{error, info, Level};
 
 use crate::{
     aws_upload::{check_is_ready, spawn_resubmit_task, spawn_uploader},
+    ciphertext_api::CiphertextApiServer,
     executor::SwitchNSquashService,
+    user_decrypt::spawn_user_decrypt_task,
 };
 
+pub use ciphertext_api::{parse_api_keys, CiphertextApiConfig};
+pub use user_decrypt::{UserDecryptConfig, EVENT_USER_DECRYPTION_REQUEST};
+
 pub const UPLOAD_QUEUE_SIZE: usize = 20;
+/// Channel notified by the gw-listener when a key is activated on the Gateway.
+pub const EVENT_KEY_ACTIVATED: &str = "event_key_activated";
 pub const SAFE_SER_LIMIT: u64 = 1024 * 1024 * 66;
 pub type InternalEvents = Option<tokio::sync::mpsc::Sender<&'static str>>;
 
@@ -51,13 +68,26 @@ pub type InternalEvents = Option<tokio::sync::mpsc::Sender<&'static str>>;
 pub struct KeySet {
     pub server_key: tfhe::ServerKey,
     pub client_key: Option<tfhe::ClientKey>,
+    /// KMS key id of the tenant, see [`fhevm_engine_common::key_version`]
+    pub key_id: Option<Vec<u8>>,
+    /// Parameters of the keys, see [`fhevm_engine_common::param_set`]
+    #[serde(skip, default = "default_param_set")]
+    pub param_set: &'static ParamSet,
+}
+
+fn default_param_set() -> &'static ParamSet {
+    &DEFAULT_PARAM_SET
 }
 
 #[derive(Clone)]
 pub struct DBConfig {
     pub url: String,
+    /// Schema of the host chain in the schema-per-chain layout, see `db_schema`.
+    pub schema: Option<String>,
     pub listen_channels: Vec<String>,
     pub notify_channel: String,
+    /// Channel of the key activations, on which the key of the tenant is checked right away
+    pub key_activated_channel: String,
     pub batch_limit: u32,
     pub gc_batch_limit: u32,
     pub polling_interval: u32,
@@ -75,8 +105,8 @@ impl std::fmt::Debug for DBConfig {
         // Custom debug impl to avoid printing sensitive information
         write!(
             f,
-            "db_listen_channel: {:?}, db_notify_channel: {}, db_batch_limit: {}, db_gc_batch_limit: {}, db_polling_interval: {}, db_cleanup_interval: {:?}, db_max_connections: {}, db_timeout: {:?}, lifo: {}",
-            self.listen_channels, self.notify_channel, self.batch_limit, self.gc_batch_limit, self.polling_interval, self.cleanup_interval, self.max_connections, self.timeout, self.lifo
+            "db_schema: {:?}, db_listen_channel: {:?}, db_notify_channel: {}, db_key_activated_channel: {}, db_batch_limit: {}, db_gc_batch_limit: {}, db_polling_interval: {}, db_cleanup_interval: {:?}, db_max_connections: {}, db_timeout: {:?}, lifo: {}",
+            self.schema, self.listen_channels, self.notify_channel, self.key_activated_channel, self.batch_limit, self.gc_batch_limit, self.polling_interval, self.cleanup_interval, self.max_connections, self.timeout, self.lifo
         )
     }
 }
@@ -102,6 +132,9 @@ pub struct S3RetryPolicy {
 pub struct HealthCheckConfig {
     pub liveness_threshold: Duration,
     pub port: u16,
+    /// Token of the admin endpoints of the health check server, not served
+    /// when unset
+    pub admin_token: Option<SecretString>,
 }
 
 #[derive(Clone, Debug)]
@@ -115,6 +148,21 @@ pub struct Config {
     pub enable_compression: bool,
     pub schedule_policy: SchedulePolicy,
     pub pg_auto_explain_with_min_duration: Option<Duration>,
+    /// Maximum total size of the serialization buffers kept for reuse
+    pub buffer_pool_max_bytes: usize,
+    /// User decryptions are re-encrypted by the KMS only when set
+    pub user_decrypt: Option<UserDecryptConfig>,
+    /// Ciphertexts are served to the relayers over HTTP only when set
+    pub ciphertext_api: Option<CiphertextApiConfig>,
+    /// Directory of the key files the keys are memory-mapped from, the keys
+    /// are read in memory when unset
+    pub keys_cache_dir: Option<PathBuf>,
+    /// Directory the ct128 are staged in until their batch is committed, so
+    /// that a restart resumes them without squashing again. Not staged if unset
+    pub staging_dir: Option<PathBuf>,
+    /// Directory the diagnostics snapshot is written to on panic, only logged
+    /// if unset
+    pub diagnostics_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -176,17 +224,23 @@ impl From<Ciphertext128Format> for i16 {
 #[derive(Clone, Debug, Default)]
 pub struct BigCiphertext {
     format: Ciphertext128Format,
-    bytes: Vec<u8>,
+    bytes: Bytes,
 }
 
 impl BigCiphertext {
-    pub fn new_with_format_id(bytes: Vec<u8>, format_id: i16) -> Option<Self> {
+    pub fn new_with_format_id(bytes: impl Into<Bytes>, format_id: i16) -> Option<Self> {
         let format = Ciphertext128Format::from_i16(format_id)?;
-        Some(Self { format, bytes })
+        Some(Self {
+            format,
+            bytes: bytes.into(),
+        })
     }
 
-    pub fn new(bytes: Vec<u8>, format: Ciphertext128Format) -> Self {
-        Self { format, bytes }
+    pub fn new(bytes: impl Into<Bytes>, format: Ciphertext128Format) -> Self {
+        Self {
+            format,
+            bytes: bytes.into(),
+        }
     }
 
     pub fn is_empty(&self) -> bool {
@@ -197,11 +251,23 @@ impl BigCiphertext {
         &self.bytes[..]
     }
 
+    /// Shared handle to the ciphertext, e.g. for uploads, without copying it.
+    pub fn to_bytes(&self) -> Bytes {
+        self.bytes.clone()
+    }
+
     pub fn format(&self) -> Ciphertext128Format {
         self.format
     }
 }
 
+impl Drop for BigCiphertext {
+    /// Recycles the buffer once the ciphertext is uploaded and dropped by all the tasks.
+    fn drop(&mut self) {
+        buffer_pool::put_bytes(std::mem::take(&mut self.bytes));
+    }
+}
+
 impl std::fmt::Display for Ciphertext128Format {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -224,7 +290,7 @@ pub struct HandleItem {
     /// Shared between the execute worker and the uploader
     ///
     /// The maximum size can be 8.1 KiB (type FheBytes256)
-    pub ct64_compressed: Arc<Vec<u8>>,
+    pub ct64_compressed: Bytes,
 
     /// The computed 128-bit ciphertext
     pub(crate) ct128: Arc<BigCiphertext>,
@@ -362,6 +428,9 @@ pub enum ExecutionError {
 
     #[error("Internal send error: {0}")]
     InternalSendError(String),
+
+    #[error("Column encryption error: {0}")]
+    ColumnEncryption(#[from] ColumnEncryptionError),
 }
 
 #[derive(Clone)]
@@ -394,6 +463,7 @@ pub async fn run_computation_loop(
     events_tx: InternalEvents,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let port = conf.health_checks.port;
+    let admin_token = conf.health_checks.admin_token.clone();
 
     let service = Arc::new(
         SwitchNSquashService::create(
@@ -407,7 +477,8 @@ pub async fn run_computation_loop(
         .await?,
     );
 
-    let http_server = HttpServer::new(service.clone(), port, token.child_token());
+    let http_server =
+        HttpServer::new(service.clone(), port, token.child_token()).with_admin_token(admin_token);
     let _http_handle = task::spawn(async move {
         if let Err(err) = http_server.start().await {
             error!(
@@ -500,6 +571,12 @@ pub async fn run_all(
     let rayon_threads = rayon::current_num_threads();
     info!(config = ?config, rayon_threads, "Starting SNS worker");
 
+    buffer_pool::set_max_retained_bytes(config.buffer_pool_max_bytes);
+    diagnostics::install(DiagnosticsConfig {
+        service: "sns-worker".to_owned(),
+        dir: config.diagnostics_dir.clone(),
+    });
+
     if !config.service_name.is_empty() {
         if let Err(err) = telemetry::setup_otlp(&config.service_name) {
             error!(error = %err, "Failed to setup OTLP");
@@ -518,6 +595,7 @@ pub async fn run_all(
     let Some(pool_mngr) = PostgresPoolManager::connect_pool(
         token.child_token(),
         conf.db.url.as_str(),
+        conf.db.schema.as_deref(),
         conf.db.timeout,
         conf.db.max_connections,
         Duration::from_secs(2),
@@ -529,6 +607,29 @@ pub async fn run_all(
         return Ok(());
     };
 
+    if let Some(user_decrypt) = conf.user_decrypt.clone() {
+        if let Err(err) =
+            spawn_user_decrypt_task(&pool_mngr, conf.clone(), user_decrypt, client.clone()).await
+        {
+            error!(error = %err, "Failed to start the user decryption pipeline");
+        }
+    }
+
+    if let Some(ciphertext_api) = conf.ciphertext_api.clone() {
+        let server = CiphertextApiServer::new(
+            pool_mngr.pool(),
+            ciphertext_api,
+            conf.s3.clone(),
+            client.clone(),
+            token.child_token(),
+        );
+        spawn(async move {
+            if let Err(err) = server.start().await {
+                error!(error = %err, "Ciphertext API server failed");
+            }
+        });
+    }
+
     let pg_mngr = pool_mngr.clone();
 
     // Spawns a task to handle S3 uploads
@@ -8,23 +8,24 @@ use tfhe::CompressedSquashedNoiseCiphertextListBuilder;
 use tfhe::SquashedNoiseFheUint;
 use tfhe::Versionize;
 
+use fhevm_engine_common::buffer_pool::{self, SizeHints};
 use fhevm_engine_common::types::SupportedFheCiphertexts;
 
 macro_rules! squash_and_serialize_with_error {
-    ($value:expr, $target_ty:ty, $enable_compression:expr) => {{
+    ($value:expr, $target_ty:ty, $enable_compression:expr, $size_hint_key:expr) => {{
         let squashed: $target_ty = $value
             .squash_noise()
             .map_err(ExecutionError::SquashedNoiseError)?;
 
         if !$enable_compression {
-            return safe_serialize(&squashed);
+            return safe_serialize(&squashed, $size_hint_key);
         }
 
         let mut builder = CompressedSquashedNoiseCiphertextListBuilder::new();
         builder.push(squashed);
         let list = builder.build()?;
 
-        Ok(safe_serialize(&list)?)
+        Ok(safe_serialize(&list, $size_hint_key)?)
     }};
 }
 
@@ -59,43 +60,104 @@ impl SquashNoiseCiphertext for SupportedFheCiphertexts {
         &self,
         enable_compression: bool,
     ) -> Result<Vec<u8>, ExecutionError> {
+        let size_hint_key = 2 * self.type_num() as usize + enable_compression as usize;
         match self {
             SupportedFheCiphertexts::FheBool(v) => {
-                squash_and_serialize_with_error!(v, tfhe::SquashedNoiseFheBool, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    tfhe::SquashedNoiseFheBool,
+                    enable_compression,
+                    size_hint_key
+                )
             }
             SupportedFheCiphertexts::FheUint4(v) => {
-                squash_and_serialize_with_error!(v, SquashedNoiseFheUint, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    SquashedNoiseFheUint,
+                    enable_compression,
+                    size_hint_key
+                )
             }
 
             SupportedFheCiphertexts::FheUint8(v) => {
-                squash_and_serialize_with_error!(v, SquashedNoiseFheUint, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    SquashedNoiseFheUint,
+                    enable_compression,
+                    size_hint_key
+                )
             }
             SupportedFheCiphertexts::FheUint16(v) => {
-                squash_and_serialize_with_error!(v, SquashedNoiseFheUint, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    SquashedNoiseFheUint,
+                    enable_compression,
+                    size_hint_key
+                )
             }
             SupportedFheCiphertexts::FheUint32(v) => {
-                squash_and_serialize_with_error!(v, SquashedNoiseFheUint, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    SquashedNoiseFheUint,
+                    enable_compression,
+                    size_hint_key
+                )
             }
             SupportedFheCiphertexts::FheUint64(v) => {
-                squash_and_serialize_with_error!(v, SquashedNoiseFheUint, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    SquashedNoiseFheUint,
+                    enable_compression,
+                    size_hint_key
+                )
             }
             SupportedFheCiphertexts::FheUint128(v) => {
-                squash_and_serialize_with_error!(v, SquashedNoiseFheUint, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    SquashedNoiseFheUint,
+                    enable_compression,
+                    size_hint_key
+                )
             }
             SupportedFheCiphertexts::FheUint160(v) => {
-                squash_and_serialize_with_error!(v, SquashedNoiseFheUint, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    SquashedNoiseFheUint,
+                    enable_compression,
+                    size_hint_key
+                )
             }
             SupportedFheCiphertexts::FheUint256(v) => {
-                squash_and_serialize_with_error!(v, SquashedNoiseFheUint, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    SquashedNoiseFheUint,
+                    enable_compression,
+                    size_hint_key
+                )
             }
             SupportedFheCiphertexts::FheBytes64(v) => {
-                squash_and_serialize_with_error!(v, SquashedNoiseFheUint, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    SquashedNoiseFheUint,
+                    enable_compression,
+                    size_hint_key
+                )
             }
             SupportedFheCiphertexts::FheBytes128(v) => {
-                squash_and_serialize_with_error!(v, SquashedNoiseFheUint, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    SquashedNoiseFheUint,
+                    enable_compression,
+                    size_hint_key
+                )
             }
             SupportedFheCiphertexts::FheBytes256(v) => {
-                squash_and_serialize_with_error!(v, SquashedNoiseFheUint, enable_compression)
+                squash_and_serialize_with_error!(
+                    v,
+                    SquashedNoiseFheUint,
+                    enable_compression,
+                    size_hint_key
+                )
             }
             SupportedFheCiphertexts::Scalar(_) => {
                 panic!("we should never need to serialize scalar")
@@ -155,11 +217,17 @@ impl SquashNoiseCiphertext for SupportedFheCiphertexts {
     }
 }
 
+/// Size of the last serialized ciphertext, by type and compression.
+static SERIALIZED_SIZE_HINTS: SizeHints<64> = SizeHints::new();
+
+/// Serializes into a pooled buffer, sized after the last ciphertext serialized with the same key.
 pub fn safe_serialize<T: Serialize + Named + Versionize>(
     object: &T,
+    size_hint_key: usize,
 ) -> Result<Vec<u8>, ExecutionError> {
-    let mut out = vec![];
+    let mut out = buffer_pool::get(SERIALIZED_SIZE_HINTS.get(size_hint_key));
     tfhe::safe_serialization::safe_serialize(object, &mut out, SAFE_SER_LIMIT)?;
+    SERIALIZED_SIZE_HINTS.record(size_hint_key, out.len());
     Ok(out)
 }
 
@@ -0,0 +1,162 @@
+//! Local staging of the squashed ciphertexts until they are uploaded.
+//!
+//! The ct128 of a batch are only stored in the database when the whole batch is squashed, so a
+//! crash in between loses all the squash results of the batch. With a staging directory, every
+//! ct128 is written there as soon as it is computed, with a manifest holding its format, digest and
+//! key. After a restart, the tasks of the interrupted batch are fetched again and their staged
+//! ct128 are reused, after checking the digest and the key, instead of being squashed again, and
+//! their uploads resume, the objects already in S3 being skipped. Entries are removed once the
+//! batch is committed, pending uploads being resubmitted from the database from then on. Entries
+//! older than [`MAX_STAGING_AGE`] are purged at startup.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime};
+
+use fhevm_engine_common::utils::compact_hex;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::aws_upload::compute_digest;
+use crate::BigCiphertext;
+
+/// Age after which the leftover entries, e.g. of tasks rejected meanwhile, are purged.
+pub const MAX_STAGING_AGE: Duration = Duration::from_secs(24 * 3600);
+
+static STAGING_EVENTS_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_sns_staging_events",
+        "Staged ct128, by event (staged, resumed, removed, purged, invalid)",
+        &["event"]
+    )
+    .unwrap()
+});
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    tenant_id: i32,
+    handle: String,
+    format: i16,
+    digest: String,
+    key_id: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Staging {
+    dir: PathBuf,
+}
+
+impl Staging {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Writes the ct128 of a handle, the manifest being written last.
+    pub fn stage(
+        &self,
+        tenant_id: i32,
+        handle: &[u8],
+        key_id: Option<&[u8]>,
+        ct128: &BigCiphertext,
+    ) -> io::Result<()> {
+        let (data_path, manifest_path) = self.paths(tenant_id, handle);
+        let manifest = Manifest {
+            tenant_id,
+            handle: hex::encode(handle),
+            format: ct128.format().into(),
+            digest: hex::encode(compute_digest(ct128.bytes())),
+            key_id: key_id.map(hex::encode),
+        };
+        write_atomic(&data_path, ct128.bytes())?;
+        write_atomic(&manifest_path, &serde_json::to_vec(&manifest)?)?;
+        STAGING_EVENTS_COUNTER.with_label_values(&["staged"]).inc();
+        Ok(())
+    }
+
+    /// Returns the staged ct128 of a handle if it is complete, valid and under `key_id`.
+    pub fn load(
+        &self,
+        tenant_id: i32,
+        handle: &[u8],
+        key_id: Option<&[u8]>,
+    ) -> Option<BigCiphertext> {
+        let (data_path, manifest_path) = self.paths(tenant_id, handle);
+        let manifest = std::fs::read(&manifest_path).ok()?;
+        let ct128 = serde_json::from_slice::<Manifest>(&manifest)
+            .ok()
+            .filter(|manifest| manifest.key_id == key_id.map(hex::encode))
+            .and_then(|manifest| {
+                let bytes = std::fs::read(&data_path).ok()?;
+                (hex::encode(compute_digest(&bytes)) == manifest.digest)
+                    .then(|| BigCiphertext::new_with_format_id(bytes, manifest.format))?
+            });
+        if ct128.is_none() {
+            warn!(
+                handle = compact_hex(handle),
+                "Invalid staged ct128, squashing again"
+            );
+            STAGING_EVENTS_COUNTER.with_label_values(&["invalid"]).inc();
+            self.remove(tenant_id, handle);
+            return None;
+        }
+        STAGING_EVENTS_COUNTER.with_label_values(&["resumed"]).inc();
+        ct128
+    }
+
+    /// Removes the entry of a handle, if any.
+    pub fn remove(&self, tenant_id: i32, handle: &[u8]) {
+        let (data_path, manifest_path) = self.paths(tenant_id, handle);
+        // The manifest goes first, a data file without manifest is never loaded
+        if std::fs::remove_file(manifest_path).is_ok() {
+            STAGING_EVENTS_COUNTER.with_label_values(&["removed"]).inc();
+        }
+        let _ = std::fs::remove_file(data_path);
+    }
+
+    /// Removes the files older than `max_age`, including the leftovers of interrupted writes.
+    pub fn purge(&self, max_age: Duration) -> io::Result<usize> {
+        let mut purged = 0;
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let age = entry
+                .metadata()?
+                .modified()?
+                .elapsed()
+                .unwrap_or(Duration::ZERO);
+            if age > max_age && std::fs::remove_file(entry.path()).is_ok() {
+                purged += 1;
+            }
+        }
+        STAGING_EVENTS_COUNTER
+            .with_label_values(&["purged"])
+            .inc_by(purged as u64);
+        info!(dir = ?self.dir, purged, "Purged staging directory");
+        Ok(purged)
+    }
+
+    fn paths(&self, tenant_id: i32, handle: &[u8]) -> (PathBuf, PathBuf) {
+        let name = format!("{tenant_id}-{}", hex::encode(handle));
+        (
+            self.dir.join(format!("{name}.ct128")),
+            self.dir.join(format!("{name}.json")),
+        )
+    }
+}
+
+/// Writes under a temporary name first, so that a partial file is never read.
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension(format!(
+        "tmp-{}",
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    ));
+    std::fs::write(&tmp_path, bytes)?;
+    std::fs::rename(tmp_path, path)
+}
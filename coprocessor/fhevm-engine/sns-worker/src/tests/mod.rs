@@ -2,7 +2,8 @@ use crate::{
     executor::{garbage_collect, query_sns_tasks, Order},
     keyset::fetch_client_key,
     squash_noise::safe_deserialize,
-    Config, DBConfig, S3Config, S3RetryPolicy, SchedulePolicy,
+    staging::Staging,
+    BigCiphertext, Ciphertext128Format, Config, DBConfig, S3Config, S3RetryPolicy, SchedulePolicy,
 };
 use anyhow::{anyhow, Ok};
 use aws_config::BehaviorVersion;
@@ -19,7 +20,8 @@ use std::{
 use test_harness::{
     db_utils::truncate_tables,
     instance::{setup_test_db, DBInstance, ImportMode},
-    localstack::{LocalstackContainer, LOCALSTACK_PORT},
+    localstack::LOCALSTACK_PORT,
+    minio::{start_minio, MinioContainer},
     s3_utils,
 };
 use tfhe::{
@@ -311,6 +313,201 @@ async fn test_lifo_mode() {
     }
 }
 
+#[tokio::test]
+#[serial(db)]
+async fn test_priority_lane() {
+    init_tracing();
+
+    let test_instance = setup_test_db(ImportMode::None)
+        .await
+        .expect("valid db instance");
+
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(3)
+        .connect(test_instance.db_url())
+        .await
+        .unwrap();
+
+    const HANDLES_COUNT: usize = 10;
+
+    for i in 0..HANDLES_COUNT {
+        test_harness::db_utils::insert_ciphertext64(
+            &pool,
+            1,
+            &Vec::from([i as u8; 32]),
+            &Vec::from([i as u8; 32]),
+            &[i as u8; 32],
+        )
+        .await
+        .unwrap();
+
+        test_harness::db_utils::insert_into_pbs_computations(&pool, 1, &Vec::from([i as u8; 32]))
+            .await
+            .unwrap();
+    }
+
+    // Allowed for public decryption
+    sqlx::query("UPDATE pbs_computations SET priority = 1 WHERE handle = $1")
+        .bind(vec![5u8; 32])
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // Allowed to the requesting contract in tenant 1 only, the same handle of tenant 2 keeps its
+    // priority
+    for i in [8u8, 9] {
+        sqlx::query(
+            "INSERT INTO allowed_handles (tenant_id, handle, account_address, event_type) VALUES (1, $1, $2, 0)",
+        )
+        .bind(vec![i; 32])
+        .bind("0x0000000000000000000000000000000000000002")
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+    test_harness::db_utils::insert_into_pbs_computations(&pool, 2, &vec![9u8; 32])
+        .await
+        .unwrap();
+
+    // Requested by a user decryption, raised by the trigger
+    sqlx::query(
+        "INSERT INTO user_decryption_requests
+            (decryption_id, user_address, ct_handles, contract_addresses, public_key)
+         VALUES ($1, $2, $3, $4, '\\x01')",
+    )
+    .bind(vec![1u8; 32])
+    .bind("0x0000000000000000000000000000000000000001")
+    .bind([vec![8u8; 32], vec![9u8; 32]].concat())
+    .bind(vec!["0x0000000000000000000000000000000000000002"; 2])
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let mut trx = pool.begin().await.unwrap();
+    let tasks = query_sns_tasks(&mut trx, 4, Order::Asc)
+        .await
+        .unwrap()
+        .expect("tasks");
+    let handles = tasks.iter().map(|task| task.handle[0]).collect::<Vec<_>>();
+    assert_eq!(handles, vec![8, 9, 5, 0]);
+
+    let priority: i16 =
+        sqlx::query_scalar("SELECT priority FROM pbs_computations WHERE tenant_id = 2")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(priority, 0);
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn test_lazy_sns() {
+    init_tracing();
+
+    let test_instance = setup_test_db(ImportMode::None)
+        .await
+        .expect("valid db instance");
+
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(3)
+        .connect(test_instance.db_url())
+        .await
+        .unwrap();
+
+    // Allowed but not enqueued by the host-listener with --lazy-sns, except 2 that is not allowed
+    for i in 0..4u8 {
+        sqlx::query(
+            "INSERT INTO ciphertexts (tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type)
+             VALUES (1, $1, $2, 0, 4)",
+        )
+        .bind(vec![i; 32])
+        .bind(vec![i; 32])
+        .execute(&pool)
+        .await
+        .unwrap();
+        if i != 2 {
+            sqlx::query(
+                "INSERT INTO allowed_handles (tenant_id, handle, account_address, event_type) VALUES (1, $1, $2, 0)",
+            )
+            .bind(vec![i; 32])
+            .bind("0x0000000000000000000000000000000000000002")
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+    }
+    // Already squashed and uploaded
+    sqlx::query(
+        "INSERT INTO ciphertext_digest (tenant_id, handle, ciphertext128) VALUES (1, $1, $2)",
+    )
+    .bind(vec![3u8; 32])
+    .bind(vec![0u8; 32])
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    // Requested by a user decryption, enqueued by the trigger
+    sqlx::query(
+        "INSERT INTO user_decryption_requests
+            (decryption_id, user_address, ct_handles, contract_addresses, public_key)
+         VALUES ($1, $2, $3, $4, '\\x01')",
+    )
+    .bind(vec![1u8; 32])
+    .bind("0x0000000000000000000000000000000000000001")
+    .bind([vec![1u8; 32], vec![2u8; 32], vec![3u8; 32]].concat())
+    .bind(vec!["0x0000000000000000000000000000000000000002"; 3])
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let mut trx = pool.begin().await.unwrap();
+    let tasks = query_sns_tasks(&mut trx, 10, Order::Asc)
+        .await
+        .unwrap()
+        .expect("tasks");
+    let handles = tasks.iter().map(|task| task.handle[0]).collect::<Vec<_>>();
+    assert_eq!(handles, vec![1]);
+}
+
+#[test]
+fn test_staging_resume() {
+    let dir = std::env::temp_dir().join(format!("sns-staging-{}", std::process::id()));
+    let staging = Staging::new(&dir).expect("staging dir");
+    let handle = vec![7u8; 32];
+    let key_id = [1u8; 32];
+    let ct128 = BigCiphertext::new(vec![42u8; 1024], Ciphertext128Format::CompressedOnCpu);
+
+    assert!(staging.load(1, &handle, Some(&key_id)).is_none());
+    staging
+        .stage(1, &handle, Some(&key_id), &ct128)
+        .expect("stage ct128");
+
+    let resumed = staging
+        .load(1, &handle, Some(&key_id))
+        .expect("staged ct128");
+    assert_eq!(resumed.bytes(), ct128.bytes());
+    assert_eq!(resumed.format(), ct128.format());
+
+    // Staged under another key, squashed again
+    assert!(staging.load(1, &handle, Some(&[2u8; 32])).is_none());
+
+    // Corrupted data, squashed again
+    staging
+        .stage(1, &handle, Some(&key_id), &ct128)
+        .expect("stage ct128");
+    let data_path = dir.join(format!("1-{}.ct128", hex::encode(&handle)));
+    std::fs::write(data_path, [0u8; 16]).unwrap();
+    assert!(staging.load(1, &handle, Some(&key_id)).is_none());
+
+    staging
+        .stage(1, &handle, Some(&key_id), &ct128)
+        .expect("stage ct128");
+    staging.remove(1, &handle);
+    assert!(staging.load(1, &handle, Some(&key_id)).is_none());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
 #[tokio::test]
 #[serial(db)]
 async fn test_garbage_collect() {
@@ -404,13 +601,467 @@ async fn test_garbage_collect() {
     );
 }
 
+#[tokio::test]
+#[serial(db)]
+async fn test_user_decrypt() -> anyhow::Result<()> {
+    init_tracing();
+    let db_instance = setup_test_db(ImportMode::WithAllKeys)
+        .await
+        .expect("valid db instance");
+    let conf = build_test_config(db_instance.db_url().to_owned(), false);
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&conf.db.url)
+        .await?;
+    let tenant_id = get_tenant_id_from_db(&pool, TENANT_API_KEY).await;
+
+    // Mock KMS, checks that the squashed ciphertext is sent and echoes its handle
+    let kms = axum::Router::new().route(
+        "/",
+        axum::routing::post(
+            |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                let ciphertext = &body["ciphertexts"][0];
+                assert_eq!(ciphertext["ciphertext"], hex::encode(b"squashed"));
+                axum::Json(serde_json::json!({
+                    "result": ciphertext["handle"],
+                    "signature": "0x0102",
+                }))
+            },
+        ),
+    );
+    let user_decrypt = UserDecryptFixture::start(kms, 1, 3).await?;
+
+    let user = "0x1111111111111111111111111111111111111111";
+    let delegator = "0x2222222222222222222222222222222222222222";
+    let contract = "0x3333333333333333333333333333333333333333";
+    let unconfirmed_delegator = "0x4444444444444444444444444444444444444444";
+    let handle = vec![7u8; 32];
+    sqlx::query(
+        "INSERT INTO ciphertexts (tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type, ciphertext128)
+         VALUES ($1, $2, '\\x00', 0, 4, $3)",
+    )
+    .bind(tenant_id)
+    .bind(&handle)
+    .bind(b"squashed".to_vec())
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        "INSERT INTO ciphertext_digest (tenant_id, handle, ciphertext128) VALUES ($1, $2, $3)",
+    )
+    .bind(tenant_id)
+    .bind(&handle)
+    .bind(vec![0u8; 32])
+    .execute(&pool)
+    .await?;
+    for account in [delegator, contract, unconfirmed_delegator] {
+        sqlx::query(
+            "INSERT INTO allowed_handles (tenant_id, handle, account_address, event_type) VALUES ($1, $2, $3, 0)",
+        )
+        .bind(tenant_id)
+        .bind(&handle)
+        .bind(account)
+        .execute(&pool)
+        .await?;
+    }
+    sqlx::query(
+        "INSERT INTO user_decryption_delegations
+            (tenant_id, delegator, delegate, contract_address, delegation_counter, expiry_date)
+         VALUES ($1, $2, $3, $4, 0, EXTRACT(EPOCH FROM NOW())::BIGINT + 3600)",
+    )
+    .bind(tenant_id)
+    .bind(delegator)
+    .bind(user)
+    .bind(contract)
+    .execute(&pool)
+    .await?;
+    // Delegated in a block not confirmed yet by the host-listener
+    sqlx::query(
+        "INSERT INTO user_decryption_delegations
+            (tenant_id, delegator, delegate, contract_address, delegation_counter, expiry_date,
+             block_number)
+         VALUES ($1, $2, $3, $4, 0, EXTRACT(EPOCH FROM NOW())::BIGINT + 3600, 100)",
+    )
+    .bind(tenant_id)
+    .bind(unconfirmed_delegator)
+    .bind(user)
+    .bind(contract)
+    .execute(&pool)
+    .await?;
+    // The user is only allowed through the delegation, once confirmed
+    let requests = [
+        (1u8, None, "rejected"),
+        (2u8, Some(delegator), "completed"),
+        (3u8, Some(unconfirmed_delegator), "queued"),
+    ];
+    for (id, delegator, _) in requests {
+        sqlx::query(
+            "INSERT INTO user_decryption_requests
+                (decryption_id, user_address, delegator_address, ct_handles, contract_addresses, public_key)
+             VALUES ($1, $2, $3, $4, $5, '\\x01')",
+        )
+        .bind(vec![id; 32])
+        .bind(user)
+        .bind(delegator)
+        .bind(&handle)
+        .bind(vec![contract])
+        .execute(&pool)
+        .await?;
+    }
+
+    let status = |id: u8| {
+        sqlx::query_scalar::<_, String>(
+            "SELECT status FROM user_decryption_requests WHERE decryption_id = $1",
+        )
+        .bind(vec![id; 32])
+        .fetch_one(&pool)
+    };
+
+    // Not confirmed while the host-listener has not reported any confirmation for the chain
+    user_decrypt.process_pending(&pool, &conf.s3).await?;
+    assert_eq!(status(3).await?, "queued");
+
+    sqlx::query(
+        "INSERT INTO host_chain_delegation_confirmations (chain_id, block_delay, confirmed_block_number)
+         SELECT chain_id, 5, 95 FROM tenants WHERE tenant_id = $1",
+    )
+    .bind(tenant_id)
+    .execute(&pool)
+    .await?;
+
+    user_decrypt.process_pending(&pool, &conf.s3).await?;
+
+    for (id, _, expected_status) in requests {
+        assert_eq!(status(id).await?, expected_status);
+    }
+    let (result, signature): (Vec<u8>, Vec<u8>) = sqlx::query_as(
+        "SELECT result, signature FROM decryption_responses WHERE decryption_id = $1 AND response_type = 1",
+    )
+    .bind(vec![2u8; 32])
+    .fetch_one(&pool)
+    .await?;
+    assert_eq!(result, handle);
+    assert_eq!(signature, vec![1, 2]);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn test_user_decrypt_dead_letter() -> anyhow::Result<()> {
+    init_tracing();
+    let db_instance = setup_test_db(ImportMode::WithAllKeys)
+        .await
+        .expect("valid db instance");
+    let conf = build_test_config(db_instance.db_url().to_owned(), false);
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&conf.db.url)
+        .await?;
+    let tenant_id = get_tenant_id_from_db(&pool, TENANT_API_KEY).await;
+
+    // Mock KMS, always unavailable
+    let kms_calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let kms = axum::Router::new().route(
+        "/",
+        axum::routing::post({
+            let kms_calls = kms_calls.clone();
+            move || async move {
+                kms_calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                axum::http::StatusCode::SERVICE_UNAVAILABLE
+            }
+        }),
+    );
+    let user_decrypt = UserDecryptFixture::start(kms, 2, 1).await?;
+
+    let user = "0x1111111111111111111111111111111111111111";
+    let contract = "0x3333333333333333333333333333333333333333";
+    // The ciphertext128 of the second handle has an unknown format, its request is poisoned
+    let handles = [(vec![8u8; 32], 10i16), (vec![9u8; 32], 0i16)];
+    for (id, (handle, format)) in [3u8, 4u8].into_iter().zip(&handles) {
+        sqlx::query(
+            "INSERT INTO ciphertexts (tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type, ciphertext128)
+             VALUES ($1, $2, '\\x00', 0, 4, $3)",
+        )
+        .bind(tenant_id)
+        .bind(handle)
+        .bind(b"squashed".to_vec())
+        .execute(&pool)
+        .await?;
+        sqlx::query(
+            "INSERT INTO ciphertext_digest (tenant_id, handle, ciphertext128, ciphertext128_format)
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(tenant_id)
+        .bind(handle)
+        .bind(vec![0u8; 32])
+        .bind(format)
+        .execute(&pool)
+        .await?;
+        for account in [user, contract] {
+            sqlx::query(
+                "INSERT INTO allowed_handles (tenant_id, handle, account_address, event_type) VALUES ($1, $2, $3, 0)",
+            )
+            .bind(tenant_id)
+            .bind(handle)
+            .bind(account)
+            .execute(&pool)
+            .await?;
+        }
+        sqlx::query(
+            "INSERT INTO user_decryption_requests
+                (decryption_id, user_address, ct_handles, contract_addresses, public_key)
+             VALUES ($1, $2, $3, $4, '\\x01')",
+        )
+        .bind(vec![id; 32])
+        .bind(user)
+        .bind(handle)
+        .bind(vec![contract])
+        .execute(&pool)
+        .await?;
+    }
+
+    user_decrypt.process_pending(&pool, &conf.s3).await?;
+
+    // The KMS is called again within the processing, not the poisoned request
+    assert_eq!(kms_calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    for (id, expected_kind) in [(3u8, "kms_unavailable"), (4u8, "poison")] {
+        let status: String = sqlx::query_scalar(
+            "SELECT status FROM user_decryption_requests WHERE decryption_id = $1",
+        )
+        .bind(vec![id; 32])
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(status, "failed");
+        let kind: String = sqlx::query_scalar(
+            "SELECT kind FROM user_decryption_dead_letters WHERE decryption_id = $1",
+        )
+        .bind(vec![id; 32])
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(kind, expected_kind);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn test_user_decrypt_claims() -> anyhow::Result<()> {
+    init_tracing();
+    let db_instance = setup_test_db(ImportMode::WithAllKeys)
+        .await
+        .expect("valid db instance");
+    let conf = build_test_config(db_instance.db_url().to_owned(), false);
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&conf.db.url)
+        .await?;
+    let tenant_id = get_tenant_id_from_db(&pool, TENANT_API_KEY).await;
+
+    // Mock KMS, echoes the handle
+    let kms_calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let kms = axum::Router::new().route(
+        "/",
+        axum::routing::post({
+            let kms_calls = kms_calls.clone();
+            move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                kms_calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                axum::Json(serde_json::json!({
+                    "result": body["ciphertexts"][0]["handle"],
+                    "signature": "0x0102",
+                }))
+            }
+        }),
+    );
+    let user_decrypt = UserDecryptFixture::start(kms, 1, 3).await?;
+
+    let user = "0x1111111111111111111111111111111111111111";
+    let contract = "0x3333333333333333333333333333333333333333";
+    let handle = vec![6u8; 32];
+    sqlx::query(
+        "INSERT INTO ciphertexts (tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type, ciphertext128)
+         VALUES ($1, $2, '\\x00', 0, 4, $3)",
+    )
+    .bind(tenant_id)
+    .bind(&handle)
+    .bind(b"squashed".to_vec())
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        "INSERT INTO ciphertext_digest (tenant_id, handle, ciphertext128) VALUES ($1, $2, $3)",
+    )
+    .bind(tenant_id)
+    .bind(&handle)
+    .bind(vec![0u8; 32])
+    .execute(&pool)
+    .await?;
+    for account in [user, contract] {
+        sqlx::query(
+            "INSERT INTO allowed_handles (tenant_id, handle, account_address, event_type) VALUES ($1, $2, $3, 0)",
+        )
+        .bind(tenant_id)
+        .bind(&handle)
+        .bind(account)
+        .execute(&pool)
+        .await?;
+    }
+
+    // Claimed by another worker, and by a worker whose claim expired, e.g. after a crash
+    let requests = [
+        (5u8, "NOW() + INTERVAL '1 hour'", "queued"),
+        (6u8, "NOW() - INTERVAL '1 second'", "completed"),
+    ];
+    for (id, claimed_until, _) in requests {
+        sqlx::query(&format!(
+            "INSERT INTO user_decryption_requests
+                (decryption_id, user_address, ct_handles, contract_addresses, public_key,
+                 claimed_until)
+             VALUES ($1, $2, $3, $4, '\\x01', {claimed_until})"
+        ))
+        .bind(vec![id; 32])
+        .bind(user)
+        .bind(&handle)
+        .bind(vec![contract])
+        .execute(&pool)
+        .await?;
+    }
+
+    let has_more = user_decrypt.process_pending(&pool, &conf.s3).await?;
+    assert!(!has_more);
+
+    assert_eq!(kms_calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    for (id, _, expected_status) in requests {
+        let (status, is_claimed): (String, bool) = sqlx::query_as(
+            "SELECT status, claimed_until IS NOT NULL FROM user_decryption_requests
+             WHERE decryption_id = $1",
+        )
+        .bind(vec![id; 32])
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(status, expected_status);
+        // Completed requests are released
+        assert_eq!(is_claimed, status == "queued");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn test_ciphertext_api() -> anyhow::Result<()> {
+    init_tracing();
+    let db_instance = setup_test_db(ImportMode::WithAllKeys)
+        .await
+        .expect("valid db instance");
+    let conf = build_test_config(db_instance.db_url().to_owned(), false);
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&conf.db.url)
+        .await?;
+    let tenant_id = get_tenant_id_from_db(&pool, TENANT_API_KEY).await;
+
+    let owner = "0x1111111111111111111111111111111111111111";
+    let delegate = "0x2222222222222222222222222222222222222222";
+    let contract = "0x3333333333333333333333333333333333333333";
+    let handle = vec![9u8; 32];
+    sqlx::query(
+        "INSERT INTO ciphertexts (tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type, ciphertext128)
+         VALUES ($1, $2, '\\x00', 0, 4, $3)",
+    )
+    .bind(tenant_id)
+    .bind(&handle)
+    .bind(b"squashed".to_vec())
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        "INSERT INTO ciphertext_digest (tenant_id, handle, ciphertext128) VALUES ($1, $2, $3)",
+    )
+    .bind(tenant_id)
+    .bind(&handle)
+    .bind(vec![5u8; 32])
+    .execute(&pool)
+    .await?;
+    for account in [owner, contract] {
+        sqlx::query(
+            "INSERT INTO allowed_handles (tenant_id, handle, account_address, event_type) VALUES ($1, $2, $3, 0)",
+        )
+        .bind(tenant_id)
+        .bind(&handle)
+        .bind(account)
+        .execute(&pool)
+        .await?;
+    }
+    sqlx::query(
+        "INSERT INTO user_decryption_delegations
+            (tenant_id, delegator, delegate, contract_address, delegation_counter, expiry_date)
+         VALUES ($1, $2, $3, $4, 0, EXTRACT(EPOCH FROM NOW())::BIGINT + 3600)",
+    )
+    .bind(tenant_id)
+    .bind(owner)
+    .bind(delegate)
+    .bind(contract)
+    .execute(&pool)
+    .await?;
+
+    let api_conf = crate::CiphertextApiConfig {
+        port: 0,
+        api_keys: crate::parse_api_keys(&format!("{owner}=owner-key\n{delegate}=delegate-key\n"))?,
+        signature_max_age: Duration::from_secs(300),
+        column_encryption: Default::default(),
+    };
+    let s3_client = aws_sdk_s3::Client::from_conf(
+        aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .build(),
+    );
+    let server = crate::ciphertext_api::CiphertextApiServer::new(
+        pool.clone(),
+        api_conf,
+        conf.s3.clone(),
+        Arc::new(s3_client),
+        Default::default(),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!(
+        "http://{}/v1/ciphertexts/0x{}",
+        listener.local_addr()?,
+        hex::encode(&handle)
+    );
+    let router = server.router();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    let client = reqwest::Client::new();
+    let get = |key: &str, query: &str| client.get(format!("{url}{query}")).bearer_auth(key).send();
+
+    let response = get("owner-key", "").await?;
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        response.headers()[crate::ciphertext_api::DIGEST_HEADER],
+        hex::encode([5u8; 32])
+    );
+    assert_eq!(response.bytes().await?.as_ref(), b"squashed");
+
+    // The delegate is only allowed through the delegation
+    let response = get("delegate-key", "").await?;
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+    let on_behalf = format!("?delegator={owner}&contract={contract}");
+    let response = get("delegate-key", &on_behalf).await?;
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.bytes().await?.as_ref(), b"squashed");
+
+    let response = get("unknown-key", "").await?;
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    Ok(())
+}
+
 #[allow(dead_code)]
 #[derive(Clone)]
 struct TestEnvironment {
     pub pool: sqlx::PgPool,
     pub client_key: Option<ClientKey>,
     pub db_instance: DBInstance,
-    pub s3_instance: Option<Arc<LocalstackContainer>>, // If None, the global LocalStack is used
+    pub s3_instance: Option<Arc<MinioContainer>>, // If None, the global LocalStack is used
     pub s3_client: aws_sdk_s3::Client,
     pub conf: Config,
 }
@@ -432,7 +1083,7 @@ async fn setup(enable_compression: bool) -> anyhow::Result<TestEnvironment> {
         .await?;
 
     // Set up S3 storage
-    let (s3_instance, s3_client) = setup_localstack(&conf).await?;
+    let (s3_instance, s3_client) = setup_s3(&conf).await?;
 
     let token = db_instance.parent_token.child_token();
     let config: Config = conf.clone();
@@ -464,37 +1115,37 @@ async fn setup(enable_compression: bool) -> anyhow::Result<TestEnvironment> {
     })
 }
 
-/// Deploys a LocalStack instance and creates S3 buckets for ciphertext128 and ciphertext64
+/// Starts a MinIO instance, or uses the global LocalStack if TEST_GLOBAL_LOCALSTACK is set, and
+/// creates S3 buckets for ciphertext128 and ciphertext64
 ///
 /// # Returns
-/// A tuple containing the LocalStack instance and the S3 client
-async fn setup_localstack(
+/// A tuple containing the MinIO instance and the S3 client
+async fn setup_s3(
     conf: &Config,
-) -> anyhow::Result<(Option<Arc<LocalstackContainer>>, aws_sdk_s3::Client)> {
-    let (localstack, host_port) =
+) -> anyhow::Result<(Option<Arc<MinioContainer>>, aws_sdk_s3::Client)> {
+    let (minio, client) =
         if std::env::var("TEST_GLOBAL_LOCALSTACK").unwrap_or("0".to_string()) == "1" {
-            (None, LOCALSTACK_PORT)
+            tracing::info!("Using global LocalStack on port: {}", LOCALSTACK_PORT);
+            let endpoint_url = format!("http://127.0.0.1:{}", LOCALSTACK_PORT);
+            std::env::set_var("AWS_ENDPOINT_URL", endpoint_url.clone());
+            std::env::set_var("AWS_REGION", "us-east-1");
+            std::env::set_var("AWS_ACCESS_KEY_ID", "test");
+            std::env::set_var("AWS_SECRET_ACCESS_KEY", "test");
+
+            let aws_conf = aws_config::load_defaults(BehaviorVersion::latest()).await;
+            (None, aws_sdk_s3::Client::new(&aws_conf))
         } else {
-            let localstack_instance = Arc::new(test_harness::localstack::start_localstack().await?);
-            let host_port = localstack_instance.host_port;
-            (Some(localstack_instance), host_port)
+            let minio_instance = Arc::new(start_minio().await?);
+            tracing::info!("MinIO started on port: {}", minio_instance.host_port);
+            minio_instance.set_aws_env();
+            let client = minio_instance.s3_client();
+            (Some(minio_instance), client)
         };
 
-    tracing::info!("LocalStack started on port: {}", host_port);
-
-    let endpoint_url = format!("http://127.0.0.1:{}", host_port);
-    std::env::set_var("AWS_ENDPOINT_URL", endpoint_url.clone());
-    std::env::set_var("AWS_REGION", "us-east-1");
-    std::env::set_var("AWS_ACCESS_KEY_ID", "test");
-    std::env::set_var("AWS_SECRET_ACCESS_KEY", "test");
-
-    let aws_conf = aws_config::load_defaults(BehaviorVersion::latest()).await;
-    let client: aws_sdk_s3::Client = aws_sdk_s3::Client::new(&aws_conf);
-
     recreate_bucket(&client, &conf.s3.bucket_ct128).await?;
     recreate_bucket(&client, &conf.s3.bucket_ct64).await?;
 
-    Ok((localstack, client))
+    Ok((minio, client))
 }
 
 async fn recreate_bucket(s3_client: &aws_sdk_s3::Client, bucket_name: &str) -> anyhow::Result<()> {
@@ -709,6 +1360,64 @@ async fn assert_ciphertext_s3_object_count(
         .await;
 }
 
+/// Mock KMS serving `kms`, with the user decryption config and the clients processing the
+/// requests against it.
+struct UserDecryptFixture {
+    conf: crate::UserDecryptConfig,
+    s3_client: aws_sdk_s3::Client,
+    kms_client: crate::user_decrypt::KmsClient,
+}
+
+impl UserDecryptFixture {
+    async fn start(
+        kms: axum::Router,
+        kms_max_attempts: u32,
+        max_retries: i32,
+    ) -> anyhow::Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let kms_url = format!("http://{}/", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, kms).await });
+
+        let conf = crate::UserDecryptConfig {
+            kms_url,
+            kms_request_timeout: Duration::from_secs(5),
+            kms_max_attempts,
+            kms_retry_backoff: Duration::from_millis(10),
+            listen_channel: crate::EVENT_USER_DECRYPTION_REQUEST.to_owned(),
+            batch_limit: 10,
+            max_retries,
+            column_encryption: Default::default(),
+        };
+        let s3_client = aws_sdk_s3::Client::from_conf(
+            aws_sdk_s3::Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .build(),
+        );
+        let kms_client = crate::user_decrypt::KmsClient::new(&conf)?;
+        Ok(Self {
+            conf,
+            s3_client,
+            kms_client,
+        })
+    }
+
+    async fn process_pending(
+        &self,
+        pool: &sqlx::PgPool,
+        s3_conf: &S3Config,
+    ) -> anyhow::Result<bool> {
+        let has_more = crate::user_decrypt::process_pending(
+            pool,
+            s3_conf,
+            &self.conf,
+            &self.s3_client,
+            &self.kms_client,
+        )
+        .await?;
+        Ok(has_more)
+    }
+}
+
 fn build_test_config(db_url: String, enable_compression: bool) -> Config {
     let batch_limit = std::env::var("BATCH_LIMIT")
         .ok()
@@ -724,8 +1433,10 @@ fn build_test_config(db_url: String, enable_compression: bool) -> Config {
         tenant_api_key: TENANT_API_KEY.to_string(),
         db: DBConfig {
             url: db_url,
+            schema: None,
             listen_channels: vec![LISTEN_CHANNEL.to_string()],
             notify_channel: "fhevm".to_string(),
+            key_activated_channel: crate::EVENT_KEY_ACTIVATED.to_string(),
             batch_limit,
             gc_batch_limit: 30,
             polling_interval: 60000,
@@ -751,9 +1462,39 @@ fn build_test_config(db_url: String, enable_compression: bool) -> Config {
         health_checks: crate::HealthCheckConfig {
             liveness_threshold: Duration::from_secs(10),
             port: 8080,
+            admin_token: None,
         },
         enable_compression,
         schedule_policy,
         pg_auto_explain_with_min_duration: Some(Duration::from_secs(1)),
+        buffer_pool_max_bytes: fhevm_engine_common::buffer_pool::DEFAULT_MAX_RETAINED_BYTES,
+        keys_cache_dir: None,
+        staging_dir: None,
+        diagnostics_dir: None,
+        user_decrypt: None,
+        ciphertext_api: None,
     }
 }
+
+#[test]
+fn test_user_decrypt_claim_covers_the_retries() {
+    let mut conf = crate::UserDecryptConfig {
+        kms_url: String::new(),
+        kms_request_timeout: Duration::from_secs(5),
+        kms_max_attempts: 1,
+        kms_retry_backoff: Duration::from_secs(1),
+        listen_channel: crate::EVENT_USER_DECRYPTION_REQUEST.to_owned(),
+        batch_limit: 10,
+        max_retries: 3,
+        column_encryption: Default::default(),
+    };
+    assert_eq!(conf.claim_duration(), Duration::from_secs(5 + 60));
+
+    // 3 calls, backing off 1s then 2s
+    conf.kms_max_attempts = 3;
+    assert_eq!(conf.claim_duration(), Duration::from_secs(3 * 5 + 3 + 60));
+
+    // Saturates instead of overflowing
+    conf.kms_max_attempts = u32::MAX;
+    assert!(conf.claim_duration() > Duration::from_secs(u32::MAX.into()));
+}
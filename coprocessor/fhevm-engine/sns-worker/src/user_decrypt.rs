@@ -0,0 +1,839 @@
+//! User decryption pipeline.
+//!
+//! Requests queued in `user_decryption_requests` are checked against the ACL, i.e. the contract
+//! and the user (or the delegator the user acts for) must be allowed on every handle. The squashed
+//! ciphertexts are then read from the database, or from S3 once garbage collected, and sent to the
+//! KMS to be re-encrypted under the user's public key. The re-encrypted result is stored as a user
+//! decryption response, sent to the Gateway by the transaction-sender.
+//!
+//! Requests are claimed one at a time for the time of their processing, see `claimed_until`, and
+//! completed in their own transaction, so that no transaction stays open across the S3 and KMS
+//! calls and a failure only affects its own request.
+//!
+//! Requests that exhaust their retries, or that cannot be processed at all, are marked as failed
+//! and recorded in `user_decryption_dead_letters` so that they do not block the queue.
+
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aws_sdk_s3::Client;
+use fhevm_engine_common::column_encryption::ColumnEncryption;
+use fhevm_engine_common::pg_listener::SupervisedListener;
+use fhevm_engine_common::pg_pool::{PostgresPoolManager, ServiceError};
+use fhevm_engine_common::telemetry::{self, gen_buckets, OtelTracer};
+use fhevm_engine_common::types::DecryptionResponseType;
+use fhevm_engine_common::utils::compact_hex;
+use prometheus::{register_histogram, register_int_counter, Histogram, IntCounter};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Transaction};
+use tokio::select;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::aws_upload::compute_digest;
+use crate::{Ciphertext128Format, Config, ExecutionError, S3Config};
+
+pub const EVENT_USER_DECRYPTION_REQUEST: &str = "event_user_decryption_request";
+
+/// Requests are retried at most every `RETRY_STEP * retry_count`, up to `MAX_RETRY_DELAY`.
+const RETRY_STEP_SECS: i32 = 2;
+const MAX_RETRY_DELAY_SECS: i32 = 60;
+
+/// Time left to download the ciphertexts and check the ACL of a claimed request, on top of its KMS
+/// calls.
+const CLAIM_MARGIN: Duration = Duration::from_secs(60);
+
+static USER_DECRYPT_COMPLETED_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_sns_user_decrypt_completed_counter",
+        "Number of user decryption requests re-encrypted by the KMS"
+    )
+    .unwrap()
+});
+
+static USER_DECRYPT_REJECTED_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_sns_user_decrypt_rejected_counter",
+        "Number of user decryption requests rejected by the ACL or the KMS"
+    )
+    .unwrap()
+});
+
+static USER_DECRYPT_FAILED_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_sns_user_decrypt_failed_counter",
+        "Number of user decryption requests moved to the dead-letter table"
+    )
+    .unwrap()
+});
+
+static KMS_UNAVAILABLE_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_sns_kms_unavailable_counter",
+        "Number of KMS re-encryption calls failed because the KMS is unreachable or erroring"
+    )
+    .unwrap()
+});
+
+static KMS_REJECTED_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_sns_kms_rejected_counter",
+        "Number of re-encryption requests refused by the KMS"
+    )
+    .unwrap()
+});
+
+static USER_DECRYPT_LATENCY_HISTOGRAM: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "coprocessor_sns_user_decrypt_latency_seconds",
+        "User decryption latencies in seconds, from the request to the stored response",
+        gen_buckets(0.5, 60.0)
+    )
+    .unwrap()
+});
+
+#[derive(Clone, Debug)]
+pub struct UserDecryptConfig {
+    /// Endpoint of the KMS re-encrypting the ciphertexts under the user's public key
+    pub kms_url: String,
+    pub kms_request_timeout: Duration,
+    /// Calls to the KMS per processing of a request while it is unavailable, the delay between
+    /// them starting at `kms_retry_backoff` and doubling
+    pub kms_max_attempts: u32,
+    pub kms_retry_backoff: Duration,
+    pub listen_channel: String,
+    pub batch_limit: u32,
+    pub max_retries: i32,
+    /// Encrypted columns of the requests and responses
+    pub column_encryption: ColumnEncryption,
+}
+
+impl UserDecryptConfig {
+    /// Time a request is claimed for, covering all its KMS calls and the backoff between them.
+    pub(crate) fn claim_duration(&self) -> Duration {
+        let attempts = self.kms_max_attempts.max(1);
+        // The backoff doubles after each of the `attempts - 1` retries
+        let backoff = self
+            .kms_retry_backoff
+            .saturating_mul(2u32.saturating_pow(attempts - 1) - 1);
+        self.kms_request_timeout
+            .saturating_mul(attempts)
+            .saturating_add(backoff)
+            .saturating_add(CLAIM_MARGIN)
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct KmsCiphertext {
+    handle: String,
+    format: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Debug)]
+struct KmsReencryptRequest<'a> {
+    decryption_id: String,
+    user_address: &'a str,
+    public_key: String,
+    extra_data: String,
+    ciphertexts: Vec<KmsCiphertext>,
+}
+
+#[derive(Deserialize, Debug)]
+struct KmsReencryptResponse {
+    result: String,
+    signature: String,
+}
+
+#[derive(Debug)]
+enum KmsError {
+    /// The KMS refused the request, retrying it is pointless
+    Rejected(String),
+    Unavailable(String),
+}
+
+/// Client of the KMS re-encryption endpoint.
+#[derive(Clone)]
+pub(crate) struct KmsClient {
+    http: reqwest::Client,
+    url: String,
+    max_attempts: u32,
+    retry_backoff: Duration,
+}
+
+impl KmsClient {
+    pub(crate) fn new(conf: &UserDecryptConfig) -> Result<Self, ExecutionError> {
+        let http = reqwest::Client::builder()
+            .timeout(conf.kms_request_timeout)
+            .build()
+            .map_err(|err| ExecutionError::ConversionError(err.into()))?;
+        Ok(Self {
+            http,
+            url: conf.kms_url.clone(),
+            max_attempts: conf.kms_max_attempts.max(1),
+            retry_backoff: conf.kms_retry_backoff,
+        })
+    }
+
+    /// Calls the KMS until it answers, at most `max_attempts` times. The request stays claimed
+    /// while backing off, no transaction nor connection is held meanwhile.
+    async fn reencrypt_with_retry(
+        &self,
+        request: &KmsReencryptRequest<'_>,
+    ) -> Result<(Vec<u8>, Vec<u8>), KmsError> {
+        let mut backoff = self.retry_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.reencrypt(request).await {
+                Err(KmsError::Unavailable(reason)) => {
+                    KMS_UNAVAILABLE_COUNTER.inc();
+                    if attempt >= self.max_attempts {
+                        return Err(KmsError::Unavailable(reason));
+                    }
+                    debug!(attempt, reason, ?backoff, "KMS unavailable, retrying");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(KmsError::Rejected(reason)) => {
+                    KMS_REJECTED_COUNTER.inc();
+                    return Err(KmsError::Rejected(reason));
+                }
+                Ok(response) => return Ok(response),
+            }
+        }
+    }
+
+    async fn reencrypt(
+        &self,
+        request: &KmsReencryptRequest<'_>,
+    ) -> Result<(Vec<u8>, Vec<u8>), KmsError> {
+        let response = self
+            .http
+            .post(&self.url)
+            .json(request)
+            .send()
+            .await
+            .map_err(|err| KmsError::Unavailable(err.to_string()))?;
+        let status = response.status();
+        if status.is_client_error() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(KmsError::Rejected(format!("{status}: {body}")));
+        }
+        if !status.is_success() {
+            return Err(KmsError::Unavailable(status.to_string()));
+        }
+        let response: KmsReencryptResponse = response
+            .json()
+            .await
+            .map_err(|err| KmsError::Unavailable(err.to_string()))?;
+        let decode = |value: &str| {
+            hex::decode(value.trim_start_matches("0x"))
+                .map_err(|err| KmsError::Unavailable(format!("invalid KMS response: {err}")))
+        };
+        Ok((decode(&response.result)?, decode(&response.signature)?))
+    }
+}
+
+struct UserDecryptionRequest {
+    decryption_id: Vec<u8>,
+    user_address: String,
+    delegator_address: Option<String>,
+    ct_handles: Vec<u8>,
+    contract_addresses: Vec<String>,
+    public_key: Vec<u8>,
+    extra_data: Vec<u8>,
+    retry_count: i32,
+    age: Duration,
+}
+
+impl UserDecryptionRequest {
+    /// Decrypts the encrypted columns read from the database.
+    fn decrypt_columns(&mut self, encryption: &ColumnEncryption) -> Result<(), ExecutionError> {
+        self.user_address = encryption.decrypt_text(std::mem::take(&mut self.user_address))?;
+        self.delegator_address = self
+            .delegator_address
+            .take()
+            .map(|delegator| encryption.decrypt_text(delegator))
+            .transpose()?;
+        self.public_key = encryption.decrypt(std::mem::take(&mut self.public_key))?;
+        Ok(())
+    }
+}
+
+enum Outcome {
+    Completed {
+        result: Vec<u8>,
+        signature: Vec<u8>,
+    },
+    /// The request can never succeed, e.g. the user is not allowed to decrypt a handle
+    Rejected(String),
+    Retry(FailureKind, String),
+    /// The request cannot be processed, it is dead-lettered without retrying
+    Poison(String),
+}
+
+/// Why a request failed, the `kind` column of `user_decryption_dead_letters`.
+#[derive(Clone, Copy, Debug)]
+enum FailureKind {
+    /// The ciphertexts are not available, e.g. not squashed yet
+    NotReady,
+    KmsUnavailable,
+    Poison,
+}
+
+impl FailureKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::NotReady => "not_ready",
+            Self::KmsUnavailable => "kms_unavailable",
+            Self::Poison => "poison",
+        }
+    }
+}
+
+pub(crate) async fn spawn_user_decrypt_task(
+    pool_mngr: &PostgresPoolManager,
+    conf: Config,
+    user_decrypt: UserDecryptConfig,
+    client: Arc<Client>,
+) -> Result<JoinHandle<()>, ExecutionError> {
+    let kms = KmsClient::new(&user_decrypt)?;
+    let op = move |pool, token| {
+        let kms = kms.clone();
+        let client = client.clone();
+        let conf = conf.clone();
+        let user_decrypt = user_decrypt.clone();
+
+        async move {
+            run_user_decrypt_loop(pool, token, conf, user_decrypt, client, kms)
+                .await
+                .map_err(ServiceError::from)
+        }
+    };
+
+    Ok(pool_mngr.spawn_with_db_retry(op, "user_decrypt").await)
+}
+
+async fn run_user_decrypt_loop(
+    pool: Pool<Postgres>,
+    token: CancellationToken,
+    conf: Config,
+    user_decrypt: UserDecryptConfig,
+    client: Arc<Client>,
+    kms: KmsClient,
+) -> Result<(), ExecutionError> {
+    let mut listener = SupervisedListener::connect(&pool, [&user_decrypt.listen_channel]).await?;
+    info!(
+        kms_url = user_decrypt.kms_url,
+        channel = user_decrypt.listen_channel,
+        "Starting user decryption pipeline"
+    );
+
+    let mut polling_ticker = interval(Duration::from_secs(conf.db.polling_interval.into()));
+    loop {
+        if process_pending(&pool, &conf.s3, &user_decrypt, &client, &kms).await? {
+            continue;
+        }
+
+        select! {
+            _ = token.cancelled() => return Ok(()),
+            event = listener.recv() => {
+                debug!(event = ?event, "Received user decryption notification");
+            },
+            _ = polling_ticker.tick() => {},
+        }
+    }
+}
+
+/// Processes a batch of queued requests. Returns true if there might be more of them.
+pub(crate) async fn process_pending(
+    pool: &Pool<Postgres>,
+    s3_conf: &S3Config,
+    conf: &UserDecryptConfig,
+    client: &Client,
+    kms: &KmsClient,
+) -> Result<bool, ExecutionError> {
+    let mut claimed = 0;
+    while claimed < conf.batch_limit {
+        let Some(mut request) = claim_request(pool, conf).await? else {
+            return Ok(false);
+        };
+        claimed += 1;
+
+        let otel = telemetry::tracer("user_decrypt", &None);
+        otel.set_attribute("decryption_id", compact_hex(&request.decryption_id));
+        let processed = match request.decrypt_columns(&conf.column_encryption) {
+            Ok(()) => process_request(pool, s3_conf, client, kms, conf, &request, &otel).await,
+            Err(err) => Err(err),
+        };
+        let outcome = match processed {
+            Ok(outcome) => outcome,
+            // Not specific to the request, it is processed again once its claim expires
+            Err(err @ ExecutionError::DbError(_)) => return Err(err),
+            Err(err) => Outcome::Poison(err.to_string()),
+        };
+        let mut trx = pool.begin().await?;
+        complete(&mut trx, conf, &request, outcome).await?;
+        trx.commit().await?;
+        otel.end();
+    }
+    Ok(true)
+}
+
+/// Claims the oldest queued request that is due, not claimed by another worker.
+async fn claim_request(
+    pool: &Pool<Postgres>,
+    conf: &UserDecryptConfig,
+) -> Result<Option<UserDecryptionRequest>, ExecutionError> {
+    let row = sqlx::query!(
+        "
+        UPDATE user_decryption_requests
+        SET claimed_until = NOW() + make_interval(secs => $3)
+        WHERE decryption_id = (
+            SELECT decryption_id
+            FROM user_decryption_requests
+            WHERE status = 'queued'
+            AND (claimed_until IS NULL OR claimed_until < NOW())
+            AND (last_error_at IS NULL
+                OR last_error_at < NOW() - make_interval(secs => LEAST(retry_count * $1, $2)))
+            ORDER BY created_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING decryption_id, user_address, delegator_address, ct_handles, contract_addresses,
+            public_key, extra_data, retry_count,
+            EXTRACT(EPOCH FROM NOW() - created_at)::FLOAT8 AS \"age!\"
+        ",
+        RETRY_STEP_SECS,
+        MAX_RETRY_DELAY_SECS,
+        conf.claim_duration().as_secs_f64(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| UserDecryptionRequest {
+        decryption_id: row.decryption_id,
+        user_address: row.user_address,
+        delegator_address: row.delegator_address,
+        ct_handles: row.ct_handles,
+        contract_addresses: row.contract_addresses,
+        public_key: row.public_key,
+        extra_data: row.extra_data,
+        retry_count: row.retry_count,
+        age: Duration::from_secs_f64(row.age.max(0.0)),
+    }))
+}
+
+async fn process_request(
+    pool: &Pool<Postgres>,
+    s3_conf: &S3Config,
+    client: &Client,
+    kms: &KmsClient,
+    conf: &UserDecryptConfig,
+    request: &UserDecryptionRequest,
+    otel: &OtelTracer,
+) -> Result<Outcome, ExecutionError> {
+    if request.ct_handles.is_empty()
+        || request.ct_handles.len() % 32 != 0
+        || request.ct_handles.len() / 32 != request.contract_addresses.len()
+    {
+        return Ok(Outcome::Rejected(
+            "malformed request, expected one contract address per 32 bytes handle".to_string(),
+        ));
+    }
+    let handles: Vec<&[u8]> = request.ct_handles.chunks(32).collect();
+
+    let s = otel.child_span("fetch_ct128");
+    let mut ciphertexts = Vec::with_capacity(handles.len());
+    // The tenant of the first handle, all the handles must belong to it
+    let mut tenant_id = None;
+    for handle in &handles {
+        match fetch_ciphertext128(pool, s3_conf, client, handle, tenant_id).await? {
+            Ok((tenant, format, ciphertext)) => {
+                tenant_id = Some(tenant);
+                ciphertexts.push(KmsCiphertext {
+                    handle: hex::encode(handle),
+                    format: format.to_string(),
+                    ciphertext: hex::encode(ciphertext),
+                });
+            }
+            Err(reason) => {
+                telemetry::end_span_with_err(s, reason.clone());
+                return Ok(Outcome::Retry(FailureKind::NotReady, reason));
+            }
+        }
+    }
+    telemetry::end_span(s);
+
+    let s = otel.child_span("check_acl");
+    let Some(tenant_id) = tenant_id else {
+        telemetry::end_span_with_err(s, "no tenant".to_string());
+        return Ok(Outcome::Rejected(
+            "the handles do not belong to any tenant".to_string(),
+        ));
+    };
+    if let Some(outcome) = check_acl(pool, conf, request, &handles, tenant_id).await? {
+        if let Outcome::Rejected(reason) | Outcome::Retry(_, reason) = &outcome {
+            telemetry::end_span_with_err(s, reason.clone());
+        }
+        return Ok(outcome);
+    }
+    telemetry::end_span(s);
+
+    let mut s = otel.child_span("kms_reencrypt");
+    telemetry::attribute(&mut s, "handles_count", handles.len().to_string());
+    let kms_request = KmsReencryptRequest {
+        decryption_id: hex::encode(&request.decryption_id),
+        user_address: &request.user_address,
+        public_key: hex::encode(&request.public_key),
+        extra_data: hex::encode(&request.extra_data),
+        ciphertexts,
+    };
+    match kms.reencrypt_with_retry(&kms_request).await {
+        Ok((result, signature)) => {
+            telemetry::end_span(s);
+            Ok(Outcome::Completed { result, signature })
+        }
+        Err(KmsError::Rejected(reason)) => {
+            telemetry::end_span_with_err(s, reason.clone());
+            Ok(Outcome::Rejected(format!("rejected by the KMS: {reason}")))
+        }
+        Err(KmsError::Unavailable(reason)) => {
+            telemetry::end_span_with_err(s, reason.clone());
+            Ok(Outcome::Retry(
+                FailureKind::KmsUnavailable,
+                format!("KMS unavailable: {reason}"),
+            ))
+        }
+    }
+}
+
+/// Returns the tenant, the format and the squashed ciphertext of the handle, of the given tenant
+/// if any, or why it is not available yet.
+async fn fetch_ciphertext128(
+    pool: &Pool<Postgres>,
+    s3_conf: &S3Config,
+    client: &Client,
+    handle: &[u8],
+    tenant_id: Option<i32>,
+) -> Result<Result<(i32, Ciphertext128Format, Vec<u8>), String>, ExecutionError> {
+    let row = sqlx::query!(
+        "
+        SELECT d.tenant_id, d.ciphertext128 AS digest, d.ciphertext128_format, c.ciphertext128
+        FROM ciphertext_digest d
+        LEFT JOIN ciphertexts c ON c.tenant_id = d.tenant_id AND c.handle = d.handle
+        WHERE d.handle = $1 AND ($2::INT IS NULL OR d.tenant_id = $2)
+        ORDER BY c.ciphertext_version DESC NULLS LAST
+        LIMIT 1
+        ",
+        handle,
+        tenant_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(Err(format!(
+            "handle {} is not computed yet",
+            compact_hex(handle)
+        )));
+    };
+    let Some(digest) = row.digest else {
+        return Ok(Err(format!(
+            "squashed ciphertext of handle {} is not uploaded yet",
+            compact_hex(handle)
+        )));
+    };
+    let format = Ciphertext128Format::from_i16(row.ciphertext128_format)
+        .ok_or_else(|| ExecutionError::DeserializationError("unknown ct128 format".to_string()))?;
+
+    if let Some(ciphertext) = row.ciphertext128 {
+        return Ok(Ok((row.tenant_id, format, ciphertext)));
+    }
+
+    // Garbage collected from the database once uploaded
+    let key = if cfg!(feature = "test_s3_use_handle_as_key") {
+        hex::encode(handle)
+    } else {
+        hex::encode(&digest)
+    };
+    let object = match client
+        .get_object()
+        .bucket(&s3_conf.bucket_ct128)
+        .key(&key)
+        .send()
+        .await
+    {
+        Ok(object) => object,
+        Err(err) => {
+            warn!(handle = compact_hex(handle), error = %err, "Failed to download ct128");
+            return Ok(Err(format!("failed to download ct128: {err}")));
+        }
+    };
+    let ciphertext = match object.body.collect().await {
+        Ok(body) => body.into_bytes().to_vec(),
+        Err(err) => return Ok(Err(format!("failed to download ct128: {err}"))),
+    };
+    if compute_digest(&ciphertext) != digest {
+        error!(
+            handle = compact_hex(handle),
+            "Downloaded ct128 does not match its digest"
+        );
+        return Ok(Err("downloaded ct128 does not match its digest".to_string()));
+    }
+    Ok(Ok((row.tenant_id, format, ciphertext)))
+}
+
+/// Returns the outcome of the request if it is not allowed, or not yet.
+///
+/// Every contract must be allowed on its handle, as well as the account the handles are decrypted
+/// for, which is either the user or the delegator, in which case the user must have a delegation
+/// for every contract. A delegation is effective once its block is confirmed by the host-listener,
+/// see `host_chain_delegation_confirmations`, the request being retried meanwhile.
+async fn check_acl(
+    pool: &Pool<Postgres>,
+    conf: &UserDecryptConfig,
+    request: &UserDecryptionRequest,
+    handles: &[&[u8]],
+    tenant_id: i32,
+) -> Result<Option<Outcome>, ExecutionError> {
+    let account = request
+        .delegator_address
+        .as_deref()
+        .unwrap_or(&request.user_address)
+        .to_lowercase();
+
+    for (handle, contract_address) in handles.iter().zip(&request.contract_addresses) {
+        let contract_address = contract_address.to_lowercase();
+        if contract_address == request.user_address.to_lowercase() {
+            return Ok(Some(Outcome::Rejected(format!(
+                "user {} cannot be the contract address",
+                request.user_address
+            ))));
+        }
+
+        let allowed: Vec<String> = sqlx::query_scalar!(
+            "SELECT LOWER(account_address) AS \"account_address!\"
+             FROM allowed_handles
+             WHERE tenant_id = $1 AND handle = $2 AND LOWER(account_address) = ANY($3)",
+            tenant_id,
+            handle,
+            &[account.clone(), contract_address.clone()],
+        )
+        .fetch_all(pool)
+        .await?;
+        if !allowed.contains(&contract_address) {
+            return Ok(Some(Outcome::Rejected(format!(
+                "contract {contract_address} is not allowed on handle {}",
+                compact_hex(handle)
+            ))));
+        }
+        if !allowed.contains(&account) {
+            return Ok(Some(Outcome::Rejected(format!(
+                "account {account} is not allowed on handle {}",
+                compact_hex(handle)
+            ))));
+        }
+
+        if request.delegator_address.is_some() {
+            let delegation = active_delegation(
+                pool,
+                &conf.column_encryption,
+                tenant_id,
+                &account,
+                &request.user_address,
+                &contract_address,
+            )
+            .await?;
+            let Some(delegation) = delegation else {
+                return Ok(Some(Outcome::Rejected(format!(
+                    "user {} has no delegation from {account} for contract {contract_address}",
+                    request.user_address
+                ))));
+            };
+            if !delegation.is_confirmed {
+                return Ok(Some(Outcome::Retry(
+                    FailureKind::NotReady,
+                    format!(
+                        "delegation from {account} for contract {contract_address} of block {} is \
+                         not confirmed yet",
+                        delegation.block_number.unwrap_or_default()
+                    ),
+                )));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Delegation of the delegate by the delegator for the contract.
+pub(crate) struct Delegation {
+    pub expiry_date: i64,
+    pub block_number: Option<i64>,
+    /// Whether its block is confirmed by the host-listener, false until the host-listener reports
+    /// confirmations for the chain
+    pub is_confirmed: bool,
+}
+
+/// Returns the unexpired delegation of `delegate` by `delegator` for `contract_address`, if any.
+/// The delegator and the contract address are lowercase.
+pub(crate) async fn active_delegation(
+    pool: &Pool<Postgres>,
+    column_encryption: &ColumnEncryption,
+    tenant_id: i32,
+    delegator: &str,
+    delegate: &str,
+    contract_address: &str,
+) -> Result<Option<Delegation>, sqlx::Error> {
+    let delegation = sqlx::query_as!(
+        Delegation,
+        "SELECT d.expiry_date, d.block_number,
+            COALESCE(d.block_number IS NULL OR d.block_number <= c.confirmed_block_number, FALSE)
+                AS \"is_confirmed!\"
+         FROM user_decryption_delegations d
+         JOIN tenants t ON t.tenant_id = d.tenant_id
+         LEFT JOIN host_chain_delegation_confirmations c ON c.chain_id = t.chain_id
+         WHERE d.tenant_id = $1
+         AND LOWER(d.delegator) = $2
+         AND LOWER(d.delegate) = LOWER($3)
+         AND LOWER(d.contract_address) = $4",
+        tenant_id,
+        column_encryption.encrypt_lookup(delegator),
+        column_encryption.encrypt_lookup(delegate),
+        contract_address,
+    )
+    .fetch_optional(pool)
+    .await?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    Ok(delegation.filter(|d| d.expiry_date > now))
+}
+
+async fn complete(
+    trx: &mut Transaction<'_, Postgres>,
+    conf: &UserDecryptConfig,
+    request: &UserDecryptionRequest,
+    outcome: Outcome,
+) -> Result<(), ExecutionError> {
+    let decryption_id = &request.decryption_id;
+    match outcome {
+        Outcome::Completed { result, signature } => {
+            sqlx::query!(
+                "INSERT INTO decryption_responses
+                    (decryption_id, response_type, result, signature, extra_data)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT DO NOTHING",
+                decryption_id,
+                DecryptionResponseType::User as i16,
+                conf.column_encryption.encrypt(&result),
+                signature,
+                request.extra_data,
+            )
+            .execute(trx.as_mut())
+            .await?;
+            set_status(trx, decryption_id, "completed", None).await?;
+            USER_DECRYPT_COMPLETED_COUNTER.inc();
+            USER_DECRYPT_LATENCY_HISTOGRAM.observe(request.age.as_secs_f64());
+            info!(
+                decryption_id = compact_hex(decryption_id),
+                latency = ?request.age,
+                "User decryption re-encrypted"
+            );
+        }
+        Outcome::Rejected(reason) => {
+            set_status(trx, decryption_id, "rejected", Some(&reason)).await?;
+            USER_DECRYPT_REJECTED_COUNTER.inc();
+            warn!(
+                decryption_id = compact_hex(decryption_id),
+                reason, "User decryption rejected"
+            );
+        }
+        Outcome::Retry(kind, reason) if request.retry_count + 1 >= conf.max_retries => {
+            dead_letter(trx, request, kind, &reason).await?;
+            error!(
+                decryption_id = compact_hex(decryption_id),
+                retry_count = request.retry_count + 1,
+                kind = kind.as_str(),
+                reason,
+                "User decryption failed, max retries reached"
+            );
+        }
+        Outcome::Poison(reason) => {
+            dead_letter(trx, request, FailureKind::Poison, &reason).await?;
+            error!(
+                decryption_id = compact_hex(decryption_id),
+                reason, "User decryption cannot be processed"
+            );
+        }
+        Outcome::Retry(_, reason) => {
+            sqlx::query!(
+                "UPDATE user_decryption_requests
+                 SET retry_count = retry_count + 1, last_error = $2, last_error_at = NOW(),
+                     claimed_until = NULL
+                 WHERE decryption_id = $1",
+                decryption_id,
+                reason,
+            )
+            .execute(trx.as_mut())
+            .await?;
+            info!(
+                decryption_id = compact_hex(decryption_id),
+                retry_count = request.retry_count + 1,
+                reason,
+                "User decryption not ready, will retry"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Marks the request as failed and records it in the dead-letter table, from which it can be
+/// requeued once the cause is fixed.
+async fn dead_letter(
+    trx: &mut Transaction<'_, Postgres>,
+    request: &UserDecryptionRequest,
+    kind: FailureKind,
+    reason: &str,
+) -> Result<(), ExecutionError> {
+    set_status(trx, &request.decryption_id, "failed", Some(reason)).await?;
+    sqlx::query!(
+        "INSERT INTO user_decryption_dead_letters (decryption_id, kind, error, retry_count)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (decryption_id) DO UPDATE
+         SET kind = EXCLUDED.kind, error = EXCLUDED.error, retry_count = EXCLUDED.retry_count,
+             created_at = NOW()",
+        request.decryption_id,
+        kind.as_str(),
+        reason,
+        request.retry_count + 1,
+    )
+    .execute(trx.as_mut())
+    .await?;
+    USER_DECRYPT_FAILED_COUNTER.inc();
+    Ok(())
+}
+
+async fn set_status(
+    trx: &mut Transaction<'_, Postgres>,
+    decryption_id: &[u8],
+    status: &str,
+    error: Option<&str>,
+) -> Result<(), ExecutionError> {
+    sqlx::query!(
+        "UPDATE user_decryption_requests
+         SET status = $2, last_error = COALESCE($3, last_error), completed_at = NOW(),
+             claimed_until = NULL
+         WHERE decryption_id = $1",
+        decryption_id,
+        status,
+        error,
+    )
+    .execute(trx.as_mut())
+    .await?;
+    Ok(())
+}
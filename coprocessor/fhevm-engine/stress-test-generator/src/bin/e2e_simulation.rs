@@ -0,0 +1,455 @@
+//! Drives the whole coprocessor pipeline in a single process:
+//! generated host events -> host-listener DB layer -> tfhe-worker -> sns-worker -> zkproof-worker
+//! -> transaction-sender -> Gateway mocks deployed on Anvil.
+//!
+//! ERC20 transfers are generated at a given rate, then the pipeline is left to drain and the
+//! outcome is checked against the events emitted on the Gateway chain.
+
+use std::ops::Sub;
+use std::time::{Duration, Instant};
+
+use alloy::network::EthereumWallet;
+use alloy::providers::{ProviderBuilder, WsConnect};
+use alloy::signers::Signer;
+use alloy::sol;
+use clap::Parser;
+use host_listener::database::tfhe_event_propagate::{Database as ListenerDatabase, Handle};
+use humantime::parse_duration;
+use stress_test_generator::args::Args as GeneratorArgs;
+use stress_test_generator::erc20::erc20_transaction;
+use stress_test_generator::simulation::{ciphertext_digests, PipelineStatus, SimulationReport};
+use stress_test_generator::utils::{
+    default_dependence_cache_size, Context, ERCTransferVariant, EnvConfig, Inputs,
+};
+use stress_test_generator::zk_gen::get_inputs_vector;
+use test_harness::anvil::{events_since, AnvilFixture};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, Level};
+use transaction_sender::{
+    make_abstract_signer, ConfigSettings, FillersWithoutNonceManagement, NonceManagedProvider,
+    TransactionSender,
+};
+
+sol!(
+    #[sol(rpc)]
+    InputVerification,
+    "../transaction-sender/artifacts/InputVerification.sol/InputVerification.json"
+);
+
+sol!(
+    #[sol(rpc)]
+    CiphertextCommits,
+    "../transaction-sender/artifacts/CiphertextCommits.sol/CiphertextCommits.json"
+);
+
+sol!(
+    #[sol(rpc)]
+    MultichainACL,
+    "../transaction-sender/artifacts/MultichainACL.sol/MultichainACL.json"
+);
+
+const GATEWAY_CHAIN_ID: u64 = 54321;
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// ERC20 transfers generated per second
+    #[arg(long, default_value_t = 1.0, value_parser = parse_rate)]
+    rate: f64,
+
+    /// How long to generate transfers for
+    #[arg(long, default_value = "60s", value_parser = parse_duration)]
+    duration: Duration,
+
+    /// How long to wait for the pipeline to drain once the generation is over
+    #[arg(long, default_value = "300s", value_parser = parse_duration)]
+    drain_timeout: Duration,
+
+    /// Use the ERC20 transfer variant without CMUX
+    #[arg(long)]
+    no_cmux: bool,
+
+    /// Encrypt and prove new inputs for each transfer instead of reusing a few cached ones
+    #[arg(long)]
+    new_inputs: bool,
+
+    /// Contract address of the generated events
+    #[arg(long, default_value = "0xa5880e99d86F081E8D3868A8C4732C8f65dfdB07")]
+    contract_address: String,
+
+    /// User address of the generated events
+    #[arg(long, default_value = "0xa0534e99d86F081E8D3868A8C4732C8f65dfdB07")]
+    user_address: String,
+
+    /// Number of threads used by tfhe-worker for FHE computations
+    #[arg(long, default_value_t = 8)]
+    coprocessor_fhe_threads: usize,
+
+    /// Number of zkproof workers
+    #[arg(long, default_value_t = 4)]
+    zkproof_worker_threads: u32,
+
+    /// S3 bucket for ciphertext128, S3 is configured with the usual AWS environment variables
+    #[arg(long, default_value = "ct128")]
+    bucket_name_ct128: String,
+
+    /// S3 bucket for ciphertext64
+    #[arg(long, default_value = "ct64")]
+    bucket_name_ct64: String,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(Level),
+        default_value_t = Level::INFO)]
+    log_level: Level,
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err("must be a positive number".to_string());
+    }
+    Ok(rate)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let ecfg = EnvConfig::new();
+    let cancel_token = CancellationToken::new();
+
+    // tfhe-worker installs the global tracing subscriber, hence it is started first.
+    let (close_tfhe_worker, close_rx) = tokio::sync::watch::channel(false);
+    start_tfhe_worker(&args, &ecfg, close_rx);
+
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&ecfg.evgen_db_url)
+        .await?;
+    let baseline = PipelineStatus::fetch(&pool).await?;
+
+    start_zkproof_worker(&args, &ecfg, cancel_token.child_token()).await?;
+    start_sns_worker(&args, &ecfg, cancel_token.child_token());
+
+    let anvil = AnvilFixture::start_with(GATEWAY_CHAIN_ID, None)?;
+    // The transaction-sender manages the nonces of account 0, deploy with account 1.
+    let deployer = anvil.provider(1).await?;
+    let input_verification = InputVerification::deploy(&deployer, false, false, false).await?;
+    let ciphertext_commits = CiphertextCommits::deploy(&deployer, false).await?;
+    let multichain_acl = MultichainACL::deploy(&deployer, false).await?;
+
+    let mut signer = anvil.signer(0);
+    signer.set_chain_id(Some(GATEWAY_CHAIN_ID));
+    let wallet = EthereumWallet::from(signer.clone());
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(wallet)
+            .connect_ws(WsConnect::new(anvil.ws_endpoint_url()))
+            .await?,
+        Some(signer.address()),
+    );
+    let txn_sender = TransactionSender::new(
+        *input_verification.address(),
+        *ciphertext_commits.address(),
+        *multichain_acl.address(),
+        make_abstract_signer(signer),
+        provider,
+        cancel_token.child_token(),
+        ConfigSettings {
+            database_url: ecfg.evgen_db_url.clone(),
+            db_polling_interval_secs: 1,
+            ..Default::default()
+        },
+        None,
+    )
+    .await?;
+    let txn_sender_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    info!(args = ?args, "Starting the simulation");
+    let started_at = Instant::now();
+    let generated_transactions = generate_transfers(&args, &ecfg, &cancel_token).await?;
+    let generation_secs = started_at.elapsed().as_secs_f64();
+
+    let drained = wait_for_drain(&pool, args.drain_timeout).await?;
+    let total_secs = started_at.elapsed().as_secs_f64();
+    let status = PipelineStatus::fetch(&pool).await?;
+
+    let add_ciphertext_events = events_since::<CiphertextCommits::AddCiphertextMaterial, _>(
+        &deployer,
+        *ciphertext_commits.address(),
+        0,
+    )
+    .await?;
+    let allow_account_events =
+        events_since::<MultichainACL::AllowAccount, _>(&deployer, *multichain_acl.address(), 0)
+            .await?;
+    let allow_public_decrypt_events = events_since::<MultichainACL::AllowPublicDecrypt, _>(
+        &deployer,
+        *multichain_acl.address(),
+        0,
+    )
+    .await?;
+    let verify_proof_events = events_since::<InputVerification::VerifyProofResponse, _>(
+        &deployer,
+        *input_verification.address(),
+        0,
+    )
+    .await?;
+    let reject_proof_events = events_since::<InputVerification::RejectProofResponse, _>(
+        &deployer,
+        *input_verification.address(),
+        0,
+    )
+    .await?;
+
+    let handles: Vec<Vec<u8>> = add_ciphertext_events
+        .iter()
+        .map(|e| e.ctHandle.to_vec())
+        .collect();
+    let digests = ciphertext_digests(&pool, ecfg.tenant_id, &handles).await?;
+    let digest_mismatches = add_ciphertext_events
+        .iter()
+        .filter(|e| match digests.get(e.ctHandle.as_slice()) {
+            Some((ct64, ct128)) => {
+                ct64.as_deref() != Some(e.ciphertextDigest.as_slice())
+                    || ct128.as_deref() != Some(e.snsCiphertextDigest.as_slice())
+            }
+            None => true,
+        })
+        .map(|e| e.ctHandle.to_string())
+        .collect();
+
+    let report = SimulationReport {
+        generated_transactions,
+        generation_secs,
+        total_secs,
+        drained,
+        new_failed_computations: status.failed_computations - baseline.failed_computations,
+        new_sent_digests: status.sent_digests - baseline.sent_digests,
+        new_sent_allowed_handles: status.sent_allowed_handles - baseline.sent_allowed_handles,
+        add_ciphertext_events: add_ciphertext_events.len(),
+        allow_events: allow_account_events.len() + allow_public_decrypt_events.len(),
+        verify_proof_events: verify_proof_events.len(),
+        reject_proof_events: reject_proof_events.len(),
+        digest_mismatches,
+    };
+
+    cancel_token.cancel();
+    let _ = close_tfhe_worker.send(true);
+    let _ = txn_sender_handle.await;
+
+    let failures = report.failures();
+    info!(
+        report = ?report,
+        transfers_per_sec = report.generated_transactions as f64 / report.generation_secs,
+        digests_per_sec = report.digests_per_sec(),
+        failures = ?failures,
+        "Simulation done"
+    );
+    if !failures.is_empty() {
+        anyhow::bail!("Simulation failed: {}", failures.join("; "));
+    }
+    Ok(())
+}
+
+fn start_tfhe_worker(args: &Args, ecfg: &EnvConfig, close_rx: tokio::sync::watch::Receiver<bool>) {
+    let fhe_threads = args.coprocessor_fhe_threads.to_string();
+    let log_level = args.log_level.to_string();
+    let worker_args = tfhe_worker::daemon_cli::Args::parse_from([
+        "tfhe_worker",
+        "--run-bg-worker",
+        "--database-url",
+        &ecfg.evgen_db_url,
+        "--coprocessor-fhe-threads",
+        &fhe_threads,
+        "--metrics-addr",
+        "",
+        "--health-check-port",
+        "0",
+        "--log-level",
+        &log_level,
+    ]);
+    std::thread::spawn(move || tfhe_worker::start_runtime(worker_args, Some(close_rx)));
+    // Let the worker set up tracing before anything is logged
+    std::thread::sleep(Duration::from_millis(500));
+}
+
+async fn start_zkproof_worker(
+    args: &Args,
+    ecfg: &EnvConfig,
+    token: CancellationToken,
+) -> anyhow::Result<()> {
+    let conf = zkproof_worker::Config {
+        database_url: ecfg.evgen_db_url.clone(),
+        database_schema: None,
+        listen_database_channel: "event_zkpok_new_work".to_owned(),
+        notify_database_channel: "event_zkpok_computed".to_owned(),
+        pg_pool_connections: 5,
+        pg_polling_interval: 1,
+        pg_timeout: Duration::from_secs(15),
+        pg_auto_explain_with_min_duration: None,
+        worker_thread_count: args.zkproof_worker_threads,
+        small_proof_workers: 0,
+        small_proof_max_bytes: 16 * 1024,
+    };
+    let Some(service) = zkproof_worker::verifier::ZkProofService::create(conf, token).await else {
+        anyhow::bail!("Failed to create zkproof service");
+    };
+    tokio::spawn(async move {
+        if let Err(err) = service.run().await {
+            error!(error = %err, "zkproof-worker failed");
+        }
+    });
+    Ok(())
+}
+
+fn start_sns_worker(args: &Args, ecfg: &EnvConfig, token: CancellationToken) {
+    let config = sns_worker::Config {
+        tenant_api_key: ecfg.api_key.clone(),
+        service_name: "".to_owned(),
+        db: sns_worker::DBConfig {
+            url: ecfg.evgen_db_url.clone(),
+            schema: None,
+            listen_channels: vec![
+                "event_pbs_computations".to_owned(),
+                "event_ciphertext_computed".to_owned(),
+            ],
+            notify_channel: "event_pbs_computed".to_owned(),
+            key_activated_channel: sns_worker::EVENT_KEY_ACTIVATED.to_owned(),
+            batch_limit: 4,
+            gc_batch_limit: 80,
+            polling_interval: 1,
+            cleanup_interval: Duration::from_secs(120),
+            max_connections: 5,
+            timeout: Duration::from_secs(15),
+            lifo: false,
+        },
+        s3: sns_worker::S3Config {
+            bucket_ct128: args.bucket_name_ct128.clone(),
+            bucket_ct64: args.bucket_name_ct64.clone(),
+            max_concurrent_uploads: 100,
+            retry_policy: sns_worker::S3RetryPolicy {
+                max_retries_per_upload: 100,
+                max_backoff: Duration::from_secs(10),
+                max_retries_timeout: Duration::from_secs(120),
+                recheck_duration: Duration::from_secs(2),
+                regular_recheck_duration: Duration::from_secs(120),
+            },
+        },
+        log_level: args.log_level,
+        health_checks: sns_worker::HealthCheckConfig {
+            liveness_threshold: Duration::from_secs(10),
+            port: 0,
+            admin_token: None,
+        },
+        enable_compression: true,
+        schedule_policy: sns_worker::SchedulePolicy::RayonParallel,
+        pg_auto_explain_with_min_duration: None,
+        buffer_pool_max_bytes: fhevm_engine_common::buffer_pool::DEFAULT_MAX_RETAINED_BYTES,
+        user_decrypt: None,
+        ciphertext_api: None,
+        keys_cache_dir: None,
+        staging_dir: None,
+        diagnostics_dir: None,
+    };
+    tokio::spawn(async move {
+        if let Err(err) = sns_worker::run_all(config, token, None).await {
+            error!(error = %err, "sns-worker failed");
+        }
+    });
+}
+
+/// Generates ERC20 transfers at `args.rate` for `args.duration`, returns the number of transfers.
+async fn generate_transfers(
+    args: &Args,
+    ecfg: &EnvConfig,
+    cancel_token: &CancellationToken,
+) -> anyhow::Result<u64> {
+    let ctx = Context {
+        args: GeneratorArgs {
+            run_server: false,
+            listen_address: "".to_owned(),
+            zkproof_notify_channel: "event_zkpok_new_work".to_owned(),
+            log_level: args.log_level.to_string(),
+        },
+        ecfg: ecfg.clone(),
+        cancel_token: cancel_token.clone(),
+    };
+    let api_key = sqlx::types::Uuid::parse_str(&ecfg.api_key)?;
+    let mut listener_event_to_db = ListenerDatabase::new(
+        &ecfg.evgen_db_url,
+        &api_key,
+        default_dependence_cache_size(),
+    )
+    .await?;
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&ecfg.evgen_db_url)
+        .await?;
+    let variant = if args.no_cmux {
+        ERCTransferVariant::NoCMUX
+    } else {
+        ERCTransferVariant::Whitepaper
+    };
+    let inputs = if args.new_inputs {
+        Inputs::NewInputs
+    } else {
+        Inputs::ReuseInputs
+    };
+
+    let time_between_transactions = Duration::from_secs_f64(1.0 / args.rate);
+    let end_target = Instant::now() + args.duration;
+    let mut txn_counter = 0;
+    while Instant::now() < end_target && !cancel_token.is_cancelled() {
+        let transaction_start = Instant::now();
+        let inputs: Vec<Option<Handle>> = get_inputs_vector(
+            &ctx,
+            inputs.clone(),
+            &args.contract_address,
+            &args.user_address,
+        )
+        .await
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+        erc20_transaction(
+            &ctx,
+            inputs[0],
+            inputs[1],
+            inputs[2],
+            None, // Transaction ID
+            &mut listener_event_to_db,
+            &pool,
+            variant.clone(),
+            &args.contract_address,
+            &args.user_address,
+        )
+        .await
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+        txn_counter += 1;
+
+        // Best effort if the target rate cannot be sustained
+        let elapsed = transaction_start.elapsed();
+        if time_between_transactions > elapsed {
+            tokio::time::sleep(time_between_transactions.sub(elapsed)).await;
+        }
+    }
+    info!(txn_counter, "Finished generating transfers");
+    Ok(txn_counter)
+}
+
+/// Waits until no stage has pending work, returns false on timeout.
+async fn wait_for_drain(pool: &sqlx::PgPool, timeout: Duration) -> anyhow::Result<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let status = PipelineStatus::fetch(pool).await?;
+        info!(status = ?status, "Pipeline status");
+        if status.is_drained() {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        tokio::time::sleep(STATUS_INTERVAL).await;
+    }
+}
@@ -0,0 +1,302 @@
+//! Inserts synthetic work directly into the coprocessor queues and measures how fast the running
+//! services drain them, for capacity planning.
+//!
+//! Unlike `stress_generator`, no host event goes through the host-listener: computations, PBS
+//! computations, allowed handles, proof requests and delegations are bulk inserted at the
+//! configured rates, so the load is only bounded by the database. The services under test (tfhe-worker, sns-worker,
+//! zkproof-worker, transaction-sender) run separately against the same database.
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use fhevm_engine_common::column_encryption::ColumnEncryption;
+use humantime::parse_duration;
+use sqlx::postgres::PgPoolOptions;
+use stress_test_generator::loadgen::{
+    insert_computations, insert_proofs, ChainMix, DrainTracker, LoadCounters, LoadTenant, Pacer,
+    QueueStats, Shape,
+};
+use stress_test_generator::seed::insert_delegations;
+use stress_test_generator::simulation::PipelineStatus;
+use stress_test_generator::utils::EnvConfig;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, Level};
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Host chains to generate load for, with their share of the rates, e.g. 12345:3,54321:1
+    #[arg(long, default_value = "12345:1")]
+    chains: ChainMix,
+
+    /// Transactions of computations inserted per second, over all chains
+    #[arg(long, default_value_t = 10.0)]
+    computations_rate: f64,
+
+    /// FheAdd computations per transaction, on top of the two trivial encryptions of the operands
+    #[arg(long, default_value_t = 4)]
+    ops_per_transaction: u16,
+
+    /// Proof requests inserted per second, over all chains
+    #[arg(long, default_value_t = 1.0)]
+    proofs_rate: f64,
+
+    /// Number of inputs in each proof
+    #[arg(long, default_value_t = 2)]
+    inputs_per_proof: u8,
+
+    /// User decryption delegations inserted per second, over all chains
+    #[arg(long, default_value_t = 0.0)]
+    delegations_rate: f64,
+
+    /// Time until the delegations expire
+    #[arg(long, default_value = "30days", value_parser = parse_duration)]
+    delegation_expiry: Duration,
+
+    /// Column key of the host-listener, file:<path> or env:<variable>, when the delegations are
+    /// encrypted
+    #[arg(long)]
+    column_encryption_key: Option<String>,
+
+    /// How the rows are spread over time
+    #[arg(long, value_enum, default_value_t = Shape::Steady)]
+    shape: Shape,
+
+    /// Interval between two bursts with the bursty shape
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    burst_period: Duration,
+
+    /// How long to generate load for
+    #[arg(long, default_value = "60s", value_parser = parse_duration)]
+    duration: Duration,
+
+    /// How long to wait for the queues to drain once the generation is over
+    #[arg(long, default_value = "300s", value_parser = parse_duration)]
+    drain_timeout: Duration,
+
+    /// Interval between two measurements of the queues
+    #[arg(long, default_value = "5s", value_parser = parse_duration)]
+    sample_interval: Duration,
+
+    /// Contract address of the proof requests
+    #[arg(long, default_value = "0xa5880e99d86F081E8D3868A8C4732C8f65dfdB07")]
+    contract_address: String,
+
+    /// User address of the proof requests
+    #[arg(long, default_value = "0xa0534e99d86F081E8D3868A8C4732C8f65dfdB07")]
+    user_address: String,
+
+    /// Channel notified when proof requests are inserted
+    #[arg(long, default_value = "event_zkpok_new_work")]
+    zkproof_notify_channel: String,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(Level),
+        default_value_t = Level::INFO)]
+    log_level: Level,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .json()
+        .with_level(true)
+        .with_max_level(args.log_level)
+        .init();
+
+    let ecfg = EnvConfig::new();
+    let pool = PgPoolOptions::new()
+        .max_connections(10)
+        .connect(&ecfg.evgen_db_url)
+        .await?;
+    let column_encryption = Arc::new(match &args.column_encryption_key {
+        Some(secret) => ColumnEncryption::from_secret(secret)?,
+        None => ColumnEncryption::default(),
+    });
+
+    let mut tenants = vec![];
+    for (chain_id, share) in args.chains.shares() {
+        info!(chain_id, share, "Proving inputs for chain");
+        let tenant = LoadTenant::load(
+            &pool,
+            chain_id,
+            args.inputs_per_proof,
+            &args.contract_address,
+            &args.user_address,
+        )
+        .await?;
+        tenants.push((Arc::new(tenant), share));
+    }
+
+    let baseline = PipelineStatus::fetch(&pool).await?;
+    let mut tracker = DrainTracker::new(baseline);
+    let counters = Arc::new(LoadCounters::default());
+    let stop = CancellationToken::new();
+    let started_at = Instant::now();
+
+    let mut generators = JoinSet::new();
+    for (tenant, share) in tenants {
+        generators.spawn(generate(
+            args.clone(),
+            pool.clone(),
+            tenant,
+            share,
+            column_encryption.clone(),
+            counters.clone(),
+            stop.clone(),
+        ));
+    }
+
+    // Measure while generating then until the queues are drained
+    let mut drained_at = None;
+    let mut interval = tokio::time::interval(args.sample_interval);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let elapsed = started_at.elapsed();
+        if elapsed >= args.duration && !stop.is_cancelled() {
+            info!("Generation done, waiting for the queues to drain");
+            stop.cancel();
+            while let Some(res) = generators.join_next().await {
+                res??;
+            }
+        }
+
+        let status = PipelineStatus::fetch(&pool).await?;
+        tracker.sample(&status, &counters, args.sample_interval);
+        info!(
+            elapsed = ?elapsed,
+            computations = ?tracker.computations,
+            pbs_computations = ?tracker.pbs_computations,
+            proofs = ?tracker.proofs,
+            failed_computations = tracker.failed_computations,
+            "Queues"
+        );
+
+        if stop.is_cancelled() {
+            if tracker.is_drained() {
+                drained_at = Some(started_at.elapsed());
+                break;
+            }
+            if elapsed >= args.duration + args.drain_timeout {
+                break;
+            }
+        }
+    }
+
+    let elapsed = drained_at.unwrap_or_else(|| started_at.elapsed());
+    report("computations", &tracker.computations, elapsed);
+    report("pbs_computations", &tracker.pbs_computations, elapsed);
+    report("proofs", &tracker.proofs, elapsed);
+    // Delegations are looked up, not processed: there is nothing to drain
+    info!(
+        inserted = counters.delegations.load(Ordering::Relaxed),
+        "Delegations"
+    );
+    match drained_at {
+        Some(drained_at) => info!(
+            drained_at = ?drained_at,
+            drain_time = ?drained_at.saturating_sub(args.duration),
+            failed_computations = tracker.failed_computations,
+            "Queues drained"
+        ),
+        None => {
+            error!(
+                drain_timeout = ?args.drain_timeout,
+                failed_computations = tracker.failed_computations,
+                "Queues not drained before the timeout"
+            );
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// Inserts the share of the load of a single chain until `stop` is cancelled.
+async fn generate(
+    args: Args,
+    pool: sqlx::PgPool,
+    tenant: Arc<LoadTenant>,
+    share: f64,
+    column_encryption: Arc<ColumnEncryption>,
+    counters: Arc<LoadCounters>,
+    stop: CancellationToken,
+) -> anyhow::Result<()> {
+    let tick = args.shape.tick(args.burst_period);
+    let mut computations = Pacer::new(args.computations_rate * share);
+    let mut proofs = Pacer::new(args.proofs_rate * share);
+    let mut delegations = Pacer::new(args.delegations_rate * share);
+    let mut last = Instant::now();
+    let mut interval = tokio::time::interval(tick);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stop.cancelled() => return Ok(()),
+        }
+        let now = Instant::now();
+        let elapsed = now - last;
+        last = now;
+
+        let transactions = computations.due(elapsed);
+        if transactions > 0 {
+            let inserted =
+                insert_computations(&pool, &tenant, transactions, args.ops_per_transaction).await?;
+            counters.computations.fetch_add(inserted, Ordering::Relaxed);
+            counters
+                .pbs_computations
+                .fetch_add(transactions, Ordering::Relaxed);
+        }
+
+        let count = proofs.due(elapsed);
+        let inserted = insert_proofs(
+            &pool,
+            &tenant,
+            count,
+            &args.contract_address,
+            &args.user_address,
+            &args.zkproof_notify_channel,
+        )
+        .await?;
+        counters.proofs.fetch_add(inserted, Ordering::Relaxed);
+
+        let delegators = match delegations.due(elapsed) {
+            0 => 0,
+            count => insert_delegations(
+                &pool,
+                &tenant,
+                count,
+                &args.contract_address,
+                args.delegation_expiry,
+                &column_encryption,
+            )
+            .await?
+            .len() as u64,
+        };
+        counters
+            .delegations
+            .fetch_add(delegators, Ordering::Relaxed);
+        tracing::debug!(
+            chain_id = tenant.chain_id,
+            transactions,
+            proofs = inserted,
+            delegations = delegators,
+            "Inserted"
+        );
+    }
+}
+
+fn report(queue: &str, stats: &QueueStats, elapsed: Duration) {
+    info!(
+        queue,
+        inserted = stats.inserted,
+        processed = stats.processed,
+        peak_backlog = stats.peak_backlog,
+        drain_rate = stats.drain_rate(elapsed),
+        "Drain rate (rows/s)"
+    );
+}
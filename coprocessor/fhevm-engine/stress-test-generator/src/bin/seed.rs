@@ -0,0 +1,154 @@
+//! Populates a development database with synthetic delegations, computations, allowed handles and
+//! ciphertext metadata, optionally uploading the matching dummy objects to S3 (e.g. MinIO).
+//!
+//! The tenants must exist, e.g. inserted with the keys by the database migration of the local
+//! setup.
+
+use std::time::Duration;
+
+use aws_config::BehaviorVersion;
+use clap::Parser;
+use fhevm_engine_common::column_encryption::ColumnEncryption;
+use humantime::parse_duration;
+use sqlx::postgres::PgPoolOptions;
+use stress_test_generator::loadgen::insert_computations;
+use stress_test_generator::seed::{
+    insert_ciphertexts, insert_delegations, load_tenant, upload_dummy_objects,
+};
+use stress_test_generator::utils::EnvConfig;
+use tracing::{info, Level};
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Host chains to seed, each mapped to its tenant in the `tenants` table
+    #[arg(long, value_delimiter = ',', default_value = "12345")]
+    chain_ids: Vec<i64>,
+
+    /// Delegations per chain, each from a new delegator to a new delegate
+    #[arg(long, default_value_t = 10)]
+    delegations: u64,
+
+    /// Transactions of computations per chain, left for the tfhe-worker and sns-worker to process
+    #[arg(long, default_value_t = 0)]
+    transactions: u64,
+
+    /// FheAdd computations per transaction, on top of the two trivial encryptions of the operands
+    #[arg(long, default_value_t = 4)]
+    ops_per_transaction: u16,
+
+    /// Handles with ciphertext metadata per chain, each allowed to one of the delegators
+    #[arg(long, default_value_t = 100)]
+    ciphertexts: u64,
+
+    /// Size of the dummy objects the ciphertext digests are computed over
+    #[arg(long, default_value_t = 1024)]
+    object_size: usize,
+
+    /// Leave the digests and allowed handles to be sent by the transaction-sender instead of
+    /// marking them as sent
+    #[arg(long)]
+    pending: bool,
+
+    /// Contract address of the delegations
+    #[arg(long, default_value = "0xa5880e99d86F081E8D3868A8C4732C8f65dfdB07")]
+    contract_address: String,
+
+    /// Time until the delegations expire
+    #[arg(long, default_value = "30days", value_parser = parse_duration)]
+    delegation_expiry: Duration,
+
+    /// Column key of the host-listener, file:<path> or env:<variable>, when the delegations are
+    /// encrypted
+    #[arg(long)]
+    column_encryption_key: Option<String>,
+
+    /// Upload the dummy objects to S3, configured with the usual AWS environment variables
+    #[arg(long)]
+    upload: bool,
+
+    /// S3 bucket for ciphertext128
+    #[arg(long, default_value = "ct128")]
+    bucket_name_ct128: String,
+
+    /// S3 bucket for ciphertext64
+    #[arg(long, default_value = "ct64")]
+    bucket_name_ct64: String,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(Level),
+        default_value_t = Level::INFO)]
+    log_level: Level,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .json()
+        .with_level(true)
+        .with_max_level(args.log_level)
+        .init();
+
+    let ecfg = EnvConfig::new();
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&ecfg.evgen_db_url)
+        .await?;
+    let column_encryption = match &args.column_encryption_key {
+        Some(secret) => ColumnEncryption::from_secret(secret)?,
+        None => ColumnEncryption::default(),
+    };
+    let s3 = if args.upload {
+        let sdk_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        Some(aws_sdk_s3::Client::new(&sdk_config))
+    } else {
+        None
+    };
+
+    for chain_id in &args.chain_ids {
+        let tenant = load_tenant(&pool, *chain_id).await?;
+        let delegators = insert_delegations(
+            &pool,
+            &tenant,
+            args.delegations,
+            &args.contract_address,
+            args.delegation_expiry,
+            &column_encryption,
+        )
+        .await?;
+        let computations =
+            insert_computations(&pool, &tenant, args.transactions, args.ops_per_transaction)
+                .await?;
+        let handles = insert_ciphertexts(
+            &pool,
+            &tenant,
+            args.ciphertexts,
+            &delegators,
+            args.object_size,
+            args.pending,
+        )
+        .await?;
+        if let Some(s3) = &s3 {
+            upload_dummy_objects(
+                s3,
+                &args.bucket_name_ct64,
+                &args.bucket_name_ct128,
+                &handles,
+                args.object_size,
+            )
+            .await?;
+        }
+        info!(
+            chain_id,
+            tenant_id = tenant.tenant_id,
+            delegations = delegators.len(),
+            computations,
+            ciphertexts = handles.len(),
+            uploaded = s3.is_some(),
+            "Seeded"
+        );
+    }
+    Ok(())
+}
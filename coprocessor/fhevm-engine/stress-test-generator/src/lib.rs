@@ -1,5 +1,8 @@
 pub mod dex;
 pub mod erc20;
+pub mod loadgen;
+pub mod seed;
+pub mod simulation;
 pub mod synthetics;
 pub mod utils;
 pub mod zk_gen;
@@ -0,0 +1,526 @@
+//! Synthetic load inserted directly into the coprocessor queues by the `loadgen` binary, and the
+//! measurement of how fast the services drain them.
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use alloy_primitives::Keccak256;
+use anyhow::Context as _;
+use fhevm_engine_common::handle::{TypedHandle, COMPUTED_HANDLE_INDEX};
+use fhevm_engine_common::types::{AllowEvents, SupportedFheOperations};
+use fhevm_engine_common::utils::{safe_deserialize_key, safe_serialize};
+use rand::Rng;
+use sqlx::PgPool;
+
+use crate::simulation::PipelineStatus;
+use crate::utils::{FheType, DEF_TYPE};
+use crate::zk_gen::{ZkData, ZK_PROOF_ID};
+
+/// How the generated rows are spread over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Shape {
+    /// Small batches every 100ms
+    Steady,
+    /// All the rows due for a burst period are inserted at once
+    Bursty,
+}
+
+const STEADY_TICK: Duration = Duration::from_millis(100);
+
+impl Shape {
+    pub fn tick(&self, burst_period: Duration) -> Duration {
+        match self {
+            Shape::Steady => STEADY_TICK,
+            Shape::Bursty => burst_period,
+        }
+    }
+}
+
+/// Weighted mix of host chains, parsed from `chain_id:weight,chain_id:weight`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainMix(pub Vec<(i64, f64)>);
+
+impl FromStr for ChainMix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mix = vec![];
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (chain_id, weight) = entry.split_once(':').unwrap_or((entry, "1"));
+            let chain_id = chain_id
+                .parse::<i64>()
+                .map_err(|e| format!("invalid chain id in '{entry}': {e}"))?;
+            let weight = weight
+                .parse::<f64>()
+                .map_err(|e| format!("invalid weight in '{entry}': {e}"))?;
+            if weight <= 0.0 {
+                return Err(format!("weight must be positive in '{entry}'"));
+            }
+            mix.push((chain_id, weight));
+        }
+        if mix.is_empty() {
+            return Err("at least one chain is required".to_owned());
+        }
+        Ok(Self(mix))
+    }
+}
+
+impl ChainMix {
+    /// Share of the total rate going to each chain.
+    pub fn shares(&self) -> Vec<(i64, f64)> {
+        let total: f64 = self.0.iter().map(|(_, w)| w).sum();
+        self.0.iter().map(|(c, w)| (*c, w / total)).collect()
+    }
+}
+
+/// Turns a rate into a number of rows due at each tick, carrying the fractional part over.
+#[derive(Debug, Default)]
+pub struct Pacer {
+    rate: f64,
+    due: f64,
+}
+
+impl Pacer {
+    pub fn new(rate: f64) -> Self {
+        Self { rate, due: 0.0 }
+    }
+
+    pub fn due(&mut self, elapsed: Duration) -> u64 {
+        self.due += self.rate * elapsed.as_secs_f64();
+        let whole = self.due.floor();
+        self.due -= whole;
+        whole as u64
+    }
+}
+
+/// A tenant the load is generated for, with a proof reused for all its proof requests.
+pub struct LoadTenant {
+    pub tenant_id: i32,
+    pub chain_id: i64,
+    pub proof: Arc<Vec<u8>>,
+}
+
+impl LoadTenant {
+    /// Loads the tenant of `chain_id` and proves `inputs_per_proof` inputs with its keys.
+    pub async fn load(
+        pool: &PgPool,
+        chain_id: i64,
+        inputs_per_proof: u8,
+        contract_address: &str,
+        user_address: &str,
+    ) -> anyhow::Result<Self> {
+        let tenant = sqlx::query!(
+            "
+            SELECT tenant_id, acl_contract_address, pks_key, public_params
+            FROM tenants
+            WHERE chain_id = $1
+            ",
+            chain_id,
+        )
+        .fetch_optional(pool)
+        .await?
+        .with_context(|| format!("no tenant for chain {chain_id}"))?;
+
+        let zk_data = ZkData {
+            contract_address: contract_address.to_owned(),
+            user_address: user_address.to_owned(),
+            acl_contract_address: tenant.acl_contract_address,
+            chain_id,
+        };
+        let aux_data = zk_data.assemble()?;
+        let proof = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<u8>> {
+            let pks: tfhe::CompactPublicKey = safe_deserialize_key(&tenant.pks_key)?;
+            let public_params: tfhe::zk::CompactPkeCrs =
+                safe_deserialize_key(&tenant.public_params)?;
+            let mut builder = tfhe::ProvenCompactCiphertextList::builder(&pks);
+            for _ in 0..inputs_per_proof {
+                builder.push(rand::rng().random::<u64>());
+            }
+            let list = builder.build_with_proof_packed(
+                &public_params,
+                &aux_data,
+                tfhe::zk::ZkComputeLoad::Proof,
+            )?;
+            Ok(safe_serialize(&list))
+        })
+        .await??;
+
+        Ok(Self {
+            tenant_id: tenant.tenant_id,
+            chain_id,
+            proof: Arc::new(proof),
+        })
+    }
+
+    pub(crate) fn random_handle(&self, ct_type: FheType) -> Vec<u8> {
+        let mut hash = Keccak256::new();
+        hash.update(rand::rng().random::<[u8; 32]>());
+        TypedHandle::from_hash(
+            hash.finalize().0,
+            COMPUTED_HANDLE_INDEX,
+            self.chain_id as u64,
+            ct_type as i16,
+            0,
+        )
+        .into()
+    }
+}
+
+/// Inserts `transactions` transactions of `ops_per_transaction` computations each.
+///
+/// Each transaction trivially encrypts two operands and chains additions on them, like the
+/// host-listener would for a sequence of `FheAdd` events. The last result is allowed, which queues
+/// its PBS computation and the allowed handle.
+pub async fn insert_computations(
+    pool: &PgPool,
+    tenant: &LoadTenant,
+    transactions: u64,
+    ops_per_transaction: u16,
+) -> Result<u64, sqlx::Error> {
+    let ops_per_transaction = ops_per_transaction.max(1);
+    let mut output_handles = vec![];
+    let mut dependencies = vec![];
+    let mut fhe_operations = vec![];
+    let mut is_scalar = vec![];
+    let mut dependence_chain_ids = vec![];
+    let mut transaction_ids = vec![];
+    let mut is_allowed = vec![];
+    let mut allowed = vec![];
+    let mut allowed_transaction_ids = vec![];
+
+    for _ in 0..transactions {
+        let transaction_id = rand::rng().random::<[u8; 32]>().to_vec();
+        let lhs = tenant.random_handle(DEF_TYPE);
+        let rhs = tenant.random_handle(DEF_TYPE);
+        let dependence_chain_id = lhs.clone();
+        for operand in [&lhs, &rhs] {
+            let plaintext = rand::rng().random::<u32>() as u64;
+            output_handles.push(operand.clone());
+            dependencies.push(encode_dependencies(&[
+                alloy_primitives::U256::from(plaintext).to_be_bytes_vec(),
+                vec![DEF_TYPE as u8],
+            ]));
+            fhe_operations.push(SupportedFheOperations::FheTrivialEncrypt as i16);
+            is_scalar.push(true);
+            dependence_chain_ids.push(dependence_chain_id.clone());
+            transaction_ids.push(transaction_id.clone());
+            is_allowed.push(false);
+        }
+
+        let mut result = lhs;
+        for op in 0..ops_per_transaction {
+            let output = tenant.random_handle(DEF_TYPE);
+            output_handles.push(output.clone());
+            dependencies.push(encode_dependencies(&[result, rhs.clone()]));
+            fhe_operations.push(SupportedFheOperations::FheAdd as i16);
+            is_scalar.push(false);
+            dependence_chain_ids.push(dependence_chain_id.clone());
+            transaction_ids.push(transaction_id.clone());
+            is_allowed.push(op + 1 == ops_per_transaction);
+            result = output;
+        }
+        allowed.push(result);
+        allowed_transaction_ids.push(transaction_id);
+    }
+
+    let inserted = output_handles.len() as u64;
+    let tenant_ids = vec![tenant.tenant_id; output_handles.len()];
+    let mut tx = pool.begin().await?;
+    // BYTEA[][] cannot be unnested row by row, the dependencies are passed as their text encoding.
+    sqlx::query!(
+        r#"
+        INSERT INTO computations (
+            tenant_id,
+            output_handle,
+            dependencies,
+            fhe_operation,
+            is_scalar,
+            dependence_chain_id,
+            transaction_id,
+            is_allowed
+        )
+        SELECT t.tenant_id, t.output_handle, t.dependencies::BYTEA[], t.fhe_operation, t.is_scalar,
+            t.dependence_chain_id, t.transaction_id, t.is_allowed
+        FROM UNNEST($1::INTEGER[], $2::BYTEA[], $3::TEXT[], $4::SMALLINT[], $5::BOOLEAN[],
+            $6::BYTEA[], $7::BYTEA[], $8::BOOLEAN[])
+            AS t(tenant_id, output_handle, dependencies, fhe_operation, is_scalar,
+                dependence_chain_id, transaction_id, is_allowed)
+        ON CONFLICT (tenant_id, output_handle, transaction_id) DO NOTHING
+        "#,
+        &tenant_ids,
+        &output_handles,
+        &dependencies,
+        &fhe_operations,
+        &is_scalar,
+        &dependence_chain_ids,
+        &transaction_ids,
+        &is_allowed,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let tenant_ids = vec![tenant.tenant_id; allowed.len()];
+    let account_addresses = vec![String::new(); allowed.len()];
+    let event_types = vec![AllowEvents::AllowedForDecryption as i16; allowed.len()];
+    sqlx::query!(
+        "
+        INSERT INTO allowed_handles(tenant_id, handle, account_address, event_type, transaction_id)
+        SELECT * FROM UNNEST($1::INTEGER[], $2::BYTEA[], $3::TEXT[], $4::SMALLINT[], $5::BYTEA[])
+        ON CONFLICT DO NOTHING
+        ",
+        &tenant_ids,
+        &allowed,
+        &account_addresses,
+        &event_types,
+        &allowed_transaction_ids,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "
+        INSERT INTO pbs_computations(tenant_id, handle, transaction_id)
+        SELECT * FROM UNNEST($1::INTEGER[], $2::BYTEA[], $3::BYTEA[])
+        ON CONFLICT DO NOTHING
+        ",
+        &tenant_ids,
+        &allowed,
+        &allowed_transaction_ids,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(inserted)
+}
+
+/// Postgres array literal of the given byte strings, e.g. `{"\\x01ff","\\x02"}`.
+fn encode_dependencies(dependencies: &[Vec<u8>]) -> String {
+    let items: Vec<String> = dependencies
+        .iter()
+        .map(|d| format!("\"\\\\x{}\"", hex::encode(d)))
+        .collect();
+    format!("{{{}}}", items.join(","))
+}
+
+/// Inserts `count` proof requests reusing the tenant proof and notifies the zkproof-worker.
+pub async fn insert_proofs(
+    pool: &PgPool,
+    tenant: &LoadTenant,
+    count: u64,
+    contract_address: &str,
+    user_address: &str,
+    notify_channel: &str,
+) -> Result<u64, sqlx::Error> {
+    if count == 0 {
+        return Ok(0);
+    }
+    let ids: Vec<i64> = (0..count)
+        .map(|_| ZK_PROOF_ID.fetch_add(1, Ordering::SeqCst))
+        .collect();
+    let transaction_ids: Vec<Vec<u8>> = (0..count)
+        .map(|_| rand::rng().random::<[u8; 32]>().to_vec())
+        .collect();
+
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "
+        INSERT INTO verify_proofs (zk_proof_id, input, chain_id, contract_address, user_address, verified, transaction_id)
+        SELECT id, $2, $3, $4, $5, NULL, transaction_id
+        FROM UNNEST($1::BIGINT[], $6::BYTEA[]) AS t(id, transaction_id)
+        ",
+        &ids,
+        tenant.proof.as_slice(),
+        tenant.chain_id,
+        contract_address,
+        user_address,
+        &transaction_ids,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("SELECT pg_notify($1, '')")
+        .bind(notify_channel)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(count)
+}
+
+/// Rows inserted by the generator tasks, per queue.
+#[derive(Debug, Default)]
+pub struct LoadCounters {
+    pub computations: AtomicU64,
+    pub pbs_computations: AtomicU64,
+    pub proofs: AtomicU64,
+    pub delegations: AtomicU64,
+}
+
+/// Drain measurement of a single queue.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct QueueStats {
+    pub inserted: u64,
+    pub backlog: i64,
+    pub peak_backlog: i64,
+    /// Rows processed since the start of the run.
+    pub processed: i64,
+    /// Rows processed per second over the last sample interval.
+    pub last_rate: f64,
+}
+
+impl QueueStats {
+    fn update(&mut self, baseline: i64, inserted: u64, backlog: i64, interval: Duration) {
+        let processed = baseline + inserted as i64 - backlog;
+        self.last_rate = (processed - self.processed) as f64 / interval.as_secs_f64();
+        self.inserted = inserted;
+        self.backlog = backlog;
+        self.peak_backlog = self.peak_backlog.max(backlog);
+        self.processed = processed;
+    }
+
+    /// Average rows processed per second over `elapsed`.
+    pub fn drain_rate(&self, elapsed: Duration) -> f64 {
+        if elapsed.is_zero() {
+            return 0.0;
+        }
+        self.processed as f64 / elapsed.as_secs_f64()
+    }
+}
+
+/// Tracks the backlog of each queue against what the generator inserted.
+///
+/// Rows queued before the run are part of the baseline, so the processed counts are only
+/// meaningful when nothing else writes to the database.
+#[derive(Debug)]
+pub struct DrainTracker {
+    baseline: PipelineStatus,
+    pub computations: QueueStats,
+    pub pbs_computations: QueueStats,
+    pub proofs: QueueStats,
+    pub failed_computations: i64,
+}
+
+impl DrainTracker {
+    pub fn new(baseline: PipelineStatus) -> Self {
+        Self {
+            baseline,
+            computations: QueueStats::default(),
+            pbs_computations: QueueStats::default(),
+            proofs: QueueStats::default(),
+            failed_computations: 0,
+        }
+    }
+
+    pub fn sample(&mut self, status: &PipelineStatus, counters: &LoadCounters, interval: Duration) {
+        self.computations.update(
+            self.baseline.pending_computations,
+            counters.computations.load(Ordering::Relaxed),
+            status.pending_computations,
+            interval,
+        );
+        self.pbs_computations.update(
+            self.baseline.pending_pbs_computations,
+            counters.pbs_computations.load(Ordering::Relaxed),
+            status.pending_pbs_computations,
+            interval,
+        );
+        self.proofs.update(
+            self.baseline.unverified_proofs,
+            counters.proofs.load(Ordering::Relaxed),
+            status.unverified_proofs,
+            interval,
+        );
+        self.failed_computations = status.failed_computations - self.baseline.failed_computations;
+    }
+
+    /// True when the queues fed by the generator are back to their baseline.
+    pub fn is_drained(&self) -> bool {
+        self.computations.backlog <= self.baseline.pending_computations
+            && self.pbs_computations.backlog <= self.baseline.pending_pbs_computations
+            && self.proofs.backlog <= self.baseline.unverified_proofs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_mix_from_str() {
+        assert_eq!(
+            ChainMix::from_str("12345:3, 67890").unwrap(),
+            ChainMix(vec![(12345, 3.0), (67890, 1.0)])
+        );
+        assert_eq!(
+            ChainMix::from_str("1:1,2:3").unwrap().shares(),
+            vec![(1, 0.25), (2, 0.75)]
+        );
+        assert!(ChainMix::from_str("").is_err());
+        assert!(ChainMix::from_str("chain:1").is_err());
+        assert!(ChainMix::from_str("1:heavy").is_err());
+        assert!(ChainMix::from_str("1:0").is_err());
+        assert!(ChainMix::from_str("1:-2").is_err());
+    }
+
+    #[test]
+    fn pacer_carries_the_fractional_part_over() {
+        let mut pacer = Pacer::new(15.0);
+        let due: Vec<u64> = (0..10)
+            .map(|_| pacer.due(Duration::from_millis(100)))
+            .collect();
+        assert_eq!(due.iter().sum::<u64>(), 15);
+        assert!(due.iter().all(|due| *due == 1 || *due == 2));
+        assert_eq!(Pacer::new(0.0).due(Duration::from_secs(10)), 0);
+    }
+
+    #[test]
+    fn queue_stats_update() {
+        let mut stats = QueueStats::default();
+        // 5 rows queued before the run, 100 inserted, 40 left
+        stats.update(5, 100, 45, Duration::from_secs(2));
+        assert_eq!(stats.processed, 60);
+        assert_eq!(stats.last_rate, 30.0);
+        assert_eq!(stats.peak_backlog, 45);
+
+        stats.update(5, 100, 5, Duration::from_secs(4));
+        assert_eq!(stats.processed, 100);
+        assert_eq!(stats.last_rate, 10.0);
+        assert_eq!(stats.backlog, 5);
+        assert_eq!(stats.peak_backlog, 45);
+        assert_eq!(stats.drain_rate(Duration::from_secs(10)), 10.0);
+        assert_eq!(stats.drain_rate(Duration::ZERO), 0.0);
+    }
+
+    #[test]
+    fn drain_tracker_is_drained_at_the_baseline() {
+        let baseline = PipelineStatus {
+            pending_computations: 5,
+            pending_pbs_computations: 2,
+            unverified_proofs: 1,
+            ..Default::default()
+        };
+        let counters = LoadCounters::default();
+        counters.computations.store(100, Ordering::Relaxed);
+        counters.pbs_computations.store(10, Ordering::Relaxed);
+        counters.proofs.store(10, Ordering::Relaxed);
+        let mut tracker = DrainTracker::new(baseline);
+
+        let mut status = PipelineStatus {
+            pending_computations: 50,
+            pending_pbs_computations: 2,
+            unverified_proofs: 1,
+            ..Default::default()
+        };
+        tracker.sample(&status, &counters, Duration::from_secs(1));
+        assert!(!tracker.is_drained());
+
+        status.pending_computations = 5;
+        tracker.sample(&status, &counters, Duration::from_secs(1));
+        assert!(tracker.is_drained());
+
+        status.unverified_proofs = 2;
+        tracker.sample(&status, &counters, Duration::from_secs(1));
+        assert!(!tracker.is_drained());
+    }
+}
@@ -0,0 +1,257 @@
+//! Synthetic fixtures written by the `seed` binary, to populate a development database without
+//! hand-crafted SQL.
+//!
+//! Unlike `loadgen`, the rows are written once and are not meant to be processed: the ciphertext
+//! metadata points at dummy objects, not at ciphertexts the workers could read.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use alloy_primitives::{Address, Keccak256};
+use anyhow::Context as _;
+use aws_sdk_s3::primitives::ByteStream;
+use fhevm_engine_common::column_encryption::ColumnEncryption;
+use fhevm_engine_common::types::AllowEvents;
+use rand::Rng;
+use sns_worker::Ciphertext128Format;
+use sqlx::PgPool;
+
+use crate::loadgen::LoadTenant;
+use crate::utils::DEF_TYPE;
+
+/// Format of the seeded ciphertext128 digests.
+const CT128_FORMAT: Ciphertext128Format = Ciphertext128Format::UncompressedOnCpu;
+
+/// Loads the tenant of `chain_id`, without proving inputs.
+pub async fn load_tenant(pool: &PgPool, chain_id: i64) -> anyhow::Result<LoadTenant> {
+    let tenant_id = sqlx::query_scalar!(
+        "SELECT tenant_id FROM tenants WHERE chain_id = $1",
+        chain_id,
+    )
+    .fetch_optional(pool)
+    .await?
+    .with_context(|| format!("no tenant for chain {chain_id}"))?;
+    Ok(LoadTenant {
+        tenant_id,
+        chain_id,
+        proof: Default::default(),
+    })
+}
+
+pub fn random_address() -> String {
+    Address::from(rand::rng().random::<[u8; 20]>()).to_string()
+}
+
+/// Inserts `count` delegations to random delegates, each delegator delegating to a single
+/// delegate, and their history. Returns the delegators.
+pub async fn insert_delegations(
+    pool: &PgPool,
+    tenant: &LoadTenant,
+    count: u64,
+    contract_address: &str,
+    expires_in: Duration,
+    column_encryption: &ColumnEncryption,
+) -> Result<Vec<String>, sqlx::Error> {
+    let delegators: Vec<String> = (0..count).map(|_| random_address()).collect();
+    let encrypted_delegators: Vec<String> = delegators
+        .iter()
+        .map(|delegator| column_encryption.encrypt_lookup(delegator))
+        .collect();
+    let delegates: Vec<String> = (0..count)
+        .map(|_| column_encryption.encrypt_lookup(&random_address()))
+        .collect();
+    let expiry_date = (SystemTime::now() + expires_in)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "
+        INSERT INTO user_decryption_delegations
+            (tenant_id, delegator, delegate, contract_address, delegation_counter, expiry_date)
+        SELECT $1, delegator, delegate, $4, 0, $5
+        FROM UNNEST($2::TEXT[], $3::TEXT[]) AS t(delegator, delegate)
+        ON CONFLICT DO NOTHING
+        ",
+        tenant.tenant_id,
+        &encrypted_delegators,
+        &delegates,
+        contract_address,
+        expiry_date,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "
+        INSERT INTO user_decryption_delegation_history
+            (tenant_id, delegator, delegate, contract_address, delegation_counter, expiry_date, status)
+        SELECT $1, delegator, delegate, $4, 0, $5, 'applied'
+        FROM UNNEST($2::TEXT[], $3::TEXT[]) AS t(delegator, delegate)
+        ON CONFLICT DO NOTHING
+        ",
+        tenant.tenant_id,
+        &encrypted_delegators,
+        &delegates,
+        contract_address,
+        expiry_date,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(delegators)
+}
+
+/// Dummy object standing for a ciphertext, derived from its handle so that its digest can be
+/// computed again when uploading it.
+pub fn dummy_object(handle: &[u8], ct128: bool, size: usize) -> Vec<u8> {
+    let mut seed = handle.to_vec();
+    seed.push(ct128 as u8);
+    let mut object = Vec::with_capacity(size);
+    while object.len() < size {
+        let mut hash = Keccak256::new();
+        hash.update(&seed);
+        seed = hash.finalize().to_vec();
+        object.extend_from_slice(&seed);
+    }
+    object.truncate(size);
+    object
+}
+
+/// Keccak256 of the object, its key in the buckets like for the sns-worker uploads.
+pub fn digest(object: &[u8]) -> Vec<u8> {
+    let mut hash = Keccak256::new();
+    hash.update(object);
+    hash.finalize().to_vec()
+}
+
+/// Inserts the ciphertext digests of `count` handles, as if squashed and uploaded by the
+/// sns-worker, each handle being allowed to one of `accounts`. Both are marked as sent to the
+/// Gateway unless `pending`, in which case they are left for the transaction-sender. Returns the
+/// handles.
+pub async fn insert_ciphertexts(
+    pool: &PgPool,
+    tenant: &LoadTenant,
+    count: u64,
+    accounts: &[String],
+    object_size: usize,
+    pending: bool,
+) -> Result<Vec<Vec<u8>>, sqlx::Error> {
+    let handles: Vec<Vec<u8>> = (0..count).map(|_| tenant.random_handle(DEF_TYPE)).collect();
+    let ct64_digests: Vec<Vec<u8>> = handles
+        .iter()
+        .map(|handle| digest(&dummy_object(handle, false, object_size)))
+        .collect();
+    let ct128_digests: Vec<Vec<u8>> = handles
+        .iter()
+        .map(|handle| digest(&dummy_object(handle, true, object_size)))
+        .collect();
+    let account_addresses: Vec<String> = (0..handles.len())
+        .map(|i| match accounts {
+            [] => random_address(),
+            accounts => accounts[i % accounts.len()].clone(),
+        })
+        .collect();
+
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "
+        INSERT INTO ciphertext_digest
+            (tenant_id, handle, ciphertext, ciphertext128, ciphertext128_format, txn_is_sent)
+        SELECT $1, handle, ciphertext, ciphertext128, $4, $5
+        FROM UNNEST($2::BYTEA[], $3::BYTEA[], $6::BYTEA[]) AS t(handle, ciphertext, ciphertext128)
+        ON CONFLICT DO NOTHING
+        ",
+        tenant.tenant_id,
+        &handles,
+        &ct64_digests,
+        i16::from(CT128_FORMAT),
+        !pending,
+        &ct128_digests,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "
+        INSERT INTO allowed_handles (tenant_id, handle, account_address, event_type, txn_is_sent)
+        SELECT $1, handle, account_address, $4, $5
+        FROM UNNEST($2::BYTEA[], $3::TEXT[]) AS t(handle, account_address)
+        ON CONFLICT DO NOTHING
+        ",
+        tenant.tenant_id,
+        &handles,
+        &account_addresses,
+        AllowEvents::AllowedAccount as i16,
+        !pending,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(handles)
+}
+
+/// Uploads the dummy objects of the handles, keyed by digest like the sns-worker uploads.
+pub async fn upload_dummy_objects(
+    client: &aws_sdk_s3::Client,
+    bucket_ct64: &str,
+    bucket_ct128: &str,
+    handles: &[Vec<u8>],
+    object_size: usize,
+) -> anyhow::Result<()> {
+    for handle in handles {
+        for (bucket, ct128) in [(bucket_ct64, false), (bucket_ct128, true)] {
+            let object = dummy_object(handle, ct128, object_size);
+            let mut put = client
+                .put_object()
+                .bucket(bucket)
+                .key(hex::encode(digest(&object)));
+            if ct128 {
+                put = put.metadata("Ct-Format", "uncompressed_on_cpu");
+            }
+            put.body(ByteStream::from(object))
+                .send()
+                .await
+                .with_context(|| format!("uploading to {bucket}"))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_addresses_are_checksummed_addresses() {
+        let address = random_address();
+        assert_eq!(address.len(), 42);
+        assert_eq!(
+            address.parse::<Address>().unwrap().to_checksum(None),
+            address
+        );
+        assert_ne!(random_address(), address);
+    }
+
+    #[test]
+    fn dummy_objects_are_derived_from_their_handle() {
+        let handle = [1u8; 32];
+        let object = dummy_object(&handle, false, 100);
+        assert_eq!(object.len(), 100);
+        assert_eq!(dummy_object(&handle, false, 100), object);
+        // The object is the prefix of the larger ones
+        assert_eq!(dummy_object(&handle, false, 10), object[..10]);
+
+        assert_ne!(dummy_object(&handle, true, 100), object);
+        assert_ne!(dummy_object(&[2u8; 32], false, 100), object);
+        assert!(dummy_object(&handle, false, 0).is_empty());
+    }
+
+    #[test]
+    fn digest_is_keccak256() {
+        assert_eq!(
+            hex::encode(digest(b"")),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+    }
+}
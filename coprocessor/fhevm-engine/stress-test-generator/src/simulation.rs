@@ -0,0 +1,149 @@
+//! Progress tracking and correctness checks for the `e2e_simulation` and `loadgen` binaries.
+
+use std::collections::HashMap;
+
+use sqlx::{PgPool, Postgres};
+
+/// Snapshot of the work queued in the database at each stage of the pipeline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStatus {
+    pub pending_computations: i64,
+    pub failed_computations: i64,
+    pub pending_pbs_computations: i64,
+    pub pending_proofs: i64,
+    pub unverified_proofs: i64,
+    pub pending_digests: i64,
+    pub sent_digests: i64,
+    pub pending_allowed_handles: i64,
+    pub sent_allowed_handles: i64,
+}
+
+impl PipelineStatus {
+    pub async fn fetch(pool: &sqlx::Pool<Postgres>) -> Result<Self, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM computations WHERE is_completed = FALSE AND is_error = FALSE) AS "pending_computations!",
+                (SELECT COUNT(*) FROM computations WHERE is_error = TRUE) AS "failed_computations!",
+                (SELECT COUNT(*) FROM pbs_computations WHERE is_completed = FALSE) AS "pending_pbs_computations!",
+                (SELECT COUNT(*) FROM verify_proofs) AS "pending_proofs!",
+                (SELECT COUNT(*) FROM verify_proofs WHERE verified IS NULL) AS "unverified_proofs!",
+                (SELECT COUNT(*) FROM ciphertext_digest WHERE txn_is_sent = FALSE) AS "pending_digests!",
+                (SELECT COUNT(*) FROM ciphertext_digest WHERE txn_is_sent = TRUE) AS "sent_digests!",
+                (SELECT COUNT(*) FROM allowed_handles WHERE txn_is_sent = FALSE) AS "pending_allowed_handles!",
+                (SELECT COUNT(*) FROM allowed_handles WHERE txn_is_sent = TRUE) AS "sent_allowed_handles!"
+            "#
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(Self {
+            pending_computations: row.pending_computations,
+            failed_computations: row.failed_computations,
+            pending_pbs_computations: row.pending_pbs_computations,
+            pending_proofs: row.pending_proofs,
+            unverified_proofs: row.unverified_proofs,
+            pending_digests: row.pending_digests,
+            sent_digests: row.sent_digests,
+            pending_allowed_handles: row.pending_allowed_handles,
+            sent_allowed_handles: row.sent_allowed_handles,
+        })
+    }
+
+    /// True when no stage has work left.
+    pub fn is_drained(&self) -> bool {
+        self.pending_computations == 0
+            && self.pending_pbs_computations == 0
+            && self.pending_proofs == 0
+            && self.pending_digests == 0
+            && self.pending_allowed_handles == 0
+    }
+}
+
+/// Returns the (ciphertext64, ciphertext128) digests stored for the given handles.
+pub async fn ciphertext_digests(
+    pool: &PgPool,
+    tenant_id: i32,
+    handles: &[Vec<u8>],
+) -> Result<HashMap<Vec<u8>, (Option<Vec<u8>>, Option<Vec<u8>>)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "
+        SELECT handle, ciphertext, ciphertext128
+        FROM ciphertext_digest
+        WHERE tenant_id = $1 AND handle = ANY($2::BYTEA[])
+        ",
+        tenant_id,
+        handles,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.handle, (row.ciphertext, row.ciphertext128)))
+        .collect())
+}
+
+/// Outcome of a simulation run.
+#[derive(Debug, Default)]
+pub struct SimulationReport {
+    pub generated_transactions: u64,
+    pub generation_secs: f64,
+    pub total_secs: f64,
+    pub drained: bool,
+    pub new_failed_computations: i64,
+    pub new_sent_digests: i64,
+    pub new_sent_allowed_handles: i64,
+    pub add_ciphertext_events: usize,
+    pub allow_events: usize,
+    pub verify_proof_events: usize,
+    pub reject_proof_events: usize,
+    /// Handles whose on-chain digests differ from the database.
+    pub digest_mismatches: Vec<String>,
+}
+
+impl SimulationReport {
+    /// Returns the reasons the run is considered failed, if any.
+    pub fn failures(&self) -> Vec<String> {
+        let mut failures = vec![];
+        if !self.drained {
+            failures.push("pipeline did not drain before the timeout".to_owned());
+        }
+        if self.new_failed_computations > 0 {
+            failures.push(format!(
+                "{} computations failed",
+                self.new_failed_computations
+            ));
+        }
+        if self.reject_proof_events > 0 {
+            failures.push(format!("{} proofs rejected", self.reject_proof_events));
+        }
+        if self.add_ciphertext_events as i64 != self.new_sent_digests {
+            failures.push(format!(
+                "{} ciphertext digests marked as sent but {} AddCiphertextMaterial events",
+                self.new_sent_digests, self.add_ciphertext_events
+            ));
+        }
+        if self.allow_events as i64 != self.new_sent_allowed_handles {
+            failures.push(format!(
+                "{} allowed handles marked as sent but {} allow events",
+                self.new_sent_allowed_handles, self.allow_events
+            ));
+        }
+        if !self.digest_mismatches.is_empty() {
+            failures.push(format!(
+                "digest mismatch for handles: {}",
+                self.digest_mismatches.join(", ")
+            ));
+        }
+        failures
+    }
+
+    /// Ciphertext digests committed on chain per second, from the start of the run.
+    pub fn digests_per_sec(&self) -> f64 {
+        if self.total_secs == 0.0 {
+            return 0.0;
+        }
+        self.add_ciphertext_events as f64 / self.total_secs
+    }
+}
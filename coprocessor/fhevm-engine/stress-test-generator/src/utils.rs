@@ -1,5 +1,6 @@
 use alloy_primitives::Keccak256;
 use bigdecimal::num_bigint::BigInt;
+use fhevm_engine_common::handle::{TypedHandle, COMPUTED_HANDLE_INDEX};
 use fhevm_engine_common::{types::AllowEvents, utils::safe_deserialize_key};
 use host_listener::contracts::TfheContract::TfheContractEvents;
 use host_listener::database::tfhe_event_propagate::{
@@ -60,14 +61,14 @@ pub fn next_random_handle(ct_type: FheType) -> Handle {
     let ecfg = EnvConfig::new();
     let mut handle_hash = Keccak256::new();
     handle_hash.update(rand::rng().random::<u64>().to_be_bytes());
-    let mut handle = handle_hash.finalize().to_vec();
-    assert_eq!(handle.len(), 32);
-    // Handle from computation
-    handle[21] = 255u8;
-    handle[22..30].copy_from_slice(&ecfg.chain_id.to_be_bytes());
-    handle[30] = ct_type as u8;
-    handle[31] = 0u8;
-    Handle::from_slice(&handle)
+    TypedHandle::from_hash(
+        handle_hash.finalize().0,
+        COMPUTED_HANDLE_INDEX,
+        ecfg.chain_id as u64,
+        ct_type as i16,
+        0,
+    )
+    .into()
 }
 pub fn default_dependence_cache_size() -> u16 {
     128
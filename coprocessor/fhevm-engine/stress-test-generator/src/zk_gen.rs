@@ -30,7 +30,7 @@ lazy_static::lazy_static! {
 }
 
 #[derive(Debug, Clone)]
-struct ZkData {
+pub struct ZkData {
     pub contract_address: String,
     pub user_address: String,
     pub acl_contract_address: String,
@@ -0,0 +1,252 @@
+//! Anvil based fixtures for the integration tests of the services talking to the Gateway or to a
+//! host chain.
+//!
+//! Contracts are usually deployed through the `sol!` bindings of the crate under test
+//! (`Contract::deploy(&provider, ..)`). [`deploy_artifact`] covers compiled artifacts that have no
+//! bindings, e.g. the Gateway or host contracts compiled by hardhat. [`GatewayMocks`] and
+//! [`HostTestContracts`] deploy the contracts emitting the Gateway and host events.
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use alloy::network::{Ethereum, EthereumWallet, TransactionBuilder};
+use alloy::node_bindings::{Anvil, AnvilInstance};
+use alloy::primitives::{Address, Bytes, U256};
+use alloy::providers::ext::AnvilApi;
+use alloy::providers::{Provider, ProviderBuilder, WsConnect};
+use alloy::rpc::types::anvil::{ReorgOptions, TransactionData};
+use alloy::rpc::types::{Filter, TransactionReceipt, TransactionRequest};
+use alloy::signers::local::PrivateKeySigner;
+use alloy::sol_types::SolEvent;
+
+pub const DEFAULT_CHAIN_ID: u64 = 12345;
+
+/// A running Anvil node, killed on drop.
+pub struct AnvilFixture {
+    anvil: AnvilInstance,
+}
+
+impl AnvilFixture {
+    /// Starts a node mining a block per transaction.
+    pub fn start() -> anyhow::Result<Self> {
+        Self::start_with(DEFAULT_CHAIN_ID, None)
+    }
+
+    /// Starts a node with the given chain ID, mining a block every `block_time` seconds if set.
+    pub fn start_with(chain_id: u64, block_time: Option<u64>) -> anyhow::Result<Self> {
+        let mut anvil = Anvil::new().chain_id(chain_id);
+        if let Some(block_time) = block_time {
+            anvil = anvil.block_time(block_time);
+        }
+        Ok(Self {
+            anvil: anvil.try_spawn()?,
+        })
+    }
+
+    /// Signer of one of the prefunded accounts.
+    pub fn signer(&self, index: usize) -> PrivateKeySigner {
+        self.anvil.keys()[index].clone().into()
+    }
+
+    pub fn wallet(&self, index: usize) -> EthereumWallet {
+        self.signer(index).into()
+    }
+
+    /// A WebSocket provider signing with the prefunded account `index`.
+    pub async fn provider(
+        &self,
+        index: usize,
+    ) -> anyhow::Result<impl Provider<Ethereum> + Clone + 'static> {
+        Ok(ProviderBuilder::new()
+            .wallet(self.wallet(index))
+            .connect_ws(WsConnect::new(self.anvil.ws_endpoint_url()))
+            .await?)
+    }
+}
+
+impl Deref for AnvilFixture {
+    type Target = AnvilInstance;
+
+    fn deref(&self) -> &Self::Target {
+        &self.anvil
+    }
+}
+
+/// Mines `blocks` empty blocks.
+pub async fn mine<P: Provider<Ethereum>>(provider: &P, blocks: u64) -> anyhow::Result<()> {
+    provider.anvil_mine(Some(blocks), None).await?;
+    Ok(())
+}
+
+/// Takes a snapshot of the chain state, to be restored with [`revert_to_snapshot`].
+pub async fn snapshot<P: Provider<Ethereum>>(provider: &P) -> anyhow::Result<U256> {
+    Ok(provider.anvil_snapshot().await?)
+}
+
+pub async fn revert_to_snapshot<P: Provider<Ethereum>>(
+    provider: &P,
+    snapshot_id: U256,
+) -> anyhow::Result<()> {
+    if !provider.anvil_revert(snapshot_id).await? {
+        anyhow::bail!("Failed to revert to snapshot {snapshot_id}");
+    }
+    Ok(())
+}
+
+/// Replaces the last `depth` blocks with new blocks holding only the `replayed` transactions, each
+/// in the new block of the given index, 0 being the first one. Subscribers are notified of the new
+/// chain.
+///
+/// Anvil may not notify subscriptions for shallow reorgs, use a depth of a few tens of blocks when
+/// the code under test relies on subscriptions.
+pub async fn reorg<P: Provider<Ethereum>>(
+    provider: &P,
+    depth: u64,
+    replayed: Vec<(TransactionRequest, u64)>,
+) -> anyhow::Result<()> {
+    provider
+        .anvil_reorg(ReorgOptions {
+            depth,
+            tx_block_pairs: replayed
+                .into_iter()
+                .map(|(txn, block)| (TransactionData::JSON(txn), block))
+                .collect(),
+        })
+        .await?;
+    Ok(())
+}
+
+/// Deploys raw creation code, constructor arguments included, and returns the contract address.
+pub async fn deploy_bytecode<P: Provider<Ethereum>>(
+    provider: &P,
+    creation_code: Bytes,
+) -> anyhow::Result<Address> {
+    let txn = TransactionRequest::default().with_deploy_code(creation_code);
+    let receipt = provider.send_transaction(txn).await?.get_receipt().await?;
+    if !receipt.status() {
+        anyhow::bail!("Deployment reverted, txn: {}", receipt.transaction_hash);
+    }
+    receipt
+        .contract_address
+        .ok_or_else(|| anyhow::anyhow!("No contract address in deployment receipt"))
+}
+
+/// Deploys a hardhat or solc JSON artifact with ABI encoded constructor arguments.
+pub async fn deploy_artifact<P: Provider<Ethereum>>(
+    provider: &P,
+    artifact: impl AsRef<Path>,
+    constructor_args: &[u8],
+) -> anyhow::Result<Address> {
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(artifact.as_ref())?)?;
+    // hardhat: "bytecode": "0x..", solc: "bytecode": { "object": ".." }
+    let bytecode = json["bytecode"]
+        .as_str()
+        .or_else(|| json["bytecode"]["object"].as_str())
+        .ok_or_else(|| {
+            anyhow::anyhow!("No bytecode in artifact {}", artifact.as_ref().display())
+        })?;
+    let mut creation_code = hex::decode(bytecode.trim_start_matches("0x"))?;
+    creation_code.extend_from_slice(constructor_args);
+    deploy_bytecode(provider, creation_code.into()).await
+}
+
+/// Path of a Gateway contract artifact, e.g. `mocks/InputVerificationMock`, compiled by hardhat in
+/// the gw-listener build script.
+pub fn gateway_artifact(contract: &str) -> PathBuf {
+    hardhat_artifact("../../../gateway-contracts/artifacts/contracts", contract)
+}
+
+/// Path of a host contract artifact, e.g. `ACL`, compiled by hardhat in the host-listener build
+/// script.
+pub fn host_artifact(contract: &str) -> PathBuf {
+    hardhat_artifact("../../../host-contracts/artifacts/contracts", contract)
+}
+
+fn hardhat_artifact(artifacts_dir: &str, contract: &str) -> PathBuf {
+    let name = contract.rsplit('/').next().unwrap_or(contract);
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(artifacts_dir)
+        .join(format!("{contract}.sol"))
+        .join(format!("{name}.json"))
+}
+
+/// The Gateway contract mocks, they emit the events of the Gateway contracts without access
+/// control nor proxy.
+#[derive(Clone, Copy, Debug)]
+pub struct GatewayMocks {
+    pub ciphertext_commits: Address,
+    pub decryption: Address,
+    pub gateway_config: Address,
+    pub input_verification: Address,
+    pub kms_generation: Address,
+    pub multichain_acl: Address,
+}
+
+impl GatewayMocks {
+    pub async fn deploy<P: Provider<Ethereum>>(provider: &P) -> anyhow::Result<Self> {
+        let deploy = |contract: &str| deploy_artifact(provider, gateway_artifact(contract), &[]);
+        Ok(Self {
+            ciphertext_commits: deploy("mocks/CiphertextCommitsMock").await?,
+            decryption: deploy("mocks/DecryptionMock").await?,
+            gateway_config: deploy("mocks/GatewayConfigMock").await?,
+            input_verification: deploy("mocks/InputVerificationMock").await?,
+            kms_generation: deploy("mocks/KMSGenerationMock").await?,
+            multichain_acl: deploy("mocks/MultichainACLMock").await?,
+        })
+    }
+}
+
+/// The host-listener test contracts, they emit the events of the host ACL and FHEVMExecutor
+/// contracts without access control nor proxy.
+#[derive(Clone, Copy, Debug)]
+pub struct HostTestContracts {
+    pub acl: Address,
+    pub executor: Address,
+}
+
+impl HostTestContracts {
+    pub async fn deploy<P: Provider<Ethereum>>(provider: &P) -> anyhow::Result<Self> {
+        let artifacts = Path::new(env!("CARGO_MANIFEST_DIR")).join("../host-listener/artifacts");
+        Ok(Self {
+            acl: deploy_artifact(provider, artifacts.join("ACLTest.sol/ACLTest.json"), &[]).await?,
+            executor: deploy_artifact(
+                provider,
+                artifacts.join("FHEVMExecutorTest.sol/FHEVMExecutorTest.json"),
+                &[],
+            )
+            .await?,
+        })
+    }
+}
+
+/// Returns the first event of type `E` emitted in the transaction.
+pub fn expect_event<E: SolEvent>(receipt: &TransactionReceipt) -> anyhow::Result<E> {
+    receipt
+        .decoded_log::<E>()
+        .map(|log| log.data)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} not emitted in txn {}",
+                E::SIGNATURE,
+                receipt.transaction_hash
+            )
+        })
+}
+
+/// Returns the events of type `E` emitted by `address` since `from_block`.
+pub async fn events_since<E: SolEvent, P: Provider<Ethereum>>(
+    provider: &P,
+    address: Address,
+    from_block: u64,
+) -> anyhow::Result<Vec<E>> {
+    let filter = Filter::new()
+        .address(address)
+        .event_signature(E::SIGNATURE_HASH)
+        .from_block(from_block);
+    provider
+        .get_logs(&filter)
+        .await?
+        .iter()
+        .map(|log| Ok(log.log_decode::<E>()?.inner.data))
+        .collect()
+}
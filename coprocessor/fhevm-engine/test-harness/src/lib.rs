@@ -1,5 +1,7 @@
+pub mod anvil;
 pub mod db_utils;
 pub mod health_check;
 pub mod instance;
 pub mod localstack;
+pub mod minio;
 pub mod s3_utils;
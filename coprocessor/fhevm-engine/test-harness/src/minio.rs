@@ -0,0 +1,79 @@
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_s3::config::Credentials;
+use testcontainers::{core::WaitFor, runners::AsyncRunner, ContainerAsync, GenericImage, ImageExt};
+use tracing::info;
+
+pub const MINIO_PORT: u16 = 9000;
+pub const MINIO_ROOT_USER: &str = "minioadmin";
+pub const MINIO_ROOT_PASSWORD: &str = "minioadmin";
+const MINIO_REGION: &str = "us-east-1";
+
+/// A MinIO server serving the S3 API, stopped on drop.
+pub struct MinioContainer {
+    pub container: ContainerAsync<GenericImage>,
+    pub host_port: u16,
+}
+
+impl MinioContainer {
+    /// Uses an IP address rather than localhost so that the AWS SDK addresses the buckets with
+    /// path-style URLs.
+    pub fn endpoint_url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.host_port)
+    }
+
+    /// An S3 client for this server, independent of the AWS environment variables.
+    pub fn s3_client(&self) -> aws_sdk_s3::Client {
+        let conf = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .endpoint_url(self.endpoint_url())
+            .region(Region::new(MINIO_REGION))
+            .credentials_provider(Credentials::new(
+                MINIO_ROOT_USER,
+                MINIO_ROOT_PASSWORD,
+                None,
+                None,
+                "minio",
+            ))
+            .force_path_style(true)
+            .build();
+        aws_sdk_s3::Client::from_conf(conf)
+    }
+
+    /// Points the AWS environment variables at this server, for the services under test that build
+    /// their S3 client from the environment.
+    pub fn set_aws_env(&self) {
+        std::env::set_var("AWS_ENDPOINT_URL", self.endpoint_url());
+        std::env::set_var("AWS_REGION", MINIO_REGION);
+        std::env::set_var("AWS_ACCESS_KEY_ID", MINIO_ROOT_USER);
+        std::env::set_var("AWS_SECRET_ACCESS_KEY", MINIO_ROOT_PASSWORD);
+    }
+
+    /// Creates the given buckets, ignoring the ones that already exist.
+    pub async fn create_buckets(&self, buckets: &[&str]) -> anyhow::Result<()> {
+        let client = self.s3_client();
+        for bucket in buckets {
+            if client.head_bucket().bucket(*bucket).send().await.is_ok() {
+                continue;
+            }
+            client.create_bucket().bucket(*bucket).send().await?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn start_minio() -> anyhow::Result<MinioContainer> {
+    let container = GenericImage::new("minio/minio", "RELEASE.2025-04-22T22-12-26Z")
+        .with_exposed_port(MINIO_PORT.into())
+        .with_wait_for(WaitFor::message_on_stdout("API:"))
+        .with_env_var("MINIO_ROOT_USER", MINIO_ROOT_USER)
+        .with_env_var("MINIO_ROOT_PASSWORD", MINIO_ROOT_PASSWORD)
+        .with_cmd(["server", "/data"])
+        .start()
+        .await?;
+    let host_port = container.get_host_port_ipv4(MINIO_PORT).await?;
+    info!(host_port, "MinIO container started");
+    Ok(MinioContainer {
+        container,
+        host_port,
+    })
+}
@@ -0,0 +1,100 @@
+use alloy::primitives::{Address, Bytes, U256};
+use alloy::providers::Provider;
+use alloy::sol;
+use test_harness::anvil::{
+    events_since, expect_event, mine, reorg, revert_to_snapshot, snapshot, AnvilFixture,
+    GatewayMocks,
+};
+
+sol! {
+    #[sol(rpc)]
+    interface InputVerificationMock {
+        event VerifyProofRequest(
+            uint256 indexed zkProofId,
+            uint256 indexed contractChainId,
+            address contractAddress,
+            address userAddress,
+            bytes ciphertextWithZKProof,
+            bytes extraData
+        );
+
+        function verifyProofRequest(
+            uint256 contractChainId,
+            address contractAddress,
+            address userAddress,
+            bytes calldata ciphertextWithZKProof,
+            bytes calldata extraData
+        ) external;
+    }
+}
+
+#[tokio::test]
+async fn mine_snapshot_and_revert() -> anyhow::Result<()> {
+    let anvil = AnvilFixture::start()?;
+    let provider = anvil.provider(0).await?;
+
+    let start = provider.get_block_number().await?;
+    mine(&provider, 3).await?;
+    assert_eq!(provider.get_block_number().await?, start + 3);
+
+    let snapshot_id = snapshot(&provider).await?;
+    mine(&provider, 2).await?;
+    assert_eq!(provider.get_block_number().await?, start + 5);
+    revert_to_snapshot(&provider, snapshot_id).await?;
+    assert_eq!(provider.get_block_number().await?, start + 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn gateway_mock_events_and_reorg() -> anyhow::Result<()> {
+    let anvil = AnvilFixture::start()?;
+    let provider = anvil.provider(0).await?;
+    let mocks = GatewayMocks::deploy(&provider).await?;
+    for address in [
+        mocks.ciphertext_commits,
+        mocks.decryption,
+        mocks.gateway_config,
+        mocks.input_verification,
+        mocks.kms_generation,
+        mocks.multichain_acl,
+    ] {
+        assert!(!provider.get_code_at(address).await?.is_empty());
+    }
+
+    let input_verification = InputVerificationMock::new(mocks.input_verification, provider.clone());
+    let from_block = provider.get_block_number().await?;
+    let receipt = input_verification
+        .verifyProofRequest(
+            U256::from(12345),
+            Address::ZERO,
+            Address::ZERO,
+            Bytes::from_static(b"proof"),
+            Bytes::new(),
+        )
+        .send()
+        .await?
+        .get_receipt()
+        .await?;
+    let request = expect_event::<InputVerificationMock::VerifyProofRequest>(&receipt)?;
+    assert_eq!(request.zkProofId, U256::from(1));
+    let events = events_since::<InputVerificationMock::VerifyProofRequest, _>(
+        &provider,
+        mocks.input_verification,
+        from_block,
+    )
+    .await?;
+    assert_eq!(events.len(), 1);
+
+    // The request is dropped with its block, the chain keeps its height
+    let head = provider.get_block_number().await?;
+    reorg(&provider, 1, vec![]).await?;
+    assert_eq!(provider.get_block_number().await?, head);
+    let events = events_since::<InputVerificationMock::VerifyProofRequest, _>(
+        &provider,
+        mocks.input_verification,
+        from_block,
+    )
+    .await?;
+    assert!(events.is_empty());
+    Ok(())
+}
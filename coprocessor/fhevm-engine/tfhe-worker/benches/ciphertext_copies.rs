@@ -0,0 +1,94 @@
+//! Measures the bytes allocated when a stored ciphertext is handed to the
+//! dataflow graph and fanned out to the transactions consuming it, comparing
+//! owned buffers (previous behaviour) with shared `Bytes` views.
+//!
+//! Allocated bytes are printed per scenario, timings are reported by criterion.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::Bytes;
+use criterion::{BenchmarkId, Criterion, Throughput};
+use fhevm_engine_common::ciphertext_format::{self, CiphertextFormat};
+use scheduler::dfg::types::DFGTxInput;
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size.saturating_sub(layout.size()), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Size of a large compressed ciphertext list.
+const CT_SIZE: usize = 4 * 1024 * 1024;
+/// Number of transactions consuming the same input.
+const CONSUMERS: [usize; 3] = [1, 8, 64];
+
+/// Loads a sealed ciphertext and fans it out with owned copies.
+fn fan_out_owned(blob: &[u8], consumers: usize) -> Vec<(i16, Vec<u8>)> {
+    let payload = ciphertext_format::into_payload(blob.to_vec()).expect("valid envelope");
+    (0..consumers).map(|_| (0, payload.clone())).collect()
+}
+
+/// Loads a sealed ciphertext and fans it out as shared views.
+fn fan_out_shared(blob: &[u8], consumers: usize) -> Vec<DFGTxInput> {
+    let (_, payload) =
+        ciphertext_format::open_bytes(Bytes::from(blob.to_vec())).expect("valid envelope");
+    (0..consumers)
+        .map(|_| DFGTxInput::Compressed((0, payload.clone())))
+        .collect()
+}
+
+fn allocated_by<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    black_box(f());
+    ALLOCATED.load(Ordering::Relaxed) - before
+}
+
+fn main() {
+    let blob = ciphertext_format::seal_as(vec![0xa5; CT_SIZE], CiphertextFormat::V1);
+
+    for consumers in CONSUMERS {
+        let owned = allocated_by(|| fan_out_owned(&blob, consumers));
+        let shared = allocated_by(|| fan_out_shared(&blob, consumers));
+        println!(
+            "consumers: {consumers}, owned: {owned} bytes, shared: {shared} bytes, saved: {} bytes",
+            owned.saturating_sub(shared)
+        );
+    }
+
+    let mut c = Criterion::default().sample_size(10).configure_from_args();
+    let mut group = c.benchmark_group("ciphertext_copies");
+    group.throughput(Throughput::Bytes(CT_SIZE as u64));
+    for consumers in CONSUMERS {
+        group.bench_with_input(
+            BenchmarkId::new("owned", consumers),
+            &consumers,
+            |b, &consumers| b.iter(|| fan_out_owned(&blob, consumers)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("shared", consumers),
+            &consumers,
+            |b, &consumers| b.iter(|| fan_out_shared(&blob, consumers)),
+        );
+    }
+    group.finish();
+    c.final_summary();
+}
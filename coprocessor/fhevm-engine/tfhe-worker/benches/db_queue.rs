@@ -0,0 +1,230 @@
+//! Measures the throughput of the computations queue in Postgres, without any
+//! FHE: rows are inserted like the host listener does, then claimed and
+//! completed by concurrent workers with the query of the tfhe worker. Also
+//! measures the latency between an insertion and the `work_available`
+//! notification waking the workers.
+//!
+//! Runs against `DB_QUEUE_BENCH_DATABASE_URL` if set, a migrated database in a
+//! container otherwise. The computations table is truncated, never point it to
+//! a database in use. Meant to evaluate schema and index changes, results are
+//! printed.
+//!
+//! Tunables: `DB_QUEUE_BENCH_ROWS` (default 20000), `DB_QUEUE_BENCH_WORKERS`
+//! (default "1,4,16"), `DB_QUEUE_BENCH_BATCH_SIZE` (default 100) and
+//! `DB_QUEUE_BENCH_WAKEUPS` (default 100).
+
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use sqlx::postgres::{PgListener, PgPoolOptions};
+use sqlx::{PgPool, QueryBuilder, Row};
+use test_harness::instance::{setup_test_db, DBInstance, ImportMode};
+
+const TENANT_ID: i32 = 1;
+const INSERT_BATCH_SIZE: usize = 100;
+
+/// Same selection as the tfhe worker, see `query_for_work`.
+const CLAIM_QUERY: &str = "
+WITH selected_computations AS (
+  SELECT DISTINCT c.transaction_id
+  FROM (
+    SELECT transaction_id
+    FROM computations
+    WHERE is_completed = FALSE
+      AND is_error = FALSE
+      AND is_allowed = TRUE
+    ORDER BY schedule_order
+    LIMIT $1
+  ) AS c
+)
+SELECT c.tenant_id, c.output_handle, c.transaction_id
+FROM computations c
+JOIN selected_computations sc ON c.transaction_id = sc.transaction_id
+FOR UPDATE SKIP LOCKED";
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    rand::rng().fill(bytes.as_mut_slice());
+    bytes
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+/// Inserts independent computations, each in its own host chain transaction.
+async fn insert_computations(pool: &PgPool, rows: usize) -> Result<Duration, sqlx::Error> {
+    let started = Instant::now();
+    for chunk_start in (0..rows).step_by(INSERT_BATCH_SIZE) {
+        let chunk_len = INSERT_BATCH_SIZE.min(rows - chunk_start);
+        let mut query = QueryBuilder::new(
+            "INSERT INTO computations (tenant_id, output_handle, dependencies, fhe_operation, \
+             is_scalar, dependence_chain_id, transaction_id, is_allowed) ",
+        );
+        query.push_values(0..chunk_len, |mut values, _| {
+            values
+                .push_bind(TENANT_ID)
+                .push_bind(random_bytes(32))
+                .push_bind(vec![random_bytes(32), random_bytes(32)])
+                .push_bind(0i16)
+                .push_bind(false)
+                .push_bind(random_bytes(32))
+                .push_bind(random_bytes(32))
+                .push_bind(true);
+        });
+        query.build().execute(pool).await?;
+    }
+    Ok(started.elapsed())
+}
+
+/// Claims and completes batches until the queue is empty, returns the rows
+/// completed and the duration of each claim.
+async fn run_worker(pool: PgPool, batch_size: i64) -> Result<(usize, Vec<Duration>), sqlx::Error> {
+    let mut completed = 0;
+    let mut claims = vec![];
+    loop {
+        let claim_started = Instant::now();
+        let mut trx = pool.begin().await?;
+        let rows = sqlx::query(CLAIM_QUERY)
+            .bind(batch_size)
+            .fetch_all(trx.as_mut())
+            .await?;
+        claims.push(claim_started.elapsed());
+        if rows.is_empty() {
+            trx.rollback().await?;
+            // Other workers may still hold rows that are going to be completed
+            let remaining: i64 =
+                sqlx::query_scalar("SELECT count(1) FROM computations WHERE is_completed = FALSE")
+                    .fetch_one(&pool)
+                    .await?;
+            if remaining == 0 {
+                return Ok((completed, claims));
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            continue;
+        }
+
+        let (handles, txn_ids): (Vec<Vec<u8>>, Vec<Vec<u8>>) = rows
+            .iter()
+            .map(|row| (row.get("output_handle"), row.get("transaction_id")))
+            .unzip();
+        sqlx::query(
+            "UPDATE computations
+             SET is_completed = TRUE, completed_at = CURRENT_TIMESTAMP
+             WHERE tenant_id = $1
+               AND (output_handle, transaction_id) IN (
+                 SELECT * FROM unnest($2::BYTEA[], $3::BYTEA[])
+               )",
+        )
+        .bind(TENANT_ID)
+        .bind(&handles)
+        .bind(&txn_ids)
+        .execute(trx.as_mut())
+        .await?;
+        trx.commit().await?;
+        completed += rows.len();
+    }
+}
+
+async fn bench_claims(
+    pool: &PgPool,
+    rows: usize,
+    workers: usize,
+    batch_size: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("TRUNCATE computations").execute(pool).await?;
+    let insert_time = insert_computations(pool, rows).await?;
+
+    let started = Instant::now();
+    let mut tasks = tokio::task::JoinSet::new();
+    for _ in 0..workers {
+        tasks.spawn(run_worker(pool.clone(), batch_size));
+    }
+    let mut completed = 0;
+    let mut claims = vec![];
+    while let Some(res) = tasks.join_next().await {
+        let (worker_completed, worker_claims) = res.expect("worker panicked")?;
+        completed += worker_completed;
+        claims.extend(worker_claims);
+    }
+    let elapsed = started.elapsed();
+    claims.sort();
+
+    println!(
+        "workers: {workers}, rows: {rows}, insert: {:.0} rows/s, claim+complete: {:.0} rows/s, \
+         claims: {}, claim p50: {:?}, claim p99: {:?}",
+        rows as f64 / insert_time.as_secs_f64(),
+        completed as f64 / elapsed.as_secs_f64(),
+        claims.len(),
+        percentile(&claims, 0.5),
+        percentile(&claims, 0.99),
+    );
+    Ok(())
+}
+
+/// Time from the start of an insertion to the `work_available` notification.
+async fn bench_wakeups(pool: &PgPool, wakeups: usize) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen("work_available").await?;
+    let mut latencies = Vec::with_capacity(wakeups);
+    for _ in 0..wakeups {
+        let started = Instant::now();
+        insert_computations(pool, 1).await?;
+        listener.recv().await?;
+        latencies.push(started.elapsed());
+    }
+    latencies.sort();
+    println!(
+        "wakeups: {wakeups}, p50: {:?}, p99: {:?}, max: {:?}",
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.99),
+        latencies.last().copied().unwrap_or_default(),
+    );
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let rows = env_or("DB_QUEUE_BENCH_ROWS", 20_000usize);
+    let batch_size = env_or("DB_QUEUE_BENCH_BATCH_SIZE", 100i64);
+    let wakeups = env_or("DB_QUEUE_BENCH_WAKEUPS", 100usize);
+    let workers: Vec<usize> = std::env::var("DB_QUEUE_BENCH_WORKERS")
+        .unwrap_or("1,4,16".to_string())
+        .split(',')
+        .map(|w| w.trim().parse().expect("worker count"))
+        .collect();
+
+    // Keeps the container alive until the end
+    let mut _db_instance: Option<DBInstance> = None;
+    let db_url = match std::env::var("DB_QUEUE_BENCH_DATABASE_URL") {
+        Ok(db_url) => db_url,
+        Err(_) => {
+            let instance = setup_test_db(ImportMode::None).await?;
+            let db_url = instance.db_url().to_owned();
+            _db_instance = Some(instance);
+            db_url
+        }
+    };
+    let max_workers = workers.iter().copied().max().unwrap_or(1);
+    let pool = PgPoolOptions::new()
+        .max_connections(max_workers as u32 + 2)
+        .connect(&db_url)
+        .await?;
+
+    for workers in workers {
+        bench_claims(&pool, rows, workers, batch_size).await?;
+    }
+    bench_wakeups(&pool, wakeups).await?;
+    sqlx::query("TRUNCATE computations").execute(&pool).await?;
+    Ok(())
+}
@@ -0,0 +1,224 @@
+//! Measures the building blocks of the worker in isolation, without database nor
+//! scheduler: FHE operations, ciphertext (de)serialization, compression, storage
+//! envelope and handle derivation.
+//!
+//! Keys are read from `../fhevm-keys`. Meant to be compared before and after a
+//! tfhe-rs upgrade, e.g. with `--save-baseline` and `--baseline`.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput};
+use fhevm_engine_common::ciphertext_format::{self, CiphertextFormat};
+use fhevm_engine_common::handle::TypedHandle;
+use fhevm_engine_common::keys::{FhevmKeys, SerializedFhevmKeys};
+use fhevm_engine_common::tfhe_ops::{
+    current_ciphertext_version, deserialize_fhe_ciphertext, perform_fhe_operation,
+};
+use fhevm_engine_common::types::{SupportedFheCiphertexts, SupportedFheOperations};
+use sha3::{Digest, Keccak256};
+use tfhe::prelude::*;
+use tfhe::ClientKey;
+
+const CHAIN_ID: u64 = 12345;
+const ACL_CONTRACT_ADDRESS: [u8; 20] = [0x33; 20];
+
+/// Representative types: the smallest, the usual token amount and the widest integer.
+fn encrypt_operands(
+    client_key: &ClientKey,
+) -> Vec<(
+    &'static str,
+    SupportedFheCiphertexts,
+    SupportedFheCiphertexts,
+)> {
+    vec![
+        (
+            "FheUint8",
+            SupportedFheCiphertexts::FheUint8(tfhe::FheUint8::encrypt(42u8, client_key)),
+            SupportedFheCiphertexts::FheUint8(tfhe::FheUint8::encrypt(7u8, client_key)),
+        ),
+        (
+            "FheUint64",
+            SupportedFheCiphertexts::FheUint64(tfhe::FheUint64::encrypt(42u64, client_key)),
+            SupportedFheCiphertexts::FheUint64(tfhe::FheUint64::encrypt(7u64, client_key)),
+        ),
+        (
+            "FheUint256",
+            SupportedFheCiphertexts::FheUint256(tfhe::FheUint256::encrypt(
+                tfhe::integer::U256::from(42u64),
+                client_key,
+            )),
+            SupportedFheCiphertexts::FheUint256(tfhe::FheUint256::encrypt(
+                tfhe::integer::U256::from(7u64),
+                client_key,
+            )),
+        ),
+    ]
+}
+
+fn bench_operations(
+    c: &mut Criterion,
+    operands: &[(&str, SupportedFheCiphertexts, SupportedFheCiphertexts)],
+    condition: &SupportedFheCiphertexts,
+) {
+    let mut group = c.benchmark_group("fhe_operations");
+    group.sample_size(10);
+    let operations = [
+        SupportedFheOperations::FheAdd,
+        SupportedFheOperations::FheSub,
+        SupportedFheOperations::FheMul,
+        SupportedFheOperations::FheBitAnd,
+        SupportedFheOperations::FheShl,
+        SupportedFheOperations::FheEq,
+        SupportedFheOperations::FheLt,
+        SupportedFheOperations::FheMin,
+    ];
+    for (type_name, lhs, rhs) in operands {
+        for op in operations {
+            let inputs = [lhs.clone(), rhs.clone()];
+            group.bench_with_input(
+                BenchmarkId::new(format!("{op:?}"), type_name),
+                &inputs,
+                |b, inputs| b.iter(|| perform_fhe_operation(op as i16, inputs, 0).unwrap()),
+            );
+        }
+
+        let inputs = [lhs.clone(), SupportedFheCiphertexts::Scalar(vec![7])];
+        group.bench_with_input(
+            BenchmarkId::new("FheAddScalar", type_name),
+            &inputs,
+            |b, inputs| {
+                b.iter(|| {
+                    perform_fhe_operation(SupportedFheOperations::FheAdd as i16, inputs, 0).unwrap()
+                })
+            },
+        );
+
+        let inputs = [condition.clone(), lhs.clone(), rhs.clone()];
+        group.bench_with_input(
+            BenchmarkId::new("FheIfThenElse", type_name),
+            &inputs,
+            |b, inputs| {
+                b.iter(|| {
+                    perform_fhe_operation(SupportedFheOperations::FheIfThenElse as i16, inputs, 0)
+                        .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_serialization(
+    c: &mut Criterion,
+    operands: &[(&str, SupportedFheCiphertexts, SupportedFheCiphertexts)],
+) {
+    let mut group = c.benchmark_group("serialization");
+    for (type_name, ct, _) in operands {
+        let (ct_type, bytes) = ct.serialize();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("serialize", type_name), ct, |b, ct| {
+            b.iter(|| ct.serialize())
+        });
+        group.bench_with_input(
+            BenchmarkId::new("deserialize", type_name),
+            &bytes,
+            |b, bytes| b.iter(|| deserialize_fhe_ciphertext(ct_type, bytes).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn bench_compression(
+    c: &mut Criterion,
+    operands: &[(&str, SupportedFheCiphertexts, SupportedFheCiphertexts)],
+) {
+    let mut group = c.benchmark_group("compression");
+    group.sample_size(10);
+    for (type_name, ct, _) in operands {
+        let (ct_type, compressed) = ct.compress();
+        group.throughput(Throughput::Bytes(compressed.len() as u64));
+        group.bench_with_input(BenchmarkId::new("compress", type_name), ct, |b, ct| {
+            b.iter(|| ct.compress())
+        });
+        group.bench_with_input(
+            BenchmarkId::new("decompress", type_name),
+            &compressed,
+            |b, compressed| {
+                b.iter(|| SupportedFheCiphertexts::decompress(ct_type, compressed, 0).unwrap())
+            },
+        );
+
+        let blob = ciphertext_format::seal_as(compressed.clone(), CiphertextFormat::V1);
+        group.bench_with_input(
+            BenchmarkId::new("seal", type_name),
+            &compressed,
+            |b, compressed| {
+                b.iter(|| ciphertext_format::seal_as(compressed.clone(), CiphertextFormat::V1))
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("open", type_name), &blob, |b, blob| {
+            b.iter(|| ciphertext_format::open(blob).unwrap().1.len())
+        });
+    }
+    group.finish();
+}
+
+/// Derivation of the handle of an input, as done for the verified input proofs.
+fn derive_input_handle(blob_hash: &[u8], ct_idx: u8, fhe_type: i16) -> TypedHandle {
+    let mut handle_hash = Keccak256::new();
+    handle_hash.update(blob_hash);
+    handle_hash.update([ct_idx]);
+    handle_hash.update(ACL_CONTRACT_ADDRESS);
+    handle_hash.update(alloy::primitives::U256::from(CHAIN_ID).to_be_bytes::<32>());
+    TypedHandle::from_hash(
+        handle_hash.finalize().into(),
+        ct_idx,
+        CHAIN_ID,
+        fhe_type,
+        current_ciphertext_version() as u8,
+    )
+}
+
+fn bench_handles(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handles");
+    let blob_hash = Keccak256::digest([0xa5; 1024]);
+    group.bench_function("derive_input_handle", |b| {
+        b.iter(|| derive_input_handle(black_box(&blob_hash), 3, 5))
+    });
+    let handle = derive_input_handle(&blob_hash, 3, 5);
+    let hex = handle.to_string();
+    group.bench_function("parse_handle", |b| {
+        b.iter(|| black_box(&hex).parse::<TypedHandle>().unwrap())
+    });
+    group.bench_function("decode_handle", |b| {
+        b.iter(|| {
+            let handle = black_box(handle);
+            (
+                handle.index(),
+                handle.chain_id(),
+                handle.fhe_type(),
+                handle.version(),
+            )
+        })
+    });
+    group.finish();
+}
+
+fn main() {
+    let keys: FhevmKeys = SerializedFhevmKeys::load_from_disk("../fhevm-keys").into();
+    let client_key = keys
+        .client_key
+        .clone()
+        .expect("client key in ../fhevm-keys");
+    keys.set_gpu_server_key_for_current_thread();
+
+    let operands = encrypt_operands(&client_key);
+    let condition = SupportedFheCiphertexts::FheBool(tfhe::FheBool::encrypt(true, &client_key));
+
+    let mut c = Criterion::default().configure_from_args();
+    bench_operations(&mut c, &operands, &condition);
+    bench_serialization(&mut c, &operands);
+    bench_compression(&mut c, &operands);
+    bench_handles(&mut c);
+    c.final_summary();
+}
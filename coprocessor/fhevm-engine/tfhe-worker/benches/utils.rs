@@ -86,25 +86,50 @@ async fn start_coprocessor(rx: Receiver<bool>, app_port: u16, db_url: &str) {
     let args: Args = Args {
         run_bg_worker: true,
         worker_polling_interval_ms: 1000,
+        notification_debounce_ms: 0,
         run_server: true,
         generate_fhe_keys: false,
         server_maximum_ciphertexts_to_schedule: 20000,
         server_maximum_ciphertexts_to_get: 20000,
         work_items_batch_size: ecfg.batch_size,
+        fair_scheduling: false,
+        caller_weights: vec![],
+        incremental_results: false,
+        result_write_batch_size: 50,
+        result_write_max_latency_ms: 20,
         dependence_chains_per_batch: 2000,
         tenant_key_cache_size: 4,
         coprocessor_fhe_threads: 64,
+        compression_threads: 4,
+        pbs_threads: 0,
+        numa_pinning: false,
         maximum_handles_per_input: 255,
         tokio_threads: 32,
         pg_pool_max_connections: 2,
         server_addr: format!("127.0.0.1:{app_port}"),
         metrics_addr: "".to_string(),
         database_url: Some(db_url.to_string()),
+        database_schema: None,
         maximum_compact_inputs_upload: 10,
         coprocessor_private_key: "./coprocessor.key".to_string(),
         service_name: "coprocessor".to_string(),
         log_level: Level::INFO,
         health_check_port: 8080,
+        ciphertext_format: Default::default(),
+        buffer_pool_max_bytes: fhevm_engine_common::buffer_pool::DEFAULT_MAX_RETAINED_BYTES,
+        status_api_port: None,
+        status_api_auth_token: None,
+        status_api_push_poll_interval_ms: 2000,
+        column_encryption_key: None,
+        webhook_url: None,
+        webhook_secret: None,
+        webhook_events: vec![],
+        webhook_batch_size: 100,
+        webhook_max_latency_ms: 1000,
+        webhook_max_attempts: 5,
+        dag_export_capacity: 0,
+        diagnostics_dir: None,
+        admin_token: None,
     };
 
     std::thread::spawn(move || {
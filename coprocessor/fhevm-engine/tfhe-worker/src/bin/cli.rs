@@ -1,8 +1,19 @@
+use std::collections::BTreeSet;
 use std::str::FromStr;
 
 use clap::Parser;
+use fhevm_engine_common::ciphertext_format::{self, CiphertextFormat};
+use fhevm_engine_common::handle::TypedHandle;
+use fhevm_engine_common::param_set::ParamSet;
+use fhevm_engine_common::types::{
+    fhe_type_name, AllowEvents, SupportedFheCiphertexts, SupportedFheOperations,
+};
+use fhevm_engine_common::utils::safe_deserialize_key;
 use rand::Rng;
+use scheduler::dfg::export::NodeStatus;
+use scheduler::dfg::{types::DFGTaskInput, DFGOp, TxNode};
 use sqlx::types::Uuid;
+use sqlx::Row;
 use tfhe_worker::server::{
     common::FheOperation,
     tfhe_worker::{
@@ -38,6 +49,35 @@ pub enum Args {
         /// Chain id
         #[arg(long)]
         chain_id: u32,
+        /// Parameter set the keys were generated with, if not the default one
+        #[arg(long)]
+        param_set: Option<String>,
+    },
+    /// Rewrites stored ciphertexts into the given storage format
+    MigrateCiphertextFormat {
+        /// Target format (legacy or v1)
+        #[arg(long)]
+        target: CiphertextFormat,
+        /// Rows rewritten per transaction
+        #[arg(long, default_value_t = 100)]
+        batch_size: i64,
+    },
+    /// Decodes a handle, or the handle of a ciphertext digest, and reports its database rows
+    InspectHandle {
+        /// Hex handle, or hex digest of a ct64 or ct128, with or without 0x prefix
+        value: String,
+    },
+    /// Exports the dependence graph of a transaction from the computations table, with the
+    /// status of each operation
+    DumpDag {
+        /// Hex transaction id, with or without 0x prefix
+        transaction_id: String,
+        /// Tenant of the transaction, required if several tenants have one with this id
+        #[arg(long)]
+        tenant_id: Option<i32>,
+        /// Output format (json or dot)
+        #[arg(long, default_value = "json")]
+        format: String,
     },
     /// Coprocessor smoke test
     SmokeTest {
@@ -61,6 +101,7 @@ fn main() {
             acl_contract_address,
             verifying_contract_address,
             chain_id,
+            param_set,
         } => {
             insert_tenant(
                 pks_file,
@@ -70,8 +111,22 @@ fn main() {
                 acl_contract_address,
                 verifying_contract_address,
                 chain_id,
+                param_set,
             );
         }
+        Args::MigrateCiphertextFormat { target, batch_size } => {
+            migrate_ciphertext_format(target, batch_size);
+        }
+        Args::InspectHandle { value } => {
+            inspect_handle(value);
+        }
+        Args::DumpDag {
+            transaction_id,
+            tenant_id,
+            format,
+        } => {
+            dump_dag(transaction_id, tenant_id, format);
+        }
         Args::SmokeTest {
             tenant_api_key,
             coprocessor_url,
@@ -81,6 +136,354 @@ fn main() {
     }
 }
 
+fn inspect_handle(value: String) {
+    let db_url =
+        std::env::var("DATABASE_URL").expect("DATABASE_URL environment variable is undefined");
+    let value = hex::decode(value.trim().trim_start_matches("0x")).expect("Can't parse hex value");
+    assert_eq!(value.len(), 32, "A handle or digest is 32 bytes");
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async move {
+            let pool = sqlx::postgres::PgPoolOptions::new()
+                .max_connections(1)
+                .connect(&db_url)
+                .await
+                .expect("Can't connect to postgres instance");
+
+            // A digest is resolved to its handle, anything else is taken as a handle
+            let digest_of: Option<Vec<u8>> = sqlx::query_scalar(
+                "SELECT handle FROM ciphertext_digest
+                 WHERE (ciphertext = $1 OR ciphertext128 = $1) AND handle <> $1
+                 LIMIT 1",
+            )
+            .bind(&value)
+            .fetch_optional(&pool)
+            .await
+            .expect("Can't query ciphertext digests");
+            let handle = match digest_of {
+                Some(handle) => {
+                    println!("Digest 0x{}", hex::encode(&value));
+                    TypedHandle::try_from(handle).expect("Invalid handle")
+                }
+                None => TypedHandle::try_from(value).expect("Invalid handle"),
+            };
+
+            print_handle_metadata(&handle);
+            let chain_id = i64::try_from(handle.chain_id()).unwrap_or(-1);
+            let tenant_ids: Vec<i32> =
+                sqlx::query_scalar("SELECT tenant_id FROM tenants WHERE chain_id = $1")
+                    .bind(chain_id)
+                    .fetch_all(&pool)
+                    .await
+                    .expect("Can't query tenants");
+            println!("  tenants:    {tenant_ids:?}");
+
+            let handle = handle.to_vec();
+            print_computations(&pool, &handle).await;
+            print_ciphertexts(&pool, &handle).await;
+            print_storage(&pool, &handle).await;
+            print_acl(&pool, &handle).await;
+        });
+}
+
+fn dump_dag(transaction_id: String, tenant_id: Option<i32>, format: String) {
+    let db_url =
+        std::env::var("DATABASE_URL").expect("DATABASE_URL environment variable is undefined");
+    let transaction_id = hex::decode(transaction_id.trim().trim_start_matches("0x"))
+        .expect("Can't parse hex transaction id");
+    assert!(
+        format == "json" || format == "dot",
+        "Unknown format {format:?}, expected json or dot"
+    );
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async move {
+            let pool = sqlx::postgres::PgPoolOptions::new()
+                .max_connections(1)
+                .connect(&db_url)
+                .await
+                .expect("Can't connect to postgres instance");
+
+            let rows = sqlx::query(
+                "SELECT tenant_id, output_handle, dependencies, fhe_operation, is_scalar,
+                    is_allowed, is_completed, is_error, error_message,
+                    completed_at::TEXT AS completed_at
+                 FROM computations
+                 WHERE transaction_id = $1 AND ($2::INT IS NULL OR tenant_id = $2)
+                 ORDER BY created_at, output_handle",
+            )
+            .bind(&transaction_id)
+            .bind(tenant_id)
+            .fetch_all(&pool)
+            .await
+            .expect("Can't query computations");
+            assert!(!rows.is_empty(), "No computation in this transaction");
+            let tenants: BTreeSet<i32> = rows.iter().map(|row| row.get("tenant_id")).collect();
+            assert_eq!(
+                tenants.len(),
+                1,
+                "Transaction found for tenants {tenants:?}, select one with --tenant-id"
+            );
+
+            let mut ops = Vec::with_capacity(rows.len());
+            for row in rows.iter() {
+                let fhe_op: SupportedFheOperations = row
+                    .get::<i16, _>("fhe_operation")
+                    .try_into()
+                    .expect("Invalid FHE operation");
+                let is_scalar: bool = row.get("is_scalar");
+                let inputs = row
+                    .get::<Vec<Vec<u8>>, _>("dependencies")
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, dependency)| {
+                        if is_scalar && idx == 1 || fhe_op.does_have_more_than_one_scalar() {
+                            DFGTaskInput::Value(SupportedFheCiphertexts::Scalar(dependency))
+                        } else {
+                            DFGTaskInput::Dependence(dependency)
+                        }
+                    })
+                    .collect();
+                ops.push(DFGOp {
+                    output_handle: row.get("output_handle"),
+                    fhe_op,
+                    inputs,
+                    is_allowed: row.get("is_allowed"),
+                });
+            }
+            let mut tx = TxNode::default();
+            tx.build(ops, &transaction_id)
+                .expect("Can't build the dependence graph");
+
+            let mut export = tx.export();
+            for row in rows.iter() {
+                let handle: Vec<u8> = row.get("output_handle");
+                let Some(node) = export.node_mut(&handle) else {
+                    continue;
+                };
+                node.completed_at = row.get("completed_at");
+                if row.get::<bool, _>("is_error") {
+                    node.status = NodeStatus::Failed;
+                    node.error = row.get("error_message");
+                } else if row.get::<bool, _>("is_completed") {
+                    node.status = NodeStatus::Completed;
+                    node.output_type = node
+                        .fhe_type
+                        .map(|fhe_type| fhe_type_name(fhe_type).unwrap_or("unknown").to_owned());
+                }
+            }
+
+            if format == "dot" {
+                println!("{}", export.to_dot());
+            } else {
+                println!(
+                    "{}",
+                    export
+                        .to_json()
+                        .expect("Can't serialize the dependence graph")
+                );
+            }
+        });
+}
+
+fn print_handle_metadata(handle: &TypedHandle) {
+    println!("Handle {handle}");
+    if handle.is_computed() {
+        println!("  origin:     computed");
+    } else {
+        println!("  origin:     input #{}", handle.index());
+    }
+    println!("  chain id:   {}", handle.chain_id());
+    println!(
+        "  type:       {} ({})",
+        fhe_type_name(handle.fhe_type()).unwrap_or("unknown"),
+        handle.fhe_type()
+    );
+    println!("  version:    {}", handle.version());
+}
+
+async fn print_computations(pool: &sqlx::PgPool, handle: &[u8]) {
+    let rows = sqlx::query(
+        "SELECT tenant_id, fhe_operation, is_completed, is_error, error_message, is_allowed,
+            created_at::TEXT AS created_at, completed_at::TEXT AS completed_at, transaction_id
+         FROM computations WHERE output_handle = $1",
+    )
+    .bind(handle)
+    .fetch_all(pool)
+    .await
+    .expect("Can't query computations");
+    println!("Computations: {}", rows.len());
+    for row in rows {
+        let operation = FheOperation::try_from(row.get::<i16, _>("fhe_operation") as i32)
+            .map(|op| op.as_str_name())
+            .unwrap_or("unknown");
+        let status = if row.get::<bool, _>("is_error") {
+            "error"
+        } else if row.get::<bool, _>("is_completed") {
+            "completed"
+        } else if !row.get::<bool, _>("is_allowed") {
+            "waiting to be allowed"
+        } else {
+            "pending"
+        };
+        println!(
+            "  tenant {}: {operation}, {status}, transaction 0x{}",
+            row.get::<i32, _>("tenant_id"),
+            hex::encode(row.get::<Vec<u8>, _>("transaction_id")),
+        );
+        println!(
+            "    created at {}, completed at {}",
+            row.get::<Option<String>, _>("created_at")
+                .unwrap_or_default(),
+            row.get::<Option<String>, _>("completed_at")
+                .unwrap_or_default(),
+        );
+        if let Some(error) = row.get::<Option<String>, _>("error_message") {
+            println!("    error: {error}");
+        }
+    }
+}
+
+async fn print_ciphertexts(pool: &sqlx::PgPool, handle: &[u8]) {
+    let rows = sqlx::query(
+        "SELECT tenant_id, ciphertext_version, ciphertext_type, key_id,
+            octet_length(ciphertext) AS ct64_len, octet_length(ciphertext128) AS ct128_len,
+            input_blob_hash, input_blob_index
+         FROM ciphertexts WHERE handle = $1",
+    )
+    .bind(handle)
+    .fetch_all(pool)
+    .await
+    .expect("Can't query ciphertexts");
+    println!("Ciphertexts: {}", rows.len());
+    for row in rows {
+        let key_id = row.get::<Option<Vec<u8>>, _>("key_id");
+        println!(
+            "  tenant {}: version {}, type {}, key {}",
+            row.get::<i32, _>("tenant_id"),
+            row.get::<i16, _>("ciphertext_version"),
+            fhe_type_name(row.get("ciphertext_type")).unwrap_or("unknown"),
+            key_id.map_or("none".to_string(), |k| format!("0x{}", hex::encode(k))),
+        );
+        println!(
+            "    ct64 {} bytes, ct128 {} bytes in the database",
+            row.get::<Option<i32>, _>("ct64_len").unwrap_or(0),
+            row.get::<Option<i32>, _>("ct128_len").unwrap_or(0),
+        );
+        if let Some(blob_hash) = row.get::<Option<Vec<u8>>, _>("input_blob_hash") {
+            println!(
+                "    input blob 0x{} #{}",
+                hex::encode(blob_hash),
+                row.get::<i32, _>("input_blob_index")
+            );
+        }
+    }
+}
+
+async fn print_storage(pool: &sqlx::PgPool, handle: &[u8]) {
+    let rows = sqlx::query(
+        "SELECT d.tenant_id, d.ciphertext, d.ciphertext128, d.ciphertext128_format,
+            d.txn_is_sent, d.txn_hash, p.is_completed AS sns_completed, p.priority
+         FROM ciphertext_digest d
+         LEFT JOIN pbs_computations p ON p.tenant_id = d.tenant_id AND p.handle = d.handle
+         WHERE d.handle = $1",
+    )
+    .bind(handle)
+    .fetch_all(pool)
+    .await
+    .expect("Can't query ciphertext digests");
+    println!("Storage: {}", rows.len());
+    for row in rows {
+        // Uploaded ciphertexts are stored in S3 under their digest
+        let s3_key =
+            |digest: Option<Vec<u8>>| digest.map_or("not uploaded".to_string(), hex::encode);
+        let sns = match row.get::<Option<bool>, _>("sns_completed") {
+            Some(true) => "completed".to_string(),
+            Some(false) => format!(
+                "pending with priority {}",
+                row.get::<Option<i16>, _>("priority").unwrap_or(0)
+            ),
+            None => "not queued".to_string(),
+        };
+        println!(
+            "  tenant {}: sns {sns}, sent to the gateway {}",
+            row.get::<i32, _>("tenant_id"),
+            row.get::<bool, _>("txn_is_sent"),
+        );
+        println!("    ct64 S3 key {}", s3_key(row.get("ciphertext")));
+        println!(
+            "    ct128 S3 key {} (format {})",
+            s3_key(row.get("ciphertext128")),
+            row.get::<i16, _>("ciphertext128_format"),
+        );
+        if let Some(txn_hash) = row.get::<Option<Vec<u8>>, _>("txn_hash") {
+            println!("    transaction 0x{}", hex::encode(txn_hash));
+        }
+    }
+}
+
+async fn print_acl(pool: &sqlx::PgPool, handle: &[u8]) {
+    let rows = sqlx::query(
+        "SELECT tenant_id, account_address, event_type, txn_is_sent, txn_hash
+         FROM allowed_handles WHERE handle = $1",
+    )
+    .bind(handle)
+    .fetch_all(pool)
+    .await
+    .expect("Can't query allowed handles");
+    println!("ACL: {}", rows.len());
+    for row in rows {
+        let allowed = match AllowEvents::try_from(row.get::<i16, _>("event_type")) {
+            Ok(AllowEvents::AllowedForDecryption) => "public decryption".to_string(),
+            _ => format!("account {}", row.get::<String, _>("account_address")),
+        };
+        println!(
+            "  tenant {}: allowed for {allowed}, sent to the gateway {}",
+            row.get::<i32, _>("tenant_id"),
+            row.get::<bool, _>("txn_is_sent"),
+        );
+        if let Some(txn_hash) = row.get::<Option<Vec<u8>>, _>("txn_hash") {
+            println!("    transaction 0x{}", hex::encode(txn_hash));
+        }
+    }
+}
+
+fn migrate_ciphertext_format(target: CiphertextFormat, batch_size: i64) {
+    let db_url =
+        std::env::var("DATABASE_URL").expect("DATABASE_URL environment variable is undefined");
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async move {
+            let pool = sqlx::postgres::PgPoolOptions::new()
+                .max_connections(1)
+                .connect(&db_url)
+                .await
+                .expect("Can't connect to postgres instance");
+
+            let mut total = 0;
+            loop {
+                let migrated = ciphertext_format::migrate_ciphertexts(&pool, target, batch_size)
+                    .await
+                    .expect("Can't migrate ciphertexts");
+                if migrated == 0 {
+                    break;
+                }
+                total += migrated;
+                println!("Migrated {total} ciphertexts to {target}");
+            }
+            println!("All ciphertexts are stored as {target}");
+        });
+}
+
 fn smoke_test(tenant_api_key: String, coprocessor_url: String) {
     tokio::runtime::Builder::new_current_thread()
         .enable_all()
@@ -199,6 +602,7 @@ fn smoke_test(tenant_api_key: String, coprocessor_url: String) {
         });
 }
 
+#[allow(clippy::too_many_arguments)]
 fn insert_tenant(
     pks_file: String,
     sks_file: String,
@@ -207,6 +611,7 @@ fn insert_tenant(
     acl_contract_address: String,
     verifying_contract_address: String,
     chain_id: u32,
+    param_set: Option<String>,
 ) {
     let db_url =
         std::env::var("DATABASE_URL").expect("DATABASE_URL environment variable is undefined");
@@ -219,6 +624,11 @@ fn insert_tenant(
     let _ = alloy::primitives::Address::from_str(&verifying_contract_address)
         .expect("Can't parse input verifier adddress");
     let tenant_api_key = Uuid::from_str(&tenant_api_key).expect("Can't parse tenant api key");
+    let pks: tfhe::CompactPublicKey =
+        safe_deserialize_key(&pks_file).expect("Can't deserialize pks file");
+    ParamSet::resolve(param_set.as_deref())
+        .and_then(|resolved| resolved.check_public_key(&pks))
+        .expect("Keys don't match the parameter set");
 
     tokio::runtime::Builder::new_current_thread()
         .enable_all()
@@ -240,7 +650,8 @@ fn insert_tenant(
                         verifying_contract_address,
                         pks_key,
                         sks_key,
-                        public_params
+                        public_params,
+                        param_set
                     )
                     VALUES (
                         $1,
@@ -249,7 +660,8 @@ fn insert_tenant(
                         $4,
                         $5,
                         $6,
-                        $7
+                        $7,
+                        $8
                     )
                 ",
                 tenant_api_key,
@@ -258,7 +670,8 @@ fn insert_tenant(
                 &verifying_contract_address,
                 &pks_file,
                 &sks_file,
-                &public_params_file
+                &public_params_file,
+                param_set
             )
             .execute(&pool)
             .await
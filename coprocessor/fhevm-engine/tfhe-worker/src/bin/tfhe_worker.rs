@@ -1,3 +1,14 @@
+#[cfg(any(feature = "jemalloc", feature = "mimalloc"))]
+use fhevm_engine_common::allocator;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: allocator::Jemalloc = allocator::Jemalloc;
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+#[global_allocator]
+static GLOBAL: allocator::MiMalloc = allocator::MiMalloc;
+
 fn main() {
     let args = tfhe_worker::daemon_cli::parse_args();
     if args.generate_fhe_keys {
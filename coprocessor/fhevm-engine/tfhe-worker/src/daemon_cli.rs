@@ -1,4 +1,9 @@
 use clap::Parser;
+use fhevm_engine_common::buffer_pool;
+use fhevm_engine_common::ciphertext_format::CiphertextFormat;
+use fhevm_engine_common::db_schema;
+use fhevm_engine_common::secret::SecretString;
+use fhevm_engine_common::webhook::{Url, WebhookEventType};
 use tracing::Level;
 
 #[derive(Parser, Debug, Clone)]
@@ -16,6 +21,10 @@ pub struct Args {
     #[arg(long, default_value_t = 1000)]
     pub worker_polling_interval_ms: u64,
 
+    /// Delay during which the notifications following a first one wake the worker only once
+    #[arg(long, default_value_t = 0)]
+    pub notification_debounce_ms: u64,
+
     /// Generate fhe keys and exit
     #[arg(long)]
     pub generate_fhe_keys: bool,
@@ -32,6 +41,30 @@ pub struct Args {
     #[arg(long, default_value_t = 100)]
     pub work_items_batch_size: i32,
 
+    /// Claim the computations in weighted fair order across their caller
+    /// contracts, instead of the global schedule order
+    #[arg(long)]
+    pub fair_scheduling: bool,
+
+    /// Fair scheduling weight of a caller contract, as <address>=<weight>.
+    /// Callers default to a weight of 1
+    #[arg(long, value_parser = parse_caller_weight, value_delimiter = ',')]
+    pub caller_weights: Vec<CallerWeight>,
+
+    /// Persist the results as soon as they are computed, in batches outside of
+    /// the work transaction, instead of at the end of the work batch
+    #[arg(long)]
+    pub incremental_results: bool,
+
+    /// Number of computed results written together with incremental results
+    #[arg(long, default_value_t = 50)]
+    pub result_write_batch_size: usize,
+
+    /// Maximum time a computed result waits for others before being written,
+    /// with incremental results
+    #[arg(long, default_value_t = 20)]
+    pub result_write_max_latency_ms: u64,
+
     /// Number of dependence chains to fetch per worker
     #[arg(long, default_value_t = 20)]
     pub dependence_chains_per_batch: i32,
@@ -52,6 +85,20 @@ pub struct Args {
     #[arg(long, default_value_t = 32)]
     pub coprocessor_fhe_threads: usize,
 
+    /// Threads decompressing, re-randomising and compressing ciphertexts,
+    /// separate from the PBS threads. 0 runs them on the PBS threads
+    #[arg(long, default_value_t = 4)]
+    pub compression_threads: usize,
+
+    /// Threads running the FHE operations, 0 uses one thread per CPU
+    #[arg(long, default_value_t = 0)]
+    pub pbs_threads: usize,
+
+    /// Pin the FHE computations to NUMA nodes, with thread pools per node.
+    /// Ignored on single node machines
+    #[arg(long)]
+    pub numa_pinning: bool,
+
     /// Tokio Async IO threads
     #[arg(long, default_value_t = 4)]
     pub tokio_threads: usize,
@@ -72,6 +119,10 @@ pub struct Args {
     #[arg(long)]
     pub database_url: Option<String>,
 
+    /// Database schema of the host chain, in the schema-per-chain layout
+    #[arg(long, value_parser = db_schema::parse_schema_name)]
+    pub database_schema: Option<String>,
+
     /// Coprocessor private key file path.
     /// Private key is in plain text 0x1234.. format.
     #[arg(long, default_value = "./coprocessor.key")]
@@ -90,6 +141,98 @@ pub struct Args {
 
     #[arg(long, default_value_t = 8080)]
     pub health_check_port: u16,
+
+    /// Storage format of newly written ciphertexts (legacy or v1).
+    /// All formats are always accepted when reading
+    #[arg(long, default_value_t = CiphertextFormat::Legacy)]
+    pub ciphertext_format: CiphertextFormat,
+
+    /// Maximum total size of the serialization buffers kept for reuse, 0 disables the pool
+    #[arg(long, default_value_t = buffer_pool::DEFAULT_MAX_RETAINED_BYTES)]
+    pub buffer_pool_max_bytes: usize,
+
+    /// Port of the read-only request status API, disabled if unspecified
+    #[arg(long)]
+    pub status_api_port: Option<u16>,
+
+    /// Bearer token of the operator on the status API, seeing all the tenants. The tenants
+    /// authenticate with their API key.
+    /// If unspecified STATUS_API_AUTH_TOKEN environment variable is used
+    #[arg(long)]
+    pub status_api_auth_token: Option<SecretString>,
+
+    /// Refresh period of the statuses pushed on the status API WebSocket, for the transitions
+    /// that are not notified by the database
+    #[arg(long, default_value_t = 2000)]
+    pub status_api_push_poll_interval_ms: u64,
+
+    /// Column key of the delegators and delegates reported by the status API, file:<path> or
+    /// env:<variable> holding 32 hex-encoded bytes
+    #[arg(long)]
+    pub column_encryption_key: Option<String>,
+
+    /// Endpoint the completed and failed computations are POSTed to, disabled if unspecified
+    #[arg(long)]
+    pub webhook_url: Option<Url>,
+
+    /// HMAC key signing the webhook batches.
+    /// If unspecified WEBHOOK_SECRET environment variable is used, the batches are unsigned if
+    /// both are unset
+    #[arg(long)]
+    pub webhook_secret: Option<SecretString>,
+
+    /// Event types exported to the webhook, comma separated, all if unspecified
+    #[arg(long, value_delimiter = ',')]
+    pub webhook_events: Vec<WebhookEventType>,
+
+    /// Maximum number of events of a webhook POST
+    #[arg(long, default_value_t = 100)]
+    pub webhook_batch_size: usize,
+
+    /// Maximum time an event waits for others before being POSTed
+    #[arg(long, default_value_t = 1000)]
+    pub webhook_max_latency_ms: u64,
+
+    /// Attempts of a webhook POST before its events are dropped
+    #[arg(long, default_value_t = 5)]
+    pub webhook_max_attempts: u32,
+
+    /// Number of recent transactions whose dependence graph is kept and served on
+    /// /debug/dag/{transaction_id} by the metrics server, disabled if 0
+    #[arg(long, default_value_t = 0)]
+    pub dag_export_capacity: usize,
+
+    /// Directory the diagnostics snapshot is written to on panic, only logged if unspecified
+    #[arg(long)]
+    pub diagnostics_dir: Option<std::path::PathBuf>,
+
+    /// Bearer token required by the admin endpoints of the health check server, e.g.
+    /// /admin/heap_profile. If unspecified ADMIN_TOKEN environment variable is used, the admin
+    /// endpoints are disabled if both are unset
+    #[arg(long)]
+    pub admin_token: Option<SecretString>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallerWeight {
+    pub caller: Vec<u8>,
+    pub weight: f64,
+}
+
+pub fn parse_caller_weight(arg: &str) -> Result<CallerWeight, String> {
+    let (caller, weight) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("invalid caller weight {arg:?}, expected <address>=<weight>"))?;
+    let caller = hex::decode(caller.trim_start_matches("0x"))
+        .ok()
+        .filter(|caller| caller.len() == 20)
+        .ok_or_else(|| format!("invalid caller address {caller:?}"))?;
+    let weight: f64 = weight
+        .parse()
+        .ok()
+        .filter(|weight: &f64| weight.is_finite() && *weight > 0.0)
+        .ok_or_else(|| format!("invalid caller weight {weight:?}, expected a positive number"))?;
+    Ok(CallerWeight { caller, weight })
 }
 
 pub fn parse_args() -> Args {
@@ -0,0 +1,51 @@
+//! Dependence graphs of the last executed transactions, to debug their results.
+//!
+//! The scheduler exports the graph of every transaction it executes or skips,
+//! with the status, result type and timings of each operation. The last
+//! transactions are kept in memory, up to [`set_capacity`], and served by the
+//! metrics server on `/debug/dag/{transaction_id}`, as JSON or with
+//! `?format=dot` as a Graphviz digraph. Graphs are not kept when the capacity
+//! is 0, the default. Older transactions are exported from the database with
+//! the `dump-dag` command of the CLI.
+
+use std::num::NonZeroUsize;
+use std::sync::{LazyLock, Mutex};
+
+use lru::LruCache;
+use scheduler::dfg::export::GraphExport;
+use tracing::info;
+
+static GRAPHS: LazyLock<Mutex<Option<LruCache<String, GraphExport>>>> =
+    LazyLock::new(|| Mutex::new(None));
+
+/// Sets the number of transactions whose graph is kept, disabled if 0.
+pub fn set_capacity(capacity: usize) {
+    info!(capacity, "Setting dependence graph export capacity");
+    *GRAPHS.lock().unwrap() = NonZeroUsize::new(capacity).map(LruCache::new);
+}
+
+pub fn is_enabled() -> bool {
+    GRAPHS.lock().unwrap().is_some()
+}
+
+pub fn record(export: GraphExport) {
+    if let Some(graphs) = GRAPHS.lock().unwrap().as_mut() {
+        graphs.put(export.transaction_id.clone(), export);
+    }
+}
+
+/// Graph of the transaction, `0x` prefixed or not.
+pub fn get(transaction_id: &str) -> Option<GraphExport> {
+    let transaction_id = format!(
+        "0x{}",
+        transaction_id
+            .strip_prefix("0x")
+            .unwrap_or(transaction_id)
+            .to_lowercase()
+    );
+    GRAPHS
+        .lock()
+        .unwrap()
+        .as_mut()
+        .and_then(|graphs| graphs.get(&transaction_id).cloned())
+}
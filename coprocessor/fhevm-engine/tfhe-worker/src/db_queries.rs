@@ -3,6 +3,7 @@ use std::sync::Arc;
 
 use crate::server::GrpcTracer;
 use crate::types::{CoprocessorError, TfheTenantKeys};
+use fhevm_engine_common::param_set::ParamSet;
 use fhevm_engine_common::utils::safe_deserialize_key;
 use opentelemetry::trace::Span;
 use opentelemetry::KeyValue;
@@ -62,6 +63,7 @@ pub struct FetchTenantKeyResult {
     pub chain_id: i64,
     pub verifying_contract_address: String,
     pub acl_contract_address: String,
+    pub param_set: &'static ParamSet,
     pub server_key: tfhe::ServerKey,
     #[cfg(feature = "gpu")]
     pub gpu_server_key: Vec<tfhe::CudaServerKey>,
@@ -86,6 +88,7 @@ where
                     chain_id: key.chain_id,
                     verifying_contract_address: key.verifying_contract_address.clone(),
                     acl_contract_address: key.acl_contract_address.clone(),
+                    param_set: key.param_set,
                     server_key: key.sks.clone(),
                     #[cfg(feature = "gpu")]
                     gpu_server_key: key.gpu_sks.clone(),
@@ -108,7 +111,7 @@ where
     let mut res = Vec::with_capacity(tenants_to_query.len());
     let keys = query!(
         "
-            SELECT tenant_id, chain_id, acl_contract_address, verifying_contract_address, pks_key, sks_key, public_params
+            SELECT tenant_id, chain_id, acl_contract_address, verifying_contract_address, key_id, param_set, pks_key, sks_key, public_params
             FROM tenants
             WHERE tenant_id = ANY($1::INT[])
         ",
@@ -117,12 +120,14 @@ where
     .fetch_all(conn)
     .await?;
     for key in keys {
+        let param_set = ParamSet::resolve(key.param_set.as_deref())?;
         #[cfg(not(feature = "gpu"))]
         {
             let sks: tfhe::ServerKey = safe_deserialize_key(&key.sks_key)
                 .expect("We can't deserialize our own validated sks key");
             let pks: tfhe::CompactPublicKey = safe_deserialize_key(&key.pks_key)
                 .expect("We can't deserialize our own validated pks key");
+            param_set.check_public_key(&pks)?;
             let public_params: tfhe::zk::CompactPkeCrs = safe_deserialize_key(&key.public_params)
                 .expect("We can't deserialize our own validated public params");
             res.push(TfheTenantKeys {
@@ -133,6 +138,8 @@ where
                 chain_id: key.chain_id,
                 acl_contract_address: key.acl_contract_address,
                 verifying_contract_address: key.verifying_contract_address,
+                key_id: key.key_id,
+                param_set,
             });
         }
         #[cfg(feature = "gpu")]
@@ -141,6 +148,7 @@ where
                 .expect("We can't deserialize the gpu compressed sks key");
             let pks: tfhe::CompactPublicKey = safe_deserialize_key(&key.pks_key)
                 .expect("We can't deserialize our own validated pks key");
+            param_set.check_public_key(&pks)?;
             let public_params: tfhe::zk::CompactPkeCrs = safe_deserialize_key(&key.public_params)
                 .expect("We can't deserialize our own validated public params");
             let num_gpus = get_number_of_gpus() as u64;
@@ -159,6 +167,8 @@ where
                 chain_id: key.chain_id,
                 acl_contract_address: key.acl_contract_address,
                 verifying_contract_address: key.verifying_contract_address,
+                key_id: key.key_id,
+                param_set,
             });
         }
     }
@@ -191,3 +201,37 @@ where
 
     Ok(())
 }
+
+/// Evicts the cached keys whose key_id no longer matches the tenant, so that
+/// they are loaded again with the current key, see
+/// [`fhevm_engine_common::key_version`].
+pub async fn evict_stale_tenant_keys<'a, T>(
+    tenants: &[i32],
+    conn: T,
+    tenant_key_cache: &std::sync::Arc<tokio::sync::RwLock<lru::LruCache<i32, TfheTenantKeys>>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    T: sqlx::PgExecutor<'a>,
+{
+    let rows = query!(
+        "SELECT tenant_id, key_id FROM tenants WHERE tenant_id = ANY($1::INT[])",
+        tenants
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let mut key_cache = tenant_key_cache.write().await;
+    for row in rows {
+        if key_cache
+            .peek(&row.tenant_id)
+            .is_some_and(|keys| keys.key_id != row.key_id)
+        {
+            tracing::info!(
+                tenant_id = row.tenant_id,
+                "Tenant key changed, evicting cached keys"
+            );
+            key_cache.pop(&row.tenant_id);
+        }
+    }
+    Ok(())
+}
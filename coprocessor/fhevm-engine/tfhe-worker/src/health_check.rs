@@ -4,6 +4,7 @@ use fhevm_engine_common::healthz_server::{
     default_get_version, HealthCheckService, HealthStatus, Version,
 };
 use fhevm_engine_common::utils::HeartBeat;
+use fhevm_engine_common::warmup::Readiness;
 
 const ACTIVITY_FRESHNESS: Duration = Duration::from_secs(10); // Not alive if tick is older
 const CONNECTED_TICK_FRESHNESS: Duration = Duration::from_secs(5); // Need to check connection if tick is older
@@ -14,6 +15,8 @@ pub struct HealthCheck {
     pub database_url: String,
     pub database_heartbeat: HeartBeat,
     pub activity_heartbeat: HeartBeat,
+    /// Set once the keys are warmed up
+    pub readiness: Readiness,
 }
 
 impl HealthCheck {
@@ -23,6 +26,7 @@ impl HealthCheck {
             database_url,
             database_heartbeat: HeartBeat::new(),
             activity_heartbeat: HeartBeat::new(),
+            readiness: Readiness::default(),
         }
     }
 
@@ -64,4 +68,8 @@ impl HealthCheckService for HealthCheck {
     fn get_version(&self) -> Version {
         default_get_version()
     }
+
+    async fn is_ready(&self) -> bool {
+        self.readiness.is_ready()
+    }
 }
@@ -1,12 +1,18 @@
 use ::tracing::{error, info};
+use fhevm_engine_common::column_encryption::ColumnEncryption;
 use fhevm_engine_common::keys::{FhevmKeys, SerializedFhevmKeys};
-use fhevm_engine_common::{healthz_server, telemetry};
+use fhevm_engine_common::secret::SecretString;
+use fhevm_engine_common::{
+    buffer_pool, ciphertext_format, diagnostics, healthz_server, status_api, status_push, telemetry,
+};
 use tokio_util::sync::CancellationToken;
 
 use std::sync::Once;
+use std::time::Duration;
 use tokio::task::JoinSet;
 
 pub mod daemon_cli;
+pub mod dag_export;
 mod db_queries;
 pub mod health_check;
 pub mod metrics;
@@ -64,6 +70,14 @@ pub async fn async_main(
 
     info!(target: "async_main", args = ?args, "Starting runtime with args");
 
+    ciphertext_format::set_write_format(args.ciphertext_format);
+    buffer_pool::set_max_retained_bytes(args.buffer_pool_max_bytes);
+    dag_export::set_capacity(args.dag_export_capacity);
+    diagnostics::install(diagnostics::DiagnosticsConfig {
+        service: "tfhe-worker".to_owned(),
+        dir: args.diagnostics_dir.clone(),
+    });
+
     if !args.service_name.is_empty() {
         if let Err(err) = telemetry::setup_otlp(&args.service_name) {
             error!(error = %err, "Failed to setup OTLP");
@@ -76,6 +90,11 @@ pub async fn async_main(
             .unwrap_or("no_database_url".to_string()),
     );
 
+    if !args.run_bg_worker {
+        // only the background worker has keys to warm up
+        health_check.readiness.set_ready(true);
+    }
+
     let mut set = JoinSet::new();
     if args.run_server {
         info!(target: "async_main", "Initializing api server");
@@ -83,6 +102,15 @@ pub async fn async_main(
     }
 
     if args.run_bg_worker {
+        let pools_conf = scheduler::pools::PoolsConfig {
+            compression_threads: args.compression_threads,
+            pbs_threads: args.pbs_threads,
+            numa_pinning: args.numa_pinning,
+        };
+        match scheduler::pools::init(&pools_conf) {
+            Ok(nodes) => info!(target: "async_main", nodes, "Thread pools initialized"),
+            Err(e) => error!(target: "async_main", error = %e, "Failed to build thread pools"),
+        }
         info!(target: "async_main", "Initializing background worker");
         set.spawn(tfhe_worker::run_tfhe_worker(
             args.clone(),
@@ -99,12 +127,22 @@ pub async fn async_main(
         panic!("No tasks specified to run");
     }
 
+    if let Some(port) = args.status_api_port {
+        info!(target: "async_main", "Initializing status API server");
+        set.spawn(run_status_api(args.clone(), port));
+    }
+
     info!(target: "async_main", "Start health check server");
     let health_check_cancel_token = CancellationToken::new();
     let health_check_server = healthz_server::HttpServer::new(
         std::sync::Arc::new(health_check.clone()),
         args.health_check_port,
         health_check_cancel_token,
+    )
+    .with_admin_token(
+        args.admin_token
+            .clone()
+            .or_else(|| std::env::var("ADMIN_TOKEN").ok().map(SecretString::from)),
     );
     let Ok(()) = health_check_server.start().await else {
         panic!("Failed to start health check server");
@@ -119,6 +157,39 @@ pub async fn async_main(
     Ok(())
 }
 
+async fn run_status_api(
+    args: daemon_cli::Args,
+    port: u16,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect_with(utils::db_connect_options(&args)?)
+        .await?;
+    let conf = status_api::StatusApiConfig {
+        port,
+        auth_token: args.status_api_auth_token.or_else(|| {
+            std::env::var("STATUS_API_AUTH_TOKEN")
+                .ok()
+                .map(SecretString::from)
+        }),
+        notify_channels: status_push::DEFAULT_NOTIFY_CHANNELS
+            .iter()
+            .map(|channel| channel.to_string())
+            .collect(),
+        push_poll_interval: Duration::from_millis(args.status_api_push_poll_interval_ms),
+        column_encryption: args
+            .column_encryption_key
+            .as_deref()
+            .map(ColumnEncryption::from_secret)
+            .transpose()?
+            .unwrap_or_default(),
+    };
+    status_api::StatusApiServer::new(pool, conf, CancellationToken::new())
+        .start()
+        .await?;
+    Ok(())
+}
+
 pub fn generate_dump_fhe_keys() {
     let keys = FhevmKeys::new();
     let ser_keys: SerializedFhevmKeys = keys.into();
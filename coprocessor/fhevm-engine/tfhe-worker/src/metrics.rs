@@ -12,6 +12,34 @@ async fn healthcheck() -> impl actix_web::Responder {
     "OK"
 }
 
+#[derive(serde::Deserialize)]
+struct DagQuery {
+    format: Option<String>,
+}
+
+/// Dependence graph of a recently executed transaction, see [`crate::dag_export`].
+async fn dag(
+    transaction_id: actix_web::web::Path<String>,
+    query: actix_web::web::Query<DagQuery>,
+) -> actix_web::HttpResponse {
+    let Some(export) = crate::dag_export::get(&transaction_id) else {
+        return actix_web::HttpResponse::NotFound().body("transaction not found");
+    };
+    match query.format.as_deref() {
+        Some("dot") => actix_web::HttpResponse::Ok()
+            .content_type("text/vnd.graphviz")
+            .body(export.to_dot()),
+        None | Some("json") => match export.to_json() {
+            Ok(json) => actix_web::HttpResponse::Ok()
+                .content_type("application/json")
+                .body(json),
+            Err(err) => actix_web::HttpResponse::InternalServerError().body(err.to_string()),
+        },
+        Some(format) => actix_web::HttpResponse::BadRequest()
+            .body(format!("unknown format {format:?}, expected json or dot")),
+    }
+}
+
 pub async fn run_metrics_server(
     args: crate::daemon_cli::Args,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -23,6 +51,7 @@ pub async fn run_metrics_server(
         actix_web::App::new()
             .route("/metrics", actix_web::web::to(metrics))
             .route("/health", actix_web::web::to(healthcheck))
+            .route("/debug/dag/{transaction_id}", actix_web::web::get().to(dag))
     })
     .bind(&args.metrics_addr)
     .expect("can't bind to metrics server address")
@@ -10,7 +10,9 @@ use crate::utils::sort_computations_by_dependencies;
 use alloy::signers::local::PrivateKeySigner;
 use alloy::signers::SignerSync;
 use alloy::sol_types::{Eip712Domain, SolStruct};
+use fhevm_engine_common::ciphertext_format;
 pub use fhevm_engine_common::common;
+use fhevm_engine_common::secret::SecretString;
 use fhevm_engine_common::tfhe_ops::{
     check_fhe_operand_types, current_ciphertext_version, trivial_encrypt_be_bytes,
     try_expand_ciphertext_list, validate_fhe_type,
@@ -105,17 +107,18 @@ pub async fn run_server_iteration(
         .server_addr
         .parse()
         .expect("Can't parse server address");
-    let db_url = crate::utils::db_url(&args);
+    let db_options = crate::utils::db_connect_options(&args)?;
 
-    let coprocessor_key_file = tokio::fs::read_to_string(&args.coprocessor_private_key).await?;
+    let coprocessor_key_file =
+        SecretString::from(tokio::fs::read_to_string(&args.coprocessor_private_key).await?);
 
-    let signer = PrivateKeySigner::from_str(coprocessor_key_file.trim())?;
+    let signer = PrivateKeySigner::from_str(coprocessor_key_file.expose().trim())?;
     info!(target: "grpc_server", { address = signer.address().to_string() }, "Coprocessor signer initiated");
 
     info!("Coprocessor listening on {}", addr);
     let pool = sqlx::postgres::PgPoolOptions::new()
         .max_connections(args.pg_pool_max_connections)
-        .connect(&db_url)
+        .connect_with(db_options)
         .await?;
 
     let tenant_key_cache: std::sync::Arc<tokio::sync::RwLock<lru::LruCache<i32, TfheTenantKeys>>> =
@@ -329,6 +332,7 @@ impl CoprocessorService {
                 .map_err(tonic::Status::from_error)?
         };
         let chain_id = fetch_key_response.chain_id;
+        let param_set = fetch_key_response.param_set;
         let chain_id_be = (chain_id as u64).to_be_bytes();
         let server_key = fetch_key_response.server_key;
         let verifying_contract_address = fetch_key_response.verifying_contract_address;
@@ -405,12 +409,15 @@ impl CoprocessorService {
                     span.end();
 
                     let mut span = tracer.child_span("expand_ciphertext_list");
-                    let expanded =
-                        try_expand_ciphertext_list(&cloned_input.input_payload, &public_params)
-                            .map_err(|e| {
-                                let err: Box<dyn std::error::Error + Send + Sync> = Box::new(e);
-                                (err, idx)
-                            })?;
+                    let expanded = try_expand_ciphertext_list(
+                        &cloned_input.input_payload,
+                        &public_params,
+                        param_set,
+                    )
+                    .map_err(|e| {
+                        let err: Box<dyn std::error::Error + Send + Sync> = Box::new(e);
+                        (err, idx)
+                    })?;
 
                     span.set_attributes(vec![
                         KeyValue::new("idx", idx as i64),
@@ -552,9 +559,10 @@ impl CoprocessorService {
                         ciphertext_version,
                         ciphertext_type,
                         input_blob_hash,
-                        input_blob_index
+                        input_blob_index,
+                        param_set
                     )
-                    VALUES($1, $2, $3, $4, $5, $6, $7)
+                    VALUES($1, $2, $3, $4, $5, $6, $7, $8)
                     ON CONFLICT (tenant_id, handle, ciphertext_version) DO NOTHING
                 ",
                     tenant_id,
@@ -563,7 +571,8 @@ impl CoprocessorService {
                     ciphertext_version,
                     serialized_type,
                     &blob_hash,
-                    ct_idx as i32
+                    ct_idx as i32,
+                    param_set.name
                 )
                 .execute(trx.as_mut())
                 .await
@@ -747,6 +756,7 @@ impl CoprocessorService {
                 .map_err(tonic::Status::from_error)?
         };
         let server_key = fetch_key_response.server_key;
+        let param_set = fetch_key_response.param_set;
         span.end();
 
         let cloned = req.values.clone();
@@ -790,11 +800,11 @@ impl CoprocessorService {
                 KeyValue::new("ciphertext_type", db_type as i64),
             ]);
             sqlx::query!("
-                    INSERT INTO ciphertexts(tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type)
-                    VALUES ($1, $2, $3, $4, $5)
+                    INSERT INTO ciphertexts(tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type, param_set)
+                    VALUES ($1, $2, $3, $4, $5, $6)
                     ON CONFLICT (tenant_id, handle, ciphertext_version) DO NOTHING
                 ",
-                tenant_id, handle, db_bytes, current_ciphertext_version(), db_type as i16
+                tenant_id, handle, db_bytes, current_ciphertext_version(), db_type as i16, param_set.name
             )
             .execute(trx.as_mut()).await.map_err(Into::<CoprocessorError>::into)?;
             span.end();
@@ -861,11 +871,12 @@ impl CoprocessorService {
             let ciphertext: Result<Option<FetchedCiphertext>, tonic::Status> = the_map
                 .get(h)
                 .map(|res| {
+                    // The storage envelope is internal, clients get the bare ciphertext.
+                    let (_, ciphertext_bytes) = ciphertext_format::open(&res.ciphertext)
+                        .map_err(CoprocessorError::FhevmError)?;
                     let signature_data = GetCiphertextResponseSignatureData {
                         handle: alloy::primitives::U256::from_be_slice(h),
-                        ciphertext_digest: Keccak256::digest(&the_map.get(h).unwrap().ciphertext)
-                            .to_vec()
-                            .into(),
+                        ciphertext_digest: Keccak256::digest(ciphertext_bytes).to_vec().into(),
                     };
                     let signing_hash =
                         signature_data.eip712_signing_hash(&self.get_ciphertext_eip712_domain);
@@ -875,7 +886,7 @@ impl CoprocessorService {
                         }
                     })?;
                     Ok(FetchedCiphertext {
-                        ciphertext_bytes: res.ciphertext.clone(),
+                        ciphertext_bytes: ciphertext_bytes.to_vec(),
                         ciphertext_type: res.ciphertext_type as i32,
                         ciphertext_version: res.ciphertext_version as i32,
                         signature: signature.into(),
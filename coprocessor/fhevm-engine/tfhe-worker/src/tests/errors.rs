@@ -12,10 +12,12 @@ use crate::{
     },
     tests::{
         inputs::{test_random_contract_address, test_random_user_address},
-        utils::{default_api_key, default_tenant_id, setup_test_app},
+        utils::{default_api_key, default_tenant_id, random_handle, setup_test_app, TestInstance},
     },
 };
+use fhevm_engine_common::param_set::DEFAULT_PARAM_SET;
 use fhevm_engine_common::utils::safe_serialize;
+use sqlx::{PgPool, Postgres};
 use tonic::metadata::MetadataValue;
 
 #[tokio::test]
@@ -674,3 +676,192 @@ async fn test_coprocessor_computation_errors() -> Result<(), Box<dyn std::error:
 
     Ok(())
 }
+
+/// Uploads two inputs, sets `column` of the ciphertext of the first one to `tampered` and of the
+/// second one to `current` if any, then adds each of them to the second one. Returns the error of
+/// the computation on the first input, which must be rejected, and the output handle of the
+/// computation on the second one.
+async fn compute_on_tampered_input<T>(
+    app: &TestInstance,
+    pool: &PgPool,
+    column: &str,
+    tampered: T,
+    current: Option<T>,
+) -> Result<(String, Vec<u8>), Box<dyn std::error::Error>>
+where
+    T: for<'q> sqlx::Encode<'q, Postgres> + sqlx::Type<Postgres> + Send,
+{
+    let mut client = FhevmCoprocessorClient::connect(app.app_url().to_string()).await?;
+    let api_key_header = format!("bearer {}", default_api_key());
+
+    let keys = query_tenant_keys(vec![default_tenant_id()], pool)
+        .await
+        .map_err(|e| {
+            let e: Box<dyn std::error::Error> = e;
+            e
+        })?;
+    let keys = &keys[0];
+
+    let mut builder = tfhe::ProvenCompactCiphertextList::builder(&keys.pks);
+    let the_list = builder
+        .push(1u8)
+        .push(2u8)
+        .build_with_proof_packed(&keys.public_params, &[], tfhe::zk::ZkComputeLoad::Proof)
+        .unwrap();
+    let mut input_request = tonic::Request::new(InputUploadBatch {
+        input_ciphertexts: vec![InputToUpload {
+            input_payload: safe_serialize(&the_list),
+            signatures: Vec::new(),
+            user_address: test_random_user_address(),
+            contract_address: test_random_contract_address(),
+        }],
+    });
+    input_request.metadata_mut().append(
+        "authorization",
+        MetadataValue::from_str(&api_key_header).unwrap(),
+    );
+    let resp = client.upload_inputs(input_request).await?;
+    let handles = &resp.get_ref().upload_responses[0].input_handles;
+    let tampered_input = handles[0].handle.clone();
+    let input = handles[1].handle.clone();
+
+    let update =
+        format!("UPDATE ciphertexts SET {column} = $1 WHERE tenant_id = $2 AND handle = $3");
+    sqlx::query(&update)
+        .bind(tampered)
+        .bind(default_tenant_id())
+        .bind(&tampered_input)
+        .execute(pool)
+        .await?;
+    if let Some(current) = current {
+        sqlx::query(&update)
+            .bind(current)
+            .bind(default_tenant_id())
+            .bind(&input)
+            .execute(pool)
+            .await?;
+    }
+
+    let transaction_id = random_handle().to_be_bytes().to_vec();
+    let rejected_output = random_handle().to_be_bytes().to_vec();
+    let computed_output = random_handle().to_be_bytes().to_vec();
+    let computation = |output_handle: &Vec<u8>, lhs: &Vec<u8>| AsyncComputation {
+        operation: FheOperation::FheAdd.into(),
+        transaction_id: transaction_id.clone(),
+        output_handle: output_handle.clone(),
+        inputs: vec![
+            AsyncComputationInput {
+                input: Some(Input::InputHandle(lhs.clone())),
+            },
+            AsyncComputationInput {
+                input: Some(Input::InputHandle(input.clone())),
+            },
+        ],
+        is_allowed: true,
+    };
+    let mut compute_request = tonic::Request::new(AsyncComputeRequest {
+        computations: vec![
+            computation(&rejected_output, &tampered_input),
+            computation(&computed_output, &input),
+        ],
+    });
+    compute_request.metadata_mut().append(
+        "authorization",
+        MetadataValue::from_str(&api_key_header).unwrap(),
+    );
+    client.async_compute(compute_request).await?;
+
+    let mut rejected = None;
+    for _ in 0..30 {
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        rejected = sqlx::query_scalar(
+            "SELECT error_message FROM computations
+            WHERE tenant_id = $1 AND output_handle = $2 AND is_error = TRUE",
+        )
+        .bind(default_tenant_id())
+        .bind(&rejected_output)
+        .fetch_optional(pool)
+        .await?;
+        if rejected.is_some() {
+            break;
+        }
+    }
+    let error_message: Option<String> = rejected.expect("computation should be rejected");
+    Ok((error_message.unwrap_or_default(), computed_output))
+}
+
+#[tokio::test]
+async fn test_coprocessor_key_version_mismatch() -> Result<(), Box<dyn std::error::Error>> {
+    let app = setup_test_app().await?;
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(app.db_url())
+        .await?;
+
+    // The first input was created under a previous key of the tenant
+    let previous_key_id = vec![1u8; 32];
+    let current_key_id = vec![2u8; 32];
+    let tenant_key_id: Option<Vec<u8>> =
+        sqlx::query_scalar("SELECT key_id FROM tenants WHERE tenant_id = $1")
+            .bind(default_tenant_id())
+            .fetch_one(&pool)
+            .await?;
+    sqlx::query("UPDATE tenants SET key_id = $1 WHERE tenant_id = $2")
+        .bind(&current_key_id)
+        .bind(default_tenant_id())
+        .execute(&pool)
+        .await?;
+
+    let (error_message, computed_output) = compute_on_tampered_input(
+        &app,
+        &pool,
+        "key_id",
+        previous_key_id,
+        Some(current_key_id.clone()),
+    )
+    .await?;
+    assert!(error_message.contains("key version mismatch"));
+
+    // The result computed under the current key is tagged with it
+    let computed_key_id: Option<Vec<u8>> =
+        sqlx::query_scalar("SELECT key_id FROM ciphertexts WHERE tenant_id = $1 AND handle = $2")
+            .bind(default_tenant_id())
+            .bind(&computed_output)
+            .fetch_one(&pool)
+            .await?;
+    assert_eq!(computed_key_id, Some(current_key_id));
+
+    sqlx::query("UPDATE tenants SET key_id = $1 WHERE tenant_id = $2")
+        .bind(tenant_key_id)
+        .bind(default_tenant_id())
+        .execute(&pool)
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_coprocessor_param_set_mismatch() -> Result<(), Box<dyn std::error::Error>> {
+    let app = setup_test_app().await?;
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(app.db_url())
+        .await?;
+
+    // The first input was expanded under another parameter set
+    let (error_message, computed_output) =
+        compute_on_tampered_input(&app, &pool, "param_set", "other_param_set", None).await?;
+    assert!(error_message.contains("param set mismatch"));
+
+    // The result computed with the tenant keys is tagged with their parameter set
+    let computed_param_set: Option<String> = sqlx::query_scalar(
+        "SELECT param_set FROM ciphertexts WHERE tenant_id = $1 AND handle = $2",
+    )
+    .bind(default_tenant_id())
+    .bind(&computed_output)
+    .fetch_one(&pool)
+    .await?;
+    assert_eq!(computed_param_set.as_deref(), Some(DEFAULT_PARAM_SET.name));
+
+    Ok(())
+}
@@ -181,6 +181,185 @@ async fn test_smoke() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_completes_persisted_results() -> Result<(), Box<dyn std::error::Error>> {
+    let app = utils::setup_test_app_with_args(|args| args.incremental_results = true).await?;
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(app.db_url())
+        .await?;
+
+    let mut client = FhevmCoprocessorClient::connect(app.app_url().to_string()).await?;
+
+    let api_key_header = format!("bearer {}", default_api_key());
+    let ct_type = 4; // i32
+
+    let transaction_id = random_handle().to_be_bytes();
+    let h1 = random_handle().to_be_bytes();
+    // never computed, the computation cannot run
+    let h2 = random_handle().to_be_bytes();
+    let h3 = random_handle().to_be_bytes();
+
+    let mut encrypt_request = tonic::Request::new(TrivialEncryptBatch {
+        values: vec![TrivialEncryptRequestSingle {
+            handle: h1.to_vec(),
+            be_value: vec![123],
+            output_type: ct_type,
+        }],
+    });
+    encrypt_request.metadata_mut().append(
+        "authorization",
+        MetadataValue::from_str(&api_key_header).unwrap(),
+    );
+    client.trivial_encrypt_ciphertexts(encrypt_request).await?;
+
+    // The result of h3 was persisted by a batch that did not complete
+    sqlx::query(
+        "INSERT INTO ciphertexts(tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type)
+        SELECT tenant_id, $1, ciphertext, ciphertext_version, ciphertext_type
+        FROM ciphertexts WHERE tenant_id = $2 AND handle = $3",
+    )
+    .bind(h3.to_vec())
+    .bind(1)
+    .bind(h1.to_vec())
+    .execute(&pool)
+    .await?;
+
+    let mut compute_request = tonic::Request::new(AsyncComputeRequest {
+        computations: vec![AsyncComputation {
+            operation: FheOperation::FheAdd.into(),
+            transaction_id: transaction_id.to_vec(),
+            output_handle: h3.to_vec(),
+            inputs: vec![
+                AsyncComputationInput {
+                    input: Some(Input::InputHandle(h2.to_vec())),
+                },
+                AsyncComputationInput {
+                    input: Some(Input::Scalar(vec![0x00, 0x10])),
+                },
+            ],
+            is_allowed: true,
+        }],
+    });
+    compute_request.metadata_mut().append(
+        "authorization",
+        MetadataValue::from_str(&api_key_header).unwrap(),
+    );
+    client.async_compute(compute_request).await?;
+
+    let mut completed = false;
+    for _ in 0..30 {
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        completed = sqlx::query_scalar(
+            "SELECT is_completed AND NOT is_error FROM computations
+            WHERE tenant_id = $1 AND output_handle = $2",
+        )
+        .bind(1)
+        .bind(h3.to_vec())
+        .fetch_one(&pool)
+        .await?;
+        if completed {
+            break;
+        }
+    }
+    assert!(
+        completed,
+        "computation should be completed from its persisted result"
+    );
+
+    let resp = decrypt_ciphertexts(&pool, 1, vec![h3.to_vec()]).await?;
+    assert_eq!(resp[0].value, "123");
+    Ok(())
+}
+
+#[test]
+fn test_parse_caller_weight() {
+    use crate::daemon_cli::{parse_caller_weight, CallerWeight};
+
+    assert_eq!(
+        parse_caller_weight("0x00000000000000000000000000000000000000aa=2.5"),
+        Ok(CallerWeight {
+            caller: [vec![0; 19], vec![0xaa]].concat(),
+            weight: 2.5,
+        })
+    );
+    assert!(parse_caller_weight("0x00000000000000000000000000000000000000aa").is_err());
+    assert!(parse_caller_weight("0xaa=1").is_err());
+    assert!(parse_caller_weight("0x00000000000000000000000000000000000000aa=0").is_err());
+}
+
+#[tokio::test]
+async fn test_fair_claim_interleaves_callers() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::daemon_cli::CallerWeight;
+    use crate::tfhe_worker::query_for_work_fair;
+    use test_harness::db_utils::insert_random_tenant;
+    use test_harness::instance::{setup_test_db, ImportMode};
+
+    let db_instance = setup_test_db(ImportMode::None).await?;
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(db_instance.db_url())
+        .await?;
+    let tenant_id = insert_random_tenant(&pool).await?;
+
+    let caller_a = vec![0xaa; 20];
+    let caller_b = vec![0xbb; 20];
+    // The backlog of caller A is scheduled first, each computation in its own transaction
+    let computations = [(Some(&caller_a), 6), (Some(&caller_b), 4), (None, 2)];
+    let mut order = 0;
+    for (caller, count) in computations {
+        for _ in 0..count {
+            order += 1;
+            sqlx::query(
+                "INSERT INTO computations
+                    (tenant_id, output_handle, output_type, dependencies, fhe_operation,
+                     is_scalar, transaction_id, caller, is_allowed, schedule_order)
+                 VALUES ($1, $2, 4, '{}', 24, FALSE, $3, $4, TRUE,
+                         '2025-01-01'::TIMESTAMP + INTERVAL '1 second' * $5)",
+            )
+            .bind(tenant_id)
+            .bind(vec![order as u8; 32])
+            .bind(vec![order as u8; 32])
+            .bind(caller)
+            .bind(order as f64)
+            .execute(&pool)
+            .await?;
+        }
+    }
+
+    let caller_of = |transaction_id: u8| match transaction_id {
+        1..=6 => "a",
+        7..=10 => "b",
+        _ => "none",
+    };
+    let mut trx = pool.begin().await?;
+    let weights = [CallerWeight {
+        caller: caller_b.clone(),
+        weight: 2.0,
+    }];
+    let claimed = query_for_work_fair(6, &weights, &mut trx).await?;
+    trx.rollback().await?;
+
+    // In virtual finish time order: B1 (0.5), A1 (1), B2 (1), NULL1 (1), B3 (1.5), A2 (2)
+    let mut claimed: Vec<_> = claimed.iter().map(|w| w.transaction_id[0]).collect();
+    claimed.sort();
+    assert_eq!(claimed, vec![1, 2, 7, 8, 9, 11]);
+    let count = |caller| claimed.iter().filter(|t| caller_of(**t) == caller).count();
+    assert_eq!((count("a"), count("b"), count("none")), (2, 3, 1));
+
+    // Without weights, each caller gets the same share
+    let mut trx = pool.begin().await?;
+    let mut claimed: Vec<_> = query_for_work_fair(6, &[], &mut trx)
+        .await?
+        .iter()
+        .map(|w| w.transaction_id[0])
+        .collect();
+    trx.rollback().await?;
+    claimed.sort();
+    assert_eq!(claimed, vec![1, 2, 7, 8, 11, 12]);
+    Ok(())
+}
+
 #[tokio::test]
 #[ignore]
 // custom test to run against local instance for decrypting custom ciphertexts
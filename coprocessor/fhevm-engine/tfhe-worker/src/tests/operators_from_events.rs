@@ -1,7 +1,7 @@
-use alloy::primitives::{FixedBytes, Log};
+use alloy::primitives::{Address, FixedBytes, Log};
 use bigdecimal::num_bigint::BigInt;
 
-use fhevm_engine_common::types::AllowEvents;
+use host_listener::contracts::AclContract::{self, AclContractEvents};
 use host_listener::contracts::TfheContract;
 use host_listener::contracts::TfheContract::TfheContractEvents;
 use host_listener::database::tfhe_event_propagate::{
@@ -51,10 +51,14 @@ pub async fn allow_handle(
     tx: &mut Transaction<'_>,
     handle: &[u8],
 ) -> Result<(), sqlx::Error> {
-    let account_address = String::new();
-    let event_type = AllowEvents::AllowedForDecryption;
-    db.insert_allowed_handle(tx, handle.to_owned(), account_address, event_type, None)
-        .await
+    let event = Log {
+        address: Address::ZERO,
+        data: AclContractEvents::AllowedForDecryption(AclContract::AllowedForDecryption {
+            caller: Address::ZERO,
+            handlesList: vec![Handle::from_slice(handle)],
+        }),
+    };
+    db.handle_acl_event(tx, &event, &None, &None).await
 }
 
 fn as_handle(big_int: &BigInt) -> Handle {
@@ -58,12 +58,20 @@ pub fn random_handle() -> u64 {
 }
 
 pub async fn setup_test_app() -> Result<TestInstance, Box<dyn std::error::Error>> {
+    setup_test_app_with_args(|_| {}).await
+}
+
+/// Sets up the test app with the arguments changed by `configure`, which are ignored when testing
+/// an app already running on localhost.
+pub async fn setup_test_app_with_args(
+    configure: fn(&mut Args),
+) -> Result<TestInstance, Box<dyn std::error::Error>> {
     if std::env::var("COPROCESSOR_TEST_LOCALHOST").is_ok() {
         setup_test_app_existing_localhost().await
     } else if std::env::var("COPROCESSOR_TEST_LOCAL_DB").is_ok() {
-        setup_test_app_existing_db().await
+        setup_test_app_existing_db(configure).await
     } else {
-        setup_test_app_custom_docker().await
+        setup_test_app_custom_docker(configure).await
     }
 }
 
@@ -79,10 +87,12 @@ pub async fn setup_test_app_existing_localhost() -> Result<TestInstance, Box<dyn
     })
 }
 
-async fn setup_test_app_existing_db() -> Result<TestInstance, Box<dyn std::error::Error>> {
+async fn setup_test_app_existing_db(
+    configure: fn(&mut Args),
+) -> Result<TestInstance, Box<dyn std::error::Error>> {
     let app_port = get_app_port();
     let (app_close_channel, rx) = tokio::sync::watch::channel(false);
-    start_coprocessor(rx, app_port, LOCAL_DB_URL).await;
+    start_coprocessor(rx, app_port, LOCAL_DB_URL, configure).await;
     Ok(TestInstance {
         _container: None,
         app_close_channel: Some(app_close_channel),
@@ -91,30 +101,61 @@ async fn setup_test_app_existing_db() -> Result<TestInstance, Box<dyn std::error
     })
 }
 
-async fn start_coprocessor(rx: Receiver<bool>, app_port: u16, db_url: &str) {
-    let args: Args = Args {
+async fn start_coprocessor(
+    rx: Receiver<bool>,
+    app_port: u16,
+    db_url: &str,
+    configure: fn(&mut Args),
+) {
+    let mut args: Args = Args {
         run_bg_worker: true,
         worker_polling_interval_ms: 1000,
+        notification_debounce_ms: 0,
         run_server: true,
         generate_fhe_keys: false,
         server_maximum_ciphertexts_to_schedule: 5000,
         server_maximum_ciphertexts_to_get: 5000,
         work_items_batch_size: 40,
+        fair_scheduling: false,
+        caller_weights: vec![],
+        incremental_results: false,
+        result_write_batch_size: 50,
+        result_write_max_latency_ms: 20,
         dependence_chains_per_batch: 10,
         tenant_key_cache_size: 4,
         coprocessor_fhe_threads: 4,
+        compression_threads: 2,
+        pbs_threads: 0,
+        numa_pinning: false,
         maximum_handles_per_input: 255,
         tokio_threads: 2,
         pg_pool_max_connections: 2,
         server_addr: format!("127.0.0.1:{app_port}"),
         metrics_addr: "".to_string(),
         database_url: Some(db_url.to_string()),
+        database_schema: None,
         maximum_compact_inputs_upload: 10,
         coprocessor_private_key: "./coprocessor.key".to_string(),
         service_name: "coprocessor".to_string(),
         log_level: Level::INFO,
         health_check_port: 8081,
+        ciphertext_format: Default::default(),
+        buffer_pool_max_bytes: fhevm_engine_common::buffer_pool::DEFAULT_MAX_RETAINED_BYTES,
+        status_api_port: None,
+        status_api_auth_token: None,
+        status_api_push_poll_interval_ms: 2000,
+        column_encryption_key: None,
+        webhook_url: None,
+        webhook_secret: None,
+        webhook_events: vec![],
+        webhook_batch_size: 100,
+        webhook_max_latency_ms: 1000,
+        webhook_max_attempts: 5,
+        dag_export_capacity: 0,
+        diagnostics_dir: None,
+        admin_token: None,
     };
+    configure(&mut args);
 
     std::thread::spawn(move || {
         crate::start_runtime(args, Some(rx));
@@ -135,7 +176,9 @@ fn get_app_port() -> u16 {
     app_port
 }
 
-async fn setup_test_app_custom_docker() -> Result<TestInstance, Box<dyn std::error::Error>> {
+async fn setup_test_app_custom_docker(
+    configure: fn(&mut Args),
+) -> Result<TestInstance, Box<dyn std::error::Error>> {
     let app_port = get_app_port();
 
     let container = GenericImage::new("postgres", "15.7")
@@ -173,7 +216,7 @@ async fn setup_test_app_custom_docker() -> Result<TestInstance, Box<dyn std::err
     println!("DB prepared");
 
     let (app_close_channel, rx) = tokio::sync::watch::channel(false);
-    start_coprocessor(rx, app_port, &db_url).await;
+    start_coprocessor(rx, app_port, &db_url, configure).await;
     Ok(TestInstance {
         _container: Some(container),
         app_close_channel: Some(app_close_channel),
@@ -1,26 +1,50 @@
+use crate::daemon_cli::CallerWeight;
+use crate::db_queries::{evict_stale_tenant_keys, populate_cache_with_tenant_keys};
 use crate::types::CoprocessorError;
-use crate::{db_queries::populate_cache_with_tenant_keys, types::TfheTenantKeys};
+use crate::types::TfheTenantKeys;
+use async_trait::async_trait;
+use bytes::Bytes;
+use fhevm_engine_common::buffer_pool;
+use fhevm_engine_common::db_query::{timed_query, QueryPolicy};
+use fhevm_engine_common::diagnostics;
+use fhevm_engine_common::key_version;
+use fhevm_engine_common::param_set::{ParamSet, DEFAULT_PARAM_SET};
+use fhevm_engine_common::pg_listener::{ListenerEvent, SupervisedListener};
+use fhevm_engine_common::secret::SecretString;
 use fhevm_engine_common::tfhe_ops::check_fhe_operand_types;
 use fhevm_engine_common::types::{FhevmError, Handle, SupportedFheCiphertexts};
+use fhevm_engine_common::warmup;
+use fhevm_engine_common::webhook::{
+    WebhookConfig, WebhookEvent, WebhookEventType, WebhookExporter,
+};
+use fhevm_engine_common::write_batcher::{BatchWriter, WriteBatcher, WriteBatcherConfig};
 use fhevm_engine_common::{tfhe_ops::current_ciphertext_version, types::SupportedFheOperations};
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use opentelemetry::trace::{Span, TraceContextExt, Tracer};
 use opentelemetry::KeyValue;
 use prometheus::{register_int_counter, IntCounter};
-use scheduler::dfg::types::{DFGTxInput, SchedulerError};
+use scheduler::dfg::types::{DFGComputedResult, DFGTxInput, SchedulerError};
 use scheduler::dfg::{scheduler::Scheduler, types::DFGTaskInput};
 use scheduler::dfg::{DFGOp, DFTxGraph, TxNode};
-use sqlx::Postgres;
-use sqlx::{postgres::PgListener, query, Acquire};
+use sqlx::{query, query_as, Acquire};
+use sqlx::{PgConnection, Postgres};
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeSet, HashMap, HashSet},
     num::NonZeroUsize,
+    time::{Duration, Instant},
 };
 use tracing::{debug, error, info, warn};
 
 const EVENT_CIPHERTEXT_COMPUTED: &str = "event_ciphertext_computed";
 
+/// Input ciphertexts under another key or parameter set than the tenant's,
+/// with the error message of the computations consuming them.
+type KeyMismatches = Vec<(Handle, String)>;
+
+/// Results already written by the [`ResultWriter`], skipped at upload.
+type PersistedResults = HashSet<Handle>;
+
 lazy_static! {
     pub static ref TIMING: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
 }
@@ -53,15 +77,92 @@ lazy_static! {
         "work items successfully processed and stored in the database"
     )
     .unwrap();
+    static ref RESULTS_PERSISTED_COUNTER: IntCounter = register_int_counter!(
+        "coprocessor_results_persisted_incrementally",
+        "results written as soon as computed, before the end of their work batch"
+    )
+    .unwrap();
+    static ref WORK_ITEMS_RECOVERED_COUNTER: IntCounter = register_int_counter!(
+        "coprocessor_work_items_recovered",
+        "claimed work items completed from a result persisted by a previous batch"
+    )
+    .unwrap();
+}
+
+/// Result written as soon as it is computed, see `--incremental-results`.
+struct PersistedResult {
+    tenant_id: i32,
+    key_id: Option<Vec<u8>>,
+    param_set: &'static ParamSet,
+    result: DFGComputedResult,
+}
+
+/// Inserts the computed ciphertexts outside of the work transaction, so that
+/// consumers get them without waiting for the whole work batch, and that a
+/// crash does not lose them. The computations stay locked by the work
+/// transaction, they are completed at upload or on the next claim, see
+/// [`complete_persisted_computations`].
+struct ResultWriter;
+
+#[async_trait]
+impl BatchWriter for ResultWriter {
+    type Item = PersistedResult;
+
+    async fn write(
+        &self,
+        conn: &mut PgConnection,
+        items: &[PersistedResult],
+    ) -> Result<(), sqlx::Error> {
+        for item in items {
+            query!(
+                "INSERT INTO ciphertexts(tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type, key_id, param_set)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (tenant_id, handle, ciphertext_version) DO NOTHING",
+                item.tenant_id,
+                item.result.handle,
+                item.result.ct_bytes,
+                current_ciphertext_version(),
+                item.result.ct_type,
+                item.key_id,
+                item.param_set.name,
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        // Notified within the batch transaction, on commit
+        query!("SELECT pg_notify($1, '')", EVENT_CIPHERTEXT_COMPUTED)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
 }
 
 pub async fn run_tfhe_worker(
     args: crate::daemon_cli::Args,
     health_check: crate::health_check::HealthCheck,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let webhook = args
+        .webhook_url
+        .clone()
+        .map(|url| {
+            WebhookExporter::spawn(WebhookConfig {
+                url,
+                secret: args
+                    .webhook_secret
+                    .clone()
+                    .or_else(|| std::env::var("WEBHOOK_SECRET").ok().map(SecretString::from)),
+                event_types: args.webhook_events.clone(),
+                max_batch_size: args.webhook_batch_size,
+                max_latency: Duration::from_millis(args.webhook_max_latency_ms),
+                max_attempts: args.webhook_max_attempts,
+            })
+        })
+        .transpose()?;
     loop {
         // here we log the errors and make sure we retry
-        if let Err(cycle_error) = tfhe_worker_cycle(&args, health_check.clone()).await {
+        if let Err(cycle_error) =
+            tfhe_worker_cycle(&args, health_check.clone(), webhook.as_ref()).await
+        {
             WORKER_ERRORS_COUNTER.inc();
             error!(target: "tfhe_worker", { error = cycle_error }, "Error in background worker, retrying shortly");
         }
@@ -72,30 +173,47 @@ pub async fn run_tfhe_worker(
 async fn tfhe_worker_cycle(
     args: &crate::daemon_cli::Args,
     health_check: crate::health_check::HealthCheck,
+    webhook: Option<&WebhookExporter>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let tracer = opentelemetry::global::tracer("tfhe_worker");
     let tenant_key_cache: std::sync::Arc<tokio::sync::RwLock<lru::LruCache<i32, TfheTenantKeys>>> =
         std::sync::Arc::new(tokio::sync::RwLock::new(lru::LruCache::new(
             NonZeroUsize::new(args.tenant_key_cache_size as usize).unwrap(),
         )));
-    let db_url = crate::utils::db_url(args);
     let pool = sqlx::postgres::PgPoolOptions::new()
         .max_connections(args.pg_pool_max_connections)
-        .connect(&db_url)
+        .connect_with(crate::utils::db_connect_options(args)?)
         .await?;
-    let mut listener = PgListener::connect_with(&pool).await?;
-    listener.listen("work_available").await?;
+    let mut listener = SupervisedListener::connect(&pool, ["work_available"]).await?;
+    let result_writer = args.incremental_results.then(|| {
+        WriteBatcher::spawn(
+            pool.clone(),
+            ResultWriter,
+            WriteBatcherConfig {
+                max_batch_size: args.result_write_batch_size,
+                max_latency: Duration::from_millis(args.result_write_max_latency_ms),
+            },
+        )
+    });
 
     #[cfg(feature = "bench")]
     populate_cache_with_tenant_keys(vec![1i32], &pool, &tenant_key_cache).await?;
+    health_check.readiness.set_ready(false);
+    warm_up_keys(args, &pool, &tenant_key_cache).await?;
+    health_check.readiness.set_ready(true);
     let mut immedially_poll_more_work = false;
     loop {
         // only if previous iteration had no work done do the wait
         if !immedially_poll_more_work {
             tokio::select! {
-                _ = listener.try_recv() => {
-                    WORK_ITEMS_NOTIFICATIONS_COUNTER.inc();
-                    info!(target: "tfhe_worker", "Received work_available notification from postgres");
+                event = listener.recv_coalesced(Duration::from_millis(args.notification_debounce_ms)) => match event.event {
+                    ListenerEvent::Notification(_) => {
+                        WORK_ITEMS_NOTIFICATIONS_COUNTER.inc();
+                        info!(target: "tfhe_worker", count = event.count, "Received work_available notifications from postgres");
+                    }
+                    ListenerEvent::Reconnected => {
+                        info!(target: "tfhe_worker", "Listener reconnected, polling for missed work");
+                    }
                 },
                 _ = tokio::time::sleep(tokio::time::Duration::from_millis(args.worker_polling_interval_ms)) => {
                     WORK_ITEMS_POLL_COUNTER.inc();
@@ -119,6 +237,8 @@ async fn tfhe_worker_cycle(
         let mut transactions =
             query_for_work(args, &health_check, &mut trx, &tracer, &loop_ctx).await?;
         if transactions.is_empty() {
+            // Keeps the computations completed from persisted results, if any
+            trx.commit().await?;
             continue;
         } else {
             // We've fetched work, so we'll poll again without waiting
@@ -135,12 +255,23 @@ async fn tfhe_worker_cycle(
         .await?;
 
         // Execute transactions segregated by tenant
+        let mut webhook_events = vec![];
         for (tenant_id, ref mut tenant_txs) in transactions.iter_mut() {
-            let mut tx_graph = build_transaction_graph_and_execute(
+            let (key_id, param_set) = tenant_key_cache
+                .write()
+                .await
+                .get(tenant_id)
+                .map_or((None, &DEFAULT_PARAM_SET), |keys| {
+                    (keys.key_id.clone(), keys.param_set)
+                });
+            let (mut tx_graph, key_mismatches, persisted) = build_transaction_graph_and_execute(
                 tenant_id,
+                key_id.as_deref(),
+                param_set,
                 tenant_txs,
                 &tenant_key_cache,
                 &health_check,
+                result_writer.as_ref(),
                 &mut trx,
                 &tracer,
                 &loop_ctx,
@@ -148,7 +279,13 @@ async fn tfhe_worker_cycle(
             .await?;
             upload_transaction_graph_results(
                 tenant_id,
+                key_id.as_deref(),
+                param_set,
                 &mut tx_graph,
+                key_mismatches,
+                persisted,
+                webhook,
+                &mut webhook_events,
                 &mut trx,
                 &tracer,
                 &loop_ctx,
@@ -157,6 +294,9 @@ async fn tfhe_worker_cycle(
         }
         s.end();
         trx.commit().await?;
+        if let Some(webhook) = webhook {
+            webhook.export_all(webhook_events);
+        }
         let _guard = loop_ctx.attach();
         #[cfg(feature = "bench")]
         {
@@ -169,6 +309,56 @@ async fn tfhe_worker_cycle(
     }
 }
 
+/// Loads the keys of the tenants, as many as the key cache holds, and runs a
+/// canary operation with each before the worker reports ready, see
+/// [`fhevm_engine_common::warmup`].
+async fn warm_up_keys(
+    args: &crate::daemon_cli::Args,
+    pool: &sqlx::PgPool,
+    tenant_key_cache: &std::sync::Arc<tokio::sync::RwLock<lru::LruCache<i32, TfheTenantKeys>>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let tenant_ids = query!(
+        "SELECT tenant_id FROM tenants ORDER BY tenant_id LIMIT $1",
+        args.tenant_key_cache_size as i64,
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| row.tenant_id);
+    for tenant_id in tenant_ids {
+        let started_at = Instant::now();
+        populate_cache_with_tenant_keys(vec![tenant_id], pool, tenant_key_cache).await?;
+        let Some((sks, pks)) = tenant_key_cache
+            .write()
+            .await
+            .get(&tenant_id)
+            .map(|keys| (keys.sks.clone(), keys.pks.clone()))
+        else {
+            continue;
+        };
+        #[cfg(feature = "gpu")]
+        let gpu_pks = pks.clone();
+        tokio::task::spawn_blocking(move || warmup::run_canary(&sks, &pks)).await??;
+        #[cfg(feature = "gpu")]
+        {
+            let gpu_sks = tenant_key_cache
+                .write()
+                .await
+                .get(&tenant_id)
+                .map(|keys| keys.gpu_sks.clone())
+                .unwrap_or_default();
+            tokio::task::spawn_blocking(move || {
+                gpu_sks
+                    .iter()
+                    .try_for_each(|sks| warmup::run_gpu_canary(sks, &gpu_pks))
+            })
+            .await??;
+        }
+        warmup::observe_warm_up(&format!("tenant_{tenant_id}"), started_at);
+    }
+    Ok(())
+}
+
 async fn query_tenants_and_keys<'a>(
     transactions: &[(i32, Vec<TxNode>)],
     tenant_key_cache: &std::sync::Arc<tokio::sync::RwLock<lru::LruCache<i32, TfheTenantKeys>>>,
@@ -177,18 +367,21 @@ async fn query_tenants_and_keys<'a>(
     loop_ctx: &opentelemetry::Context,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let mut s = tracer.start_with_context("populate_key_cache", loop_ctx);
-    let mut tenants_to_query: BTreeSet<i32> = BTreeSet::new();
-    let mut keys_to_query: BTreeSet<i32> = BTreeSet::new();
+    let tenants_to_query = transactions
+        .iter()
+        .map(|(tenant_id, _)| *tenant_id)
+        .collect::<BTreeSet<i32>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    // Compute with the current key of the tenants, stale keys are loaded again
+    evict_stale_tenant_keys(&tenants_to_query, trx.as_mut(), tenant_key_cache).await?;
     let key_cache = tenant_key_cache.read().await;
-    for (tenant_id, _) in transactions.iter() {
-        let _ = tenants_to_query.insert(*tenant_id);
-        if !key_cache.contains(tenant_id) {
-            let _ = keys_to_query.insert(*tenant_id);
-        }
-    }
+    let keys_to_query = tenants_to_query
+        .iter()
+        .filter(|tenant_id| !key_cache.contains(tenant_id))
+        .copied()
+        .collect::<Vec<_>>();
     drop(key_cache);
-    let tenants_to_query = tenants_to_query.into_iter().collect::<Vec<_>>();
-    let keys_to_query = keys_to_query.into_iter().collect::<Vec<_>>();
     s.set_attribute(KeyValue::new("keys_to_query", keys_to_query.len() as i64));
     s.set_attribute(KeyValue::new(
         "tenants_to_query",
@@ -199,27 +392,35 @@ async fn query_tenants_and_keys<'a>(
     Ok(())
 }
 
+/// Type, serialized ciphertext, key id and parameter set of an input
+/// ciphertext
+type CiphertextRow = (i16, Vec<u8>, Option<Vec<u8>>, Option<String>);
+
 async fn query_ciphertexts<'a>(
     cts_to_query: &[Vec<u8>],
     tenant_id: i32,
     trx: &mut sqlx::Transaction<'a, Postgres>,
     tracer: &opentelemetry::global::BoxedTracer,
     loop_ctx: &opentelemetry::Context,
-) -> Result<HashMap<Vec<u8>, (i16, Vec<u8>)>, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<HashMap<Vec<u8>, CiphertextRow>, Box<dyn std::error::Error + Send + Sync>> {
     let mut s = tracer.start_with_context("query_ciphertext_batch", loop_ctx);
     s.set_attribute(KeyValue::new("cts_to_query", cts_to_query.len() as i64));
     // TODO: select all the ciphertexts where they're contained in the tuples
-    let ciphertexts_rows = query!(
-        "
-                SELECT tenant_id, handle, ciphertext, ciphertext_type
+    let ciphertexts_rows = timed_query(
+        "tfhe_worker.query_ciphertexts",
+        &QueryPolicy::default(),
+        query!(
+            "
+                SELECT tenant_id, handle, ciphertext, ciphertext_type, key_id, param_set
                 FROM ciphertexts
                 WHERE tenant_id = $1
                 AND handle = ANY($2::BYTEA[])
             ",
-        &tenant_id,
-        &cts_to_query
+            &tenant_id,
+            &cts_to_query
+        )
+        .fetch_all(trx.as_mut()),
     )
-    .fetch_all(trx.as_mut())
     .await
     .map_err(|err| {
         error!(target: "tfhe_worker", { error = %err }, "error while querying ciphertexts");
@@ -228,12 +429,17 @@ async fn query_ciphertexts<'a>(
 
     s.end();
     // index ciphertexts in hashmap
-    let mut ciphertext_map: HashMap<Vec<u8>, (i16, Vec<u8>)> =
+    let mut ciphertext_map: HashMap<Vec<u8>, CiphertextRow> =
         HashMap::with_capacity(ciphertexts_rows.len());
     for row in &ciphertexts_rows {
         let _ = ciphertext_map.insert(
             row.handle.clone(),
-            (row.ciphertext_type, row.ciphertext.clone()),
+            (
+                row.ciphertext_type,
+                row.ciphertext.clone(),
+                row.key_id.clone(),
+                row.param_set.clone(),
+            ),
         );
     }
     Ok(ciphertext_map)
@@ -284,6 +490,199 @@ async fn update_uncomputable_handles<'a>(
     Ok(())
 }
 
+/// A computation claimed by the worker
+pub(crate) struct WorkItem {
+    tenant_id: i32,
+    output_handle: Vec<u8>,
+    dependencies: Vec<Vec<u8>>,
+    fhe_operation: i16,
+    is_scalar: bool,
+    is_allowed: bool,
+    dependence_chain_id: Option<Vec<u8>>,
+    pub(crate) transaction_id: Vec<u8>,
+}
+
+/// Claims the computations in weighted fair order across their callers.
+///
+/// Each pending computation is ranked within its caller by schedule order,
+/// and computations are claimed by increasing rank divided by the weight of
+/// their caller, i.e. their virtual finish time in weighted fair queuing. A
+/// contract with thousands of queued operations then gets its share of each
+/// batch, while the single operation of another contract is claimed in the
+/// next one.
+///
+/// Only the first `work_items_batch_size` pending computations of each caller
+/// can be claimed, so only those are ranked: the callers are enumerated with a
+/// skip scan of the caller index, and the computations of each are read from
+/// it in schedule order, instead of ranking the whole backlog.
+pub(crate) async fn query_for_work_fair<'a>(
+    work_items_batch_size: i32,
+    caller_weights: &[CallerWeight],
+    trx: &mut sqlx::Transaction<'a, Postgres>,
+) -> Result<Vec<WorkItem>, sqlx::Error> {
+    let (callers, weights): (Vec<_>, Vec<_>) = caller_weights
+        .iter()
+        .map(|w| (w.caller.clone(), w.weight))
+        .unzip();
+    timed_query(
+        "tfhe_worker.query_for_work_fair",
+        &QueryPolicy::default(),
+        query_as!(
+            WorkItem,
+            "
+WITH RECURSIVE callers AS (
+  (
+    SELECT caller
+    FROM computations
+    WHERE is_completed = FALSE
+      AND is_error = FALSE
+      AND is_allowed = TRUE
+      AND caller IS NOT NULL
+    ORDER BY caller
+    LIMIT 1
+  )
+  UNION ALL
+  SELECT (
+    SELECT c.caller
+    FROM computations c
+    WHERE c.is_completed = FALSE
+      AND c.is_error = FALSE
+      AND c.is_allowed = TRUE
+      AND c.caller > callers.caller
+    ORDER BY c.caller
+    LIMIT 1
+  )
+  FROM callers
+  WHERE callers.caller IS NOT NULL
+),
+pending AS (
+  SELECT callers.caller, p.transaction_id, p.schedule_order
+  FROM callers
+  CROSS JOIN LATERAL (
+    SELECT c.transaction_id, c.schedule_order
+    FROM computations c
+    WHERE c.is_completed = FALSE
+      AND c.is_error = FALSE
+      AND c.is_allowed = TRUE
+      AND c.caller = callers.caller
+    ORDER BY c.schedule_order
+    LIMIT $1
+  ) p
+  UNION ALL
+  (
+    SELECT NULL, transaction_id, schedule_order
+    FROM computations
+    WHERE is_completed = FALSE
+      AND is_error = FALSE
+      AND is_allowed = TRUE
+      AND caller IS NULL
+    ORDER BY schedule_order
+    LIMIT $1
+  )
+),
+ranked AS (
+  SELECT
+    caller,
+    transaction_id,
+    schedule_order,
+    ROW_NUMBER() OVER (PARTITION BY caller ORDER BY schedule_order) AS caller_rank
+  FROM pending
+),
+selected_computations AS (
+  SELECT DISTINCT
+    c.transaction_id
+  FROM (
+    SELECT r.transaction_id
+    FROM ranked r
+    LEFT JOIN unnest($2::BYTEA[], $3::FLOAT8[]) AS w(caller, weight)
+      ON w.caller = r.caller
+    ORDER BY r.caller_rank / COALESCE(w.weight, 1.0), r.schedule_order
+    LIMIT $1
+  ) as c
+)
+-- Acquire all computations from this transaction set
+SELECT
+  c.tenant_id,
+  c.output_handle,
+  c.dependencies,
+  c.fhe_operation,
+  c.is_scalar,
+  c.is_allowed,
+  c.dependence_chain_id,
+  c.transaction_id
+FROM computations c
+JOIN selected_computations sc
+  ON  c.transaction_id = sc.transaction_id
+FOR UPDATE SKIP LOCKED",
+            work_items_batch_size as i64,
+            &callers,
+            &weights,
+        )
+        .fetch_all(trx.as_mut()),
+    )
+    .await
+}
+
+/// Completes the claimed computations whose result was persisted by a batch
+/// that did not finish, e.g. on a crash, and removes them from the work. Their
+/// consumers read the persisted results like any other input.
+async fn complete_persisted_computations<'a>(
+    the_work: &mut Vec<WorkItem>,
+    trx: &mut sqlx::Transaction<'a, Postgres>,
+) -> Result<(), sqlx::Error> {
+    let (tenant_ids, handles): (Vec<_>, Vec<_>) = the_work
+        .iter()
+        .filter(|w| w.is_allowed)
+        .map(|w| (w.tenant_id, w.output_handle.clone()))
+        .unzip();
+    let persisted: HashSet<(i32, Vec<u8>)> = query!(
+        "
+        SELECT c.tenant_id, c.handle
+          FROM ciphertexts c
+          JOIN unnest($1::INTEGER[], $2::BYTEA[]) AS w(tenant_id, handle)
+            ON c.tenant_id = w.tenant_id AND c.handle = w.handle
+         WHERE c.ciphertext_version = $3
+        ",
+        &tenant_ids,
+        &handles,
+        current_ciphertext_version(),
+    )
+    .fetch_all(trx.as_mut())
+    .await?
+    .into_iter()
+    .map(|row| (row.tenant_id, row.handle))
+    .collect();
+    if persisted.is_empty() {
+        return Ok(());
+    }
+
+    let (recovered, remaining): (Vec<_>, Vec<_>) = std::mem::take(the_work)
+        .into_iter()
+        .partition(|w| w.is_allowed && persisted.contains(&(w.tenant_id, w.output_handle.clone())));
+    *the_work = remaining;
+    let (tenant_ids, (handles, txn_ids)): (Vec<_>, (Vec<_>, Vec<_>)) = recovered
+        .into_iter()
+        .map(|w| (w.tenant_id, (w.output_handle, w.transaction_id)))
+        .unzip();
+    query!(
+        "
+        UPDATE computations
+        SET is_completed = true, completed_at = CURRENT_TIMESTAMP
+        WHERE (tenant_id, output_handle, transaction_id) IN (
+            SELECT * FROM unnest($1::INTEGER[], $2::BYTEA[], $3::BYTEA[])
+        )
+        ",
+        &tenant_ids,
+        &handles,
+        &txn_ids
+    )
+    .execute(trx.as_mut())
+    .await?;
+    WORK_ITEMS_RECOVERED_COUNTER.inc_by(handles.len() as u64);
+    info!(target: "tfhe_worker", { count = handles.len() }, "Completed work items from persisted results");
+    Ok(())
+}
+
 async fn query_for_work<'a>(
     args: &crate::daemon_cli::Args,
     health_check: &crate::health_check::HealthCheck,
@@ -293,8 +692,15 @@ async fn query_for_work<'a>(
 ) -> Result<Vec<(i32, Vec<TxNode>)>, Box<dyn std::error::Error + Send + Sync>> {
     // This query locks our work items so other worker doesn't select them.
     let mut s = tracer.start_with_context("query_work_items", loop_ctx);
-    let the_work = query!(
-        "
+    let mut the_work = if args.fair_scheduling {
+        query_for_work_fair(args.work_items_batch_size, &args.caller_weights, trx).await
+    } else {
+        timed_query(
+            "tfhe_worker.query_for_work",
+            &QueryPolicy::default(),
+            query_as!(
+                WorkItem,
+                "
 WITH selected_computations AS (
   (
     SELECT DISTINCT
@@ -324,10 +730,12 @@ FROM computations c
 JOIN selected_computations sc
   ON  c.transaction_id = sc.transaction_id
 FOR UPDATE SKIP LOCKED            ",
-        args.work_items_batch_size as i32,
-    )
-    .fetch_all(trx.as_mut())
-    .await
+                args.work_items_batch_size as i32,
+            )
+            .fetch_all(trx.as_mut()),
+        )
+        .await
+    }
     .map_err(|err| {
         error!(target: "tfhe_worker", { error = %err }, "error while querying work items");
         err
@@ -335,6 +743,33 @@ FOR UPDATE SKIP LOCKED            ",
     s.set_attribute(KeyValue::new("count", the_work.len() as i64));
     s.end();
     health_check.update_db_access();
+    if args.incremental_results && !the_work.is_empty() {
+        complete_persisted_computations(&mut the_work, trx).await?;
+    }
+    // Reported if the worker crashes while processing the batch
+    diagnostics::set(
+        "batch_transactions",
+        the_work
+            .iter()
+            .map(|w| {
+                format!(
+                    "tenant {} transaction 0x{}",
+                    w.tenant_id,
+                    hex::encode(&w.transaction_id)
+                )
+            })
+            .unique(),
+    );
+    diagnostics::set(
+        "claimed_computations",
+        the_work.iter().map(|w| {
+            format!(
+                "tenant {} handle 0x{}",
+                w.tenant_id,
+                hex::encode(&w.output_handle)
+            )
+        }),
+    );
     if the_work.is_empty() {
         health_check.update_activity();
         return Ok(vec![]);
@@ -407,26 +842,81 @@ FOR UPDATE SKIP LOCKED            ",
     Ok(transactions)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn build_transaction_graph_and_execute<'a>(
     tenant_id: &i32,
+    key_id: Option<&[u8]>,
+    param_set: &'static ParamSet,
     tenant_txs: &mut Vec<TxNode>,
     tenant_key_cache: &std::sync::Arc<tokio::sync::RwLock<lru::LruCache<i32, TfheTenantKeys>>>,
     health_check: &crate::health_check::HealthCheck,
+    result_writer: Option<&WriteBatcher<PersistedResult>>,
     trx: &mut sqlx::Transaction<'a, Postgres>,
     tracer: &opentelemetry::global::BoxedTracer,
     loop_ctx: &opentelemetry::Context,
-) -> Result<DFTxGraph, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<(DFTxGraph, KeyMismatches, PersistedResults), Box<dyn std::error::Error + Send + Sync>>
+{
     let mut tx_graph = DFTxGraph::default();
     tx_graph.build(tenant_txs)?;
     let cts_to_query = tx_graph.needed_map.keys().cloned().collect::<Vec<_>>();
     let ciphertext_map =
         query_ciphertexts(&cts_to_query, *tenant_id, trx, tracer, loop_ctx).await?;
-    for (handle, (ct_type, mut ct)) in ciphertext_map.into_iter() {
-        tx_graph.add_input(
-            &handle,
-            &DFGTxInput::Compressed((ct_type, std::mem::take(&mut ct))),
-        )?;
+    let mut key_mismatches = vec![];
+    for (handle, (ct_type, ct, ct_key_id, ct_param_set)) in ciphertext_map.into_iter() {
+        // Inputs under another key or parameter set are withheld, their
+        // consumers are rejected
+        let message = if !key_version::is_compatible(ct_key_id.as_deref(), key_id) {
+            Some(key_version::mismatch_message(
+                "tfhe_worker",
+                ct_key_id.as_deref(),
+                key_id,
+            ))
+        } else if !param_set.is_compatible(ct_param_set.as_deref()) {
+            Some(param_set.mismatch_message("tfhe_worker", ct_param_set.as_deref()))
+        } else {
+            None
+        };
+        if let Some(message) = message {
+            warn!(target: "tfhe_worker", { tenant_id = tenant_id, handle = format!("0x{}", hex::encode(&handle)), error = %message }, "rejecting input ciphertext");
+            key_mismatches.push((handle, message));
+            continue;
+        }
+        // Inputs are shared by every transaction that consumes them, the
+        // buffer is moved once and then reference counted.
+        tx_graph.add_input(&handle, &DFGTxInput::Compressed((ct_type, Bytes::from(ct))))?;
     }
+    // Results are written as they are computed, if enabled
+    let mut result_sender = None;
+    let mut result_forwarder = None;
+    if let Some(result_writer) = result_writer {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let result_writer = result_writer.clone();
+        let tenant_id = *tenant_id;
+        let key_id = key_id.map(|key_id| key_id.to_vec());
+        result_sender = Some(sender);
+        result_forwarder = Some(tokio::spawn(async move {
+            let mut writes = vec![];
+            while let Some(result) = receiver.recv().await {
+                let handle = result.handle.clone();
+                let write = result_writer
+                    .write(PersistedResult {
+                        tenant_id,
+                        key_id: key_id.clone(),
+                        param_set,
+                        result,
+                    })
+                    .await;
+                writes.push((handle, write));
+            }
+            writes
+        }));
+    }
+    let (export_sender, export_receiver) = if crate::dag_export::is_enabled() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        (Some(sender), Some(receiver))
+    } else {
+        (None, None)
+    };
     // Execute the DFG with the current tenant's keys
     let mut s_compute = tracer.start_with_context("compute_fhe_ops", loop_ctx);
     {
@@ -442,19 +932,60 @@ async fn build_transaction_graph_and_execute<'a>(
             keys.gpu_sks.clone(),
             health_check.activity_heartbeat.clone(),
         );
+        if let Some(sender) = result_sender {
+            sched = sched.with_result_sender(sender);
+        }
+        if let Some(sender) = export_sender {
+            sched = sched.with_graph_export(sender);
+        }
         sched.schedule(loop_ctx).await?;
     }
+    // The scheduler is dropped, all the graphs are queued
+    if let Some(mut export_receiver) = export_receiver {
+        while let Ok(export) = export_receiver.try_recv() {
+            crate::dag_export::record(export);
+        }
+    }
     s_compute.end();
-    Ok(tx_graph)
+    // The scheduler is dropped with its sender, the forwarder ends once all
+    // the results are queued
+    let mut persisted = PersistedResults::new();
+    if let Some(result_forwarder) = result_forwarder {
+        for (handle, write) in result_forwarder.await? {
+            match write.committed().await {
+                Ok(()) => {
+                    RESULTS_PERSISTED_COUNTER.inc();
+                    persisted.insert(handle);
+                }
+                // Written again with the upload
+                Err(err) => {
+                    warn!(target: "tfhe_worker", { tenant_id = tenant_id, handle = format!("0x{}", hex::encode(&handle)), error = %err }, "failed to persist result")
+                }
+            }
+        }
+    }
+    Ok((tx_graph, key_mismatches, persisted))
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn upload_transaction_graph_results<'a>(
     tenant_id: &i32,
+    key_id: Option<&[u8]>,
+    param_set: &ParamSet,
     tx_graph: &mut DFTxGraph,
+    key_mismatches: KeyMismatches,
+    persisted: PersistedResults,
+    webhook: Option<&WebhookExporter>,
+    webhook_events: &mut Vec<WebhookEvent>,
     trx: &mut sqlx::Transaction<'a, Postgres>,
     tracer: &opentelemetry::global::BoxedTracer,
     loop_ctx: &opentelemetry::Context,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Exported once the work transaction is committed
+    let export_completed =
+        webhook.is_some_and(|webhook| webhook.wants(WebhookEventType::ComputationCompleted));
+    let export_failed =
+        webhook.is_some_and(|webhook| webhook.wants(WebhookEventType::ComputationFailed));
     // Get computation results
     let graph_results = tx_graph.get_results();
 
@@ -466,6 +997,12 @@ async fn upload_transaction_graph_results<'a>(
     for result in graph_results.into_iter() {
         match result.compressed_ct {
             Ok((db_type, db_bytes)) => {
+                handles_to_update.push((result.handle.clone(), result.transaction_id.clone()));
+                WORK_ITEMS_PROCESSED_COUNTER.inc();
+                if persisted.contains(&result.handle) {
+                    buffer_pool::put(db_bytes);
+                    continue;
+                }
                 cts_to_insert.push((
                     *tenant_id,
                     (
@@ -473,8 +1010,6 @@ async fn upload_transaction_graph_results<'a>(
                         (db_bytes, (current_ciphertext_version(), db_type)),
                     ),
                 ));
-                handles_to_update.push((result.handle.clone(), result.transaction_id.clone()));
-                WORK_ITEMS_PROCESSED_COUNTER.inc();
             }
             Err(mut err) => {
                 let cerr: Box<dyn std::error::Error + Send + Sync> =
@@ -550,6 +1085,14 @@ async fn upload_transaction_graph_results<'a>(
                 .execute(trx.as_mut())
                 .await?;
                 s.end();
+                if export_failed {
+                    webhook_events.push(WebhookEvent::ComputationFailed {
+                        tenant_id: *tenant_id,
+                        handle: format!("0x{}", hex::encode(&result.handle)),
+                        transaction_id: format!("0x{}", hex::encode(&result.transaction_id)),
+                        error: err_string,
+                    });
+                }
             }
         }
     }
@@ -572,16 +1115,18 @@ async fn upload_transaction_graph_results<'a>(
     ) = cts_to_insert.into_iter().unzip();
     let _ = query!(
 			"
-                    INSERT INTO ciphertexts(tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type)
-                    SELECT * FROM UNNEST($1::INTEGER[], $2::BYTEA[], $3::BYTEA[], $4::SMALLINT[], $5::SMALLINT[])
+                    INSERT INTO ciphertexts(tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type, key_id, param_set)
+                    SELECT *, $6::BYTEA, $7::TEXT FROM UNNEST($1::INTEGER[], $2::BYTEA[], $3::BYTEA[], $4::SMALLINT[], $5::SMALLINT[])
                     ON CONFLICT (tenant_id, handle, ciphertext_version) DO NOTHING
                     ",
-		&tenant_ids, &handles, &ciphertexts, &ciphertext_versions, &ciphertext_types)
+		&tenant_ids, &handles, &ciphertexts, &ciphertext_versions, &ciphertext_types, key_id, param_set.name)
 			.execute(trx.as_mut())
 			.await.map_err(|err| {
                     error!(target: "tfhe_worker", { tenant_id = *tenant_id, error = %err }, "error while inserting new ciphertexts");
                     err
                 })?;
+    // The serialized ciphertexts are written, their buffers can be reused
+    ciphertexts.into_iter().for_each(buffer_pool::put);
     // Notify all workers that new ciphertext is inserted
     // For now, it's only the SnS workers that are listening for these events
     let _ = sqlx::query!("SELECT pg_notify($1, '')", EVENT_CIPHERTEXT_COMPUTED)
@@ -619,7 +1164,51 @@ async fn upload_transaction_graph_results<'a>(
                 })?;
 
     s.end();
+    if export_completed {
+        webhook_events.extend(
+            handles_vec
+                .iter()
+                .zip(&txn_ids_vec)
+                .map(|(handle, txn_id)| WebhookEvent::ComputationCompleted {
+                    tenant_id: *tenant_id,
+                    handle: format!("0x{}", hex::encode(handle)),
+                    transaction_id: format!("0x{}", hex::encode(txn_id)),
+                }),
+        );
+    }
 
     update_uncomputable_handles(uncomputable, *tenant_id, trx, tracer, loop_ctx).await?;
+    reject_key_mismatches(key_mismatches, *tenant_id, trx).await?;
+    Ok(())
+}
+
+/// Marks the computations consuming ciphertexts under another key or parameter
+/// set as failed, see [`fhevm_engine_common::key_version`] and
+/// [`fhevm_engine_common::param_set`]. This runs after the results are
+/// uploaded, so that the key mismatch replaces their missing inputs error.
+async fn reject_key_mismatches<'a>(
+    key_mismatches: KeyMismatches,
+    tenant_id: i32,
+    trx: &mut sqlx::Transaction<'a, Postgres>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if key_mismatches.is_empty() {
+        return Ok(());
+    }
+    let (handles, messages): (Vec<_>, Vec<_>) = key_mismatches.into_iter().unzip();
+    let _ = query!(
+        "
+        UPDATE computations c
+           SET is_error = true, error_message = m.message
+          FROM unnest($2::BYTEA[], $3::TEXT[]) AS m(handle, message)
+         WHERE c.tenant_id = $1
+           AND c.is_completed = FALSE
+           AND m.handle = ANY(c.dependencies)
+        ",
+        tenant_id,
+        &handles,
+        &messages
+    )
+    .execute(trx.as_mut())
+    .await?;
     Ok(())
 }
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use fhevm_engine_common::param_set::ParamSet;
 use fhevm_engine_common::types::FhevmError;
 use scheduler::dfg::types::SchedulerError;
 
@@ -191,6 +192,10 @@ pub struct TfheTenantKeys {
     pub chain_id: i64,
     pub verifying_contract_address: String,
     pub acl_contract_address: String,
+    /// KMS key id of the tenant, see [`fhevm_engine_common::key_version`]
+    pub key_id: Option<Vec<u8>>,
+    /// Parameters of the keys, see [`fhevm_engine_common::param_set`]
+    pub param_set: &'static ParamSet,
     pub sks: tfhe::ServerKey,
     #[cfg(feature = "gpu")]
     pub csks: tfhe::CompressedServerKey,
@@ -1,6 +1,8 @@
 use std::collections::{BTreeSet, HashMap, HashSet};
 
+use fhevm_engine_common::db_schema;
 use fhevm_engine_common::types::{FhevmError, SupportedFheOperations};
+use sqlx::postgres::PgConnectOptions;
 
 #[cfg(test)]
 use crate::server::tfhe_worker::AsyncComputationInput;
@@ -175,6 +177,11 @@ pub fn db_url(args: &crate::daemon_cli::Args) -> String {
     std::env::var("DATABASE_URL").expect("DATABASE_URL is undefined")
 }
 
+/// Connection options of the database, using the schema of the host chain if any.
+pub fn db_connect_options(args: &crate::daemon_cli::Args) -> Result<PgConnectOptions, sqlx::Error> {
+    db_schema::connect_options(&db_url(args), args.database_schema.as_deref())
+}
+
 #[test]
 fn test_invalid_handle_too_short() {
     let comp = vec![AsyncComputation {
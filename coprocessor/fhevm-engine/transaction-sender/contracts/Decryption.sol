@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: BSD-3-Clause-Clear
+pragma solidity ^0.8.24;
+
+/// @title Decryption smart contract
+/// @dev sources:
+///      - github.com/zama-ai/fhevm/blob/main/gateway-contracts/contracts/Decryption.sol
+///      - github.com/zama-ai/fhevm/blob/main/gateway-contracts/contracts/interfaces/IDecryption.sol
+/// @notice This contract is a mock of the Decryption contract from L2.
+contract Decryption {
+    error KmsNodeAlreadySigned(uint256 decryptionId, address signer);
+    error DecryptionNotRequested(uint256 decryptionId);
+
+    event PublicDecryptionResponse(uint256 indexed decryptionId, bytes decryptedResult, bytes[] signatures, bytes extraData);
+    event UserDecryptionResponse(
+        uint256 indexed decryptionId,
+        uint256 indexShare,
+        bytes userDecryptedShare,
+        bytes signature,
+        bytes extraData
+    );
+
+    bool alreadySignedRevert;
+
+    constructor(bool _alreadySignedRevert) {
+        alreadySignedRevert = _alreadySignedRevert;
+    }
+
+    function publicDecryptionResponse(
+        uint256 decryptionId,
+        bytes calldata decryptedResult,
+        bytes calldata signature,
+        bytes calldata extraData
+    ) public {
+        if (alreadySignedRevert) {
+            revert KmsNodeAlreadySigned(decryptionId, msg.sender);
+        }
+        bytes[] memory signatures = new bytes[](1);
+        signatures[0] = signature;
+        emit PublicDecryptionResponse(decryptionId, decryptedResult, signatures, extraData);
+    }
+
+    function userDecryptionResponse(
+        uint256 decryptionId,
+        bytes calldata userDecryptedShare,
+        bytes calldata signature,
+        bytes calldata extraData
+    ) public {
+        if (alreadySignedRevert) {
+            revert KmsNodeAlreadySigned(decryptionId, msg.sender);
+        }
+        emit UserDecryptionResponse(decryptionId, 0, userDecryptedShare, signature, extraData);
+    }
+}
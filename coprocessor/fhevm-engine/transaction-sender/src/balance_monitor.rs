@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use alloy::{network::Ethereum, primitives::Address, providers::Provider};
+use fhevm_engine_common::error::FhevmEngineError;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::{
+    metrics::{ACCOUNT_BALANCE, SENDING_PAUSED},
+    nonce_managed_provider::NonceManagedProvider,
+    ConfigSettings, REVIEW,
+};
+
+/// Periodically reads the balance of the accounts of the sender and pauses sending while the
+/// account paying the fees is below `balance_pause_threshold`. The operations stop picking work
+/// while paused, see [`wait_until_resumed`], so the queues are left untouched instead of failing
+/// with insufficient funds.
+pub struct BalanceMonitor<P: Provider<Ethereum> + Clone + 'static> {
+    provider: NonceManagedProvider<P>,
+    /// Account paying the fees, the thresholds apply to it
+    payer_address: Address,
+    /// Other accounts whose balance is only exported, e.g. the signer of user operations
+    other_addresses: Vec<Address>,
+    conf: ConfigSettings,
+    paused: Arc<watch::Sender<bool>>,
+    cancel_token: CancellationToken,
+}
+
+impl<P: Provider<Ethereum> + Clone + 'static> BalanceMonitor<P> {
+    pub fn new(
+        provider: NonceManagedProvider<P>,
+        payer_address: Address,
+        other_addresses: Vec<Address>,
+        conf: ConfigSettings,
+        paused: Arc<watch::Sender<bool>>,
+        cancel_token: CancellationToken,
+    ) -> Self {
+        Self {
+            provider,
+            payer_address,
+            other_addresses,
+            conf,
+            paused,
+            cancel_token,
+        }
+    }
+
+    pub(crate) async fn run(self) -> anyhow::Result<()> {
+        info!(
+            payer_address = %self.payer_address,
+            other_addresses = ?self.other_addresses,
+            warn_threshold = self.conf.balance_warn_threshold,
+            pause_threshold = self.conf.balance_pause_threshold,
+            interval = ?self.conf.balance_check_interval,
+            "Starting balance monitor"
+        );
+        loop {
+            // Monitoring is best effort, the sending state is kept on error.
+            if let Err(e) = self.check().await {
+                warn!(error = %e, "Balance check failed");
+            }
+
+            tokio::select! {
+                _ = self.cancel_token.cancelled() => {
+                    info!("Balance monitor stopping");
+                    break;
+                }
+                _ = tokio::time::sleep(self.conf.balance_check_interval) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Balance in wei, saturated to `u128`, exported as a gauge.
+    async fn balance_of(&self, address: Address) -> Result<u128, FhevmEngineError> {
+        let balance = self.provider.inner().get_balance(address).await?;
+        let balance = u128::try_from(balance).unwrap_or(u128::MAX);
+        ACCOUNT_BALANCE
+            .with_label_values(&[&address.to_string()])
+            .set(balance as f64);
+        Ok(balance)
+    }
+
+    /// Reads the balances and pauses or resumes sending.
+    pub async fn check(&self) -> Result<(), FhevmEngineError> {
+        for address in &self.other_addresses {
+            self.balance_of(*address).await?;
+        }
+
+        let balance = self.balance_of(self.payer_address).await?;
+        let pause = balance < self.conf.balance_pause_threshold;
+        let was_paused = self.paused.send_replace(pause);
+        SENDING_PAUSED.set(pause as i64);
+        match (was_paused, pause) {
+            (false, true) => error!(
+                action = REVIEW,
+                payer_address = %self.payer_address,
+                balance,
+                pause_threshold = self.conf.balance_pause_threshold,
+                "Balance below the pause threshold, sending is paused until topped up"
+            ),
+            (true, false) => info!(
+                payer_address = %self.payer_address,
+                balance,
+                "Balance topped up, sending is resumed"
+            ),
+            _ => {}
+        }
+        if !pause && balance < self.conf.balance_warn_threshold {
+            warn!(
+                payer_address = %self.payer_address,
+                balance,
+                warn_threshold = self.conf.balance_warn_threshold,
+                "Balance is low"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Waits until sending is resumed, immediately if it is not paused. Returns false if cancelled
+/// meanwhile.
+pub async fn wait_until_resumed(
+    paused: &mut watch::Receiver<bool>,
+    cancel_token: &CancellationToken,
+) -> bool {
+    tokio::select! {
+        _ = cancel_token.cancelled() => false,
+        resumed = paused.wait_for(|paused| !paused) => resumed.is_ok(),
+    }
+}
@@ -1,24 +1,36 @@
-use std::{str::FromStr, time::Duration};
+use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 
 use alloy::{
     network::EthereumWallet,
-    primitives::Address,
-    providers::{ProviderBuilder, WsConnect},
+    primitives::{Address, Bytes},
+    providers::ProviderBuilder,
     signers::{aws::AwsSigner, local::PrivateKeySigner, Signer},
     transports::http::reqwest::Url,
 };
 use anyhow::Context;
 use aws_config::BehaviorVersion;
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use tokio::signal::unix::{signal, SignalKind};
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, Level};
+use transaction_sender::scheduler::Schedule;
+use transaction_sender::signing_policy::StaticSigningPolicy;
+use transaction_sender::smoke_test::run_smoke_test;
+use transaction_sender::user_operation::{UserOperationConfig, UserOperationSender};
 use transaction_sender::{
     get_chain_id, http_server::HttpServer, make_abstract_signer, AbstractSigner, ConfigSettings,
-    FillersWithoutNonceManagement, NonceManagedProvider, TransactionSender,
+    FillersWithoutNonceManagement, NonceManagedProvider, QuorumPolicy, TransactionSender,
 };
 
+use fhevm_engine_common::column_encryption::ColumnEncryption;
+use fhevm_engine_common::db_query::QueryPolicy;
+use fhevm_engine_common::db_schema;
+use fhevm_engine_common::notification_bus::{BusConfig, BusKind, DEFAULT_SUBJECT_PREFIX};
+use fhevm_engine_common::secret::SecretString;
 use fhevm_engine_common::telemetry;
+use fhevm_engine_common::tls::{RpcTlsConfig, TlsVerify};
+use fhevm_engine_common::webhook::{Url, WebhookConfig, WebhookEventType, WebhookExporter};
+use fhevm_engine_common::write_batcher::WriteBatcherConfig;
 use humantime::parse_duration;
 
 #[derive(Parser, Debug, Clone, ValueEnum)]
@@ -27,9 +39,27 @@ enum SignerType {
     AwsKms,
 }
 
+#[derive(Parser, Debug, Clone, ValueEnum)]
+enum SubmissionBackend {
+    /// Transactions sent from the signer's account
+    Eoa,
+    /// ERC-4337 user operations of a smart account owned by the signer, sent to a bundler
+    UserOperation,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Sends a zero-value self-send through the regular nonce and receipt path, reports the signer,
+    /// nonce, gas and confirmation time, and exits. Fails if the transaction is not confirmed.
+    SmokeTest,
+}
+
 #[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 struct Conf {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(short, long)]
     input_verification_address: Address,
 
@@ -39,14 +69,93 @@ struct Conf {
     #[arg(short, long)]
     multichain_acl_address: Address,
 
+    /// MultichainACL address of a host chain, as CHAIN_ID=ADDRESS, overriding
+    /// --multichain-acl-address for the handles of that chain. Can be repeated.
+    #[arg(long, value_delimiter = ',', value_parser = parse_chain_address)]
+    multichain_acl_chain_address: Vec<(u64, Address)>,
+
+    /// Gateway Decryption contract address. Decryption responses are only sent when set.
+    #[arg(long)]
+    decryption_address: Option<Address>,
+
+    /// Gateway GatewayConfig contract address. When set, the public decryption quorum is checked
+    /// against its KMS signers and thresholds on startup.
+    #[arg(long)]
+    gateway_config_address: Option<Address>,
+
     #[arg(short, long)]
     gateway_url: Url,
 
+    /// PEM bundle of the CAs trusted for the Gateway node instead of the platform ones
+    #[arg(long)]
+    rpc_tls_ca_cert: Option<PathBuf>,
+
+    /// PEM certificate chain presented to the Gateway node, for mutual TLS
+    #[arg(long)]
+    rpc_tls_client_cert: Option<PathBuf>,
+
+    /// PEM private key of the client certificate
+    #[arg(long)]
+    rpc_tls_client_key: Option<PathBuf>,
+
+    /// Name sent as SNI and verified instead of the host of the Gateway URL
+    #[arg(long)]
+    rpc_tls_server_name: Option<String>,
+
+    /// Verification of the certificate of the Gateway node: full, ca-only (no name check) or none
+    #[arg(long, default_value = "full")]
+    rpc_tls_verify: TlsVerify,
+
+    /// Expected chain ID of the Gateway. When set, the sender does not start if the node reports
+    /// another one. Transactions are refused whenever the node reports another chain ID than the
+    /// one found on startup.
+    #[arg(long)]
+    gateway_chain_id: Option<u64>,
+
+    /// Interval between two checks of the chain ID reported by the node, before sending
+    #[arg(long, default_value = "5m", value_parser = parse_duration)]
+    chain_id_check_interval: Duration,
+
+    /// JSON file of the signing policy: allowed target contracts and method selectors, maximum
+    /// gas price and value. Transactions it denies are not signed.
+    #[arg(long)]
+    signing_policy: Option<String>,
+
     #[arg(short, long, value_enum, default_value = "private-key")]
     signer_type: SignerType,
 
     #[arg(short, long)]
-    private_key: Option<String>,
+    private_key: Option<SecretString>,
+
+    #[arg(long, value_enum, default_value = "eoa")]
+    submission_backend: SubmissionBackend,
+
+    /// Bundler RPC URL, required by the user-operation backend
+    #[arg(long)]
+    bundler_url: Option<Url>,
+
+    /// EntryPoint v0.7 contract address, required by the user-operation backend
+    #[arg(long)]
+    entry_point_address: Option<Address>,
+
+    /// Smart account sending the user operations, required by the user-operation backend. It must
+    /// be registered as the coprocessor transaction sender on the Gateway.
+    #[arg(long)]
+    smart_account_address: Option<Address>,
+
+    /// Paymaster sponsoring the user operations fees, if any
+    #[arg(long)]
+    paymaster_address: Option<Address>,
+
+    /// Data passed to the paymaster, hex encoded
+    #[arg(long)]
+    paymaster_data: Option<Bytes>,
+
+    #[arg(long, default_value = "60s", value_parser = parse_duration)]
+    user_operation_inclusion_timeout: Duration,
+
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    user_operation_poll_interval: Duration,
 
     #[arg(short, long)]
     database_url: Option<String>,
@@ -54,9 +163,91 @@ struct Conf {
     #[arg(long, default_value = "10")]
     database_pool_size: u32,
 
+    /// Database schema of the host chain, in the schema-per-chain layout
+    #[arg(long, value_parser = db_schema::parse_schema_name)]
+    database_schema: Option<String>,
+
     #[arg(long, default_value = "5")]
     database_polling_interval_secs: u16,
 
+    /// Delay during which the notifications following a first one wake an operation only once
+    #[arg(long, default_value = "0s", value_parser = parse_duration)]
+    database_notification_debounce: Duration,
+
+    /// Bus the operations are notified on: postgres (LISTEN/NOTIFY) or nats
+    #[arg(long, default_value = "postgres")]
+    message_bus: BusKind,
+
+    /// Server of the NATS message bus
+    #[arg(long)]
+    nats_url: Option<String>,
+
+    /// Prefix of the NATS subjects, followed by the database channel
+    #[arg(long, default_value = DEFAULT_SUBJECT_PREFIX)]
+    nats_subject_prefix: String,
+
+    /// Column key of the decryption responses, file:<path> or env:<variable> holding 32
+    /// hex-encoded bytes
+    #[arg(long)]
+    column_encryption_key: Option<String>,
+
+    /// Endpoint the confirmed decryption responses are POSTed to, disabled if unspecified
+    #[arg(long)]
+    webhook_url: Option<Url>,
+
+    /// HMAC key signing the webhook batches. If unspecified WEBHOOK_SECRET environment variable
+    /// is used, the batches are unsigned if both are unset
+    #[arg(long)]
+    webhook_secret: Option<SecretString>,
+
+    /// Event types exported to the webhook, comma separated, all if unspecified
+    #[arg(long, value_delimiter = ',')]
+    webhook_events: Vec<WebhookEventType>,
+
+    /// Maximum number of events of a webhook POST
+    #[arg(long, default_value_t = 100)]
+    webhook_batch_size: usize,
+
+    /// Maximum time an event waits for others before being POSTed
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    webhook_max_latency: Duration,
+
+    /// Attempts of a webhook POST before its events are dropped
+    #[arg(long, default_value_t = 5)]
+    webhook_max_attempts: u32,
+
+    /// Timeout of each attempt of a database query
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    database_query_timeout: Duration,
+
+    /// Attempts of a database query failing with a transient error
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    database_query_max_attempts: u32,
+
+    #[arg(long, default_value = "100ms", value_parser = parse_duration)]
+    database_query_retry_backoff: Duration,
+
+    /// Database queries taking longer are logged
+    #[arg(long, default_value = "1s", value_parser = parse_duration)]
+    database_slow_query_threshold: Duration,
+
+    /// Interval between two purges of the handled queue rows
+    #[arg(long, default_value = "10m", value_parser = parse_duration)]
+    purge_interval: Duration,
+
+    /// Schedule of the purges, replacing --purge-interval when set: a cron expression in UTC,
+    /// e.g. "30 3 * * *", @hourly, @daily or "@every 10m"
+    #[arg(long)]
+    purge_schedule: Option<Schedule>,
+
+    /// Maximum random delay added before each purge
+    #[arg(long, default_value = "0s", value_parser = parse_duration)]
+    purge_jitter: Duration,
+
+    /// Maximum number of rows deleted by a single statement of a purge
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    purge_batch_size: u32,
+
     #[arg(long, default_value = "verify_proof_responses")]
     verify_proof_resp_database_channel: String,
 
@@ -66,6 +257,9 @@ struct Conf {
     #[arg(long, default_value = "event_allowed_handle")]
     allow_handle_database_channel: String,
 
+    #[arg(long, default_value = "event_decryption_response")]
+    decryption_response_database_channel: String,
+
     #[arg(long, default_value = "128")]
     verify_proof_resp_batch_limit: u32,
 
@@ -75,15 +269,80 @@ struct Conf {
     #[arg(long, default_value = "true")]
     verify_proof_remove_after_max_retries: bool,
 
+    /// How long proofs are kept once sent or failed before being purged
+    #[arg(long, default_value = "24h", value_parser = parse_duration)]
+    verify_proof_retention: Duration,
+
+    /// Maximum number of proof response txns being sent at the same time. 0 means no limit.
+    #[arg(long, default_value = "32")]
+    verify_proof_resp_max_in_flight: u32,
+
     #[arg(long, default_value = "10")]
     add_ciphertexts_batch_limit: u32,
 
+    /// Maximum number of add ciphertext txns being sent at the same time. 0 means no limit.
+    #[arg(long, default_value = "32")]
+    add_ciphertexts_max_in_flight: u32,
+
     #[arg(long, default_value = "10")]
     allow_handle_batch_limit: u32,
 
     #[arg(long, default_value = "10")]
     allow_handle_max_retries: u32,
 
+    /// Maximum number of allow handle txns being sent at the same time. 0 means no limit.
+    #[arg(long, default_value = "32")]
+    allow_handle_max_in_flight: u32,
+
+    /// Number of allow handle txns sent before waiting for all of them to be marked as sent. A
+    /// failure stops the batch after its chunk. 0 means the whole batch
+    #[arg(long, default_value = "0")]
+    allow_handle_send_chunk_size: u32,
+
+    /// Maximum number of allowed handles marked as sent in a single transaction
+    #[arg(long, default_value = "100")]
+    allow_handle_write_batch_size: usize,
+
+    /// Maximum time an allowed handle waits for others before being marked as sent
+    #[arg(long, default_value = "10ms", value_parser = parse_duration)]
+    allow_handle_write_max_latency: Duration,
+
+    #[arg(long, default_value = "10")]
+    decryption_response_batch_limit: u32,
+
+    #[arg(long, default_value = "10")]
+    decryption_response_max_retries: u32,
+
+    /// Maximum number of decryption response txns being sent at the same time. 0 means no limit.
+    #[arg(long, default_value = "32")]
+    decryption_response_max_in_flight: u32,
+
+    /// KMS signers whose public decryption shares are sent once a threshold of them agree on the
+    /// result. Aggregation is disabled when empty.
+    #[arg(long, value_delimiter = ',')]
+    public_decryption_kms_signers: Vec<Address>,
+
+    /// Number of signers that must agree on a public decryption result. Defaults to a majority
+    /// of the KMS signers.
+    #[arg(long)]
+    public_decryption_threshold: Option<usize>,
+
+    /// JSON file with the versioned KMS signer sets and per-key threshold overrides of the public
+    /// decryption quorum. Replaces the KMS signers and threshold options when set.
+    #[arg(long)]
+    public_decryption_quorum_policy: Option<String>,
+
+    /// Give up on a public decryption if the threshold is not reached that long after its first
+    /// share.
+    #[arg(long, default_value = "5m", value_parser = parse_duration)]
+    public_decryption_share_timeout: Duration,
+
+    #[arg(long, default_value = "event_public_decryption_share")]
+    public_decryption_shares_database_channel: String,
+
+    #[arg(long, default_value = "100")]
+    public_decryption_aggregation_batch_limit: u32,
+
     #[arg(long, default_value = "15")]
     add_ciphertexts_max_retries: u32,
 
@@ -124,14 +383,66 @@ struct Conf {
     #[arg(long, default_value = "120", value_parser = clap::value_parser!(u32).range(100..))]
     gas_limit_overprovision_percent: u32,
 
+    /// Attempts of a gas estimation failing with a transient error.
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    gas_estimate_max_attempts: u32,
+
+    #[arg(long, default_value = "200ms", value_parser = parse_duration)]
+    gas_estimate_retry_backoff: Duration,
+
+    /// Gas limit of a transaction whose gas estimation fails transiently, when the method was
+    /// never estimated before. Not set by default.
+    #[arg(long)]
+    gas_limit_ceiling: Option<u64>,
+
     #[arg(long, default_value = "8s", value_parser = parse_duration)]
     graceful_shutdown_timeout: Duration,
 
+    /// Report the nonce as stuck if it doesn't move for that long while transactions are queued
+    /// behind it. 0s disables the check.
+    #[arg(long, default_value = "5m", value_parser = parse_duration)]
+    stuck_nonce_timeout: Duration,
+
+    /// Interval between two comparisons of the confirmed and pending nonces of the signer.
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    stuck_nonce_check_interval: Duration,
+
+    /// Replace a stuck nonce with a zero-value self-send with escalated fees.
+    #[arg(long, default_value = "false")]
+    stuck_nonce_auto_cancel: bool,
+
+    /// Fee bump in percent applied on each cancel attempt. Nodes usually require at least 110.
+    #[arg(long, default_value = "150", value_parser = clap::value_parser!(u32).range(111..))]
+    stuck_nonce_fee_bump_percent: u32,
+
+    /// Interval between two balance checks of the accounts. 0s disables the balance monitor.
+    #[arg(long, default_value = "60s", value_parser = parse_duration)]
+    balance_check_interval: Duration,
+
+    /// Balance in wei of the account paying the fees under which a low balance is reported.
+    /// 0 disables the warning.
+    #[arg(long, default_value = "0")]
+    balance_warn_threshold: u128,
+
+    /// Balance in wei of the account paying the fees under which sending is paused, leaving the
+    /// queues untouched until the account is topped up. 0 disables the pause.
+    #[arg(long, default_value = "0")]
+    balance_pause_threshold: u128,
+
+    /// Do not check at startup that the deployed contracts match the bindings
+    #[arg(long, default_value = "false")]
+    skip_bindings_check: bool,
+
     /// service name in OTLP traces
     #[arg(long, default_value = "txn-sender")]
     pub service_name: String,
 }
 
+fn parse_chain_address(value: &str) -> anyhow::Result<(u64, Address)> {
+    let (chain_id, address) = value.split_once('=').context("expected CHAIN_ID=ADDRESS")?;
+    Ok((chain_id.trim().parse()?, address.trim().parse()?))
+}
+
 fn install_signal_handlers(cancel_token: CancellationToken) -> anyhow::Result<()> {
     let mut sigint = signal(SignalKind::interrupt())?;
     let mut sigterm = signal(SignalKind::terminate())?;
@@ -161,11 +472,21 @@ async fn main() -> anyhow::Result<()> {
     let cancel_token = CancellationToken::new();
     install_signal_handlers(cancel_token.clone())?;
 
+    let rpc_tls = RpcTlsConfig {
+        ca_cert: conf.rpc_tls_ca_cert.clone(),
+        client_cert: conf.rpc_tls_client_cert.clone(),
+        client_key: conf.rpc_tls_client_key.clone(),
+        server_name: conf.rpc_tls_server_name.clone(),
+        verify: conf.rpc_tls_verify,
+    }
+    .build()?;
+
     // Try to get the chain ID until cancelled.
     let chain_id = tokio::select! {
         chain_id = get_chain_id(
             conf.gateway_url.clone(),
             conf.graceful_shutdown_timeout,
+            &rpc_tls,
         ) => chain_id,
 
         _ = cancel_token.cancelled() => {
@@ -173,6 +494,17 @@ async fn main() -> anyhow::Result<()> {
             return Ok(());
         }
     };
+    if let Some(expected_chain_id) = conf.gateway_chain_id {
+        if chain_id != expected_chain_id {
+            error!(
+                chain_id,
+                expected_chain_id,
+                gateway_url = %conf.gateway_url,
+                "Wrong chain, the Gateway node reports another chain ID"
+            );
+            anyhow::bail!("Gateway node reports chain ID {chain_id}, expected {expected_chain_id}");
+        }
+    }
 
     if !conf.service_name.is_empty() {
         if let Err(err) = telemetry::setup_otlp(&conf.service_name) {
@@ -189,7 +521,7 @@ async fn main() -> anyhow::Result<()> {
                     "Private key is required for PrivateKey signer"
                 ));
             }
-            let mut signer = PrivateKeySigner::from_str(conf.private_key.unwrap().trim())?;
+            let mut signer = PrivateKeySigner::from_str(conf.private_key.unwrap().expose().trim())?;
             signer.set_chain_id(Some(chain_id));
             abstract_signer = make_abstract_signer(signer);
         }
@@ -203,10 +535,34 @@ async fn main() -> anyhow::Result<()> {
         }
     }
     let wallet = EthereumWallet::new(abstract_signer.clone());
-    let database_url = match conf.database_url.clone() {
-        Some(url) => url,
-        None => std::env::var("DATABASE_URL").context("DATABASE_URL is undefined")?,
-    };
+    let signing_policy = conf
+        .signing_policy
+        .as_deref()
+        .map(StaticSigningPolicy::from_file)
+        .transpose()?;
+    let column_encryption = conf
+        .column_encryption_key
+        .as_deref()
+        .map(ColumnEncryption::from_secret)
+        .transpose()?
+        .unwrap_or_default();
+    let webhook = conf
+        .webhook_url
+        .clone()
+        .map(|url| {
+            WebhookExporter::spawn(WebhookConfig {
+                url,
+                secret: conf
+                    .webhook_secret
+                    .clone()
+                    .or_else(|| std::env::var("WEBHOOK_SECRET").ok().map(SecretString::from)),
+                event_types: conf.webhook_events.clone(),
+                max_batch_size: conf.webhook_batch_size,
+                max_latency: conf.webhook_max_latency,
+                max_attempts: conf.webhook_max_attempts,
+            })
+        })
+        .transpose()?;
 
     let provider = loop {
         if cancel_token.is_cancelled() {
@@ -216,11 +572,12 @@ async fn main() -> anyhow::Result<()> {
         match ProviderBuilder::default()
             .filler(FillersWithoutNonceManagement::default())
             .wallet(wallet.clone())
-            .connect_ws(
+            .connect_pubsub_with(
                 // Note here that max_retries and retry_interval apply to sending requests, not to initial connection.
                 // We assume they are set to big values such that when they are reached, the following `BackendGone` error
                 // means we can't move on and we would exit the whole sender.
-                WsConnect::new(conf.gateway_url.clone())
+                rpc_tls
+                    .connect(conf.gateway_url.clone())
                     .with_max_retries(conf.provider_max_retries)
                     .with_retry_interval(conf.provider_retry_interval),
             )
@@ -234,7 +591,8 @@ async fn main() -> anyhow::Result<()> {
                 break NonceManagedProvider::new(
                     inner_provider,
                     Some(wallet.default_signer().address()),
-                );
+                )
+                .with_chain_id_guard(chain_id, conf.chain_id_check_interval);
             }
             Err(e) => {
                 error!(
@@ -248,29 +606,152 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
+    let provider = match signing_policy {
+        Some(policy) => {
+            info!(policy = ?policy, "Signing policy enabled");
+            provider.with_signing_policy(Arc::new(policy))
+        }
+        None => provider,
+    };
+
+    let provider = match conf.submission_backend {
+        SubmissionBackend::Eoa => provider,
+        SubmissionBackend::UserOperation => {
+            let user_operation_conf = UserOperationConfig {
+                bundler_url: conf
+                    .bundler_url
+                    .clone()
+                    .context("--bundler-url is required for user operations")?,
+                entry_point_address: conf
+                    .entry_point_address
+                    .context("--entry-point-address is required for user operations")?,
+                smart_account_address: conf
+                    .smart_account_address
+                    .context("--smart-account-address is required for user operations")?,
+                paymaster_address: conf.paymaster_address,
+                paymaster_data: conf.paymaster_data.clone().unwrap_or_default(),
+                inclusion_timeout: conf.user_operation_inclusion_timeout,
+                poll_interval: conf.user_operation_poll_interval,
+            };
+            provider.with_user_operations(UserOperationSender::new(
+                user_operation_conf,
+                abstract_signer.clone(),
+            ))
+        }
+    };
+
+    if let Some(Command::SmokeTest) = conf.command {
+        let report = run_smoke_test(
+            &provider,
+            Duration::from_secs(conf.txn_receipt_timeout_secs as u64),
+            conf.required_txn_confirmations as u64,
+        )
+        .await?;
+        info!(
+            signer_address = %report.signer_address,
+            sender_address = %report.sender_address,
+            balance = %report.balance,
+            confirmed_nonce = report.confirmed_nonce,
+            pending_nonce = report.pending_nonce,
+            transaction_hash = %report.transaction_hash,
+            block_number = report.block_number,
+            gas_used = report.gas_used,
+            effective_gas_price = report.effective_gas_price,
+            confirmation_time = ?report.confirmation_time,
+            "Smoke test passed"
+        );
+        return Ok(());
+    }
+
+    let database_url = match conf.database_url.clone() {
+        Some(url) => url,
+        None => std::env::var("DATABASE_URL").context("DATABASE_URL is undefined")?,
+    };
+
+    let public_decryption_quorum = match &conf.public_decryption_quorum_policy {
+        Some(path) => QuorumPolicy::from_file(path)?,
+        None => {
+            let threshold = conf
+                .public_decryption_threshold
+                .unwrap_or(conf.public_decryption_kms_signers.len() / 2 + 1);
+            let policy = QuorumPolicy::single(conf.public_decryption_kms_signers, threshold);
+            policy.validate()?;
+            policy
+        }
+    };
+
     let config = ConfigSettings {
         database_url,
         database_pool_size: conf.database_pool_size,
+        database_schema: conf.database_schema,
         verify_proof_resp_db_channel: conf.verify_proof_resp_database_channel,
         add_ciphertexts_db_channel: conf.add_ciphertexts_database_channel,
         allow_handle_db_channel: conf.allow_handle_database_channel,
+        decryption_response_db_channel: conf.decryption_response_database_channel,
         verify_proof_resp_batch_limit: conf.verify_proof_resp_batch_limit,
         verify_proof_resp_max_retries: conf.verify_proof_resp_max_retries,
         verify_proof_remove_after_max_retries: conf.verify_proof_remove_after_max_retries,
+        verify_proof_retention: conf.verify_proof_retention,
+        verify_proof_resp_max_in_flight: conf.verify_proof_resp_max_in_flight,
         add_ciphertexts_batch_limit: conf.add_ciphertexts_batch_limit,
+        add_ciphertexts_max_in_flight: conf.add_ciphertexts_max_in_flight,
         db_polling_interval_secs: conf.database_polling_interval_secs,
+        db_notification_debounce: conf.database_notification_debounce,
+        message_bus: BusConfig {
+            kind: conf.message_bus,
+            nats_url: conf.nats_url,
+            subject_prefix: conf.nats_subject_prefix,
+        },
+        column_encryption,
+        webhook,
+        db_query: QueryPolicy {
+            timeout: conf.database_query_timeout,
+            max_attempts: conf.database_query_max_attempts,
+            retry_backoff: conf.database_query_retry_backoff,
+            slow_query_threshold: conf.database_slow_query_threshold,
+        },
+        purge_interval: conf.purge_interval,
+        purge_schedule: conf.purge_schedule.clone(),
+        purge_jitter: conf.purge_jitter,
+        purge_batch_size: conf.purge_batch_size,
         error_sleep_initial_secs: conf.error_sleep_initial_secs,
         error_sleep_max_secs: conf.error_sleep_max_secs,
         add_ciphertexts_max_retries: conf.add_ciphertexts_max_retries,
         allow_handle_batch_limit: conf.allow_handle_batch_limit,
         allow_handle_max_retries: conf.allow_handle_max_retries,
+        allow_handle_max_in_flight: conf.allow_handle_max_in_flight,
+        allow_handle_send_chunk_size: conf.allow_handle_send_chunk_size,
+        allow_handle_write_batch: WriteBatcherConfig {
+            max_batch_size: conf.allow_handle_write_batch_size,
+            max_latency: conf.allow_handle_write_max_latency,
+        },
+        multichain_acl_addresses: conf.multichain_acl_chain_address.iter().copied().collect(),
+        decryption_address: conf.decryption_address,
+        decryption_response_batch_limit: conf.decryption_response_batch_limit,
+        decryption_response_max_retries: conf.decryption_response_max_retries,
+        decryption_response_max_in_flight: conf.decryption_response_max_in_flight,
+        public_decryption_quorum,
+        gateway_config_address: conf.gateway_config_address,
+        public_decryption_share_timeout: conf.public_decryption_share_timeout,
+        public_decryption_shares_db_channel: conf.public_decryption_shares_database_channel,
+        public_decryption_aggregation_batch_limit: conf.public_decryption_aggregation_batch_limit,
         txn_receipt_timeout_secs: conf.txn_receipt_timeout_secs,
         required_txn_confirmations: conf.required_txn_confirmations,
         review_after_unlimited_retries: conf.review_after_unlimited_retries,
         http_server_port: conf.http_server_port,
         health_check_timeout: conf.health_check_timeout,
         gas_limit_overprovision_percent: conf.gas_limit_overprovision_percent,
+        gas_estimate_max_attempts: conf.gas_estimate_max_attempts,
+        gas_estimate_retry_backoff: conf.gas_estimate_retry_backoff,
+        gas_limit_ceiling: conf.gas_limit_ceiling,
         graceful_shutdown_timeout: conf.graceful_shutdown_timeout,
+        stuck_nonce_timeout: conf.stuck_nonce_timeout,
+        stuck_nonce_check_interval: conf.stuck_nonce_check_interval,
+        stuck_nonce_auto_cancel: conf.stuck_nonce_auto_cancel,
+        stuck_nonce_fee_bump_percent: conf.stuck_nonce_fee_bump_percent,
+        balance_check_interval: conf.balance_check_interval,
+        balance_warn_threshold: conf.balance_warn_threshold,
+        balance_pause_threshold: conf.balance_pause_threshold,
     };
 
     let transaction_sender = std::sync::Arc::new(
@@ -287,6 +768,10 @@ async fn main() -> anyhow::Result<()> {
         .await?,
     );
 
+    if !conf.skip_bindings_check {
+        transaction_sender.check_bindings().await?;
+    }
+
     let http_server = HttpServer::new(
         transaction_sender.clone(),
         conf.http_server_port,
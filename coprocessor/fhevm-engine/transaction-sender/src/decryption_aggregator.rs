@@ -0,0 +1,399 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use alloy::{
+    primitives::{Address, Bytes, FixedBytes, Signature, U256},
+    sol,
+    sol_types::{Eip712Domain, SolStruct, SolValue},
+};
+use fhevm_engine_common::{
+    error::FhevmEngineError,
+    notification_bus::{BusEvent, NotificationBus},
+    types::DecryptionResponseType,
+    utils::compact_hex,
+};
+use futures_util::FutureExt;
+use sqlx::{Pool, Postgres};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    metrics::{
+        PUBLIC_DECRYPTION_AGGREGATED_COUNTER, PUBLIC_DECRYPTION_INVALID_SHARE_COUNTER,
+        PUBLIC_DECRYPTION_TIMEOUT_COUNTER,
+    },
+    ConfigSettings, REVIEW,
+};
+
+sol! {
+    struct PublicDecryptVerification {
+        bytes32[] ctHandles;
+        bytes decryptedResult;
+        bytes extraData;
+    }
+}
+
+/// Values of the `public_decryption_aggregations.status` column.
+const STATUS_AGGREGATED: i16 = 0;
+const STATUS_TIMED_OUT: i16 = 1;
+
+/// A public decryption share, as signed by a KMS signer.
+struct Share {
+    signer: Address,
+    ct_handles: Vec<u8>,
+    result: Vec<u8>,
+    signature: Vec<u8>,
+    extra_data: Vec<u8>,
+}
+
+/// Collects the public decryption shares of the KMS signers, checks their signatures and, once
+/// enough signers agree on a result, enqueues their signatures in the `decryption_responses`
+/// table, to be sent one by one by the decryption response operation.
+///
+/// Decryptions that do not reach the quorum within `public_decryption_share_timeout` of their
+/// first share are given up and reported for review, with the signers that did not answer.
+#[derive(Clone)]
+pub(crate) struct PublicDecryptionAggregator {
+    db_pool: Pool<Postgres>,
+    bus: Arc<dyn NotificationBus>,
+    conf: ConfigSettings,
+    domain: Eip712Domain,
+    cancel_token: CancellationToken,
+}
+
+impl PublicDecryptionAggregator {
+    pub(crate) fn new(
+        decryption_address: Address,
+        gw_chain_id: u64,
+        db_pool: Pool<Postgres>,
+        bus: Arc<dyn NotificationBus>,
+        conf: ConfigSettings,
+        cancel_token: CancellationToken,
+    ) -> anyhow::Result<Self> {
+        conf.public_decryption_quorum.validate()?;
+        let domain = alloy::sol_types::eip712_domain! {
+            name: "Decryption",
+            version: "1",
+            chain_id: gw_chain_id,
+            verifying_contract: decryption_address,
+        };
+        Ok(Self {
+            db_pool,
+            bus,
+            conf,
+            domain,
+            cancel_token,
+        })
+    }
+
+    pub(crate) async fn run(self) -> anyhow::Result<()> {
+        info!(
+            quorum = ?self.conf.public_decryption_quorum,
+            timeout = ?self.conf.public_decryption_share_timeout,
+            "Starting public decryption aggregator"
+        );
+        let channel = &self.conf.public_decryption_shares_db_channel;
+        let mut listener = self.bus.subscribe(&[channel.clone()]).await?;
+        // Timeouts are not notified, pending decryptions are rechecked periodically
+        let polling_interval = Duration::from_secs(self.conf.db_polling_interval_secs.into())
+            .min(self.conf.public_decryption_share_timeout);
+
+        loop {
+            if self.cancel_token.is_cancelled() {
+                break;
+            }
+
+            match self.aggregate_pending().await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => {
+                    error!(error = %e, "Public decryption aggregation failed");
+                }
+            }
+
+            let notification = listener
+                .recv_coalesced(self.conf.db_notification_debounce)
+                .fuse();
+            tokio::select! {
+                _ = self.cancel_token.cancelled() => break,
+                n = notification => {
+                    if let BusEvent::Reconnected = n.event {
+                        info!(channel, "Listener reconnected, rechecking pending decryptions");
+                    }
+                }
+                _ = tokio::time::sleep(polling_interval) => {}
+            }
+        }
+        info!("Public decryption aggregator stopping");
+        Ok(())
+    }
+
+    /// Processes the decryptions with new shares or past their timeout. Returns true if there
+    /// might be more of them.
+    async fn aggregate_pending(&self) -> Result<bool, FhevmEngineError> {
+        let rows = sqlx::query!(
+            "
+            SELECT s.decryption_id, EXTRACT(EPOCH FROM NOW() - MIN(s.created_at))::FLOAT8 AS \"age!\"
+            FROM public_decryption_shares s
+            WHERE NOT EXISTS (
+                SELECT 1 FROM public_decryption_aggregations a
+                WHERE a.decryption_id = s.decryption_id
+            )
+            GROUP BY s.decryption_id
+            HAVING BOOL_OR(s.is_valid IS NULL)
+            OR MIN(s.created_at) < NOW() - make_interval(secs => $1)
+            ORDER BY MIN(s.created_at)
+            LIMIT $2
+            ",
+            self.conf.public_decryption_share_timeout.as_secs_f64(),
+            self.conf.public_decryption_aggregation_batch_limit as i64,
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        debug!(
+            rows_count = rows.len(),
+            "Selected public decryptions to aggregate"
+        );
+        let maybe_has_more_work =
+            rows.len() == self.conf.public_decryption_aggregation_batch_limit as usize;
+        for row in rows {
+            self.aggregate(
+                &row.decryption_id,
+                Duration::from_secs_f64(row.age.max(0.0)),
+            )
+            .await?;
+        }
+        Ok(maybe_has_more_work)
+    }
+
+    async fn aggregate(&self, decryption_id: &[u8], age: Duration) -> Result<(), FhevmEngineError> {
+        let mut tx = self.db_pool.begin().await?;
+        // Locked so that concurrent senders do not aggregate the same decryption twice
+        let rows = sqlx::query!(
+            "
+            SELECT signer, ct_handles, result, signature, extra_data, key_id, is_valid
+            FROM public_decryption_shares
+            WHERE decryption_id = $1
+            FOR UPDATE
+            ",
+            decryption_id,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        // Thresholds may be overridden for the key of the decrypted ciphertexts
+        let key_id = rows
+            .iter()
+            .find_map(|row| row.key_id.as_deref())
+            .and_then(|key_id| U256::try_from_be_slice(key_id));
+        let policy = &self.conf.public_decryption_quorum;
+
+        let mut valid_shares = vec![];
+        for row in rows {
+            let share = Address::try_from(row.signer.as_slice())
+                .ok()
+                .map(|signer| Share {
+                    signer,
+                    ct_handles: row.ct_handles,
+                    result: row.result,
+                    signature: row.signature,
+                    extra_data: row.extra_data,
+                });
+            let is_valid = match row.is_valid {
+                Some(is_valid) => is_valid,
+                None => {
+                    let is_valid = share.as_ref().is_some_and(|share| self.verify(share));
+                    if !is_valid {
+                        PUBLIC_DECRYPTION_INVALID_SHARE_COUNTER.inc();
+                        warn!(
+                            decryption_id = compact_hex(decryption_id),
+                            signer = compact_hex(&row.signer),
+                            "Invalid public decryption share"
+                        );
+                    }
+                    sqlx::query!(
+                        "UPDATE public_decryption_shares SET is_valid = $1
+                         WHERE decryption_id = $2 AND signer = $3",
+                        is_valid,
+                        decryption_id,
+                        row.signer,
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                    is_valid
+                }
+            };
+            if let (true, Some(share)) = (is_valid, share) {
+                valid_shares.push(share);
+            }
+        }
+
+        // Signers may disagree, only the shares of a single result count for the quorum
+        let mut results: HashMap<(&[u8], &[u8], &[u8]), BTreeMap<Address, &[u8]>> = HashMap::new();
+        for share in &valid_shares {
+            results
+                .entry((
+                    share.ct_handles.as_slice(),
+                    share.result.as_slice(),
+                    share.extra_data.as_slice(),
+                ))
+                .or_default()
+                .insert(share.signer, &share.signature);
+        }
+        let quorum = results.iter().find_map(|(values, signatures)| {
+            policy
+                .reached(key_id, signatures.keys())
+                .map(|set| (values, signatures, set))
+        });
+
+        match quorum {
+            Some((&(_, result, extra_data), signatures, set)) => {
+                // Only the signatures of the signer set that reached its threshold are sent
+                let signatures: Vec<Bytes> = signatures
+                    .iter()
+                    .filter(|(signer, _)| set.signers.contains(signer))
+                    .map(|(_, signature)| Bytes::copy_from_slice(signature))
+                    .collect();
+                sqlx::query!(
+                    "INSERT INTO decryption_responses
+                        (decryption_id, response_type, result, signature, extra_data)
+                     VALUES ($1, $2, $3, $4, $5)
+                     ON CONFLICT DO NOTHING",
+                    decryption_id,
+                    DecryptionResponseType::PublicAggregated as i16,
+                    self.conf.column_encryption.encrypt(result),
+                    signatures.abi_encode(),
+                    extra_data,
+                )
+                .execute(&mut *tx)
+                .await?;
+                self.complete(
+                    &mut tx,
+                    decryption_id,
+                    STATUS_AGGREGATED,
+                    signatures.len(),
+                    Some(set.version),
+                    None,
+                )
+                .await?;
+                PUBLIC_DECRYPTION_AGGREGATED_COUNTER.inc();
+                info!(
+                    decryption_id = compact_hex(decryption_id),
+                    signers_count = signatures.len(),
+                    signer_set_version = set.version,
+                    "Public decryption shares aggregated"
+                );
+            }
+            _ if age >= self.conf.public_decryption_share_timeout => {
+                // Reported against the most recent signer set, the one expected to answer
+                let Some(current) = policy.current() else {
+                    return Err(FhevmEngineError::Internal(
+                        "Empty public decryption quorum policy".to_owned(),
+                    ));
+                };
+                let agreeing = results
+                    .values()
+                    .map(|signatures| {
+                        signatures
+                            .keys()
+                            .filter(|signer| current.signers.contains(signer))
+                            .count()
+                    })
+                    .max()
+                    .unwrap_or(0);
+                let answered: HashSet<Address> = valid_shares.iter().map(|s| s.signer).collect();
+                let missing: Vec<String> = current
+                    .signers
+                    .iter()
+                    .filter(|signer| !answered.contains(signer))
+                    .map(|signer| signer.to_string())
+                    .collect();
+                let reason = format!(
+                    "{} of {} required signers of signer set {} agreed, missing signers: [{}]",
+                    agreeing,
+                    policy.threshold(current, key_id),
+                    current.version,
+                    missing.join(", ")
+                );
+                self.complete(
+                    &mut tx,
+                    decryption_id,
+                    STATUS_TIMED_OUT,
+                    agreeing,
+                    None,
+                    Some(&reason),
+                )
+                .await?;
+                PUBLIC_DECRYPTION_TIMEOUT_COUNTER.inc();
+                error!(
+                    action = REVIEW,
+                    decryption_id = compact_hex(decryption_id),
+                    age = ?age,
+                    reason,
+                    "Public decryption quorum not reached before timeout"
+                );
+            }
+            _ => {
+                debug!(
+                    decryption_id = compact_hex(decryption_id),
+                    valid_shares = valid_shares.len(),
+                    "Waiting for more public decryption shares"
+                );
+            }
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn complete(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+        decryption_id: &[u8],
+        status: i16,
+        signers_count: usize,
+        signer_set_version: Option<u64>,
+        error: Option<&str>,
+    ) -> Result<(), FhevmEngineError> {
+        sqlx::query!(
+            "INSERT INTO public_decryption_aggregations
+                (decryption_id, status, signers_count, signer_set_version, error)
+             VALUES ($1, $2, $3, $4, $5)",
+            decryption_id,
+            status,
+            signers_count as i32,
+            signer_set_version.map(|version| version as i64),
+            error,
+        )
+        .execute(&mut **tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Checks that the share is signed by the KMS signer it claims to come from.
+    fn verify(&self, share: &Share) -> bool {
+        if !self.conf.public_decryption_quorum.is_signer(&share.signer)
+            || share.ct_handles.len() % 32 != 0
+        {
+            return false;
+        }
+        let Ok(signature) = Signature::from_raw(&share.signature) else {
+            return false;
+        };
+        let signing_hash = PublicDecryptVerification {
+            ctHandles: share
+                .ct_handles
+                .chunks(32)
+                .map(FixedBytes::<32>::from_slice)
+                .collect(),
+            decryptedResult: share.result.clone().into(),
+            extraData: share.extra_data.clone().into(),
+        }
+        .eip712_signing_hash(&self.domain);
+        signature
+            .recover_address_from_prehash(&signing_hash)
+            .is_ok_and(|recovered| recovered == share.signer)
+    }
+}
@@ -0,0 +1,178 @@
+//! Classification of the errors returned when sending a transaction.
+//!
+//! A single classifier, [`classify`], decides how every operation retries a transaction that
+//! could not be sent, from the signer errors, the transport error kinds and the contract errors.
+//! Each operation only lists the contract errors it expects in an [`ErrorTable`], by selector,
+//! and implements [`SendErrorHandler`] over its queue, so that [`handle_send_error`] applies the
+//! class the same way for all of them.
+
+use std::fmt::Debug;
+
+use alloy::{
+    rpc::types::TransactionRequest,
+    sol_types::SolInterface,
+    transports::{RpcError, TransportErrorKind},
+};
+use async_trait::async_trait;
+use fhevm_engine_common::error::FhevmEngineError;
+use prometheus::IntCounter;
+use tracing::{error, warn};
+
+use crate::signing_policy::PolicyDenial;
+
+/// What to do with a queue item whose transaction could not be sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryClass {
+    /// Transient, retried without limit, e.g. the node is unreachable.
+    RetryForever,
+    /// Might succeed later, counted against the limited retries of the item, which is left for
+    /// review once they are exhausted.
+    RetryBounded,
+    /// The contract already holds what the transaction brings, e.g. sent by a previous attempt
+    /// whose receipt was lost. The item is marked as sent.
+    Skip,
+    /// Can't succeed whatever the number of attempts, e.g. the request is unknown to the
+    /// contract. The item is left for review right away.
+    DeadLetter,
+}
+
+/// Classes of the contract errors of an operation, by error selector. The errors that are not
+/// listed are retried a bounded number of times.
+pub type ErrorTable = &'static [([u8; 4], RetryClass)];
+
+/// Classifies a sending error, decoding it with the `E` contract errors. Returns the decoded
+/// contract error, if any, for logging.
+pub fn classify<E: SolInterface>(
+    err: &RpcError<TransportErrorKind>,
+    table: ErrorTable,
+) -> (RetryClass, Option<E>) {
+    match err {
+        // Signer errors. Local usage errors are retried without limit as they might be transient
+        // with external AWS KMS signers, while a transaction denied by the signing policy is
+        // denied again until the operator changes either.
+        RpcError::LocalUsageError(_) => return (RetryClass::RetryForever, None),
+        RpcError::Transport(TransportErrorKind::Custom(inner))
+            if inner.downcast_ref::<PolicyDenial>().is_some() =>
+        {
+            return (RetryClass::DeadLetter, None)
+        }
+        // Transport errors
+        RpcError::Transport(inner)
+            if inner.is_retry_err() || matches!(inner, TransportErrorKind::BackendGone) =>
+        {
+            return (RetryClass::RetryForever, None)
+        }
+        _ => {}
+    }
+
+    // Contract errors
+    let contract_error = err
+        .as_error_resp()
+        .and_then(|payload| payload.as_decoded_interface_error::<E>());
+    let class = contract_error
+        .as_ref()
+        .and_then(|error| {
+            table
+                .iter()
+                .find(|(selector, _)| *selector == error.selector())
+                .map(|(_, class)| *class)
+        })
+        .unwrap_or(RetryClass::RetryBounded);
+    (class, contract_error)
+}
+
+/// A failed attempt at sending the transaction of a queue item.
+pub(crate) struct SendAttempt<'a, K: ?Sized> {
+    pub key: &'a K,
+    pub transaction_request: &'a TransactionRequest,
+    pub limited_retries_count: i32,
+    pub unlimited_retries_count: i32,
+    pub src_transaction_id: Option<Vec<u8>>,
+}
+
+/// The queue of an operation, as seen by [`handle_send_error`].
+#[async_trait]
+pub(crate) trait SendErrorHandler: Send + Sync {
+    type Key: ?Sized + Sync;
+    /// Contract errors of the operation.
+    type Errors: SolInterface + Debug + Send;
+
+    const ERRORS: ErrorTable;
+    /// Logged when an item is skipped.
+    const SKIP_REASON: &'static str;
+
+    /// Identifies an item in the logs.
+    fn log_key(key: &Self::Key) -> String;
+
+    fn fail_counter(&self) -> &IntCounter;
+
+    /// Marks the item as sent.
+    async fn skip(&self, attempt: SendAttempt<'_, Self::Key>) -> Result<(), FhevmEngineError>;
+
+    async fn retry_forever(
+        &self,
+        attempt: SendAttempt<'_, Self::Key>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError>;
+
+    async fn retry_bounded(
+        &self,
+        attempt: SendAttempt<'_, Self::Key>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError>;
+
+    /// Stops retrying the item and leaves it for review.
+    async fn dead_letter(
+        &self,
+        attempt: SendAttempt<'_, Self::Key>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError>;
+}
+
+/// Applies the class of a sending error to the item. Returns the class, for telemetry, and the
+/// result of the send: Ok if the item is skipped, the sending error otherwise.
+pub(crate) async fn handle_send_error<H: SendErrorHandler>(
+    handler: &H,
+    attempt: SendAttempt<'_, H::Key>,
+    err: RpcError<TransportErrorKind>,
+) -> (RetryClass, Result<(), FhevmEngineError>) {
+    let (class, contract_error) = classify::<H::Errors>(&err, H::ERRORS);
+    let key = H::log_key(attempt.key);
+    let message = err.to_string();
+    let applied = match class {
+        RetryClass::Skip => {
+            warn!(error = ?contract_error, key = %key, "{}", H::SKIP_REASON);
+            return (class, handler.skip(attempt).await);
+        }
+        RetryClass::RetryForever => {
+            warn!(
+                transaction_request = ?attempt.transaction_request,
+                error = %err,
+                key = %key,
+                "Transaction sending failed with unlimited retry error"
+            );
+            handler.retry_forever(attempt, &message).await
+        }
+        RetryClass::RetryBounded => {
+            warn!(
+                transaction_request = ?attempt.transaction_request,
+                error = %err,
+                key = %key,
+                "Transaction sending failed"
+            );
+            handler.retry_bounded(attempt, &message).await
+        }
+        RetryClass::DeadLetter => {
+            error!(
+                transaction_request = ?attempt.transaction_request,
+                error = %err,
+                key = %key,
+                "Transaction sending failed with an error that can't be retried"
+            );
+            handler.dead_letter(attempt, &message).await
+        }
+    };
+    handler.fail_counter().inc();
+    let result = applied.and(Err(FhevmEngineError::from_rpc::<H::Errors>(err)));
+    (class, result)
+}
@@ -0,0 +1,118 @@
+use std::str::FromStr;
+
+use alloy::{primitives::U256, rpc::types::TransactionReceipt};
+use fhevm_engine_common::error::FhevmEngineError;
+use sqlx::{types::time::OffsetDateTime, Pool, Postgres};
+use tracing::warn;
+
+use crate::user_operation::UserOperationSpend;
+
+/// Operation names as stored in the `gas_spend.operation` column.
+pub const OP_VERIFY_PROOF: &str = "verify_proof";
+pub const OP_ADD_CIPHERTEXT: &str = "add_ciphertext";
+pub const OP_ALLOW_ACCOUNT: &str = "allow_account";
+pub const OP_ALLOW_PUBLIC_DECRYPT: &str = "allow_public_decrypt";
+pub const OP_PUBLIC_DECRYPTION_RESPONSE: &str = "public_decryption_response";
+pub const OP_USER_DECRYPTION_RESPONSE: &str = "user_decryption_response";
+pub const OP_PUBLIC_DECRYPTION_AGGREGATED_RESPONSE: &str = "public_decryption_aggregated_response";
+
+/// Aggregated fee spend for one operation on one chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GasSpendSummary {
+    pub operation: String,
+    pub chain_id: i64,
+    pub txn_count: i64,
+    pub failed_txn_count: i64,
+    pub total_gas_used: i64,
+    /// Total cost in wei.
+    pub total_cost: U256,
+}
+
+/// Records the fees paid for a mined transaction, whatever its status. The fees of a user
+/// operation are its share of the bundle transaction.
+///
+/// Accounting must never fail the operation that sent the transaction, hence errors are only
+/// logged.
+pub(crate) async fn record_receipt(
+    db_pool: &Pool<Postgres>,
+    operation: &str,
+    chain_id: u64,
+    receipt: &TransactionReceipt,
+    user_operation: Option<&UserOperationSpend>,
+) {
+    let (gas_used, effective_gas_price, total_cost) = match user_operation {
+        Some(spend) => (
+            spend.gas_used,
+            spend.total_cost / U256::from(spend.gas_used.max(1)),
+            spend.total_cost,
+        ),
+        None => (
+            receipt.gas_used,
+            U256::from(receipt.effective_gas_price),
+            U256::from(receipt.gas_used as u128 * receipt.effective_gas_price),
+        ),
+    };
+    if let Err(e) = sqlx::query!(
+        "INSERT INTO gas_spend (txn_hash, user_op_hash, operation, chain_id, block_number, gas_used, effective_gas_price, total_cost, success)
+        VALUES ($1, $2, $3, $4, $5, $6, $7::TEXT::NUMERIC, $8::TEXT::NUMERIC, $9)
+        ON CONFLICT (txn_hash, user_op_hash) DO NOTHING",
+        receipt.transaction_hash.as_slice(),
+        user_operation.map(|spend| spend.user_op_hash.as_slice()),
+        operation,
+        chain_id as i64,
+        receipt.block_number.map(|bn| bn as i64),
+        gas_used as i64,
+        effective_gas_price.to_string(),
+        total_cost.to_string(),
+        receipt.status(),
+    )
+    .execute(db_pool)
+    .await
+    {
+        warn!(
+            transaction_hash = %receipt.transaction_hash,
+            operation,
+            error = %e,
+            "Failed to record gas spend"
+        );
+    }
+}
+
+/// Returns the fee spend per operation and chain for transactions recorded in `[from, to)`.
+pub async fn gas_spend_summary(
+    db_pool: &Pool<Postgres>,
+    from: OffsetDateTime,
+    to: OffsetDateTime,
+) -> Result<Vec<GasSpendSummary>, FhevmEngineError> {
+    let rows = sqlx::query!(
+        r#"SELECT
+            operation,
+            chain_id,
+            COUNT(*) AS "txn_count!",
+            COUNT(*) FILTER (WHERE NOT success) AS "failed_txn_count!",
+            SUM(gas_used)::BIGINT AS "total_gas_used!",
+            SUM(total_cost)::TEXT AS "total_cost!"
+        FROM gas_spend
+        WHERE created_at >= $1 AND created_at < $2
+        GROUP BY operation, chain_id
+        ORDER BY operation, chain_id"#,
+        from,
+        to,
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(GasSpendSummary {
+                total_cost: U256::from_str(&row.total_cost)
+                    .map_err(|e| FhevmEngineError::Internal(format!("total_cost: {e}")))?,
+                operation: row.operation,
+                chain_id: row.chain_id,
+                txn_count: row.txn_count,
+                failed_txn_count: row.failed_txn_count,
+                total_gas_used: row.total_gas_used,
+            })
+        })
+        .collect()
+}
@@ -1,10 +1,24 @@
+mod balance_monitor;
 pub mod config;
+mod decryption_aggregator;
+pub mod error_class;
+pub mod gas_spend;
 pub mod http_server;
 mod metrics;
 mod nonce_managed_provider;
 mod ops;
 pub mod overprovision_gas_limit;
+mod purger;
+pub mod quorum_policy;
+mod receipts;
+pub mod scheduler;
+pub mod signing_policy;
+pub mod smoke_test;
+mod stuck_nonce_monitor;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
 mod transaction_sender;
+pub mod user_operation;
 
 use std::sync::Arc;
 use std::time::Duration;
@@ -12,7 +26,6 @@ use std::time::Duration;
 use alloy::network::TxSigner;
 use alloy::providers::Provider;
 use alloy::providers::ProviderBuilder;
-use alloy::providers::WsConnect;
 use alloy::signers::Signature;
 use alloy::signers::Signer;
 use alloy::transports::http::reqwest::Url;
@@ -20,8 +33,10 @@ use alloy::transports::TransportError;
 use alloy::transports::TransportErrorKind;
 use anyhow::Error;
 pub use config::ConfigSettings;
+use fhevm_engine_common::tls::RpcTls;
 pub use nonce_managed_provider::FillersWithoutNonceManagement;
 pub use nonce_managed_provider::NonceManagedProvider;
+pub use quorum_policy::QuorumPolicy;
 use tracing::error;
 pub use transaction_sender::TransactionSender;
 
@@ -80,11 +95,12 @@ impl HealthStatus {
 
 // Gets the chain ID from the given WebSocket URL.
 // This is a utility function that will try to connect until it succeeds.
-pub async fn get_chain_id(ws_url: Url, retry_interval: Duration) -> u64 {
+pub async fn get_chain_id(ws_url: Url, retry_interval: Duration, rpc_tls: &RpcTls) -> u64 {
     loop {
         let provider = match ProviderBuilder::new()
-            .connect_ws(
-                WsConnect::new(ws_url.clone())
+            .connect_pubsub_with(
+                rpc_tls
+                    .connect(ws_url.clone())
                     .with_max_retries(1)
                     .with_retry_interval(retry_interval),
             )
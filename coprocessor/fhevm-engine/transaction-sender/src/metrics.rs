@@ -1,4 +1,7 @@
-use prometheus::{register_int_counter, IntCounter};
+use prometheus::{
+    register_gauge_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, GaugeVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
 use std::sync::LazyLock;
 
 pub(crate) static VERIFY_PROOF_SUCCESS_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
@@ -50,3 +53,122 @@ pub(crate) static ALLOW_HANDLE_FAIL_COUNTER: LazyLock<IntCounter> = LazyLock::ne
     )
     .unwrap()
 });
+
+pub(crate) static DECRYPTION_RESPONSE_SUCCESS_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_txn_sender_decryption_response_success_counter",
+        "Number of successful decryption response txns in transaction-sender"
+    )
+    .unwrap()
+});
+
+pub(crate) static DECRYPTION_RESPONSE_FAIL_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_txn_sender_decryption_response_fail_counter",
+        "Number of failed decryption response txns requests in transaction-sender"
+    )
+    .unwrap()
+});
+
+pub(crate) static PUBLIC_DECRYPTION_AGGREGATED_COUNTER: LazyLock<IntCounter> =
+    LazyLock::new(|| {
+        register_int_counter!(
+            "coprocessor_txn_sender_public_decryption_aggregated_counter",
+            "Number of public decryptions whose shares reached the quorum in transaction-sender"
+        )
+        .unwrap()
+    });
+
+pub(crate) static PUBLIC_DECRYPTION_TIMEOUT_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_txn_sender_public_decryption_timeout_counter",
+        "Number of public decryptions given up before reaching the quorum in transaction-sender"
+    )
+    .unwrap()
+});
+
+pub(crate) static PUBLIC_DECRYPTION_INVALID_SHARE_COUNTER: LazyLock<IntCounter> =
+    LazyLock::new(|| {
+        register_int_counter!(
+            "coprocessor_txn_sender_public_decryption_invalid_share_counter",
+            "Number of public decryption shares with an invalid signature in transaction-sender"
+        )
+        .unwrap()
+    });
+
+pub(crate) static STUCK_NONCE_DETECTED_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_txn_sender_stuck_nonce_detected_counter",
+        "Number of times a stuck nonce was detected in transaction-sender"
+    )
+    .unwrap()
+});
+
+pub(crate) static STUCK_NONCE_CANCEL_SUCCESS_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_txn_sender_stuck_nonce_cancel_success_counter",
+        "Number of successfully sent stuck nonce cancel txns in transaction-sender"
+    )
+    .unwrap()
+});
+
+pub(crate) static STUCK_NONCE_CANCEL_FAIL_COUNTER: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "coprocessor_txn_sender_stuck_nonce_cancel_fail_counter",
+        "Number of failed stuck nonce cancel txns requests in transaction-sender"
+    )
+    .unwrap()
+});
+
+pub(crate) static ACCOUNT_BALANCE: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "coprocessor_txn_sender_account_balance_wei",
+        "Balance in wei of the accounts of transaction-sender",
+        &["address"]
+    )
+    .unwrap()
+});
+
+pub(crate) static SENDING_PAUSED: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "coprocessor_txn_sender_sending_paused",
+        "1 while transaction-sender does not send because of a low balance, 0 otherwise"
+    )
+    .unwrap()
+});
+
+pub(crate) static IN_FLIGHT_TXNS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "coprocessor_txn_sender_in_flight_txns",
+        "Number of txns of an operation being sent in transaction-sender",
+        &["operation"]
+    )
+    .unwrap()
+});
+
+pub(crate) static SCHEDULED_TASK_RUNS_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_txn_sender_scheduled_task_runs_counter",
+        "Number of runs of the scheduled tasks in transaction-sender, by status: success, error or skipped when the previous run is still in progress",
+        &["task", "status"]
+    )
+    .unwrap()
+});
+
+pub(crate) static PURGED_ROWS_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_txn_sender_purged_rows_counter",
+        "Number of handled queue rows purged after their retention period in transaction-sender",
+        &["table"]
+    )
+    .unwrap()
+});
+
+pub(crate) static SIGNING_POLICY_DENIED_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_txn_sender_signing_policy_denied_counter",
+        "Number of txns denied by the signing policy in transaction-sender, by rule",
+        &["rule"]
+    )
+    .unwrap()
+});
@@ -1,8 +1,11 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use alloy::{
-    network::Ethereum,
-    primitives::Address,
+    network::{Ethereum, TransactionBuilder},
+    primitives::{Address, TxHash},
     providers::{
         fillers::{
             BlobGasFiller, CachedNonceManager, ChainIdFiller, GasFiller, JoinFill, NonceManager,
@@ -10,9 +13,17 @@ use alloy::{
         PendingTransactionBuilder,
     },
     rpc::types::TransactionRequest,
-    transports::TransportResult,
+    transports::{RpcError, TransportErrorKind, TransportResult},
 };
 use futures_util::lock::Mutex;
+use tracing::error;
+
+use crate::{
+    metrics::SIGNING_POLICY_DENIED_COUNTER,
+    signing_policy::SigningPolicy,
+    user_operation::{UserOperationSender, UserOperationSpend},
+    REVIEW,
+};
 
 pub type FillersWithoutNonceManagement =
     JoinFill<GasFiller, JoinFill<BlobGasFiller, ChainIdFiller>>;
@@ -28,6 +39,18 @@ where
     provider: P,
     nonce_manager: Arc<Mutex<CachedNonceManager>>,
     signer_address: Option<Address>,
+    /// When set, transactions are sent as user operations instead of from the signer's EOA.
+    user_operations: Option<Arc<UserOperationSender>>,
+    /// When set, transactions are refused if the node reports another chain id.
+    chain_id_guard: Option<Arc<ChainIdGuard>>,
+    /// When set, transactions are checked against it before signing.
+    signing_policy: Option<Arc<dyn SigningPolicy>>,
+}
+
+struct ChainIdGuard {
+    expected: u64,
+    check_interval: Duration,
+    last_check: Mutex<Option<Instant>>,
 }
 
 impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> NonceManagedProvider<P> {
@@ -36,7 +59,100 @@ impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> NonceManagedProv
             provider,
             nonce_manager: Default::default(),
             signer_address,
+            user_operations: None,
+            chain_id_guard: None,
+            signing_policy: None,
+        }
+    }
+
+    /// Checks every transaction against `policy` before signing it. The fees of the transactions
+    /// sent from the EOA are then estimated before the check, instead of by the gas filler.
+    pub fn with_signing_policy(mut self, policy: Arc<dyn SigningPolicy>) -> Self {
+        self.signing_policy = Some(policy);
+        self
+    }
+
+    /// Refuses to send transactions when the node reports another chain id than `expected`. The
+    /// chain id is checked before the first transaction, then again once `check_interval` has
+    /// elapsed since the last check.
+    pub fn with_chain_id_guard(mut self, expected: u64, check_interval: Duration) -> Self {
+        self.chain_id_guard = Some(Arc::new(ChainIdGuard {
+            expected,
+            check_interval,
+            last_check: Default::default(),
+        }));
+        self
+    }
+
+    /// Fails if the node reports another chain id than the expected one. A mismatch is a local
+    /// usage error so that it is retried without limit, in the hope the node gets fixed.
+    pub async fn check_chain_id(&self) -> TransportResult<()> {
+        let Some(guard) = &self.chain_id_guard else {
+            return Ok(());
+        };
+        let mut last_check = guard.last_check.lock().await;
+        if last_check.is_some_and(|at| at.elapsed() < guard.check_interval) {
+            return Ok(());
+        }
+        let chain_id = self.provider.get_chain_id().await?;
+        if chain_id != guard.expected {
+            error!(
+                chain_id,
+                expected_chain_id = guard.expected,
+                "Wrong chain, refusing to send transactions"
+            );
+            return Err(RpcError::local_usage_str(&format!(
+                "wrong chain, node reports chain ID {chain_id}, expected {}",
+                guard.expected
+            )));
+        }
+        *last_check = Some(Instant::now());
+        Ok(())
+    }
+
+    pub fn with_user_operations(mut self, sender: UserOperationSender) -> Self {
+        self.user_operations = Some(Arc::new(sender));
+        self
+    }
+
+    pub fn uses_user_operations(&self) -> bool {
+        self.user_operations.is_some()
+    }
+
+    /// Checks the chain id and the signing policy before `tx` is signed. Transactions sent
+    /// through the inner provider, bypassing the nonce manager, must be checked with it.
+    pub async fn check_before_signing(&self, tx: &mut TransactionRequest) -> TransportResult<()> {
+        self.check_chain_id().await?;
+        if let Some(policy) = &self.signing_policy {
+            if self.user_operations.is_none()
+                && tx.gas_price.is_none()
+                && tx.max_fee_per_gas.is_none()
+            {
+                let fees = self.provider.estimate_eip1559_fees().await?;
+                tx.max_fee_per_gas = Some(fees.max_fee_per_gas);
+                tx.max_priority_fee_per_gas = Some(fees.max_priority_fee_per_gas);
+            }
+            if let Err(denial) = policy.check(&tx).await {
+                SIGNING_POLICY_DENIED_COUNTER
+                    .with_label_values(&[denial.rule])
+                    .inc();
+                error!(
+                    target: "audit",
+                    action = REVIEW,
+                    rule = denial.rule,
+                    reason = %denial.reason,
+                    signer_address = ?self.signer_address,
+                    to = ?tx.to,
+                    value = ?tx.value,
+                    max_fee_per_gas = ?tx.max_fee_per_gas.or(tx.gas_price),
+                    input = ?tx.input.input(),
+                    "Transaction denied by the signing policy"
+                );
+                // Dead-lettered, see `error_class::classify`
+                return Err(TransportErrorKind::custom(denial));
+            }
         }
+        Ok(())
     }
 
     pub async fn send_transaction(
@@ -44,6 +160,10 @@ impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> NonceManagedProv
         tx: impl Into<TransactionRequest>,
     ) -> TransportResult<PendingTransactionBuilder<Ethereum>> {
         let mut tx = tx.into();
+        self.check_before_signing(&mut tx).await?;
+        if let Some(user_operations) = &self.user_operations {
+            return user_operations.send_transaction(&self.provider, tx).await;
+        }
         if let Some(signer_address) = self.signer_address {
             let nonce_manager = self.nonce_manager.lock().await;
             let nonce = nonce_manager
@@ -71,6 +191,40 @@ impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> NonceManagedProv
         self.provider.get_block_number().await
     }
 
+    pub fn signer_address(&self) -> Option<Address> {
+        self.signer_address
+    }
+
+    /// The account the Gateway contracts see as the sender of the transactions.
+    pub fn sender_address(&self) -> Option<Address> {
+        match &self.user_operations {
+            Some(user_operations) => Some(user_operations.smart_account_address()),
+            None => self.signer_address,
+        }
+    }
+
+    /// Sets the sender of the transaction to the account the Gateway contracts see, for its gas to
+    /// be estimated from it.
+    pub fn with_sender(&self, tx: impl Into<TransactionRequest>) -> TransactionRequest {
+        let tx = tx.into();
+        match (&self.user_operations, self.signer_address) {
+            (Some(user_operations), _) => tx.with_from(user_operations.smart_account_address()),
+            (None, Some(signer_address)) if tx.from.is_none() => tx.with_from(signer_address),
+            _ => tx,
+        }
+    }
+
+    /// Gas of the user operation the transaction was sent as, bundled in `txn_hash`. Taken once.
+    pub fn take_user_operation_spend(
+        &self,
+        txn_hash: &TxHash,
+        tx: &TransactionRequest,
+    ) -> Option<UserOperationSpend> {
+        self.user_operations
+            .as_ref()
+            .and_then(|user_operations| user_operations.take_spend(txn_hash, tx))
+    }
+
     pub fn inner(&self) -> &P {
         &self.provider
     }
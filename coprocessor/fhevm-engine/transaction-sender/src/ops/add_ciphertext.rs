@@ -1,13 +1,15 @@
 use std::time::Duration;
 
 use crate::{
+    error_class::{handle_send_error, ErrorTable, RetryClass, SendAttempt, SendErrorHandler},
+    gas_spend,
     metrics::{ADD_CIPHERTEXT_MATERIAL_FAIL_COUNTER, ADD_CIPHERTEXT_MATERIAL_SUCCESS_COUNTER},
     nonce_managed_provider::NonceManagedProvider,
-    overprovision_gas_limit::try_overprovision_gas_limit,
-    REVIEW,
+    overprovision_gas_limit::{try_overprovision_gas_limit, GasEstimates},
+    receipts, REVIEW,
 };
 
-use super::common::try_into_array;
+use super::common::{try_into_array, InFlightLimit};
 use super::TransactionOperation;
 use alloy::{
     network::{Ethereum, TransactionBuilder},
@@ -15,11 +17,15 @@ use alloy::{
     providers::Provider,
     rpc::types::TransactionRequest,
     sol,
-    transports::{RpcError, TransportErrorKind},
+    sol_types::SolError,
 };
-use anyhow::bail;
 use async_trait::async_trait;
-use fhevm_engine_common::{telemetry, tenant_keys::query_tenant_info, utils::compact_hex};
+use fhevm_engine_common::{
+    db_query::run_query, error::FhevmEngineError, handle::TypedHandle, telemetry,
+    tenant_keys::query_tenant_info, utils::compact_hex,
+};
+use fhevm_gateway_bindings::drift::ExpectedSelector;
+use prometheus::IntCounter;
 use sqlx::{Pool, Postgres};
 use tokio::task::JoinSet;
 use tracing::{error, info, warn};
@@ -31,13 +37,22 @@ sol!(
     "artifacts/CiphertextCommits.sol/CiphertextCommits.json"
 );
 
+/// The ciphertext commit was already added by a previous attempt.
+const ADD_CIPHERTEXT_ERRORS: ErrorTable = &[(
+    CiphertextCommits::CoprocessorAlreadyAdded::SELECTOR,
+    RetryClass::Skip,
+)];
+
 #[derive(Clone)]
 pub struct AddCiphertextOperation<P: Provider<Ethereum> + Clone + 'static> {
     ciphertext_commits_address: Address,
     provider: NonceManagedProvider<P>,
     conf: crate::ConfigSettings,
     gas: Option<u64>,
+    gw_chain_id: u64,
     db_pool: Pool<Postgres>,
+    gas_estimates: GasEstimates,
+    in_flight: InFlightLimit,
 }
 
 impl<P: Provider<Ethereum> + Clone + 'static> AddCiphertextOperation<P> {
@@ -48,16 +63,17 @@ impl<P: Provider<Ethereum> + Clone + 'static> AddCiphertextOperation<P> {
         current_limited_retries_count: i32,
         current_unlimited_retries_count: i32,
         src_transaction_id: Option<Vec<u8>>,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), FhevmEngineError> {
         let h = compact_hex(handle);
 
         info!(handle = h, "Processing transaction");
         let _t = telemetry::tracer("call_add_ciphertext", &src_transaction_id);
 
         let overprovisioned_txn_req = try_overprovision_gas_limit(
-            txn_request,
+            self.provider.with_sender(txn_request),
             self.provider.inner(),
-            self.conf.gas_limit_overprovision_percent,
+            &self.conf,
+            &self.gas_estimates,
         )
         .await;
         let transaction = match self
@@ -66,52 +82,15 @@ impl<P: Provider<Ethereum> + Clone + 'static> AddCiphertextOperation<P> {
             .await
         {
             Ok(txn) => txn,
-            Err(e) if self.already_added_error(&e).is_some() => {
-                warn!(
-                    handle = h,
-                    address = ?self.already_added_error(&e),
-                    "Coprocessor has already added the ciphertext commit",
-                );
-                self.set_txn_is_sent(handle, None, None, src_transaction_id)
-                    .await?;
-                return Ok(());
-            }
-            // Consider transport retryable errors, BackendGone and local usage errors as something that must be retried infinitely.
-            // Local usage are included as they might be transient due to external AWS KMS signers.
-            Err(e)
-                if matches!(&e, RpcError::Transport(inner) if inner.is_retry_err() || matches!(inner, TransportErrorKind::BackendGone))
-                    || matches!(&e, RpcError::LocalUsageError(_)) =>
-            {
-                ADD_CIPHERTEXT_MATERIAL_FAIL_COUNTER.inc();
-                warn!(
-                    transaction_request = ?overprovisioned_txn_req,
-                    error = %e,
-                    handle = h,
-                    "Transaction sending failed with unlimited retry error"
-                );
-                self.increment_txn_unlimited_retries_count(
-                    handle,
-                    &e.to_string(),
-                    current_unlimited_retries_count,
-                )
-                .await?;
-                bail!(e);
-            }
             Err(e) => {
-                ADD_CIPHERTEXT_MATERIAL_FAIL_COUNTER.inc();
-                warn!(
-                    transaction_request = ?overprovisioned_txn_req,
-                    error = %e,
-                    handle = h,
-                    "Transaction sending failed"
-                );
-                self.increment_txn_limited_retries_count(
-                    handle,
-                    &e.to_string(),
-                    current_limited_retries_count,
-                )
-                .await?;
-                bail!(e);
+                let attempt = SendAttempt {
+                    key: handle,
+                    transaction_request: &overprovisioned_txn_req,
+                    limited_retries_count: current_limited_retries_count,
+                    unlimited_retries_count: current_unlimited_retries_count,
+                    src_transaction_id,
+                };
+                return handle_send_error(self, attempt, e).await.1;
             }
         };
 
@@ -135,10 +114,21 @@ impl<P: Provider<Ethereum> + Clone + 'static> AddCiphertextOperation<P> {
                     current_limited_retries_count,
                 )
                 .await?;
-                return Err(anyhow::Error::new(e));
+                return Err(e.into());
             }
         };
 
+        receipts::record_receipt::<CiphertextCommitsErrors, _>(
+            &self.db_pool,
+            &self.provider,
+            gas_spend::OP_ADD_CIPHERTEXT,
+            self.gw_chain_id,
+            &[alloy::hex::encode_prefixed(handle)],
+            &overprovisioned_txn_req,
+            &receipt,
+        )
+        .await;
+
         if receipt.status() {
             self.set_txn_is_sent(
                 handle,
@@ -169,43 +159,44 @@ impl<P: Provider<Ethereum> + Clone + 'static> AddCiphertextOperation<P> {
             )
             .await?;
 
-            return Err(anyhow::anyhow!(
-                "Transaction {} failed with status {}, handle: {}",
-                receipt.transaction_hash,
-                receipt.status(),
-                h,
-            ));
+            return Err(FhevmEngineError::ContractRevert {
+                reason: format!(
+                    "Transaction {} failed with status {}, handle: {}",
+                    receipt.transaction_hash,
+                    receipt.status(),
+                    h,
+                ),
+                data: None,
+            });
         }
         Ok(())
     }
 
-    fn already_added_error(&self, err: &RpcError<TransportErrorKind>) -> Option<Address> {
-        err.as_error_resp()
-            .and_then(|payload| payload.as_decoded_interface_error::<CiphertextCommitsErrors>())
-            .map(|error| match error {
-                CiphertextCommitsErrors::CoprocessorAlreadyAdded(c) => c.coprocessorTxSenderAddress,
-            })
-    }
-
     async fn set_txn_is_sent(
         &self,
         handle: &[u8],
         txn_hash: Option<&[u8]>,
         txn_block_number: Option<i64>,
         src_transaction_id: Option<Vec<u8>>,
-    ) -> anyhow::Result<()> {
-        sqlx::query!(
-            "UPDATE ciphertext_digest
+    ) -> Result<(), FhevmEngineError> {
+        run_query(
+            "add_ciphertext.set_txn_is_sent",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE ciphertext_digest
             SET
                 txn_is_sent = true,
                 txn_hash = $1,
                 txn_block_number = $2
             WHERE handle = $3",
-            txn_hash,
-            txn_block_number,
-            handle
+                    txn_hash,
+                    txn_block_number,
+                    handle
+                )
+                .execute(&self.db_pool)
+            },
         )
-        .execute(&self.db_pool)
         .await?;
 
         if let Some(txn_hash) = src_transaction_id {
@@ -222,6 +213,7 @@ impl<P: Provider<Ethereum> + Clone + 'static> AddCiphertextOperation<P> {
         provider: NonceManagedProvider<P>,
         conf: crate::ConfigSettings,
         gas: Option<u64>,
+        gw_chain_id: u64,
         db_pool: Pool<Postgres>,
     ) -> Self {
         info!(
@@ -230,12 +222,16 @@ impl<P: Provider<Ethereum> + Clone + 'static> AddCiphertextOperation<P> {
             "Creating AddCiphertextOperation"
         );
 
+        let in_flight = InFlightLimit::new("add_ciphertext", conf.add_ciphertexts_max_in_flight);
         Self {
             db_pool,
             ciphertext_commits_address,
             provider,
             conf,
             gas,
+            gw_chain_id,
+            gas_estimates: GasEstimates::default(),
+            in_flight,
         }
     }
 
@@ -244,7 +240,7 @@ impl<P: Provider<Ethereum> + Clone + 'static> AddCiphertextOperation<P> {
         handle: &[u8],
         err: &str,
         current_retry_count: i32,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), FhevmEngineError> {
         let compact_hex_handle = compact_hex(handle);
         if current_retry_count == (self.conf.add_ciphertexts_max_retries as i32) - 1 {
             error!(
@@ -260,17 +256,23 @@ impl<P: Provider<Ethereum> + Clone + 'static> AddCiphertextOperation<P> {
                 "Updating limited retries count"
             );
         }
-        sqlx::query!(
-            "UPDATE ciphertext_digest
+        run_query(
+            "add_ciphertext.increment_txn_limited_retries_count",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE ciphertext_digest
             SET
             txn_limited_retries_count = txn_limited_retries_count + 1,
             txn_last_error = $1,
             txn_last_error_at = NOW()
             WHERE handle = $2",
-            err,
-            handle,
+                    err,
+                    handle,
+                )
+                .execute(&self.db_pool)
+            },
         )
-        .execute(&self.db_pool)
         .await?;
         Ok(())
     }
@@ -280,7 +282,7 @@ impl<P: Provider<Ethereum> + Clone + 'static> AddCiphertextOperation<P> {
         handle: &[u8],
         err: &str,
         current_unlimited_retries_count: i32,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), FhevmEngineError> {
         let compact_hex_handle = compact_hex(handle);
         if current_unlimited_retries_count >= (self.conf.review_after_unlimited_retries as i32) - 1
         {
@@ -297,17 +299,98 @@ impl<P: Provider<Ethereum> + Clone + 'static> AddCiphertextOperation<P> {
                 "Updating unlimited retries count"
             );
         }
-        sqlx::query!(
-            "UPDATE ciphertext_digest
+        run_query(
+            "add_ciphertext.increment_txn_unlimited_retries_count",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE ciphertext_digest
             SET
             txn_unlimited_retries_count = txn_unlimited_retries_count + 1,
             txn_last_error = $1,
             txn_last_error_at = NOW()
             WHERE handle = $2",
+                    err,
+                    handle,
+                )
+                .execute(&self.db_pool)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<P> SendErrorHandler for AddCiphertextOperation<P>
+where
+    P: alloy::providers::Provider<Ethereum> + Clone + 'static,
+{
+    type Key = [u8];
+    type Errors = CiphertextCommitsErrors;
+
+    const ERRORS: ErrorTable = ADD_CIPHERTEXT_ERRORS;
+    const SKIP_REASON: &'static str = "Coprocessor has already added the ciphertext commit";
+
+    fn log_key(handle: &[u8]) -> String {
+        compact_hex(handle)
+    }
+
+    fn fail_counter(&self) -> &IntCounter {
+        &ADD_CIPHERTEXT_MATERIAL_FAIL_COUNTER
+    }
+
+    async fn skip(&self, attempt: SendAttempt<'_, [u8]>) -> Result<(), FhevmEngineError> {
+        self.set_txn_is_sent(attempt.key, None, None, attempt.src_transaction_id)
+            .await
+    }
+
+    async fn retry_forever(
+        &self,
+        attempt: SendAttempt<'_, [u8]>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        self.increment_txn_unlimited_retries_count(
+            attempt.key,
             err,
-            handle,
+            attempt.unlimited_retries_count,
         )
-        .execute(&self.db_pool)
+        .await
+    }
+
+    async fn retry_bounded(
+        &self,
+        attempt: SendAttempt<'_, [u8]>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        self.increment_txn_limited_retries_count(attempt.key, err, attempt.limited_retries_count)
+            .await
+    }
+
+    async fn dead_letter(
+        &self,
+        attempt: SendAttempt<'_, [u8]>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        error!(
+            action = REVIEW,
+            handle = compact_hex(attempt.key),
+            "Adding ciphertext can't succeed, not retrying"
+        );
+        run_query("add_ciphertext.dead_letter", &self.conf.db_query, || {
+            sqlx::query!(
+                "UPDATE ciphertext_digest
+            SET
+            txn_limited_retries_count = GREATEST(txn_limited_retries_count, $1),
+            txn_last_error = $2,
+            txn_last_error_at = NOW()
+            WHERE handle = $3",
+                self.conf.add_ciphertexts_max_retries as i32,
+                err,
+                attempt.key,
+            )
+            .execute(&self.db_pool)
+        })
         .await?;
         Ok(())
     }
@@ -322,11 +405,22 @@ where
         &self.conf.add_ciphertexts_db_channel
     }
 
-    async fn execute(&self) -> anyhow::Result<bool> {
+    fn expected_selectors(&self) -> (Address, Vec<ExpectedSelector>) {
+        (
+            self.ciphertext_commits_address,
+            vec![
+                ExpectedSelector::function::<CiphertextCommits::addCiphertextMaterialCall>(),
+                ExpectedSelector::error::<CiphertextCommits::CoprocessorAlreadyAdded>(),
+            ],
+        )
+    }
+
+    async fn execute(&self) -> Result<bool, FhevmEngineError> {
         // The service responsible for populating the ciphertext_digest table must
         // ensure that ciphertext and ciphertext128 are non-null only after the
         // ciphertexts have been successfully uploaded to AWS S3 buckets.
-        let rows = sqlx::query!(
+        let rows = run_query("add_ciphertext.execute", &self.conf.db_query, || {
+            sqlx::query!(
             "
             SELECT handle, ciphertext, ciphertext128, tenant_id, txn_limited_retries_count, txn_unlimited_retries_count, transaction_id
             FROM ciphertext_digest
@@ -339,6 +433,7 @@ where
             self.conf.add_ciphertexts_batch_limit as i64,
         )
         .fetch_all(&self.db_pool)
+        })
         .await?;
 
         let ciphertext_manager =
@@ -375,7 +470,7 @@ where
                     }
                 };
 
-            let handle_bytes32 = FixedBytes::from(try_into_array::<32>(handle.clone())?);
+            let handle_bytes32: FixedBytes<32> = TypedHandle::try_from(handle.as_slice())?.into();
             let key_id = U256::from_be_bytes(tenant_info.key_id);
 
             info!(
@@ -410,7 +505,9 @@ where
             t.end();
 
             let operation = self.clone();
+            let permit = self.in_flight.acquire().await;
             join_set.spawn(async move {
+                let _permit = permit;
                 operation
                     .send_transaction(
                         &row.handle,
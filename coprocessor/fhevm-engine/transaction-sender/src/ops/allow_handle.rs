@@ -5,13 +5,15 @@ use std::{
 };
 
 use crate::{
+    error_class::{handle_send_error, ErrorTable, RetryClass, SendAttempt, SendErrorHandler},
+    gas_spend,
     metrics::{ALLOW_HANDLE_FAIL_COUNTER, ALLOW_HANDLE_SUCCESS_COUNTER},
     nonce_managed_provider::NonceManagedProvider,
-    ops::common::try_into_array,
-    overprovision_gas_limit::try_overprovision_gas_limit,
-    REVIEW,
+    overprovision_gas_limit::{try_overprovision_gas_limit, GasEstimates},
+    receipts, REVIEW,
 };
 
+use super::common::{join_chunk, InFlightLimit};
 use super::TransactionOperation;
 use alloy::{
     network::{Ethereum, TransactionBuilder},
@@ -19,14 +21,22 @@ use alloy::{
     providers::Provider,
     rpc::types::TransactionRequest,
     sol,
-    transports::{RpcError, TransportErrorKind},
+    sol_types::SolError,
 };
-use anyhow::bail;
 use async_trait::async_trait;
 use fhevm_engine_common::{
-    telemetry, tenant_keys::query_tenant_info, types::AllowEvents, utils::compact_hex,
+    db_query::run_query,
+    error::FhevmEngineError,
+    handle::TypedHandle,
+    telemetry::{self, KeyValue},
+    tenant_keys::query_tenant_info,
+    types::AllowEvents,
+    utils::compact_hex,
+    write_batcher::{BatchWriter, WriteBatcher},
 };
-use sqlx::{Pool, Postgres};
+use fhevm_gateway_bindings::drift::ExpectedSelector;
+use prometheus::IntCounter;
+use sqlx::{PgConnection, Pool, Postgres};
 use tokio::task::JoinSet;
 use tracing::{debug, error, info, warn};
 use MultichainACL::MultichainACLErrors;
@@ -37,7 +47,19 @@ sol!(
     "artifacts/MultichainACL.sol/MultichainACL.json"
 );
 
-struct Key {
+/// The ACL entry was already added by a previous attempt.
+const ALLOW_HANDLE_ERRORS: ErrorTable = &[
+    (
+        MultichainACL::CoprocessorAlreadyAllowedAccount::SELECTOR,
+        RetryClass::Skip,
+    ),
+    (
+        MultichainACL::CoprocessorAlreadyAllowedPublicDecrypt::SELECTOR,
+        RetryClass::Skip,
+    ),
+];
+
+pub(crate) struct Key {
     handle: Vec<u8>,
     account_addr: String,
     tenant_id: i32,
@@ -57,13 +79,56 @@ impl Display for Key {
     }
 }
 
+/// Marks an allowed handle as sent, with the transaction if one was mined.
+struct TxnSent {
+    handle: Vec<u8>,
+    account_addr: String,
+    tenant_id: i32,
+    txn_hash: Option<Vec<u8>>,
+    txn_block_number: Option<i64>,
+}
+
+struct TxnSentWriter;
+
+#[async_trait]
+impl BatchWriter for TxnSentWriter {
+    type Item = TxnSent;
+
+    async fn write(&self, conn: &mut PgConnection, items: &[TxnSent]) -> Result<(), sqlx::Error> {
+        for item in items {
+            sqlx::query!(
+                "UPDATE allowed_handles
+                     SET
+                        txn_is_sent = true,
+                        txn_hash = $1,
+                        txn_block_number = $2
+                     WHERE handle = $3
+                     AND account_address = $4
+                     AND tenant_id = $5",
+                item.txn_hash,
+                item.txn_block_number,
+                item.handle,
+                item.account_addr,
+                item.tenant_id
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct MultichainACLOperation<P: Provider<Ethereum> + Clone + 'static> {
     multichain_acl_address: Address,
     provider: NonceManagedProvider<P>,
     conf: crate::ConfigSettings,
     gas: Option<u64>,
+    gw_chain_id: u64,
     db_pool: Pool<Postgres>,
+    txn_sent: WriteBatcher<TxnSent>,
+    gas_estimates: GasEstimates,
+    in_flight: InFlightLimit,
 }
 
 impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
@@ -77,75 +142,77 @@ impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
         current_limited_retries_count: i32,
         current_unlimited_retries_count: i32,
         src_transaction_id: Option<Vec<u8>>,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), FhevmEngineError> {
         let h = compact_hex(&key.handle);
 
         info!(handle = h, "Processing transaction");
-        let _t = telemetry::tracer("call_allow_account", &src_transaction_id);
+        let t = telemetry::tracer("call_allow_account", &src_transaction_id);
+        t.set_attribute("handle", h.clone());
+        t.set_attribute("event_type", format!("{:?}", key.event_type));
+        t.set_value("gw_chain_id", self.gw_chain_id as i64);
+        t.set_value("unlimited_retries", current_unlimited_retries_count as i64);
+        t.set_value("limited_retries", current_limited_retries_count as i64);
 
         let overprovisioned_txn_req = try_overprovision_gas_limit(
-            txn_request,
+            self.provider.with_sender(txn_request),
             self.provider.inner(),
-            self.conf.gas_limit_overprovision_percent,
+            &self.conf,
+            &self.gas_estimates,
         )
         .await;
+        if let Some(gas_limit) = overprovisioned_txn_req.gas {
+            t.set_value("gas_limit", gas_limit as i64);
+        }
         let transaction = match self
             .provider
             .send_transaction(overprovisioned_txn_req.clone())
             .await
         {
-            Ok(txn) => txn,
-            Err(e) if self.already_allowed_error(&e).is_some() => {
-                warn!(
-                    address = ?self.already_allowed_error(&e),
-                    handle = h,
-                    "Coprocessor has already added the ACL entry"
+            Ok(txn) => {
+                t.add_event(
+                    "sent",
+                    vec![KeyValue::new("txn_hash", txn.tx_hash().to_string())],
                 );
-                self.set_txn_is_sent(key, None, None, src_transaction_id)
-                    .await?;
-                return Ok(());
-            }
-            // Consider transport retryable errors, BackendGone and local usage errors as something that must be retried infinitely.
-            // Local usage are included as they might be transient due to external AWS KMS signers.
-            Err(e)
-                if matches!(&e, RpcError::Transport(inner) if inner.is_retry_err() || matches!(inner, TransportErrorKind::BackendGone))
-                    || matches!(&e, RpcError::LocalUsageError(_)) =>
-            {
-                ALLOW_HANDLE_FAIL_COUNTER.inc();
-                warn!(
-                    transaction_request = ?overprovisioned_txn_req,
-                    error = %e,
-                    handle = h,
-                    "Transaction sending failed with unlimited retry error"
-                );
-                self.increment_txn_unlimited_retries_count(
-                    key,
-                    &e.to_string(),
-                    current_unlimited_retries_count,
-                )
-                .await?;
-                bail!(e);
+                txn
             }
             Err(e) => {
-                ALLOW_HANDLE_FAIL_COUNTER.inc();
-                warn!(
-                    transaction_request = ?overprovisioned_txn_req,
-                    error = %e,
-                    handle = h,
-                    "Transaction sending failed"
-                );
-                self.increment_txn_limited_retries_count(
+                let attempt = SendAttempt {
                     key,
-                    &e.to_string(),
-                    current_limited_retries_count,
-                )
-                .await?;
-                bail!(e);
+                    transaction_request: &overprovisioned_txn_req,
+                    limited_retries_count: current_limited_retries_count,
+                    unlimited_retries_count: current_unlimited_retries_count,
+                    src_transaction_id,
+                };
+                let error = e.to_string();
+                let (class, result) = handle_send_error(self, attempt, e).await;
+                let retry = match class {
+                    RetryClass::Skip => {
+                        t.add_event("already_allowed", vec![]);
+                        t.end();
+                        return result;
+                    }
+                    RetryClass::RetryForever => "unlimited",
+                    RetryClass::RetryBounded => "limited",
+                    RetryClass::DeadLetter => "none",
+                };
+                t.add_event("send_failed", vec![KeyValue::new("retry", retry)]);
+                t.end_with_err(error);
+                return result;
             }
         };
 
         // We assume that if we were able to send the transaction, we will be able to get a receipt, eventually. If there is a transport
         // error in-between, we rely on the retry logic to handle it.
+        let s = t.child_span_with_attributes(
+            "wait_receipt",
+            vec![
+                KeyValue::new("txn_hash", transaction.tx_hash().to_string()),
+                KeyValue::new(
+                    "required_confirmations",
+                    self.conf.required_txn_confirmations as i64,
+                ),
+            ],
+        );
         let receipt = match transaction
             .with_timeout(Some(Duration::from_secs(
                 self.conf.txn_receipt_timeout_secs as u64,
@@ -154,8 +221,13 @@ impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
             .get_receipt()
             .await
         {
-            Ok(receipt) => receipt,
+            Ok(receipt) => {
+                telemetry::end_span(s);
+                receipt
+            }
             Err(e) => {
+                telemetry::end_span_with_err(s, e.to_string());
+                t.end_with_err(e.to_string());
                 ALLOW_HANDLE_FAIL_COUNTER.inc();
                 error!(error = %e, "Getting receipt failed");
                 self.increment_txn_limited_retries_count(
@@ -164,10 +236,34 @@ impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
                     current_limited_retries_count,
                 )
                 .await?;
-                return Err(anyhow::Error::new(e));
+                return Err(e.into());
             }
         };
 
+        let operation = match key.event_type {
+            AllowEvents::AllowedAccount => gas_spend::OP_ALLOW_ACCOUNT,
+            AllowEvents::AllowedForDecryption => gas_spend::OP_ALLOW_PUBLIC_DECRYPT,
+        };
+        receipts::record_receipt::<MultichainACLErrors, _>(
+            &self.db_pool,
+            &self.provider,
+            operation,
+            self.gw_chain_id,
+            &[format!(
+                "{}:{}",
+                alloy::hex::encode_prefixed(&key.handle),
+                key.account_addr
+            )],
+            &overprovisioned_txn_req,
+            &receipt,
+        )
+        .await;
+
+        if let Some(block_number) = receipt.block_number {
+            t.set_value("block_number", block_number as i64);
+        }
+        t.set_value("gas_used", receipt.gas_used as i64);
+        t.set_value("success", receipt.status());
         if receipt.status() {
             self.set_txn_is_sent(
                 key,
@@ -183,7 +279,9 @@ impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
                 "Allow txn succeeded"
             );
             ALLOW_HANDLE_SUCCESS_COUNTER.inc();
+            t.end();
         } else {
+            t.end_with_err("receipt status = false".to_owned());
             ALLOW_HANDLE_FAIL_COUNTER.inc();
             error!(
                 transaction_hash = %receipt.transaction_hash,
@@ -199,49 +297,38 @@ impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
             )
             .await?;
 
-            return Err(anyhow::anyhow!(
-                "Transaction {} failed with status {}, handle: {}",
-                receipt.transaction_hash,
-                receipt.status(),
-                h,
-            ));
+            return Err(FhevmEngineError::ContractRevert {
+                reason: format!(
+                    "Transaction {} failed with status {}, handle: {}",
+                    receipt.transaction_hash,
+                    receipt.status(),
+                    h,
+                ),
+                data: None,
+            });
         }
         Ok(())
     }
 
-    fn already_allowed_error(&self, err: &RpcError<TransportErrorKind>) -> Option<Address> {
-        err.as_error_resp()
-            .and_then(|payload| payload.as_decoded_interface_error::<MultichainACLErrors>())
-            .map(|error| match error {
-                MultichainACLErrors::CoprocessorAlreadyAllowedAccount(c) => c.txSender, /* coprocessor address */
-                MultichainACLErrors::CoprocessorAlreadyAllowedPublicDecrypt(c) => c.txSender,
-            })
-    }
-
     async fn set_txn_is_sent(
         &self,
         key: &Key,
         txn_hash: Option<&[u8]>,
         txn_block_number: Option<i64>,
         src_transaction_id: Option<Vec<u8>>,
-    ) -> anyhow::Result<()> {
-        sqlx::query!(
-            "UPDATE allowed_handles
-                 SET
-                    txn_is_sent = true,
-                    txn_hash = $1,
-                    txn_block_number = $2
-                 WHERE handle = $3
-                 AND account_address = $4
-                 AND tenant_id = $5",
-            txn_hash,
-            txn_block_number,
-            key.handle,
-            key.account_addr,
-            key.tenant_id
-        )
-        .execute(&self.db_pool)
-        .await?;
+    ) -> Result<(), FhevmEngineError> {
+        // Concurrent transactions are marked as sent together
+        self.txn_sent
+            .write(TxnSent {
+                handle: key.handle.clone(),
+                account_addr: key.account_addr.clone(),
+                tenant_id: key.tenant_id,
+                txn_hash: txn_hash.map(<[u8]>::to_vec),
+                txn_block_number,
+            })
+            .await
+            .committed()
+            .await?;
 
         telemetry::try_end_l1_transaction(&self.db_pool, &src_transaction_id.unwrap_or_default())
             .await?;
@@ -256,29 +343,50 @@ impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
         provider: NonceManagedProvider<P>,
         conf: crate::ConfigSettings,
         gas: Option<u64>,
+        gw_chain_id: u64,
         db_pool: Pool<Postgres>,
     ) -> Self {
         info!(
             gas = gas.unwrap_or(0),
             multichain_acl_address = %multichain_acl_address,
+            multichain_acl_addresses = ?conf.multichain_acl_addresses,
             "Creating MultichainACLOperation"
         );
 
+        let txn_sent = WriteBatcher::spawn(
+            db_pool.clone(),
+            TxnSentWriter,
+            conf.allow_handle_write_batch,
+        );
+        let in_flight = InFlightLimit::new("allow_handle", conf.allow_handle_max_in_flight);
         Self {
             multichain_acl_address,
             provider,
             conf,
             gas,
+            gw_chain_id,
             db_pool,
+            txn_sent,
+            gas_estimates: GasEstimates::default(),
+            in_flight,
         }
     }
 
+    /// MultichainACL of the handles of a host chain, the default one unless overridden for the chain.
+    fn multichain_acl_address(&self, host_chain_id: i64) -> Address {
+        u64::try_from(host_chain_id)
+            .ok()
+            .and_then(|chain_id| self.conf.multichain_acl_addresses.get(&chain_id))
+            .copied()
+            .unwrap_or(self.multichain_acl_address)
+    }
+
     async fn increment_txn_limited_retries_count(
         &self,
         key: &Key,
         err: &str,
         current_limited_retries_count: i32,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), FhevmEngineError> {
         debug!("Updating retry count for key {}", key);
 
         if current_limited_retries_count == (self.conf.allow_handle_max_retries as i32) - 1 {
@@ -296,8 +404,12 @@ impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
             );
         }
 
-        sqlx::query!(
-            "UPDATE allowed_handles
+        run_query(
+            "allow_handle.increment_txn_limited_retries_count",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE allowed_handles
             SET
             txn_limited_retries_count = txn_limited_retries_count + 1,
             txn_last_error = $1,
@@ -305,12 +417,14 @@ impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
             WHERE handle = $2
             AND account_address = $3
             AND tenant_id = $4",
-            err,
-            key.handle,
-            key.account_addr,
-            key.tenant_id
+                    err,
+                    key.handle,
+                    key.account_addr,
+                    key.tenant_id
+                )
+                .execute(&self.db_pool)
+            },
         )
-        .execute(&self.db_pool)
         .await?;
         Ok(())
     }
@@ -320,7 +434,7 @@ impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
         key: &Key,
         err: &str,
         current_unlimited_retries_count: i32,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), FhevmEngineError> {
         debug!("Updating unlimited retries count, {}", key);
 
         if current_unlimited_retries_count == (self.conf.review_after_unlimited_retries as i32) - 1
@@ -339,8 +453,12 @@ impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
             );
         }
 
-        sqlx::query!(
-            "UPDATE allowed_handles
+        run_query(
+            "allow_handle.increment_txn_unlimited_retries_count",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE allowed_handles
             SET
             txn_unlimited_retries_count = txn_unlimited_retries_count + 1,
             txn_last_error = $1,
@@ -348,12 +466,94 @@ impl<P: Provider<Ethereum> + Clone + 'static> MultichainACLOperation<P> {
             WHERE handle = $2
             AND account_address = $3
             AND tenant_id = $4",
+                    err,
+                    key.handle,
+                    key.account_addr,
+                    key.tenant_id
+                )
+                .execute(&self.db_pool)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<P> SendErrorHandler for MultichainACLOperation<P>
+where
+    P: alloy::providers::Provider<Ethereum> + Clone + 'static,
+{
+    type Key = Key;
+    type Errors = MultichainACLErrors;
+
+    const ERRORS: ErrorTable = ALLOW_HANDLE_ERRORS;
+    const SKIP_REASON: &'static str = "Coprocessor has already added the ACL entry";
+
+    fn log_key(key: &Key) -> String {
+        key.to_string()
+    }
+
+    fn fail_counter(&self) -> &IntCounter {
+        &ALLOW_HANDLE_FAIL_COUNTER
+    }
+
+    async fn skip(&self, attempt: SendAttempt<'_, Key>) -> Result<(), FhevmEngineError> {
+        self.set_txn_is_sent(attempt.key, None, None, attempt.src_transaction_id)
+            .await
+    }
+
+    async fn retry_forever(
+        &self,
+        attempt: SendAttempt<'_, Key>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        self.increment_txn_unlimited_retries_count(
+            attempt.key,
             err,
-            key.handle,
-            key.account_addr,
-            key.tenant_id
+            attempt.unlimited_retries_count,
         )
-        .execute(&self.db_pool)
+        .await
+    }
+
+    async fn retry_bounded(
+        &self,
+        attempt: SendAttempt<'_, Key>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        self.increment_txn_limited_retries_count(attempt.key, err, attempt.limited_retries_count)
+            .await
+    }
+
+    async fn dead_letter(
+        &self,
+        attempt: SendAttempt<'_, Key>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        let key = attempt.key;
+        error!(
+            action = REVIEW,
+            key = %key,
+            "Adding the ACL entry can't succeed, not retrying"
+        );
+        run_query("allow_handle.dead_letter", &self.conf.db_query, || {
+            sqlx::query!(
+                "UPDATE allowed_handles
+            SET
+            txn_limited_retries_count = GREATEST(txn_limited_retries_count, $1),
+            txn_last_error = $2,
+            txn_last_error_at = NOW()
+            WHERE handle = $3
+            AND account_address = $4
+            AND tenant_id = $5",
+                self.conf.allow_handle_max_retries as i32,
+                err,
+                key.handle,
+                key.account_addr,
+                key.tenant_id
+            )
+            .execute(&self.db_pool)
+        })
         .await?;
         Ok(())
     }
@@ -368,8 +568,21 @@ where
         &self.conf.allow_handle_db_channel
     }
 
-    async fn execute(&self) -> anyhow::Result<bool> {
-        let rows = sqlx::query!(
+    fn expected_selectors(&self) -> (Address, Vec<ExpectedSelector>) {
+        (
+            self.multichain_acl_address,
+            vec![
+                ExpectedSelector::function::<MultichainACL::allowAccountCall>(),
+                ExpectedSelector::function::<MultichainACL::allowPublicDecryptCall>(),
+                ExpectedSelector::error::<MultichainACL::CoprocessorAlreadyAllowedAccount>(),
+                ExpectedSelector::error::<MultichainACL::CoprocessorAlreadyAllowedPublicDecrypt>(),
+            ],
+        )
+    }
+
+    async fn execute(&self) -> Result<bool, FhevmEngineError> {
+        let rows = run_query("allow_handle.execute", &self.conf.db_query, || {
+            sqlx::query!(
             "
             SELECT handle, tenant_id, account_address, event_type, txn_limited_retries_count, txn_unlimited_retries_count, transaction_id
             FROM allowed_handles 
@@ -381,14 +594,17 @@ where
             self.conf.allow_handle_batch_limit as i32,
         )
         .fetch_all(&self.db_pool)
+        })
         .await?;
 
-        let multichain_acl = MultichainACL::new(self.multichain_acl_address, self.provider.inner());
-
         info!(rows_count = rows.len(), "Selected rows to process");
 
         let maybe_has_more_work = rows.len() == self.conf.allow_handle_batch_limit as usize;
 
+        let chunk_size = match self.conf.allow_handle_send_chunk_size {
+            0 => usize::MAX,
+            n => n as usize,
+        };
         let mut join_set = JoinSet::new();
         for row in rows.into_iter() {
             let src_transaction_id = row.transaction_id.clone();
@@ -406,6 +622,8 @@ where
             };
 
             let chain_id = tenant.chain_id;
+            let multichain_acl =
+                MultichainACL::new(self.multichain_acl_address(chain_id), self.provider.inner());
             let handle = row.handle.clone();
             let h_as_hex = compact_hex(&handle);
             let event_type = match AllowEvents::try_from(row.event_type) {
@@ -429,7 +647,7 @@ where
                 "Allow handle"
             );
 
-            let handle_bytes32 = FixedBytes::from(try_into_array::<32>(handle)?);
+            let handle_bytes32: FixedBytes<32> = TypedHandle::try_from(handle)?.into();
             let extra_data = Bytes::new();
 
             let txn_request = match event_type {
@@ -481,7 +699,9 @@ where
             t.end();
 
             let operation = self.clone();
+            let permit = self.in_flight.acquire().await;
             join_set.spawn(async move {
+                let _permit = permit;
                 operation
                     .send_transaction(
                         &key,
@@ -492,12 +712,237 @@ where
                     )
                     .await
             });
-        }
 
-        while let Some(res) = join_set.join_next().await {
-            res??;
+            // Later chunks are not sent if a transaction of this one fails
+            if join_set.len() == chunk_size {
+                join_chunk(&mut join_set).await?;
+            }
         }
 
+        join_chunk(&mut join_set).await?;
+
         Ok(maybe_has_more_work)
     }
 }
+
+#[cfg(all(test, feature = "test-utils"))]
+mod tests {
+    use alloy::{
+        rpc::json_rpc::ErrorPayload,
+        transports::{TransportError, TransportErrorKind},
+    };
+    use serial_test::serial;
+    use test_harness::{
+        db_utils::insert_random_tenant,
+        instance::{setup_test_db, DBInstance, ImportMode},
+    };
+
+    use super::*;
+    use crate::{
+        signing_policy::PolicyDenial,
+        test_utils::{
+            already_allowed_account_error, backend_gone_error, revert_error, MockProvider,
+        },
+        ConfigSettings,
+    };
+
+    const ACCOUNT: Address = Address::repeat_byte(0xaa);
+
+    enum Scripted {
+        Error(ErrorPayload),
+        Transport(TransportError),
+    }
+
+    struct Row {
+        txn_is_sent: bool,
+        txn_limited_retries_count: i32,
+        txn_unlimited_retries_count: i32,
+        txn_last_error: Option<String>,
+    }
+
+    struct Setup {
+        operation: MultichainACLOperation<alloy::providers::RootProvider<Ethereum>>,
+        mock: MockProvider,
+        key: Key,
+        _db_instance: DBInstance,
+    }
+
+    impl Setup {
+        async fn new() -> anyhow::Result<Self> {
+            let db_instance = setup_test_db(ImportMode::None)
+                .await
+                .map_err(|e| anyhow::anyhow!("{e}"))?;
+            let pool = Pool::<Postgres>::connect(db_instance.db_url()).await?;
+            let tenant_id = insert_random_tenant(&pool).await?;
+            let key = Key {
+                handle: vec![7; 32],
+                account_addr: ACCOUNT.to_string(),
+                tenant_id,
+                event_type: AllowEvents::AllowedAccount,
+            };
+            sqlx::query(
+                "INSERT INTO allowed_handles (tenant_id, handle, account_address, event_type)
+                 VALUES ($1, $2, $3, $4)",
+            )
+            .bind(key.tenant_id)
+            .bind(&key.handle)
+            .bind(&key.account_addr)
+            .bind(key.event_type as i16)
+            .execute(&pool)
+            .await?;
+
+            let mock = MockProvider::new();
+            let operation = MultichainACLOperation::new(
+                Address::ZERO,
+                NonceManagedProvider::new(mock.provider(), None),
+                ConfigSettings::default(),
+                None,
+                0,
+                pool,
+            );
+            Ok(Self {
+                operation,
+                mock,
+                key,
+                _db_instance: db_instance,
+            })
+        }
+
+        /// Sends a transaction failing with the scripted error and handles the error.
+        async fn fail_with(
+            &self,
+            scripted: Scripted,
+        ) -> (RetryClass, Result<(), FhevmEngineError>) {
+            match scripted {
+                Scripted::Error(payload) => self.mock.push_error("eth_sendTransaction", payload),
+                Scripted::Transport(err) => {
+                    self.mock.push_transport_error("eth_sendTransaction", err)
+                }
+            }
+            let transaction_request = TransactionRequest::default();
+            let err = self
+                .mock
+                .provider()
+                .send_transaction(transaction_request.clone())
+                .await
+                .expect_err("scripted error");
+            let attempt = SendAttempt {
+                key: &self.key,
+                transaction_request: &transaction_request,
+                limited_retries_count: 0,
+                unlimited_retries_count: 0,
+                src_transaction_id: None,
+            };
+            handle_send_error(&self.operation, attempt, err).await
+        }
+
+        async fn row(&self) -> anyhow::Result<Row> {
+            let (
+                txn_is_sent,
+                txn_limited_retries_count,
+                txn_unlimited_retries_count,
+                txn_last_error,
+            ) = sqlx::query_as::<_, (bool, i32, i32, Option<String>)>(
+                "SELECT txn_is_sent, txn_limited_retries_count, txn_unlimited_retries_count,
+                        txn_last_error
+                     FROM allowed_handles
+                     WHERE handle = $1 AND account_address = $2 AND tenant_id = $3",
+            )
+            .bind(&self.key.handle)
+            .bind(&self.key.account_addr)
+            .bind(self.key.tenant_id)
+            .fetch_one(&self.operation.db_pool)
+            .await?;
+            Ok(Row {
+                txn_is_sent,
+                txn_limited_retries_count,
+                txn_unlimited_retries_count,
+                txn_last_error,
+            })
+        }
+    }
+
+    #[tokio::test]
+    #[serial(db)]
+    async fn already_allowed_is_skipped_and_marked_as_sent() -> anyhow::Result<()> {
+        let setup = Setup::new().await?;
+        let (class, result) = setup
+            .fail_with(Scripted::Error(already_allowed_account_error(
+                FixedBytes::from_slice(&setup.key.handle),
+                ACCOUNT,
+                Address::ZERO,
+            )))
+            .await;
+        assert_eq!(class, RetryClass::Skip);
+        assert!(result.is_ok());
+
+        let row = setup.row().await?;
+        assert!(row.txn_is_sent);
+        assert_eq!(row.txn_limited_retries_count, 0);
+        assert_eq!(row.txn_unlimited_retries_count, 0);
+        assert_eq!(row.txn_last_error, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial(db)]
+    async fn backend_gone_is_retried_without_limit() -> anyhow::Result<()> {
+        let setup = Setup::new().await?;
+        let (class, result) = setup
+            .fail_with(Scripted::Transport(backend_gone_error()))
+            .await;
+        assert_eq!(class, RetryClass::RetryForever);
+        assert!(result.is_err());
+
+        let row = setup.row().await?;
+        assert!(!row.txn_is_sent);
+        assert_eq!(row.txn_limited_retries_count, 0);
+        assert_eq!(row.txn_unlimited_retries_count, 1);
+        assert!(row.txn_last_error.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial(db)]
+    async fn unknown_revert_is_retried_with_limit() -> anyhow::Result<()> {
+        let setup = Setup::new().await?;
+        let (class, result) = setup
+            .fail_with(Scripted::Error(revert_error(vec![0xde, 0xad, 0xbe, 0xef])))
+            .await;
+        assert_eq!(class, RetryClass::RetryBounded);
+        assert!(result.is_err());
+
+        let row = setup.row().await?;
+        assert!(!row.txn_is_sent);
+        assert_eq!(row.txn_limited_retries_count, 1);
+        assert_eq!(row.txn_unlimited_retries_count, 0);
+        assert!(row.txn_last_error.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial(db)]
+    async fn policy_denial_is_dead_lettered() -> anyhow::Result<()> {
+        let setup = Setup::new().await?;
+        let denial = PolicyDenial {
+            rule: "test_rule",
+            reason: "denied by the test".to_owned(),
+        };
+        let (class, result) = setup
+            .fail_with(Scripted::Transport(TransportErrorKind::custom(denial)))
+            .await;
+        assert_eq!(class, RetryClass::DeadLetter);
+        assert!(result.is_err());
+
+        // Exhausted, not picked up again
+        let row = setup.row().await?;
+        assert!(!row.txn_is_sent);
+        assert_eq!(
+            row.txn_limited_retries_count,
+            setup.operation.conf.allow_handle_max_retries as i32
+        );
+        assert_eq!(row.txn_unlimited_retries_count, 0);
+        assert!(row.txn_last_error.unwrap().contains("denied by the test"));
+        Ok(())
+    }
+}
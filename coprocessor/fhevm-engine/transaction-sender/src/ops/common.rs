@@ -1,15 +1,83 @@
-use anyhow::{anyhow, Result};
+use crate::metrics::IN_FLIGHT_TXNS;
+use fhevm_engine_common::error::FhevmEngineError;
+use prometheus::IntGauge;
 use std::convert::TryInto;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
 
-pub(crate) fn try_into_array<const SIZE: usize>(vec: Vec<u8>) -> Result<[u8; SIZE]> {
+pub(crate) fn try_into_array<const SIZE: usize>(
+    vec: Vec<u8>,
+) -> Result<[u8; SIZE], FhevmEngineError> {
     if vec.len() != SIZE {
-        return Err(anyhow!(
+        return Err(FhevmEngineError::Validation(format!(
             "invalid len, expected {} but got {}",
             SIZE,
             vec.len()
-        ));
+        )));
     }
 
     vec.try_into()
-        .map_err(|_| anyhow!("Failed to convert Vec to array"))
+        .map_err(|_| FhevmEngineError::Validation("Failed to convert Vec to array".to_owned()))
+}
+
+/// Bounds the number of transactions an operation sends concurrently, 0 meaning unbounded.
+#[derive(Clone)]
+pub(crate) struct InFlightLimit {
+    semaphore: Arc<Semaphore>,
+    gauge: IntGauge,
+}
+
+/// Held by a transaction while it is being sent.
+pub(crate) struct InFlightPermit {
+    _permit: OwnedSemaphorePermit,
+    gauge: IntGauge,
+}
+
+impl InFlightLimit {
+    pub(crate) fn new(operation: &str, max_in_flight: u32) -> Self {
+        let permits = match max_in_flight {
+            0 => Semaphore::MAX_PERMITS,
+            n => n as usize,
+        };
+        Self {
+            semaphore: Arc::new(Semaphore::new(permits)),
+            gauge: IN_FLIGHT_TXNS.with_label_values(&[operation]),
+        }
+    }
+
+    /// Waits until fewer than the maximum transactions are in flight.
+    pub(crate) async fn acquire(&self) -> InFlightPermit {
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("in-flight semaphore is never closed");
+        self.gauge.inc();
+        InFlightPermit {
+            _permit: permit,
+            gauge: self.gauge.clone(),
+        }
+    }
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
+
+/// Waits for all the transactions of a chunk, returning the first error. The other transactions
+/// are not aborted, so the ones that succeeded are marked as sent and not sent again on retry.
+pub(crate) async fn join_chunk(
+    join_set: &mut JoinSet<Result<(), FhevmEngineError>>,
+) -> Result<(), FhevmEngineError> {
+    let mut first_err = None;
+    while let Some(res) = join_set.join_next().await {
+        if let Err(err) = res.map_err(FhevmEngineError::from).and_then(|res| res) {
+            first_err.get_or_insert(err);
+        }
+    }
+    first_err.map_or(Ok(()), Err)
 }
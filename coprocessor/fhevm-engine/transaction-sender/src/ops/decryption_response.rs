@@ -0,0 +1,648 @@
+use std::{
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+
+use crate::{
+    error_class::{handle_send_error, ErrorTable, RetryClass, SendAttempt, SendErrorHandler},
+    gas_spend,
+    metrics::{DECRYPTION_RESPONSE_FAIL_COUNTER, DECRYPTION_RESPONSE_SUCCESS_COUNTER},
+    nonce_managed_provider::NonceManagedProvider,
+    overprovision_gas_limit::{try_overprovision_gas_limit, GasEstimates},
+    receipts, REVIEW,
+};
+
+use super::common::InFlightLimit;
+use super::TransactionOperation;
+use alloy::{
+    network::{Ethereum, TransactionBuilder},
+    primitives::{Address, Bytes, U256},
+    providers::Provider,
+    rpc::types::TransactionRequest,
+    sol,
+    sol_types::{SolError, SolValue},
+};
+use async_trait::async_trait;
+use fhevm_engine_common::{
+    db_query::run_query, error::FhevmEngineError, types::DecryptionResponseType,
+    utils::compact_hex, webhook::WebhookEvent,
+};
+use fhevm_gateway_bindings::drift::ExpectedSelector;
+use prometheus::IntCounter;
+use sqlx::{Pool, Postgres};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+use Decryption::DecryptionErrors;
+
+sol!(
+    #[sol(rpc)]
+    Decryption,
+    "artifacts/Decryption.sol/Decryption.json"
+);
+
+/// The response was already sent by a previous attempt, or the decryption is unknown to the
+/// Gateway and it never will be.
+const DECRYPTION_RESPONSE_ERRORS: ErrorTable = &[
+    (Decryption::KmsNodeAlreadySigned::SELECTOR, RetryClass::Skip),
+    (
+        Decryption::DecryptionNotRequested::SELECTOR,
+        RetryClass::DeadLetter,
+    ),
+];
+
+#[derive(Clone)]
+pub(crate) struct Key {
+    decryption_id: Vec<u8>,
+    response_type: DecryptionResponseType,
+    /// Index of the call among the calls of the response, and their count
+    call: usize,
+    calls: usize,
+}
+
+impl Key {
+    fn is_last_call(&self) -> bool {
+        self.call + 1 == self.calls
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Key {{ decryption_id: {}, response_type: {:?}",
+            compact_hex(&self.decryption_id),
+            self.response_type
+        )?;
+        if self.calls > 1 {
+            write!(f, ", call: {}/{}", self.call + 1, self.calls)?;
+        }
+        write!(f, " }}")
+    }
+}
+
+/// Sends the public and user decryption responses stored in the `decryption_responses` table to
+/// the Gateway Decryption contract.
+#[derive(Clone)]
+pub struct DecryptionResponseOperation<P: Provider<Ethereum> + Clone + 'static> {
+    decryption_address: Address,
+    provider: NonceManagedProvider<P>,
+    conf: crate::ConfigSettings,
+    gas: Option<u64>,
+    gw_chain_id: u64,
+    db_pool: Pool<Postgres>,
+    gas_estimates: GasEstimates,
+    in_flight: InFlightLimit,
+}
+
+impl<P: Provider<Ethereum> + Clone + 'static> DecryptionResponseOperation<P> {
+    pub fn new(
+        decryption_address: Address,
+        provider: NonceManagedProvider<P>,
+        conf: crate::ConfigSettings,
+        gas: Option<u64>,
+        gw_chain_id: u64,
+        db_pool: Pool<Postgres>,
+    ) -> Self {
+        info!(
+            gas = gas.unwrap_or(0),
+            decryption_address = %decryption_address,
+            "Creating DecryptionResponseOperation"
+        );
+
+        let in_flight = InFlightLimit::new(
+            "decryption_response",
+            conf.decryption_response_max_in_flight,
+        );
+        Self {
+            decryption_address,
+            provider,
+            conf,
+            gas,
+            gw_chain_id,
+            db_pool,
+            gas_estimates: GasEstimates::default(),
+            in_flight,
+        }
+    }
+
+    /// Sends the calls of a response in order, the response is sent once its last call is.
+    async fn send_transactions(
+        &self,
+        key: Key,
+        txn_requests: Vec<TransactionRequest>,
+        current_limited_retries_count: i32,
+        current_unlimited_retries_count: i32,
+    ) -> Result<(), FhevmEngineError> {
+        let calls = txn_requests.len();
+        for (call, txn_request) in txn_requests.into_iter().enumerate() {
+            let key = Key {
+                call,
+                calls,
+                ..key.clone()
+            };
+            self.send_transaction(
+                &key,
+                txn_request,
+                current_limited_retries_count,
+                current_unlimited_retries_count,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn send_transaction(
+        &self,
+        key: &Key,
+        txn_request: impl Into<TransactionRequest>,
+        current_limited_retries_count: i32,
+        current_unlimited_retries_count: i32,
+    ) -> Result<(), FhevmEngineError> {
+        info!(key = %key, "Processing transaction");
+
+        let overprovisioned_txn_req = try_overprovision_gas_limit(
+            self.provider.with_sender(txn_request),
+            self.provider.inner(),
+            &self.conf,
+            &self.gas_estimates,
+        )
+        .await;
+        let transaction = match self
+            .provider
+            .send_transaction(overprovisioned_txn_req.clone())
+            .await
+        {
+            Ok(txn) => txn,
+            Err(e) => {
+                let attempt = SendAttempt {
+                    key,
+                    transaction_request: &overprovisioned_txn_req,
+                    limited_retries_count: current_limited_retries_count,
+                    unlimited_retries_count: current_unlimited_retries_count,
+                    src_transaction_id: None,
+                };
+                return handle_send_error(self, attempt, e).await.1;
+            }
+        };
+
+        // We assume that if we were able to send the transaction, we will be able to get a receipt, eventually. If there is a transport
+        // error in-between, we rely on the retry logic to handle it.
+        let receipt = match transaction
+            .with_timeout(Some(Duration::from_secs(
+                self.conf.txn_receipt_timeout_secs as u64,
+            )))
+            .with_required_confirmations(self.conf.required_txn_confirmations as u64)
+            .get_receipt()
+            .await
+        {
+            Ok(receipt) => receipt,
+            Err(e) => {
+                DECRYPTION_RESPONSE_FAIL_COUNTER.inc();
+                error!(error = %e, key = %key, "Getting receipt failed");
+                self.increment_txn_limited_retries_count(
+                    key,
+                    &e.to_string(),
+                    current_limited_retries_count,
+                )
+                .await?;
+                return Err(e.into());
+            }
+        };
+
+        let operation = match key.response_type {
+            DecryptionResponseType::Public => gas_spend::OP_PUBLIC_DECRYPTION_RESPONSE,
+            DecryptionResponseType::User => gas_spend::OP_USER_DECRYPTION_RESPONSE,
+            DecryptionResponseType::PublicAggregated => {
+                gas_spend::OP_PUBLIC_DECRYPTION_AGGREGATED_RESPONSE
+            }
+        };
+        receipts::record_receipt::<DecryptionErrors, _>(
+            &self.db_pool,
+            &self.provider,
+            operation,
+            self.gw_chain_id,
+            &[alloy::hex::encode_prefixed(&key.decryption_id)],
+            &overprovisioned_txn_req,
+            &receipt,
+        )
+        .await;
+
+        if receipt.status() && !key.is_last_call() {
+            info!(
+                transaction_hash = %receipt.transaction_hash,
+                key = %key,
+                "Decryption response share sent"
+            );
+        } else if receipt.status() {
+            self.set_txn_is_sent(
+                key,
+                Some(receipt.transaction_hash.as_slice()),
+                receipt.block_number.map(|bn| bn as i64),
+            )
+            .await?;
+
+            info!(
+                transaction_hash = %receipt.transaction_hash,
+                key = %key,
+                "Decryption response txn succeeded"
+            );
+            DECRYPTION_RESPONSE_SUCCESS_COUNTER.inc();
+            if let Some(webhook) = &self.conf.webhook {
+                webhook.export(WebhookEvent::DecryptionCompleted {
+                    decryption_id: alloy::hex::encode_prefixed(&key.decryption_id),
+                    response_type: key.response_type.as_str().to_owned(),
+                    transaction_hash: receipt.transaction_hash.to_string(),
+                });
+            }
+        } else {
+            DECRYPTION_RESPONSE_FAIL_COUNTER.inc();
+            error!(
+                transaction_hash = %receipt.transaction_hash,
+                status = receipt.status(),
+                key = %key,
+                "Decryption response txn failed"
+            );
+
+            self.increment_txn_limited_retries_count(
+                key,
+                "receipt status = false",
+                current_limited_retries_count,
+            )
+            .await?;
+
+            return Err(FhevmEngineError::ContractRevert {
+                reason: format!(
+                    "Transaction {} failed with status {}, {}",
+                    receipt.transaction_hash,
+                    receipt.status(),
+                    key,
+                ),
+                data: None,
+            });
+        }
+        Ok(())
+    }
+
+    async fn set_txn_is_sent(
+        &self,
+        key: &Key,
+        txn_hash: Option<&[u8]>,
+        txn_block_number: Option<i64>,
+    ) -> Result<(), FhevmEngineError> {
+        run_query(
+            "decryption_response.set_txn_is_sent",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE decryption_responses
+             SET
+                txn_is_sent = true,
+                txn_hash = $1,
+                txn_block_number = $2
+             WHERE decryption_id = $3
+             AND response_type = $4",
+                    txn_hash,
+                    txn_block_number,
+                    key.decryption_id,
+                    key.response_type as i16
+                )
+                .execute(&self.db_pool)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn increment_txn_limited_retries_count(
+        &self,
+        key: &Key,
+        err: &str,
+        current_limited_retries_count: i32,
+    ) -> Result<(), FhevmEngineError> {
+        debug!("Updating retry count for key {}", key);
+
+        if current_limited_retries_count == (self.conf.decryption_response_max_retries as i32) - 1 {
+            error!(
+                action = REVIEW,
+                key = %key,
+                max_retries = self.conf.decryption_response_max_retries,
+                "Max limited retries reached"
+            );
+        } else {
+            warn!(
+                limited_reties_count = current_limited_retries_count + 1,
+                key = %key,
+                "Updating limited retry count"
+            );
+        }
+
+        run_query(
+            "decryption_response.increment_txn_limited_retries_count",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE decryption_responses
+            SET
+            txn_limited_retries_count = txn_limited_retries_count + 1,
+            txn_last_error = $1,
+            txn_last_error_at = NOW()
+            WHERE decryption_id = $2
+            AND response_type = $3",
+                    err,
+                    key.decryption_id,
+                    key.response_type as i16
+                )
+                .execute(&self.db_pool)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn increment_txn_unlimited_retries_count(
+        &self,
+        key: &Key,
+        err: &str,
+        current_unlimited_retries_count: i32,
+    ) -> Result<(), FhevmEngineError> {
+        debug!("Updating unlimited retries count, {}", key);
+
+        if current_unlimited_retries_count == (self.conf.review_after_unlimited_retries as i32) - 1
+        {
+            error!(
+                action = REVIEW,
+                unlimited_retries_count = current_unlimited_retries_count,
+                key = %key,
+                "Unlimited retries threshold reached"
+            );
+        } else {
+            warn!(
+                unlimited_retries_count = current_unlimited_retries_count + 1,
+                key = %key,
+                "Updating unlimited retries count"
+            );
+        }
+
+        run_query(
+            "decryption_response.increment_txn_unlimited_retries_count",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE decryption_responses
+            SET
+            txn_unlimited_retries_count = txn_unlimited_retries_count + 1,
+            txn_last_error = $1,
+            txn_last_error_at = NOW()
+            WHERE decryption_id = $2
+            AND response_type = $3",
+                    err,
+                    key.decryption_id,
+                    key.response_type as i16
+                )
+                .execute(&self.db_pool)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<P> SendErrorHandler for DecryptionResponseOperation<P>
+where
+    P: alloy::providers::Provider<Ethereum> + Clone + 'static,
+{
+    type Key = Key;
+    type Errors = DecryptionErrors;
+
+    const ERRORS: ErrorTable = DECRYPTION_RESPONSE_ERRORS;
+    const SKIP_REASON: &'static str = "KMS node has already sent the decryption response";
+
+    fn log_key(key: &Key) -> String {
+        key.to_string()
+    }
+
+    fn fail_counter(&self) -> &IntCounter {
+        &DECRYPTION_RESPONSE_FAIL_COUNTER
+    }
+
+    async fn skip(&self, attempt: SendAttempt<'_, Key>) -> Result<(), FhevmEngineError> {
+        // The signature of an earlier call may have been sent by a previous attempt, the next
+        // calls are still to be sent
+        if !attempt.key.is_last_call() {
+            return Ok(());
+        }
+        self.set_txn_is_sent(attempt.key, None, None).await
+    }
+
+    async fn retry_forever(
+        &self,
+        attempt: SendAttempt<'_, Key>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        self.increment_txn_unlimited_retries_count(
+            attempt.key,
+            err,
+            attempt.unlimited_retries_count,
+        )
+        .await
+    }
+
+    async fn retry_bounded(
+        &self,
+        attempt: SendAttempt<'_, Key>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        self.increment_txn_limited_retries_count(attempt.key, err, attempt.limited_retries_count)
+            .await
+    }
+
+    async fn dead_letter(
+        &self,
+        attempt: SendAttempt<'_, Key>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        let key = attempt.key;
+        error!(
+            action = REVIEW,
+            key = %key,
+            "Sending the decryption response can't succeed, not retrying"
+        );
+        run_query(
+            "decryption_response.dead_letter",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE decryption_responses
+            SET
+            txn_limited_retries_count = GREATEST(txn_limited_retries_count, $1),
+            txn_last_error = $2,
+            txn_last_error_at = NOW()
+            WHERE decryption_id = $3
+            AND response_type = $4",
+                    self.conf.decryption_response_max_retries as i32,
+                    err,
+                    key.decryption_id,
+                    key.response_type as i16
+                )
+                .execute(&self.db_pool)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<P> TransactionOperation<P> for DecryptionResponseOperation<P>
+where
+    P: alloy::providers::Provider<Ethereum> + Clone + 'static,
+{
+    fn channel(&self) -> &str {
+        &self.conf.decryption_response_db_channel
+    }
+
+    fn expected_selectors(&self) -> (Address, Vec<ExpectedSelector>) {
+        let expected = vec![
+            ExpectedSelector::function::<Decryption::publicDecryptionResponseCall>(),
+            ExpectedSelector::function::<Decryption::userDecryptionResponseCall>(),
+            ExpectedSelector::error::<Decryption::KmsNodeAlreadySigned>(),
+            ExpectedSelector::error::<Decryption::DecryptionNotRequested>(),
+        ];
+        (self.decryption_address, expected)
+    }
+
+    async fn execute(&self) -> Result<bool, FhevmEngineError> {
+        let rows = run_query("decryption_response.execute", &self.conf.db_query, || {
+            sqlx::query!(
+            "
+            SELECT decryption_id, response_type, result, signature, extra_data, txn_limited_retries_count, txn_unlimited_retries_count
+            FROM decryption_responses
+            WHERE txn_is_sent = false
+            AND txn_limited_retries_count < $1
+            ORDER BY created_at
+            LIMIT $2;
+            ",
+            self.conf.decryption_response_max_retries as i32,
+            self.conf.decryption_response_batch_limit as i32,
+        )
+        .fetch_all(&self.db_pool)
+        })
+        .await?;
+
+        let decryption = Decryption::new(self.decryption_address, self.provider.inner());
+
+        info!(rows_count = rows.len(), "Selected rows to process");
+
+        let maybe_has_more_work = rows.len() == self.conf.decryption_response_batch_limit as usize;
+
+        let mut join_set = JoinSet::new();
+        for row in rows.into_iter() {
+            let response_type = match DecryptionResponseType::try_from(row.response_type) {
+                Ok(response_type) => response_type,
+                Err(_) => {
+                    error!(
+                        response_type = row.response_type,
+                        decryption_id = compact_hex(&row.decryption_id),
+                        "Invalid response_type"
+                    );
+                    continue;
+                }
+            };
+
+            if row.decryption_id.len() > 32 {
+                error!(
+                    decryption_id = compact_hex(&row.decryption_id),
+                    "Invalid decryption_id length"
+                );
+                continue;
+            }
+            let decryption_id = U256::from_be_slice(&row.decryption_id);
+
+            let result = match self.conf.column_encryption.decrypt(row.result) {
+                Ok(result) => Bytes::from(result),
+                Err(err) => {
+                    error!(
+                        decryption_id = compact_hex(&row.decryption_id),
+                        error = %err,
+                        "Cannot decrypt the decryption result"
+                    );
+                    continue;
+                }
+            };
+            let extra_data = Bytes::from(row.extra_data);
+
+            let txn_requests = match response_type {
+                DecryptionResponseType::Public => vec![decryption
+                    .publicDecryptionResponse(
+                        decryption_id,
+                        result,
+                        row.signature.into(),
+                        extra_data,
+                    )
+                    .into_transaction_request()],
+                DecryptionResponseType::User => vec![decryption
+                    .userDecryptionResponse(decryption_id, result, row.signature.into(), extra_data)
+                    .into_transaction_request()],
+                // The Gateway takes one signature per response, the signatures of the quorum are
+                // sent one after the other
+                DecryptionResponseType::PublicAggregated => {
+                    let Ok(signatures) = Vec::<Bytes>::abi_decode(&row.signature) else {
+                        error!(
+                            decryption_id = compact_hex(&row.decryption_id),
+                            "Invalid aggregated signatures encoding"
+                        );
+                        continue;
+                    };
+                    signatures
+                        .into_iter()
+                        .map(|signature| {
+                            decryption
+                                .publicDecryptionResponse(
+                                    decryption_id,
+                                    result.clone(),
+                                    signature,
+                                    extra_data.clone(),
+                                )
+                                .into_transaction_request()
+                        })
+                        .collect()
+                }
+            };
+            let txn_requests = txn_requests
+                .into_iter()
+                .map(|txn_request| match &self.gas {
+                    Some(gas_limit) => txn_request.with_gas_limit(*gas_limit),
+                    None => txn_request,
+                })
+                .collect();
+
+            let key = Key {
+                decryption_id: row.decryption_id,
+                response_type,
+                call: 0,
+                calls: 1,
+            };
+
+            let operation = self.clone();
+            let permit = self.in_flight.acquire().await;
+            join_set.spawn(async move {
+                let _permit = permit;
+                operation
+                    .send_transactions(
+                        key,
+                        txn_requests,
+                        row.txn_limited_retries_count,
+                        row.txn_unlimited_retries_count,
+                    )
+                    .await
+            });
+        }
+
+        while let Some(res) = join_set.join_next().await {
+            res??;
+        }
+
+        Ok(maybe_has_more_work)
+    }
+}
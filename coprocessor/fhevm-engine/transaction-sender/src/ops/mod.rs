@@ -1,5 +1,7 @@
-use alloy::network::Ethereum;
+use alloy::{network::Ethereum, primitives::Address};
 use async_trait::async_trait;
+use fhevm_engine_common::error::FhevmEngineError;
+use fhevm_gateway_bindings::drift::ExpectedSelector;
 
 #[async_trait]
 pub trait TransactionOperation<P>: Send + Sync
@@ -8,11 +10,16 @@ where
 {
     fn channel(&self) -> &str;
 
-    async fn execute(&self) -> anyhow::Result<bool>;
+    /// The contract the operation sends transactions to and the function and error selectors the
+    /// operation relies on.
+    fn expected_selectors(&self) -> (Address, Vec<ExpectedSelector>);
+
+    async fn execute(&self) -> Result<bool, FhevmEngineError>;
 }
 
 pub(crate) mod add_ciphertext;
 pub(crate) mod allow_handle;
+pub(crate) mod decryption_response;
 pub(crate) mod verify_proof;
 
 mod common;
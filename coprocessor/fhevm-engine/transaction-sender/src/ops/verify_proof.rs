@@ -1,16 +1,29 @@
+use super::common::InFlightLimit;
 use super::TransactionOperation;
+use crate::error_class::{
+    handle_send_error, ErrorTable, RetryClass, SendAttempt, SendErrorHandler,
+};
+use crate::gas_spend;
 use crate::metrics::{VERIFY_PROOF_FAIL_COUNTER, VERIFY_PROOF_SUCCESS_COUNTER};
 use crate::nonce_managed_provider::NonceManagedProvider;
-use crate::overprovision_gas_limit::try_overprovision_gas_limit;
+use crate::overprovision_gas_limit::{try_overprovision_gas_limit, GasEstimates};
+use crate::receipts;
 use crate::AbstractSigner;
+use crate::REVIEW;
 use alloy::network::TransactionBuilder;
 use alloy::primitives::{Address, U256};
 use alloy::providers::Provider;
 use alloy::rpc::types::TransactionRequest;
 use alloy::sol;
-use alloy::{network::Ethereum, primitives::FixedBytes, sol_types::SolStruct};
+use alloy::{
+    network::Ethereum,
+    primitives::FixedBytes,
+    sol_types::{SolError, SolStruct},
+};
 use async_trait::async_trait;
-use fhevm_engine_common::telemetry;
+use fhevm_engine_common::{db_query::run_query, error::FhevmEngineError, telemetry};
+use fhevm_gateway_bindings::drift::ExpectedSelector;
+use prometheus::IntCounter;
 use sqlx::{Pool, Postgres};
 use std::convert::TryInto;
 use std::time::Duration;
@@ -34,6 +47,23 @@ sol!(
     "artifacts/InputVerification.sol/InputVerification.json"
 );
 
+/// The proof was already verified or rejected by a previous attempt, or the proof request is
+/// unknown to the Gateway and it never will be.
+const VERIFY_PROOF_ERRORS: ErrorTable = &[
+    (
+        InputVerification::CoprocessorAlreadyVerified::SELECTOR,
+        RetryClass::Skip,
+    ),
+    (
+        InputVerification::CoprocessorAlreadyRejected::SELECTOR,
+        RetryClass::Skip,
+    ),
+    (
+        InputVerification::VerifyProofNotRequested::SELECTOR,
+        RetryClass::DeadLetter,
+    ),
+];
+
 #[derive(Clone)]
 pub(crate) struct VerifyProofOperation<P: Provider<Ethereum> + Clone + 'static> {
     input_verification_address: Address,
@@ -43,6 +73,8 @@ pub(crate) struct VerifyProofOperation<P: Provider<Ethereum> + Clone + 'static>
     gas: Option<u64>,
     gw_chain_id: u64,
     db_pool: Pool<Postgres>,
+    gas_estimates: GasEstimates,
+    in_flight: InFlightLimit,
 }
 
 impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> VerifyProofOperation<P> {
@@ -53,8 +85,9 @@ impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> VerifyProofOpera
         conf: crate::ConfigSettings,
         gas: Option<u64>,
         db_pool: Pool<Postgres>,
-    ) -> anyhow::Result<Self> {
+    ) -> Result<Self, FhevmEngineError> {
         let gw_chain_id = provider.get_chain_id().await?;
+        let in_flight = InFlightLimit::new("verify_proof", conf.verify_proof_resp_max_in_flight);
         Ok(Self {
             input_verification_address,
             provider,
@@ -63,16 +96,53 @@ impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> VerifyProofOpera
             gas,
             gw_chain_id,
             db_pool,
+            gas_estimates: GasEstimates::default(),
+            in_flight,
+        })
+    }
+
+    /// Marks the response of the proof as on the Gateway. Handled proofs are kept, so that a
+    /// replayed request is not verified and sent again, until purged after the retention period.
+    async fn mark_proof_sent(
+        &self,
+        zk_proof_id: i64,
+        txn_hash: Option<&[u8]>,
+    ) -> Result<(), FhevmEngineError> {
+        debug!(zk_proof_id = zk_proof_id, "Marking proof as sent");
+        run_query("verify_proof.mark_proof_sent", &self.conf.db_query, || {
+            sqlx::query!(
+                "UPDATE verify_proofs
+                SET txn_status = 'sent', txn_hash = COALESCE($2, txn_hash), txn_status_updated_at = NOW()
+                WHERE zk_proof_id = $1",
+                zk_proof_id,
+                txn_hash
+            )
+            .execute(&self.db_pool)
         })
+        .await?;
+        Ok(())
     }
 
-    async fn remove_proof_by_id(&self, zk_proof_id: i64) -> anyhow::Result<()> {
-        debug!(zk_proof_id = zk_proof_id, "Removing proof");
-        sqlx::query!(
-            "DELETE FROM verify_proofs WHERE zk_proof_id = $1",
-            zk_proof_id
+    async fn mark_proof_failed(
+        &self,
+        zk_proof_id: i64,
+        error: &str,
+    ) -> Result<(), FhevmEngineError> {
+        debug!(zk_proof_id = zk_proof_id, "Marking proof as failed");
+        run_query(
+            "verify_proof.mark_proof_failed",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE verify_proofs
+                SET txn_status = 'failed', last_error = $2, txn_status_updated_at = NOW()
+                WHERE zk_proof_id = $1",
+                    zk_proof_id,
+                    error
+                )
+                .execute(&self.db_pool)
+            },
         )
-        .execute(&self.db_pool)
         .await?;
         Ok(())
     }
@@ -82,36 +152,50 @@ impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> VerifyProofOpera
         zk_proof_id: i64,
         current_retry_count: i32,
         error: &str,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), FhevmEngineError> {
         if current_retry_count == (self.conf.verify_proof_resp_max_retries as i32) - 1 {
             error!(zk_proof_id = zk_proof_id, "Max retries reached for proof");
         }
         debug!(zk_proof_id = zk_proof_id, "Updating retry count of proof");
-        sqlx::query!(
-            "UPDATE verify_proofs
+        run_query(
+            "verify_proof.update_retry_count_by_proof_id",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE verify_proofs
             SET
                 retry_count = retry_count + 1,
                 last_error = $2,
                 last_retry_at = NOW()
             WHERE zk_proof_id = $1",
-            zk_proof_id,
-            error
+                    zk_proof_id,
+                    error
+                )
+                .execute(&self.db_pool)
+            },
         )
-        .execute(&self.db_pool)
         .await?;
         Ok(())
     }
 
-    async fn remove_proofs_by_retry_count(&self) -> anyhow::Result<()> {
+    async fn fail_proofs_by_retry_count(&self) -> Result<(), FhevmEngineError> {
         debug!(
             max_retries = self.conf.verify_proof_resp_max_retries,
-            "Removing proofs with retry count >= max_retries"
+            "Marking proofs with retry count >= max_retries as failed"
         );
-        sqlx::query!(
-            "DELETE FROM verify_proofs WHERE retry_count >= $1",
-            self.conf.verify_proof_resp_max_retries as i64
+        run_query(
+            "verify_proof.fail_proofs_by_retry_count",
+            &self.conf.db_query,
+            || {
+                sqlx::query!(
+                    "UPDATE verify_proofs
+                    SET txn_status = 'failed', txn_status_updated_at = NOW()
+                    WHERE txn_status = 'pending' AND retry_count >= $1",
+                    self.conf.verify_proof_resp_max_retries as i64
+                )
+                .execute(&self.db_pool)
+            },
         )
-        .execute(&self.db_pool)
         .await?;
         Ok(())
     }
@@ -121,14 +205,15 @@ impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> VerifyProofOpera
         txn_request: (i64, impl Into<TransactionRequest>),
         current_retry_count: i32,
         src_transaction_id: Option<Vec<u8>>,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), FhevmEngineError> {
         info!(zk_proof_id = txn_request.0, "Processing transaction");
         let _t = telemetry::tracer("call_verify_proof_resp", &src_transaction_id);
 
         let overprovisioned_txn_req = try_overprovision_gas_limit(
-            txn_request.1,
+            self.provider.with_sender(txn_request.1),
             self.provider.inner(),
-            self.conf.gas_limit_overprovision_percent,
+            &self.conf,
+            &self.gas_estimates,
         )
         .await;
         let transaction = match self
@@ -138,43 +223,14 @@ impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> VerifyProofOpera
         {
             Ok(txn) => txn,
             Err(e) => {
-                if let Some(InputVerificationErrors::CoprocessorAlreadyVerified(_)) =
-                    e.as_error_resp().and_then(|payload| {
-                        payload.as_decoded_interface_error::<InputVerificationErrors>()
-                    })
-                {
-                    warn!(
-                        zk_proof_id = txn_request.0,
-                        "Coprocessor has already verified the proof, removing from DB"
-                    );
-                    self.remove_proof_by_id(txn_request.0).await?;
-                    return Ok(());
-                } else if let Some(InputVerificationErrors::CoprocessorAlreadyRejected(_)) =
-                    e.as_error_resp().and_then(|payload| {
-                        payload.as_decoded_interface_error::<InputVerificationErrors>()
-                    })
-                {
-                    warn!(
-                        zk_proof_id = txn_request.0,
-                        "Coprocessor has already rejected the proof, removing from DB"
-                    );
-                    self.remove_proof_by_id(txn_request.0).await?;
-                    return Ok(());
-                } else {
-                    VERIFY_PROOF_FAIL_COUNTER.inc();
-                    error!(
-                        transaction_request = ?overprovisioned_txn_req,
-                        error = %e,
-                        "Transaction sending failed"
-                    );
-                    self.update_retry_count_by_proof_id(
-                        txn_request.0,
-                        current_retry_count,
-                        &e.to_string(),
-                    )
-                    .await?;
-                    return Err(anyhow::Error::new(e));
-                }
+                let attempt = SendAttempt {
+                    key: &txn_request.0,
+                    transaction_request: &overprovisioned_txn_req,
+                    limited_retries_count: current_retry_count,
+                    unlimited_retries_count: current_retry_count,
+                    src_transaction_id,
+                };
+                return handle_send_error(self, attempt, e).await.1;
             }
         };
 
@@ -196,16 +252,28 @@ impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> VerifyProofOpera
                     &e.to_string(),
                 )
                 .await?;
-                return Err(anyhow::Error::new(e));
+                return Err(e.into());
             }
         };
 
+        receipts::record_receipt::<InputVerificationErrors, _>(
+            &self.db_pool,
+            &self.provider,
+            gas_spend::OP_VERIFY_PROOF,
+            self.gw_chain_id,
+            &[txn_request.0.to_string()],
+            &overprovisioned_txn_req,
+            &receipt,
+        )
+        .await;
+
         if receipt.status() {
             info!(
                 transaction_hash = %receipt.transaction_hash,
                 "Transaction succeeded"
             );
-            self.remove_proof_by_id(txn_request.0).await?;
+            self.mark_proof_sent(txn_request.0, Some(receipt.transaction_hash.as_slice()))
+                .await?;
             VERIFY_PROOF_SUCCESS_COUNTER.inc();
 
             telemetry::try_end_zkproof_transaction(
@@ -226,16 +294,93 @@ impl<P: alloy::providers::Provider<Ethereum> + Clone + 'static> VerifyProofOpera
                 "receipt status = false",
             )
             .await?;
-            return Err(anyhow::anyhow!(
-                "Transaction {} failed with status {}",
-                receipt.transaction_hash,
-                receipt.status()
-            ));
+            return Err(FhevmEngineError::ContractRevert {
+                reason: format!(
+                    "Transaction {} failed with status {}",
+                    receipt.transaction_hash,
+                    receipt.status()
+                ),
+                data: None,
+            });
         }
         Ok(())
     }
 }
 
+/// Proof responses have a single retry count, used by both the bounded and unbounded retries.
+#[async_trait]
+impl<P> SendErrorHandler for VerifyProofOperation<P>
+where
+    P: alloy::providers::Provider<Ethereum> + Clone + 'static,
+{
+    type Key = i64;
+    type Errors = InputVerificationErrors;
+
+    const ERRORS: ErrorTable = VERIFY_PROOF_ERRORS;
+    const SKIP_REASON: &'static str =
+        "Coprocessor has already verified or rejected the proof, marking as sent";
+
+    fn log_key(zk_proof_id: &i64) -> String {
+        zk_proof_id.to_string()
+    }
+
+    fn fail_counter(&self) -> &IntCounter {
+        &VERIFY_PROOF_FAIL_COUNTER
+    }
+
+    async fn skip(&self, attempt: SendAttempt<'_, i64>) -> Result<(), FhevmEngineError> {
+        self.mark_proof_sent(*attempt.key, None).await
+    }
+
+    async fn retry_forever(
+        &self,
+        attempt: SendAttempt<'_, i64>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        self.update_retry_count_by_proof_id(*attempt.key, attempt.unlimited_retries_count, err)
+            .await
+    }
+
+    async fn retry_bounded(
+        &self,
+        attempt: SendAttempt<'_, i64>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        self.update_retry_count_by_proof_id(*attempt.key, attempt.limited_retries_count, err)
+            .await
+    }
+
+    /// Exhausts the retries of the proof, which is then failed or left pending depending on
+    /// `verify_proof_remove_after_max_retries`.
+    async fn dead_letter(
+        &self,
+        attempt: SendAttempt<'_, i64>,
+        err: &str,
+    ) -> Result<(), FhevmEngineError> {
+        let zk_proof_id = *attempt.key;
+        error!(
+            action = REVIEW,
+            zk_proof_id, "Sending the proof response can't succeed, not retrying"
+        );
+        run_query("verify_proof.dead_letter", &self.conf.db_query, || {
+            sqlx::query!(
+                "UPDATE verify_proofs
+            SET
+                retry_count = GREATEST(retry_count, $2),
+                last_error = $3,
+                last_retry_at = NOW()
+            WHERE zk_proof_id = $1",
+                zk_proof_id,
+                self.conf.verify_proof_resp_max_retries as i32,
+                err
+            )
+            .execute(&self.db_pool)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl<P> TransactionOperation<P> for VerifyProofOperation<P>
 where
@@ -245,22 +390,37 @@ where
         &self.conf.verify_proof_resp_db_channel
     }
 
-    async fn execute(&self) -> anyhow::Result<bool> {
+    fn expected_selectors(&self) -> (Address, Vec<ExpectedSelector>) {
+        (
+            self.input_verification_address,
+            vec![
+                ExpectedSelector::function::<InputVerification::verifyProofResponseCall>(),
+                ExpectedSelector::function::<InputVerification::rejectProofResponseCall>(),
+                ExpectedSelector::error::<InputVerification::CoprocessorAlreadyVerified>(),
+                ExpectedSelector::error::<InputVerification::CoprocessorAlreadyRejected>(),
+                ExpectedSelector::error::<InputVerification::VerifyProofNotRequested>(),
+            ],
+        )
+    }
+
+    async fn execute(&self) -> Result<bool, FhevmEngineError> {
         let input_verification =
             InputVerification::new(self.input_verification_address, self.provider.inner());
         if self.conf.verify_proof_remove_after_max_retries {
-            self.remove_proofs_by_retry_count().await?;
+            self.fail_proofs_by_retry_count().await?;
         }
-        let rows = sqlx::query!(
+        let rows = run_query("verify_proof.execute", &self.conf.db_query, || {
+            sqlx::query!(
             "SELECT zk_proof_id, chain_id, contract_address, user_address, handles, verified, retry_count, extra_data, transaction_id
              FROM verify_proofs
-             WHERE verified IS NOT NULL AND retry_count < $1
+             WHERE verified IS NOT NULL AND txn_status = 'pending' AND retry_count < $1
              ORDER BY zk_proof_id
              LIMIT $2",
             self.conf.verify_proof_resp_max_retries as i64,
             self.conf.verify_proof_resp_batch_limit as i64
         )
         .fetch_all(&self.db_pool)
+        })
         .await?;
         info!(rows_count = rows.len(), "Selected rows to process");
         let maybe_has_more_work = rows.len() == self.conf.verify_proof_resp_batch_limit as usize;
@@ -272,15 +432,19 @@ where
             let txn_request = match row.verified {
                 Some(true) => {
                     info!(zk_proof_id = row.zk_proof_id, "Processing verified proof");
-                    let handles = row
-                        .handles
-                        .ok_or(anyhow::anyhow!("handles field is None"))?;
+                    let handles = row.handles.ok_or(FhevmEngineError::Validation(
+                        "handles field is None".to_owned(),
+                    ))?;
                     if handles.len() % 32 != 0 {
                         error!(
                             handles_len = handles.len(),
                             "Bad handles field, len is not divisible by 32"
                         );
-                        self.remove_proof_by_id(row.zk_proof_id).await?;
+                        self.mark_proof_failed(
+                            row.zk_proof_id,
+                            "handles length is not divisible by 32",
+                        )
+                        .await?;
                         continue;
                     }
                     let handles: Vec<FixedBytes<32>> = handles
@@ -374,7 +538,9 @@ where
 
             let self_clone = self.clone();
             let src_transaction_id = transaction_id;
+            let permit = self.in_flight.acquire().await;
             join_set.spawn(async move {
+                let _permit = permit;
                 self_clone
                     .process_proof(txn_request, row.retry_count, src_transaction_id)
                     .await
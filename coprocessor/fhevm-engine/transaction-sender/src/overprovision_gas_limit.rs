@@ -1,17 +1,47 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
 use alloy::network::{Ethereum, TransactionBuilder};
+use alloy::primitives::{Address, FixedBytes};
 use alloy::providers::Provider;
 use alloy::rpc::types::TransactionRequest;
+use fhevm_engine_common::error::FhevmEngineError;
 use tracing::{debug, warn};
 
+use crate::ConfigSettings;
+
+type MethodKey = (Option<Address>, FixedBytes<4>);
+
+/// Last successful gas estimate of each called contract method, used when the estimation fails.
+#[derive(Clone, Default)]
+pub struct GasEstimates(Arc<Mutex<HashMap<MethodKey, u64>>>);
+
+impl GasEstimates {
+    fn get(&self, method: &MethodKey) -> Option<u64> {
+        self.0.lock().unwrap().get(method).copied()
+    }
+
+    fn insert(&self, method: MethodKey, gas: u64) {
+        self.0.lock().unwrap().insert(method, gas);
+    }
+}
+
 // If `txn_request.gas` is set, overprovision it by the given percent.
 // If `txn_request.gas` is not set, estimate the gas limit and then overprovision it by the given percent.
 // If the percent is less than 100, code will assert.
-// If the gas estimation fails, it will not set the gas limit for overprovisioning and will log a warning.
+// If the gas estimation fails transiently, e.g. the node is unreachable, it falls back, in order, to:
+//  - retrying, up to `gas_estimate_max_attempts` attempts with backoff
+//  - the last estimate of the same method of the same contract, overprovisioned
+//  - the `gas_limit_ceiling`, as is
+// Otherwise, e.g. when the transaction reverts, it will not set the gas limit and will log a
+// warning, so that the send fails the same way and its error is classified.
 pub async fn try_overprovision_gas_limit<T: Provider<Ethereum>>(
     txn_request: impl Into<TransactionRequest>,
     provider: &T,
-    percent: u32,
+    conf: &ConfigSettings,
+    estimates: &GasEstimates,
 ) -> TransactionRequest {
+    let percent = conf.gas_limit_overprovision_percent;
     assert!(percent >= 100, "Overprovision percent must be at least 100");
 
     let overprovision = |gas: u64| (gas as u128 * percent as u128 / 100) as u64;
@@ -19,20 +49,51 @@ pub async fn try_overprovision_gas_limit<T: Provider<Ethereum>>(
     let mut txn: TransactionRequest = txn_request.into();
 
     let new_gas = match txn.gas {
-        Some(existing_gas) => Some(existing_gas),
-        None => match provider.estimate_gas(txn.clone()).await {
-            Ok(estimated_gas) => Some(estimated_gas),
-            Err(err) => {
-                warn!(
-                    error = %err,
-                    gas_limit_overprovision_percent = percent,
-                    "Failed to estimate gas for overprovisioning, not setting gas limit"
-                );
-                None
+        Some(existing_gas) => Some(overprovision(existing_gas)),
+        None => {
+            let method = method_key(&txn);
+            match estimate_gas_with_retries(&txn, provider, conf).await {
+                Ok(estimated_gas) => {
+                    if let Some(method) = method {
+                        estimates.insert(method, estimated_gas);
+                    }
+                    Some(overprovision(estimated_gas))
+                }
+                Err(err) if !err.is_transient() => {
+                    warn!(
+                        error = %err,
+                        "Gas estimation failed, not setting gas limit"
+                    );
+                    None
+                }
+                Err(err) => {
+                    let last_estimate = method.and_then(|method| estimates.get(&method));
+                    if let Some(last_estimate) = last_estimate {
+                        warn!(
+                            error = %err,
+                            last_estimate,
+                            "Failed to estimate gas, using the last estimate of the method"
+                        );
+                        Some(overprovision(last_estimate))
+                    } else if let Some(ceiling) = conf.gas_limit_ceiling {
+                        warn!(
+                            error = %err,
+                            gas_limit_ceiling = ceiling,
+                            "Failed to estimate gas, using the gas limit ceiling"
+                        );
+                        Some(ceiling)
+                    } else {
+                        warn!(
+                            error = %err,
+                            gas_limit_overprovision_percent = percent,
+                            "Failed to estimate gas for overprovisioning, not setting gas limit"
+                        );
+                        None
+                    }
+                }
             }
-        },
-    }
-    .map(overprovision);
+        }
+    };
 
     if let Some(gas) = new_gas {
         debug!(
@@ -45,3 +106,37 @@ pub async fn try_overprovision_gas_limit<T: Provider<Ethereum>>(
 
     txn
 }
+
+async fn estimate_gas_with_retries<T: Provider<Ethereum>>(
+    txn: &TransactionRequest,
+    provider: &T,
+    conf: &ConfigSettings,
+) -> Result<u64, FhevmEngineError> {
+    let mut backoff = conf.gas_estimate_retry_backoff;
+    let mut attempt = 1;
+    loop {
+        match provider.estimate_gas(txn.clone()).await {
+            Ok(gas) => return Ok(gas),
+            Err(err) => {
+                let err = FhevmEngineError::from(err);
+                // A revert will not be estimated any better on retry
+                if !err.is_transient() || attempt >= conf.gas_estimate_max_attempts {
+                    return Err(err);
+                }
+                debug!(error = %err, attempt, "Gas estimation failed, retrying");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+fn method_key(txn: &TransactionRequest) -> Option<MethodKey> {
+    let input = txn.input.input()?;
+    let selector = input.get(..4)?;
+    Some((
+        txn.to.and_then(|to| to.to().copied()),
+        FixedBytes::from_slice(selector),
+    ))
+}
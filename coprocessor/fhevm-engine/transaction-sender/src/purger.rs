@@ -0,0 +1,81 @@
+use fhevm_engine_common::db_query::run_query;
+use sqlx::{Pool, Postgres};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::{metrics::PURGED_ROWS_COUNTER, scheduler::Schedule, ConfigSettings};
+
+/// Deletes the queue rows that reached a final status longer than their retention period ago.
+///
+/// Operations move the rows they handled to a final status instead of deleting them, which keeps
+/// them idempotent when a request is replayed after a crash between the send and the update. The
+/// rows are deleted in batches, so that a large backlog does not hold locks for long.
+pub(crate) struct Purger {
+    db_pool: Pool<Postgres>,
+    conf: ConfigSettings,
+    cancel_token: CancellationToken,
+}
+
+impl Purger {
+    pub(crate) fn new(
+        db_pool: Pool<Postgres>,
+        conf: ConfigSettings,
+        cancel_token: CancellationToken,
+    ) -> Self {
+        Self {
+            db_pool,
+            conf,
+            cancel_token,
+        }
+    }
+
+    /// Schedule of the purges, `purge_interval` unless a schedule is set.
+    pub(crate) fn schedule(&self) -> Schedule {
+        self.conf
+            .purge_schedule
+            .clone()
+            .unwrap_or(Schedule::Every(self.conf.purge_interval))
+    }
+
+    /// Purges all the tables once, run by the scheduler.
+    pub(crate) async fn purge(&self) -> anyhow::Result<()> {
+        debug!(
+            verify_proof_retention = ?self.conf.verify_proof_retention,
+            "Purging handled queue rows"
+        );
+        // Purging is best effort, rows are retried on the next run.
+        if let Err(e) = self.purge_verify_proofs().await {
+            warn!(error = %e, "Failed to purge handled proofs");
+        }
+        Ok(())
+    }
+
+    async fn purge_verify_proofs(&self) -> Result<(), sqlx::Error> {
+        let retention = self.conf.verify_proof_retention.as_secs_f64();
+        loop {
+            let purged = run_query("purger.verify_proofs", &self.conf.db_query, || {
+                sqlx::query!(
+                    "DELETE FROM verify_proofs
+                    WHERE zk_proof_id IN (
+                        SELECT zk_proof_id FROM verify_proofs
+                        WHERE txn_status <> 'pending'
+                        AND txn_status_updated_at < NOW() - make_interval(secs => $1)
+                        LIMIT $2
+                    )",
+                    retention,
+                    self.conf.purge_batch_size as i64,
+                )
+                .execute(&self.db_pool)
+            })
+            .await?
+            .rows_affected();
+            PURGED_ROWS_COUNTER
+                .with_label_values(&["verify_proofs"])
+                .inc_by(purged);
+            debug!(purged, "Purged handled proofs");
+            if purged < self.conf.purge_batch_size as u64 || self.cancel_token.is_cancelled() {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use alloy::{
+    network::Ethereum,
+    primitives::{Address, U256},
+    providers::Provider,
+};
+use anyhow::{bail, Context};
+use fhevm_gateway_bindings::gateway_config::GatewayConfig;
+use serde::Deserialize;
+use tracing::info;
+
+/// A version of the KMS signer set, with the number of its signers that must agree on a response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignerSet {
+    pub version: u64,
+    pub signers: Vec<Address>,
+    pub threshold: usize,
+}
+
+/// Quorum required for the KMS responses to be aggregated.
+///
+/// The most recent version of the signer set is the current one, only its signers count towards
+/// the quorum. The previous versions are kept to tell the signers of a rotated KMS node apart from
+/// unknown ones. Thresholds can be raised for the decryptions under specific keys, never lowered.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QuorumPolicy {
+    pub signer_sets: Vec<SignerSet>,
+    pub key_overrides: HashMap<U256, usize>,
+}
+
+/// The policy as written in a JSON file, e.g.
+/// `{"signer_sets": [{"version": 1, "signers": ["0x.."], "threshold": 2}],
+///   "key_overrides": {"0x01": 3}}`
+#[derive(Deserialize)]
+struct PolicyFile {
+    signer_sets: Vec<SignerSetFile>,
+    #[serde(default)]
+    key_overrides: HashMap<String, usize>,
+}
+
+#[derive(Deserialize)]
+struct SignerSetFile {
+    version: u64,
+    signers: Vec<String>,
+    threshold: usize,
+}
+
+impl QuorumPolicy {
+    /// A policy with a single signer set.
+    pub fn single(signers: Vec<Address>, threshold: usize) -> Self {
+        if signers.is_empty() {
+            return Self::default();
+        }
+        Self {
+            signer_sets: vec![SignerSet {
+                version: 0,
+                signers,
+                threshold,
+            }],
+            key_overrides: HashMap::new(),
+        }
+    }
+
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read quorum policy {path}"))?;
+        let file: PolicyFile = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid quorum policy {path}"))?;
+
+        let mut signer_sets = vec![];
+        for set in file.signer_sets {
+            let signers = set
+                .signers
+                .iter()
+                .map(|signer| Address::from_str(signer))
+                .collect::<Result<_, _>>()
+                .with_context(|| format!("Invalid signer in signer set {}", set.version))?;
+            signer_sets.push(SignerSet {
+                version: set.version,
+                signers,
+                threshold: set.threshold,
+            });
+        }
+        let mut key_overrides = HashMap::new();
+        for (key_id, threshold) in file.key_overrides {
+            let key_id =
+                U256::from_str(&key_id).with_context(|| format!("Invalid key id {key_id}"))?;
+            key_overrides.insert(key_id, threshold);
+        }
+
+        let policy = Self {
+            signer_sets,
+            key_overrides,
+        };
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signer_sets.is_empty()
+    }
+
+    /// Checks that every threshold can be reached by its signer set. Key overrides are checked
+    /// against the current signer set only, the previous ones may be too small to reach them.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut versions = HashSet::new();
+        for set in &self.signer_sets {
+            if !versions.insert(set.version) {
+                bail!("Duplicate signer set version {}", set.version);
+            }
+            let signers_count = set.signers.iter().collect::<HashSet<_>>().len();
+            if signers_count != set.signers.len() {
+                bail!("Duplicate signer in signer set {}", set.version);
+            }
+            if set.threshold == 0 || set.threshold > signers_count {
+                bail!(
+                    "Invalid threshold {} for the {} signers of signer set {}",
+                    set.threshold,
+                    signers_count,
+                    set.version
+                );
+            }
+        }
+        if let Some(current) = self.current() {
+            for (key_id, threshold) in &self.key_overrides {
+                if *threshold == 0 || *threshold > current.signers.len() {
+                    bail!(
+                        "Invalid threshold {threshold} of key {key_id} for the {} signers of \
+                         signer set {}",
+                        current.signers.len(),
+                        current.version
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The most recent signer set.
+    pub fn current(&self) -> Option<&SignerSet> {
+        self.signer_sets.iter().max_by_key(|set| set.version)
+    }
+
+    pub fn is_signer(&self, signer: &Address) -> bool {
+        self.signer_sets
+            .iter()
+            .any(|set| set.signers.contains(signer))
+    }
+
+    /// Threshold of the signer set for the key, an override below the threshold of the set is
+    /// ignored.
+    pub fn threshold(&self, set: &SignerSet, key_id: Option<U256>) -> usize {
+        key_id
+            .and_then(|key_id| self.key_overrides.get(&key_id))
+            .map_or(set.threshold, |threshold| (*threshold).max(set.threshold))
+    }
+
+    /// Returns the current signer set if its threshold is reached by the agreeing signers.
+    pub fn reached<'a>(
+        &self,
+        key_id: Option<U256>,
+        agreeing: impl IntoIterator<Item = &'a Address>,
+    ) -> Option<&SignerSet> {
+        let current = self.current()?;
+        let count = agreeing
+            .into_iter()
+            .filter(|signer| current.signers.contains(signer))
+            .collect::<HashSet<_>>()
+            .len();
+        (count >= self.threshold(current, key_id)).then_some(current)
+    }
+
+    /// Fails if the current signer set is not the one of the Gateway or if a threshold is below
+    /// the public decryption threshold of the Gateway.
+    pub async fn validate_onchain<P: Provider<Ethereum>>(
+        &self,
+        provider: &P,
+        gateway_config_address: Address,
+    ) -> anyhow::Result<()> {
+        let Some(current) = self.current() else {
+            return Ok(());
+        };
+        let gateway_config = GatewayConfig::new(gateway_config_address, provider);
+        let onchain_signers: HashSet<Address> = gateway_config
+            .getKmsSigners()
+            .call()
+            .await?
+            .into_iter()
+            .collect();
+        let onchain_threshold: usize = gateway_config
+            .getPublicDecryptionThreshold()
+            .call()
+            .await?
+            .try_into()?;
+
+        let signers: HashSet<Address> = current.signers.iter().copied().collect();
+        if signers != onchain_signers {
+            bail!(
+                "Signer set {} does not match the KMS signers of the Gateway, missing: {:?}, \
+                 unknown: {:?}",
+                current.version,
+                onchain_signers.difference(&signers).collect::<Vec<_>>(),
+                signers.difference(&onchain_signers).collect::<Vec<_>>()
+            );
+        }
+        let thresholds =
+            std::iter::once(current.threshold).chain(self.key_overrides.values().copied());
+        for threshold in thresholds {
+            if threshold < onchain_threshold {
+                bail!(
+                    "Threshold {threshold} is below the public decryption threshold \
+                     {onchain_threshold} of the Gateway"
+                );
+            }
+        }
+        info!(
+            version = current.version,
+            onchain_threshold, "Quorum policy matches the Gateway KMS configuration"
+        );
+        Ok(())
+    }
+}
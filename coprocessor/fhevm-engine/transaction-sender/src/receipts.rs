@@ -0,0 +1,105 @@
+use alloy::{
+    eips::BlockId,
+    network::{Ethereum, TransactionBuilder},
+    providers::Provider,
+    rpc::types::{TransactionReceipt, TransactionRequest},
+    sol_types::SolInterface,
+};
+use fhevm_engine_common::error::FhevmEngineError;
+use sqlx::{Pool, Postgres};
+use tracing::warn;
+
+use crate::{gas_spend, nonce_managed_provider::NonceManagedProvider};
+
+/// Stores the receipt of a mined transaction with the keys of the queue rows it was sent for, see
+/// the `transaction_receipts` table, and accounts its fees in `gas_spend`. A bundle transaction is
+/// recorded once per user operation of the transaction-sender it carries.
+///
+/// The revert reason of a failed transaction is not part of its receipt, it is recovered by
+/// replaying the call on the state its block started from and decoded with the `E` contract
+/// errors. Like the gas accounting, recording never fails the operation, errors are only logged.
+pub(crate) async fn record_receipt<E, P>(
+    db_pool: &Pool<Postgres>,
+    provider: &NonceManagedProvider<P>,
+    operation: &str,
+    chain_id: u64,
+    queue_keys: &[String],
+    txn_request: &TransactionRequest,
+    receipt: &TransactionReceipt,
+) where
+    E: SolInterface + std::fmt::Debug,
+    P: Provider<Ethereum> + Clone + 'static,
+{
+    let user_operation = provider.take_user_operation_spend(&receipt.transaction_hash, txn_request);
+    gas_spend::record_receipt(
+        db_pool,
+        operation,
+        chain_id,
+        receipt,
+        user_operation.as_ref(),
+    )
+    .await;
+
+    let revert_reason = if receipt.status() {
+        None
+    } else {
+        revert_reason::<E, P>(provider.inner(), txn_request, receipt).await
+    };
+
+    if let Err(e) = sqlx::query!(
+        "INSERT INTO transaction_receipts (txn_hash, user_op_hash, operation, chain_id, queue_keys, block_number, block_hash, success, revert_reason)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (txn_hash, user_op_hash) DO NOTHING",
+        receipt.transaction_hash.as_slice(),
+        user_operation
+            .as_ref()
+            .map(|spend| spend.user_op_hash.as_slice()),
+        operation,
+        chain_id as i64,
+        queue_keys,
+        receipt.block_number.map(|bn| bn as i64),
+        receipt.block_hash.as_ref().map(|bh| bh.as_slice()),
+        receipt.status(),
+        revert_reason,
+    )
+    .execute(db_pool)
+    .await
+    {
+        warn!(
+            transaction_hash = %receipt.transaction_hash,
+            operation,
+            error = %e,
+            "Failed to record receipt"
+        );
+    }
+}
+
+async fn revert_reason<E, P>(
+    provider: &P,
+    txn_request: &TransactionRequest,
+    receipt: &TransactionReceipt,
+) -> Option<String>
+where
+    E: SolInterface + std::fmt::Debug,
+    P: Provider<Ethereum>,
+{
+    let mut call = txn_request.clone().with_from(receipt.from);
+    call.nonce = None;
+    // The state after the block could already hold what the transaction brought
+    let block = BlockId::number(receipt.block_number?.checked_sub(1)?);
+    match provider.call(call).block(block).await {
+        // The replay can succeed if the state changed within the block
+        Ok(_) => None,
+        Err(e) => match FhevmEngineError::from_rpc::<E>(e) {
+            FhevmEngineError::ContractRevert { reason, .. } => Some(reason),
+            err => {
+                warn!(
+                    transaction_hash = %receipt.transaction_hash,
+                    error = %err,
+                    "Failed to replay the reverted transaction"
+                );
+                None
+            }
+        },
+    }
+}
@@ -0,0 +1,295 @@
+//! Time-driven maintenance tasks, e.g. purges, as opposed to the operations woken up by the
+//! database notifications.
+
+use std::{future::Future, pin::Pin, str::FromStr, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Datelike, TimeDelta, Timelike, Utc};
+use rand::Rng;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::metrics::SCHEDULED_TASK_RUNS_COUNTER;
+
+/// How far a cron expression is searched for its next match, e.g. `0 0 29 2 *` matches at least
+/// once every 8 years.
+const CRON_SEARCH_LIMIT: TimeDelta = TimeDelta::days(8 * 366);
+
+/// When a task runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Cron expression, in UTC
+    Cron(CronSchedule),
+    /// Fixed delay between the end of a wait and the next one, written `@every <duration>`
+    Every(Duration),
+}
+
+impl Schedule {
+    /// Delay from `now` to the next run, `None` if the schedule never matches again.
+    pub fn delay_after(&self, now: DateTime<Utc>) -> Option<Duration> {
+        match self {
+            Schedule::Every(interval) => Some(*interval),
+            Schedule::Cron(cron) => cron
+                .next_after(now)
+                .map(|next| (next - now).to_std().unwrap_or_default()),
+        }
+    }
+}
+
+/// Parses a cron expression, `@hourly`, `@daily`, `@weekly`, `@monthly` or `@every <duration>`,
+/// e.g. `@every 10m`.
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let expression = match s {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            _ => match s.strip_prefix("@every") {
+                Some(interval) => {
+                    let interval = humantime::parse_duration(interval.trim())?;
+                    anyhow::ensure!(!interval.is_zero(), "The interval of {s} is zero");
+                    return Ok(Schedule::Every(interval));
+                }
+                None => s,
+            },
+        };
+        Ok(Schedule::Cron(expression.parse()?))
+    }
+}
+
+/// Five fields cron expression: minute, hour, day of month, month and day of week (0 or 7 is
+/// Sunday). Fields are `*`, values, ranges `a-b` and steps `*/n`, `a/n` or `a-b/n`, separated by
+/// commas. As usual, a day matches if either day field matches when both are restricted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    days_or: bool,
+}
+
+impl CronSchedule {
+    /// First minute strictly after `after` matching the expression.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let limit = after + CRON_SEARCH_LIMIT;
+        let mut t = after.with_second(0)?.with_nanosecond(0)? + TimeDelta::minutes(1);
+        while t <= limit {
+            if !has(self.months, t.month()) {
+                let (year, month) = match t.month() {
+                    12 => (t.year() + 1, 1),
+                    month => (t.year(), month + 1),
+                };
+                t = t
+                    .date_naive()
+                    .with_day(1)?
+                    .with_year(year)?
+                    .with_month(month)?
+                    .and_hms_opt(0, 0, 0)?
+                    .and_utc();
+                continue;
+            }
+            if !self.matches_day(&t) {
+                t = t.date_naive().succ_opt()?.and_hms_opt(0, 0, 0)?.and_utc();
+                continue;
+            }
+            if !has(self.hours, t.hour()) {
+                t = t.with_minute(0)? + TimeDelta::hours(1);
+                continue;
+            }
+            if !has(self.minutes, t.minute()) {
+                t += TimeDelta::minutes(1);
+                continue;
+            }
+            return Some(t);
+        }
+        None
+    }
+
+    fn matches_day(&self, t: &DateTime<Utc>) -> bool {
+        let day_of_month = has(self.days_of_month, t.day());
+        let day_of_week = has(self.days_of_week, t.weekday().num_days_from_sunday());
+        if self.days_or {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            anyhow::bail!("Expected 5 fields in cron expression {s:?}");
+        };
+        let mut days_of_week_bits = parse_field(days_of_week, 0, 7)?;
+        if has(days_of_week_bits, 7) {
+            days_of_week_bits = (days_of_week_bits & !(1 << 7)) | 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days_of_month: parse_field(days_of_month, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            days_of_week: days_of_week_bits,
+            days_or: !days_of_month.starts_with('*') && !days_of_week.starts_with('*'),
+        })
+    }
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+/// Parses a cron field into a bit set of the matching values.
+fn parse_field(field: &str, min: u32, max: u32) -> anyhow::Result<u64> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (part, 1),
+        };
+        anyhow::ensure!(step > 0, "Zero step in cron field {part:?}");
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (start.parse()?, end.parse()?),
+            // `a/n` goes up to the maximum
+            None if part.contains('/') => (range.parse()?, max),
+            None => {
+                let value = range.parse()?;
+                (value, value)
+            }
+        };
+        anyhow::ensure!(
+            min <= start && start <= end && end <= max,
+            "Cron field {part:?} is out of {min}-{max}"
+        );
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+type TaskFn =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>> + Send + Sync>;
+
+struct ScheduledTask {
+    name: &'static str,
+    schedule: Schedule,
+    jitter: Duration,
+    run: TaskFn,
+}
+
+/// Runs the registered tasks on their schedule, until cancelled.
+///
+/// Each wait is extended by a random delay of up to the jitter of the task, so that replicas do
+/// not all run it at the same time. A run is skipped when the previous run of the same task is
+/// still going. Tasks are expected to stop early on cancellation, the scheduler waits for the
+/// runs in progress before returning.
+pub struct Scheduler {
+    tasks: Vec<ScheduledTask>,
+    cancel_token: CancellationToken,
+}
+
+impl Scheduler {
+    pub fn new(cancel_token: CancellationToken) -> Self {
+        Self {
+            tasks: vec![],
+            cancel_token,
+        }
+    }
+
+    pub fn register<F, Fut>(
+        &mut self,
+        name: &'static str,
+        schedule: Schedule,
+        jitter: Duration,
+        task: F,
+    ) -> &mut Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.tasks.push(ScheduledTask {
+            name,
+            schedule,
+            jitter,
+            run: Arc::new(move || Box::pin(task())),
+        });
+        self
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        let mut join_set = JoinSet::new();
+        for task in self.tasks {
+            join_set.spawn(run_task(task, self.cancel_token.clone()));
+        }
+        while let Some(res) = join_set.join_next().await {
+            res?;
+        }
+        Ok(())
+    }
+}
+
+async fn run_task(task: ScheduledTask, cancel_token: CancellationToken) {
+    info!(
+        task = task.name,
+        schedule = ?task.schedule,
+        jitter = ?task.jitter,
+        "Scheduling task"
+    );
+    let mut running: Option<JoinHandle<()>> = None;
+    loop {
+        let Some(delay) = task.schedule.delay_after(Utc::now()) else {
+            warn!(task = task.name, "Schedule never matches again");
+            break;
+        };
+        let jitter = match task.jitter.as_millis() as u64 {
+            0 => Duration::ZERO,
+            max => Duration::from_millis(rand::rng().random_range(0..=max)),
+        };
+        tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            _ = tokio::time::sleep(delay + jitter) => {}
+        }
+
+        if running.as_ref().is_some_and(|run| !run.is_finished()) {
+            SCHEDULED_TASK_RUNS_COUNTER
+                .with_label_values(&[task.name, "skipped"])
+                .inc();
+            warn!(task = task.name, "Previous run still in progress, skipping");
+            continue;
+        }
+        let name = task.name;
+        let run = (task.run)();
+        running = Some(tokio::spawn(async move {
+            match run.await {
+                Ok(()) => {
+                    SCHEDULED_TASK_RUNS_COUNTER
+                        .with_label_values(&[name, "success"])
+                        .inc();
+                    debug!(task = name, "Task run done");
+                }
+                Err(e) => {
+                    SCHEDULED_TASK_RUNS_COUNTER
+                        .with_label_values(&[name, "error"])
+                        .inc();
+                    error!(task = name, error = %e, "Task run failed");
+                }
+            }
+        }));
+    }
+    if let Some(run) = running {
+        let _ = run.await;
+    }
+    info!(task = task.name, "Task stopped");
+}
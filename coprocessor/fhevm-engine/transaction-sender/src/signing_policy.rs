@@ -0,0 +1,151 @@
+//! Checks of the transactions before they are signed.
+//!
+//! A [`SigningPolicy`] set on the [`crate::NonceManagedProvider`] is asked about every transaction
+//! before it is signed. A denied transaction is never sent, the denial is logged for audit and
+//! returned as an error that [`crate::error_class::classify`] dead-letters, as the transaction
+//! would be denied again on every retry.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use alloy::{
+    primitives::{Address, FixedBytes, U256},
+    rpc::types::TransactionRequest,
+};
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::Deserialize;
+
+/// Why a transaction is refused.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PolicyDenial {
+    /// Rule that refused the transaction, used as metric label
+    pub rule: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for PolicyDenial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "signing policy denied the transaction ({}): {}",
+            self.rule, self.reason
+        )
+    }
+}
+
+impl std::error::Error for PolicyDenial {}
+
+/// A gate before signing, e.g. backed by an external policy engine.
+#[async_trait]
+pub trait SigningPolicy: Send + Sync {
+    /// Checks a transaction about to be signed. The fees are filled before the check when sending
+    /// from the EOA, they are left unset with user operations.
+    async fn check(&self, tx: &TransactionRequest) -> Result<(), PolicyDenial>;
+}
+
+/// Policy from static configuration. The empty sets and unset maximums allow anything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StaticSigningPolicy {
+    pub allowed_targets: HashSet<Address>,
+    pub allowed_selectors: HashSet<FixedBytes<4>>,
+    /// Maximum fee per gas in wei, the legacy gas price or the EIP-1559 max fee
+    pub max_gas_price: Option<u128>,
+    /// Maximum value in wei
+    pub max_value: Option<U256>,
+}
+
+/// The policy as written in a JSON file, e.g.
+/// `{"allowed_targets": ["0x.."], "allowed_selectors": ["0x3f2ab4c5"],
+///   "max_gas_price": 100000000000, "max_value": "0"}`
+#[derive(Deserialize)]
+struct PolicyFile {
+    #[serde(default)]
+    allowed_targets: Vec<String>,
+    #[serde(default)]
+    allowed_selectors: Vec<String>,
+    max_gas_price: Option<u128>,
+    max_value: Option<String>,
+}
+
+impl StaticSigningPolicy {
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read signing policy {path}"))?;
+        let file: PolicyFile = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid signing policy {path}"))?;
+
+        let allowed_targets = file
+            .allowed_targets
+            .iter()
+            .map(|target| {
+                Address::from_str(target).with_context(|| format!("Invalid target {target}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let allowed_selectors = file
+            .allowed_selectors
+            .iter()
+            .map(|selector| {
+                FixedBytes::from_str(selector)
+                    .with_context(|| format!("Invalid selector {selector}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let max_value = file
+            .max_value
+            .map(|value| U256::from_str(&value).with_context(|| format!("Invalid value {value}")))
+            .transpose()?;
+        Ok(Self {
+            allowed_targets,
+            allowed_selectors,
+            max_gas_price: file.max_gas_price,
+            max_value,
+        })
+    }
+}
+
+#[async_trait]
+impl SigningPolicy for StaticSigningPolicy {
+    async fn check(&self, tx: &TransactionRequest) -> Result<(), PolicyDenial> {
+        let deny = |rule, reason| Err(PolicyDenial { rule, reason });
+        if !self.allowed_targets.is_empty() {
+            match tx.to.and_then(|to| to.to().copied()) {
+                Some(to) if self.allowed_targets.contains(&to) => {}
+                Some(to) => return deny("target", format!("target {to} is not allowed")),
+                None => return deny("target", "contract creations are not allowed".to_owned()),
+            }
+        }
+        if !self.allowed_selectors.is_empty() {
+            let input = tx.input.input().map(|input| &input[..]).unwrap_or_default();
+            match input.get(..4).map(FixedBytes::<4>::from_slice) {
+                Some(selector) if self.allowed_selectors.contains(&selector) => {}
+                Some(selector) => {
+                    return deny("selector", format!("method {selector} is not allowed"))
+                }
+                None => {
+                    return deny(
+                        "selector",
+                        "calls without a method are not allowed".to_owned(),
+                    )
+                }
+            }
+        }
+        if let Some(max_value) = self.max_value {
+            let value = tx.value.unwrap_or_default();
+            if value > max_value {
+                return deny("value", format!("value {value} is above {max_value}"));
+            }
+        }
+        if let Some(max_gas_price) = self.max_gas_price {
+            if let Some(gas_price) = tx.max_fee_per_gas.or(tx.gas_price) {
+                if gas_price > max_gas_price {
+                    return deny(
+                        "gas_price",
+                        format!("gas price {gas_price} is above {max_gas_price}"),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
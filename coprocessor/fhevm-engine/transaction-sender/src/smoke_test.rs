@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant};
+
+use alloy::{
+    network::{Ethereum, TransactionBuilder},
+    primitives::{Address, TxHash, U256},
+    providers::Provider,
+    rpc::types::TransactionRequest,
+};
+use anyhow::Context;
+use tracing::info;
+
+use crate::NonceManagedProvider;
+
+/// Outcome of a smoke test transaction.
+#[derive(Debug, Clone)]
+pub struct SmokeTestReport {
+    pub signer_address: Address,
+    /// Account the Gateway sees as the sender, the smart account with user operations
+    pub sender_address: Address,
+    pub balance: U256,
+    /// Nonces of the signer before sending, a gap means transactions are queued
+    pub confirmed_nonce: u64,
+    pub pending_nonce: u64,
+    pub transaction_hash: TxHash,
+    pub block_number: Option<u64>,
+    pub gas_used: u64,
+    pub effective_gas_price: u128,
+    /// From sending to the receipt with the required confirmations
+    pub confirmation_time: Duration,
+}
+
+/// Sends a zero-value self-send of the sender through the nonce managed provider, i.e. the same
+/// nonce, fees and submission path as the operations, and waits for its receipt. Meant for
+/// post-deploy verification, it fails if the transaction can't be sent, is not confirmed in time
+/// or reverts.
+pub async fn run_smoke_test<P>(
+    provider: &NonceManagedProvider<P>,
+    receipt_timeout: Duration,
+    required_confirmations: u64,
+) -> anyhow::Result<SmokeTestReport>
+where
+    P: Provider<Ethereum> + Clone + 'static,
+{
+    let signer_address = provider
+        .signer_address()
+        .context("The smoke test requires a signer")?;
+    let sender_address = provider.sender_address().unwrap_or(signer_address);
+
+    let balance = provider.inner().get_balance(signer_address).await?;
+    let confirmed_nonce = provider
+        .inner()
+        .get_transaction_count(signer_address)
+        .latest()
+        .await?;
+    let pending_nonce = provider
+        .inner()
+        .get_transaction_count(signer_address)
+        .pending()
+        .await?;
+    info!(
+        %signer_address,
+        %sender_address,
+        %balance,
+        confirmed_nonce,
+        pending_nonce,
+        "Sending smoke test transaction"
+    );
+
+    let txn_request = TransactionRequest::default()
+        .with_to(sender_address)
+        .with_value(U256::ZERO);
+    let sent_at = Instant::now();
+    let transaction = provider
+        .send_transaction(txn_request)
+        .await
+        .context("Sending the smoke test transaction failed")?;
+    let transaction_hash = *transaction.tx_hash();
+    let receipt = transaction
+        .with_timeout(Some(receipt_timeout))
+        .with_required_confirmations(required_confirmations)
+        .get_receipt()
+        .await
+        .with_context(|| format!("No receipt for smoke test transaction {transaction_hash}"))?;
+    anyhow::ensure!(
+        receipt.status(),
+        "Smoke test transaction {} reverted",
+        receipt.transaction_hash
+    );
+
+    Ok(SmokeTestReport {
+        signer_address,
+        sender_address,
+        balance,
+        confirmed_nonce,
+        pending_nonce,
+        transaction_hash: receipt.transaction_hash,
+        block_number: receipt.block_number,
+        gas_used: receipt.gas_used,
+        effective_gas_price: receipt.effective_gas_price,
+        confirmation_time: sent_at.elapsed(),
+    })
+}
@@ -0,0 +1,198 @@
+use std::time::Instant;
+
+use alloy::{
+    network::{Ethereum, TransactionBuilder},
+    primitives::{Address, U256},
+    providers::Provider,
+    rpc::types::TransactionRequest,
+};
+use fhevm_engine_common::error::FhevmEngineError;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    metrics::{
+        STUCK_NONCE_CANCEL_FAIL_COUNTER, STUCK_NONCE_CANCEL_SUCCESS_COUNTER,
+        STUCK_NONCE_DETECTED_COUNTER,
+    },
+    nonce_managed_provider::NonceManagedProvider,
+    ConfigSettings, REVIEW,
+};
+
+/// Gas limit of a plain value transfer.
+const SELF_SEND_GAS_LIMIT: u64 = 21_000;
+
+/// The confirmed nonce being watched and since when it has not moved.
+pub struct StuckNonceState {
+    nonce: u64,
+    since: Instant,
+    cancel_attempts: u32,
+}
+
+/// Detects when the lowest pending nonce of the sender is not being mined while other transactions
+/// are queued behind it and, optionally, replaces it with a zero-value self-send with escalated
+/// fees.
+pub struct StuckNonceMonitor<P: Provider<Ethereum> + Clone + 'static> {
+    provider: NonceManagedProvider<P>,
+    signer_address: Address,
+    conf: ConfigSettings,
+    cancel_token: CancellationToken,
+}
+
+impl<P: Provider<Ethereum> + Clone + 'static> StuckNonceMonitor<P> {
+    pub fn new(
+        provider: NonceManagedProvider<P>,
+        signer_address: Address,
+        conf: ConfigSettings,
+        cancel_token: CancellationToken,
+    ) -> Self {
+        Self {
+            provider,
+            signer_address,
+            conf,
+            cancel_token,
+        }
+    }
+
+    pub(crate) async fn run(self) -> anyhow::Result<()> {
+        info!(
+            signer_address = %self.signer_address,
+            timeout = ?self.conf.stuck_nonce_timeout,
+            auto_cancel = self.conf.stuck_nonce_auto_cancel,
+            "Starting stuck nonce monitor"
+        );
+        let mut state = None;
+        loop {
+            tokio::select! {
+                _ = self.cancel_token.cancelled() => {
+                    info!("Stuck nonce monitor stopping");
+                    break;
+                }
+                _ = tokio::time::sleep(self.conf.stuck_nonce_check_interval) => {}
+            }
+
+            // Monitoring is best effort, the operations report their own transport errors.
+            if let Err(e) = self.check(&mut state).await {
+                warn!(error = %e, "Stuck nonce check failed");
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares the confirmed and pending nonces with the previous check, returning whether the
+    /// nonce was reported as stuck.
+    pub async fn check(
+        &self,
+        state: &mut Option<StuckNonceState>,
+    ) -> Result<bool, FhevmEngineError> {
+        let confirmed = self
+            .provider
+            .inner()
+            .get_transaction_count(self.signer_address)
+            .latest()
+            .await?;
+        let pending = self
+            .provider
+            .inner()
+            .get_transaction_count(self.signer_address)
+            .pending()
+            .await?;
+
+        if pending <= confirmed {
+            // Nothing is queued, hence nothing can be stuck.
+            *state = None;
+            return Ok(false);
+        }
+
+        let current = match state {
+            Some(s) if s.nonce == confirmed => s,
+            _ => {
+                debug!(
+                    confirmed_nonce = confirmed,
+                    pending_nonce = pending,
+                    "Watching confirmed nonce"
+                );
+                *state = Some(StuckNonceState {
+                    nonce: confirmed,
+                    since: Instant::now(),
+                    cancel_attempts: 0,
+                });
+                return Ok(false);
+            }
+        };
+
+        let stuck_for = current.since.elapsed();
+        if stuck_for < self.conf.stuck_nonce_timeout {
+            return Ok(false);
+        }
+
+        STUCK_NONCE_DETECTED_COUNTER.inc();
+        error!(
+            action = REVIEW,
+            signer_address = %self.signer_address,
+            stuck_nonce = confirmed,
+            pending_nonce = pending,
+            queued_txns = pending - confirmed,
+            stuck_for = ?stuck_for,
+            "Nonce is stuck, queued transactions are not being mined"
+        );
+
+        if self.conf.stuck_nonce_auto_cancel {
+            current.cancel_attempts += 1;
+            match self.cancel_nonce(confirmed, current.cancel_attempts).await {
+                Ok(()) => STUCK_NONCE_CANCEL_SUCCESS_COUNTER.inc(),
+                Err(e) => {
+                    STUCK_NONCE_CANCEL_FAIL_COUNTER.inc();
+                    error!(
+                        action = REVIEW,
+                        stuck_nonce = confirmed,
+                        cancel_attempts = current.cancel_attempts,
+                        error = %e,
+                        "Failed to cancel stuck nonce"
+                    );
+                }
+            }
+        }
+
+        // Give the chain (or the cancel transaction) another full timeout before alerting again.
+        current.since = Instant::now();
+        Ok(true)
+    }
+
+    /// Sends a zero-value self-send at the given nonce. Fees are bumped by
+    /// `stuck_nonce_fee_bump_percent` once per attempt on top of the current estimate, so
+    /// that repeated attempts eventually outbid the stuck transaction.
+    async fn cancel_nonce(&self, nonce: u64, attempt: u32) -> Result<(), FhevmEngineError> {
+        let fees = self.provider.inner().estimate_eip1559_fees().await?;
+        let bump = |fee: u128| {
+            (0..attempt).fold(fee, |fee, _| {
+                fee.saturating_mul(self.conf.stuck_nonce_fee_bump_percent as u128) / 100
+            })
+        };
+        let max_fee_per_gas = bump(fees.max_fee_per_gas);
+        let max_priority_fee_per_gas = bump(fees.max_priority_fee_per_gas);
+
+        let mut txn_request = TransactionRequest::default()
+            .with_from(self.signer_address)
+            .with_to(self.signer_address)
+            .with_value(U256::ZERO)
+            .with_nonce(nonce)
+            .with_gas_limit(SELF_SEND_GAS_LIMIT)
+            .with_max_fee_per_gas(max_fee_per_gas)
+            .with_max_priority_fee_per_gas(max_priority_fee_per_gas);
+
+        // Bypass the nonce manager: the whole point is to reuse the stuck nonce. The chain id
+        // and the signing policy are still checked.
+        self.provider.check_before_signing(&mut txn_request).await?;
+        let transaction = self.provider.inner().send_transaction(txn_request).await?;
+        info!(
+            transaction_hash = %transaction.tx_hash(),
+            stuck_nonce = nonce,
+            attempt,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            "Sent self-send to cancel stuck nonce"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+//! Test support for the code talking to the Gateway, enabled with the `test-utils` feature.
+//!
+//! [`MockProvider`] is a provider whose responses are scripted per JSON-RPC method, so that retry
+//! and error classification logic can be unit tested without a node. The `*_error` functions build
+//! the RPC errors returned by the Gateway contracts when a transaction reverts.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use alloy::network::Ethereum;
+use alloy::primitives::{Address, Bytes, FixedBytes, TxHash};
+use alloy::providers::RootProvider;
+use alloy::rpc::client::RpcClient;
+use alloy::rpc::json_rpc::{
+    ErrorPayload, RequestPacket, Response, ResponsePacket, ResponsePayload, SerializedRequest,
+};
+use alloy::rpc::types::TransactionReceipt;
+use alloy::sol_types::SolError;
+use alloy::transports::{TransportError, TransportErrorKind, TransportFut};
+use serde::Serialize;
+use serde_json::value::RawValue;
+
+pub use crate::ops::allow_handle::MultichainACL;
+
+pub use crate::ops::allow_handle::MultichainACL::MultichainACLErrors;
+
+pub use crate::balance_monitor::{wait_until_resumed, BalanceMonitor};
+
+pub use crate::stuck_nonce_monitor::StuckNonceMonitor;
+
+/// JSON-RPC error code of reverted calls and gas estimations.
+pub const EXECUTION_REVERTED_CODE: i64 = 3;
+
+/// Scripted outcome of a single JSON-RPC request.
+#[derive(Debug)]
+pub enum MockResponse {
+    Success(Box<RawValue>),
+    /// Error returned by the node, e.g. a revert.
+    ErrorResp(ErrorPayload),
+    /// Failure of the whole request, e.g. a lost connection.
+    Transport(TransportError),
+}
+
+impl MockResponse {
+    pub fn success<T: Serialize>(value: &T) -> Self {
+        Self::Success(serde_json::value::to_raw_value(value).expect("serializable response"))
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    scripted: HashMap<String, VecDeque<MockResponse>>,
+    defaults: HashMap<String, Box<RawValue>>,
+    calls: Vec<(String, Option<Box<RawValue>>)>,
+}
+
+/// A transport answering with the responses scripted for each method, in order.
+///
+/// Once the scripted responses of a method are consumed, its default response is returned if one
+/// is set, otherwise an error.
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockTransport {
+    fn respond(&self, request: &SerializedRequest) -> Result<Response, TransportError> {
+        let mut state = self.state.lock().expect("mock state lock");
+        state.calls.push((
+            request.method().to_owned(),
+            request.params().map(ToOwned::to_owned),
+        ));
+        let scripted = state
+            .scripted
+            .get_mut(request.method())
+            .and_then(VecDeque::pop_front);
+        let payload = match scripted {
+            Some(MockResponse::Success(value)) => ResponsePayload::Success(value),
+            Some(MockResponse::ErrorResp(error)) => ResponsePayload::Failure(error),
+            Some(MockResponse::Transport(error)) => return Err(error),
+            None => match state.defaults.get(request.method()) {
+                Some(value) => ResponsePayload::Success(value.clone()),
+                None => ResponsePayload::Failure(ErrorPayload {
+                    code: -32601,
+                    message: format!("no mock response for {}", request.method()).into(),
+                    data: None,
+                }),
+            },
+        };
+        Ok(Response {
+            id: request.id().clone(),
+            payload,
+        })
+    }
+}
+
+impl tower::Service<RequestPacket> for MockTransport {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        let response = match request {
+            RequestPacket::Single(request) => self.respond(&request).map(ResponsePacket::Single),
+            RequestPacket::Batch(requests) => requests
+                .iter()
+                .map(|request| self.respond(request))
+                .collect::<Result<Vec<_>, _>>()
+                .map(ResponsePacket::Batch),
+        };
+        Box::pin(async move { response })
+    }
+}
+
+/// A programmable provider for unit tests.
+///
+/// ```ignore
+/// let mock = MockProvider::new();
+/// mock.push_error("eth_sendTransaction", already_allowed_account_error(handle, account, sender));
+/// mock.push_transport_error("eth_sendTransaction", backend_gone_error());
+/// let provider = mock.provider();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockProvider {
+    transport: MockTransport,
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A provider without fillers backed by the scripted responses. Wrap it with the fillers of
+    /// the code under test if needed.
+    pub fn provider(&self) -> RootProvider<Ethereum> {
+        RootProvider::new(RpcClient::new(self.transport.clone(), true))
+    }
+
+    pub fn push(&self, method: &str, response: MockResponse) {
+        self.state()
+            .scripted
+            .entry(method.to_owned())
+            .or_default()
+            .push_back(response);
+    }
+
+    pub fn push_success<T: Serialize>(&self, method: &str, value: &T) {
+        self.push(method, MockResponse::success(value));
+    }
+
+    pub fn push_error(&self, method: &str, error: ErrorPayload) {
+        self.push(method, MockResponse::ErrorResp(error));
+    }
+
+    pub fn push_transport_error(&self, method: &str, error: TransportError) {
+        self.push(method, MockResponse::Transport(error));
+    }
+
+    /// Response of `method` once its scripted responses are consumed.
+    pub fn set_default<T: Serialize>(&self, method: &str, value: &T) {
+        let value = serde_json::value::to_raw_value(value).expect("serializable response");
+        self.state().defaults.insert(method.to_owned(), value);
+    }
+
+    /// Scripts a successful send of a transaction with the given hash, either signed locally or by
+    /// the node.
+    pub fn push_sent_transaction(&self, tx_hash: TxHash) {
+        self.push_success("eth_sendRawTransaction", &tx_hash);
+        self.push_success("eth_sendTransaction", &tx_hash);
+    }
+
+    /// Scripts the next receipt returned by `eth_getTransactionReceipt`.
+    pub fn push_receipt(&self, receipt: &TransactionReceipt) {
+        self.push_success("eth_getTransactionReceipt", receipt);
+    }
+
+    /// Methods called so far, in order.
+    pub fn calls(&self) -> Vec<String> {
+        self.state()
+            .calls
+            .iter()
+            .map(|(method, _)| method.clone())
+            .collect()
+    }
+
+    /// Params of each call of `method`, in order.
+    pub fn params(&self, method: &str) -> Vec<serde_json::Value> {
+        self.state()
+            .calls
+            .iter()
+            .filter(|(m, _)| m == method)
+            .map(|(_, params)| {
+                params
+                    .as_ref()
+                    .map(|p| serde_json::from_str(p.get()).expect("valid JSON params"))
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.transport.state.lock().expect("mock state lock")
+    }
+}
+
+/// A mined transaction receipt, with `status` false if the transaction reverted.
+pub fn receipt(tx_hash: TxHash, status: bool, block_number: u64) -> TransactionReceipt {
+    serde_json::from_value(serde_json::json!({
+        "type": "0x2",
+        "status": if status { "0x1" } else { "0x0" },
+        "cumulativeGasUsed": "0x5208",
+        "logs": [],
+        "logsBloom": format!("0x{}", "00".repeat(256)),
+        "transactionHash": tx_hash,
+        "transactionIndex": "0x0",
+        "blockHash": FixedBytes::<32>::with_last_byte(1),
+        "blockNumber": format!("{block_number:#x}"),
+        "gasUsed": "0x5208",
+        "effectiveGasPrice": "0x3b9aca00",
+        "from": Address::ZERO,
+        "to": Address::ZERO,
+        "contractAddress": null,
+    }))
+    .expect("valid receipt")
+}
+
+/// Error returned by the node when a call reverts with the given data.
+pub fn revert_error(data: impl Into<Bytes>) -> ErrorPayload {
+    let data: Bytes = data.into();
+    ErrorPayload {
+        code: EXECUTION_REVERTED_CODE,
+        message: "execution reverted".into(),
+        data: Some(serde_json::value::to_raw_value(&data).expect("serializable revert data")),
+    }
+}
+
+/// Error returned by the node when a call reverts with a custom contract error.
+pub fn contract_error<E: SolError>(error: &E) -> ErrorPayload {
+    revert_error(error.abi_encode())
+}
+
+pub fn already_allowed_account_error(
+    handle: FixedBytes<32>,
+    account: Address,
+    tx_sender: Address,
+) -> ErrorPayload {
+    contract_error(&MultichainACL::CoprocessorAlreadyAllowedAccount {
+        ctHandle: handle,
+        account,
+        txSender: tx_sender,
+    })
+}
+
+pub fn already_allowed_public_decrypt_error(
+    handle: FixedBytes<32>,
+    tx_sender: Address,
+) -> ErrorPayload {
+    contract_error(&MultichainACL::CoprocessorAlreadyAllowedPublicDecrypt {
+        ctHandle: handle,
+        txSender: tx_sender,
+    })
+}
+
+/// The connection to the node is lost for good.
+pub fn backend_gone_error() -> TransportError {
+    TransportErrorKind::backend_gone()
+}
+
+/// An HTTP error the transport considers retryable, e.g. rate limiting.
+pub fn retryable_http_error() -> TransportError {
+    TransportErrorKind::http_error(429, "rate limited".to_owned())
+}
+
+/// A signing failure, as returned by an unreachable AWS KMS signer.
+pub fn local_usage_error() -> TransportError {
+    TransportError::local_usage_str("signer unavailable")
+}
@@ -1,14 +1,19 @@
 use alloy::{network::Ethereum, primitives::Address, providers::Provider};
+use fhevm_engine_common::db_schema;
+use fhevm_engine_common::notification_bus::{self, BusEvent, NotificationBus};
+use fhevm_gateway_bindings::drift::check_selectors;
 use futures_util::FutureExt;
-use sqlx::{postgres::PgListener, Pool, Postgres};
+use sqlx::{Pool, Postgres};
 use std::{sync::Arc, time::Duration};
-use tokio::task::JoinSet;
+use tokio::{sync::watch, task::JoinSet};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info};
 
+use crate::balance_monitor::wait_until_resumed;
 use crate::{
-    is_backend_gone, nonce_managed_provider::NonceManagedProvider, ops, AbstractSigner,
-    ConfigSettings, HealthStatus,
+    balance_monitor::BalanceMonitor, decryption_aggregator::PublicDecryptionAggregator,
+    nonce_managed_provider::NonceManagedProvider, ops, purger::Purger, scheduler::Scheduler,
+    stuck_nonce_monitor::StuckNonceMonitor, AbstractSigner, ConfigSettings, HealthStatus,
 };
 
 #[derive(Clone)]
@@ -20,7 +25,11 @@ pub struct TransactionSender<P: Provider<Ethereum> + Clone + 'static> {
     ciphertext_commits_address: Address,
     multichain_acl_address: Address,
     db_pool: Pool<Postgres>,
+    bus: Arc<dyn NotificationBus>,
     provider: NonceManagedProvider<P>,
+    public_decryption_aggregator: Option<PublicDecryptionAggregator>,
+    /// Set by the balance monitor while the balance is too low to send
+    paused: Arc<watch::Sender<bool>>,
 }
 
 impl<P: Provider<Ethereum> + Clone + 'static> TransactionSender<P> {
@@ -37,10 +46,15 @@ impl<P: Provider<Ethereum> + Clone + 'static> TransactionSender<P> {
     ) -> anyhow::Result<Self> {
         let db_pool = sqlx::postgres::PgPoolOptions::new()
             .max_connections(conf.database_pool_size)
-            .connect(&conf.database_url)
+            .connect_with(db_schema::connect_options(
+                &conf.database_url,
+                conf.database_schema.as_deref(),
+            )?)
             .await?;
+        let gw_chain_id = provider.get_chain_id().await?;
+        let bus = notification_bus::connect(&conf.message_bus, &db_pool).await?;
 
-        let operations: Vec<Arc<dyn ops::TransactionOperation<P>>> = vec![
+        let mut operations: Vec<Arc<dyn ops::TransactionOperation<P>>> = vec![
             Arc::new(
                 ops::verify_proof::VerifyProofOperation::new(
                     input_verification_address,
@@ -57,6 +71,7 @@ impl<P: Provider<Ethereum> + Clone + 'static> TransactionSender<P> {
                 provider.clone(),
                 conf.clone(),
                 gas,
+                gw_chain_id,
                 db_pool.clone(),
             )),
             Arc::new(ops::allow_handle::MultichainACLOperation::new(
@@ -64,9 +79,40 @@ impl<P: Provider<Ethereum> + Clone + 'static> TransactionSender<P> {
                 provider.clone(),
                 conf.clone(),
                 gas,
+                gw_chain_id,
                 db_pool.clone(),
             )),
         ];
+        if let Some(decryption_address) = conf.decryption_address {
+            operations.push(Arc::new(
+                ops::decryption_response::DecryptionResponseOperation::new(
+                    decryption_address,
+                    provider.clone(),
+                    conf.clone(),
+                    gas,
+                    gw_chain_id,
+                    db_pool.clone(),
+                ),
+            ));
+        }
+        let public_decryption_aggregator = match conf.decryption_address {
+            Some(decryption_address) if conf.public_decryption_aggregation_enabled() => {
+                if let Some(gateway_config_address) = conf.gateway_config_address {
+                    conf.public_decryption_quorum
+                        .validate_onchain(provider.inner(), gateway_config_address)
+                        .await?;
+                }
+                Some(PublicDecryptionAggregator::new(
+                    decryption_address,
+                    gw_chain_id,
+                    db_pool.clone(),
+                    bus.clone(),
+                    conf.clone(),
+                    cancel_token.clone(),
+                )?)
+            }
+            _ => None,
+        };
         Ok(Self {
             cancel_token,
             conf,
@@ -75,7 +121,10 @@ impl<P: Provider<Ethereum> + Clone + 'static> TransactionSender<P> {
             ciphertext_commits_address,
             multichain_acl_address,
             db_pool,
+            bus,
             provider,
+            public_decryption_aggregator,
+            paused: Arc::new(watch::channel(false).0),
         })
     }
 
@@ -85,6 +134,7 @@ impl<P: Provider<Ethereum> + Clone + 'static> TransactionSender<P> {
             input_verification_address = %self.input_verification_address,
             ciphertext_commits_address = %self.ciphertext_commits_address,
             multichain_acl_address = %self.multichain_acl_address,
+            decryption_address = ?self.conf.decryption_address,
             "Starting Transaction Sender"
         );
 
@@ -99,24 +149,36 @@ impl<P: Provider<Ethereum> + Clone + 'static> TransactionSender<P> {
                 info!(channel = op_channel, "Spawning operation loop");
                 async move {
                     let mut sleep_duration = sender.conf.error_sleep_initial_secs as u64;
-                    let mut listener = PgListener::connect_with(&sender.db_pool).await?;
-                    listener.listen(&op_channel).await?;
+                    let mut listener = sender.bus.subscribe(&[op_channel.clone()]).await?;
+                    let mut paused = sender.paused.subscribe();
                     loop {
                         if token.is_cancelled() {
                             info!(channel = op_channel, "Operation stopping");
                             break;
                         }
 
+                        if *paused.borrow() {
+                            info!(
+                                channel = op_channel,
+                                "Sending paused, waiting for the balance to be topped up"
+                            );
+                            if !wait_until_resumed(&mut paused, &token).await {
+                                info!(channel = op_channel, "Operation stopping");
+                                break;
+                            }
+                            continue;
+                        }
+
                         match op.execute().await {
                             Err(e) => {
-                                if is_backend_gone(&e) {
+                                if e.is_backend_gone() {
                                     error!(
                                         channel = op_channel,
                                         error = %e,
                                         "Backend gone error, stopping operation and signalling other operations to stop"
                                     );
                                     token.cancel();
-                                    return Err(e);
+                                    return Err(e.into());
                                 }
                                 error!(
                                     channel = op_channel,
@@ -137,36 +199,28 @@ impl<P: Provider<Ethereum> + Clone + 'static> TransactionSender<P> {
                                 // Maybe no more work to do, go and wait for the next notification.
                                 sender.reset_sleep_duration(&mut sleep_duration);
 
-                                let notification = listener.try_recv().fuse();
+                                let notification = listener
+                                    .recv_coalesced(sender.conf.db_notification_debounce)
+                                    .fuse();
                                 tokio::select! {
                                     _ = token.cancelled() => {
                                         info!(channel = op_channel, "Operation stopping");
                                         break;
                                     }
                                     n = notification => {
-                                        match n {
-                                            Ok(Some(_)) => {
+                                        match n.event {
+                                            BusEvent::Notification { .. } => {
                                                 debug!(
                                                     channel = op_channel,
-                                                    "Received notification, rechecking for work"
+                                                    count = n.count,
+                                                    "Received notifications, rechecking for work"
                                                 );
                                             },
-                                            Ok(None) => {
-                                                debug!(
-                                                    channel = op_channel,
-                                                    sleep_duration = sleep_duration,
-                                                    "Received empty notification, sleeping"
-                                                );
-                                                sender.sleep_with_backoff(&mut sleep_duration).await;
-                                            }
-                                            Err(e) => {
-                                                error!(
+                                            BusEvent::Reconnected => {
+                                                info!(
                                                     channel = op_channel,
-                                                    error = %e,
-                                                    sleep_duration = sleep_duration,
-                                                    "Notification error, sleeping"
+                                                    "Listener reconnected, rechecking for missed work"
                                                 );
-                                                sender.sleep_with_backoff(&mut sleep_duration).await;
                                             }
                                         }
                                     }
@@ -185,6 +239,70 @@ impl<P: Provider<Ethereum> + Clone + 'static> TransactionSender<P> {
             });
         }
 
+        // The EOA does not send transactions itself with user operations
+        match self.provider.signer_address() {
+            Some(signer_address)
+                if !self.conf.stuck_nonce_timeout.is_zero()
+                    && !self.provider.uses_user_operations() =>
+            {
+                let monitor = StuckNonceMonitor::new(
+                    self.provider.clone(),
+                    signer_address,
+                    self.conf.clone(),
+                    self.cancel_token.clone(),
+                );
+                join_set.spawn(monitor.run());
+            }
+            _ => info!("Stuck nonce monitor disabled"),
+        }
+
+        // The thresholds apply to the account paying the fees, the smart account with user
+        // operations
+        match self.provider.sender_address() {
+            Some(payer_address) if !self.conf.balance_check_interval.is_zero() => {
+                let other_addresses = self
+                    .provider
+                    .signer_address()
+                    .filter(|signer_address| *signer_address != payer_address)
+                    .into_iter()
+                    .collect();
+                let monitor = BalanceMonitor::new(
+                    self.provider.clone(),
+                    payer_address,
+                    other_addresses,
+                    self.conf.clone(),
+                    self.paused.clone(),
+                    self.cancel_token.clone(),
+                );
+                join_set.spawn(monitor.run());
+            }
+            _ => info!("Balance monitor disabled"),
+        }
+
+        let purger = Arc::new(Purger::new(
+            self.db_pool.clone(),
+            self.conf.clone(),
+            self.cancel_token.clone(),
+        ));
+        let mut scheduler = Scheduler::new(self.cancel_token.clone());
+        scheduler.register(
+            "purge",
+            purger.schedule(),
+            self.conf.purge_jitter,
+            move || {
+                let purger = purger.clone();
+                async move { purger.purge().await }
+            },
+        );
+        join_set.spawn(scheduler.run());
+
+        match &self.public_decryption_aggregator {
+            Some(aggregator) => {
+                join_set.spawn(aggregator.clone().run());
+            }
+            None => info!("Public decryption aggregation disabled"),
+        }
+
         self.cancel_token.cancelled().await;
         info!("Cancellation requested, waiting for operations to stop");
         // Make sure we don't wait indefinitely.
@@ -220,6 +338,22 @@ impl<P: Provider<Ethereum> + Clone + 'static> TransactionSender<P> {
         }
     }
 
+    /// Fails if a contract the operations send transactions to does not match the bindings, e.g.
+    /// a function or an error selector the operation relies on is not in the deployed code.
+    pub async fn check_bindings(&self) -> anyhow::Result<()> {
+        for op in &self.operations {
+            let (address, expected) = op.expected_selectors();
+            let code_hash = check_selectors(self.provider.inner(), address, &expected).await?;
+            info!(
+                channel = op.channel(),
+                %address,
+                %code_hash,
+                "Bindings match the deployed contract"
+            );
+        }
+        Ok(())
+    }
+
     fn reset_sleep_duration(&self, sleep_duration: &mut u64) {
         *sleep_duration = self.conf.error_sleep_initial_secs as u64;
     }
@@ -0,0 +1,613 @@
+//! Submission of transactions as ERC-4337 user operations.
+//!
+//! Instead of being sent from the EOA of the signer, the calls of the operations are executed by a
+//! smart account, owned by the signer, through the EntryPoint v0.7 contract. The user operations
+//! are sent to a bundler and their fees can be sponsored by a paymaster. The transaction of the
+//! bundle is then followed like any other transaction, the gas of the user operation being
+//! accounted instead of the gas of the whole bundle.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use alloy::{
+    network::Ethereum,
+    primitives::{Address, Bytes, FixedBytes, TxHash, U256},
+    providers::{PendingTransactionBuilder, Provider, RootProvider},
+    rpc::{json_rpc::ErrorPayload, types::TransactionRequest},
+    signers::Signer,
+    sol,
+    sol_types::SolCall,
+    transports::{http::reqwest::Url, RpcError, TransportErrorKind, TransportResult},
+};
+use futures_util::lock::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::AbstractSigner;
+
+sol! {
+    #[sol(rpc)]
+    interface IEntryPoint {
+        struct PackedUserOperation {
+            address sender;
+            uint256 nonce;
+            bytes initCode;
+            bytes callData;
+            bytes32 accountGasLimits;
+            uint256 preVerificationGas;
+            bytes32 gasFees;
+            bytes paymasterAndData;
+            bytes signature;
+        }
+
+        function getNonce(address sender, uint192 key) external view returns (uint256 nonce);
+        function getUserOpHash(PackedUserOperation calldata userOp) external view returns (bytes32);
+    }
+
+    interface ISmartAccount {
+        function execute(address dest, uint256 value, bytes calldata func) external;
+    }
+}
+
+/// JSON-RPC error code of a reverted call, used to report a failed user operation like a reverted
+/// transaction so that the operations decode the contract errors as usual.
+const EXECUTION_REVERTED_CODE: i64 = 3;
+
+/// Spends of the bundled user operations kept until their receipt is recorded. Older ones, of
+/// operations that failed before recording it, are dropped.
+const MAX_PENDING_SPENDS: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub struct UserOperationConfig {
+    pub bundler_url: Url,
+    pub entry_point_address: Address,
+    /// Smart account executing the calls, the signer must be its owner.
+    pub smart_account_address: Address,
+    pub paymaster_address: Option<Address>,
+    pub paymaster_data: Bytes,
+    /// How long to wait for a user operation to be bundled.
+    pub inclusion_timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+/// User operation in the unpacked format of the bundler RPC API.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UserOperation {
+    sender: Address,
+    nonce: U256,
+    call_data: Bytes,
+    call_gas_limit: U256,
+    verification_gas_limit: U256,
+    pre_verification_gas: U256,
+    max_fee_per_gas: U256,
+    max_priority_fee_per_gas: U256,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paymaster: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paymaster_verification_gas_limit: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paymaster_post_op_gas_limit: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paymaster_data: Option<Bytes>,
+    signature: Bytes,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserOperationGas {
+    pre_verification_gas: U256,
+    verification_gas_limit: U256,
+    call_gas_limit: U256,
+    #[serde(default)]
+    paymaster_verification_gas_limit: Option<U256>,
+    #[serde(default)]
+    paymaster_post_op_gas_limit: Option<U256>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserOperationReceipt {
+    user_op_hash: FixedBytes<32>,
+    success: bool,
+    #[serde(default)]
+    reason: Option<Bytes>,
+    actual_gas_used: U256,
+    actual_gas_cost: U256,
+    receipt: BundleReceipt,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BundleReceipt {
+    transaction_hash: TxHash,
+}
+
+/// Gas of a user operation, the share of its bundle transaction paid for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserOperationSpend {
+    pub user_op_hash: FixedBytes<32>,
+    pub gas_used: u64,
+    /// Cost in wei.
+    pub total_cost: U256,
+}
+
+/// Bundled user operation whose spend is not recorded yet.
+struct PendingSpend {
+    txn_hash: TxHash,
+    dest: Address,
+    input: Bytes,
+    spend: UserOperationSpend,
+}
+
+/// Sends the transactions of the operations as user operations, see the module documentation.
+pub struct UserOperationSender {
+    conf: UserOperationConfig,
+    bundler: RootProvider<Ethereum>,
+    signer: AbstractSigner,
+    /// Next EntryPoint nonce of the smart account, fetched again after a failure.
+    nonce: Mutex<Option<U256>>,
+    pending_spends: std::sync::Mutex<VecDeque<PendingSpend>>,
+}
+
+impl UserOperationSender {
+    pub fn new(conf: UserOperationConfig, signer: AbstractSigner) -> Self {
+        info!(
+            bundler_url = %conf.bundler_url,
+            entry_point_address = %conf.entry_point_address,
+            smart_account_address = %conf.smart_account_address,
+            paymaster_address = ?conf.paymaster_address,
+            "Sending transactions as user operations"
+        );
+        Self {
+            bundler: RootProvider::new_http(conf.bundler_url.clone()),
+            conf,
+            signer,
+            nonce: Mutex::new(None),
+            pending_spends: Default::default(),
+        }
+    }
+
+    pub fn smart_account_address(&self) -> Address {
+        self.conf.smart_account_address
+    }
+
+    /// Wraps the call of the transaction into a user operation and waits for it to be bundled.
+    /// The returned pending transaction is the one of the bundle.
+    pub async fn send_transaction<P: Provider<Ethereum>>(
+        &self,
+        provider: &P,
+        tx: TransactionRequest,
+    ) -> TransportResult<PendingTransactionBuilder<Ethereum>> {
+        let mut nonce = self.nonce.lock().await;
+        let res = self.send_user_operation(provider, &tx, &mut nonce).await;
+        if res.is_err() {
+            *nonce = None;
+        }
+        drop(nonce);
+        let user_op_hash = res?;
+
+        let receipt = self.wait_for_receipt(user_op_hash).await?;
+        if let Some(dest) = tx.to.and_then(|to| to.to().copied()) {
+            self.push_spend(PendingSpend {
+                txn_hash: receipt.receipt.transaction_hash,
+                dest,
+                input: tx.input.input().cloned().unwrap_or_default(),
+                spend: UserOperationSpend {
+                    user_op_hash: receipt.user_op_hash,
+                    gas_used: receipt.actual_gas_used.saturating_to(),
+                    total_cost: receipt.actual_gas_cost,
+                },
+            });
+        }
+        if !receipt.success {
+            warn!(
+                user_op_hash = %user_op_hash,
+                transaction_hash = %receipt.receipt.transaction_hash,
+                "User operation reverted"
+            );
+            return Err(RpcError::ErrorResp(ErrorPayload {
+                code: EXECUTION_REVERTED_CODE,
+                message: "execution reverted".into(),
+                data: receipt
+                    .reason
+                    .and_then(|reason| serde_json::value::to_raw_value(&reason).ok()),
+            }));
+        }
+        Ok(PendingTransactionBuilder::new(
+            provider.root().clone(),
+            receipt.receipt.transaction_hash,
+        ))
+    }
+
+    /// Takes the spend of the user operation sent for the transaction, bundled in `txn_hash`.
+    pub fn take_spend(
+        &self,
+        txn_hash: &TxHash,
+        tx: &TransactionRequest,
+    ) -> Option<UserOperationSpend> {
+        let dest = tx.to.and_then(|to| to.to().copied())?;
+        let input = tx.input.input().cloned().unwrap_or_default();
+        let mut pending = self.pending_spends.lock().unwrap();
+        let position = pending.iter().position(|pending| {
+            pending.txn_hash == *txn_hash && pending.dest == dest && pending.input == input
+        })?;
+        pending.remove(position).map(|pending| pending.spend)
+    }
+
+    fn push_spend(&self, spend: PendingSpend) {
+        let mut pending = self.pending_spends.lock().unwrap();
+        if pending.len() >= MAX_PENDING_SPENDS {
+            pending.pop_front();
+        }
+        pending.push_back(spend);
+    }
+
+    async fn send_user_operation<P: Provider<Ethereum>>(
+        &self,
+        provider: &P,
+        tx: &TransactionRequest,
+        nonce: &mut Option<U256>,
+    ) -> TransportResult<FixedBytes<32>> {
+        let dest = tx.to.and_then(|to| to.to().copied()).ok_or_else(|| {
+            TransportErrorKind::custom_str("user operations must call a contract")
+        })?;
+        let call_data = ISmartAccount::executeCall {
+            dest,
+            value: tx.value.unwrap_or_default(),
+            func: tx.input.input().cloned().unwrap_or_default(),
+        }
+        .abi_encode();
+
+        let entry_point = IEntryPoint::new(self.conf.entry_point_address, provider);
+        let current_nonce = match *nonce {
+            Some(nonce) => nonce,
+            None => entry_point
+                .getNonce(self.conf.smart_account_address, Default::default())
+                .call()
+                .await
+                .map_err(contract_error)?,
+        };
+        let fees = provider.estimate_eip1559_fees().await?;
+
+        let mut user_op = UserOperation {
+            sender: self.conf.smart_account_address,
+            nonce: current_nonce,
+            call_data: call_data.into(),
+            max_fee_per_gas: U256::from(fees.max_fee_per_gas),
+            max_priority_fee_per_gas: U256::from(fees.max_priority_fee_per_gas),
+            paymaster: self.conf.paymaster_address,
+            paymaster_data: self
+                .conf
+                .paymaster_address
+                .map(|_| self.conf.paymaster_data.clone()),
+            // The estimation requires a signature of the right length
+            signature: Bytes::from(vec![0xff; 65]),
+            ..Default::default()
+        };
+        let gas: UserOperationGas = self
+            .bundler
+            .raw_request(
+                "eth_estimateUserOperationGas".into(),
+                (&user_op, self.conf.entry_point_address),
+            )
+            .await?;
+        user_op.pre_verification_gas = gas.pre_verification_gas;
+        user_op.verification_gas_limit = gas.verification_gas_limit;
+        user_op.call_gas_limit = gas.call_gas_limit;
+        if user_op.paymaster.is_some() {
+            user_op.paymaster_verification_gas_limit = gas.paymaster_verification_gas_limit;
+            user_op.paymaster_post_op_gas_limit = gas.paymaster_post_op_gas_limit;
+        }
+
+        let hash = entry_point
+            .getUserOpHash(pack(&user_op))
+            .call()
+            .await
+            .map_err(contract_error)?;
+        let signature = self
+            .signer
+            .sign_message(hash.as_slice())
+            .await
+            .map_err(TransportErrorKind::custom)?;
+        user_op.signature = Bytes::copy_from_slice(&signature.as_bytes());
+
+        let user_op_hash: FixedBytes<32> = self
+            .bundler
+            .raw_request(
+                "eth_sendUserOperation".into(),
+                (&user_op, self.conf.entry_point_address),
+            )
+            .await?;
+        *nonce = Some(current_nonce + U256::from(1));
+        debug!(user_op_hash = %user_op_hash, nonce = %current_nonce, "User operation sent");
+        Ok(user_op_hash)
+    }
+
+    async fn wait_for_receipt(
+        &self,
+        user_op_hash: FixedBytes<32>,
+    ) -> TransportResult<UserOperationReceipt> {
+        let deadline = tokio::time::Instant::now() + self.conf.inclusion_timeout;
+        loop {
+            let receipt: Option<UserOperationReceipt> = self
+                .bundler
+                .raw_request("eth_getUserOperationReceipt".into(), (user_op_hash,))
+                .await?;
+            if let Some(receipt) = receipt {
+                return Ok(receipt);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(TransportErrorKind::custom_str(&format!(
+                    "user operation {user_op_hash} not bundled after {:?}",
+                    self.conf.inclusion_timeout
+                )));
+            }
+            tokio::time::sleep(self.conf.poll_interval).await;
+        }
+    }
+}
+
+fn contract_error(err: alloy::contract::Error) -> RpcError<TransportErrorKind> {
+    match err {
+        alloy::contract::Error::TransportError(err) => err,
+        err => TransportErrorKind::custom(err),
+    }
+}
+
+/// Packs the gas fields of the user operation as in the EntryPoint v0.7.
+fn pack(user_op: &UserOperation) -> IEntryPoint::PackedUserOperation {
+    let pack_u128 = |high: U256, low: U256| -> FixedBytes<32> {
+        let mut packed = [0u8; 32];
+        packed[..16].copy_from_slice(&high.to_be_bytes::<32>()[16..]);
+        packed[16..].copy_from_slice(&low.to_be_bytes::<32>()[16..]);
+        packed.into()
+    };
+    let paymaster_and_data = match user_op.paymaster {
+        Some(paymaster) => {
+            let mut data = paymaster.to_vec();
+            for gas in [
+                user_op.paymaster_verification_gas_limit,
+                user_op.paymaster_post_op_gas_limit,
+            ] {
+                data.extend_from_slice(&gas.unwrap_or_default().to_be_bytes::<32>()[16..]);
+            }
+            data.extend_from_slice(user_op.paymaster_data.as_deref().unwrap_or_default());
+            data.into()
+        }
+        None => Bytes::new(),
+    };
+    IEntryPoint::PackedUserOperation {
+        sender: user_op.sender,
+        nonce: user_op.nonce,
+        initCode: Bytes::new(),
+        callData: user_op.call_data.clone(),
+        accountGasLimits: pack_u128(user_op.verification_gas_limit, user_op.call_gas_limit),
+        preVerificationGas: user_op.pre_verification_gas,
+        gasFees: pack_u128(user_op.max_priority_fee_per_gas, user_op.max_fee_per_gas),
+        paymasterAndData: paymaster_and_data,
+        signature: user_op.signature.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{primitives::address, signers::local::PrivateKeySigner};
+    use serde_json::json;
+
+    use super::*;
+    use crate::make_abstract_signer;
+
+    const PAYMASTER: Address = address!("0x00000000000000000000000000000000000000aa");
+
+    fn user_op() -> UserOperation {
+        UserOperation {
+            sender: address!("0x00000000000000000000000000000000000000bb"),
+            nonce: U256::from(7),
+            call_data: Bytes::from_static(&[1, 2, 3]),
+            call_gas_limit: U256::from(0x22),
+            verification_gas_limit: U256::from(0x11),
+            pre_verification_gas: U256::from(0x33),
+            max_fee_per_gas: U256::from(0x55),
+            max_priority_fee_per_gas: U256::from(0x44),
+            signature: Bytes::from_static(&[9; 65]),
+            ..Default::default()
+        }
+    }
+
+    fn sender() -> UserOperationSender {
+        UserOperationSender::new(
+            UserOperationConfig {
+                bundler_url: "http://localhost:4337".parse().unwrap(),
+                entry_point_address: Address::ZERO,
+                smart_account_address: Address::ZERO,
+                paymaster_address: None,
+                paymaster_data: Bytes::new(),
+                inclusion_timeout: Duration::from_secs(1),
+                poll_interval: Duration::from_millis(10),
+            },
+            make_abstract_signer(PrivateKeySigner::random()),
+        )
+    }
+
+    fn pending_spend(txn_hash: TxHash, input: &'static [u8], user_op_hash: u8) -> PendingSpend {
+        PendingSpend {
+            txn_hash,
+            dest: PAYMASTER,
+            input: Bytes::from_static(input),
+            spend: UserOperationSpend {
+                user_op_hash: FixedBytes::repeat_byte(user_op_hash),
+                gas_used: 21000,
+                total_cost: U256::from(42000),
+            },
+        }
+    }
+
+    #[test]
+    fn pack_without_paymaster() {
+        let user_op = user_op();
+        let packed = pack(&user_op);
+        assert_eq!(packed.sender, user_op.sender);
+        assert_eq!(packed.nonce, user_op.nonce);
+        assert!(packed.initCode.is_empty());
+        assert_eq!(packed.callData, user_op.call_data);
+        let mut expected = [0u8; 32];
+        expected[15] = 0x11;
+        expected[31] = 0x22;
+        assert_eq!(packed.accountGasLimits, FixedBytes::from(expected));
+        assert_eq!(packed.preVerificationGas, U256::from(0x33));
+        expected[15] = 0x44;
+        expected[31] = 0x55;
+        assert_eq!(packed.gasFees, FixedBytes::from(expected));
+        assert!(packed.paymasterAndData.is_empty());
+        assert_eq!(packed.signature, user_op.signature);
+    }
+
+    #[test]
+    fn pack_with_paymaster() {
+        let user_op = UserOperation {
+            paymaster: Some(PAYMASTER),
+            paymaster_verification_gas_limit: Some(U256::from(0x66)),
+            paymaster_post_op_gas_limit: Some(U256::from(0x77)),
+            paymaster_data: Some(Bytes::from_static(&[0xde, 0xad])),
+            ..user_op()
+        };
+        let packed = pack(&user_op);
+        let data = packed.paymasterAndData;
+        assert_eq!(data.len(), 20 + 16 + 16 + 2);
+        assert_eq!(&data[..20], PAYMASTER.as_slice());
+        assert_eq!(data[20..35], [0; 15]);
+        assert_eq!(data[35], 0x66);
+        assert_eq!(data[36..51], [0; 15]);
+        assert_eq!(data[51], 0x77);
+        assert_eq!(&data[52..], &[0xde, 0xad]);
+    }
+
+    #[test]
+    fn pack_with_paymaster_and_no_gas_limits() {
+        let user_op = UserOperation {
+            paymaster: Some(PAYMASTER),
+            ..user_op()
+        };
+        let data = pack(&user_op).paymasterAndData;
+        assert_eq!(&data[..20], PAYMASTER.as_slice());
+        assert_eq!(data[20..], [0; 32]);
+    }
+
+    #[test]
+    fn user_operation_serialization() {
+        let value = serde_json::to_value(user_op()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "sender": "0x00000000000000000000000000000000000000bb",
+                "nonce": "0x7",
+                "callData": "0x010203",
+                "callGasLimit": "0x22",
+                "verificationGasLimit": "0x11",
+                "preVerificationGas": "0x33",
+                "maxFeePerGas": "0x55",
+                "maxPriorityFeePerGas": "0x44",
+                "signature": format!("0x{}", "09".repeat(65)),
+            })
+        );
+
+        let value = serde_json::to_value(UserOperation {
+            paymaster: Some(PAYMASTER),
+            paymaster_verification_gas_limit: Some(U256::from(0x66)),
+            paymaster_post_op_gas_limit: Some(U256::from(0x77)),
+            paymaster_data: Some(Bytes::new()),
+            ..user_op()
+        })
+        .unwrap();
+        assert_eq!(value["paymaster"], json!(PAYMASTER));
+        assert_eq!(value["paymasterVerificationGasLimit"], "0x66");
+        assert_eq!(value["paymasterPostOpGasLimit"], "0x77");
+        assert_eq!(value["paymasterData"], "0x");
+    }
+
+    #[test]
+    fn user_operation_gas_deserialization() {
+        let gas: UserOperationGas = serde_json::from_value(json!({
+            "preVerificationGas": "0x33",
+            "verificationGasLimit": "0x11",
+            "callGasLimit": "0x22",
+        }))
+        .unwrap();
+        assert_eq!(gas.pre_verification_gas, U256::from(0x33));
+        assert_eq!(gas.verification_gas_limit, U256::from(0x11));
+        assert_eq!(gas.call_gas_limit, U256::from(0x22));
+        assert_eq!(gas.paymaster_verification_gas_limit, None);
+        assert_eq!(gas.paymaster_post_op_gas_limit, None);
+
+        let gas: UserOperationGas = serde_json::from_value(json!({
+            "preVerificationGas": "0x33",
+            "verificationGasLimit": "0x11",
+            "callGasLimit": "0x22",
+            "paymasterVerificationGasLimit": "0x66",
+            "paymasterPostOpGasLimit": "0x77",
+        }))
+        .unwrap();
+        assert_eq!(gas.paymaster_verification_gas_limit, Some(U256::from(0x66)));
+        assert_eq!(gas.paymaster_post_op_gas_limit, Some(U256::from(0x77)));
+    }
+
+    #[test]
+    fn user_operation_receipt_deserialization() {
+        let user_op_hash = FixedBytes::<32>::repeat_byte(1);
+        let transaction_hash = TxHash::repeat_byte(2);
+        let receipt: UserOperationReceipt = serde_json::from_value(json!({
+            "userOpHash": user_op_hash,
+            "sender": "0x00000000000000000000000000000000000000bb",
+            "success": false,
+            "reason": "0x08c379a0",
+            "actualGasUsed": "0x5208",
+            "actualGasCost": "0xa410",
+            "receipt": { "transactionHash": transaction_hash, "blockNumber": "0x1" },
+        }))
+        .unwrap();
+        assert_eq!(receipt.user_op_hash, user_op_hash);
+        assert!(!receipt.success);
+        assert_eq!(
+            receipt.reason,
+            Some(Bytes::from_static(&[0x08, 0xc3, 0x79, 0xa0]))
+        );
+        assert_eq!(receipt.actual_gas_used, U256::from(21000));
+        assert_eq!(receipt.actual_gas_cost, U256::from(42000));
+        assert_eq!(receipt.receipt.transaction_hash, transaction_hash);
+    }
+
+    #[test]
+    fn spends_are_taken_once_per_user_operation() {
+        let sender = sender();
+        let txn_hash = TxHash::repeat_byte(2);
+        sender.push_spend(pending_spend(txn_hash, &[1], 1));
+        sender.push_spend(pending_spend(txn_hash, &[2], 2));
+        let tx = |input: &'static [u8]| {
+            TransactionRequest::default()
+                .to(PAYMASTER)
+                .input(Bytes::from_static(input).into())
+        };
+
+        assert_eq!(sender.take_spend(&TxHash::repeat_byte(3), &tx(&[1])), None);
+        assert_eq!(sender.take_spend(&txn_hash, &tx(&[3])), None);
+        let spend = sender.take_spend(&txn_hash, &tx(&[2])).unwrap();
+        assert_eq!(spend.user_op_hash, FixedBytes::repeat_byte(2));
+        assert_eq!(spend.gas_used, 21000);
+        assert_eq!(spend.total_cost, U256::from(42000));
+        assert_eq!(sender.take_spend(&txn_hash, &tx(&[2])), None);
+        assert!(sender.take_spend(&txn_hash, &tx(&[1])).is_some());
+    }
+
+    #[test]
+    fn pending_spends_are_bounded() {
+        let sender = sender();
+        for i in 0..=MAX_PENDING_SPENDS {
+            sender.push_spend(pending_spend(TxHash::with_last_byte(i as u8), &[], 1));
+        }
+        assert_eq!(
+            sender.pending_spends.lock().unwrap().len(),
+            MAX_PENDING_SPENDS
+        );
+    }
+}
@@ -1,6 +1,7 @@
 mod common;
 
 use alloy::network::TxSigner;
+use alloy::primitives::U256;
 use alloy::providers::{ProviderBuilder, WsConnect};
 use alloy::signers::local::PrivateKeySigner;
 use common::{CiphertextCommits, TestEnvironment};
@@ -9,9 +10,11 @@ use common::SignerType;
 use rand::{random, Rng};
 use rstest::*;
 use serial_test::serial;
+use sqlx::types::time::{self, OffsetDateTime};
 use std::time::Duration;
 use test_harness::db_utils::{insert_ciphertext_digest, insert_random_tenant};
 use tokio::time::sleep;
+use transaction_sender::gas_spend::{gas_spend_summary, OP_ADD_CIPHERTEXT};
 use transaction_sender::{
     is_backend_gone, ConfigSettings, FillersWithoutNonceManagement, NonceManagedProvider,
     TransactionSender,
@@ -121,6 +124,118 @@ async fn add_ciphertext_digests(#[case] signer_type: SignerType) -> anyhow::Resu
     Ok(())
 }
 
+#[rstest]
+#[case::private_key(SignerType::PrivateKey)]
+#[case::aws_kms(SignerType::AwsKms)]
+#[tokio::test]
+#[serial(db)]
+async fn add_ciphertext_records_gas_spend(#[case] signer_type: SignerType) -> anyhow::Result<()> {
+    let env = TestEnvironment::new(signer_type).await?;
+    let provider_deploy = ProviderBuilder::new()
+        .wallet(env.wallet.clone())
+        .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+        .await?;
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(env.wallet.clone())
+            .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+            .await?,
+        Some(env.wallet.default_signer().address()),
+    );
+
+    let already_added_revert = false;
+    let ciphertext_commits =
+        CiphertextCommits::deploy(&provider_deploy, already_added_revert).await?;
+    let txn_sender = TransactionSender::new(
+        PrivateKeySigner::random().address(),
+        *ciphertext_commits.address(),
+        PrivateKeySigner::random().address(),
+        env.signer.clone(),
+        provider.clone(),
+        env.cancel_token.clone(),
+        env.conf.clone(),
+        None,
+    )
+    .await?;
+
+    let started_at = OffsetDateTime::now_utc() - time::Duration::seconds(1);
+    let run_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    let tenant_id = insert_random_tenant(&env.db_pool).await?;
+    let handle = random::<[u8; 32]>();
+    insert_ciphertext_digest(
+        &env.db_pool,
+        tenant_id,
+        &handle,
+        &random::<[u8; 32]>(),
+        &random::<[u8; 32]>(),
+        1,
+    )
+    .await?;
+
+    sqlx::query!(
+        "
+        SELECT pg_notify($1, '')",
+        env.conf.add_ciphertexts_db_channel
+    )
+    .execute(&env.db_pool)
+    .await?;
+
+    loop {
+        let rows = sqlx::query!(
+            "SELECT txn_is_sent
+             FROM ciphertext_digest
+             WHERE handle = $1",
+            &handle,
+        )
+        .fetch_one(&env.db_pool)
+        .await?;
+        if rows.txn_is_sent {
+            break;
+        }
+
+        sleep(Duration::from_millis(500)).await;
+    }
+
+    let summary = gas_spend_summary(
+        &env.db_pool,
+        started_at,
+        OffsetDateTime::now_utc() + time::Duration::seconds(1),
+    )
+    .await?;
+    assert_eq!(summary.len(), 1);
+    assert_eq!(summary[0].operation, OP_ADD_CIPHERTEXT);
+    assert_eq!(summary[0].txn_count, 1);
+    assert_eq!(summary[0].failed_txn_count, 0);
+    assert!(summary[0].total_gas_used > 0);
+    assert!(summary[0].total_cost > U256::ZERO);
+
+    let receipt = sqlx::query!(
+        "SELECT operation, success, revert_reason
+         FROM transaction_receipts
+         WHERE $1 = ANY(queue_keys)",
+        alloy::hex::encode_prefixed(handle),
+    )
+    .fetch_one(&env.db_pool)
+    .await?;
+    assert_eq!(receipt.operation, OP_ADD_CIPHERTEXT);
+    assert!(receipt.success);
+    assert!(receipt.revert_reason.is_none());
+
+    sqlx::query!(
+        "
+        delete from tenants where tenant_id = $1",
+        tenant_id
+    )
+    .execute(&env.db_pool)
+    .await?;
+
+    env.cancel_token.cancel();
+    run_handle.await??;
+    Ok(())
+}
+
 #[rstest]
 #[case::private_key(SignerType::PrivateKey)]
 #[case::aws_kms(SignerType::AwsKms)]
@@ -1,7 +1,11 @@
 use alloy::network::TxSigner;
+use alloy::providers::Provider;
 use alloy::providers::ProviderBuilder;
 use alloy::signers::local::PrivateKeySigner;
-use alloy::{primitives::Address, providers::WsConnect};
+use alloy::{
+    primitives::{Address, U256},
+    providers::WsConnect,
+};
 use common::{MultichainACL, SignerType, TestEnvironment};
 
 use fhevm_engine_common::types::AllowEvents;
@@ -397,3 +401,212 @@ async fn retry_on_aws_kms_error(#[case] signer_type: SignerType) -> anyhow::Resu
 
     Ok(())
 }
+
+#[tokio::test]
+#[serial(db)]
+async fn pause_on_low_balance() -> anyhow::Result<()> {
+    // Above the balance of the anvil accounts
+    let conf = ConfigSettings {
+        balance_check_interval: Duration::from_millis(200),
+        balance_pause_threshold: 100_000 * 10u128.pow(18),
+        ..Default::default()
+    };
+    let env = TestEnvironment::new_with_config(SignerType::PrivateKey, conf.clone(), false).await?;
+    let signer_address = env.wallet.default_signer().address();
+    let provider_deploy = ProviderBuilder::new()
+        .wallet(env.wallet.clone())
+        .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+        .await?;
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(env.wallet.clone())
+            .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+            .await?,
+        Some(signer_address),
+    );
+    let multichain_acl = MultichainACL::deploy(&provider_deploy, false).await?;
+
+    let txn_sender = TransactionSender::new(
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        *multichain_acl.address(),
+        env.signer.clone(),
+        provider.clone(),
+        env.cancel_token.clone(),
+        env.conf.clone(),
+        None,
+    )
+    .await?;
+
+    let run_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    let tenant_id = insert_random_tenant(&env.db_pool).await?;
+    let initial_tx_count = provider.get_transaction_count(signer_address).await?;
+
+    let handle = random::<[u8; 32]>();
+    insert_allowed_handle(
+        &env.db_pool,
+        tenant_id,
+        &handle,
+        PrivateKeySigner::random().address(),
+        AllowEvents::AllowedAccount,
+    )
+    .await?;
+    sqlx::query!(
+        "
+        SELECT pg_notify($1, '')",
+        env.conf.allow_handle_db_channel
+    )
+    .execute(&env.db_pool)
+    .await?;
+
+    // Nothing is sent nor retried while paused
+    sleep(Duration::from_secs(3)).await;
+    let rows = sqlx::query!(
+        "SELECT txn_is_sent, txn_limited_retries_count, txn_unlimited_retries_count
+             FROM allowed_handles
+             WHERE handle = $1",
+        &handle,
+    )
+    .fetch_one(&env.db_pool)
+    .await?;
+    assert!(!rows.txn_is_sent);
+    assert_eq!(rows.txn_limited_retries_count, 0);
+    assert_eq!(rows.txn_unlimited_retries_count, 0);
+    assert_eq!(
+        provider.get_transaction_count(signer_address).await?,
+        initial_tx_count
+    );
+
+    // Sending resumes once topped up
+    provider_deploy
+        .raw_request::<_, ()>(
+            "anvil_setBalance".into(),
+            (
+                signer_address,
+                U256::from(200_000u64) * U256::from(10u64).pow(U256::from(18)),
+            ),
+        )
+        .await?;
+    loop {
+        let rows = sqlx::query!(
+            "SELECT txn_is_sent
+             FROM allowed_handles
+             WHERE handle = $1",
+            &handle,
+        )
+        .fetch_one(&env.db_pool)
+        .await?;
+        if rows.txn_is_sent {
+            break;
+        }
+
+        sleep(Duration::from_millis(500)).await;
+    }
+    sqlx::query!(
+        "
+        delete from tenants where tenant_id = $1",
+        tenant_id
+    )
+    .execute(&env.db_pool)
+    .await?;
+
+    env.cancel_token.cancel();
+    run_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn allow_account_in_chunks() -> anyhow::Result<()> {
+    let conf = ConfigSettings {
+        allow_handle_batch_limit: 5,
+        allow_handle_send_chunk_size: 2,
+        ..Default::default()
+    };
+    let env = TestEnvironment::new_with_config(SignerType::PrivateKey, conf.clone(), false).await?;
+    let signer_address = env.wallet.default_signer().address();
+    let provider_deploy = ProviderBuilder::new()
+        .wallet(env.wallet.clone())
+        .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+        .await?;
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(env.wallet.clone())
+            .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+            .await?,
+        Some(signer_address),
+    );
+    let multichain_acl = MultichainACL::deploy(&provider_deploy, false).await?;
+
+    let txn_sender = TransactionSender::new(
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        *multichain_acl.address(),
+        env.signer.clone(),
+        provider.clone(),
+        env.cancel_token.clone(),
+        env.conf.clone(),
+        None,
+    )
+    .await?;
+
+    let run_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    let tenant_id = insert_random_tenant(&env.db_pool).await?;
+    let initial_tx_count = provider.get_transaction_count(signer_address).await?;
+
+    // Sent in 3 chunks
+    let handles_count = 5;
+    for _ in 0..handles_count {
+        insert_allowed_handle(
+            &env.db_pool,
+            tenant_id,
+            &random::<[u8; 32]>(),
+            PrivateKeySigner::random().address(),
+            AllowEvents::AllowedAccount,
+        )
+        .await?;
+    }
+    sqlx::query!(
+        "
+        SELECT pg_notify($1, '')",
+        env.conf.allow_handle_db_channel
+    )
+    .execute(&env.db_pool)
+    .await?;
+
+    loop {
+        let sent = sqlx::query_scalar!(
+            "SELECT COUNT(*)
+             FROM allowed_handles
+             WHERE tenant_id = $1 AND txn_is_sent = true",
+            tenant_id,
+        )
+        .fetch_one(&env.db_pool)
+        .await?;
+        if sent == Some(handles_count) {
+            break;
+        }
+
+        sleep(Duration::from_millis(500)).await;
+    }
+
+    let tx_count = provider.get_transaction_count(signer_address).await?;
+    assert_eq!(tx_count, initial_tx_count + handles_count as u64);
+
+    sqlx::query!(
+        "
+        delete from tenants where tenant_id = $1",
+        tenant_id
+    )
+    .execute(&env.db_pool)
+    .await?;
+
+    env.cancel_token.cancel();
+    run_handle.await??;
+
+    Ok(())
+}
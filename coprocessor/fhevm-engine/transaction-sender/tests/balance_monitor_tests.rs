@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use alloy::network::Ethereum;
+use alloy::primitives::Address;
+use alloy::providers::RootProvider;
+use tokio::sync::watch;
+use tokio::time::{sleep, timeout};
+use tokio_util::sync::CancellationToken;
+use transaction_sender::test_utils::{wait_until_resumed, BalanceMonitor, MockProvider};
+use transaction_sender::{ConfigSettings, NonceManagedProvider};
+
+const PAYER: Address = Address::repeat_byte(0x42);
+const PAUSE_THRESHOLD: u128 = 1_000;
+
+fn monitor(
+    mock: &MockProvider,
+    paused: Arc<watch::Sender<bool>>,
+) -> BalanceMonitor<RootProvider<Ethereum>> {
+    let conf = ConfigSettings {
+        balance_pause_threshold: PAUSE_THRESHOLD,
+        balance_warn_threshold: 2 * PAUSE_THRESHOLD,
+        ..Default::default()
+    };
+    BalanceMonitor::new(
+        NonceManagedProvider::new(mock.provider(), Some(PAYER)),
+        PAYER,
+        vec![],
+        conf,
+        paused,
+        CancellationToken::new(),
+    )
+}
+
+fn push_balance(mock: &MockProvider, balance: u128) {
+    mock.push_success("eth_getBalance", &format!("{balance:#x}"));
+}
+
+#[tokio::test]
+async fn sending_is_paused_until_topped_up() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    let paused = Arc::new(watch::channel(false).0);
+    let monitor = monitor(&mock, paused.clone());
+
+    push_balance(&mock, PAUSE_THRESHOLD + 1);
+    monitor.check().await?;
+    assert!(!*paused.borrow());
+
+    push_balance(&mock, PAUSE_THRESHOLD - 1);
+    monitor.check().await?;
+    assert!(*paused.borrow());
+
+    // An operation waits instead of picking work
+    let mut receiver = paused.subscribe();
+    let token = CancellationToken::new();
+    let operation = tokio::spawn(async move { wait_until_resumed(&mut receiver, &token).await });
+    sleep(Duration::from_millis(50)).await;
+    assert!(!operation.is_finished());
+
+    // A failed check keeps the sending state
+    monitor.check().await.unwrap_err();
+    assert!(*paused.borrow());
+
+    push_balance(&mock, PAUSE_THRESHOLD - 1);
+    monitor.check().await?;
+    sleep(Duration::from_millis(50)).await;
+    assert!(!operation.is_finished());
+
+    push_balance(&mock, 2 * PAUSE_THRESHOLD);
+    monitor.check().await?;
+    assert!(!*paused.borrow());
+    assert!(timeout(Duration::from_secs(1), operation).await??);
+    Ok(())
+}
+
+#[tokio::test]
+async fn paused_operation_stops_on_cancel() {
+    let (_paused, mut receiver) = watch::channel(true);
+    let token = CancellationToken::new();
+    token.cancel();
+    assert!(!wait_until_resumed(&mut receiver, &token).await);
+}
+
+#[tokio::test]
+async fn unpaused_operation_does_not_wait() {
+    let (_paused, mut receiver) = watch::channel(false);
+    assert!(wait_until_resumed(&mut receiver, &CancellationToken::new()).await);
+}
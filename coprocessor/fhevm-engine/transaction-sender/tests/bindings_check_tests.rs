@@ -0,0 +1,61 @@
+mod common;
+
+use alloy::providers::{ProviderBuilder, WsConnect};
+use common::{CiphertextCommits, InputVerification, MultichainACL, SignerType, TestEnvironment};
+use rstest::*;
+use serial_test::serial;
+use transaction_sender::{FillersWithoutNonceManagement, NonceManagedProvider, TransactionSender};
+
+#[rstest]
+#[case::private_key(SignerType::PrivateKey)]
+#[tokio::test]
+#[serial(db)]
+async fn check_bindings_detects_drift(#[case] signer_type: SignerType) -> anyhow::Result<()> {
+    let env = TestEnvironment::new(signer_type).await?;
+    let provider_deploy = ProviderBuilder::new()
+        .wallet(env.wallet.clone())
+        .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+        .await?;
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(env.wallet.clone())
+            .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+            .await?,
+        Some(env.wallet.default_signer().address()),
+    );
+
+    let input_verification =
+        InputVerification::deploy(&provider_deploy, false, false, false).await?;
+    let ciphertext_commits = CiphertextCommits::deploy(&provider_deploy, false).await?;
+    let multichain_acl = MultichainACL::deploy(&provider_deploy, false).await?;
+
+    let txn_sender = TransactionSender::new(
+        *input_verification.address(),
+        *ciphertext_commits.address(),
+        *multichain_acl.address(),
+        env.signer.clone(),
+        provider.clone(),
+        env.cancel_token.clone(),
+        env.conf.clone(),
+        None,
+    )
+    .await?;
+    txn_sender.check_bindings().await?;
+
+    // The CiphertextCommits contract does not implement the MultichainACL functions.
+    let txn_sender = TransactionSender::new(
+        *input_verification.address(),
+        *ciphertext_commits.address(),
+        *ciphertext_commits.address(),
+        env.signer.clone(),
+        provider.clone(),
+        env.cancel_token.clone(),
+        env.conf.clone(),
+        None,
+    )
+    .await?;
+    let err = txn_sender.check_bindings().await.unwrap_err();
+    assert!(err.to_string().contains("allowAccount"));
+    Ok(())
+}
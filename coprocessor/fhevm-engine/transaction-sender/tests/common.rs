@@ -11,7 +11,9 @@ use alloy::{
     sol,
     transports::http::reqwest::Url,
 };
+use fhevm_engine_common::tls::RpcTls;
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use test_harness::instance::{setup_test_db, DBInstance, ImportMode};
 use test_harness::localstack::{
     create_aws_aws_kms_client, create_localstack_kms_signing_key, start_localstack,
     LocalstackContainer, LOCALSTACK_PORT,
@@ -38,6 +40,12 @@ sol!(
     "artifacts/MultichainACL.sol/MultichainACL.json"
 );
 
+sol!(
+    #[sol(rpc)]
+    Decryption,
+    "artifacts/Decryption.sol/Decryption.json"
+);
+
 pub enum SignerType {
     PrivateKey,
     AwsKms,
@@ -54,6 +62,7 @@ pub struct TestEnvironment {
     pub wallet: EthereumWallet,
     // Just keep the handle to destroy the container when it is dropped.
     _localstack: Option<LocalstackContainer>,
+    _db_instance: Option<DBInstance>,
 }
 
 impl TestEnvironment {
@@ -69,7 +78,7 @@ impl TestEnvironment {
 
     pub async fn new_with_config(
         signer_type: SignerType,
-        conf: ConfigSettings,
+        mut conf: ConfigSettings,
         force_per_test_localstack: bool,
     ) -> anyhow::Result<Self> {
         let _ = tracing_subscriber::fmt()
@@ -79,6 +88,16 @@ impl TestEnvironment {
             .with_test_writer()
             .try_init();
 
+        // Start a migrated Postgres per test unless an existing database is forced
+        let mut db_instance = None;
+        if std::env::var("FORCE_DATABASE_URL").is_err() {
+            let instance = setup_test_db(ImportMode::None)
+                .await
+                .map_err(|e| anyhow::anyhow!("{e}"))?;
+            conf.database_url = instance.db_url().to_owned();
+            db_instance = Some(instance);
+        }
+
         let db_pool = PgPoolOptions::new()
             .max_connections(1)
             .connect(&conf.database_url)
@@ -86,13 +105,24 @@ impl TestEnvironment {
 
         Self::truncate_tables(
             &db_pool,
-            vec!["verify_proofs", "ciphertext_digest", "allowed_handles"],
+            vec![
+                "verify_proofs",
+                "ciphertext_digest",
+                "allowed_handles",
+                "decryption_responses",
+                "public_decryption_shares",
+                "public_decryption_aggregations",
+            ],
         )
         .await?;
 
         let anvil = Self::new_anvil()?;
-        let chain_id =
-            get_chain_id(anvil.ws_endpoint_url(), std::time::Duration::from_secs(1)).await;
+        let chain_id = get_chain_id(
+            anvil.ws_endpoint_url(),
+            std::time::Duration::from_secs(1),
+            &RpcTls::default(),
+        )
+        .await;
         let abstract_signer;
         let localstack;
         match signer_type {
@@ -133,6 +163,7 @@ impl TestEnvironment {
             anvil: Some(anvil),
             wallet,
             _localstack: localstack,
+            _db_instance: db_instance,
         })
     }
 
@@ -0,0 +1,362 @@
+mod common;
+
+use alloy::consensus::Transaction as _;
+use alloy::network::TxSigner;
+use alloy::primitives::{B256, U256};
+use alloy::providers::{Provider, ProviderBuilder, WsConnect};
+use alloy::signers::local::PrivateKeySigner;
+use common::{Decryption, SignerType, TestEnvironment};
+use fhevm_engine_common::column_encryption::ColumnEncryption;
+use fhevm_engine_common::types::DecryptionResponseType;
+use fhevm_engine_common::webhook::{self, WebhookConfig, WebhookExporter};
+use rand::random;
+use rstest::*;
+use serial_test::serial;
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+use tokio::time::sleep;
+use transaction_sender::{FillersWithoutNonceManagement, NonceManagedProvider, TransactionSender};
+
+async fn insert_decryption_response(
+    db_pool: &Pool<Postgres>,
+    decryption_id: U256,
+    response_type: DecryptionResponseType,
+    result: &[u8],
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT INTO decryption_responses (decryption_id, response_type, result, signature)
+         VALUES ($1, $2, $3, $4)",
+        &decryption_id.to_be_bytes::<32>(),
+        response_type as i16,
+        result,
+        &random::<[u8; 65]>(),
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(())
+}
+
+async fn wait_until_sent(
+    db_pool: &Pool<Postgres>,
+    decryption_id: U256,
+    response_type: DecryptionResponseType,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    loop {
+        let row = sqlx::query!(
+            "SELECT txn_is_sent, txn_hash
+             FROM decryption_responses
+             WHERE decryption_id = $1
+             AND response_type = $2",
+            &decryption_id.to_be_bytes::<32>(),
+            response_type as i16,
+        )
+        .fetch_one(db_pool)
+        .await?;
+        if row.txn_is_sent {
+            return Ok(row.txn_hash);
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+}
+
+#[rstest]
+#[case::private_key(SignerType::PrivateKey)]
+#[case::aws_kms(SignerType::AwsKms)]
+#[tokio::test]
+#[serial(db)]
+async fn send_decryption_responses(#[case] signer_type: SignerType) -> anyhow::Result<()> {
+    let mut env = TestEnvironment::new(signer_type).await?;
+    let provider_deploy = ProviderBuilder::new()
+        .wallet(env.wallet.clone())
+        .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+        .await?;
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(env.wallet.clone())
+            .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+            .await?,
+        Some(env.wallet.default_signer().address()),
+    );
+
+    let already_signed_revert = false;
+    let decryption = Decryption::deploy(&provider_deploy, already_signed_revert).await?;
+    env.conf.decryption_address = Some(*decryption.address());
+    let txn_sender = TransactionSender::new(
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        env.signer.clone(),
+        provider.clone(),
+        env.cancel_token.clone(),
+        env.conf.clone(),
+        None,
+    )
+    .await?;
+
+    let run_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    let initial_tx_count = provider
+        .get_transaction_count(TxSigner::address(&env.signer))
+        .await?;
+
+    let public_id = U256::from(random::<u64>());
+    let user_id = U256::from(random::<u64>());
+    insert_decryption_response(
+        &env.db_pool,
+        public_id,
+        DecryptionResponseType::Public,
+        &random::<[u8; 32]>(),
+    )
+    .await?;
+    insert_decryption_response(
+        &env.db_pool,
+        user_id,
+        DecryptionResponseType::User,
+        &random::<[u8; 32]>(),
+    )
+    .await?;
+
+    let public_txn_hash =
+        wait_until_sent(&env.db_pool, public_id, DecryptionResponseType::Public).await?;
+    let user_txn_hash =
+        wait_until_sent(&env.db_pool, user_id, DecryptionResponseType::User).await?;
+    assert!(public_txn_hash.is_some());
+    assert!(user_txn_hash.is_some());
+
+    let tx_count = provider.get_transaction_count(env.signer.address()).await?;
+    assert_eq!(
+        tx_count,
+        initial_tx_count + 2,
+        "Expected two new transactions to be sent"
+    );
+
+    env.cancel_token.cancel();
+    run_handle.await??;
+    Ok(())
+}
+
+#[rstest]
+#[case::private_key(SignerType::PrivateKey)]
+#[case::aws_kms(SignerType::AwsKms)]
+#[tokio::test]
+#[serial(db)]
+async fn decryption_response_already_signed(#[case] signer_type: SignerType) -> anyhow::Result<()> {
+    let mut env = TestEnvironment::new(signer_type).await?;
+    let provider_deploy = ProviderBuilder::new()
+        .wallet(env.wallet.clone())
+        .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+        .await?;
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(env.wallet.clone())
+            .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+            .await?,
+        Some(env.wallet.default_signer().address()),
+    );
+
+    let already_signed_revert = true;
+    let decryption = Decryption::deploy(&provider_deploy, already_signed_revert).await?;
+    env.conf.decryption_address = Some(*decryption.address());
+    let txn_sender = TransactionSender::new(
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        env.signer.clone(),
+        provider.clone(),
+        env.cancel_token.clone(),
+        env.conf.clone(),
+        None,
+    )
+    .await?;
+
+    let run_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    let decryption_id = U256::from(random::<u64>());
+    insert_decryption_response(
+        &env.db_pool,
+        decryption_id,
+        DecryptionResponseType::Public,
+        &random::<[u8; 32]>(),
+    )
+    .await?;
+
+    // The response is considered sent, but no transaction has been mined for it.
+    let txn_hash =
+        wait_until_sent(&env.db_pool, decryption_id, DecryptionResponseType::Public).await?;
+    assert!(txn_hash.is_none());
+
+    env.cancel_token.cancel();
+    run_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn send_encrypted_decryption_response() -> anyhow::Result<()> {
+    let mut env = TestEnvironment::new(SignerType::PrivateKey).await?;
+    let provider_deploy = ProviderBuilder::new()
+        .wallet(env.wallet.clone())
+        .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+        .await?;
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(env.wallet.clone())
+            .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+            .await?,
+        Some(env.wallet.default_signer().address()),
+    );
+
+    let already_signed_revert = false;
+    let decryption = Decryption::deploy(&provider_deploy, already_signed_revert).await?;
+    env.conf.decryption_address = Some(*decryption.address());
+    env.conf.column_encryption = ColumnEncryption::from_key(&random::<[u8; 32]>())?;
+    let txn_sender = TransactionSender::new(
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        env.signer.clone(),
+        provider.clone(),
+        env.cancel_token.clone(),
+        env.conf.clone(),
+        None,
+    )
+    .await?;
+
+    let run_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    // Stored encrypted, sent in clear
+    let result = random::<[u8; 32]>();
+    let encrypted = env.conf.column_encryption.encrypt(&result);
+    assert!(!encrypted
+        .windows(result.len())
+        .any(|window| window == result));
+    let decryption_id = U256::from(random::<u64>());
+    insert_decryption_response(
+        &env.db_pool,
+        decryption_id,
+        DecryptionResponseType::User,
+        &encrypted,
+    )
+    .await?;
+
+    let txn_hash = wait_until_sent(&env.db_pool, decryption_id, DecryptionResponseType::User)
+        .await?
+        .expect("transaction hash");
+    let txn = provider
+        .inner()
+        .get_transaction_by_hash(B256::from_slice(&txn_hash))
+        .await?
+        .expect("sent transaction");
+    assert!(txn
+        .input()
+        .windows(result.len())
+        .any(|window| window == result));
+
+    env.cancel_token.cancel();
+    run_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn export_decryption_response_to_webhook() -> anyhow::Result<()> {
+    let mut env = TestEnvironment::new(SignerType::PrivateKey).await?;
+    let provider_deploy = ProviderBuilder::new()
+        .wallet(env.wallet.clone())
+        .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+        .await?;
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(env.wallet.clone())
+            .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+            .await?,
+        Some(env.wallet.default_signer().address()),
+    );
+
+    // Webhook receiver forwarding the signature headers and the body of the POSTs
+    let (posts_tx, mut posts_rx) = tokio::sync::mpsc::unbounded_channel();
+    let receiver = axum::Router::new().route(
+        "/events",
+        axum::routing::post(move |headers: axum::http::HeaderMap, body: String| {
+            let header = |name| {
+                headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_owned)
+            };
+            let _ = posts_tx.send((
+                header(webhook::TIMESTAMP_HEADER),
+                header(webhook::SIGNATURE_HEADER),
+                body,
+            ));
+            async { axum::http::StatusCode::NO_CONTENT }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/events", listener.local_addr()?).parse()?;
+    tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+    let secret = "webhook secret";
+    env.conf.webhook = Some(WebhookExporter::spawn(WebhookConfig {
+        secret: Some(secret.to_owned().into()),
+        max_latency: Duration::from_millis(10),
+        ..WebhookConfig::new(url)
+    })?);
+
+    let already_signed_revert = false;
+    let decryption = Decryption::deploy(&provider_deploy, already_signed_revert).await?;
+    env.conf.decryption_address = Some(*decryption.address());
+    let txn_sender = TransactionSender::new(
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        env.signer.clone(),
+        provider.clone(),
+        env.cancel_token.clone(),
+        env.conf.clone(),
+        None,
+    )
+    .await?;
+
+    let run_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    let decryption_id = U256::from(random::<u64>());
+    insert_decryption_response(
+        &env.db_pool,
+        decryption_id,
+        DecryptionResponseType::Public,
+        &random::<[u8; 32]>(),
+    )
+    .await?;
+    let txn_hash = wait_until_sent(&env.db_pool, decryption_id, DecryptionResponseType::Public)
+        .await?
+        .expect("transaction hash");
+
+    let (timestamp, signature, body) =
+        tokio::time::timeout(Duration::from_secs(10), posts_rx.recv())
+            .await?
+            .expect("webhook POST");
+    let timestamp = timestamp.expect("timestamp header");
+    assert_eq!(
+        signature.as_deref(),
+        Some(webhook::sign(secret, &timestamp, body.as_bytes()).as_str())
+    );
+    let events: serde_json::Value = serde_json::from_str(&body)?;
+    assert_eq!(
+        events,
+        serde_json::json!([{
+            "type": "decryption_completed",
+            "decryption_id": alloy::hex::encode_prefixed(decryption_id.to_be_bytes::<32>()),
+            "response_type": "public",
+            "transaction_hash": B256::from_slice(&txn_hash).to_string(),
+        }])
+    );
+
+    env.cancel_token.cancel();
+    run_handle.await??;
+    Ok(())
+}
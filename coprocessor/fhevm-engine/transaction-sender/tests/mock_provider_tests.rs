@@ -0,0 +1,224 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use alloy::primitives::{Address, Bytes, FixedBytes, TxHash, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use alloy::sol_types::SolError;
+use alloy::transports::{RpcError, TransportErrorKind};
+use fhevm_engine_common::error::FhevmEngineError;
+use transaction_sender::error_class::{classify, ErrorTable, RetryClass};
+use transaction_sender::signing_policy::StaticSigningPolicy;
+use transaction_sender::test_utils::{
+    already_allowed_account_error, already_allowed_public_decrypt_error, backend_gone_error,
+    local_usage_error, receipt, retryable_http_error, MockProvider, MultichainACL,
+    MultichainACLErrors,
+};
+use transaction_sender::NonceManagedProvider;
+
+#[tokio::test]
+async fn contract_revert_is_decoded() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    let tx_sender = Address::repeat_byte(0x42);
+    mock.push_error(
+        "eth_sendTransaction",
+        already_allowed_account_error(FixedBytes([1u8; 32]), Address::ZERO, tx_sender),
+    );
+
+    let err = mock
+        .provider()
+        .send_transaction(TransactionRequest::default())
+        .await
+        .expect_err("scripted revert");
+    let decoded = err
+        .as_error_resp()
+        .and_then(|payload| payload.as_decoded_interface_error::<MultichainACLErrors>());
+    assert!(matches!(
+        decoded,
+        Some(MultichainACLErrors::CoprocessorAlreadyAllowedAccount(e)) if e.txSender == tx_sender
+    ));
+
+    let err = FhevmEngineError::from_rpc::<MultichainACLErrors>(err);
+    assert!(matches!(
+        err,
+        FhevmEngineError::ContractRevert { data: Some(_), .. }
+    ));
+    assert!(!err.is_transient());
+    Ok(())
+}
+
+#[tokio::test]
+async fn transport_errors_are_transient() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    mock.push_transport_error("eth_blockNumber", backend_gone_error());
+    mock.push_transport_error("eth_blockNumber", retryable_http_error());
+    mock.push_transport_error("eth_blockNumber", local_usage_error());
+    mock.set_default("eth_blockNumber", &"0x10");
+    let provider = mock.provider();
+
+    let gone: FhevmEngineError = provider.get_block_number().await.unwrap_err().into();
+    assert!(gone.is_backend_gone());
+    assert!(gone.is_transient());
+    for _ in 0..2 {
+        let err: FhevmEngineError = provider.get_block_number().await.unwrap_err().into();
+        assert!(err.is_transient());
+        assert!(!err.is_backend_gone());
+    }
+    assert_eq!(provider.get_block_number().await?, 16);
+    assert_eq!(mock.calls().len(), 4);
+    Ok(())
+}
+
+#[tokio::test]
+async fn scripted_receipts() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    let tx_hash = TxHash::repeat_byte(0x11);
+    mock.push_sent_transaction(tx_hash);
+    mock.push_receipt(&receipt(tx_hash, false, 7));
+    let provider = mock.provider();
+
+    let pending = provider
+        .send_transaction(TransactionRequest::default())
+        .await?;
+    assert_eq!(*pending.tx_hash(), tx_hash);
+    let receipt = provider
+        .get_transaction_receipt(tx_hash)
+        .await?
+        .expect("scripted receipt");
+    assert!(!receipt.status());
+    assert_eq!(receipt.block_number, Some(7));
+
+    // Nothing scripted anymore
+    assert!(provider.get_transaction_receipt(tx_hash).await.is_err());
+    assert_eq!(mock.params("eth_getTransactionReceipt").len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn errors_are_classified() -> anyhow::Result<()> {
+    // Only one of the contract errors is listed
+    let table: ErrorTable = &[(
+        MultichainACL::CoprocessorAlreadyAllowedAccount::SELECTOR,
+        RetryClass::Skip,
+    )];
+    let mock = MockProvider::new();
+    let tx_sender = Address::repeat_byte(0x42);
+    mock.push_error(
+        "eth_sendTransaction",
+        already_allowed_account_error(FixedBytes([1u8; 32]), Address::ZERO, tx_sender),
+    );
+    mock.push_error(
+        "eth_sendTransaction",
+        already_allowed_public_decrypt_error(FixedBytes([1u8; 32]), tx_sender),
+    );
+    mock.push_error(
+        "eth_sendTransaction",
+        already_allowed_public_decrypt_error(FixedBytes([1u8; 32]), tx_sender),
+    );
+    mock.push_transport_error("eth_sendTransaction", backend_gone_error());
+    mock.push_transport_error("eth_sendTransaction", retryable_http_error());
+    mock.push_transport_error("eth_sendTransaction", local_usage_error());
+    let provider = mock.provider();
+    let send = async || -> RpcError<TransportErrorKind> {
+        provider
+            .send_transaction(TransactionRequest::default())
+            .await
+            .expect_err("scripted error")
+    };
+
+    let (class, contract_error) = classify::<MultichainACLErrors>(&send().await, table);
+    assert_eq!(class, RetryClass::Skip);
+    assert!(matches!(
+        contract_error,
+        Some(MultichainACLErrors::CoprocessorAlreadyAllowedAccount(e)) if e.txSender == tx_sender
+    ));
+
+    // Decoded but not listed
+    let (class, contract_error) = classify::<MultichainACLErrors>(&send().await, table);
+    assert_eq!(class, RetryClass::RetryBounded);
+    assert!(contract_error.is_some());
+
+    let dead_letter: ErrorTable = &[(
+        MultichainACL::CoprocessorAlreadyAllowedPublicDecrypt::SELECTOR,
+        RetryClass::DeadLetter,
+    )];
+    let (class, _) = classify::<MultichainACLErrors>(&send().await, dead_letter);
+    assert_eq!(class, RetryClass::DeadLetter);
+
+    for _ in 0..3 {
+        let (class, _) = classify::<MultichainACLErrors>(&send().await, table);
+        assert_eq!(class, RetryClass::RetryForever);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn wrong_chain_is_refused() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    mock.set_default("eth_chainId", &"0x1");
+    let provider = NonceManagedProvider::new(mock.provider(), None)
+        .with_chain_id_guard(12345, Duration::from_secs(3600));
+
+    let err = provider
+        .send_transaction(TransactionRequest::default())
+        .await
+        .expect_err("wrong chain");
+    let (class, _) = classify::<MultichainACLErrors>(&err, &[]);
+    assert_eq!(class, RetryClass::RetryForever);
+    assert!(mock.params("eth_sendTransaction").is_empty());
+
+    // Checked once per interval on the right chain
+    mock.set_default("eth_chainId", &"0x3039");
+    mock.push_sent_transaction(TxHash::repeat_byte(0x11));
+    mock.push_sent_transaction(TxHash::repeat_byte(0x22));
+    for _ in 0..2 {
+        provider
+            .send_transaction(TransactionRequest::default())
+            .await?;
+    }
+    assert_eq!(mock.params("eth_sendTransaction").len(), 2);
+    assert_eq!(mock.params("eth_chainId").len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn signing_policy_denials() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    let target = Address::repeat_byte(0x01);
+    let selector = FixedBytes([0x12, 0x34, 0x56, 0x78]);
+    let policy = StaticSigningPolicy {
+        allowed_targets: [target].into(),
+        allowed_selectors: [selector].into(),
+        max_gas_price: Some(1_000),
+        max_value: Some(U256::ZERO),
+    };
+    let provider =
+        NonceManagedProvider::new(mock.provider(), None).with_signing_policy(Arc::new(policy));
+    // Fees set so that they are not estimated
+    let allowed = TransactionRequest::default()
+        .to(target)
+        .input(Bytes::from([0x12, 0x34, 0x56, 0x78, 0x00]).into())
+        .max_fee_per_gas(1_000)
+        .max_priority_fee_per_gas(1);
+
+    let denied = [
+        allowed.clone().to(Address::repeat_byte(0x02)),
+        allowed
+            .clone()
+            .input(Bytes::from([0x87, 0x65, 0x43, 0x21]).into()),
+        allowed.clone().input(Bytes::new().into()),
+        allowed.clone().value(U256::from(1)),
+        allowed.clone().max_fee_per_gas(1_001),
+    ];
+    for tx in denied {
+        let err = provider.send_transaction(tx).await.expect_err("denied");
+        let (class, _) = classify::<MultichainACLErrors>(&err, &[]);
+        assert_eq!(class, RetryClass::DeadLetter);
+    }
+    assert!(mock.params("eth_sendTransaction").is_empty());
+
+    mock.push_sent_transaction(TxHash::repeat_byte(0x11));
+    provider.send_transaction(allowed).await?;
+    assert_eq!(mock.params("eth_sendTransaction").len(), 1);
+    Ok(())
+}
@@ -1,13 +1,33 @@
 mod common;
 
-use alloy::primitives::{FixedBytes, U256};
+use alloy::network::TransactionBuilder;
+use alloy::primitives::{Address, Bytes, FixedBytes, U256};
 use alloy::providers::{Provider, ProviderBuilder, WsConnect};
+use alloy::rpc::types::TransactionRequest;
 use common::SignerType;
 use common::{CiphertextCommits, TestEnvironment};
 use rstest::*;
 use serial_test::serial;
 use std::time::Duration;
-use transaction_sender::overprovision_gas_limit::try_overprovision_gas_limit;
+use transaction_sender::overprovision_gas_limit::{try_overprovision_gas_limit, GasEstimates};
+use transaction_sender::test_utils::{
+    already_allowed_account_error, retryable_http_error, revert_error, MockProvider,
+};
+use transaction_sender::ConfigSettings;
+
+fn conf() -> ConfigSettings {
+    ConfigSettings {
+        gas_limit_overprovision_percent: 120,
+        gas_estimate_retry_backoff: Duration::from_millis(1),
+        ..Default::default()
+    }
+}
+
+fn method_call(selector: u8) -> TransactionRequest {
+    TransactionRequest::default()
+        .with_to(Address::repeat_byte(0x42))
+        .with_input(Bytes::from(vec![selector; 36]))
+}
 
 #[rstest]
 #[case::private_key(SignerType::PrivateKey)]
@@ -39,10 +59,11 @@ async fn overprovision_gas_limit(#[case] signer_type: SignerType) -> anyhow::Res
     );
 
     let without_overprovision = provider.estimate_gas(txn_req.clone()).await?;
-    let with_overprovision = try_overprovision_gas_limit(txn_req, &provider, 120)
-        .await
-        .gas
-        .expect("Gas limit is set after overprovisioning");
+    let with_overprovision =
+        try_overprovision_gas_limit(txn_req, &provider, &conf(), &GasEstimates::default())
+            .await
+            .gas
+            .expect("Gas limit is set after overprovisioning");
 
     assert_eq!(
         with_overprovision,
@@ -89,11 +110,117 @@ async fn overprovision_estimate_failure(#[case] signer_type: SignerType) -> anyh
 
     env.drop_anvil();
 
-    let with_overprovision = try_overprovision_gas_limit(txn_req, &provider, 120)
+    let with_overprovision =
+        try_overprovision_gas_limit(txn_req, &provider, &conf(), &GasEstimates::default())
+            .await
+            .gas;
+
+    assert!(with_overprovision.is_none(), "Gas limit should not be set");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn overprovision_retries_transient_estimate_failure() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    mock.push_transport_error("eth_estimateGas", retryable_http_error());
+    mock.push_transport_error("eth_estimateGas", retryable_http_error());
+    mock.push_success("eth_estimateGas", &"0x5208");
+
+    let gas = try_overprovision_gas_limit(
+        method_call(1),
+        &mock.provider(),
+        &conf(),
+        &GasEstimates::default(),
+    )
+    .await
+    .gas;
+
+    assert_eq!(gas, Some(21000 * 120 / 100));
+    assert_eq!(mock.calls().len(), 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn overprovision_estimate_fallbacks() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    let provider = mock.provider();
+    let estimates = GasEstimates::default();
+    let conf = ConfigSettings {
+        gas_limit_ceiling: Some(1_000_000),
+        gas_estimate_max_attempts: 1,
+        ..conf()
+    };
+
+    mock.push_success("eth_estimateGas", &"0x5208");
+    let gas = try_overprovision_gas_limit(method_call(1), &provider, &conf, &estimates)
+        .await
+        .gas;
+    assert_eq!(gas, Some(21000 * 120 / 100));
+
+    // The node is unreachable, the last estimate of the method is used
+    mock.push_transport_error("eth_estimateGas", retryable_http_error());
+    let gas = try_overprovision_gas_limit(method_call(1), &provider, &conf, &estimates)
         .await
         .gas;
+    assert_eq!(gas, Some(21000 * 120 / 100));
+    assert_eq!(mock.calls().len(), 2);
 
-    assert!(with_overprovision.is_none(), "Gas limit should not be set");
+    // Never estimated method, the ceiling is used
+    mock.push_transport_error("eth_estimateGas", retryable_http_error());
+    let gas = try_overprovision_gas_limit(method_call(2), &provider, &conf, &estimates)
+        .await
+        .gas;
+    assert_eq!(gas, Some(1_000_000));
+
+    // Without ceiling, the gas limit is not set
+    mock.push_transport_error("eth_estimateGas", retryable_http_error());
+    let gas = try_overprovision_gas_limit(
+        method_call(2),
+        &provider,
+        &ConfigSettings {
+            gas_estimate_max_attempts: 1,
+            ..conf()
+        },
+        &estimates,
+    )
+    .await
+    .gas;
+    assert!(gas.is_none());
+    Ok(())
+}
 
+#[tokio::test]
+async fn overprovision_estimate_revert_has_no_fallback() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    let provider = mock.provider();
+    let estimates = GasEstimates::default();
+    let conf = ConfigSettings {
+        gas_limit_ceiling: Some(1_000_000),
+        ..conf()
+    };
+
+    mock.push_success("eth_estimateGas", &"0x5208");
+    try_overprovision_gas_limit(method_call(1), &provider, &conf, &estimates).await;
+
+    // Already allowed, a Skip error: the gas limit is left unset for the send to fail the same
+    // way and be skipped, instead of being mined and reverting on-chain
+    mock.push_error(
+        "eth_estimateGas",
+        already_allowed_account_error(FixedBytes([1u8; 32]), Address::ZERO, Address::ZERO),
+    );
+    let gas = try_overprovision_gas_limit(method_call(1), &provider, &conf, &estimates)
+        .await
+        .gas;
+    assert!(gas.is_none());
+    // Not retried
+    assert_eq!(mock.calls().len(), 2);
+
+    // Neither is the ceiling used
+    mock.push_error("eth_estimateGas", revert_error(vec![1, 2, 3, 4]));
+    let gas = try_overprovision_gas_limit(method_call(2), &provider, &conf, &estimates)
+        .await
+        .gas;
+    assert!(gas.is_none());
     Ok(())
 }
@@ -0,0 +1,385 @@
+mod common;
+
+use alloy::primitives::{Address, FixedBytes, U256};
+use alloy::providers::{Provider, ProviderBuilder, WsConnect};
+use alloy::signers::{local::PrivateKeySigner, SignerSync};
+use alloy::sol;
+use alloy::sol_types::{eip712_domain, SolStruct};
+use common::{Decryption, SignerType, TestEnvironment};
+use fhevm_engine_common::types::DecryptionResponseType;
+use rand::random;
+use serial_test::serial;
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+use tokio::time::sleep;
+use transaction_sender::{
+    quorum_policy::SignerSet, FillersWithoutNonceManagement, NonceManagedProvider, QuorumPolicy,
+    TransactionSender,
+};
+
+sol! {
+    struct PublicDecryptVerification {
+        bytes32[] ctHandles;
+        bytes decryptedResult;
+        bytes extraData;
+    }
+}
+
+const STATUS_AGGREGATED: i16 = 0;
+const STATUS_TIMED_OUT: i16 = 1;
+
+async fn insert_share(
+    db_pool: &Pool<Postgres>,
+    decryption_id: U256,
+    signer: &PrivateKeySigner,
+    signature_signer: &PrivateKeySigner,
+    verification: &PublicDecryptVerification,
+    key_id: Option<U256>,
+    decryption_address: Address,
+    chain_id: u64,
+) -> anyhow::Result<()> {
+    let domain = eip712_domain! {
+        name: "Decryption",
+        version: "1",
+        chain_id: chain_id,
+        verifying_contract: decryption_address,
+    };
+    let signature = signature_signer.sign_hash_sync(&verification.eip712_signing_hash(&domain))?;
+    sqlx::query!(
+        "INSERT INTO public_decryption_shares
+            (decryption_id, signer, ct_handles, result, signature, extra_data, key_id)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        &decryption_id.to_be_bytes::<32>(),
+        signer.address().as_slice(),
+        verification
+            .ctHandles
+            .iter()
+            .flat_map(|handle| handle.0)
+            .collect::<Vec<u8>>(),
+        verification.decryptedResult.as_ref(),
+        &signature.as_bytes(),
+        verification.extraData.as_ref(),
+        key_id.map(|key_id| key_id.to_be_bytes::<32>().to_vec()),
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(())
+}
+
+async fn wait_until_completed(
+    db_pool: &Pool<Postgres>,
+    decryption_id: U256,
+) -> anyhow::Result<(i16, i32, Option<String>)> {
+    loop {
+        let row = sqlx::query!(
+            "SELECT status, signers_count, error
+             FROM public_decryption_aggregations
+             WHERE decryption_id = $1",
+            &decryption_id.to_be_bytes::<32>(),
+        )
+        .fetch_optional(db_pool)
+        .await?;
+        if let Some(row) = row {
+            return Ok((row.status, row.signers_count, row.error));
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+}
+
+async fn setup(
+    env: &mut TestEnvironment,
+    quorum: QuorumPolicy,
+) -> anyhow::Result<(TransactionSender<impl Provider + Clone>, Address, u64)> {
+    let provider_deploy = ProviderBuilder::new()
+        .wallet(env.wallet.clone())
+        .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+        .await?;
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(env.wallet.clone())
+            .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+            .await?,
+        Some(env.wallet.default_signer().address()),
+    );
+    let chain_id = provider_deploy.get_chain_id().await?;
+
+    let already_signed_revert = false;
+    let decryption = Decryption::deploy(&provider_deploy, already_signed_revert).await?;
+    env.conf.decryption_address = Some(*decryption.address());
+    env.conf.public_decryption_quorum = quorum;
+    let txn_sender = TransactionSender::new(
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        PrivateKeySigner::random().address(),
+        env.signer.clone(),
+        provider,
+        env.cancel_token.clone(),
+        env.conf.clone(),
+        None,
+    )
+    .await?;
+    Ok((txn_sender, *decryption.address(), chain_id))
+}
+
+fn single_quorum(kms_signers: &[PrivateKeySigner], threshold: usize) -> QuorumPolicy {
+    QuorumPolicy::single(kms_signers.iter().map(|s| s.address()).collect(), threshold)
+}
+
+fn random_verification() -> PublicDecryptVerification {
+    PublicDecryptVerification {
+        ctHandles: vec![FixedBytes::from(random::<[u8; 32]>())],
+        decryptedResult: random::<[u8; 32]>().to_vec().into(),
+        extraData: vec![0u8].into(),
+    }
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn aggregate_public_decryption_shares() -> anyhow::Result<()> {
+    let mut env = TestEnvironment::new(SignerType::PrivateKey).await?;
+    let kms_signers: Vec<_> = (0..3).map(|_| PrivateKeySigner::random()).collect();
+    let (txn_sender, decryption_address, chain_id) =
+        setup(&mut env, single_quorum(&kms_signers, 2)).await?;
+    let run_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    let decryption_id = U256::from(random::<u64>());
+    let verification = random_verification();
+    // Signed by a key that is not the claimed signer, rejected
+    let impostor = PrivateKeySigner::random();
+    insert_share(
+        &env.db_pool,
+        decryption_id,
+        &kms_signers[0],
+        &impostor,
+        &verification,
+        None,
+        decryption_address,
+        chain_id,
+    )
+    .await?;
+    for signer in &kms_signers[1..] {
+        insert_share(
+            &env.db_pool,
+            decryption_id,
+            signer,
+            signer,
+            &verification,
+            None,
+            decryption_address,
+            chain_id,
+        )
+        .await?;
+    }
+
+    let (status, signers_count, error) = wait_until_completed(&env.db_pool, decryption_id).await?;
+    assert_eq!(status, STATUS_AGGREGATED);
+    assert_eq!(signers_count, 2);
+    assert!(error.is_none());
+
+    loop {
+        let row = sqlx::query!(
+            "SELECT txn_is_sent, txn_hash
+             FROM decryption_responses
+             WHERE decryption_id = $1
+             AND response_type = $2",
+            &decryption_id.to_be_bytes::<32>(),
+            DecryptionResponseType::PublicAggregated as i16,
+        )
+        .fetch_one(&env.db_pool)
+        .await?;
+        if row.txn_is_sent {
+            assert!(row.txn_hash.is_some());
+            break;
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+
+    // One response per signature of the quorum, as the Gateway expects
+    let provider = ProviderBuilder::new()
+        .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+        .await?;
+    let responses = Decryption::new(decryption_address, &provider)
+        .PublicDecryptionResponse_filter()
+        .from_block(0)
+        .query()
+        .await?;
+    let signatures: Vec<_> = responses
+        .iter()
+        .filter(|(response, _)| response.decryptionId == decryption_id)
+        .map(|(response, _)| {
+            assert_eq!(response.decryptedResult, verification.decryptedResult);
+            assert_eq!(response.signatures.len(), 1);
+            response.signatures[0].clone()
+        })
+        .collect();
+    assert_eq!(signatures.len(), 2);
+
+    let is_valid: Vec<bool> = sqlx::query_scalar!(
+        "SELECT is_valid AS \"is_valid!\"
+         FROM public_decryption_shares
+         WHERE decryption_id = $1
+         ORDER BY is_valid",
+        &decryption_id.to_be_bytes::<32>(),
+    )
+    .fetch_all(&env.db_pool)
+    .await?;
+    assert_eq!(is_valid, vec![false, true, true]);
+
+    env.cancel_token.cancel();
+    run_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn public_decryption_quorum_timeout() -> anyhow::Result<()> {
+    let mut env = TestEnvironment::new(SignerType::PrivateKey).await?;
+    env.conf.public_decryption_share_timeout = Duration::from_secs(2);
+    let kms_signers: Vec<_> = (0..3).map(|_| PrivateKeySigner::random()).collect();
+    let (txn_sender, decryption_address, chain_id) =
+        setup(&mut env, single_quorum(&kms_signers, 2)).await?;
+    let run_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    let decryption_id = U256::from(random::<u64>());
+    // Two valid shares that disagree on the result
+    for signer in &kms_signers[..2] {
+        insert_share(
+            &env.db_pool,
+            decryption_id,
+            signer,
+            signer,
+            &random_verification(),
+            None,
+            decryption_address,
+            chain_id,
+        )
+        .await?;
+    }
+
+    let (status, signers_count, error) = wait_until_completed(&env.db_pool, decryption_id).await?;
+    assert_eq!(status, STATUS_TIMED_OUT);
+    assert_eq!(signers_count, 1);
+    assert!(error
+        .unwrap()
+        .contains(&kms_signers[2].address().to_string()));
+
+    let responses_count = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM decryption_responses WHERE decryption_id = $1",
+        &decryption_id.to_be_bytes::<32>(),
+    )
+    .fetch_one(&env.db_pool)
+    .await?;
+    assert_eq!(responses_count, Some(0));
+
+    env.cancel_token.cancel();
+    run_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+#[serial(db)]
+async fn public_decryption_quorum_policy() -> anyhow::Result<()> {
+    let mut env = TestEnvironment::new(SignerType::PrivateKey).await?;
+    env.conf.public_decryption_share_timeout = Duration::from_secs(2);
+    let old_signers: Vec<_> = (0..2).map(|_| PrivateKeySigner::random()).collect();
+    let new_signers: Vec<_> = (0..3).map(|_| PrivateKeySigner::random()).collect();
+    let addresses = |signers: &[PrivateKeySigner]| signers.iter().map(|s| s.address()).collect();
+    let overridden_key_id = U256::from(7);
+    let quorum = QuorumPolicy {
+        signer_sets: vec![
+            SignerSet {
+                version: 1,
+                signers: addresses(&old_signers),
+                threshold: 2,
+            },
+            SignerSet {
+                version: 2,
+                signers: addresses(&new_signers),
+                threshold: 2,
+            },
+        ],
+        key_overrides: [(overridden_key_id, 3)].into(),
+    };
+    quorum.validate()?;
+    let (txn_sender, decryption_address, chain_id) = setup(&mut env, quorum).await?;
+    let run_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    // The previous signer set does not count anymore
+    let rotated_id = U256::from(random::<u64>());
+    let verification = random_verification();
+    for signer in &old_signers {
+        insert_share(
+            &env.db_pool,
+            rotated_id,
+            signer,
+            signer,
+            &verification,
+            None,
+            decryption_address,
+            chain_id,
+        )
+        .await?;
+    }
+    // Reached by the current signer set
+    let current_id = U256::from(random::<u64>());
+    let verification = random_verification();
+    for signer in &new_signers[..2] {
+        insert_share(
+            &env.db_pool,
+            current_id,
+            signer,
+            signer,
+            &verification,
+            None,
+            decryption_address,
+            chain_id,
+        )
+        .await?;
+    }
+    // Two signers are not enough for the overridden key
+    let overridden_id = U256::from(random::<u64>());
+    let verification = random_verification();
+    for signer in &new_signers[..2] {
+        insert_share(
+            &env.db_pool,
+            overridden_id,
+            signer,
+            signer,
+            &verification,
+            Some(overridden_key_id),
+            decryption_address,
+            chain_id,
+        )
+        .await?;
+    }
+
+    let (status, signers_count, _) = wait_until_completed(&env.db_pool, current_id).await?;
+    assert_eq!(status, STATUS_AGGREGATED);
+    assert_eq!(signers_count, 2);
+    let version = sqlx::query_scalar!(
+        "SELECT signer_set_version FROM public_decryption_aggregations WHERE decryption_id = $1",
+        &current_id.to_be_bytes::<32>(),
+    )
+    .fetch_one(&env.db_pool)
+    .await?;
+    assert_eq!(version, Some(2));
+
+    let (status, signers_count, error) = wait_until_completed(&env.db_pool, rotated_id).await?;
+    assert_eq!(status, STATUS_TIMED_OUT);
+    assert_eq!(signers_count, 0);
+    assert!(error
+        .unwrap()
+        .starts_with("0 of 2 required signers of signer set 2"));
+
+    let (status, signers_count, error) = wait_until_completed(&env.db_pool, overridden_id).await?;
+    assert_eq!(status, STATUS_TIMED_OUT);
+    assert_eq!(signers_count, 2);
+    assert!(error
+        .unwrap()
+        .starts_with("2 of 3 required signers of signer set 2"));
+
+    env.cancel_token.cancel();
+    run_handle.await??;
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use alloy::primitives::{Address, U256};
+use transaction_sender::quorum_policy::SignerSet;
+use transaction_sender::QuorumPolicy;
+
+fn write_policy(name: &str, content: &str) -> String {
+    let path = std::env::temp_dir().join(format!("{name}-{}.json", std::process::id()));
+    std::fs::write(&path, content).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn quorum_policy_from_file() -> anyhow::Result<()> {
+    let path = write_policy(
+        "quorum-policy",
+        r#"{
+            "signer_sets": [
+                {"version": 1, "signers": ["0x0000000000000000000000000000000000000001",
+                                           "0x0000000000000000000000000000000000000002"], "threshold": 2},
+                {"version": 2, "signers": ["0x0000000000000000000000000000000000000003",
+                                           "0x0000000000000000000000000000000000000004",
+                                           "0x0000000000000000000000000000000000000005"], "threshold": 2}
+            ],
+            "key_overrides": {"0x07": 3}
+        }"#,
+    );
+    let policy = QuorumPolicy::from_file(&path)?;
+    std::fs::remove_file(path)?;
+
+    let current = policy.current().unwrap();
+    assert_eq!(current.version, 2);
+    assert_eq!(policy.threshold(current, None), 2);
+    assert_eq!(policy.threshold(current, Some(U256::from(7))), 3);
+    assert_eq!(policy.threshold(current, Some(U256::from(8))), 2);
+    assert!(policy.is_signer(&Address::with_last_byte(1)));
+    assert!(!policy.is_signer(&Address::with_last_byte(6)));
+
+    // Only the current signer set counts
+    let old = [Address::with_last_byte(1), Address::with_last_byte(2)];
+    assert!(policy.reached(None, &old).is_none());
+    let new = [Address::with_last_byte(3), Address::with_last_byte(4)];
+    assert_eq!(policy.reached(None, &new).map(|set| set.version), Some(2));
+    assert!(policy.reached(Some(U256::from(7)), &new).is_none());
+    let mixed = [Address::with_last_byte(1), Address::with_last_byte(3)];
+    assert!(policy.reached(None, &mixed).is_none());
+    let repeated = [Address::with_last_byte(3), Address::with_last_byte(3)];
+    assert!(policy.reached(None, &repeated).is_none());
+    Ok(())
+}
+
+#[test]
+fn key_override_below_the_threshold_is_ignored() {
+    let signers = (1..=3).map(Address::with_last_byte).collect();
+    let mut policy = QuorumPolicy::single(signers, 2);
+    policy.key_overrides.insert(U256::from(1), 1);
+    policy.key_overrides.insert(U256::from(2), 3);
+    let current = policy.current().unwrap().clone();
+    assert_eq!(policy.threshold(&current, Some(U256::from(1))), 2);
+    assert_eq!(policy.threshold(&current, Some(U256::from(2))), 3);
+
+    let one = [Address::with_last_byte(1)];
+    assert!(policy.reached(Some(U256::from(1)), &one).is_none());
+}
+
+#[test]
+fn invalid_quorum_policy() {
+    let signers = vec![Address::with_last_byte(1), Address::with_last_byte(2)];
+    assert!(QuorumPolicy::single(signers.clone(), 2).validate().is_ok());
+    assert!(QuorumPolicy::single(signers.clone(), 0).validate().is_err());
+    assert!(QuorumPolicy::single(signers.clone(), 3).validate().is_err());
+    assert!(QuorumPolicy::single(vec![signers[0], signers[0]], 1)
+        .validate()
+        .is_err());
+
+    let mut policy = QuorumPolicy::single(signers.clone(), 1);
+    policy.signer_sets.push(SignerSet {
+        version: 0,
+        signers: signers.clone(),
+        threshold: 1,
+    });
+    assert!(policy.validate().is_err(), "duplicate version");
+
+    let mut policy = QuorumPolicy::single(signers, 1);
+    policy.key_overrides.insert(U256::from(1), 3);
+    assert!(
+        policy.validate().is_err(),
+        "override above the signers count"
+    );
+
+    let path = write_policy(
+        "invalid-quorum-policy",
+        r#"{"signer_sets": [{"version": 1}]}"#,
+    );
+    assert!(QuorumPolicy::from_file(&path).is_err());
+    std::fs::remove_file(path).unwrap();
+}
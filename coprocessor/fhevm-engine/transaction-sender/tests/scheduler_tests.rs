@@ -0,0 +1,119 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use tokio_util::sync::CancellationToken;
+use transaction_sender::scheduler::{CronSchedule, Schedule, Scheduler};
+
+fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+        .unwrap()
+}
+
+fn next(expression: &str, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    expression
+        .parse::<CronSchedule>()
+        .unwrap()
+        .next_after(after)
+}
+
+#[test]
+fn cron_next_match() {
+    let after = at(2025, 10, 28, 10, 17);
+    assert_eq!(next("* * * * *", after), Some(at(2025, 10, 28, 10, 18)));
+    assert_eq!(next("*/15 * * * *", after), Some(at(2025, 10, 28, 10, 30)));
+    assert_eq!(next("30 3 * * *", after), Some(at(2025, 10, 29, 3, 30)));
+    assert_eq!(next("0 0 1 * *", after), Some(at(2025, 11, 1, 0, 0)));
+    assert_eq!(next("0 12 * 1-3 *", after), Some(at(2026, 1, 1, 12, 0)));
+    // 2025-10-28 is a Tuesday, 7 is Sunday like 0
+    assert_eq!(next("0 0 * * 7", after), Some(at(2025, 11, 2, 0, 0)));
+    assert_eq!(next("0 9 * * 1-5", after), Some(at(2025, 10, 29, 9, 0)));
+    // Either day field matches when both are restricted
+    assert_eq!(next("0 0 15 * 3", after), Some(at(2025, 10, 29, 0, 0)));
+    assert_eq!(next("0 0 29 2 *", after), Some(at(2028, 2, 29, 0, 0)));
+    assert_eq!(
+        next("5,10-12 8/6 * * *", after),
+        Some(at(2025, 10, 28, 14, 5))
+    );
+    // Strictly after, even on a match
+    assert_eq!(
+        next("17 10 * * *", at(2025, 10, 28, 10, 17)),
+        Some(at(2025, 10, 29, 10, 17))
+    );
+    assert_eq!(next("0 0 31 2 *", after), None);
+}
+
+#[test]
+fn schedule_parsing() {
+    assert_eq!(
+        "@every 10m".parse::<Schedule>().unwrap(),
+        Schedule::Every(Duration::from_secs(600))
+    );
+    assert_eq!(
+        "@daily".parse::<Schedule>().unwrap(),
+        "0 0 * * *".parse::<Schedule>().unwrap()
+    );
+    for invalid in [
+        "",
+        "* * * *",
+        "* * * * * *",
+        "60 * * * *",
+        "* 24 * * *",
+        "* * 0 * *",
+        "* * * 13 *",
+        "* * * * 8",
+        "*/0 * * * *",
+        "5-1 * * * *",
+        "a * * * *",
+        "@every 0s",
+        "@yearly",
+    ] {
+        assert!(invalid.parse::<Schedule>().is_err(), "{invalid:?}");
+    }
+}
+
+#[tokio::test]
+async fn overlapping_runs_are_skipped() -> anyhow::Result<()> {
+    let cancel_token = CancellationToken::new();
+    let runs = Arc::new(AtomicUsize::new(0));
+    let concurrent = Arc::new(AtomicUsize::new(0));
+    let max_concurrent = Arc::new(AtomicUsize::new(0));
+
+    let mut scheduler = Scheduler::new(cancel_token.clone());
+    {
+        let (runs, concurrent, max_concurrent) =
+            (runs.clone(), concurrent.clone(), max_concurrent.clone());
+        scheduler.register(
+            "slow",
+            Schedule::Every(Duration::from_millis(20)),
+            Duration::ZERO,
+            move || {
+                let (runs, concurrent, max_concurrent) =
+                    (runs.clone(), concurrent.clone(), max_concurrent.clone());
+                async move {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    let current = concurrent.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_concurrent.fetch_max(current, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    concurrent.fetch_sub(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            },
+        );
+    }
+    let scheduler = tokio::spawn(scheduler.run());
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    cancel_token.cancel();
+    scheduler.await??;
+
+    let runs = runs.load(Ordering::SeqCst);
+    assert!((2..=6).contains(&runs), "{runs} runs");
+    assert_eq!(max_concurrent.load(Ordering::SeqCst), 1);
+    // The run in progress on cancellation is waited for
+    assert_eq!(concurrent.load(Ordering::SeqCst), 0);
+    Ok(())
+}
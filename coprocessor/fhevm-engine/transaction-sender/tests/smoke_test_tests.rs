@@ -0,0 +1,53 @@
+mod common;
+
+use std::time::Duration;
+
+use alloy::providers::{Provider, ProviderBuilder, WsConnect};
+use common::{SignerType, TestEnvironment};
+use rstest::*;
+use serial_test::serial;
+use transaction_sender::smoke_test::run_smoke_test;
+use transaction_sender::{FillersWithoutNonceManagement, NonceManagedProvider};
+
+#[rstest]
+#[case::private_key(SignerType::PrivateKey)]
+#[case::aws_kms(SignerType::AwsKms)]
+#[tokio::test]
+#[serial(db)]
+async fn smoke_test_sends_self_transfer(#[case] signer_type: SignerType) -> anyhow::Result<()> {
+    let env = TestEnvironment::new(signer_type).await?;
+    let signer_address = env.wallet.default_signer().address();
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(env.wallet.clone())
+            .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+            .await?,
+        Some(signer_address),
+    );
+
+    let report = run_smoke_test(&provider, Duration::from_secs(10), 0).await?;
+    assert_eq!(report.signer_address, signer_address);
+    assert_eq!(report.sender_address, signer_address);
+    assert_eq!(report.confirmed_nonce, report.pending_nonce);
+    assert_eq!(report.gas_used, 21_000);
+    assert!(report.block_number.is_some());
+    assert_eq!(
+        provider
+            .inner()
+            .get_transaction_count(signer_address)
+            .await?,
+        report.confirmed_nonce + 1
+    );
+
+    // The nonce manager keeps going from the smoke test transaction
+    let report = run_smoke_test(&provider, Duration::from_secs(10), 0).await?;
+    assert_eq!(
+        provider
+            .inner()
+            .get_transaction_count(signer_address)
+            .await?,
+        report.confirmed_nonce + 1
+    );
+    Ok(())
+}
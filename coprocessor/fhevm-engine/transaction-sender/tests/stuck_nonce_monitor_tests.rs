@@ -0,0 +1,190 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use alloy::network::Ethereum;
+use alloy::primitives::{Address, TxHash};
+use alloy::providers::RootProvider;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use transaction_sender::signing_policy::StaticSigningPolicy;
+use transaction_sender::test_utils::{MockProvider, StuckNonceMonitor};
+use transaction_sender::{ConfigSettings, NonceManagedProvider};
+
+const SIGNER: Address = Address::repeat_byte(0x42);
+const TIMEOUT: Duration = Duration::from_millis(20);
+
+fn monitor(mock: &MockProvider, auto_cancel: bool) -> StuckNonceMonitor<RootProvider<Ethereum>> {
+    monitor_with(
+        NonceManagedProvider::new(mock.provider(), Some(SIGNER)),
+        auto_cancel,
+    )
+}
+
+fn monitor_with(
+    provider: NonceManagedProvider<RootProvider<Ethereum>>,
+    auto_cancel: bool,
+) -> StuckNonceMonitor<RootProvider<Ethereum>> {
+    let conf = ConfigSettings {
+        stuck_nonce_timeout: TIMEOUT,
+        stuck_nonce_auto_cancel: auto_cancel,
+        stuck_nonce_fee_bump_percent: 150,
+        ..Default::default()
+    };
+    StuckNonceMonitor::new(provider, SIGNER, conf, CancellationToken::new())
+}
+
+/// Scripts the confirmed then the pending nonce returned to the next check.
+fn push_nonces(mock: &MockProvider, confirmed: u64, pending: u64) {
+    mock.push_success("eth_getTransactionCount", &format!("{confirmed:#x}"));
+    mock.push_success("eth_getTransactionCount", &format!("{pending:#x}"));
+}
+
+fn fee(params: &serde_json::Value, field: &str) -> u128 {
+    let hex = params[0][field].as_str().expect("fee is set");
+    u128::from_str_radix(hex.trim_start_matches("0x"), 16).expect("hex fee")
+}
+
+#[tokio::test]
+async fn nonce_without_queued_transactions_is_not_stuck() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    let monitor = monitor(&mock, true);
+    let mut state = None;
+
+    for _ in 0..3 {
+        push_nonces(&mock, 5, 5);
+        assert!(!monitor.check(&mut state).await?);
+        sleep(TIMEOUT * 2).await;
+    }
+    assert!(mock.params("eth_sendTransaction").is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn moving_nonce_is_not_stuck() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    let monitor = monitor(&mock, true);
+    let mut state = None;
+
+    for confirmed in 5..8 {
+        push_nonces(&mock, confirmed, confirmed + 2);
+        assert!(!monitor.check(&mut state).await?);
+        sleep(TIMEOUT * 2).await;
+    }
+    assert!(mock.params("eth_sendTransaction").is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn stuck_nonce_is_only_reported_without_auto_cancel() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    let monitor = monitor(&mock, false);
+    let mut state = None;
+
+    push_nonces(&mock, 5, 7);
+    assert!(!monitor.check(&mut state).await?);
+    // Not stuck for long enough yet
+    push_nonces(&mock, 5, 7);
+    assert!(!monitor.check(&mut state).await?);
+    sleep(TIMEOUT * 2).await;
+    push_nonces(&mock, 5, 7);
+    assert!(monitor.check(&mut state).await?);
+    // Reported again only after another full timeout
+    push_nonces(&mock, 5, 7);
+    assert!(!monitor.check(&mut state).await?);
+    assert!(mock.params("eth_sendTransaction").is_empty());
+    Ok(())
+}
+
+fn set_fee_history(mock: &MockProvider) {
+    mock.set_default(
+        "eth_feeHistory",
+        &serde_json::json!({
+            "oldestBlock": "0x1",
+            "baseFeePerGas": ["0x3b9aca00", "0x3b9aca00"],
+            "gasUsedRatio": [0.5],
+            "baseFeePerBlobGas": [],
+            "blobGasUsedRatio": [],
+            "reward": [["0x5f5e100"]]
+        }),
+    );
+}
+
+#[tokio::test]
+async fn stuck_nonce_is_cancelled_with_bumped_fees() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    set_fee_history(&mock);
+    mock.push_sent_transaction(TxHash::repeat_byte(0x11));
+    mock.push_sent_transaction(TxHash::repeat_byte(0x22));
+    let monitor = monitor(&mock, true);
+    let mut state = None;
+
+    push_nonces(&mock, 5, 7);
+    assert!(!monitor.check(&mut state).await?);
+    for _ in 0..2 {
+        sleep(TIMEOUT * 2).await;
+        push_nonces(&mock, 5, 7);
+        assert!(monitor.check(&mut state).await?);
+    }
+
+    let sent = mock.params("eth_sendTransaction");
+    assert_eq!(sent.len(), 2);
+    for params in &sent {
+        assert_eq!(params[0]["nonce"], "0x5");
+        assert_eq!(params[0]["to"], serde_json::json!(SIGNER));
+        assert_eq!(params[0]["value"], "0x0");
+    }
+    // Each attempt bumps the fees once more
+    for field in ["maxFeePerGas", "maxPriorityFeePerGas"] {
+        assert_eq!(fee(&sent[1], field), fee(&sent[0], field) * 150 / 100);
+    }
+
+    // The nonce moved, the watch starts over
+    push_nonces(&mock, 6, 7);
+    assert!(!monitor.check(&mut state).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn denied_cancel_is_not_signed() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    set_fee_history(&mock);
+    // The self-send targets the signer, which the policy does not allow
+    let policy = StaticSigningPolicy {
+        allowed_targets: [Address::repeat_byte(0x01)].into(),
+        allowed_selectors: Default::default(),
+        max_gas_price: None,
+        max_value: None,
+    };
+    let provider = NonceManagedProvider::new(mock.provider(), Some(SIGNER))
+        .with_signing_policy(Arc::new(policy));
+    let monitor = monitor_with(provider, true);
+    let mut state = None;
+
+    push_nonces(&mock, 5, 7);
+    assert!(!monitor.check(&mut state).await?);
+    sleep(TIMEOUT * 2).await;
+    push_nonces(&mock, 5, 7);
+    // Still reported as stuck, the failed cancel is only logged
+    assert!(monitor.check(&mut state).await?);
+    assert!(mock.params("eth_sendTransaction").is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancel_on_the_wrong_chain_is_not_signed() -> anyhow::Result<()> {
+    let mock = MockProvider::new();
+    set_fee_history(&mock);
+    mock.set_default("eth_chainId", &"0x1");
+    let provider = NonceManagedProvider::new(mock.provider(), Some(SIGNER))
+        .with_chain_id_guard(12345, Duration::from_secs(3600));
+    let monitor = monitor_with(provider, true);
+    let mut state = None;
+
+    push_nonces(&mock, 5, 7);
+    assert!(!monitor.check(&mut state).await?);
+    sleep(TIMEOUT * 2).await;
+    push_nonces(&mock, 5, 7);
+    assert!(monitor.check(&mut state).await?);
+    assert!(mock.params("eth_sendTransaction").is_empty());
+    Ok(())
+}
@@ -120,17 +120,17 @@ async fn verify_proof_response_success(#[case] signer_type: SignerType) -> anyho
     assert_eq!(event.0.zkProofId, expected_proof_id);
     assert_eq!(event.0.ctHandles, expected_handles);
 
-    // Make sure the proof is removed from the database.
+    // Make sure the proof is marked as sent in the database.
     loop {
         let rows = sqlx::query!(
-            "SELECT *
+            "SELECT zk_proof_id
              FROM verify_proofs
-             WHERE zk_proof_id = $1",
+             WHERE zk_proof_id = $1 AND txn_status = 'sent'",
             proof_id as i64,
         )
         .fetch_all(&env.db_pool)
         .await?;
-        if rows.is_empty() {
+        if !rows.is_empty() {
             break;
         }
         sleep(Duration::from_millis(500)).await;
@@ -239,17 +239,17 @@ async fn verify_proof_response_empty_handles_success(
     assert_eq!(event.0.zkProofId, expected_proof_id);
     assert_eq!(event.0.ctHandles, expected_handles);
 
-    // Make sure the proof is removed from the database.
+    // Make sure the proof is marked as sent in the database.
     loop {
         let rows = sqlx::query!(
-            "SELECT *
+            "SELECT zk_proof_id
              FROM verify_proofs
-             WHERE zk_proof_id = $1",
+             WHERE zk_proof_id = $1 AND txn_status = 'sent'",
             proof_id as i64,
         )
         .fetch_all(&env.db_pool)
         .await?;
-        if rows.is_empty() {
+        if !rows.is_empty() {
             break;
         }
         sleep(Duration::from_millis(500)).await;
@@ -360,11 +360,12 @@ async fn verify_proof_response_concurrent_success(
         assert_eq!(event.0.ctHandles, expected_handles);
     }
 
-    // Make sure the proofs are removed from the database.
+    // Make sure the proofs are marked as sent in the database.
     loop {
         let rows = sqlx::query!(
-            "SELECT *
-             FROM verify_proofs"
+            "SELECT zk_proof_id
+             FROM verify_proofs
+             WHERE txn_status <> 'sent'"
         )
         .fetch_all(&env.db_pool)
         .await?;
@@ -466,17 +467,17 @@ async fn reject_proof_response_success(#[case] signer_type: SignerType) -> anyho
 
     assert_eq!(event.0.zkProofId, expected_proof_id);
 
-    // Make sure the proof is removed from the database.
+    // Make sure the proof is marked as sent in the database.
     loop {
         let rows = sqlx::query!(
-            "SELECT *
+            "SELECT zk_proof_id
              FROM verify_proofs
-             WHERE zk_proof_id = $1",
+             WHERE zk_proof_id = $1 AND txn_status = 'sent'",
             proof_id as i64,
         )
         .fetch_all(&env.db_pool)
         .await?;
-        if rows.is_empty() {
+        if !rows.is_empty() {
             break;
         }
         sleep(Duration::from_millis(500)).await;
@@ -559,17 +560,17 @@ async fn verify_proof_response_reversal_already_verified(
     .execute(&env.db_pool)
     .await?;
 
-    // Make sure the proof is removed from the database.
+    // Make sure the proof is marked as sent in the database.
     loop {
         let rows = sqlx::query!(
-            "SELECT *
+            "SELECT zk_proof_id
              FROM verify_proofs
-             WHERE zk_proof_id = $1",
+             WHERE zk_proof_id = $1 AND txn_status = 'sent'",
             proof_id as i64,
         )
         .fetch_all(&env.db_pool)
         .await?;
-        if rows.is_empty() {
+        if !rows.is_empty() {
             break;
         }
         sleep(Duration::from_millis(500)).await;
@@ -660,17 +661,17 @@ async fn reject_proof_response_reversal_already_rejected(
     .execute(&env.db_pool)
     .await?;
 
-    // Make sure the proof is removed from the database.
+    // Make sure the proof is marked as sent in the database.
     loop {
         let rows = sqlx::query!(
-            "SELECT *
+            "SELECT zk_proof_id
              FROM verify_proofs
-             WHERE zk_proof_id = $1",
+             WHERE zk_proof_id = $1 AND txn_status = 'sent'",
             proof_id as i64,
         )
         .fetch_all(&env.db_pool)
         .await?;
-        if rows.is_empty() {
+        if !rows.is_empty() {
             break;
         }
         sleep(Duration::from_millis(500)).await;
@@ -1085,7 +1086,7 @@ async fn reject_proof_response_other_reversal_gas_estimation(
 #[case::aws_kms(SignerType::AwsKms)]
 #[tokio::test]
 #[serial(db)]
-async fn verify_proof_max_retries_remove_entry(
+async fn verify_proof_max_retries_mark_failed(
     #[case] signer_type: SignerType,
 ) -> anyhow::Result<()> {
     let mut env = TestEnvironment::new(signer_type).await?;
@@ -1149,17 +1150,17 @@ async fn verify_proof_max_retries_remove_entry(
     .execute(&env.db_pool)
     .await?;
 
-    // Make sure the proof is removed from the database.
+    // Make sure the proof is marked as failed in the database.
     loop {
         let rows = sqlx::query!(
-            "SELECT *
+            "SELECT zk_proof_id
              FROM verify_proofs
-             WHERE zk_proof_id = $1",
+             WHERE zk_proof_id = $1 AND txn_status = 'failed'",
             proof_id as i64,
         )
         .fetch_all(&env.db_pool)
         .await?;
-        if rows.is_empty() {
+        if !rows.is_empty() {
             break;
         }
         sleep(Duration::from_millis(500)).await;
@@ -1272,3 +1273,89 @@ async fn verify_proof_max_retries_do_not_remove_entry(
 
     Ok(())
 }
+
+#[tokio::test]
+#[serial(db)]
+async fn verify_proof_purged_after_retention() -> anyhow::Result<()> {
+    let mut env = TestEnvironment::new(SignerType::PrivateKey).await?;
+    env.conf.verify_proof_retention = Duration::from_secs(60);
+    env.conf.purge_interval = Duration::from_millis(100);
+    let provider_deploy = ProviderBuilder::new()
+        .wallet(env.wallet.clone())
+        .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+        .await?;
+    let provider = NonceManagedProvider::new(
+        ProviderBuilder::default()
+            .filler(FillersWithoutNonceManagement::default())
+            .wallet(env.wallet.clone())
+            .connect_ws(WsConnect::new(env.ws_endpoint_url()))
+            .await?,
+        Some(env.wallet.default_signer().address()),
+    );
+    let input_verification =
+        InputVerification::deploy(&provider_deploy, false, false, false).await?;
+    let ciphertext_commits = CiphertextCommits::deploy(&provider_deploy, false).await?;
+    let txn_sender = TransactionSender::new(
+        *input_verification.address(),
+        *ciphertext_commits.address(),
+        PrivateKeySigner::random().address(),
+        env.signer.clone(),
+        provider.clone(),
+        env.cancel_token.clone(),
+        env.conf.clone(),
+        None,
+    )
+    .await?;
+
+    // A proof sent beyond the retention, one sent within it and one still being verified.
+    let expired_id = random::<u32>() as i64;
+    let recent_id = expired_id + 1;
+    let pending_id = expired_id + 2;
+    sqlx::query!(
+        "INSERT INTO verify_proofs (zk_proof_id, chain_id, contract_address, user_address, verified, txn_status, txn_status_updated_at)
+        VALUES
+            ($1, 42, $4, $5, true, 'sent', NOW() - INTERVAL '1 hour'),
+            ($2, 42, $4, $5, true, 'sent', NOW()),
+            ($3, 42, $4, $5, NULL, 'pending', NULL)",
+        expired_id,
+        recent_id,
+        pending_id,
+        env.contract_address.to_string(),
+        env.user_address.to_string(),
+    )
+    .execute(&env.db_pool)
+    .await?;
+
+    let run_handle = tokio::spawn(async move { txn_sender.run().await });
+
+    loop {
+        let rows = sqlx::query!(
+            "SELECT zk_proof_id FROM verify_proofs WHERE zk_proof_id = $1",
+            expired_id,
+        )
+        .fetch_all(&env.db_pool)
+        .await?;
+        if rows.is_empty() {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    let remaining: Vec<i64> = sqlx::query_scalar!(
+        "SELECT zk_proof_id FROM verify_proofs WHERE zk_proof_id = ANY($1) ORDER BY zk_proof_id",
+        &[recent_id, pending_id][..],
+    )
+    .fetch_all(&env.db_pool)
+    .await?;
+    assert_eq!(remaining, vec![recent_id, pending_id]);
+
+    sqlx::query!(
+        "DELETE FROM verify_proofs WHERE zk_proof_id = ANY($1)",
+        &[recent_id, pending_id][..],
+    )
+    .execute(&env.db_pool)
+    .await?;
+    env.cancel_token.cancel();
+    run_handle.await??;
+    Ok(())
+}
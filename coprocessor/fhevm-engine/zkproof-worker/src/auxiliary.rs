@@ -4,7 +4,7 @@ const SIZE: usize = 92;
 
 /// ZkData is the data that is used to generate the ZKPs
 #[derive(Debug, Clone)]
-pub(crate) struct ZkData {
+pub struct ZkData {
     pub contract_address: String,
     pub user_address: String,
     pub acl_contract_address: String,
@@ -1,5 +1,8 @@
 use clap::{command, Parser};
+use fhevm_engine_common::ciphertext_format::{self, CiphertextFormat};
+use fhevm_engine_common::db_schema;
 use fhevm_engine_common::healthz_server::HttpServer;
+use fhevm_engine_common::secret::SecretString;
 use fhevm_engine_common::telemetry;
 use humantime::parse_duration;
 use std::{sync::Arc, time::Duration};
@@ -8,6 +11,17 @@ use tokio_util::sync::CancellationToken;
 use tracing::{error, info, Level};
 use zkproof_worker::verifier::ZkProofService;
 
+#[cfg(any(feature = "jemalloc", feature = "mimalloc"))]
+use fhevm_engine_common::allocator;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: allocator::Jemalloc = allocator::Jemalloc;
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+#[global_allocator]
+static GLOBAL: allocator::MiMalloc = allocator::MiMalloc;
+
 #[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 pub struct Args {
@@ -41,10 +55,24 @@ pub struct Args {
     #[arg(long)]
     pub database_url: Option<String>,
 
+    /// Database schema of the host chain, in the schema-per-chain layout
+    #[arg(long, value_parser = db_schema::parse_schema_name)]
+    pub database_schema: Option<String>,
+
     /// Number of zkproof workers to process proofs in parallel
     #[arg(long, default_value_t = 8)]
     pub worker_thread_count: u32,
 
+    /// Number of the zkproof workers reserved to small proofs, so that they
+    /// are not queued behind big multi-ciphertext proofs. At least one worker
+    /// is left for the other proofs
+    #[arg(long, default_value_t = 1)]
+    pub small_proof_workers: u32,
+
+    /// Maximum size in bytes of the input of a small proof
+    #[arg(long, default_value_t = 16 * 1024)]
+    pub small_proof_max_bytes: u32,
+
     /// Zkproof-worker service name in OTLP traces
     #[arg(long, default_value = "zkproof-worker")]
     pub service_name: String,
@@ -59,6 +87,17 @@ pub struct Args {
     /// HTTP server port for health checks
     #[arg(long, default_value_t = 8080)]
     health_check_port: u16,
+
+    /// Storage format of newly written ciphertexts (legacy or v1)
+    #[arg(long, default_value_t = CiphertextFormat::Legacy)]
+    pub ciphertext_format: CiphertextFormat,
+
+    /// Bearer token required by the admin endpoints of the health check
+    /// server, e.g. /admin/heap_profile. If unspecified ADMIN_TOKEN
+    /// environment variable is used, the admin endpoints are disabled if both
+    /// are unset
+    #[arg(long)]
+    pub admin_token: Option<SecretString>,
 }
 
 pub fn parse_args() -> Args {
@@ -81,13 +120,18 @@ async fn main() {
         .clone()
         .unwrap_or_else(|| std::env::var("DATABASE_URL").expect("DATABASE_URL is undefined"));
 
+    ciphertext_format::set_write_format(args.ciphertext_format);
+
     let conf = zkproof_worker::Config {
         database_url,
+        database_schema: args.database_schema,
         listen_database_channel: args.pg_listen_channel,
         notify_database_channel: args.pg_notify_channel,
         pg_pool_connections: args.pg_pool_connections,
         pg_polling_interval: args.pg_polling_interval,
         worker_thread_count: args.worker_thread_count,
+        small_proof_workers: args.small_proof_workers,
+        small_proof_max_bytes: args.small_proof_max_bytes,
         pg_timeout: args.pg_timeout,
         pg_auto_explain_with_min_duration: args.pg_auto_explain_with_min_duration,
     };
@@ -110,6 +154,10 @@ async fn main() {
         service.clone(),
         args.health_check_port,
         cancel_token.child_token(),
+    )
+    .with_admin_token(
+        args.admin_token
+            .or_else(|| std::env::var("ADMIN_TOKEN").ok().map(SecretString::from)),
     );
 
     let http_task = task::spawn(async move {
@@ -1,4 +1,5 @@
 pub mod auxiliary;
+pub mod proof_system;
 
 #[cfg(test)]
 mod tests;
@@ -69,6 +70,8 @@ impl From<ExecutionError> for ServiceError {
 #[derive(Default, Debug, Clone)]
 pub struct Config {
     pub database_url: String,
+    /// Schema of the host chain in the schema-per-chain layout, see `db_schema`.
+    pub database_schema: Option<String>,
     pub listen_database_channel: String,
     pub notify_database_channel: String,
     pub pg_pool_connections: u32,
@@ -77,4 +80,8 @@ pub struct Config {
     pub pg_auto_explain_with_min_duration: Option<Duration>,
 
     pub worker_thread_count: u32,
+    /// Workers reserved to the proofs up to `small_proof_max_bytes`, out of
+    /// `worker_thread_count`
+    pub small_proof_workers: u32,
+    pub small_proof_max_bytes: u32,
 }
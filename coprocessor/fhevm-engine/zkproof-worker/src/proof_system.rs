@@ -0,0 +1,175 @@
+//! Formats of the input lists uploaded by the clients.
+//!
+//! A list is either safe-serialized by tfhe-rs, the legacy format, or sent in the compact format:
+//! a version byte followed by the packed list and its proof serialized without the
+//! safe-serialization header. Safe-serialized lists start with the little-endian length of their
+//! serialization version, a few bytes, so the version bytes of the compact formats start at 0x80
+//! and the format is negotiated from the first byte of the payload. The blob hash, hence the
+//! handles, is computed over the whole payload, version byte included.
+
+use std::sync::LazyLock;
+
+use bincode::Options;
+use fhevm_engine_common::utils::{safe_deserialize_conformant, SAFE_SER_DESER_LIMIT};
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use tfhe::integer::ciphertext::IntegerProvenCompactCiphertextListConformanceParams;
+use tfhe::prelude::ParameterSetConformant;
+use tfhe::{ProvenCompactCiphertextList, Unversionize, Versionize};
+
+use crate::ExecutionError;
+
+/// Version byte of the first compact format
+pub const COMPACT_FORMAT_V1: u8 = 0x81;
+/// Version bytes from this one are compact formats
+const COMPACT_FORMAT_MIN: u8 = 0x80;
+
+static INPUT_FORMAT_COUNTER: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "coprocessor_zkproof_input_format_counter",
+        "Number of input lists parsed per format",
+        &["format"]
+    )
+    .unwrap()
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    SafeSerialized,
+    CompactV1,
+}
+
+impl InputFormat {
+    pub fn detect(payload: &[u8]) -> Result<Self, ExecutionError> {
+        match payload.first() {
+            Some(&COMPACT_FORMAT_V1) => Ok(Self::CompactV1),
+            Some(&version) if version >= COMPACT_FORMAT_MIN => {
+                Err(ExecutionError::InvalidCiphertextBytes(format!(
+                    "unsupported input format version {version:#x}"
+                )))
+            }
+            _ => Ok(Self::SafeSerialized),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::SafeSerialized => "safe_serialized",
+            Self::CompactV1 => "compact_v1",
+        }
+    }
+
+    pub fn proof_system(&self) -> &'static dyn ProofSystem {
+        match self {
+            Self::SafeSerialized => &SafeSerialized,
+            Self::CompactV1 => &CompactV1,
+        }
+    }
+}
+
+/// Parsing of an input list in a given format, and verification of its proof.
+pub trait ProofSystem: Send + Sync {
+    /// Deserializes the list, checking that it conforms to the keys. The proof is not verified.
+    fn parse(
+        &self,
+        payload: &[u8],
+        params: &IntegerProvenCompactCiphertextListConformanceParams,
+    ) -> Result<ProvenCompactCiphertextList, ExecutionError>;
+
+    fn verify_and_expand(
+        &self,
+        list: &ProvenCompactCiphertextList,
+        public_params: &tfhe::zk::CompactPkeCrs,
+        pks: &tfhe::CompactPublicKey,
+        aux_data: &[u8],
+    ) -> Result<tfhe::CompactCiphertextListExpander, String> {
+        list.verify_and_expand(public_params, pks, aux_data)
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Lists safe-serialized by tfhe-rs.
+pub struct SafeSerialized;
+
+impl ProofSystem for SafeSerialized {
+    fn parse(
+        &self,
+        payload: &[u8],
+        params: &IntegerProvenCompactCiphertextListConformanceParams,
+    ) -> Result<ProvenCompactCiphertextList, ExecutionError> {
+        Ok(safe_deserialize_conformant(payload, params)?)
+    }
+}
+
+/// [`COMPACT_FORMAT_V1`] followed by the versioned list, bincode serialized.
+pub struct CompactV1;
+
+fn compact_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(SAFE_SER_DESER_LIMIT)
+}
+
+impl ProofSystem for CompactV1 {
+    fn parse(
+        &self,
+        payload: &[u8],
+        params: &IntegerProvenCompactCiphertextListConformanceParams,
+    ) -> Result<ProvenCompactCiphertextList, ExecutionError> {
+        let body = payload.strip_prefix(&[COMPACT_FORMAT_V1]).ok_or_else(|| {
+            ExecutionError::InvalidCiphertextBytes("not a compact v1 input".to_owned())
+        })?;
+        let versioned: <ProvenCompactCiphertextList as Unversionize>::VersionedOwned =
+            compact_options().deserialize(body)?;
+        let list = ProvenCompactCiphertextList::unversionize(versioned)
+            .map_err(|err| ExecutionError::InvalidCiphertextBytes(err.to_string()))?;
+        if !list.is_conformant(params) {
+            return Err(ExecutionError::InvalidCiphertextBytes(
+                "input list does not conform to the keys".to_owned(),
+            ));
+        }
+        Ok(list)
+    }
+}
+
+/// Encodes a list in the compact format, as the clients do.
+pub fn encode_compact(list: &ProvenCompactCiphertextList) -> Result<Vec<u8>, ExecutionError> {
+    let mut payload = vec![COMPACT_FORMAT_V1];
+    compact_options().serialize_into(&mut payload, &list.versionize())?;
+    Ok(payload)
+}
+
+/// Parses the payload with the proof system of its format.
+pub fn parse(
+    payload: &[u8],
+    params: &IntegerProvenCompactCiphertextListConformanceParams,
+) -> Result<(InputFormat, ProvenCompactCiphertextList), ExecutionError> {
+    let format = InputFormat::detect(payload)?;
+    INPUT_FORMAT_COUNTER
+        .with_label_values(&[format.as_str()])
+        .inc();
+    let list = format.proof_system().parse(payload, params)?;
+    Ok((format, list))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        // safe-serialized lists start with the length of their serialization version
+        assert_eq!(
+            InputFormat::detect(&[3, 0, 0, 0, 0, 0, 0, 0]).unwrap(),
+            InputFormat::SafeSerialized
+        );
+        assert_eq!(
+            InputFormat::detect(&[COMPACT_FORMAT_V1, 1, 2]).unwrap(),
+            InputFormat::CompactV1
+        );
+        assert!(InputFormat::detect(&[0x82]).is_err());
+        assert_eq!(
+            InputFormat::detect(&[]).unwrap(),
+            InputFormat::SafeSerialized
+        );
+    }
+}
@@ -40,6 +40,45 @@ async fn test_verify_proof() {
         .unwrap());
 }
 
+#[tokio::test]
+#[serial(db)]
+async fn test_verify_compact_proof() {
+    let (pool_mngr, _instance) = utils::setup().await.expect("valid setup");
+    let pool = pool_mngr.pool();
+
+    let aux: (crate::auxiliary::ZkData, [u8; 92]) =
+        utils::aux_fixture(ACL_CONTRACT_ADDR.to_owned());
+    let zk_pok = utils::generate_sample_compact_zk_pok(&pool, &aux.1).await;
+    assert_eq!(zk_pok[0], crate::proof_system::COMPACT_FORMAT_V1);
+    let request_id_valid = utils::insert_proof(&pool, 101, &zk_pok, &aux.0)
+        .await
+        .unwrap();
+
+    // Truncated payload
+    let request_id_truncated = utils::insert_proof(&pool, 102, &zk_pok[..zk_pok.len() / 2], &aux.0)
+        .await
+        .unwrap();
+
+    // Unknown version byte
+    let mut unknown_version = zk_pok.clone();
+    unknown_version[0] = 0xff;
+    let request_id_unknown = utils::insert_proof(&pool, 103, &unknown_version, &aux.0)
+        .await
+        .unwrap();
+
+    let max_retries = 1000;
+
+    assert!(utils::is_valid(&pool, request_id_valid, max_retries)
+        .await
+        .unwrap());
+    assert!(!utils::is_valid(&pool, request_id_truncated, max_retries)
+        .await
+        .unwrap());
+    assert!(!utils::is_valid(&pool, request_id_unknown, max_retries)
+        .await
+        .unwrap());
+}
+
 #[tokio::test]
 #[serial(db)]
 async fn test_verify_empty_input_list() {
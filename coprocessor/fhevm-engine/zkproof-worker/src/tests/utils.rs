@@ -16,11 +16,14 @@ pub async fn setup() -> anyhow::Result<(PostgresPoolManager, DBInstance)> {
 
     let conf = crate::Config {
         database_url: test_instance.db_url().to_owned(),
+        database_schema: None,
         listen_database_channel: "fhevm".to_string(),
         notify_database_channel: "notify".to_string(),
         pg_pool_connections: 10,
         pg_polling_interval: 60,
         worker_thread_count: 1,
+        small_proof_workers: 0,
+        small_proof_max_bytes: 16 * 1024,
         pg_timeout: Duration::from_secs(15),
         pg_auto_explain_with_min_duration: None,
     };
@@ -28,6 +31,7 @@ pub async fn setup() -> anyhow::Result<(PostgresPoolManager, DBInstance)> {
     let pool_mngr = PostgresPoolManager::connect_pool(
         test_instance.parent_token.child_token(),
         conf.database_url.as_str(),
+        conf.database_schema.as_deref(),
         conf.pg_timeout,
         conf.pg_pool_connections,
         Duration::from_secs(2),
@@ -94,6 +98,14 @@ pub(crate) async fn generate_zk_pok_with_inputs(
     aux_data: &[u8],
     inputs: &[ZkInput],
 ) -> Vec<u8> {
+    safe_serialize(&build_zk_pok_list(pool, aux_data, inputs).await)
+}
+
+async fn build_zk_pok_list(
+    pool: &sqlx::PgPool,
+    aux_data: &[u8],
+    inputs: &[ZkInput],
+) -> tfhe::ProvenCompactCiphertextList {
     let keys: Vec<tenant_keys::TfheTenantKeys> =
         tenant_keys::query_tenant_keys(vec![1], pool, true)
             .await
@@ -115,26 +127,36 @@ pub(crate) async fn generate_zk_pok_with_inputs(
         };
     }
 
-    let the_list = builder
+    builder
         .build_with_proof_packed(
             &keys.public_params,
             aux_data,
             tfhe::zk::ZkComputeLoad::Proof,
         )
-        .unwrap();
-
-    safe_serialize(&the_list)
+        .unwrap()
 }
 
-pub(crate) async fn generate_sample_zk_pok(pool: &sqlx::PgPool, aux_data: &[u8]) -> Vec<u8> {
-    let inputs = vec![
+fn sample_inputs() -> Vec<ZkInput> {
+    vec![
         ZkInput::Bool(true),
         ZkInput::U8(42),
         ZkInput::U16(12345),
         ZkInput::U32(67890),
         ZkInput::U64(1234567890),
-    ];
-    generate_zk_pok_with_inputs(pool, aux_data, &inputs).await
+    ]
+}
+
+pub(crate) async fn generate_sample_zk_pok(pool: &sqlx::PgPool, aux_data: &[u8]) -> Vec<u8> {
+    generate_zk_pok_with_inputs(pool, aux_data, &sample_inputs()).await
+}
+
+/// Same inputs as [`generate_sample_zk_pok`], in the compact format
+pub(crate) async fn generate_sample_compact_zk_pok(
+    pool: &sqlx::PgPool,
+    aux_data: &[u8],
+) -> Vec<u8> {
+    let the_list = build_zk_pok_list(pool, aux_data, &sample_inputs()).await;
+    crate::proof_system::encode_compact(&the_list).unwrap()
 }
 
 pub(crate) async fn generate_empty_input_list(pool: &sqlx::PgPool, aux_data: &[u8]) -> Vec<u8> {
@@ -1,4 +1,8 @@
 use alloy_primitives::Address;
+use fhevm_engine_common::db_query::{run_query, timed_query, QueryPolicy};
+use fhevm_engine_common::handle::TypedHandle;
+use fhevm_engine_common::param_set::ParamSet;
+use fhevm_engine_common::pg_listener::{ListenerEvent, SupervisedListener};
 use fhevm_engine_common::pg_pool::{PostgresPoolManager, ServiceError};
 use fhevm_engine_common::telemetry::{self, gen_buckets};
 use fhevm_engine_common::tenant_keys::TfheTenantKeys;
@@ -6,13 +10,12 @@ use fhevm_engine_common::tenant_keys::{self, FetchTenantKeyResult};
 use fhevm_engine_common::tfhe_ops::{current_ciphertext_version, extract_ct_list};
 use fhevm_engine_common::types::SupportedFheCiphertexts;
 
-use fhevm_engine_common::utils::safe_deserialize_conformant;
 use hex::encode;
 use lru::LruCache;
 use prometheus::{register_histogram, Histogram};
 use sha3::Digest;
 use sha3::Keccak256;
-use sqlx::{postgres::PgListener, PgPool, Row};
+use sqlx::{PgPool, Row};
 use sqlx::{Postgres, Transaction};
 use std::num::NonZero;
 use std::str::FromStr;
@@ -20,6 +23,7 @@ use tfhe::integer::ciphertext::IntegerProvenCompactCiphertextListConformancePara
 use tokio::sync::RwLock;
 use tokio::task::JoinSet;
 
+use crate::proof_system::{self, InputFormat};
 use crate::{auxiliary, Config, ExecutionError, MAX_INPUT_INDEX};
 use anyhow::Result;
 
@@ -103,6 +107,7 @@ impl ZkProofService {
         let Some(pool_mngr) = PostgresPoolManager::connect_pool(
             token.child_token(),
             conf.database_url.as_str(),
+            conf.database_schema.as_deref(),
             conf.pg_timeout,
             max_pool_connections,
             Duration::from_secs(2),
@@ -149,10 +154,21 @@ pub async fn execute_verify_proofs_loop(
     telemetry::attribute(&mut s, "count", conf.worker_thread_count.to_string());
     let mut task_set = JoinSet::new();
 
+    // Small proofs have their own lane, the other workers take any proof in order
+    let small_proof_workers = conf
+        .small_proof_workers
+        .min(conf.worker_thread_count.saturating_sub(1));
+    info!(
+        small_proof_workers,
+        small_proof_max_bytes = conf.small_proof_max_bytes,
+        "Small proof lane"
+    );
+
     for index in 0..conf.worker_thread_count {
         let conf = conf.clone();
         let tenant_key_cache = tenant_key_cache.clone();
         let last_active_at = last_active_at.clone();
+        let max_input_len = (index < small_proof_workers).then_some(conf.small_proof_max_bytes);
 
         // Spawn a ZK-proof worker
         // All workers compete for zk-proof tasks queued in the 'verify_proof' table.
@@ -161,9 +177,16 @@ pub async fn execute_verify_proofs_loop(
             let last_active_at = last_active_at.clone();
             let conf = conf.clone();
             async move {
-                execute_worker(conf, pool, ct, tenant_key_cache, last_active_at)
-                    .await
-                    .map_err(ServiceError::from)
+                execute_worker(
+                    conf,
+                    pool,
+                    ct,
+                    tenant_key_cache,
+                    last_active_at,
+                    max_input_len,
+                )
+                .await
+                .map_err(ServiceError::from)
             }
         };
 
@@ -184,39 +207,37 @@ pub async fn execute_verify_proofs_loop(
     Ok(())
 }
 
+/// Runs a worker, restricted to the proofs whose input is up to `max_input_len` bytes if set.
 async fn execute_worker(
     conf: Config,
     pool: sqlx::Pool<sqlx::Postgres>,
     token: CancellationToken,
     tenant_key_cache: Arc<RwLock<LruCache<i64, TfheTenantKeys>>>,
     last_active_at: Arc<RwLock<SystemTime>>,
+    max_input_len: Option<u32>,
 ) -> Result<(), ExecutionError> {
     update_last_active(last_active_at.clone()).await;
 
-    let mut listener = PgListener::connect_with(&pool).await?;
-    listener.listen(&conf.listen_database_channel).await?;
+    let mut listener =
+        SupervisedListener::connect(&pool, [conf.listen_database_channel.as_str()]).await?;
 
     let mut idle_event = interval(Duration::from_secs(conf.pg_polling_interval as u64));
 
     loop {
         update_last_active(last_active_at.clone()).await;
 
-        execute_verify_proof_routine(&pool, &tenant_key_cache, &conf).await?;
-        let count = get_remaining_tasks(&pool).await?;
+        execute_verify_proof_routine(&pool, &tenant_key_cache, &conf, max_input_len).await?;
+        let count = get_remaining_tasks(&pool, max_input_len).await?;
         if count > 0 {
             info!({ count }, "zkproof requests available");
             continue;
         }
 
         select! {
-            res = listener.try_recv() => {
-                let res = res?;
-                match res {
-                    Some(notification) => info!( src = %notification.process_id(), "Received notification"),
-                    None => {
-                        error!("Connection lost");
-                        continue;
-                    },
+            event = listener.recv() => {
+                match event {
+                    ListenerEvent::Notification(notification) => info!( src = %notification.process_id(), "Received notification"),
+                    ListenerEvent::Reconnected => info!("Listener reconnected, rechecking for requests"),
                 };
             },
             _ = idle_event.tick() => {
@@ -235,16 +256,23 @@ async fn execute_verify_proof_routine(
     pool: &PgPool,
     tenant_key_cache: &Arc<RwLock<LruCache<i64, TfheTenantKeys>>>,
     conf: &Config,
+    max_input_len: Option<u32>,
 ) -> Result<(), ExecutionError> {
     let mut txn: sqlx::Transaction<'_, sqlx::Postgres> = pool.begin().await?;
-    if let Ok(row) = sqlx::query(
-        "SELECT zk_proof_id, input, chain_id, contract_address, user_address, transaction_id
+    if let Ok(row) = timed_query(
+        "zkproof_worker.fetch_proof",
+        &QueryPolicy::default(),
+        sqlx::query(
+            "SELECT zk_proof_id, input, chain_id, contract_address, user_address, transaction_id
             FROM verify_proofs
             WHERE verified IS NULL
+            AND ($1::INT8 IS NULL OR octet_length(input) <= $1)
             ORDER BY zk_proof_id ASC
             LIMIT 1 FOR UPDATE SKIP LOCKED",
+        )
+        .bind(max_input_len.map(i64::from))
+        .fetch_one(&mut *txn),
     )
-    .fetch_one(&mut *txn)
     .await
     {
         let started_at = SystemTime::now();
@@ -262,18 +290,25 @@ async fn execute_verify_proof_routine(
             user_address,
             contract_address,
             input_len = format!("{}", input.len()),
+            small_proof_lane = max_input_len.is_some(),
         );
 
         let t: telemetry::OtelTracer = telemetry::tracer("verify_task", &transaction_id);
         t.set_attribute("request_id", request_id.to_string());
 
         let s = t.child_span("fetch_keys");
+        // Verify with the current key of the tenant, see fhevm_engine_common::key_version
+        tenant_keys::evict_stale_tenant_keys(&[chain_id], pool, tenant_key_cache, false)
+            .await
+            .map_err(|err| ExecutionError::ServerKeysNotFound(err.to_string()))?;
         let keys = tenant_keys::fetch_tenant_server_key(chain_id, pool, tenant_key_cache, false)
             .await
             .map_err(|err| ExecutionError::ServerKeysNotFound(err.to_string()))?;
         telemetry::end_span(s);
 
         let tenant_id = keys.tenant_id;
+        let key_id = keys.key_id.clone();
+        let param_set = keys.param_set;
         info!(message = "Keys retrieved", request_id, chain_id);
 
         let res = tokio::task::spawn_blocking(move || {
@@ -307,7 +342,15 @@ async fn execute_verify_proof_routine(
                 });
                 verified = true;
                 let count = cts.len();
-                insert_ciphertexts(&mut txn, tenant_id, cts, blob_hash).await?;
+                insert_ciphertexts(
+                    &mut txn,
+                    tenant_id,
+                    key_id.as_deref(),
+                    param_set,
+                    cts,
+                    blob_hash,
+                )
+                .await?;
 
                 info!(message = "Ciphertexts inserted", request_id);
                 t.set_attribute("count", count.to_string());
@@ -404,14 +447,12 @@ fn try_verify_and_expand_ciphertext_list(
         .assemble()
         .map_err(|e| ExecutionError::InvalidAuxData(e.to_string()))?;
 
-    let the_list: tfhe::ProvenCompactCiphertextList = safe_deserialize_conformant(raw_ct,
-        &IntegerProvenCompactCiphertextListConformanceParams::from_public_key_encryption_parameters_and_crs_parameters(
-            keys.pks.parameters(), &keys.public_params,
-        ))?;
+    let (format, the_list) = parse_input_list(raw_ct, &keys.pks, &keys.public_params)?;
 
     info!(
         message = "Input list deserialized",
         len = format!("{}", the_list.len()),
+        format = format.as_str(),
         request_id,
     );
 
@@ -421,15 +462,32 @@ fn try_verify_and_expand_ciphertext_list(
         return Ok(vec![]);
     }
 
+    let expanded: tfhe::CompactCiphertextListExpander = format
+        .proof_system()
+        .verify_and_expand(&the_list, &keys.public_params, &keys.pks, &aux_data_bytes)
+        .map_err(|err| ExecutionError::InvalidProof(request_id, err))?;
+
+    Ok(extract_ct_list(&expanded)?)
+}
+
+/// Deserializes an input list as sent by a client, in any of the formats of [`proof_system`],
+/// checking that it conforms to the tenant keys and does not hold more than [`MAX_INPUT_INDEX`] + 1
+/// inputs. The proof is not verified.
+pub fn parse_input_list(
+    raw_ct: &[u8],
+    pks: &tfhe::CompactPublicKey,
+    public_params: &tfhe::zk::CompactPkeCrs,
+) -> Result<(InputFormat, tfhe::ProvenCompactCiphertextList), ExecutionError> {
+    let (format, the_list) = proof_system::parse(raw_ct,
+        &IntegerProvenCompactCiphertextListConformanceParams::from_public_key_encryption_parameters_and_crs_parameters(
+            pks.parameters(), public_params,
+        ))?;
+
     if the_list.len() > (MAX_INPUT_INDEX + 1) as usize {
         return Err(ExecutionError::TooManyInputs(the_list.len()));
     }
 
-    let expanded: tfhe::CompactCiphertextListExpander = the_list
-        .verify_and_expand(&keys.public_params, &keys.pks, &aux_data_bytes)
-        .map_err(|err| ExecutionError::InvalidProof(request_id, err.to_string()))?;
-
-    Ok(extract_ct_list(&expanded)?)
+    Ok((format, the_list))
 }
 
 /// Creates a ciphertext
@@ -458,21 +516,24 @@ fn create_ciphertext(
             .into_array(),
     );
     handle_hash.update(chain_id_bytes);
-    let mut handle = handle_hash.finalize().to_vec();
-    assert_eq!(handle.len(), 32);
+    let hash: [u8; 32] = handle_hash.finalize().into();
 
     // Add the full 256bit hash as re-randomization metadata, NOT the
     // truncated hash of the handle
-    the_ct.add_re_randomization_metadata(&handle);
+    the_ct.add_re_randomization_metadata(&hash);
     let (serialized_type, compressed) = the_ct.compress();
 
     // idx cast to u8 must succeed because we don't allow
     // more handles than u8 size
-    handle[21] = ct_idx as u8;
     // TODO: change chain ID to be u64
-    handle[22..30].copy_from_slice(&(aux_data.chain_id as u64).to_be_bytes());
-    handle[30] = serialized_type as u8;
-    handle[31] = current_ciphertext_version() as u8;
+    let handle = TypedHandle::from_hash(
+        hash,
+        ct_idx as u8,
+        aux_data.chain_id as u64,
+        serialized_type,
+        current_ciphertext_version() as u8,
+    )
+    .to_vec();
 
     let t = &mut span.child_span("create_handle");
     telemetry::attribute(t, "request_id", request_id.to_string());
@@ -499,21 +560,32 @@ fn create_ciphertext(
     })
 }
 
-/// Returns the number of remaining tasks in the database.
-async fn get_remaining_tasks(pool: &PgPool) -> Result<i64, ExecutionError> {
-    let row = sqlx::query(
-        "
+/// Returns the number of remaining tasks in the database, up to `max_input_len` bytes if set.
+async fn get_remaining_tasks(
+    pool: &PgPool,
+    max_input_len: Option<u32>,
+) -> Result<i64, ExecutionError> {
+    let row = run_query(
+        "zkproof_worker.get_remaining_tasks",
+        &QueryPolicy::default(),
+        || {
+            sqlx::query(
+                "
         SELECT COUNT(*)
         FROM (
             SELECT 1
             FROM verify_proofs
             WHERE verified IS NULL
+            AND ($1::INT8 IS NULL OR octet_length(input) <= $1)
             ORDER BY zk_proof_id ASC
             FOR UPDATE SKIP LOCKED
         ) AS unlocked_rows;
         ",
+            )
+            .bind(max_input_len.map(i64::from))
+            .fetch_one(pool)
+        },
     )
-    .fetch_one(pool)
     .await?;
 
     let count: i64 = row.get("count");
@@ -524,6 +596,8 @@ async fn get_remaining_tasks(pool: &PgPool) -> Result<i64, ExecutionError> {
 pub(crate) async fn insert_ciphertexts(
     db_txn: &mut Transaction<'_, Postgres>,
     tenant_id: i32,
+    key_id: Option<&[u8]>,
+    param_set: &ParamSet,
     cts: &[Ciphertext],
     blob_hash: &Vec<u8>,
 ) -> Result<(), ExecutionError> {
@@ -532,8 +606,8 @@ pub(crate) async fn insert_ciphertexts(
             r#"
             INSERT INTO ciphertexts (
                 tenant_id, handle, ciphertext, ciphertext_version, ciphertext_type, 
-                input_blob_hash, input_blob_index, created_at
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+                input_blob_hash, input_blob_index, key_id, param_set, created_at
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, NOW())
             ON CONFLICT (tenant_id, handle, ciphertext_version) DO NOTHING;
             "#,
             tenant_id,
@@ -543,6 +617,8 @@ pub(crate) async fn insert_ciphertexts(
             ct.ct_type,
             &blob_hash,
             i as i32,
+            key_id,
+            param_set.name,
         )
         .execute(db_txn.as_mut())
         .await?;
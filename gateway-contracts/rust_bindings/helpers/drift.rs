@@ -0,0 +1,196 @@
+//! Detection of drift between these bindings and the contracts deployed on chain.
+//!
+//! Services call [`check_selectors`] at startup with the selectors they rely on. The deployed
+//! runtime code (the implementation code for ERC-1967 proxies) is scanned for the selectors and
+//! the service can fail fast instead of sending transactions that revert or listening to events
+//! that are never emitted.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use alloy::network::Network;
+use alloy::primitives::{Address, B256, Bytes, b256, keccak256};
+use alloy::providers::Provider;
+use alloy::sol_types::{SolCall, SolError, SolEvent};
+use alloy::transports::TransportError;
+
+/// `bytes32(uint256(keccak256("eip1967.proxy.implementation")) - 1)`
+pub const ERC1967_IMPLEMENTATION_SLOT: B256 =
+    b256!("0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc");
+
+const PUSH1: u8 = 0x60;
+const PUSH32: u8 = 0x7f;
+
+/// A selector a service expects to find in the deployed code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpectedSelector {
+    /// Solidity signature, used for reporting only.
+    pub signature: &'static str,
+    pub kind: SelectorKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectorKind {
+    /// 4-byte function or error selector.
+    Short([u8; 4]),
+    /// 32-byte event topic.
+    Topic(B256),
+}
+
+impl ExpectedSelector {
+    pub fn function<C: SolCall>() -> Self {
+        Self {
+            signature: C::SIGNATURE,
+            kind: SelectorKind::Short(C::SELECTOR),
+        }
+    }
+
+    pub fn error<E: SolError>() -> Self {
+        Self {
+            signature: E::SIGNATURE,
+            kind: SelectorKind::Short(E::SELECTOR),
+        }
+    }
+
+    pub fn event<E: SolEvent>() -> Self {
+        Self {
+            signature: E::SIGNATURE,
+            kind: SelectorKind::Topic(E::SIGNATURE_HASH),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DriftError {
+    Transport(TransportError),
+    /// There is no code at the given address.
+    NoCode(Address),
+    /// The deployed code does not reference some of the expected selectors.
+    MissingSelectors {
+        address: Address,
+        /// The address the code was read from, i.e. the proxy implementation if any.
+        code_address: Address,
+        code_hash: B256,
+        missing: Vec<&'static str>,
+    },
+}
+
+impl fmt::Display for DriftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(e) => write!(f, "failed to fetch deployed code: {e}"),
+            Self::NoCode(address) => write!(f, "no code deployed at {address}"),
+            Self::MissingSelectors {
+                address,
+                code_address,
+                code_hash,
+                missing,
+            } => write!(
+                f,
+                "bindings do not match the contract at {address} (code at {code_address}, \
+                 hash {code_hash}), missing: {}",
+                missing.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DriftError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TransportError> for DriftError {
+    fn from(err: TransportError) -> Self {
+        Self::Transport(err)
+    }
+}
+
+/// Returns the address holding the runtime code of `address` and that code. For ERC-1967 proxies
+/// this is the implementation.
+pub async fn deployed_code<N: Network, P: Provider<N>>(
+    provider: &P,
+    address: Address,
+) -> Result<(Address, Bytes), DriftError> {
+    let slot = provider
+        .get_storage_at(address, ERC1967_IMPLEMENTATION_SLOT.into())
+        .await?;
+    let implementation = Address::from_word(slot.into());
+    let code_address = if implementation.is_zero() {
+        address
+    } else {
+        implementation
+    };
+    let code = provider.get_code_at(code_address).await?;
+    if code.is_empty() {
+        return Err(DriftError::NoCode(code_address));
+    }
+    Ok((code_address, code))
+}
+
+/// Returns the expected selectors that are not pushed by the given runtime code.
+///
+/// Function and error selectors are pushed by the dispatcher and the revert paths, event topics
+/// by the `LOG` call sites. The optimizer drops leading zero bytes, hence shorter pushes are
+/// left padded before comparing.
+pub fn missing_selectors<'a>(
+    code: &[u8],
+    expected: &'a [ExpectedSelector],
+) -> Vec<&'a ExpectedSelector> {
+    let mut short = HashSet::new();
+    let mut topics = HashSet::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        let op = code[pc];
+        pc += 1;
+        if !(PUSH1..=PUSH32).contains(&op) {
+            continue;
+        }
+        let len = (op - PUSH1 + 1) as usize;
+        let Some(data) = code.get(pc..pc + len) else {
+            break;
+        };
+        pc += len;
+        if len <= 4 {
+            let mut selector = [0u8; 4];
+            selector[4 - len..].copy_from_slice(data);
+            short.insert(selector);
+        }
+        let mut topic = B256::ZERO;
+        topic[32 - len..].copy_from_slice(data);
+        topics.insert(topic);
+    }
+
+    expected
+        .iter()
+        .filter(|e| match &e.kind {
+            SelectorKind::Short(selector) => !short.contains(selector),
+            SelectorKind::Topic(topic) => !topics.contains(topic),
+        })
+        .collect()
+}
+
+/// Checks that the contract deployed at `address` references all `expected` selectors. Returns
+/// the hash of the checked code on success.
+pub async fn check_selectors<N: Network, P: Provider<N>>(
+    provider: &P,
+    address: Address,
+    expected: &[ExpectedSelector],
+) -> Result<B256, DriftError> {
+    let (code_address, code) = deployed_code(provider, address).await?;
+    let code_hash = keccak256(&code);
+    let missing = missing_selectors(&code, expected);
+    if !missing.is_empty() {
+        return Err(DriftError::MissingSelectors {
+            address,
+            code_address,
+            code_hash,
+            missing: missing.into_iter().map(|e| e.signature).collect(),
+        });
+    }
+    Ok(code_hash)
+}
@@ -0,0 +1,138 @@
+//! Typed decoding of raw logs emitted by the Gateway contracts.
+//!
+//! Consumers should go through [`decode_event`] (when the emitting contract is known) or
+//! [`decode_any_event`] rather than matching topics manually, so that the block and transaction
+//! metadata of the log is always carried along with the decoded event.
+
+use alloy::primitives::{Address, B256, Log as PrimitiveLog};
+use alloy::rpc::types::Log;
+use alloy::sol_types::{self, SolEventInterface};
+
+use crate::ciphertext_commits::CiphertextCommits::CiphertextCommitsEvents;
+use crate::decryption::Decryption::DecryptionEvents;
+use crate::gateway_config::GatewayConfig::GatewayConfigEvents;
+use crate::input_verification::InputVerification::InputVerificationEvents;
+use crate::kms_generation::KMSGeneration::KMSGenerationEvents;
+use crate::multichain_acl::MultichainACL::MultichainACLEvents;
+
+/// A decoded event along with the metadata of the log it was decoded from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedEvent<E> {
+    /// The emitting contract and the decoded event.
+    pub log: PrimitiveLog<E>,
+    pub block_hash: Option<B256>,
+    pub block_number: Option<u64>,
+    pub block_timestamp: Option<u64>,
+    pub transaction_hash: Option<B256>,
+    pub transaction_index: Option<u64>,
+    pub log_index: Option<u64>,
+    /// Whether the log was removed by a reorg.
+    pub removed: bool,
+}
+
+impl<E> DecodedEvent<E> {
+    pub fn event(&self) -> &E {
+        &self.log.data
+    }
+
+    pub fn into_event(self) -> E {
+        self.log.data
+    }
+
+    /// Address of the contract that emitted the event.
+    pub fn address(&self) -> Address {
+        self.log.address
+    }
+
+    /// Converts the event, keeping the log metadata.
+    pub fn map<F>(self, f: impl FnOnce(E) -> F) -> DecodedEvent<F> {
+        DecodedEvent {
+            log: PrimitiveLog {
+                address: self.log.address,
+                data: f(self.log.data),
+            },
+            block_hash: self.block_hash,
+            block_number: self.block_number,
+            block_timestamp: self.block_timestamp,
+            transaction_hash: self.transaction_hash,
+            transaction_index: self.transaction_index,
+            log_index: self.log_index,
+            removed: self.removed,
+        }
+    }
+}
+
+pub type CiphertextCommitsEvent = DecodedEvent<CiphertextCommitsEvents>;
+pub type DecryptionEvent = DecodedEvent<DecryptionEvents>;
+pub type GatewayConfigEvent = DecodedEvent<GatewayConfigEvents>;
+pub type InputVerificationEvent = DecodedEvent<InputVerificationEvents>;
+pub type KMSGenerationEvent = DecodedEvent<KMSGenerationEvents>;
+pub type MultichainACLEvent = DecodedEvent<MultichainACLEvents>;
+
+/// Decodes a log with the events of a given contract, e.g. `DecryptionEvents`.
+///
+/// This works with any `sol!` generated events enum, not only the Gateway ones.
+pub fn decode_event<E: SolEventInterface>(log: &Log) -> Result<DecodedEvent<E>, sol_types::Error> {
+    let decoded = E::decode_log(&log.inner)?;
+    Ok(DecodedEvent {
+        log: decoded,
+        block_hash: log.block_hash,
+        block_number: log.block_number,
+        block_timestamp: log.block_timestamp,
+        transaction_hash: log.transaction_hash,
+        transaction_index: log.transaction_index,
+        log_index: log.log_index,
+        removed: log.removed,
+    })
+}
+
+/// Any event emitted by one of the Gateway contracts.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GatewayEvent {
+    CiphertextCommits(CiphertextCommitsEvents),
+    Decryption(DecryptionEvents),
+    GatewayConfig(GatewayConfigEvents),
+    InputVerification(InputVerificationEvents),
+    KMSGeneration(KMSGenerationEvents),
+    MultichainACL(MultichainACLEvents),
+}
+
+/// Addresses of the Gateway contracts to decode events from.
+///
+/// Contracts share some events (e.g. `Initialized`, `Upgraded`), hence the emitting address is
+/// needed to pick the right contract.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GatewayAddresses {
+    pub ciphertext_commits: Option<Address>,
+    pub decryption: Option<Address>,
+    pub gateway_config: Option<Address>,
+    pub input_verification: Option<Address>,
+    pub kms_generation: Option<Address>,
+    pub multichain_acl: Option<Address>,
+}
+
+/// Decodes a log emitted by any of the given Gateway contracts.
+///
+/// Returns `Ok(None)` if the log was not emitted by one of them.
+pub fn decode_any_event(
+    log: &Log,
+    addresses: &GatewayAddresses,
+) -> Result<Option<DecodedEvent<GatewayEvent>>, sol_types::Error> {
+    let address = Some(log.address());
+    let event = if address == addresses.ciphertext_commits {
+        decode_event(log)?.map(GatewayEvent::CiphertextCommits)
+    } else if address == addresses.decryption {
+        decode_event(log)?.map(GatewayEvent::Decryption)
+    } else if address == addresses.gateway_config {
+        decode_event(log)?.map(GatewayEvent::GatewayConfig)
+    } else if address == addresses.input_verification {
+        decode_event(log)?.map(GatewayEvent::InputVerification)
+    } else if address == addresses.kms_generation {
+        decode_event(log)?.map(GatewayEvent::KMSGeneration)
+    } else if address == addresses.multichain_acl {
+        decode_event(log)?.map(GatewayEvent::MultichainACL)
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(event))
+}
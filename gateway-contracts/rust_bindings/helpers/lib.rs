@@ -0,0 +1,13 @@
+//! Rust bindings of the Gateway contracts.
+//!
+//! The bindings themselves are generated in `src/` by `make update-bindings` and must not be
+//! edited manually. Hand-written helpers live next to this file so that regenerating the bindings
+//! does not wipe them.
+
+#[path = "../src/mod.rs"]
+mod generated;
+
+pub use generated::*;
+
+pub mod drift;
+pub mod events;
@@ -0,0 +1,106 @@
+//! Development tasks for the Gateway contracts, run with `cargo xtask <task>`.
+//!
+//! Tasks:
+//! - `update-bindings`: regenerates `rust_bindings/src` from the hardhat artifacts.
+//! - `check-bindings`: fails if `rust_bindings/src` is not up-to-date with the hardhat artifacts.
+//!
+//! The artifacts must be compiled beforehand (`npx hardhat compile`). Bumping the crate version is
+//! still done by `make update-bindings`.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+
+/// To update forge to the latest version locally, run `foundryup`.
+const ALLOWED_FORGE_VERSIONS: &[&str] = &["1.3.1-v1.3.1", "1.3.1-stable", "1.3.2-stable"];
+
+fn main() -> ExitCode {
+    let task = std::env::args().nth(1);
+    let result = match task.as_deref() {
+        Some("update-bindings") => bindings(true),
+        Some("check-bindings") => bindings(false),
+        _ => {
+            eprintln!("Usage: cargo xtask <update-bindings|check-bindings>");
+            return ExitCode::FAILURE;
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("[-] {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn gateway_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("xtask is in the gateway-contracts directory")
+        .to_path_buf()
+}
+
+fn check_forge_version() -> Result<(), String> {
+    let output = Command::new("forge")
+        .arg("--version")
+        .output()
+        .map_err(|e| format!("forge is not installed: {e}"))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches("forge Version: ")
+        .trim();
+    if !ALLOWED_FORGE_VERSIONS.contains(&version) {
+        return Err(format!(
+            "Required forge version to be one of {ALLOWED_FORGE_VERSIONS:?} but '{version}' is installed"
+        ));
+    }
+    Ok(())
+}
+
+fn bindings(overwrite: bool) -> Result<(), String> {
+    check_forge_version()?;
+
+    let root = gateway_root();
+    let crate_src = root.join("rust_bindings").join("src");
+    let mocks = root.join("contracts").join("mocks");
+    let out_dir = std::env::temp_dir().join(format!("gateway-bindings-{}", std::process::id()));
+
+    let mut forge = Command::new("forge");
+    forge
+        .arg("bind")
+        .arg("--root")
+        .arg(&root)
+        .arg("--hh")
+        .arg("-b")
+        .arg(&crate_src)
+        .arg("--module")
+        .arg("-o")
+        .arg(&out_dir)
+        .args(["--skip", "Example", "--skip"])
+        .arg(format!("{}/*", mocks.display()))
+        // Avoid updating the bytecode of every contract including an interface that changed.
+        .arg("--no-metadata")
+        .stdout(Stdio::null());
+    if overwrite {
+        println!("[*] Updating Gateway contracts' bindings...");
+        forge.arg("--overwrite");
+    } else {
+        println!("[*] Checking that the Gateway contracts' bindings are up-to-date...");
+        forge.arg("--skip-cargo-toml");
+    }
+
+    let status = forge.status().map_err(|e| format!("failed to run forge: {e}"));
+    let _ = std::fs::remove_dir_all(&out_dir);
+    if !status?.success() {
+        return Err(if overwrite {
+            "forge bind failed".to_owned()
+        } else {
+            "Some binding files are outdated, run `cargo xtask update-bindings`".to_owned()
+        });
+    }
+
+    println!("[+] The Gateway contracts' bindings are up-to-date!");
+    Ok(())
+}